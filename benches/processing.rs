@@ -0,0 +1,108 @@
+//! End-to-end benchmarks of the processing of synthetic datasets of transactions,
+//! from the CSV input to the accounts report.
+//!
+//! The datasets are generated in memory with the [`TransactionsGenerator`] before measuring, so the numbers don't include
+//! reading the input from disk. The number of transactions of every dataset can be changed with `BENCH_TRANSACTIONS`,
+//! like `BENCH_TRANSACTIONS=100000,1000000 cargo bench`.
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use tokio::runtime::Runtime;
+use toy_payments_engine::io::{
+  CsvAccountsReportWriter, CsvTransactionsReader, GeneratorConfig, TransactionsGenerator,
+};
+use toy_payments_engine::payments::{EngineConfig, InMemoryPaymentsEngine, ShardedPaymentsEngine};
+use toy_payments_engine::processors;
+
+const DEFAULT_TRANSACTIONS: &[u64] = &[1_000_000, 10_000_000];
+
+fn datasets(runtime: &Runtime) -> Vec<(u64, Vec<u8>)> {
+  let sizes = match std::env::var("BENCH_TRANSACTIONS") {
+    Ok(sizes) => sizes
+      .split(',')
+      .map(|size| {
+        size
+          .trim()
+          .parse::<u64>()
+          .expect("Invalid BENCH_TRANSACTIONS")
+      })
+      .collect(),
+    Err(_) => DEFAULT_TRANSACTIONS.to_vec(),
+  };
+  sizes
+    .into_iter()
+    .map(|transactions| {
+      let config = GeneratorConfig {
+        transactions,
+        clients: 10_000,
+        ..GeneratorConfig::default()
+      };
+      let mut data = Vec::new();
+      runtime
+        .block_on(TransactionsGenerator::new(config).write_csv(&mut data))
+        .unwrap();
+      (transactions, data)
+    })
+    .collect()
+}
+
+fn processing(c: &mut Criterion) {
+  let runtime = tokio::runtime::Builder::new_multi_thread()
+    .enable_all()
+    .build()
+    .unwrap();
+  let partitions = num_cpus::get();
+
+  let mut group = c.benchmark_group("processing");
+  // every iteration processes the whole dataset, so a few samples are enough
+  group.sample_size(10);
+  for (transactions, data) in datasets(&runtime) {
+    group.throughput(Throughput::Elements(transactions));
+
+    group.bench_with_input(
+      BenchmarkId::new("simple", transactions),
+      &data,
+      |b, data| {
+        b.to_async(&runtime).iter(|| {
+          processors::simple::run(
+            CsvTransactionsReader::new(data.as_slice()),
+            InMemoryPaymentsEngine::new(),
+            CsvAccountsReportWriter::new(tokio::io::sink()),
+          )
+        })
+      },
+    );
+
+    group.bench_with_input(
+      BenchmarkId::new("partitioned", transactions),
+      &data,
+      |b, data| {
+        b.to_async(&runtime).iter(|| {
+          processors::partitioned::run(
+            CsvTransactionsReader::new(data.as_slice()),
+            partitions,
+            InMemoryPaymentsEngine::new,
+            CsvAccountsReportWriter::new(tokio::io::sink()),
+          )
+        })
+      },
+    );
+
+    group.bench_with_input(
+      BenchmarkId::new("sharded", transactions),
+      &data,
+      |b, data| {
+        b.to_async(&runtime).iter(|| {
+          processors::simple::run(
+            CsvTransactionsReader::new(data.as_slice()),
+            ShardedPaymentsEngine::new(partitions, EngineConfig::default()),
+            CsvAccountsReportWriter::new(tokio::io::sink()),
+          )
+        })
+      },
+    );
+  }
+  group.finish();
+}
+
+criterion_group!(benches, processing);
+criterion_main!(benches);
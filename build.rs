@@ -0,0 +1,15 @@
+fn main() -> std::io::Result<()> {
+  println!("cargo:rerun-if-changed=build.rs");
+  println!("cargo:rerun-if-changed=proto/payments.proto");
+
+  // the types of the protobuf messages are generated with the protoc bundled with prost-build,
+  // and with the `grpc` feature tonic-build also generates the server of the `Payments` service
+  #[cfg(all(feature = "protobuf", not(feature = "grpc")))]
+  prost_build::compile_protos(&["proto/payments.proto"], &["proto/"])?;
+  #[cfg(feature = "grpc")]
+  tonic_build::configure()
+    .build_client(false)
+    .compile(&["proto/payments.proto"], &["proto/"])?;
+
+  Ok(())
+}
@@ -0,0 +1,16 @@
+//! Feed arbitrary bytes into the [`CsvTransactionsReader`], which must read every record (or fail to) without panicking.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use tokio_stream::StreamExt;
+use toy_payments_engine::io::{CsvTransactionsReader, TransactionRecord};
+
+fuzz_target!(|data: &[u8]| {
+  let mut reader = CsvTransactionsReader::new(data);
+  let records: Vec<TransactionRecord> = futures::executor::block_on(reader.records().collect());
+  for record in records {
+    // the raw record is kept for the accepted transactions, so they can be reported
+    assert!(record.transaction.is_err() || record.raw.is_some());
+  }
+});
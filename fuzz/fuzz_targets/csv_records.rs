@@ -0,0 +1,43 @@
+//! Write arbitrary transactions as CSV, and check that the [`CsvTransactionsReader`] reads them in the same way
+//! whether the empty columns at the end of the records are written or omitted.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use tokio_stream::StreamExt;
+use toy_payments_engine::io::{CsvTransactionsReader, Transaction};
+
+fuzz_target!(|transactions: Vec<Transaction>| {
+  let mut writer = csv::Writer::from_writer(Vec::new());
+  for transaction in &transactions {
+    writer.serialize(transaction).unwrap();
+  }
+  let complete = writer.into_inner().unwrap();
+
+  let mut writer = csv::WriterBuilder::new()
+    .flexible(true)
+    .from_writer(Vec::new());
+  let mut reader = csv::ReaderBuilder::new()
+    .has_headers(false)
+    .from_reader(complete.as_slice());
+  for record in reader.records() {
+    let record = record.unwrap();
+    let fields: Vec<&str> = record.iter().collect();
+    let len = fields.iter().rposition(|field| !field.is_empty()).unwrap() + 1;
+    writer.write_record(&fields[..len]).unwrap();
+  }
+  let truncated = writer.into_inner().unwrap();
+
+  let complete = read(&complete);
+  let truncated = read(&truncated);
+  assert_eq!(complete.len(), transactions.len());
+  assert_eq!(complete, truncated);
+});
+
+/// The transactions read, or `None` for the records that can't be read
+/// (their errors are not compared, as they refer to the positions in the input).
+fn read(data: &[u8]) -> Vec<Option<toy_payments_engine::payments::Transaction>> {
+  let mut reader = CsvTransactionsReader::new(data);
+  let transactions = reader.transactions().map(|transaction| transaction.ok());
+  futures::executor::block_on(transactions.collect())
+}
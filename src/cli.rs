@@ -0,0 +1,1566 @@
+//! Parsing of the command line arguments.
+//!
+//! The input can be given either as a positional argument or with `--input`, and it defaults to the stdin.
+//! The rest of the behaviour is configured with the flags of the [`SETTINGS`], which can also be given with
+//! their environment variables (see the README).
+//! Some of them can be given as defaults in a configuration file with `--config` (see [`ConfigFile`]).
+//! The completions for the most common shells are generated from the same definition, with the `completions` subcommand.
+
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use anyhow::Result;
+use clap::{App, AppSettings, Arg, ArgMatches, Shell, SubCommand};
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use toy_payments_engine::io::{GeneratorConfig, Sampling};
+use toy_payments_engine::payments::AccountFilter;
+
+use crate::config::ConfigFile;
+
+const RECONCILE_COMMAND: &str = "reconcile";
+const HISTORY_COMMAND: &str = "history";
+const STATEMENTS_COMMAND: &str = "statements";
+const REPL_COMMAND: &str = "repl";
+const DISPUTES_COMMAND: &str = "disputes";
+const SUB_ACCOUNTS_COMMAND: &str = "sub-accounts";
+const EXPORT_BUNDLE_COMMAND: &str = "export-bundle";
+const GENERATE_COMMAND: &str = "generate";
+const COMPLETIONS_COMMAND: &str = "completions";
+#[cfg(feature = "http")]
+const SERVE_COMMAND: &str = "serve";
+#[cfg(feature = "http")]
+const SERVE_REPORT_COMMAND: &str = "serve-report";
+#[cfg(feature = "grpc")]
+const SERVE_GRPC_COMMAND: &str = "serve-grpc";
+
+#[cfg(not(feature = "protobuf"))]
+const REPORT_FORMATS: &[&str] = &["csv", "json"];
+#[cfg(feature = "protobuf")]
+const REPORT_FORMATS: &[&str] = &["csv", "json", "protobuf"];
+#[cfg(not(feature = "protobuf"))]
+const REPORT_FORMATS_HELP: &str = "Format of the accounts report:\n\
+  - csv: the `client, available, held, total, locked` columns (more columns with REPORT_SCHEMA=v2)\n\
+  - json: newline delimited JSON, with one account per line";
+#[cfg(feature = "protobuf")]
+const REPORT_FORMATS_HELP: &str = "Format of the accounts report:\n\
+  - csv: the `client, available, held, total, locked` columns (more columns with REPORT_SCHEMA=v2)\n\
+  - json: newline delimited JSON, with one account per line\n\
+  - protobuf: length delimited `AccountReport` messages (see proto/payments.proto)";
+
+/// The options that select the accounts of the report, which are the parameters of an [`AccountFilter`] with dashes.
+const FILTER_SWITCHES: &[&str] = &["locked-only", "has-open-disputes"];
+const FILTER_VALUES: &[&str] = &["min-total", "max-total", "client-ids"];
+
+const ENGINES_HELP: &str = "Implementation of the payments engine:\n\
+  - memory: the accounts are kept in memory (default)\n\
+  - persistent: the accounts are kept in memory, and persisted into the write-ahead log of the --database path\n\
+  - sqlite: the accounts are kept in the SQLite database of the --database path (with the sqlite feature)\n\
+  - postgres: the accounts are kept in the PostgreSQL database of the --database URL (with the postgres feature)";
+
+/// What the binary has to do.
+#[derive(Debug, PartialEq)]
+pub enum Command {
+  /// Process the transactions and write the accounts report.
+  Process,
+  /// Reconcile the accounts against the balances of an external source, and write the breaks report.
+  Reconcile {
+    balances: String,
+    tolerance: Decimal,
+  },
+  /// Process the transactions and write the history of the transactions recorded by the accounts.
+  History,
+  /// Process the transactions and write the statement of every client, as newline delimited JSON or CSV.
+  Statements { json: bool },
+  /// Process the transactions and answer the queries read from the stdin, like the timeline of a client.
+  Repl,
+  /// Process the transactions and write the accounts with open disputes.
+  Disputes,
+  /// Process the transactions and write the funds of the sub-accounts of the clients.
+  SubAccounts,
+  /// Process the transactions and write an audit bundle with the results, the configuration and the audit log of the run.
+  ExportBundle,
+  /// Generate a synthetic dataset of transactions, to benchmark the processing.
+  Generate(GeneratorConfig),
+  /// Serve the payments engine through HTTP.
+  #[cfg(feature = "http")]
+  Serve { address: String },
+  /// Serve the accounts report of a snapshot through HTTP, without accepting any transaction.
+  #[cfg(feature = "http")]
+  ServeReport { address: String, snapshot: String },
+  /// Serve the payments engine through gRPC.
+  #[cfg(feature = "grpc")]
+  ServeGrpc { address: String },
+  /// Write the completions of the command line for a shell (one of [`Shell::variants`]).
+  Completions { shell: String },
+}
+
+/// Format of the accounts report.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ReportFormat {
+  Csv,
+  /// Newline delimited JSON, with one account per line.
+  Json,
+  /// Length delimited protobuf messages, with one account per message.
+  #[cfg(feature = "protobuf")]
+  Protobuf,
+}
+
+/// Implementation of the payments engine.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Engine {
+  /// The accounts are kept in memory.
+  Memory,
+  /// The accounts are kept in memory, and persisted into the write-ahead log of the path, which is replayed when starting.
+  Persistent { path: String },
+  /// The accounts are kept in the SQLite database of the path.
+  #[cfg(feature = "sqlite")]
+  Sqlite { path: String },
+  /// The accounts are kept in the PostgreSQL database of the URL, which can be shared by several instances.
+  #[cfg(feature = "postgres")]
+  Postgres { url: String },
+}
+
+/// Format of the logs written into the stderr.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LogFormat {
+  /// One line per event, the default.
+  Text,
+  /// One JSON object per event, for log aggregators.
+  Json,
+  /// Human readable, for the terminal.
+  Pretty,
+}
+
+/// A setting of the engine, the processing or the reports, given either with its flag or its environment variable.
+struct Setting {
+  /// The name of the environment variable, which also identifies the setting.
+  name: &'static str,
+  flag: &'static str,
+  /// Whether the setting is enabled by its presence, without a value.
+  switch: bool,
+  help: &'static str,
+}
+
+impl Setting {
+  const fn value(name: &'static str, flag: &'static str, help: &'static str) -> Self {
+    Self {
+      name,
+      flag,
+      switch: false,
+      help,
+    }
+  }
+
+  const fn switch(name: &'static str, flag: &'static str, help: &'static str) -> Self {
+    Self {
+      name,
+      flag,
+      switch: true,
+      help,
+    }
+  }
+
+  fn arg(&self) -> Arg<'static, 'static> {
+    let arg = Arg::with_name(self.flag)
+      .long(self.flag)
+      .global(true)
+      .help(self.help);
+    if self.switch {
+      arg
+    } else {
+      arg.takes_value(true).env(self.name).hide_env_values(true)
+    }
+  }
+}
+
+/// The settings of the engine, the processing and the reports (see the README for the details of every one).
+const SETTINGS: &[Setting] = &[
+  Setting::value(
+    crate::ERRORS_FILE_VAR,
+    "errors-file",
+    "Where to write the rejected records as CSV",
+  ),
+  Setting::value(
+    crate::QUARANTINE_FILE_VAR,
+    "quarantine-file",
+    "Where to put the records that can't be read in quarantine as CSV",
+  ),
+  Setting::value(
+    crate::MAX_QUARANTINED_VAR,
+    "max-quarantined",
+    "Maximum number of records put in quarantine before aborting the processing",
+  ),
+  Setting::value(
+    crate::DUPLICATES_FILE_VAR,
+    "duplicates-file",
+    "Where to write the duplicated transactions as CSV",
+  ),
+  Setting::value(
+    crate::EVENTS_FILE_VAR,
+    "events-file",
+    "Where to write the events of the engine as JSON, one per line",
+  ),
+  Setting::value(
+    crate::WAL_FILE_VAR,
+    "wal-file",
+    "The write-ahead log where to append the accepted transactions, replayed when it exists",
+  ),
+  Setting::value(
+    crate::DUMPS_DIR_VAR,
+    "dumps-dir",
+    "Directory where to dump the accounts report on SIGUSR1",
+  ),
+  Setting::value(
+    crate::DIGESTS_DIR_VAR,
+    "digests-dir",
+    "Directory shared by the replicas to exchange the digests of their accounts",
+  ),
+  Setting::value(
+    crate::REPLICA_NAME_VAR,
+    "replica-name",
+    "The name of this replica in the digests directory",
+  ),
+  Setting::value(
+    crate::DIGEST_INTERVAL_VAR,
+    "digest-interval",
+    "Number of records between the digests exchanged by the replicas (10000 by default)",
+  ),
+  Setting::switch(
+    crate::CHECK_INVARIANTS_VAR,
+    "check-invariants",
+    "Check the invariants of the engine after every transaction",
+  ),
+  Setting::value(
+    crate::PARTITIONS_VAR,
+    "partitions",
+    "Number of partitions to process the transactions in parallel, or auto for one per CPU",
+  ),
+  Setting::value(
+    crate::PARSE_CHUNK_SIZE_VAR,
+    "parse-chunk-size",
+    "Size in bytes of the chunks to parse the input CSV in parallel",
+  ),
+  #[cfg(feature = "kafka")]
+  Setting::value(
+    crate::KAFKA_GROUP_VAR,
+    "kafka-group",
+    "The consumer group of the transactions read from Kafka (toy-payments-engine by default)",
+  ),
+  Setting::value(
+    crate::AMOUNTS_VAR,
+    "amounts",
+    "How to parse the amounts: lenient (default) or strict",
+  ),
+  Setting::value(
+    crate::NORMALIZE_PRECISION_VAR,
+    "normalize-precision",
+    "Number of decimal places to round the amounts to",
+  ),
+  Setting::value(
+    crate::DROP_CLIENTS_VAR,
+    "drop-clients",
+    "Comma separated ranges of client IDs whose transactions are dropped, like 9000-9999",
+  ),
+  Setting::value(
+    crate::CLIENT_ID_REMAPPING_VAR,
+    "client-id-remapping",
+    "The client IDs remapping file",
+  ),
+  Setting::value(crate::SOURCE_TAG_VAR, "source-tag", "The tag of the source of the input"),
+  Setting::value(
+    crate::IDEMPOTENCY_STORE_VAR,
+    "idempotency-store",
+    "The file with the keys of the records already ingested",
+  ),
+  Setting::value(
+    crate::IDEMPOTENCY_KEY_VAR,
+    "idempotency-key",
+    "How the ingested records are identified: record (default) or position",
+  ),
+  Setting::value(
+    crate::INPUT_HISTORY_VAR,
+    "input-history",
+    "The history of the input files already processed",
+  ),
+  Setting::value(
+    crate::DUPLICATE_INPUT_VAR,
+    "duplicate-input",
+    "Only warn (warn) instead of refusing the inputs already in the history",
+  ),
+  Setting::value(
+    crate::ARCHIVE_DIR_VAR,
+    "archive-dir",
+    "Directory where to archive a copy of the raw input before processing it",
+  ),
+  Setting::value(
+    crate::MAX_OPEN_DISPUTES_VAR,
+    "max-open-disputes",
+    "Maximum number of open disputes per account",
+  ),
+  Setting::switch(
+    crate::DETERMINISTIC_REPORT_VAR,
+    "deterministic-report",
+    "Write the accounts report ordered by client",
+  ),
+  Setting::value(
+    crate::LOCKED_ACCOUNT_DISPUTES_VAR,
+    "locked-account-disputes",
+    "Whether the locked accounts allow or reject the disputes",
+  ),
+  Setting::value(
+    crate::ZERO_AMOUNTS_VAR,
+    "zero-amounts",
+    "Whether to accept, reject or skip the transactions without amount",
+  ),
+  Setting::value(
+    crate::CHARGEBACK_FEE_VAR,
+    "chargeback-fee",
+    "The fee charged to the client on every chargeback",
+  ),
+  Setting::value(
+    crate::CHARGEBACK_FEE_POLICY_VAR,
+    "chargeback-fee-policy",
+    "Whether the chargeback fee is capped to the available funds (cap) or can make them negative (allow-negative)",
+  ),
+  Setting::value(
+    crate::SYNTHETIC_DISPUTES_VAR,
+    "synthetic-disputes",
+    "Whether the synthetic transactions, like the chargeback fees, allow or reject the disputes",
+  ),
+  Setting::value(
+    crate::UNLOCK_HELD_FUNDS_VAR,
+    "unlock-held-funds",
+    "Whether to keep or release the held funds of the unlocked accounts",
+  ),
+  Setting::value(
+    crate::DUPLICATE_POLICY_VAR,
+    "duplicate-policy",
+    "Whether the duplicated transactions are rejected (reject) or ignored (idempotent)",
+  ),
+  Setting::value(
+    crate::ESCROW_INTEREST_RATE_VAR,
+    "escrow-interest-rate",
+    "The escrow interest accrued by the held funds of the disputes per day",
+  ),
+  Setting::value(
+    crate::EXPOSURE_THRESHOLD_VAR,
+    "exposure-threshold",
+    "Maximum exposure of a client before its account is flagged in the report",
+  ),
+  Setting::value(
+    crate::AUTHORIZATION_EXPIRY_VAR,
+    "authorization-expiry",
+    "The expiry of the authorizations, in seconds",
+  ),
+  Setting::value(
+    crate::DISPUTE_WINDOW_DAYS_VAR,
+    "dispute-window-days",
+    "The days after a transaction during which it can be disputed",
+  ),
+  Setting::value(
+    crate::DORMANCY_DAYS_VAR,
+    "dormancy-days",
+    "The days without activity after which an account is dormant",
+  ),
+  Setting::value(
+    crate::DORMANCY_POLICY_VAR,
+    "dormancy-policy",
+    "Whether the dormant accounts are only flagged (flag) or their withdrawals are rejected (block-withdrawals)",
+  ),
+  Setting::value(
+    crate::LIMITS_FILE_VAR,
+    "limits-file",
+    "A TOML file with the limits on the withdrawals of every client",
+  ),
+  Setting::value(
+    crate::REPORT_SCHEMA_VAR,
+    "report-schema",
+    "The version of the schema of the CSV accounts report: v1 (default), v2 or extended",
+  ),
+  Setting::value(
+    crate::EXTENDED_REPORT_FILE_VAR,
+    "extended-report-file",
+    "A file where to write a copy of the accounts report with the extended schema",
+  ),
+  Setting::value(
+    crate::REPORT_CURRENCY_VAR,
+    "report-currency",
+    "The currency of the funds reported by the extended schema",
+  ),
+  Setting::value(
+    crate::REPORT_SORT_VAR,
+    "report-sort",
+    "The order of the accounts report: client or total, optionally followed by :desc",
+  ),
+  Setting::value(
+    crate::REPORT_BUFFER_ACCOUNTS_VAR,
+    "report-buffer-accounts",
+    "Maximum number of accounts of the report kept in memory before spilling them",
+  ),
+  Setting::value(
+    crate::REPORT_SOCKET_VAR,
+    "report-socket",
+    "A Unix domain socket where to stream a copy of the accounts report",
+  ),
+  #[cfg(any(feature = "sqlite", feature = "postgres"))]
+  Setting::value(
+    crate::WRITE_BATCH_SIZE_VAR,
+    "write-batch-size",
+    "Number of transactions whose writes are flushed together into the database, recovered from the WAL_FILE",
+  ),
+  #[cfg(feature = "kv")]
+  Setting::value(
+    crate::REPORT_KV_DB_VAR,
+    "report-kv-db",
+    "An embedded database where to export a copy of the accounts report",
+  ),
+  #[cfg(feature = "kv")]
+  Setting::value(
+    crate::TRANSACTIONS_SPILL_DIR_VAR,
+    "transactions-spill-dir",
+    "Directory where to spill the settled transactions of the accounts",
+  ),
+  #[cfg(feature = "kv")]
+  Setting::value(
+    crate::TRANSACTIONS_MEMORY_VAR,
+    "transactions-memory",
+    "Maximum number of settled transactions kept in memory (up to 1000000 by default, depending on the memory limit)",
+  ),
+  Setting::value(
+    crate::CLIENT_METADATA_VAR,
+    "client-metadata",
+    "The client metadata file to join into the accounts report",
+  ),
+  Setting::value(
+    crate::CLIENT_METADATA_FIELDS_VAR,
+    "client-metadata-fields",
+    "Comma separated fields to join from the client metadata (name,tier by default)",
+  ),
+  Setting::value(
+    crate::BASELINE_SNAPSHOT_VAR,
+    "baseline-snapshot",
+    "The snapshot whose accounts the report is compared with",
+  ),
+  #[cfg(feature = "http")]
+  Setting::value(crate::API_KEYS_VAR, "api-keys", "The API keys allowed to use the services"),
+  #[cfg(feature = "http")]
+  Setting::value(
+    crate::AUDIT_LOG_VAR,
+    "audit-log",
+    "The log where to audit the requests to the services",
+  ),
+  #[cfg(feature = "http")]
+  Setting::value(
+    crate::TLS_CERT_VAR,
+    "tls-cert",
+    "The PEM encoded TLS certificate chain of the services",
+  ),
+  #[cfg(feature = "http")]
+  Setting::value(crate::TLS_KEY_VAR, "tls-key", "The PEM encoded TLS private key of the services"),
+  #[cfg(feature = "http")]
+  Setting::switch(crate::METRICS_VAR, "metrics", "Expose the metrics of the services in /metrics"),
+  #[cfg(feature = "http")]
+  Setting::value(
+    crate::ADMISSION_MEMORY_LIMIT_VAR,
+    "admission-memory-limit",
+    "Memory in bytes (or a percentage of the container limit, like 80%) past which the uploads are rejected with 429",
+  ),
+  #[cfg(feature = "http")]
+  Setting::switch(
+    crate::TIMELINE_VAR,
+    "timeline",
+    "Record the timeline of the clients, exposed in /accounts/{id}/timeline",
+  ),
+  #[cfg(any(feature = "http", feature = "grpc"))]
+  Setting::value(
+    crate::SNAPSHOTS_DIR_VAR,
+    "snapshots-dir",
+    "Directory where a service with --engine persistent snapshots its state, replaying only the log after the latest one",
+  ),
+  #[cfg(any(feature = "http", feature = "grpc"))]
+  Setting::value(
+    crate::SNAPSHOT_INTERVAL_VAR,
+    "snapshot-interval",
+    "Minutes between the snapshots of the service (5 by default)",
+  ),
+  #[cfg(any(feature = "http", feature = "grpc"))]
+  Setting::value(
+    crate::SNAPSHOT_TRANSACTIONS_VAR,
+    "snapshot-transactions",
+    "Number of transactions between the snapshots of the service",
+  ),
+  #[cfg(any(feature = "http", feature = "grpc"))]
+  Setting::value(
+    crate::SNAPSHOTS_KEPT_VAR,
+    "snapshots-kept",
+    "Number of the latest snapshots of the service kept (3 by default)",
+  ),
+  Setting::value(
+    crate::LOG_LEVEL_VAR,
+    "log-level",
+    "The level of the logs: error, warn (default), info, debug or trace",
+  ),
+];
+
+/// The settings given with their flags or environment variables, by the name of their variable,
+/// falling back to the ones of the configuration file.
+#[derive(Debug, Default, PartialEq)]
+pub struct Settings {
+  values: HashMap<&'static str, String>,
+}
+
+impl Settings {
+  #[cfg(test)]
+  fn new<I>(values: I) -> Self
+  where
+    I: IntoIterator<Item = (&'static str, String)>,
+  {
+    Self {
+      values: values.into_iter().collect(),
+    }
+  }
+
+  fn from_matches(matches: &ArgMatches, config: &ConfigFile) -> Result<Self> {
+    Self::resolve(matches, config, env_switch)
+  }
+
+  /// Resolve every switch as its flag, its environment variable, its value in the file or disabled, whatever is given first,
+  /// so a switch enabled by the file is disabled by an environment variable set to `false`. Only the enabled switches are kept.
+  fn resolve<F>(matches: &ArgMatches, config: &ConfigFile, env_switch: F) -> Result<Self>
+  where
+    F: Fn(&str) -> Result<Option<bool>>,
+  {
+    let file_switches: HashMap<&str, bool> = config.switches().into_iter().collect();
+    let mut values = HashMap::new();
+    for setting in SETTINGS {
+      let value = if setting.switch {
+        let enabled = if matches.is_present(setting.flag) {
+          Some(true)
+        } else {
+          env_switch(setting.name)?
+        };
+        let enabled = enabled
+          .or_else(|| file_switches.get(setting.name).copied())
+          .unwrap_or(false);
+        Some("true".to_string()).filter(|_| enabled)
+      } else {
+        value(matches, setting.flag)
+      };
+      if let Some(value) = value {
+        values.insert(setting.name, value);
+      }
+    }
+    for (name, value) in config.settings() {
+      values.entry(name).or_insert(value);
+    }
+    Ok(Self { values })
+  }
+
+  /// The value of the setting, if it is given.
+  pub fn get(&self, name: &str) -> Option<String> {
+    self.values.get(name).cloned()
+  }
+
+  pub fn contains(&self, name: &str) -> bool {
+    self.values.contains_key(name)
+  }
+
+  /// Whether the switch is enabled, once resolved from all the sources.
+  pub fn enabled(&self, name: &str) -> bool {
+    self.get(name).map_or(false, |value| value == "true")
+  }
+}
+
+/// The parsed command line arguments.
+#[derive(Debug, PartialEq)]
+pub struct Cli {
+  pub command: Command,
+  pub input: Option<String>,
+  pub output: Option<String>,
+  pub format: ReportFormat,
+  pub engine: Engine,
+  pub workers: Option<usize>,
+  /// Whether to continue a processing that was started under a different engine configuration.
+  pub allow_config_change: bool,
+  /// Whether to take over the lock of the write-ahead log held by another process.
+  pub steal_lock: bool,
+  /// Whether to skip the records of the input processed by a previous run, and record the ones processed by this one.
+  pub resume: bool,
+  /// Only process a subset of the input.
+  pub sampling: Option<Sampling>,
+  /// The format of the logs, which are always written into the stderr.
+  pub log_format: LogFormat,
+  /// Select the accounts of the report.
+  pub filter: AccountFilter,
+  /// The settings of the engine, the processing and the reports.
+  pub settings: Settings,
+  /// The configuration file, whose settings are already merged into the rest of the fields.
+  pub config: ConfigFile,
+}
+
+impl Cli {
+  /// Parse the arguments of the process, exiting with the usage when they are not valid or the help is requested.
+  pub fn parse() -> Result<Self> {
+    Self::from_matches(&app().get_matches())
+  }
+
+  #[cfg(test)]
+  fn parse_from<I, T>(args: I) -> Result<Self>
+  where
+    I: IntoIterator<Item = T>,
+    T: Into<std::ffi::OsString> + Clone,
+  {
+    Self::from_matches(&app().get_matches_from_safe(args)?)
+  }
+
+  fn from_matches(matches: &ArgMatches) -> Result<Self> {
+    let command = match matches.subcommand() {
+      (RECONCILE_COMMAND, Some(matches)) => Command::Reconcile {
+        balances: value(matches, "balances").unwrap_or_default(),
+        tolerance: matches
+          .value_of("tolerance")
+          .map(Decimal::from_str)
+          .transpose()?
+          .unwrap_or(Decimal::ZERO),
+      },
+      (HISTORY_COMMAND, Some(_)) => Command::History,
+      (STATEMENTS_COMMAND, Some(matches)) => Command::Statements {
+        json: matches.is_present("json"),
+      },
+      (REPL_COMMAND, Some(_)) => Command::Repl,
+      (DISPUTES_COMMAND, Some(_)) => Command::Disputes,
+      (SUB_ACCOUNTS_COMMAND, Some(_)) => Command::SubAccounts,
+      (EXPORT_BUNDLE_COMMAND, Some(_)) => Command::ExportBundle,
+      (GENERATE_COMMAND, Some(matches)) => Command::Generate(generator_config(matches)?),
+      #[cfg(feature = "http")]
+      (SERVE_COMMAND, Some(matches)) => Command::Serve {
+        address: value(matches, "address").unwrap_or_default(),
+      },
+      #[cfg(feature = "http")]
+      (SERVE_REPORT_COMMAND, Some(matches)) => Command::ServeReport {
+        address: value(matches, "address").unwrap_or_default(),
+        snapshot: value(matches, "snapshot").unwrap_or_default(),
+      },
+      #[cfg(feature = "grpc")]
+      (SERVE_GRPC_COMMAND, Some(matches)) => Command::ServeGrpc {
+        address: value(matches, "address").unwrap_or_default(),
+      },
+      (COMPLETIONS_COMMAND, Some(matches)) => Command::Completions {
+        shell: value(matches, "shell").unwrap_or_default(),
+      },
+      _ => Command::Process,
+    };
+
+    let config = match matches.value_of("config") {
+      Some(path) => ConfigFile::load(path)?,
+      None => ConfigFile::default(),
+    };
+
+    let format = match matches.value_of("format") {
+      Some("json") => ReportFormat::Json,
+      #[cfg(feature = "protobuf")]
+      Some("protobuf") => ReportFormat::Protobuf,
+      Some(_) => ReportFormat::Csv,
+      None => config.io.format.unwrap_or(ReportFormat::Csv),
+    };
+
+    let sampling = if let Some(head) = matches.value_of("head") {
+      Some(Sampling::Head(head.parse::<usize>()?))
+    } else if let Some(clients) = matches.value_of("clients-sample") {
+      Some(Sampling::Clients(clients.parse::<usize>()?))
+    } else if let Some(sample) = matches.value_of("sample") {
+      let percent = sample.trim_end_matches('%').parse::<f64>()?;
+      if !(0.0..=100.0).contains(&percent) {
+        anyhow::bail!("Invalid sample: {}", sample);
+      }
+      let seed = matches
+        .value_of("sample-seed")
+        .map(|seed| seed.parse::<u64>())
+        .transpose()?
+        .unwrap_or_default();
+      Some(Sampling::Fraction {
+        fraction: percent / 100.0,
+        seed,
+      })
+    } else {
+      None
+    };
+
+    let database = value(matches, "database");
+    let engine = match matches.value_of("engine") {
+      Some("persistent") => Engine::Persistent {
+        path: database.ok_or_else(|| anyhow::anyhow!("--engine persistent requires --database"))?,
+      },
+      #[cfg(feature = "sqlite")]
+      Some("sqlite") => Engine::Sqlite {
+        path: database.ok_or_else(|| anyhow::anyhow!("--engine sqlite requires --database"))?,
+      },
+      #[cfg(feature = "postgres")]
+      Some("postgres") => Engine::Postgres {
+        url: database.ok_or_else(|| anyhow::anyhow!("--engine postgres requires --database"))?,
+      },
+      _ if database.is_some() => anyhow::bail!("--database requires a database --engine"),
+      _ => Engine::Memory,
+    };
+
+    let log_format = match matches.value_of("log-format") {
+      Some("json") => LogFormat::Json,
+      Some("pretty") => LogFormat::Pretty,
+      _ => LogFormat::Text,
+    };
+
+    let workers = matches
+      .value_of("workers")
+      .map(|workers| workers.parse::<usize>())
+      .transpose()?
+      .or(config.processing.workers);
+
+    Ok(Self {
+      command,
+      input: value(matches, "input").or_else(|| value(matches, "INPUT")),
+      output: value(matches, "output").or_else(|| config.output.report.clone()),
+      format,
+      engine,
+      workers,
+      allow_config_change: matches.is_present("allow-config-change"),
+      steal_lock: matches.is_present("steal-lock"),
+      resume: matches.is_present("resume"),
+      sampling,
+      log_format,
+      filter: account_filter(matches)?,
+      settings: Settings::from_matches(matches, &config)?,
+      config,
+    })
+  }
+}
+
+/// Write the completions of the command line for the shell into the `out`.
+pub fn write_completions<W: std::io::Write>(shell: &str, out: &mut W) -> Result<()> {
+  let shell = Shell::from_str(shell).map_err(anyhow::Error::msg)?;
+  app().gen_completions_to(env!("CARGO_PKG_NAME"), shell, out);
+  Ok(())
+}
+
+fn account_filter(matches: &ArgMatches) -> Result<AccountFilter> {
+  let mut filter = AccountFilter::default();
+  for name in FILTER_SWITCHES {
+    if matches.is_present(name) {
+      filter = filter.with_param(&name.replace('-', "_"), "true")?;
+    }
+  }
+  for name in FILTER_VALUES {
+    if let Some(value) = matches.value_of(name) {
+      filter = filter.with_param(&name.replace('-', "_"), value)?;
+    }
+  }
+  Ok(filter)
+}
+
+fn generator_config(matches: &ArgMatches) -> Result<GeneratorConfig> {
+  let defaults = GeneratorConfig::default();
+  Ok(GeneratorConfig {
+    transactions: parse_or(matches, "transactions", defaults.transactions)?,
+    clients: parse_or(matches, "clients", defaults.clients)?,
+    dispute_ratio: parse_or(matches, "dispute-ratio", defaults.dispute_ratio)?,
+    chargeback_ratio: parse_or(matches, "chargeback-ratio", defaults.chargeback_ratio)?,
+    seed: parse_or(matches, "seed", defaults.seed)?,
+  })
+}
+
+fn parse_or<T>(matches: &ArgMatches, name: &str, default: T) -> Result<T>
+where
+  T: FromStr,
+  T::Err: std::error::Error + Send + Sync + 'static,
+{
+  Ok(
+    matches
+      .value_of(name)
+      .map(T::from_str)
+      .transpose()?
+      .unwrap_or(default),
+  )
+}
+
+/// The engines available with the features enabled.
+fn engines() -> Vec<&'static str> {
+  #[allow(unused_mut)]
+  let mut engines = vec!["memory", "persistent"];
+  #[cfg(feature = "sqlite")]
+  engines.push("sqlite");
+  #[cfg(feature = "postgres")]
+  engines.push("postgres");
+  engines
+}
+
+/// Whether the environment variable of a switch enables it, which requires it to be `true` or `false` when given.
+/// The value of the environment variable of a switch, or `None` when it is not set.
+fn env_switch(name: &str) -> Result<Option<bool>> {
+  match std::env::var(name) {
+    Ok(value) => value
+      .parse()
+      .map(Some)
+      .map_err(|_| anyhow::anyhow!("{} must be true or false, but it is {:?}", name, value)),
+    Err(std::env::VarError::NotPresent) => Ok(None),
+    Err(err) => Err(anyhow::anyhow!("{}: {}", name, err)),
+  }
+}
+
+fn value(matches: &ArgMatches, name: &str) -> Option<String> {
+  matches.value_of(name).map(str::to_string)
+}
+
+fn app() -> App<'static, 'static> {
+  let app = App::new(env!("CARGO_PKG_NAME"))
+    .version(env!("CARGO_PKG_VERSION"))
+    .about("Processes payment transactions and reports the state of the client accounts")
+    .setting(AppSettings::ArgsNegateSubcommands)
+    .setting(AppSettings::VersionlessSubcommands)
+    .after_help(
+      "Every setting can also be given with its environment variable, \
+       which is its flag in uppercase with underscores, like PARTITIONS for --partitions (see the README).\n\
+       Use `help <subcommand>` for the arguments of every subcommand.",
+    )
+    .arg(
+      Arg::with_name("INPUT")
+        .help("The transactions file (the stdin by default)")
+        .conflicts_with("input"),
+    )
+    .arg(
+      Arg::with_name("input")
+        .long("input")
+        .short("i")
+        .takes_value(true)
+        .global(true)
+        .help("The transactions file (the stdin by default)"),
+    )
+    .arg(
+      Arg::with_name("output")
+        .long("output")
+        .short("o")
+        .takes_value(true)
+        .global(true)
+        .help("Where to write the report (the stdout by default)"),
+    )
+    .arg(
+      Arg::with_name("format")
+        .long("format")
+        .takes_value(true)
+        .possible_values(REPORT_FORMATS)
+        .help("Format of the accounts report (csv by default)")
+        .long_help(REPORT_FORMATS_HELP),
+    )
+    .arg(
+      Arg::with_name("engine")
+        .long("engine")
+        .takes_value(true)
+        .possible_values(&engines())
+        .global(true)
+        .help("Implementation of the payments engine (memory by default)")
+        .long_help(ENGINES_HELP),
+    )
+    .arg(
+      Arg::with_name("database")
+        .long("database")
+        .takes_value(true)
+        .global(true)
+        .help("The database where the engine keeps the accounts"),
+    )
+    .arg(
+      Arg::with_name("workers")
+        .long("workers")
+        .takes_value(true)
+        .global(true)
+        .help("Number of worker threads (the CPUs available by default)"),
+    )
+    .arg(
+      Arg::with_name("log-format")
+        .long("log-format")
+        .takes_value(true)
+        .possible_values(&["text", "json", "pretty"])
+        .global(true)
+        .help("The format of the logs written into the stderr (text by default)")
+        .long_help(
+          "The format of the logs written into the stderr:\n\
+           - text: one line per event (default)\n\
+           - json: one JSON object per event\n\
+           - pretty: human readable\n\
+           The level is warn by default, and it can be changed with --log-level (error, warn, info, debug or trace).",
+        ),
+    )
+    .arg(
+      Arg::with_name("config")
+        .long("config")
+        .short("c")
+        .takes_value(true)
+        .global(true)
+        .help("A TOML or YAML file with the defaults of the flags and environment variables (see the README)"),
+    )
+    .arg(
+      Arg::with_name("allow-config-change")
+        .long("allow-config-change")
+        .help("Continue the write-ahead log even if it was started with a different engine configuration"),
+    )
+    .arg(
+      Arg::with_name("steal-lock")
+        .long("steal-lock")
+        .help("Take over the lock of the write-ahead log even if another process holds it"),
+    )
+    .arg(
+      Arg::with_name("resume")
+        .long("resume")
+        .help("Skip the records processed by a previous run of the same input, and record the ones processed by this one")
+        .long_help(
+          "Skip the records processed by a previous run of the same input, and record the ones processed by this one.\n\
+           The number of records processed is kept in a file next to the input (like transactions.csv.progress),\n\
+           and the state of the accounts is recovered from the WAL_FILE, which is required.",
+        ),
+    )
+    .arg(
+      Arg::with_name("sample")
+        .long("sample")
+        .takes_value(true)
+        .conflicts_with_all(&["head", "clients-sample"])
+        .help("Only process the transactions of a percentage of the clients, like 1%"),
+    )
+    .arg(
+      Arg::with_name("sample-seed")
+        .long("sample-seed")
+        .takes_value(true)
+        .requires("sample")
+        .help("Seed to choose the clients of the sample (0 by default)"),
+    )
+    .arg(
+      Arg::with_name("head")
+        .long("head")
+        .takes_value(true)
+        .conflicts_with("clients-sample")
+        .help("Only process the first N transactions"),
+    )
+    .arg(
+      Arg::with_name("clients-sample")
+        .long("clients-sample")
+        .takes_value(true)
+        .help("Only process the transactions of the first N clients found"),
+    )
+    .arg(
+      Arg::with_name("locked-only")
+        .long("locked-only")
+        .global(true)
+        .help("Only report the accounts that are locked"),
+    )
+    .arg(
+      Arg::with_name("has-open-disputes")
+        .long("has-open-disputes")
+        .global(true)
+        .help("Only report the accounts with some transaction in dispute"),
+    )
+    .arg(
+      Arg::with_name("min-total")
+        .long("min-total")
+        .takes_value(true)
+        .global(true)
+        .help("Only report the accounts with a total greater or equal than this"),
+    )
+    .arg(
+      Arg::with_name("max-total")
+        .long("max-total")
+        .takes_value(true)
+        .global(true)
+        .help("Only report the accounts with a total lower or equal than this"),
+    )
+    .arg(
+      Arg::with_name("client-ids")
+        .long("client-ids")
+        .takes_value(true)
+        .global(true)
+        .help("Only report the accounts of the clients in a range, like 1-100"),
+    )
+    .subcommand(
+      SubCommand::with_name(RECONCILE_COMMAND)
+        .about("Reconciles the accounts against the balances of an external source")
+        .arg(
+          Arg::with_name("balances")
+            .required(true)
+            .help("The balances file, with the client and total columns"),
+        )
+        .arg(
+          Arg::with_name("tolerance")
+            .help("The maximum difference allowed between the totals (zero by default)"),
+        )
+        .after_help(
+          "The breaks report is written as CSV, with one row for every client \
+           whose total differs from the external balance, or that is missing on either side.",
+        ),
+    )
+    .subcommand(
+      SubCommand::with_name(HISTORY_COMMAND)
+        .about("Writes the history of the transactions recorded by the accounts")
+        .after_help(
+          "The history is written as CSV, with one row for every transaction recorded by an account, \
+           its amount and its dispute state. The rejected transactions are not included.",
+        ),
+    )
+    .subcommand(
+      SubCommand::with_name(STATEMENTS_COMMAND)
+        .about("Writes the statement of every client, with the balances after each transaction")
+        .arg(
+          Arg::with_name("json")
+            .long("json")
+            .help("Write the statements as newline delimited JSON instead of CSV"),
+        )
+        .after_help(
+          "The statements are written client by client, with one row for every transaction accepted for the client \
+           in the order it was processed: its ID, type and amount (the one of the disputed transaction for disputes, \
+           resolves and chargebacks), and the available, held and total funds right after it.",
+        ),
+    )
+    .subcommand(
+      SubCommand::with_name(REPL_COMMAND)
+        .about("Answers the queries read from the stdin about the transactions of the input")
+        .after_help(
+          "The transactions of the input (which can't be the stdin) are processed first, and then one query \
+           is read per line from the stdin:\n\
+           - history client=<id>: the timeline of the client as CSV, with every transaction applied to its account \
+           and the available, held and total funds right after it",
+        ),
+    )
+    .subcommand(
+      SubCommand::with_name(DISPUTES_COMMAND)
+        .about("Writes the accounts with open disputes")
+        .after_help(
+          "The report is written as CSV, with one row for every account with some transaction in dispute: \
+           the number of open disputes, the funds they hold, and whether the account is locked.",
+        ),
+    )
+    .subcommand(
+      SubCommand::with_name(SUB_ACCOUNTS_COMMAND)
+        .about("Writes the funds of the sub-accounts of the clients")
+        .after_help(
+          "The report is written as CSV, with one row for every sub-account addressed as `client:sub`: \
+           its available, held and total funds, and whether the client is locked. The clients without \
+           sub-accounts only have the main one (`client:0`), with all their funds.",
+        ),
+    )
+    .subcommand(
+      SubCommand::with_name(EXPORT_BUNDLE_COMMAND)
+        .about("Writes an audit bundle of the processing of the transactions")
+        .after_help(
+          "The bundle is a tar archive with the manifest of the run (manifest.json), the configuration of the engine \
+           (config.txt), the stats (stats.json), the accounts report (accounts.csv), the open disputes (disputes.csv), \
+           the accepted transactions in the format of the write-ahead log (audit.csv), and the fingerprint of the \
+           input (input.sha256) when it is a file.",
+        ),
+    )
+    .subcommand(
+      SubCommand::with_name(GENERATE_COMMAND)
+        .about("Writes a synthetic dataset of transactions")
+        .arg(
+          Arg::with_name("transactions")
+            .long("transactions")
+            .takes_value(true)
+            .help("The number of transactions (1000000 by default)"),
+        )
+        .arg(
+          Arg::with_name("clients")
+            .long("clients")
+            .takes_value(true)
+            .help("The number of clients (1000 by default)"),
+        )
+        .arg(
+          Arg::with_name("dispute-ratio")
+            .long("dispute-ratio")
+            .takes_value(true)
+            .help("The fraction of the deposits that are disputed (0.01 by default)"),
+        )
+        .arg(
+          Arg::with_name("chargeback-ratio")
+            .long("chargeback-ratio")
+            .takes_value(true)
+            .help("The fraction of the disputes that are charged back (0.2 by default)"),
+        )
+        .arg(
+          Arg::with_name("seed")
+            .long("seed")
+            .takes_value(true)
+            .help("The seed of the dataset, which is the same for the same seed (0 by default)"),
+        )
+        .after_help(
+          "The transactions are written as CSV, with the type, client, tx and amount columns. \
+           The disputes refer to recent deposits, and are eventually either resolved or charged back.",
+        ),
+    )
+    .subcommand(
+      SubCommand::with_name(COMPLETIONS_COMMAND)
+        .about("Writes the completions of the command line for a shell")
+        .arg(
+          Arg::with_name("shell")
+            .required(true)
+            .possible_values(&Shell::variants())
+            .help("The shell to generate the completions for"),
+        )
+        .after_help("For example: toy-payments-engine completions bash >/etc/bash_completion.d/toy-payments-engine"),
+    );
+
+  let app = SETTINGS
+    .iter()
+    .fold(app, |app, setting| app.arg(setting.arg()));
+
+  #[cfg(feature = "http")]
+  let app = app.subcommand(
+    SubCommand::with_name(SERVE_COMMAND)
+      .about("Serves the payments engine through HTTP")
+      .arg(
+        Arg::with_name("address")
+          .required(true)
+          .help("The address to listen to, like 127.0.0.1:8080"),
+      ),
+  );
+
+  #[cfg(feature = "http")]
+  let app = app.subcommand(
+    SubCommand::with_name(SERVE_REPORT_COMMAND)
+      .about("Serves the accounts report of a snapshot through HTTP, rejecting any transaction")
+      .arg(
+        Arg::with_name("address")
+          .required(true)
+          .help("The address to listen to, like 127.0.0.1:8080"),
+      )
+      .arg(
+        Arg::with_name("snapshot")
+          .long("snapshot")
+          .takes_value(true)
+          .value_name("FILE")
+          .required(true)
+          .help("The snapshot container with the accounts to serve"),
+      ),
+  );
+
+  #[cfg(feature = "grpc")]
+  let app = app.subcommand(
+    SubCommand::with_name(SERVE_GRPC_COMMAND)
+      .about("Serves the payments engine through gRPC")
+      .arg(
+        Arg::with_name("address")
+          .required(true)
+          .help("The address to listen to, like 127.0.0.1:50051"),
+      ),
+  );
+
+  app
+}
+
+#[cfg(test)]
+mod tests {
+
+  use rust_decimal_macros::dec;
+
+  use super::*;
+
+  #[test]
+  fn parse_process() {
+    assert_eq!(
+      Cli::parse_from(vec!["bin", "transactions.csv"]).unwrap(),
+      Cli {
+        command: Command::Process,
+        input: Some("transactions.csv".to_string()),
+        output: None,
+        format: ReportFormat::Csv,
+        engine: Engine::Memory,
+        workers: None,
+        allow_config_change: false,
+        steal_lock: false,
+        resume: false,
+        sampling: None,
+        log_format: LogFormat::Text,
+        filter: AccountFilter::default(),
+        settings: Settings::default(),
+        config: ConfigFile::default(),
+      }
+    );
+
+    assert_eq!(
+      Cli::parse_from(vec![
+        "bin",
+        "--input",
+        "transactions.csv",
+        "--output",
+        "report.json",
+        "--format",
+        "json",
+        "--workers",
+        "2",
+        "--errors-file",
+        "rejected.csv",
+        "--allow-config-change",
+        "--steal-lock",
+        "--resume",
+        "--log-format",
+        "json",
+      ])
+      .unwrap(),
+      Cli {
+        command: Command::Process,
+        input: Some("transactions.csv".to_string()),
+        output: Some("report.json".to_string()),
+        format: ReportFormat::Json,
+        engine: Engine::Memory,
+        workers: Some(2),
+        allow_config_change: true,
+        steal_lock: true,
+        resume: true,
+        sampling: None,
+        log_format: LogFormat::Json,
+        filter: AccountFilter::default(),
+        settings: Settings::new(vec![(crate::ERRORS_FILE_VAR, "rejected.csv".to_string())]),
+        config: ConfigFile::default(),
+      }
+    );
+  }
+
+  #[test]
+  fn parse_config_defaults() {
+    let path = std::env::temp_dir().join(format!(
+      "toy-payments-engine-config-{}.toml",
+      std::process::id()
+    ));
+    std::fs::write(
+      &path,
+      "[processing]\nworkers = 2\npartitions = 8\n[io]\nformat = \"json\"\n[output]\nreport = \"accounts.json\"\n",
+    )
+    .unwrap();
+    let config = path.to_str().unwrap();
+
+    let cli = Cli::parse_from(vec!["bin", "--config", config]).unwrap();
+    assert_eq!(cli.workers, Some(2));
+    assert_eq!(cli.format, ReportFormat::Json);
+    assert_eq!(cli.output, Some("accounts.json".to_string()));
+    assert_eq!(cli.settings.get("PARTITIONS"), Some("8".to_string()));
+
+    let cli = Cli::parse_from(vec![
+      "bin", "-c", config, "--format", "csv", "-o", "out.csv",
+    ])
+    .unwrap();
+    assert_eq!(cli.workers, Some(2));
+    assert_eq!(cli.format, ReportFormat::Csv);
+    assert_eq!(cli.output, Some("out.csv".to_string()));
+
+    let cli = Cli::parse_from(vec!["bin", "-c", config, "--partitions", "2"]).unwrap();
+    assert_eq!(cli.settings.get("PARTITIONS"), Some("2".to_string()));
+
+    std::fs::remove_file(&path).unwrap();
+  }
+
+  #[test]
+  fn parse_settings() {
+    let cli = Cli::parse_from(vec!["bin", "--partitions", "4", "--check-invariants"]).unwrap();
+    assert_eq!(
+      cli.settings.get(crate::PARTITIONS_VAR),
+      Some("4".to_string())
+    );
+    assert!(cli.settings.enabled(crate::CHECK_INVARIANTS_VAR));
+    assert!(!cli.settings.contains(crate::WAL_FILE_VAR));
+
+    // the settings can be given after the subcommands too
+    let cli = Cli::parse_from(vec!["bin", "history", "--amounts", "strict"]).unwrap();
+    assert_eq!(
+      cli.settings.get(crate::AMOUNTS_VAR),
+      Some("strict".to_string())
+    );
+  }
+
+  #[test]
+  fn parse_env_switch() {
+    let name = "TOY_PAYMENTS_ENGINE_TEST_SWITCH";
+    assert_eq!(env_switch(name).unwrap(), None);
+    std::env::set_var(name, "true");
+    assert_eq!(env_switch(name).unwrap(), Some(true));
+    std::env::set_var(name, "false");
+    assert_eq!(env_switch(name).unwrap(), Some(false));
+    std::env::set_var(name, "no");
+    assert!(env_switch(name).is_err());
+    std::env::remove_var(name);
+  }
+
+  #[test]
+  fn resolve_switches_by_their_value() {
+    let config = ConfigFile::from_toml("[processing]\ncheck_invariants = true\n").unwrap();
+    let env = |value: Option<bool>| {
+      move |name: &str| -> Result<Option<bool>> {
+        Ok(value.filter(|_| name == crate::CHECK_INVARIANTS_VAR))
+      }
+    };
+
+    // the environment variable set to false disables the switch enabled by the file
+    let matches = app().get_matches_from(vec!["bin"]);
+    let settings = Settings::resolve(&matches, &config, env(Some(false))).unwrap();
+    assert!(!settings.enabled(crate::CHECK_INVARIANTS_VAR));
+
+    let settings = Settings::resolve(&matches, &config, env(None)).unwrap();
+    assert!(settings.enabled(crate::CHECK_INVARIANTS_VAR));
+
+    let settings = Settings::resolve(&matches, &ConfigFile::default(), env(Some(true))).unwrap();
+    assert!(settings.enabled(crate::CHECK_INVARIANTS_VAR));
+
+    // the flag takes precedence over both of them
+    let matches = app().get_matches_from(vec!["bin", "--check-invariants"]);
+    let settings = Settings::resolve(&matches, &config, env(Some(false))).unwrap();
+    assert!(settings.enabled(crate::CHECK_INVARIANTS_VAR));
+  }
+
+  #[test]
+  fn parse_reconcile() {
+    let cli = Cli::parse_from(vec![
+      "bin",
+      "reconcile",
+      "balances.csv",
+      "0.01",
+      "-i",
+      "tx.csv",
+    ])
+    .unwrap();
+
+    assert_eq!(
+      cli.command,
+      Command::Reconcile {
+        balances: "balances.csv".to_string(),
+        tolerance: dec!(0.01),
+      }
+    );
+    assert_eq!(cli.input, Some("tx.csv".to_string()));
+  }
+
+  #[test]
+  fn parse_history() {
+    let cli = Cli::parse_from(vec!["bin", "history", "-i", "tx.csv", "-o", "history.csv"]).unwrap();
+
+    assert_eq!(cli.command, Command::History);
+    assert_eq!(cli.input, Some("tx.csv".to_string()));
+    assert_eq!(cli.output, Some("history.csv".to_string()));
+  }
+
+  #[test]
+  fn parse_account_filter() {
+    let cli = Cli::parse_from(vec![
+      "bin",
+      "--locked-only",
+      "--min-total",
+      "10.5",
+      "--client-ids",
+      "1-100",
+    ])
+    .unwrap();
+
+    assert_eq!(
+      cli.filter,
+      AccountFilter {
+        locked_only: true,
+        min_total: Some(dec!(10.5)),
+        client_ids: Some(1..=100),
+        ..AccountFilter::default()
+      }
+    );
+    assert_eq!(
+      Cli::parse_from(vec!["bin"]).unwrap().filter,
+      AccountFilter::default()
+    );
+    assert!(Cli::parse_from(vec!["bin", "--client-ids", "100-"]).is_err());
+  }
+
+  #[test]
+  fn parse_disputes() {
+    let cli = Cli::parse_from(vec!["bin", "disputes", "-i", "tx.csv", "--locked-only"]).unwrap();
+
+    assert_eq!(cli.command, Command::Disputes);
+    assert_eq!(cli.input, Some("tx.csv".to_string()));
+    assert!(cli.filter.locked_only);
+  }
+
+  #[test]
+  fn parse_sub_accounts() {
+    let cli = Cli::parse_from(vec!["bin", "sub-accounts", "--locked-only"]).unwrap();
+
+    assert_eq!(cli.command, Command::SubAccounts);
+    assert!(cli.filter.locked_only);
+  }
+
+  #[test]
+  fn parse_statements() {
+    let cli = Cli::parse_from(vec!["bin", "statements", "--json", "-i", "tx.csv"]).unwrap();
+
+    assert_eq!(cli.command, Command::Statements { json: true });
+    assert_eq!(cli.input, Some("tx.csv".to_string()));
+  }
+
+  #[test]
+  fn parse_repl() {
+    let cli = Cli::parse_from(vec!["bin", "repl", "-i", "tx.csv"]).unwrap();
+
+    assert_eq!(cli.command, Command::Repl);
+    assert_eq!(cli.input, Some("tx.csv".to_string()));
+  }
+
+  #[test]
+  fn parse_export_bundle() {
+    let cli = Cli::parse_from(vec![
+      "bin",
+      "export-bundle",
+      "-i",
+      "tx.csv",
+      "-o",
+      "bundle.tar",
+    ])
+    .unwrap();
+
+    assert_eq!(cli.command, Command::ExportBundle);
+    assert_eq!(cli.input, Some("tx.csv".to_string()));
+    assert_eq!(cli.output, Some("bundle.tar".to_string()));
+  }
+
+  #[test]
+  fn parse_generate() {
+    let cli = Cli::parse_from(vec![
+      "bin",
+      "generate",
+      "--transactions",
+      "100",
+      "--dispute-ratio",
+      "0.5",
+      "-o",
+      "tx.csv",
+    ])
+    .unwrap();
+
+    assert_eq!(
+      cli.command,
+      Command::Generate(GeneratorConfig {
+        transactions: 100,
+        dispute_ratio: 0.5,
+        ..GeneratorConfig::default()
+      })
+    );
+    assert_eq!(cli.output, Some("tx.csv".to_string()));
+    assert!(Cli::parse_from(vec!["bin", "generate", "--clients", "many"]).is_err());
+  }
+
+  #[test]
+  fn parse_sampling() {
+    let sampling = |args: Vec<&str>| Cli::parse_from(args).unwrap().sampling;
+
+    assert_eq!(
+      sampling(vec!["bin", "--head", "100"]),
+      Some(Sampling::Head(100))
+    );
+    assert_eq!(
+      sampling(vec!["bin", "--clients-sample", "10"]),
+      Some(Sampling::Clients(10))
+    );
+    assert_eq!(
+      sampling(vec!["bin", "--sample", "5%", "--sample-seed", "3"]),
+      Some(Sampling::Fraction {
+        fraction: 0.05,
+        seed: 3
+      })
+    );
+    assert!(Cli::parse_from(vec!["bin", "--sample", "120%"]).is_err());
+    assert!(Cli::parse_from(vec!["bin", "--head", "1", "--sample", "1%"]).is_err());
+  }
+
+  #[cfg(feature = "http")]
+  #[test]
+  fn parse_serve_report() {
+    let cli = Cli::parse_from(vec![
+      "bin",
+      "serve-report",
+      "127.0.0.1:8080",
+      "--snapshot",
+      "eod.snapshot",
+    ])
+    .unwrap();
+
+    assert_eq!(
+      cli.command,
+      Command::ServeReport {
+        address: "127.0.0.1:8080".to_string(),
+        snapshot: "eod.snapshot".to_string(),
+      }
+    );
+    assert!(Cli::parse_from(vec!["bin", "serve-report", "127.0.0.1:8080"]).is_err());
+  }
+
+  #[test]
+  fn parse_completions() {
+    let cli = Cli::parse_from(vec!["bin", "completions", "bash"]).unwrap();
+
+    assert_eq!(
+      cli.command,
+      Command::Completions {
+        shell: "bash".to_string()
+      }
+    );
+    assert!(Cli::parse_from(vec!["bin", "completions", "cmd"]).is_err());
+  }
+
+  #[test]
+  fn write_bash_completions() {
+    let mut out = Vec::<u8>::new();
+
+    write_completions("bash", &mut out).unwrap();
+
+    let completions = String::from_utf8_lossy(&out);
+    assert!(completions.contains("reconcile"));
+    assert!(completions.contains("--format"));
+    assert!(write_completions("cmd", &mut out).is_err());
+  }
+
+  #[test]
+  fn parse_persistent_engine() {
+    let cli = Cli::parse_from(vec![
+      "bin",
+      "--engine",
+      "persistent",
+      "--database",
+      "payments.wal",
+    ])
+    .unwrap();
+
+    assert_eq!(
+      cli.engine,
+      Engine::Persistent {
+        path: "payments.wal".to_string()
+      }
+    );
+    assert!(Cli::parse_from(vec!["bin", "--engine", "persistent"]).is_err());
+  }
+
+  #[cfg(feature = "sqlite")]
+  #[test]
+  fn parse_engine() {
+    let cli = Cli::parse_from(vec![
+      "bin",
+      "--engine",
+      "sqlite",
+      "--database",
+      "accounts.sqlite",
+    ])
+    .unwrap();
+
+    assert_eq!(
+      cli.engine,
+      Engine::Sqlite {
+        path: "accounts.sqlite".to_string()
+      }
+    );
+    assert!(Cli::parse_from(vec!["bin", "--engine", "sqlite"]).is_err());
+  }
+
+  #[test]
+  fn parse_invalid_arguments() {
+    assert!(Cli::parse_from(vec!["bin", "--format", "xml"]).is_err());
+    assert!(Cli::parse_from(vec!["bin", "--engine", "disk"]).is_err());
+    assert!(Cli::parse_from(vec!["bin", "--database", "accounts.sqlite"]).is_err());
+    assert!(Cli::parse_from(vec!["bin", "--workers", "many"]).is_err());
+    assert!(Cli::parse_from(vec!["bin", "reconcile"]).is_err());
+  }
+}
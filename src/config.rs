@@ -0,0 +1,245 @@
+//! The configuration file of the process (`--config`), in TOML or YAML (when its extension is `.yaml` or `.yml`).
+//!
+//! It gathers in a single place the settings that would be given otherwise as command line flags and environment variables.
+//! Both of them take precedence over the file, so a shared configuration can be tuned for a single run:
+//!
+//! ```toml
+//! [engine]
+//! precision = 4
+//! max_open_disputes = 10
+//! deterministic_report = true
+//!
+//! [processing]
+//! workers = 4
+//! partitions = 8
+//!
+//! [io]
+//! format = "json"
+//! amounts = "strict"
+//!
+//! [output]
+//! report = "accounts.json"
+//! errors = "errors.csv"
+//! wal = "payments.wal"
+//!
+//! [limits]
+//! max_withdrawal = 1000
+//! ```
+
+use std::path::Path;
+
+use anyhow::Result;
+use serde::Deserialize;
+
+use toy_payments_engine::payments::LimitsPolicy;
+
+use crate::cli::ReportFormat;
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ConfigFile {
+  #[serde(default)]
+  pub engine: EngineSettings,
+  #[serde(default)]
+  pub processing: ProcessingSettings,
+  #[serde(default)]
+  pub io: IoSettings,
+  #[serde(default)]
+  pub output: OutputSettings,
+  /// The withdrawal limits, unless a `LIMITS_FILE` is given.
+  pub limits: Option<LimitsPolicy>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct EngineSettings {
+  /// Number of decimal places to round the amounts to (`NORMALIZE_PRECISION`).
+  pub precision: Option<u32>,
+  /// Maximum number of open disputes per account (`MAX_OPEN_DISPUTES`).
+  pub max_open_disputes: Option<usize>,
+  /// Whether to write the accounts report ordered by client (`DETERMINISTIC_REPORT`).
+  pub deterministic_report: Option<bool>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ProcessingSettings {
+  /// Number of worker threads (`--workers`).
+  pub workers: Option<usize>,
+  /// Number of partitions to process the transactions in parallel (`PARTITIONS`).
+  pub partitions: Option<usize>,
+  /// Size in bytes of the chunks to parse the input in parallel (`PARSE_CHUNK_SIZE`).
+  pub parse_chunk_size: Option<usize>,
+  /// Whether to check the invariants of the engine after every transaction (`CHECK_INVARIANTS`).
+  pub check_invariants: Option<bool>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct IoSettings {
+  /// Format of the accounts report (`--format`).
+  pub format: Option<ReportFormat>,
+  /// How to parse the amounts (`AMOUNTS`).
+  pub amounts: Option<String>,
+  /// Version of the schema of the accounts report (`REPORT_SCHEMA`).
+  pub report_schema: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct OutputSettings {
+  /// Where to write the report (`--output`).
+  pub report: Option<String>,
+  /// Where to write the rejected records (`ERRORS_FILE`).
+  pub errors: Option<String>,
+  /// Where to write the duplicated transactions (`DUPLICATES_FILE`).
+  pub duplicates: Option<String>,
+  /// The write-ahead log (`WAL_FILE`).
+  pub wal: Option<String>,
+}
+
+impl ConfigFile {
+  /// The settings of the file given as environment variables otherwise, by the name of their variable.
+  pub fn settings(&self) -> Vec<(&'static str, String)> {
+    let to_string = |value: Option<usize>| value.map(|value| value.to_string());
+    let settings = vec![
+      (
+        crate::NORMALIZE_PRECISION_VAR,
+        self.engine.precision.map(|precision| precision.to_string()),
+      ),
+      (
+        crate::MAX_OPEN_DISPUTES_VAR,
+        to_string(self.engine.max_open_disputes),
+      ),
+      (crate::PARTITIONS_VAR, to_string(self.processing.partitions)),
+      (
+        crate::PARSE_CHUNK_SIZE_VAR,
+        to_string(self.processing.parse_chunk_size),
+      ),
+      (crate::AMOUNTS_VAR, self.io.amounts.clone()),
+      (crate::REPORT_SCHEMA_VAR, self.io.report_schema.clone()),
+      (crate::ERRORS_FILE_VAR, self.output.errors.clone()),
+      (crate::DUPLICATES_FILE_VAR, self.output.duplicates.clone()),
+      (crate::WAL_FILE_VAR, self.output.wal.clone()),
+    ];
+    settings
+      .into_iter()
+      .filter_map(|(name, value)| value.map(|value| (name, value)))
+      .collect()
+  }
+
+  /// The switches of the file given as environment variables otherwise, by the name of their variable.
+  pub fn switches(&self) -> Vec<(&'static str, bool)> {
+    let switches = vec![
+      (
+        crate::DETERMINISTIC_REPORT_VAR,
+        self.engine.deterministic_report,
+      ),
+      (
+        crate::CHECK_INVARIANTS_VAR,
+        self.processing.check_invariants,
+      ),
+    ];
+    switches
+      .into_iter()
+      .filter_map(|(name, enabled)| enabled.map(|enabled| (name, enabled)))
+      .collect()
+  }
+
+  pub fn from_toml(content: &str) -> Result<Self> {
+    toml::from_str(content).map_err(anyhow::Error::from)
+  }
+
+  pub fn from_yaml(content: &str) -> Result<Self> {
+    serde_yaml::from_str(content).map_err(anyhow::Error::from)
+  }
+
+  /// Load the configuration with the format given by the extension of the file, which is TOML by default.
+  pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+    let path = path.as_ref();
+    let content = std::fs::read_to_string(path)
+      .map_err(|err| anyhow::anyhow!("Failed to read {}: {}", path.display(), err))?;
+    match path.extension().and_then(|extension| extension.to_str()) {
+      Some("yaml") | Some("yml") => Self::from_yaml(&content),
+      _ => Self::from_toml(&content),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+
+  use indoc::indoc;
+  use rust_decimal_macros::dec;
+
+  use super::*;
+
+  #[test]
+  fn load_config_from_toml_and_yaml() {
+    let expected = ConfigFile {
+      engine: EngineSettings {
+        precision: Some(4),
+        max_open_disputes: None,
+        deterministic_report: Some(true),
+      },
+      processing: ProcessingSettings {
+        workers: Some(2),
+        partitions: None,
+        parse_chunk_size: None,
+        check_invariants: None,
+      },
+      io: IoSettings {
+        format: Some(ReportFormat::Json),
+        amounts: None,
+        report_schema: Some("v2".to_string()),
+      },
+      output: OutputSettings {
+        report: Some("accounts.json".to_string()),
+        errors: None,
+        duplicates: None,
+        wal: None,
+      },
+      limits: Some(LimitsPolicy {
+        max_withdrawal: Some(dec!(1000)),
+        ..LimitsPolicy::default()
+      }),
+    };
+
+    let toml = ConfigFile::from_toml(indoc! {r#"
+      [engine]
+      precision = 4
+      deterministic_report = true
+      [processing]
+      workers = 2
+      [io]
+      format = "json"
+      report_schema = "v2"
+      [output]
+      report = "accounts.json"
+      [limits]
+      max_withdrawal = 1000
+    "#})
+    .unwrap();
+    assert_eq!(toml, expected);
+
+    let yaml = ConfigFile::from_yaml(indoc! {r#"
+      engine:
+        precision: 4
+        deterministic_report: true
+      processing:
+        workers: 2
+      io:
+        format: json
+        report_schema: v2
+      output:
+        report: accounts.json
+      limits:
+        max_withdrawal: "1000"
+    "#})
+    .unwrap();
+    assert_eq!(yaml, expected);
+
+    assert!(ConfigFile::from_toml("[engine]\nprecission = 4").is_err());
+    assert_eq!(ConfigFile::from_toml("").unwrap(), ConfigFile::default());
+  }
+}
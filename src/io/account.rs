@@ -1,11 +1,14 @@
 use rust_decimal::Decimal;
 use serde::Serialize;
 
-use crate::payments::{self, ClientId};
+use super::baseline::Baseline;
+use super::metadata::{ClientMetadata, MetadataField};
+use crate::payments::{self, ClientId, SubAccountId, TransactionId};
 
 const MAX_PRECISION: u32 = 4;
 
-/// A report on an account state used to serialize into a CSV file
+/// A report on an account state used to serialize into a CSV file,
+/// followed by the changes since a [`Baseline`] when it is given (see [`AccountReport::with_baseline`])
 #[derive(Debug, PartialEq, Serialize)]
 pub struct AccountReport {
   client: ClientId,
@@ -13,22 +16,419 @@ pub struct AccountReport {
   held: Decimal,
   total: Decimal,
   locked: bool,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  available_change: Option<Decimal>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  held_change: Option<Decimal>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  newly_locked: Option<bool>,
+}
+
+impl AccountReport {
+  /// Add the `available_change`, `held_change` and `newly_locked` columns, with the changes since the baseline.
+  pub fn with_baseline(mut self, baseline: &Baseline) -> Self {
+    let deltas = baseline.deltas(self.client, self.available, self.held, self.locked);
+    self.available_change = Some(deltas.available_change);
+    self.held_change = Some(deltas.held_change);
+    self.newly_locked = Some(deltas.newly_locked);
+    self
+  }
 }
 
 impl From<payments::AccountReport> for AccountReport {
   /// A conversion between the domain representation of an account report into a serializable structure
   fn from(account_report: payments::AccountReport) -> Self {
+    let (available, held, total) = rounded_funds(account_report.available, account_report.held);
+    AccountReport {
+      client: account_report.client_id,
+      available,
+      held,
+      total,
+      locked: account_report.locked,
+      available_change: None,
+      held_change: None,
+      newly_locked: None,
+    }
+  }
+}
+
+impl<'a> From<payments::AccountView<'a>> for AccountReport {
+  /// The same conversion, but reading the funds directly from the state of the engine
+  fn from(view: payments::AccountView<'a>) -> Self {
+    let (available, held, total) = rounded_funds(view.available(), view.held());
     AccountReport {
+      client: view.client_id(),
+      available,
+      held,
+      total,
+      locked: view.locked(),
+      available_change: None,
+      held_change: None,
+      newly_locked: None,
+    }
+  }
+}
+
+/// Version of the schema of the accounts report.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ReportSchema {
+  /// The original columns: `client, available, held, total, locked`.
+  V1,
+  /// Adds the `schema_version` first, and the `status`, `open_disputes` and `charged_back_total` at the end,
+  /// followed by the `escrow_interest` when it is tracked, the `exposure_alert` when it is watched, the `dormant` flag when it is tracked, and the selected fields of the [`ClientMetadata`], when available.
+  V2,
+  /// The original columns followed by the `status`, `currency`, `fees` and `risk_score` (see [`AccountReportExtended`]),
+  /// for the consumers migrating from the original columns, which can keep reading them at the same positions.
+  Extended,
+}
+
+impl Default for ReportSchema {
+  fn default() -> Self {
+    ReportSchema::V1
+  }
+}
+
+/// Status of the account in the version 2 and the extended reports
+#[derive(Debug, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Status {
+  Active,
+  Locked,
+  /// Only reported by the extended report, when the dormancy is tracked.
+  Dormant,
+}
+
+/// A report on an account state following the version 2 of the schema
+#[derive(Debug, PartialEq, Serialize)]
+pub struct AccountReportV2 {
+  schema_version: u8,
+  client: ClientId,
+  available: Decimal,
+  held: Decimal,
+  total: Decimal,
+  locked: bool,
+  status: Status,
+  open_disputes: usize,
+  charged_back_total: Decimal,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  escrow_interest: Option<Decimal>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  exposure_alert: Option<bool>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  dormant: Option<bool>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  name: Option<String>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  tier: Option<String>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  available_change: Option<Decimal>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  held_change: Option<Decimal>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  newly_locked: Option<bool>,
+}
+
+impl AccountReportV2 {
+  /// Join the fields of the client metadata, which are only serialized when selected.
+  pub fn with_metadata(mut self, metadata: &ClientMetadata) -> Self {
+    self.name = metadata.field(self.client, MetadataField::Name);
+    self.tier = metadata.field(self.client, MetadataField::Tier);
+    self
+  }
+
+  /// Add the changes since the baseline after the rest of columns, like [`AccountReport::with_baseline`].
+  pub fn with_baseline(mut self, baseline: &Baseline) -> Self {
+    let deltas = baseline.deltas(self.client, self.available, self.held, self.locked);
+    self.available_change = Some(deltas.available_change);
+    self.held_change = Some(deltas.held_change);
+    self.newly_locked = Some(deltas.newly_locked);
+    self
+  }
+}
+
+impl From<payments::AccountReport> for AccountReportV2 {
+  fn from(account_report: payments::AccountReport) -> Self {
+    let status = if account_report.locked {
+      Status::Locked
+    } else {
+      Status::Active
+    };
+    let (available, held, total) = rounded_funds(account_report.available, account_report.held);
+
+    AccountReportV2 {
+      schema_version: 2,
+      client: account_report.client_id,
+      available,
+      held,
+      total,
+      locked: account_report.locked,
+      status,
+      open_disputes: account_report.open_disputes,
+      charged_back_total: with_max_precission(account_report.charged_back_total),
+      escrow_interest: account_report.escrow_interest.map(with_max_precission),
+      exposure_alert: account_report.exposure_alert,
+      dormant: account_report.dormant,
+      name: None,
+      tier: None,
+      available_change: None,
+      held_change: None,
+      newly_locked: None,
+    }
+  }
+}
+
+/// A report on an account state following the extended schema, which adds columns after the original ones:
+/// - the `status`, which is `locked`, `dormant` (when the dormancy is tracked) or `active`
+/// - the `currency` of the funds, which is empty unless it is given, as the engine is not aware of it
+/// - the `fees` charged to the client, like the chargeback fees
+/// - the `risk_score`, from 0 to 1, which is the share of the funds of the client that are held or were charged back,
+///   or 1 for the locked accounts
+#[derive(Debug, PartialEq, Serialize)]
+pub struct AccountReportExtended {
+  client: ClientId,
+  available: Decimal,
+  held: Decimal,
+  total: Decimal,
+  locked: bool,
+  status: Status,
+  currency: String,
+  fees: Decimal,
+  risk_score: Decimal,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  available_change: Option<Decimal>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  held_change: Option<Decimal>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  newly_locked: Option<bool>,
+}
+
+impl AccountReportExtended {
+  pub fn with_currency(mut self, currency: Option<&str>) -> Self {
+    self.currency = currency.unwrap_or_default().to_string();
+    self
+  }
+
+  /// Add the changes since the baseline after the rest of columns, like [`AccountReport::with_baseline`].
+  pub fn with_baseline(mut self, baseline: &Baseline) -> Self {
+    let deltas = baseline.deltas(self.client, self.available, self.held, self.locked);
+    self.available_change = Some(deltas.available_change);
+    self.held_change = Some(deltas.held_change);
+    self.newly_locked = Some(deltas.newly_locked);
+    self
+  }
+}
+
+impl From<payments::AccountReport> for AccountReportExtended {
+  fn from(account_report: payments::AccountReport) -> Self {
+    let status = if account_report.locked {
+      Status::Locked
+    } else if account_report.dormant == Some(true) {
+      Status::Dormant
+    } else {
+      Status::Active
+    };
+    let (available, held, total) = rounded_funds(account_report.available, account_report.held);
+
+    AccountReportExtended {
       client: account_report.client_id,
-      available: with_max_precission(account_report.available),
+      available,
+      held,
+      total,
+      locked: account_report.locked,
+      status,
+      currency: String::new(),
+      fees: with_max_precission(account_report.fees),
+      risk_score: risk_score(&account_report),
+      available_change: None,
+      held_change: None,
+      newly_locked: None,
+    }
+  }
+}
+
+/// The share of the funds of the client that are held or were charged back, from 0 to 1, or 1 for the locked accounts.
+fn risk_score(account_report: &payments::AccountReport) -> Decimal {
+  if account_report.locked {
+    return Decimal::ONE;
+  }
+  let held = account_report.held.max(Decimal::ZERO);
+  let at_risk = held.checked_add(account_report.charged_back_total);
+  let funds = account_report
+    .available
+    .max(Decimal::ZERO)
+    .checked_add(held)
+    .and_then(|funds| funds.checked_add(account_report.charged_back_total));
+  match (at_risk, funds) {
+    (Some(at_risk), Some(funds)) if !funds.is_zero() => {
+      with_max_precission((at_risk / funds).min(Decimal::ONE))
+    }
+    (_, Some(_)) => Decimal::ZERO,
+    _ => Decimal::ONE,
+  }
+}
+
+/// The kinds of the recorded transactions supported by the transactions history writer
+#[derive(Debug, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TransactionKind {
+  Deposit,
+  Withdrawal,
+  Transfer,
+  Authorization,
+  VoidedAuthorization,
+  Fee,
+}
+
+impl From<payments::TransactionKind> for TransactionKind {
+  fn from(kind: payments::TransactionKind) -> Self {
+    match kind {
+      payments::TransactionKind::Deposit => TransactionKind::Deposit,
+      payments::TransactionKind::Withdrawal => TransactionKind::Withdrawal,
+      payments::TransactionKind::Transfer => TransactionKind::Transfer,
+      payments::TransactionKind::Authorization => TransactionKind::Authorization,
+      payments::TransactionKind::VoidedAuthorization => TransactionKind::VoidedAuthorization,
+      payments::TransactionKind::Fee => TransactionKind::Fee,
+    }
+  }
+}
+
+/// The dispute states of the recorded transactions supported by the transactions history writer
+#[derive(Debug, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DisputeState {
+  Recorded,
+  Disputed,
+  Resolved,
+  ChargedBack,
+}
+
+impl From<payments::DisputeState> for DisputeState {
+  fn from(state: payments::DisputeState) -> Self {
+    match state {
+      payments::DisputeState::Recorded => DisputeState::Recorded,
+      payments::DisputeState::Disputed => DisputeState::Disputed,
+      payments::DisputeState::Resolved => DisputeState::Resolved,
+      payments::DisputeState::ChargedBack => DisputeState::ChargedBack,
+    }
+  }
+}
+
+/// A transaction recorded by an account used to serialize the transactions history into a CSV file
+#[derive(Debug, PartialEq, Serialize)]
+pub struct TransactionEntry {
+  client: ClientId,
+  tx: TransactionId,
+  #[serde(rename = "type")]
+  kind: TransactionKind,
+  amount: Decimal,
+  state: DisputeState,
+  in_dispute: bool,
+  disputed_at: Option<u64>,
+}
+
+impl From<payments::TransactionInfo> for TransactionEntry {
+  fn from(transaction: payments::TransactionInfo) -> Self {
+    TransactionEntry {
+      client: transaction.client_id,
+      tx: transaction.transaction_id,
+      kind: transaction.kind.into(),
+      amount: with_max_precission(transaction.amount),
+      state: transaction.state.into(),
+      in_dispute: transaction.in_dispute(),
+      disputed_at: transaction.disputed_at,
+    }
+  }
+}
+
+/// An account with open disputes used to serialize the open disputes report into a CSV file
+#[derive(Debug, PartialEq, Serialize)]
+pub struct OpenDisputesEntry {
+  client: ClientId,
+  open_disputes: usize,
+  held: Decimal,
+  locked: bool,
+}
+
+impl From<payments::AccountReport> for OpenDisputesEntry {
+  fn from(account_report: payments::AccountReport) -> Self {
+    OpenDisputesEntry {
+      client: account_report.client_id,
+      open_disputes: account_report.open_disputes,
       held: with_max_precission(account_report.held),
-      total: with_max_precission(account_report.total),
       locked: account_report.locked,
     }
   }
 }
 
-fn with_max_precission(mut value: Decimal) -> Decimal {
+/// A sub-account used to serialize the sub-accounts report into a CSV file, addressed as `client:sub`
+#[derive(Debug, PartialEq, Serialize)]
+pub struct SubAccountEntry {
+  account: String,
+  available: Decimal,
+  held: Decimal,
+  total: Decimal,
+  locked: bool,
+}
+
+impl SubAccountEntry {
+  /// The entries of all the sub-accounts of the client, which is only the main one with all the funds
+  /// when it has no others. They share the lock of the client.
+  pub fn from_account_report(account_report: payments::AccountReport) -> Vec<Self> {
+    let client_id = account_report.client_id;
+    let locked = account_report.locked;
+    let entry = |sub_account: SubAccountId, available: Decimal, held: Decimal| {
+      let (available, held, total) = rounded_funds(available, held);
+      SubAccountEntry {
+        account: format!("{}:{}", client_id, sub_account),
+        available,
+        held,
+        total,
+        locked,
+      }
+    };
+    if account_report.sub_accounts.is_empty() {
+      vec![entry(
+        payments::MAIN_SUB_ACCOUNT,
+        account_report.available,
+        account_report.held,
+      )]
+    } else {
+      account_report
+        .sub_accounts
+        .iter()
+        .map(|sub_account| {
+          entry(
+            sub_account.sub_account,
+            sub_account.available,
+            sub_account.held,
+          )
+        })
+        .collect()
+    }
+  }
+}
+
+/// The `available`, `held` and `total` funds with the maximum precision, where the `total` is derived
+/// from the rounded `available` and `held`, so the report always satisfies `available + held = total`.
+/// Rounding the `total` independently could make it differ from that sum in the last decimal.
+/// The account report with its funds rounded the same way they are written out, so they can be compared with other sources.
+pub fn rounded_account_report(account_report: payments::AccountReport) -> payments::AccountReport {
+  let (available, held, total) = rounded_funds(account_report.available, account_report.held);
+  payments::AccountReport {
+    available,
+    held,
+    total,
+    ..account_report
+  }
+}
+
+pub(super) fn rounded_funds(available: Decimal, held: Decimal) -> (Decimal, Decimal, Decimal) {
+  let available = with_max_precission(available);
+  let held = with_max_precission(held);
+  (available, held, with_max_precission(available + held))
+}
+
+pub(super) fn with_max_precission(mut value: Decimal) -> Decimal {
   if value.scale() > MAX_PRECISION {
     value.rescale(MAX_PRECISION);
   }
@@ -57,12 +457,122 @@ mod tests {
         client: 1,
         available: dec!(100.1235),
         held: dec!(10.0123),
-        total: dec!(110.5678),
-        locked: false
+        total: dec!(110.1358),
+        locked: false,
+        available_change: None,
+        held_change: None,
+        newly_locked: None,
       }
     )
   }
 
+  #[test]
+  fn from_payments_account_report_v2() {
+    let payments_account_report =
+      payments::AccountReport::new(1, dec!(100.12345), dec!(10.012345), dec!(110.5678), true)
+        .with_disputes(1, dec!(20.00001));
+
+    let account_report: AccountReportV2 = payments_account_report.into();
+
+    assert_eq!(
+      account_report,
+      AccountReportV2 {
+        schema_version: 2,
+        client: 1,
+        available: dec!(100.1235),
+        held: dec!(10.0123),
+        total: dec!(110.1358),
+        locked: true,
+        status: Status::Locked,
+        open_disputes: 1,
+        charged_back_total: dec!(20.0000),
+        escrow_interest: None,
+        exposure_alert: None,
+        dormant: None,
+        name: None,
+        tier: None,
+        available_change: None,
+        held_change: None,
+        newly_locked: None,
+      }
+    )
+  }
+
+  #[test]
+  fn from_payments_account_report_to_extended() {
+    let cases = vec![
+      (
+        payments::AccountReport::new(1, dec!(60), dec!(20), dec!(80), false)
+          .with_disputes(1, dec!(20))
+          .with_fees(dec!(15)),
+        Status::Active,
+        dec!(0.4),
+      ),
+      (
+        payments::AccountReport::new(2, dec!(90), dec!(0), dec!(90), true),
+        Status::Locked,
+        dec!(1),
+      ),
+      (
+        payments::AccountReport::new(3, dec!(0), dec!(0), dec!(0), false).with_dormant(true),
+        Status::Dormant,
+        dec!(0),
+      ),
+      (
+        payments::AccountReport::new(4, dec!(2), dec!(1), dec!(3), false),
+        Status::Active,
+        dec!(0.3333),
+      ),
+    ];
+
+    for (account_report, status, risk_score) in cases {
+      let fees = account_report.fees;
+      let extended = AccountReportExtended::from(account_report).with_currency(Some("EUR"));
+      assert_eq!(extended.status, status);
+      assert_eq!(extended.currency, "EUR");
+      assert_eq!(extended.fees, fees);
+      assert_eq!(extended.risk_score, risk_score);
+    }
+  }
+
+  #[test]
+  fn from_payments_account_report_derives_total() {
+    let cases = vec![
+      (dec!(0.00005), dec!(0.00005), "0.0001", "0.0001", "0.0002"),
+      (dec!(1.00005), dec!(2.00005), "1.0001", "2.0001", "3.0002"),
+      (dec!(1.00004), dec!(2.00004), "1.0000", "2.0000", "3.0000"),
+      (dec!(-0.00005), dec!(0.00005), "-0.0001", "0.0001", "0"),
+      (dec!(0.33333), dec!(0.66667), "0.3333", "0.6667", "1.0000"),
+      (dec!(10), dec!(0), "10", "0", "10"),
+    ];
+
+    for (available, held, expected_available, expected_held, expected_total) in cases {
+      let total = available + held;
+      let account_report: AccountReport =
+        payments::AccountReport::new(1, available, held, total, false).into();
+
+      assert_eq!(
+        (
+          account_report.available.to_string(),
+          account_report.held.to_string(),
+          account_report.total.to_string()
+        ),
+        (
+          expected_available.to_string(),
+          expected_held.to_string(),
+          expected_total.to_string()
+        ),
+        "available {} held {}",
+        available,
+        held
+      );
+      assert_eq!(
+        account_report.available + account_report.held,
+        account_report.total
+      );
+    }
+  }
+
   #[test]
   fn with_max_precission_rescales() {
     let cases = vec![
@@ -0,0 +1,177 @@
+use std::str::FromStr;
+
+use rust_decimal::Decimal;
+use thiserror::Error;
+
+/// Maximum number of decimal places accepted by the strict parser by default.
+const DEFAULT_MAX_SCALE: u32 = 4;
+
+/// Possible reasons for an amount to be rejected.
+#[derive(Debug, Clone, Error, PartialEq)]
+pub enum AmountError {
+  #[error("Empty amount")]
+  Empty,
+
+  #[error("Unexpected sign in amount: {0}")]
+  UnexpectedSign(String),
+
+  #[error("Unexpected character '{1}' in amount: {0}")]
+  UnexpectedCharacter(String, char),
+
+  #[error("More than one decimal point in amount: {0}")]
+  MultipleDecimalPoints(String),
+
+  #[error("Amount with more than {1} decimal places: {0}")]
+  TooManyDecimalPlaces(String, u32),
+
+  #[error("Invalid amount {0}: {1}")]
+  Invalid(String, String),
+}
+
+/// How the amounts of the transactions are parsed.
+///
+/// The lenient parser accepts anything that [`Decimal`] accepts, which depends on its version
+/// (for example, some versions interpret `1e3` in surprising ways).
+/// The strict parser only accepts digits with, at most, a single decimal point and `max_scale` decimal places,
+/// and an optional sign (`+` or `-`) only when `allow_sign` is set.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AmountParser {
+  Lenient,
+  Strict { allow_sign: bool, max_scale: u32 },
+}
+
+impl AmountParser {
+  /// The strict parser without signs and up to four decimal places.
+  pub fn strict() -> Self {
+    AmountParser::Strict {
+      allow_sign: false,
+      max_scale: DEFAULT_MAX_SCALE,
+    }
+  }
+
+  pub fn parse(&self, value: &str) -> Result<Decimal, AmountError> {
+    if let AmountParser::Strict {
+      allow_sign,
+      max_scale,
+    } = *self
+    {
+      check_strict(value, allow_sign, max_scale)?;
+    }
+
+    Decimal::from_str(value).map_err(|err| AmountError::Invalid(value.to_string(), err.to_string()))
+  }
+}
+
+impl Default for AmountParser {
+  fn default() -> Self {
+    AmountParser::Lenient
+  }
+}
+
+fn check_strict(value: &str, allow_sign: bool, max_scale: u32) -> Result<(), AmountError> {
+  let unsigned = value.trim_start_matches(|c| c == '+' || c == '-');
+  let sign_len = value.len() - unsigned.len();
+  if sign_len > 1 || (sign_len == 1 && !allow_sign) {
+    return Err(AmountError::UnexpectedSign(value.to_string()));
+  }
+
+  if let Some(c) = unsigned.chars().find(|c| !c.is_ascii_digit() && *c != '.') {
+    return Err(AmountError::UnexpectedCharacter(value.to_string(), c));
+  }
+
+  let mut parts = unsigned.split('.');
+  let integer = parts.next().unwrap_or_default();
+  let decimals = parts.next().unwrap_or_default();
+  if parts.next().is_some() {
+    Err(AmountError::MultipleDecimalPoints(value.to_string()))
+  } else if integer.is_empty() && decimals.is_empty() {
+    Err(AmountError::Empty)
+  } else if decimals.len() > max_scale as usize {
+    Err(AmountError::TooManyDecimalPlaces(
+      value.to_string(),
+      max_scale,
+    ))
+  } else {
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+
+  use rust_decimal_macros::dec;
+
+  use super::*;
+
+  #[test]
+  fn parse_strict_success() {
+    let parser = AmountParser::strict();
+
+    let cases = vec![
+      ("100", dec!(100)),
+      ("10.5", dec!(10.5)),
+      ("0.1234", dec!(0.1234)),
+      (".5", dec!(0.5)),
+      ("5.", dec!(5)),
+    ];
+
+    for (input, expected) in cases {
+      assert_eq!(parser.parse(input), Ok(expected));
+    }
+  }
+
+  #[test]
+  fn parse_strict_errors() {
+    let parser = AmountParser::strict();
+
+    let cases = vec![
+      ("", AmountError::Empty),
+      (".", AmountError::Empty),
+      ("-1", AmountError::UnexpectedSign("-1".to_string())),
+      (
+        "1e3",
+        AmountError::UnexpectedCharacter("1e3".to_string(), 'e'),
+      ),
+      (
+        "1,5",
+        AmountError::UnexpectedCharacter("1,5".to_string(), ','),
+      ),
+      (
+        "1.2.3",
+        AmountError::MultipleDecimalPoints("1.2.3".to_string()),
+      ),
+      (
+        "0.12345",
+        AmountError::TooManyDecimalPlaces("0.12345".to_string(), 4),
+      ),
+    ];
+
+    for (input, expected) in cases {
+      assert_eq!(parser.parse(input), Err(expected));
+    }
+  }
+
+  #[test]
+  fn parse_strict_with_sign() {
+    let parser = AmountParser::Strict {
+      allow_sign: true,
+      max_scale: 2,
+    };
+
+    assert_eq!(parser.parse("-1.5"), Ok(dec!(-1.5)));
+    assert_eq!(parser.parse("+1.5"), Ok(dec!(1.5)));
+    assert_eq!(
+      parser.parse("--1"),
+      Err(AmountError::UnexpectedSign("--1".to_string()))
+    );
+  }
+
+  #[test]
+  fn parse_lenient() {
+    let parser = AmountParser::Lenient;
+
+    assert_eq!(parser.parse("-1.5"), Ok(dec!(-1.5)));
+    assert_eq!(parser.parse("0.12345"), Ok(dec!(0.12345)));
+    assert!(parser.parse("abc").is_err());
+  }
+}
@@ -0,0 +1,110 @@
+use std::collections::HashMap;
+
+use anyhow::Result;
+use serde::Deserialize;
+use tokio::io::AsyncRead;
+use tokio_stream::StreamExt;
+
+/// A deserializable entry of the API keys file
+#[derive(Debug, Deserialize)]
+struct ApiKeyRecord {
+  key: String,
+  name: String,
+  requests_per_minute: Option<u32>,
+}
+
+/// A client of the services, identified by its API key.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ApiKey {
+  /// The name used to audit the requests of the key, so the key itself is never written anywhere.
+  pub name: String,
+  /// Maximum number of requests allowed every minute, or `None` for no limit.
+  pub requests_per_minute: Option<u32>,
+}
+
+/// The API keys allowed to use the services.
+///
+/// They are loaded from a CSV with the `key, name, requests_per_minute` columns, where the limit can be empty.
+#[derive(Debug, Default, PartialEq)]
+pub struct ApiKeys(HashMap<String, ApiKey>);
+
+impl ApiKeys {
+  pub async fn load<R>(reader: R) -> Result<Self>
+  where
+    R: AsyncRead + Unpin + Send + Sync,
+  {
+    let mut keys = HashMap::new();
+
+    let mut records = csv_async::AsyncReaderBuilder::new()
+      .create_reader(reader)
+      .into_records();
+    while let Some(record) = records.next().await {
+      let mut record = record?;
+      record.trim();
+      let entry = record.deserialize::<ApiKeyRecord>(None)?;
+      // the names are written as they are into the audit log
+      if entry.name.contains(|c| c == ',' || c == '"' || c == '\n') {
+        anyhow::bail!("Invalid API key name: {}", entry.name);
+      }
+      keys.insert(
+        entry.key,
+        ApiKey {
+          name: entry.name,
+          requests_per_minute: entry.requests_per_minute,
+        },
+      );
+    }
+
+    Ok(Self(keys))
+  }
+
+  pub fn get(&self, key: &str) -> Option<&ApiKey> {
+    self.0.get(key)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+
+  use indoc::indoc;
+
+  use super::*;
+
+  #[tokio::test]
+  async fn load_api_keys() {
+    let api_keys = ApiKeys::load(
+      indoc! { "
+        key,      name,      requests_per_minute
+        s3cr3t,   backoffice,
+        t0k3n,    partner,   60
+      " }
+      .as_bytes(),
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(
+      api_keys.get("s3cr3t"),
+      Some(&ApiKey {
+        name: "backoffice".to_string(),
+        requests_per_minute: None,
+      })
+    );
+    assert_eq!(
+      api_keys.get("t0k3n"),
+      Some(&ApiKey {
+        name: "partner".to_string(),
+        requests_per_minute: Some(60),
+      })
+    );
+    assert_eq!(api_keys.get("backoffice"), None);
+  }
+
+  #[tokio::test]
+  async fn load_api_keys_with_invalid_name() {
+    let result =
+      ApiKeys::load("key,name,requests_per_minute\ns3cr3t,\"back,office\",\n".as_bytes()).await;
+
+    assert!(result.is_err());
+  }
+}
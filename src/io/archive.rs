@@ -0,0 +1,75 @@
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::Result;
+use sha2::{Digest, Sha256};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWriteExt};
+
+const READ_BUFFER_SIZE: usize = 64 * 1024;
+
+/// Copy the raw input, exactly as it was received, into a new file in the archive directory named after the current time,
+/// and write its SHA-256 checksum next to it (with the `.sha256` extension, in the format of `sha256sum`).
+///
+/// It returns the path of the archived copy, so it can be processed instead of the original input.
+/// This way the archive is complete even when the input can't be parsed.
+pub async fn archive_input<R>(mut reader: R, archive_dir: &Path) -> Result<PathBuf>
+where
+  R: AsyncRead + Unpin,
+{
+  let timestamp = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis();
+  let name = format!("input-{}.csv", timestamp);
+  let path = archive_dir.join(&name);
+
+  let mut file = tokio::fs::File::create(&path).await?;
+  let mut hasher = Sha256::new();
+  let mut buffer = vec![0u8; READ_BUFFER_SIZE];
+  loop {
+    let size = reader.read(&mut buffer).await?;
+    if size == 0 {
+      break;
+    }
+    hasher.update(&buffer[..size]);
+    file.write_all(&buffer[..size]).await?;
+  }
+  file.flush().await?;
+
+  let checksum = format!("{:x}  {}\n", hasher.finalize(), name);
+  tokio::fs::write(archive_dir.join(format!("{}.sha256", name)), checksum).await?;
+
+  Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+
+  use super::*;
+
+  #[tokio::test]
+  async fn archive_input_with_checksum() {
+    let archive_dir = std::env::temp_dir().join(format!(
+      "toy-payments-engine-archive-{}",
+      std::process::id()
+    ));
+    tokio::fs::create_dir_all(&archive_dir).await.unwrap();
+
+    let path = archive_input("type,client,tx,amount\n".as_bytes(), &archive_dir)
+      .await
+      .unwrap();
+
+    let contents = tokio::fs::read_to_string(&path).await.unwrap();
+    let checksum_path = PathBuf::from(format!("{}.sha256", path.display()));
+    let checksum = tokio::fs::read_to_string(&checksum_path).await.unwrap();
+    tokio::fs::remove_dir_all(&archive_dir).await.unwrap();
+
+    let name = path.file_name().unwrap().to_string_lossy();
+    assert!(name.starts_with("input-") && name.ends_with(".csv"));
+    assert_eq!(contents, "type,client,tx,amount\n");
+    assert_eq!(
+      checksum,
+      format!(
+        "0c4bb2c522b6691f4c8e807cc5ba1e464fb93b298a7c3fad6b54cf850a09a987  {}\n",
+        name
+      )
+    );
+  }
+}
@@ -0,0 +1,99 @@
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+
+use super::transaction::log_record;
+use crate::payments::Transaction;
+
+const HEADER: &str = "key,type,client,tx,amount,to,timestamp\n";
+
+/// A log of the transactions accepted from every client of the services, to audit which API key submitted them.
+///
+/// Every record has the name of the [`ApiKey`](super::ApiKey) followed by the transaction in the same format than the input,
+/// and it is flushed before the transaction is applied, the same way than the [`WalPaymentsEngine`](crate::payments::WalPaymentsEngine).
+pub struct AuditLog<W> {
+  log: W,
+  header_pending: bool,
+}
+
+impl<W> AuditLog<W>
+where
+  W: AsyncWrite + Unpin,
+{
+  /// Start a new log, which will begin with the CSV header.
+  pub fn new(log: W) -> Self {
+    Self {
+      log,
+      header_pending: true,
+    }
+  }
+
+  /// Keep appending to an existing log, which already has the CSV header.
+  pub fn appending(log: W) -> Self {
+    Self {
+      log,
+      header_pending: false,
+    }
+  }
+
+  pub async fn append(&mut self, key_name: &str, transaction: &Transaction) -> std::io::Result<()> {
+    let mut record = String::new();
+    if self.header_pending {
+      record.push_str(HEADER);
+    }
+    record.push_str(key_name);
+    record.push(',');
+    record.push_str(&log_record(transaction));
+    self.log.write_all(record.as_bytes()).await?;
+    self.log.flush().await?;
+    self.header_pending = false;
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+
+  use indoc::indoc;
+  use rust_decimal_macros::dec;
+
+  use super::*;
+
+  #[tokio::test]
+  async fn append_transactions() {
+    let mut log = Vec::<u8>::new();
+    let mut audit_log = AuditLog::new(&mut log);
+
+    audit_log
+      .append(
+        "backoffice",
+        &Transaction::Deposit {
+          client_id: 1,
+          transaction_id: 101,
+          amount: dec!(10.5),
+          timestamp: None,
+          sub_account: 0,
+        },
+      )
+      .await
+      .unwrap();
+    audit_log
+      .append(
+        "partner",
+        &Transaction::Dispute {
+          client_id: 1,
+          transaction_id: 101,
+          timestamp: None,
+        },
+      )
+      .await
+      .unwrap();
+
+    assert_eq!(
+      String::from_utf8_lossy(&log),
+      indoc! { "
+        key,type,client,tx,amount,to,timestamp
+        backoffice,deposit,1,101,10.5,,
+        partner,dispute,1,101,,,
+      " }
+    );
+  }
+}
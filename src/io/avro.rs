@@ -0,0 +1,957 @@
+use std::collections::HashMap;
+use std::convert::TryFrom;
+
+use anyhow::{anyhow, bail, Result};
+use csv_async::StringRecord;
+use rust_decimal::Decimal;
+use serde_json::Value as Json;
+use tokio::io::{AsyncRead, AsyncReadExt};
+use tokio_stream::{Stream, StreamExt};
+
+use super::pipeline::{
+  DecodedTransactionsReader, RecordSource, SourceItem, TransactionDecoder, COLUMNS,
+};
+
+/// The schema of the transactions published by the pipelines, to register it in the [`AvroSchemas`] with its ID.
+/// The `amount` can also be a `decimal` logical type, a `double` or a `float` in other schemas.
+pub const TRANSACTION_SCHEMA: &str = r#"{
+  "type": "record",
+  "name": "Transaction",
+  "namespace": "payments",
+  "fields": [
+    {
+      "name": "type",
+      "type": {
+        "type": "enum",
+        "name": "TransactionType",
+        "symbols": ["deposit", "withdrawal", "dispute", "resolve", "chargeback", "transfer", "unlock", "authorize", "capture", "void", "reactivate"]
+      }
+    },
+    {"name": "client", "type": "int"},
+    {"name": "tx", "type": "long"},
+    {"name": "amount", "type": ["null", "string"], "default": null},
+    {"name": "to", "type": ["null", "int"], "default": null},
+    {"name": "timestamp", "type": ["null", "long"], "default": null}
+  ]
+}"#;
+
+const CONTAINER_MAGIC: &[u8] = b"Obj\x01";
+const CONFLUENT_MAGIC: u8 = 0;
+const SYNC_SIZE: usize = 16;
+
+/// Implementation of [`TransactionsReader`] for [Avro](https://avro.apache.org/docs/current/spec.html) data,
+/// either an object container file, or the messages framed by the Confluent serializers (a zero byte,
+/// the ID of the schema in the registry, and the record).
+///
+/// The records are mapped by the names of their fields, which are the same than the columns of the CSV format,
+/// so they are interpreted the same way. Only the `null` codec is supported for the container files.
+///
+/// The decoding is implemented here rather than with the `apache-avro` crate, as the transactions only need
+/// the binary encoding and a subset of the schemas, and that crate isn't available to the build.
+pub type AvroTransactionsReader = DecodedTransactionsReader<AvroSource, AvroDecoder>;
+
+/// A [`RecordSource`] of the values of Avro data, decoded with the schema they were written with.
+pub struct AvroSource(Source);
+
+enum Source {
+  Container {
+    schema: Schema,
+    data: Vec<u8>,
+    sync: [u8; SYNC_SIZE],
+  },
+  Messages {
+    messages: Box<dyn Stream<Item = Vec<u8>> + Unpin + Send + Sync>,
+    schemas: AvroSchemas,
+  },
+}
+
+impl AvroTransactionsReader {
+  /// Load an object container file, with the schema the records were written with in its header.
+  /// The file is not streamed, so it is loaded into memory.
+  pub async fn load<R>(mut reader: R) -> Result<Self>
+  where
+    R: AsyncRead + Unpin,
+  {
+    let mut data = Vec::new();
+    reader.read_to_end(&mut data).await?;
+
+    let mut decoder = Decoder::new(&data);
+    if decoder.read_fixed(CONTAINER_MAGIC.len())? != CONTAINER_MAGIC {
+      bail!("Not an Avro object container file");
+    }
+    let mut metadata = HashMap::new();
+    decoder.read_blocks(|decoder| {
+      let key = decoder.read_string()?;
+      metadata.insert(key, decoder.read_bytes()?.to_vec());
+      Ok(())
+    })?;
+    match metadata.get("avro.codec").map(Vec::as_slice) {
+      None | Some(b"null") => {}
+      Some(codec) => bail!("Unsupported Avro codec: {}", String::from_utf8_lossy(codec)),
+    }
+    let schema = metadata
+      .get("avro.schema")
+      .ok_or_else(|| anyhow!("The Avro file has no schema"))?;
+    let schema = Schema::parse(&serde_json::from_slice(schema)?)?;
+    let sync = <[u8; SYNC_SIZE]>::try_from(decoder.read_fixed(SYNC_SIZE)?)?;
+    let body = data.split_off(decoder.position);
+
+    let source = AvroSource(Source::Container {
+      schema,
+      data: body,
+      sync,
+    });
+    Ok(Self::from_parts(source, AvroDecoder))
+  }
+
+  /// Read the messages of a topic serialized with the Confluent framing, whose schemas are looked up by their ID.
+  pub fn from_messages<S>(messages: S, schemas: AvroSchemas) -> Self
+  where
+    S: Stream<Item = Vec<u8>> + Unpin + Send + Sync + 'static,
+  {
+    let source = AvroSource(Source::Messages {
+      messages: Box::new(messages),
+      schemas,
+    });
+    Self::from_parts(source, AvroDecoder)
+  }
+}
+
+impl RecordSource for AvroSource {
+  type Item = Value;
+
+  /// The records in the order they were written.
+  fn items<'a>(&'a mut self) -> Box<dyn Stream<Item = SourceItem<Self::Item>> + Unpin + 'a> {
+    let values: Box<dyn Stream<Item = Result<Value>> + Unpin + 'a> = match &mut self.0 {
+      Source::Container { schema, data, sync } => Box::new(tokio_stream::iter(ContainerValues {
+        schema,
+        decoder: Decoder::new(data),
+        sync,
+        remaining: 0,
+        failed: false,
+      })),
+      Source::Messages { messages, schemas } => {
+        let schemas = &*schemas;
+        Box::new(messages.map(move |message| schemas.decode(&message)))
+      }
+    };
+    Box::new(values.map(|value| SourceItem {
+      line: None,
+      item: value,
+    }))
+  }
+}
+
+/// A [`TransactionDecoder`] of the Avro records, by the names of their fields.
+pub struct AvroDecoder;
+
+impl TransactionDecoder for AvroDecoder {
+  type Item = Value;
+
+  fn decode(&self, value: Self::Item) -> Result<StringRecord> {
+    value.into_record()
+  }
+}
+
+/// The schemas of the registry, by their ID, to decode the messages framed by the Confluent serializers.
+/// The messages of schemas not registered are rejected, as they can't be decoded.
+#[derive(Default)]
+pub struct AvroSchemas {
+  schemas: HashMap<u32, Schema>,
+}
+
+impl AvroSchemas {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Register the schema (in its JSON form) with the ID assigned by the registry.
+  pub fn with_schema(mut self, id: u32, schema: &str) -> Result<Self> {
+    let schema = Schema::parse(&serde_json::from_str(schema)?)?;
+    self.schemas.insert(id, schema);
+    Ok(self)
+  }
+
+  fn decode(&self, message: &[u8]) -> Result<Value> {
+    let mut decoder = Decoder::new(message);
+    if decoder.read_fixed(1)? != [CONFLUENT_MAGIC] {
+      bail!("Not a message framed by the Confluent serializers");
+    }
+    let id = u32::from_be_bytes(<[u8; 4]>::try_from(decoder.read_fixed(4)?)?);
+    let schema = self
+      .schemas
+      .get(&id)
+      .ok_or_else(|| anyhow!("Unknown Avro schema ID {}", id))?;
+    let value = decoder.read_value(schema)?;
+    if decoder.position != message.len() {
+      bail!("Unexpected bytes after the record with schema {}", id);
+    }
+    Ok(value)
+  }
+}
+
+/// The records of the blocks of an object container file.
+/// A block that can't be decoded stops the reading, as the records after it can't be found.
+struct ContainerValues<'a> {
+  schema: &'a Schema,
+  decoder: Decoder<'a>,
+  sync: &'a [u8; SYNC_SIZE],
+  remaining: i64,
+  failed: bool,
+}
+
+impl<'a> ContainerValues<'a> {
+  fn next_value(&mut self) -> Result<Value> {
+    if self.remaining == 0 {
+      let count = self.decoder.read_long()?;
+      // the records are decoded one by one, but the size is checked to fail early with a truncated block,
+      // and to bound the number of records, as every one of them takes at least one byte
+      let size = self.decoder.read_long()?;
+      let size = usize::try_from(size)
+        .ok()
+        .filter(|size| *size <= self.decoder.remaining())
+        .ok_or_else(|| anyhow!("Truncated block of {} bytes", size))?;
+      if count <= 0 || count as u64 > size as u64 {
+        bail!("Invalid number of records in a block: {}", count);
+      }
+      self.remaining = count;
+    }
+    let value = self.decoder.read_value(self.schema)?;
+    self.remaining -= 1;
+    if self.remaining == 0 && self.decoder.read_fixed(SYNC_SIZE)? != self.sync {
+      bail!("Invalid sync marker at the end of a block");
+    }
+    Ok(value)
+  }
+}
+
+impl<'a> Iterator for ContainerValues<'a> {
+  type Item = Result<Value>;
+
+  fn next(&mut self) -> Option<Self::Item> {
+    if self.failed || (self.remaining == 0 && self.decoder.is_empty()) {
+      return None;
+    }
+    let value = self.next_value();
+    self.failed = value.is_err();
+    Some(value)
+  }
+}
+
+/// The subset of the Avro schemas needed to decode the records, with the named types already resolved.
+#[derive(Debug, Clone, PartialEq)]
+enum Schema {
+  Null,
+  Boolean,
+  Int,
+  Long,
+  Float,
+  Double,
+  Bytes,
+  String,
+  Fixed(usize),
+  /// The `decimal` logical type, on `bytes` or on a `fixed` of the size.
+  Decimal {
+    scale: u32,
+    size: Option<usize>,
+  },
+  Enum(Vec<String>),
+  Array(Box<Schema>),
+  Map(Box<Schema>),
+  Union(Vec<Schema>),
+  Record(Vec<(String, Schema)>),
+}
+
+impl Schema {
+  fn parse(json: &Json) -> Result<Self> {
+    Self::parse_named(json, &mut HashMap::new())
+  }
+
+  fn parse_named(json: &Json, names: &mut HashMap<String, Schema>) -> Result<Self> {
+    match json {
+      Json::String(name) => Self::parse_type(name, json, names),
+      Json::Array(schemas) => Ok(Schema::Union(
+        schemas
+          .iter()
+          .map(|schema| Self::parse_named(schema, names))
+          .collect::<Result<_>>()?,
+      )),
+      Json::Object(object) => {
+        let name = object
+          .get("type")
+          .and_then(Json::as_str)
+          .ok_or_else(|| anyhow!("Invalid Avro schema: {}", json))?;
+        Self::parse_type(name, json, names)
+      }
+      _ => bail!("Invalid Avro schema: {}", json),
+    }
+  }
+
+  fn parse_type(name: &str, json: &Json, names: &mut HashMap<String, Schema>) -> Result<Self> {
+    let schema = match name {
+      "null" => Schema::Null,
+      "boolean" => Schema::Boolean,
+      "int" => Schema::Int,
+      "long" => Schema::Long,
+      "float" => Schema::Float,
+      "double" => Schema::Double,
+      "string" => Schema::String,
+      "bytes" if is_decimal(json) => Schema::Decimal {
+        scale: decimal_scale(json),
+        size: None,
+      },
+      "bytes" => Schema::Bytes,
+      "fixed" => {
+        let size = json
+          .get("size")
+          .and_then(Json::as_u64)
+          .ok_or_else(|| anyhow!("Invalid Avro fixed: {}", json))? as usize;
+        if is_decimal(json) {
+          Schema::Decimal {
+            scale: decimal_scale(json),
+            size: Some(size),
+          }
+        } else {
+          Schema::Fixed(size)
+        }
+      }
+      "enum" => Schema::Enum(
+        json
+          .get("symbols")
+          .and_then(Json::as_array)
+          .ok_or_else(|| anyhow!("Invalid Avro enum: {}", json))?
+          .iter()
+          .map(|symbol| symbol.as_str().unwrap_or_default().to_string())
+          .collect(),
+      ),
+      "array" => Schema::Array(Box::new(Self::parse_named(
+        json
+          .get("items")
+          .ok_or_else(|| anyhow!("Invalid Avro array: {}", json))?,
+        names,
+      )?)),
+      "map" => Schema::Map(Box::new(Self::parse_named(
+        json
+          .get("values")
+          .ok_or_else(|| anyhow!("Invalid Avro map: {}", json))?,
+        names,
+      )?)),
+      "record" | "error" => Schema::Record(
+        json
+          .get("fields")
+          .and_then(Json::as_array)
+          .ok_or_else(|| anyhow!("Invalid Avro record: {}", json))?
+          .iter()
+          .map(|field| {
+            let name = field
+              .get("name")
+              .and_then(Json::as_str)
+              .ok_or_else(|| anyhow!("Invalid Avro field: {}", field))?;
+            let schema = field
+              .get("type")
+              .ok_or_else(|| anyhow!("Invalid Avro field: {}", field))?;
+            Ok((name.to_string(), Self::parse_named(schema, names)?))
+          })
+          .collect::<Result<_>>()?,
+      ),
+      // a complex type given as `{"type": {...}}`, or a named type defined before
+      _ => match json.get("type") {
+        Some(inner) if inner.is_object() || inner.is_array() => Self::parse_named(inner, names)?,
+        _ => names
+          .get(name)
+          .or_else(|| names.get(name.rsplit('.').next().unwrap_or(name)))
+          .cloned()
+          .ok_or_else(|| anyhow!("Unknown Avro type: {}", name))?,
+      },
+    };
+
+    if let Some(type_name) = json.get("name").and_then(Json::as_str) {
+      names.insert(type_name.to_string(), schema.clone());
+      if let Some(namespace) = json.get("namespace").and_then(Json::as_str) {
+        names.insert(format!("{}.{}", namespace, type_name), schema.clone());
+      }
+    }
+    Ok(schema)
+  }
+}
+
+fn is_decimal(json: &Json) -> bool {
+  json.get("logicalType").and_then(Json::as_str) == Some("decimal")
+}
+
+fn decimal_scale(json: &Json) -> u32 {
+  json.get("scale").and_then(Json::as_u64).unwrap_or_default() as u32
+}
+
+/// A decoded Avro value.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+  Null,
+  Boolean(bool),
+  Long(i64),
+  Double(f64),
+  Bytes(Vec<u8>),
+  String(String),
+  Decimal(Decimal),
+  Array(Vec<Value>),
+  Map(Vec<(String, Value)>),
+  Record(Vec<(String, Value)>),
+}
+
+impl Value {
+  /// The fields of a record in the order of the columns of the CSV format, missing the ones not in the record.
+  fn into_record(self) -> Result<StringRecord> {
+    let mut fields = match self {
+      Value::Record(fields) => fields,
+      _ => bail!("The Avro value is not a record"),
+    };
+    Ok(
+      COLUMNS
+        .iter()
+        .map(|name| {
+          fields
+            .iter_mut()
+            .find(|(field, _)| field == name)
+            .map(|(_, value)| std::mem::replace(value, Value::Null).into_field())
+            .unwrap_or_default()
+        })
+        .collect(),
+    )
+  }
+
+  fn into_field(self) -> String {
+    match self {
+      Value::Null => String::new(),
+      Value::Boolean(value) => value.to_string(),
+      Value::Long(value) => value.to_string(),
+      Value::Double(value) => value.to_string(),
+      Value::String(value) => value,
+      Value::Decimal(value) => value.to_string(),
+      Value::Bytes(_) | Value::Array(_) | Value::Map(_) | Value::Record(_) => {
+        "<unsupported>".to_string()
+      }
+    }
+  }
+}
+
+/// A decoder of the binary encoding of Avro, which fails instead of panicking with malformed data.
+struct Decoder<'a> {
+  data: &'a [u8],
+  position: usize,
+}
+
+impl<'a> Decoder<'a> {
+  fn new(data: &'a [u8]) -> Self {
+    Self { data, position: 0 }
+  }
+
+  fn is_empty(&self) -> bool {
+    self.remaining() == 0
+  }
+
+  fn remaining(&self) -> usize {
+    self.data.len().saturating_sub(self.position)
+  }
+
+  fn read_fixed(&mut self, size: usize) -> Result<&'a [u8]> {
+    let end = self
+      .position
+      .checked_add(size)
+      .filter(|end| *end <= self.data.len())
+      .ok_or_else(|| anyhow!("Unexpected end of the Avro data"))?;
+    let bytes = &self.data[self.position..end];
+    self.position = end;
+    Ok(bytes)
+  }
+
+  /// A zig-zag encoded variable length integer.
+  fn read_long(&mut self) -> Result<i64> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+      let byte = self.read_fixed(1)?[0];
+      // the tenth byte only has room for the highest bit
+      if shift == 63 && byte & 0x7e != 0 {
+        bail!("Invalid Avro integer");
+      }
+      value |= u64::from(byte & 0x7f) << shift;
+      if byte & 0x80 == 0 {
+        return Ok((value >> 1) as i64 ^ -((value & 1) as i64));
+      }
+    }
+    bail!("Invalid Avro integer")
+  }
+
+  fn read_bytes(&mut self) -> Result<&'a [u8]> {
+    let size = self.read_long()?;
+    let size = usize::try_from(size).map_err(|_| anyhow!("Invalid Avro length: {}", size))?;
+    self.read_fixed(size)
+  }
+
+  fn read_string(&mut self) -> Result<String> {
+    Ok(std::str::from_utf8(self.read_bytes()?)?.to_string())
+  }
+
+  /// The blocks of arrays and maps, calling `read_item` for every item.
+  /// A block can't have more items than bytes left, so an oversized count fails instead of looping,
+  /// which also means that the arrays of nulls are only read up to that size.
+  fn read_blocks<F>(&mut self, mut read_item: F) -> Result<()>
+  where
+    F: FnMut(&mut Self) -> Result<()>,
+  {
+    loop {
+      let mut count = self.read_long()?;
+      if count == 0 {
+        return Ok(());
+      }
+      if count < 0 {
+        count = count
+          .checked_neg()
+          .ok_or_else(|| anyhow!("Invalid Avro block"))?;
+        // the size of the block in bytes, only needed to skip it
+        self.read_long()?;
+      }
+      if count as u64 > self.remaining() as u64 {
+        bail!("Invalid Avro block of {} items", count);
+      }
+      for _ in 0..count {
+        read_item(self)?;
+      }
+    }
+  }
+
+  fn read_value(&mut self, schema: &Schema) -> Result<Value> {
+    match schema {
+      Schema::Null => Ok(Value::Null),
+      Schema::Boolean => Ok(Value::Boolean(self.read_fixed(1)?[0] != 0)),
+      Schema::Int | Schema::Long => Ok(Value::Long(self.read_long()?)),
+      Schema::Float => {
+        let bytes = <[u8; 4]>::try_from(self.read_fixed(4)?)?;
+        Ok(Value::Double(f64::from(f32::from_le_bytes(bytes))))
+      }
+      Schema::Double => {
+        let bytes = <[u8; 8]>::try_from(self.read_fixed(8)?)?;
+        Ok(Value::Double(f64::from_le_bytes(bytes)))
+      }
+      Schema::Bytes => Ok(Value::Bytes(self.read_bytes()?.to_vec())),
+      Schema::String => Ok(Value::String(self.read_string()?)),
+      Schema::Fixed(size) => Ok(Value::Bytes(self.read_fixed(*size)?.to_vec())),
+      Schema::Decimal { scale, size } => {
+        let bytes = match size {
+          Some(size) => self.read_fixed(*size)?,
+          None => self.read_bytes()?,
+        };
+        Ok(Value::Decimal(decimal_from_bytes(bytes, *scale)?))
+      }
+      Schema::Enum(symbols) => {
+        let index = self.read_long()?;
+        usize::try_from(index)
+          .ok()
+          .and_then(|index| symbols.get(index))
+          .map(|symbol| Value::String(symbol.clone()))
+          .ok_or_else(|| anyhow!("Invalid Avro enum index: {}", index))
+      }
+      Schema::Array(items) => {
+        let mut values = Vec::new();
+        self.read_blocks(|decoder| {
+          values.push(decoder.read_value(items)?);
+          Ok(())
+        })?;
+        Ok(Value::Array(values))
+      }
+      Schema::Map(values_schema) => {
+        let mut values = Vec::new();
+        self.read_blocks(|decoder| {
+          let key = decoder.read_string()?;
+          values.push((key, decoder.read_value(values_schema)?));
+          Ok(())
+        })?;
+        Ok(Value::Map(values))
+      }
+      Schema::Union(schemas) => {
+        let index = self.read_long()?;
+        let schema = usize::try_from(index)
+          .ok()
+          .and_then(|index| schemas.get(index))
+          .ok_or_else(|| anyhow!("Invalid Avro union index: {}", index))?;
+        self.read_value(schema)
+      }
+      Schema::Record(fields) => Ok(Value::Record(
+        fields
+          .iter()
+          .map(|(name, schema)| Ok((name.clone(), self.read_value(schema)?)))
+          .collect::<Result<_>>()?,
+      )),
+    }
+  }
+}
+
+/// A decimal from the big endian two's complement of its unscaled value.
+fn decimal_from_bytes(bytes: &[u8], scale: u32) -> Result<Decimal> {
+  if bytes.is_empty() || bytes.len() > 16 {
+    bail!("Invalid Avro decimal of {} bytes", bytes.len());
+  }
+  let fill = if bytes[0] & 0x80 == 0 { 0 } else { 0xff };
+  let mut unscaled = [fill; 16];
+  unscaled[16 - bytes.len()..].copy_from_slice(bytes);
+  Decimal::try_from_i128_with_scale(i128::from_be_bytes(unscaled), scale)
+    .map_err(|err| anyhow!("Invalid Avro decimal: {}", err))
+}
+
+#[cfg(test)]
+mod tests {
+
+  use rust_decimal_macros::dec;
+
+  use super::*;
+  use crate::io::TransactionsReader;
+  use crate::payments::Transaction;
+
+  fn write_long(out: &mut Vec<u8>, value: i64) {
+    let mut value = ((value << 1) ^ (value >> 63)) as u64;
+    while value >= 0x80 {
+      out.push((value as u8) | 0x80);
+      value >>= 7;
+    }
+    out.push(value as u8);
+  }
+
+  fn write_bytes(out: &mut Vec<u8>, bytes: &[u8]) {
+    write_long(out, bytes.len() as i64);
+    out.extend_from_slice(bytes);
+  }
+
+  /// A record of the [`TRANSACTION_SCHEMA`].
+  fn transaction(
+    kind: i64,
+    client: i64,
+    tx: i64,
+    amount: Option<&str>,
+    to: Option<i64>,
+  ) -> Vec<u8> {
+    let mut out = Vec::new();
+    write_long(&mut out, kind);
+    write_long(&mut out, client);
+    write_long(&mut out, tx);
+    match amount {
+      Some(amount) => {
+        write_long(&mut out, 1);
+        write_bytes(&mut out, amount.as_bytes());
+      }
+      None => write_long(&mut out, 0),
+    }
+    match to {
+      Some(to) => {
+        write_long(&mut out, 1);
+        write_long(&mut out, to);
+      }
+      None => write_long(&mut out, 0),
+    }
+    write_long(&mut out, 0);
+    out
+  }
+
+  fn container(blocks: &[Vec<Vec<u8>>]) -> Vec<u8> {
+    let sync = [7u8; SYNC_SIZE];
+    let mut out = CONTAINER_MAGIC.to_vec();
+    write_long(&mut out, 1);
+    write_bytes(&mut out, b"avro.schema");
+    write_bytes(&mut out, TRANSACTION_SCHEMA.as_bytes());
+    write_long(&mut out, 0);
+    out.extend_from_slice(&sync);
+    for records in blocks {
+      let data: Vec<u8> = records.concat();
+      write_long(&mut out, records.len() as i64);
+      write_long(&mut out, data.len() as i64);
+      out.extend_from_slice(&data);
+      out.extend_from_slice(&sync);
+    }
+    out
+  }
+
+  #[tokio::test]
+  async fn read_container_file() {
+    let input = container(&[
+      vec![
+        transaction(0, 1, 101, Some("10.5"), None),
+        transaction(2, 1, 101, None, None),
+      ],
+      vec![
+        transaction(5, 1, 102, Some("2"), Some(2)),
+        transaction(0, 1, 103, Some("abc"), None),
+      ],
+    ]);
+    let mut reader = AvroTransactionsReader::load(input.as_slice())
+      .await
+      .unwrap();
+
+    let records: Vec<(Option<u64>, Option<Transaction>)> = reader
+      .read_records()
+      .map(|record| (record.line, record.transaction.ok()))
+      .collect()
+      .await;
+
+    assert_eq!(
+      records,
+      vec![
+        (
+          Some(1),
+          Some(Transaction::Deposit {
+            client_id: 1,
+            transaction_id: 101,
+            amount: dec!(10.5),
+            timestamp: None,
+            sub_account: 0,
+          })
+        ),
+        (
+          Some(2),
+          Some(Transaction::Dispute {
+            client_id: 1,
+            transaction_id: 101,
+            timestamp: None,
+          })
+        ),
+        (
+          Some(3),
+          Some(Transaction::Transfer {
+            from_client: 1,
+            to_client: 2,
+            transaction_id: 102,
+            amount: dec!(2),
+            timestamp: None,
+          })
+        ),
+        (Some(4), None),
+      ]
+    );
+  }
+
+  #[tokio::test]
+  async fn read_malformed_container_file() {
+    assert!(AvroTransactionsReader::load(b"Obj".as_ref()).await.is_err());
+
+    let mut input = container(&[vec![transaction(0, 1, 101, Some("1"), None)]]);
+    // the sync marker at the end of the block is corrupted
+    let last = input.len() - 1;
+    input[last] = 0;
+    let mut reader = AvroTransactionsReader::load(input.as_slice())
+      .await
+      .unwrap();
+
+    let transactions: Vec<Result<Transaction>> = reader.read_transactions().collect().await;
+    assert_eq!(transactions.len(), 1);
+    assert!(transactions[0].is_err());
+  }
+
+  /// The positions where the sync marker of the [`container`] starts.
+  fn sync_positions(input: &[u8]) -> Vec<usize> {
+    input
+      .windows(SYNC_SIZE)
+      .enumerate()
+      .filter(|(_, window)| *window == [7u8; SYNC_SIZE])
+      .map(|(position, _)| position)
+      .collect()
+  }
+
+  #[tokio::test]
+  async fn read_container_file_with_a_bad_sync_marker() {
+    let mut input = container(&[
+      vec![
+        transaction(0, 1, 101, Some("1"), None),
+        transaction(0, 1, 102, Some("1"), None),
+      ],
+      vec![transaction(0, 1, 103, Some("1"), None)],
+    ]);
+    // the marker after the first block, as the first one ends the header
+    let position = sync_positions(&input)[1];
+    input[position + 3] = 0;
+    let mut reader = AvroTransactionsReader::load(input.as_slice())
+      .await
+      .unwrap();
+
+    let transactions: Vec<Result<Transaction>> = reader.read_transactions().collect().await;
+
+    // the records after the marker can't be trusted to start where the block ends
+    assert_eq!(transactions.len(), 2);
+    assert!(transactions[0].is_ok());
+    assert!(transactions[1].is_err());
+  }
+
+  #[tokio::test]
+  async fn read_truncated_container_file() {
+    let input = container(&[
+      vec![
+        transaction(0, 1, 101, Some("1"), None),
+        transaction(0, 1, 102, Some("1"), None),
+      ],
+      vec![transaction(0, 1, 103, Some("1"), None)],
+    ]);
+
+    // truncated in the middle of the record of the second block
+    let truncated = &input[..input.len() - SYNC_SIZE - 3];
+    let mut reader = AvroTransactionsReader::load(truncated).await.unwrap();
+    let transactions: Vec<Result<Transaction>> = reader.read_transactions().collect().await;
+    assert_eq!(transactions.len(), 3);
+    assert!(transactions[0].is_ok());
+    assert!(transactions[1].is_ok());
+    assert!(transactions[2].is_err());
+
+    // truncated in the middle of the header
+    let header_end = sync_positions(&input)[0];
+    assert!(AvroTransactionsReader::load(&input[..header_end - 10])
+      .await
+      .is_err());
+    assert!(AvroTransactionsReader::load(&input[..header_end + 4])
+      .await
+      .is_err());
+  }
+
+  #[tokio::test]
+  async fn read_container_file_with_oversized_lengths() {
+    let header_end = sync_positions(&container(&[]))[0] + SYNC_SIZE;
+    let record = transaction(0, 1, 101, Some("1"), None);
+    for (count, size) in [
+      (i64::MAX, record.len() as i64),
+      (1, i64::MAX),
+      (1, -1),
+      (-1, record.len() as i64),
+    ] {
+      let mut input = container(&[])[..header_end].to_vec();
+      write_long(&mut input, count);
+      write_long(&mut input, size);
+      input.extend_from_slice(&record);
+      input.extend_from_slice(&[7u8; SYNC_SIZE]);
+      let mut reader = AvroTransactionsReader::load(input.as_slice())
+        .await
+        .unwrap();
+
+      let transactions: Vec<Result<Transaction>> = reader.read_transactions().collect().await;
+
+      assert_eq!(transactions.len(), 1, "count {} and size {}", count, size);
+      assert!(transactions[0].is_err());
+    }
+  }
+
+  #[test]
+  fn decode_oversized_varints() {
+    // more than ten bytes, and a tenth byte with more than the highest bit
+    let mut too_long = vec![0xff; 10];
+    too_long.push(0x01);
+    assert!(Decoder::new(&too_long).read_long().is_err());
+    let mut overflow = vec![0xff; 9];
+    overflow.push(0x02);
+    assert!(Decoder::new(&overflow).read_long().is_err());
+    let mut min = vec![0xff; 9];
+    min.push(0x01);
+    assert_eq!(Decoder::new(&min).read_long().unwrap(), i64::MIN);
+
+    // lengths larger than the data or negative
+    let mut oversized = Vec::new();
+    write_long(&mut oversized, i64::MAX);
+    oversized.extend_from_slice(b"abc");
+    assert!(Decoder::new(&oversized).read_string().is_err());
+    let mut negative = Vec::new();
+    write_long(&mut negative, -3);
+    negative.extend_from_slice(b"abc");
+    assert!(Decoder::new(&negative).read_bytes().is_err());
+
+    // arrays with more items than bytes, even when the items take none
+    let mut array = Vec::new();
+    write_long(&mut array, i64::MAX);
+    assert!(Decoder::new(&array)
+      .read_value(&Schema::Array(Box::new(Schema::Null)))
+      .is_err());
+  }
+
+  #[test]
+  fn decode_the_examples_of_the_specification() {
+    // the encodings of the ints and longs, and of the string "foo", listed by the Avro specification
+    let longs: &[(&[u8], i64)] = &[
+      (&[0x00], 0),
+      (&[0x01], -1),
+      (&[0x02], 1),
+      (&[0x03], -2),
+      (&[0x04], 2),
+      (&[0x7f], -64),
+      (&[0x80, 0x01], 64),
+    ];
+    for (bytes, expected) in longs {
+      assert_eq!(Decoder::new(bytes).read_long().unwrap(), *expected);
+    }
+    assert_eq!(
+      Decoder::new(&[0x06, 0x66, 0x6f, 0x6f])
+        .read_string()
+        .unwrap(),
+      "foo"
+    );
+  }
+
+  #[tokio::test]
+  async fn read_confluent_messages() {
+    let decimal_schema = r#"{
+      "type": "record",
+      "name": "Deposit",
+      "fields": [
+        {"name": "type", "type": "string"},
+        {"name": "client", "type": "int"},
+        {"name": "tx", "type": "long"},
+        {"name": "amount", "type": {"type": "bytes", "logicalType": "decimal", "precision": 18, "scale": 4}}
+      ]
+    }"#;
+    let schemas = AvroSchemas::new()
+      .with_schema(7, decimal_schema)
+      .unwrap()
+      .with_schema(1, TRANSACTION_SCHEMA)
+      .unwrap();
+
+    let mut deposit = vec![CONFLUENT_MAGIC, 0, 0, 0, 7];
+    write_bytes(&mut deposit, b"deposit");
+    write_long(&mut deposit, 3);
+    write_long(&mut deposit, 301);
+    // 1.2345 is 12345 (0x3039) with a scale of 4
+    write_bytes(&mut deposit, &[0x30, 0x39]);
+    let mut withdrawal = vec![CONFLUENT_MAGIC, 0, 0, 0, 1];
+    withdrawal.extend(transaction(1, 3, 302, Some("1"), None));
+    let not_framed = transaction(1, 3, 303, Some("1"), None);
+    let mut unknown_schema = vec![CONFLUENT_MAGIC, 0, 0, 0, 9];
+    unknown_schema.extend(transaction(1, 3, 304, Some("1"), None));
+
+    let mut reader = AvroTransactionsReader::from_messages(
+      tokio_stream::iter(vec![deposit, withdrawal, not_framed, unknown_schema]),
+      schemas,
+    );
+    let transactions: Vec<Option<Transaction>> = reader
+      .read_transactions()
+      .map(|transaction| transaction.ok())
+      .collect()
+      .await;
+
+    assert_eq!(
+      transactions,
+      vec![
+        Some(Transaction::Deposit {
+          client_id: 3,
+          transaction_id: 301,
+          amount: dec!(1.2345),
+          timestamp: None,
+          sub_account: 0,
+        }),
+        Some(Transaction::Withdrawal {
+          client_id: 3,
+          transaction_id: 302,
+          amount: dec!(1),
+          timestamp: None,
+          sub_account: 0,
+        }),
+        None,
+        None,
+      ]
+    );
+  }
+
+  #[test]
+  fn decode_negative_decimals() {
+    assert_eq!(decimal_from_bytes(&[0xff, 0x85], 2).unwrap(), dec!(-1.23));
+    assert!(decimal_from_bytes(&[], 2).is_err());
+  }
+}
@@ -0,0 +1,152 @@
+use std::collections::HashMap;
+
+use anyhow::Result;
+use rust_decimal::Decimal;
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+use super::account::{rounded_funds, with_max_precission};
+use crate::payments::{ClientId, InMemoryPaymentsEngine, Snapshot};
+
+/// The accounts of a baseline snapshot, like the one of the previous day, to report how every client changed since then
+/// (see [`CsvAccountsReportWriter::with_baseline`](super::CsvAccountsReportWriter::with_baseline)).
+/// The funds are rounded the same way they are written out, so the changes add up with the previous report.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Baseline(HashMap<ClientId, BaselineAccount>);
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+struct BaselineAccount {
+  available: Decimal,
+  held: Decimal,
+  locked: bool,
+}
+
+/// The changes of an account since the [`Baseline`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(super) struct Deltas {
+  pub available_change: Decimal,
+  pub held_change: Decimal,
+  pub newly_locked: bool,
+}
+
+impl Baseline {
+  /// The baseline of the accounts of a snapshot, which is restored even when it was created under a different configuration,
+  /// as only the funds and the lock of the accounts are compared.
+  pub fn from_snapshot(snapshot: Snapshot) -> Result<Self> {
+    let mut payments_engine = InMemoryPaymentsEngine::new();
+    payments_engine.restore_with_config_change(snapshot)?;
+    let accounts = payments_engine
+      .accounts_report()
+      .map(|account_report| {
+        let (available, held, _) = rounded_funds(account_report.available, account_report.held);
+        let account = BaselineAccount {
+          available,
+          held,
+          locked: account_report.locked,
+        };
+        (account_report.client_id, account)
+      })
+      .collect();
+    Ok(Self(accounts))
+  }
+
+  /// Read the baseline from a snapshot container (see [`Snapshot::write_to`]).
+  pub async fn load<R>(mut reader: R) -> Result<Self>
+  where
+    R: AsyncRead + Unpin,
+  {
+    let mut container = Vec::new();
+    reader.read_to_end(&mut container).await?;
+    Self::from_snapshot(Snapshot::read_from(container.as_slice())?)
+  }
+
+  /// The changes of the rounded funds of a client since the baseline, where the clients without an account start from zero.
+  pub(super) fn deltas(
+    &self,
+    client_id: ClientId,
+    available: Decimal,
+    held: Decimal,
+    locked: bool,
+  ) -> Deltas {
+    let baseline = self.0.get(&client_id).copied().unwrap_or_default();
+    Deltas {
+      available_change: change(available, baseline.available),
+      held_change: change(held, baseline.held),
+      newly_locked: locked && !baseline.locked,
+    }
+  }
+}
+
+/// The difference between the funds, saturated when it doesn't fit.
+fn change(current: Decimal, baseline: Decimal) -> Decimal {
+  match current.checked_sub(baseline) {
+    Some(change) => with_max_precission(change),
+    None if current > baseline => Decimal::MAX,
+    None => Decimal::MIN,
+  }
+}
+
+#[cfg(test)]
+mod test {
+
+  use rust_decimal_macros::dec;
+
+  use super::*;
+  use crate::payments::{PaymentsEngine, Transaction};
+
+  #[tokio::test]
+  async fn deltas_since_the_snapshot() {
+    let mut payments_engine = InMemoryPaymentsEngine::new();
+    let deposit = |client_id, amount| Transaction::Deposit {
+      client_id,
+      transaction_id: client_id.into(),
+      amount,
+      timestamp: None,
+      sub_account: 0,
+    };
+    let transactions = vec![
+      deposit(1, dec!(100)),
+      deposit(2, dec!(50)),
+      Transaction::Dispute {
+        client_id: 2,
+        transaction_id: 2,
+        timestamp: None,
+      },
+    ];
+    for transaction in transactions {
+      payments_engine.process(transaction).await.unwrap();
+    }
+    let mut container = Vec::new();
+    payments_engine
+      .snapshot()
+      .unwrap()
+      .write_to(&mut container)
+      .unwrap();
+
+    let baseline = Baseline::load(container.as_slice()).await.unwrap();
+
+    assert_eq!(
+      baseline.deltas(1, dec!(70.5), dec!(0), false),
+      Deltas {
+        available_change: dec!(-29.5),
+        held_change: dec!(0),
+        newly_locked: false,
+      }
+    );
+    assert_eq!(
+      baseline.deltas(2, dec!(0), dec!(0), true),
+      Deltas {
+        available_change: dec!(0),
+        held_change: dec!(-50),
+        newly_locked: true,
+      }
+    );
+    assert_eq!(
+      baseline.deltas(3, dec!(10), dec!(0), false),
+      Deltas {
+        available_change: dec!(10),
+        held_change: dec!(0),
+        newly_locked: false,
+      }
+    );
+  }
+}
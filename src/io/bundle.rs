@@ -0,0 +1,113 @@
+use anyhow::Result;
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+
+const BLOCK_SIZE: usize = 512;
+
+/// A writer of tar archives (in the POSIX ustar format) with regular files, like the audit bundles,
+/// which can be extracted by any `tar` without further tooling.
+///
+/// The files are written in the order they are appended, with the permissions `0644` and no owner.
+/// Their names are limited to 100 bytes, as the longer ones would need the prefix field of the format.
+pub struct TarWriter<W> {
+  writer: W,
+}
+
+impl<W> TarWriter<W>
+where
+  W: AsyncWrite + Unpin,
+{
+  pub fn new(writer: W) -> Self {
+    Self { writer }
+  }
+
+  /// Append a file with its content and its modification time, in seconds since the Unix epoch.
+  pub async fn append(&mut self, name: &str, content: &[u8], modified: u64) -> Result<()> {
+    if name.is_empty() || name.len() > 100 {
+      anyhow::bail!("Invalid name for a file of a tar archive: {:?}", name);
+    }
+    self
+      .writer
+      .write_all(&header(name, content.len() as u64, modified))
+      .await?;
+    self.writer.write_all(content).await?;
+    let padding = (BLOCK_SIZE - content.len() % BLOCK_SIZE) % BLOCK_SIZE;
+    self.writer.write_all(&vec![0u8; padding]).await?;
+    Ok(())
+  }
+
+  /// Write the end of the archive, which is two empty blocks, and return the inner writer.
+  pub async fn finish(mut self) -> Result<W> {
+    self.writer.write_all(&[0u8; 2 * BLOCK_SIZE]).await?;
+    self.writer.flush().await?;
+    Ok(self.writer)
+  }
+}
+
+fn header(name: &str, size: u64, modified: u64) -> [u8; BLOCK_SIZE] {
+  let mut header = [0u8; BLOCK_SIZE];
+  header[..name.len()].copy_from_slice(name.as_bytes());
+  octal(&mut header[100..108], 0o644);
+  octal(&mut header[108..116], 0);
+  octal(&mut header[116..124], 0);
+  octal(&mut header[124..136], size);
+  octal(&mut header[136..148], modified);
+  header[156] = b'0';
+  header[257..263].copy_from_slice(b"ustar\0");
+  header[263..265].copy_from_slice(b"00");
+
+  // the checksum is computed with its own field filled with spaces
+  header[148..156].copy_from_slice(b"        ");
+  let checksum: u64 = header.iter().map(|byte| *byte as u64).sum();
+  octal(&mut header[148..155], checksum);
+  header
+}
+
+/// Fill the field with the value as octal digits padded with zeros, followed by a NUL.
+fn octal(field: &mut [u8], value: u64) {
+  let digits = format!("{:0width$o}", value, width = field.len() - 1);
+  field[..field.len() - 1].copy_from_slice(&digits.as_bytes()[digits.len() - (field.len() - 1)..]);
+  field[field.len() - 1] = 0;
+}
+
+#[cfg(test)]
+mod tests {
+
+  use super::*;
+
+  #[tokio::test]
+  async fn write_a_tar_archive() {
+    let mut tar = TarWriter::new(Vec::<u8>::new());
+    tar
+      .append("accounts.csv", b"client,available\n", 1600000000)
+      .await
+      .unwrap();
+    let archive = tar.finish().await.unwrap();
+
+    assert_eq!(archive.len(), 4 * BLOCK_SIZE);
+    assert_eq!(&archive[..12], b"accounts.csv");
+    assert_eq!(&archive[124..136], b"00000000021\0");
+    assert_eq!(&archive[136..148], b"13727410000\0");
+    assert_eq!(&archive[257..265], b"ustar\x0000");
+    let checksum: u64 = archive[..BLOCK_SIZE]
+      .iter()
+      .enumerate()
+      .map(|(index, byte)| match index {
+        148..=155 => b' ' as u64,
+        _ => *byte as u64,
+      })
+      .sum();
+    assert_eq!(
+      &archive[148..156],
+      format!("{:06o}\0 ", checksum).as_bytes()
+    );
+    assert_eq!(&archive[BLOCK_SIZE..BLOCK_SIZE + 17], b"client,available\n");
+    assert!(archive[BLOCK_SIZE + 17..].iter().all(|byte| *byte == 0));
+  }
+
+  #[tokio::test]
+  async fn refuse_long_names() {
+    let mut tar = TarWriter::new(Vec::<u8>::new());
+
+    assert!(tar.append(&"a".repeat(101), b"", 0).await.is_err());
+  }
+}
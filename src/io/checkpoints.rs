@@ -0,0 +1,151 @@
+use std::path::PathBuf;
+
+use anyhow::Result;
+use tokio::io::AsyncWriteExt;
+
+use crate::payments::Snapshot;
+
+const PREFIX: &str = "snapshot-";
+const SUFFIX: &str = ".snapshot";
+
+/// The number of snapshots kept by default, so an older one can still be restored when the latest one is unreadable.
+pub const DEFAULT_KEPT_CHECKPOINTS: usize = 3;
+
+/// A directory with the latest snapshots of an engine, every one taken at some position of its write-ahead log,
+/// so the state can be recovered by restoring the latest one and only replaying the log after its position.
+///
+/// The snapshots are written as `snapshot-<position>.snapshot` containers (see [`Snapshot::write_to`]).
+/// Every one is written and synced under another name first, and then renamed, so a process killed while writing it
+/// never leaves a partial snapshot behind the name of a complete one. Only the latest `kept` snapshots are kept.
+pub struct Checkpoints {
+  dir: PathBuf,
+  kept: usize,
+}
+
+impl Checkpoints {
+  pub fn new<P: Into<PathBuf>>(dir: P, kept: usize) -> Self {
+    Self {
+      dir: dir.into(),
+      kept: kept.max(1),
+    }
+  }
+
+  /// Write the snapshot taken at the position of the log, and remove the ones older than the snapshots kept.
+  pub async fn write(&self, position: u64, snapshot: &Snapshot) -> Result<PathBuf> {
+    tokio::fs::create_dir_all(&self.dir).await?;
+    let mut container = Vec::new();
+    snapshot.write_to(&mut container)?;
+
+    let file_name = format!("{}{:020}{}", PREFIX, position, SUFFIX);
+    let path = self.dir.join(&file_name);
+    let partial_path = self.dir.join(format!(".{}.partial", file_name));
+    let mut file = tokio::fs::File::create(&partial_path).await?;
+    file.write_all(&container).await?;
+    file.sync_all().await?;
+    drop(file);
+    tokio::fs::rename(&partial_path, &path).await?;
+
+    for old_position in self.positions().await?.into_iter().skip(self.kept) {
+      tokio::fs::remove_file(self.path(old_position)).await.ok();
+    }
+    Ok(path)
+  }
+
+  /// The latest snapshot that can be read, with the position of the log it was taken at.
+  /// The unreadable ones are skipped, falling back to the older ones.
+  pub async fn latest(&self) -> Result<Option<(u64, Snapshot)>> {
+    for position in self.positions().await? {
+      let path = self.path(position);
+      let snapshot = tokio::fs::read(&path)
+        .await
+        .map_err(anyhow::Error::from)
+        .and_then(|container| Snapshot::read_from(container.as_slice()).map_err(Into::into));
+      match snapshot {
+        Ok(snapshot) => return Ok(Some((position, snapshot))),
+        Err(err) => {
+          tracing::warn!(error = %err, path = %path.display(), "Skipping an unreadable snapshot")
+        }
+      }
+    }
+    Ok(None)
+  }
+
+  /// The positions of the snapshots of the directory, from the latest to the oldest.
+  async fn positions(&self) -> Result<Vec<u64>> {
+    let mut entries = match tokio::fs::read_dir(&self.dir).await {
+      Ok(entries) => entries,
+      Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+      Err(err) => return Err(err.into()),
+    };
+    let mut positions = Vec::new();
+    while let Some(entry) = entries.next_entry().await? {
+      let file_name = entry.file_name().to_string_lossy().to_string();
+      if let Some(position) = file_name
+        .strip_prefix(PREFIX)
+        .and_then(|name| name.strip_suffix(SUFFIX))
+        .and_then(|position| position.parse::<u64>().ok())
+      {
+        positions.push(position);
+      }
+    }
+    positions.sort_unstable_by(|a, b| b.cmp(a));
+    Ok(positions)
+  }
+
+  fn path(&self, position: u64) -> PathBuf {
+    self
+      .dir
+      .join(format!("{}{:020}{}", PREFIX, position, SUFFIX))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+
+  use rust_decimal_macros::dec;
+
+  use super::*;
+  use crate::payments::{InMemoryPaymentsEngine, SyncPaymentsEngine, Transaction};
+
+  #[tokio::test]
+  async fn keep_the_latest_snapshots() {
+    let dir = std::env::temp_dir().join(format!(
+      "toy-payments-engine-checkpoints-{}",
+      std::process::id()
+    ));
+    tokio::fs::remove_dir_all(&dir).await.ok();
+    let checkpoints = Checkpoints::new(&dir, 2);
+    let mut payments_engine = InMemoryPaymentsEngine::new();
+
+    assert!(checkpoints.latest().await.unwrap().is_none());
+    for position in 1..=3 {
+      payments_engine
+        .process_sync(Transaction::Deposit {
+          client_id: 1,
+          transaction_id: position as u32,
+          amount: dec!(10),
+          timestamp: None,
+          sub_account: 0,
+        })
+        .unwrap();
+      checkpoints
+        .write(position, &payments_engine.snapshot().unwrap())
+        .await
+        .unwrap();
+    }
+    // a snapshot that was being written when the process was killed
+    tokio::fs::write(checkpoints.path(4), b"partial")
+      .await
+      .unwrap();
+
+    let positions = checkpoints.positions().await.unwrap();
+    let (position, snapshot) = checkpoints.latest().await.unwrap().unwrap();
+    tokio::fs::remove_dir_all(&dir).await.unwrap();
+
+    assert_eq!(positions, vec![4, 3, 2]);
+    assert_eq!(position, 3);
+    let mut restored = InMemoryPaymentsEngine::new();
+    restored.restore(snapshot).unwrap();
+    assert_eq!(restored.account(1).unwrap().total, dec!(30));
+  }
+}
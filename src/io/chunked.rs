@@ -0,0 +1,152 @@
+use anyhow::Result;
+use futures::StreamExt;
+use tokio::io::{AsyncRead, AsyncReadExt};
+use tokio_stream::Stream;
+
+use super::amount::AmountParser;
+use super::reader::{CsvTransactionsReader, TransactionsReader};
+use crate::payments::Transaction;
+
+/// Implementation of [`TransactionsReader`] that parses the CSV input in parallel chunks.
+///
+/// The whole input is loaded into memory and split into chunks of approximately `chunk_size` bytes on line boundaries,
+/// which are parsed concurrently by the workers of the runtime. The transactions are yielded in their original order,
+/// so the engine sees exactly the same sequence than with the [`CsvTransactionsReader`].
+///
+/// Records are expected not to contain quoted line breaks, which is never the case for the transactions.
+/// Note that the positions reported in the format errors are relative to the chunk where they were found.
+pub struct ChunkedCsvTransactionsReader {
+  header: Vec<u8>,
+  body: Vec<u8>,
+  chunk_size: usize,
+  amount_parser: AmountParser,
+}
+
+impl ChunkedCsvTransactionsReader {
+  pub async fn load<R>(mut reader: R, chunk_size: usize) -> Result<Self>
+  where
+    R: AsyncRead + Unpin,
+  {
+    let mut header = Vec::new();
+    reader.read_to_end(&mut header).await?;
+    let header_end = header
+      .iter()
+      .position(|byte| *byte == b'\n')
+      .map_or(header.len(), |position| position + 1);
+    let body = header.split_off(header_end);
+
+    Ok(Self {
+      header,
+      body,
+      chunk_size: chunk_size.max(1),
+      amount_parser: AmountParser::default(),
+    })
+  }
+
+  pub fn with_amount_parser(mut self, amount_parser: AmountParser) -> Self {
+    self.amount_parser = amount_parser;
+    self
+  }
+
+  /// Split the body on line boundaries, and prepend the header to every chunk,
+  /// so they can be parsed independently with the same columns.
+  fn chunks(&self) -> Vec<Vec<u8>> {
+    let mut chunks = Vec::new();
+    let mut start = 0;
+    while start < self.body.len() {
+      let end = (start + self.chunk_size).min(self.body.len());
+      let end = self.body[end..]
+        .iter()
+        .position(|byte| *byte == b'\n')
+        .map_or(self.body.len(), |position| end + position + 1);
+
+      let mut chunk = Vec::with_capacity(self.header.len() + end - start);
+      chunk.extend_from_slice(&self.header);
+      chunk.extend_from_slice(&self.body[start..end]);
+      chunks.push(chunk);
+      start = end;
+    }
+    chunks
+  }
+}
+
+impl TransactionsReader for ChunkedCsvTransactionsReader {
+  fn read_transactions<'a>(
+    &'a mut self,
+  ) -> Box<dyn Stream<Item = Result<Transaction>> + Unpin + 'a> {
+    let amount_parser = self.amount_parser;
+    let parsers = self
+      .chunks()
+      .into_iter()
+      .map(|chunk| {
+        tokio::spawn(async move {
+          let mut reader = CsvTransactionsReader::new(std::io::Cursor::new(chunk))
+            .with_amount_parser(amount_parser);
+          reader
+            .transactions()
+            .collect::<Vec<Result<Transaction>>>()
+            .await
+        })
+      })
+      .collect::<Vec<_>>();
+
+    // awaiting the parsers in the order of the chunks restores the original order of the transactions
+    Box::new(Box::pin(
+      futures::stream::iter(parsers)
+        .then(|parser| async move {
+          parser
+            .await
+            .unwrap_or_else(|error| vec![Err(anyhow::Error::from(error))])
+        })
+        .flat_map(futures::stream::iter),
+    ))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+
+  use super::*;
+  use indoc::indoc;
+
+  const INPUT: &str = indoc! { "
+    type,       client,   tx,  amount
+    deposit,         1,  101,     100
+     withdrawal,     2,  102,    10.5
+    dispute,         1,  101,
+    unknown,1,2,3
+    resolve,         1,  101
+    deposit,         3,  301,       1
+    chargeback,      1,  101,
+  " };
+
+  async fn read_all<R: TransactionsReader>(reader: &mut R) -> Vec<Option<Transaction>> {
+    reader
+      .read_transactions()
+      .map(|tx| tx.ok())
+      .collect::<Vec<Option<Transaction>>>()
+      .await
+  }
+
+  #[tokio::test(flavor = "multi_thread")]
+  async fn read_transactions_in_original_order() {
+    let expected = read_all(&mut CsvTransactionsReader::new(INPUT.as_bytes())).await;
+
+    for chunk_size in &[1, 10, 40, 1000] {
+      let mut reader = ChunkedCsvTransactionsReader::load(INPUT.as_bytes(), *chunk_size)
+        .await
+        .unwrap();
+
+      assert_eq!(read_all(&mut reader).await, expected);
+    }
+  }
+
+  #[tokio::test]
+  async fn read_transactions_without_records() {
+    let mut reader = ChunkedCsvTransactionsReader::load("type,client,tx,amount".as_bytes(), 10)
+      .await
+      .unwrap();
+
+    assert!(read_all(&mut reader).await.is_empty());
+  }
+}
@@ -0,0 +1,189 @@
+use std::ops::RangeInclusive;
+use std::path::PathBuf;
+
+use anyhow::Result;
+use tokio::fs::OpenOptions;
+use tokio::io::AsyncWriteExt;
+
+use crate::payments::{ClientId, StateDigest};
+
+const DIVERGENCES_FILE: &str = "divergences.csv";
+const DIVERGENCES_HEADER: &str = "position,replica,peer,clients\n";
+
+/// The number of positions whose digests are kept by every replica, so the slower ones can still compare with them.
+const DEFAULT_KEPT_POSITIONS: usize = 16;
+
+/// The replicas that diverged from another one at some position, and the ranges of client IDs whose accounts differ.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Divergence {
+  pub position: u64,
+  pub replica: String,
+  pub peer: String,
+  pub clients: Vec<RangeInclusive<ClientId>>,
+}
+
+/// The exchange of the [`StateDigest`]s of the replicas through a directory shared by all of them,
+/// like a network file system.
+///
+/// Every replica publishes its digest as `<position>/<replica>.json`, and compares it with the ones already published
+/// by the rest of the replicas at the same position, so the last replica getting to a position detects any divergence.
+/// The divergences are appended to the `divergences.csv` report of the directory,
+/// with the `position, replica, peer, clients` columns, where the clients are ranges like `256-511` separated by `;`.
+pub struct DigestExchange {
+  dir: PathBuf,
+  replica: String,
+  kept_positions: usize,
+}
+
+impl DigestExchange {
+  pub fn new<P: Into<PathBuf>>(dir: P, replica: String) -> Self {
+    Self {
+      dir: dir.into(),
+      replica,
+      kept_positions: DEFAULT_KEPT_POSITIONS,
+    }
+  }
+
+  /// Publish the digest of the replica, and return the divergences from the replicas that already published theirs.
+  pub async fn exchange(&self, digest: &StateDigest) -> Result<Vec<Divergence>> {
+    let position_dir = self.dir.join(digest.position.to_string());
+    tokio::fs::create_dir_all(&position_dir).await?;
+
+    // written under another name first, so the rest of the replicas never read it partially written
+    let path = position_dir.join(format!("{}.json", self.replica));
+    let partial_path = position_dir.join(format!(".{}.json.partial", self.replica));
+    tokio::fs::write(&partial_path, serde_json::to_vec(digest)?).await?;
+    tokio::fs::rename(&partial_path, &path).await?;
+
+    let mut divergences = Vec::new();
+    let mut entries = tokio::fs::read_dir(&position_dir).await?;
+    while let Some(entry) = entries.next_entry().await? {
+      let file_name = entry.file_name().to_string_lossy().to_string();
+      let peer = match file_name.strip_suffix(".json") {
+        Some(peer) if peer != self.replica && !peer.starts_with('.') => peer.to_string(),
+        _ => continue,
+      };
+      let peer_digest: StateDigest = serde_json::from_slice(&tokio::fs::read(entry.path()).await?)?;
+      let clients = digest.diverging_clients(&peer_digest);
+      if !clients.is_empty() {
+        divergences.push(Divergence {
+          position: digest.position,
+          replica: self.replica.clone(),
+          peer,
+          clients,
+        });
+      }
+    }
+    divergences.sort_by(|a, b| a.peer.cmp(&b.peer));
+
+    self.report(&divergences).await?;
+    self.remove_old_digests(digest.position).await?;
+    Ok(divergences)
+  }
+
+  async fn report(&self, divergences: &[Divergence]) -> Result<()> {
+    if divergences.is_empty() {
+      return Ok(());
+    }
+    let path = self.dir.join(DIVERGENCES_FILE);
+    let mut file = OpenOptions::new()
+      .create(true)
+      .append(true)
+      .open(&path)
+      .await?;
+    let mut records = String::new();
+    if file.metadata().await?.len() == 0 {
+      records.push_str(DIVERGENCES_HEADER);
+    }
+    for divergence in divergences {
+      let clients: Vec<String> = divergence
+        .clients
+        .iter()
+        .map(|range| format!("{}-{}", range.start(), range.end()))
+        .collect();
+      records.push_str(&format!(
+        "{},{},{},{}\n",
+        divergence.position,
+        divergence.replica,
+        divergence.peer,
+        clients.join(";")
+      ));
+    }
+    file.write_all(records.as_bytes()).await?;
+    file.flush().await?;
+    Ok(())
+  }
+
+  /// Remove the digests of the replica older than the positions kept.
+  async fn remove_old_digests(&self, position: u64) -> Result<()> {
+    let mut positions = Vec::new();
+    let mut entries = tokio::fs::read_dir(&self.dir).await?;
+    while let Some(entry) = entries.next_entry().await? {
+      if let Ok(entry_position) = entry.file_name().to_string_lossy().parse::<u64>() {
+        if entry_position < position {
+          positions.push(entry_position);
+        }
+      }
+    }
+    positions.sort_unstable_by(|a, b| b.cmp(a));
+    for old_position in positions.into_iter().skip(self.kept_positions) {
+      let path = self
+        .dir
+        .join(old_position.to_string())
+        .join(format!("{}.json", self.replica));
+      tokio::fs::remove_file(&path).await.ok();
+    }
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+
+  use rust_decimal_macros::dec;
+
+  use super::*;
+  use crate::payments::AccountReport;
+
+  #[tokio::test]
+  async fn exchange_digests_between_replicas() {
+    let dir = std::env::temp_dir().join(format!(
+      "toy-payments-engine-digests-{}",
+      std::process::id()
+    ));
+    tokio::fs::remove_dir_all(&dir).await.ok();
+    let digest = |position, locked| {
+      StateDigest::of(
+        position,
+        vec![AccountReport::new(7, dec!(10), dec!(0), dec!(10), locked)],
+      )
+    };
+    let first = DigestExchange::new(&dir, "first".to_string());
+    let second = DigestExchange::new(&dir, "second".to_string());
+
+    let nothing_to_compare = first.exchange(&digest(10, false)).await.unwrap();
+    let same = second.exchange(&digest(10, false)).await.unwrap();
+    first.exchange(&digest(20, false)).await.unwrap();
+    let diverged = second.exchange(&digest(20, true)).await.unwrap();
+    let report = tokio::fs::read_to_string(dir.join(DIVERGENCES_FILE))
+      .await
+      .unwrap();
+    tokio::fs::remove_dir_all(&dir).await.unwrap();
+
+    assert!(nothing_to_compare.is_empty());
+    assert!(same.is_empty());
+    assert_eq!(
+      diverged,
+      vec![Divergence {
+        position: 20,
+        replica: "second".to_string(),
+        peer: "first".to_string(),
+        clients: vec![0..=255],
+      }]
+    );
+    assert_eq!(
+      report,
+      "position,replica,peer,clients\n20,second,first,0-255\n"
+    );
+  }
+}
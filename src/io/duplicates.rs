@@ -0,0 +1,219 @@
+use std::collections::HashMap;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use serde::Serialize;
+use tokio::io::AsyncWrite;
+
+use crate::payments::{ClientId, PaymentsEngineError, Transaction, TransactionId};
+
+/// A transaction rejected as a duplicate of one accepted before for the same client.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Duplicate {
+  pub client_id: ClientId,
+  pub transaction_id: TransactionId,
+  /// The line of the transaction accepted first, when known.
+  pub first_line: Option<u64>,
+  /// The line of the duplicate, when known.
+  pub duplicate_line: Option<u64>,
+  /// Whether all the fields of the duplicate match the ones of the transaction accepted first,
+  /// which usually means that it was submitted twice, instead of reusing the id for a different transaction.
+  pub matching: bool,
+}
+
+/// Keeps track of the transactions accepted by the engine, to describe the duplicates it rejects afterwards.
+#[derive(Debug, Default)]
+pub struct DuplicateDetector {
+  first_seen: HashMap<(ClientId, TransactionId), (Option<u64>, Transaction)>,
+}
+
+impl DuplicateDetector {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Observe the result of processing the transaction read from the line.
+  /// It returns the [`Duplicate`] when the engine rejected it as such.
+  pub fn observe(
+    &mut self,
+    line: Option<u64>,
+    transaction: Transaction,
+    result: &Result<(), PaymentsEngineError>,
+  ) -> Option<Duplicate> {
+    let client_id = transaction.client_id();
+    match (&transaction, result) {
+      (
+        Transaction::Deposit { transaction_id, .. }
+        | Transaction::Withdrawal { transaction_id, .. }
+        | Transaction::Transfer { transaction_id, .. },
+        Ok(()),
+      ) => {
+        let key = (client_id, *transaction_id);
+        self.first_seen.entry(key).or_insert((line, transaction));
+        None
+      }
+      (_, Err(PaymentsEngineError::DuplicatedTransaction(transaction_id))) => {
+        let first_seen = self.first_seen.get(&(client_id, *transaction_id));
+        Some(Duplicate {
+          client_id,
+          transaction_id: *transaction_id,
+          first_line: first_seen.and_then(|(line, _)| *line),
+          duplicate_line: line,
+          matching: first_seen.map_or(false, |(_, first)| *first == transaction),
+        })
+      }
+      _ => None,
+    }
+  }
+}
+
+/// Interface for a destination of the [`Duplicate`] transactions, as soon as they are detected.
+#[async_trait]
+pub trait DuplicatesSink: Send {
+  async fn duplicate(&mut self, duplicate: Duplicate) -> Result<()>;
+}
+
+/// A serializable duplicate
+#[derive(Serialize)]
+struct DuplicateRecord {
+  client: ClientId,
+  tx: TransactionId,
+  first_line: Option<u64>,
+  duplicate_line: Option<u64>,
+  matching: bool,
+}
+
+impl From<Duplicate> for DuplicateRecord {
+  fn from(duplicate: Duplicate) -> Self {
+    DuplicateRecord {
+      client: duplicate.client_id,
+      tx: duplicate.transaction_id,
+      first_line: duplicate.first_line,
+      duplicate_line: duplicate.duplicate_line,
+      matching: duplicate.matching,
+    }
+  }
+}
+
+/// An implementation of [`DuplicatesSink`] for the CSV format, with the `client, tx, first_line, duplicate_line, matching` columns.
+/// Every duplicate is flushed as soon as it is written, so the file is complete even if the processing fails afterwards.
+pub struct CsvDuplicatesSink<W>
+where
+  W: AsyncWrite + Unpin,
+{
+  serializer: csv_async::AsyncSerializer<W>,
+}
+
+impl<W> CsvDuplicatesSink<W>
+where
+  W: AsyncWrite + Unpin + Send + Sync,
+{
+  pub fn new(writer: W) -> Self {
+    Self {
+      serializer: csv_async::AsyncSerializer::from_writer(writer),
+    }
+  }
+}
+
+#[async_trait]
+impl<W> DuplicatesSink for CsvDuplicatesSink<W>
+where
+  W: AsyncWrite + Unpin + Send + Sync,
+{
+  async fn duplicate(&mut self, duplicate: Duplicate) -> Result<()> {
+    self
+      .serializer
+      .serialize(DuplicateRecord::from(duplicate))
+      .await?;
+    self.serializer.flush().await?;
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+
+  use indoc::indoc;
+  use rust_decimal_macros::dec;
+
+  use super::*;
+
+  #[test]
+  fn detect_duplicates() {
+    let mut detector = DuplicateDetector::new();
+    let deposit = |client_id, amount| Transaction::Deposit {
+      client_id,
+      transaction_id: 101,
+      amount,
+      timestamp: None,
+      sub_account: 0,
+    };
+    let duplicated = Err(PaymentsEngineError::DuplicatedTransaction(101));
+
+    assert_eq!(
+      detector.observe(Some(2), deposit(1, dec!(10)), &Ok(())),
+      None
+    );
+    assert_eq!(
+      detector.observe(Some(3), deposit(2, dec!(10)), &Ok(())),
+      None
+    );
+    assert_eq!(
+      detector.observe(Some(4), deposit(1, dec!(10)), &duplicated),
+      Some(Duplicate {
+        client_id: 1,
+        transaction_id: 101,
+        first_line: Some(2),
+        duplicate_line: Some(4),
+        matching: true,
+      })
+    );
+    assert_eq!(
+      detector.observe(Some(5), deposit(2, dec!(20)), &duplicated),
+      Some(Duplicate {
+        client_id: 2,
+        transaction_id: 101,
+        first_line: Some(3),
+        duplicate_line: Some(5),
+        matching: false,
+      })
+    );
+  }
+
+  #[tokio::test]
+  async fn duplicates_into_csv() {
+    let mut buffer = Vec::<u8>::with_capacity(1024);
+    let mut sink = CsvDuplicatesSink::new(&mut buffer);
+
+    sink
+      .duplicate(Duplicate {
+        client_id: 1,
+        transaction_id: 101,
+        first_line: Some(2),
+        duplicate_line: Some(4),
+        matching: true,
+      })
+      .await
+      .unwrap();
+    sink
+      .duplicate(Duplicate {
+        client_id: 2,
+        transaction_id: 102,
+        first_line: None,
+        duplicate_line: None,
+        matching: false,
+      })
+      .await
+      .unwrap();
+    drop(sink);
+
+    assert_eq!(
+      String::from_utf8_lossy(buffer.as_slice()),
+      indoc! { "
+        client,tx,first_line,duplicate_line,matching
+        1,101,2,4,true
+        2,102,,,false
+      " }
+    );
+  }
+}
@@ -0,0 +1,242 @@
+use std::collections::VecDeque;
+
+use anyhow::Result;
+use rust_decimal::Decimal;
+use serde::Serialize;
+use tokio::io::AsyncWrite;
+
+use crate::payments::{ClientId, Transaction, TransactionId, MAIN_SUB_ACCOUNT};
+
+/// Number of the latest deposits that can be disputed, as disputes usually refer to recent transactions.
+const DISPUTABLE_DEPOSITS: usize = 65_536;
+
+/// The shape of a synthetic dataset of transactions.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GeneratorConfig {
+  /// The number of transactions generated.
+  pub transactions: u64,
+  /// The number of clients the transactions are spread across.
+  pub clients: ClientId,
+  /// The fraction of the deposits that are disputed, which are later either resolved or charged back.
+  pub dispute_ratio: f64,
+  /// The fraction of the disputes that end up charged back instead of resolved.
+  pub chargeback_ratio: f64,
+  /// The same seed generates the same transactions.
+  pub seed: u64,
+}
+
+impl Default for GeneratorConfig {
+  fn default() -> Self {
+    Self {
+      transactions: 1_000_000,
+      clients: 1_000,
+      dispute_ratio: 0.01,
+      chargeback_ratio: 0.2,
+      seed: 0,
+    }
+  }
+}
+
+/// Generates a synthetic sequence of transactions, to benchmark the engine with realistic datasets of any size.
+///
+/// Most of the transactions are deposits and withdrawals of random amounts, uniformly spread across the clients.
+/// A fraction of the latest deposits are disputed, and every dispute is eventually either resolved or charged back,
+/// so the locked accounts and the rejected transactions grow with the dataset as they do in real inputs.
+pub struct TransactionsGenerator {
+  config: GeneratorConfig,
+  state: u64,
+  generated: u64,
+  deposits: VecDeque<(ClientId, TransactionId)>,
+  disputes: VecDeque<(ClientId, TransactionId)>,
+}
+
+impl TransactionsGenerator {
+  pub fn new(config: GeneratorConfig) -> Self {
+    let state = config.seed;
+    Self {
+      config,
+      state,
+      generated: 0,
+      deposits: VecDeque::new(),
+      disputes: VecDeque::new(),
+    }
+  }
+
+  /// Write all the transactions as CSV, with the `type, client, tx, amount` columns.
+  pub async fn write_csv<W>(self, writer: W) -> Result<()>
+  where
+    W: AsyncWrite + Unpin + Send + Sync,
+  {
+    let mut serializer = csv_async::AsyncSerializer::from_writer(writer);
+    for transaction in self {
+      serializer
+        .serialize(GeneratedRecord::from(&transaction))
+        .await?;
+    }
+    serializer.flush().await?;
+    Ok(())
+  }
+
+  /// A random number (splitmix64), which is enough for synthetic data and keeps the datasets reproducible.
+  fn next_random(&mut self) -> u64 {
+    self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    let mut z = self.state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+  }
+
+  /// A random number in `[0, 1)`.
+  fn next_fraction(&mut self) -> f64 {
+    (self.next_random() >> 11) as f64 / (1u64 << 53) as f64
+  }
+
+  fn next_client(&mut self) -> ClientId {
+    (self.next_random() % u64::from(self.config.clients.max(1))) as ClientId + 1
+  }
+
+  /// An amount between 0.0001 and 1000.0000.
+  fn next_amount(&mut self) -> Decimal {
+    Decimal::new((self.next_random() % 10_000_000) as i64 + 1, 4)
+  }
+}
+
+impl Iterator for TransactionsGenerator {
+  type Item = Transaction;
+
+  fn next(&mut self) -> Option<Self::Item> {
+    if self.generated >= self.config.transactions {
+      return None;
+    }
+    self.generated += 1;
+    let transaction_id = self.generated as TransactionId;
+
+    // the disputes are closed at the same pace they are open, so only a few are open at any time
+    if self.next_fraction() < self.config.dispute_ratio {
+      if let Some((client_id, transaction_id)) = self.disputes.pop_front() {
+        return Some(if self.next_fraction() < self.config.chargeback_ratio {
+          Transaction::Chargeback {
+            client_id,
+            transaction_id,
+            timestamp: None,
+          }
+        } else {
+          Transaction::Resolve {
+            client_id,
+            transaction_id,
+            timestamp: None,
+          }
+        });
+      }
+    }
+
+    if !self.deposits.is_empty() && self.next_fraction() < self.config.dispute_ratio {
+      let index = self.next_random() as usize % self.deposits.len();
+      if let Some((client_id, transaction_id)) = self.deposits.swap_remove_back(index) {
+        self.disputes.push_back((client_id, transaction_id));
+        return Some(Transaction::Dispute {
+          client_id,
+          transaction_id,
+          timestamp: None,
+        });
+      }
+    }
+
+    let client_id = self.next_client();
+    let amount = self.next_amount();
+    // more deposits than withdrawals, so most of the withdrawals have enough funds
+    if self.next_fraction() < 0.65 {
+      if self.deposits.len() == DISPUTABLE_DEPOSITS {
+        self.deposits.pop_front();
+      }
+      self.deposits.push_back((client_id, transaction_id));
+      Some(Transaction::Deposit {
+        client_id,
+        transaction_id,
+        amount,
+        timestamp: None,
+        sub_account: MAIN_SUB_ACCOUNT,
+      })
+    } else {
+      Some(Transaction::Withdrawal {
+        client_id,
+        transaction_id,
+        amount,
+        timestamp: None,
+        sub_account: MAIN_SUB_ACCOUNT,
+      })
+    }
+  }
+}
+
+/// A serializable generated transaction
+#[derive(Serialize)]
+struct GeneratedRecord {
+  #[serde(rename = "type")]
+  kind: &'static str,
+  client: ClientId,
+  tx: Option<TransactionId>,
+  amount: Option<Decimal>,
+}
+
+impl From<&Transaction> for GeneratedRecord {
+  fn from(transaction: &Transaction) -> Self {
+    let amount = match *transaction {
+      Transaction::Deposit { amount, .. } | Transaction::Withdrawal { amount, .. } => Some(amount),
+      _ => None,
+    };
+    GeneratedRecord {
+      kind: transaction.type_name(),
+      client: transaction.client_id(),
+      tx: transaction.transaction_id(),
+      amount,
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+
+  use tokio_stream::StreamExt;
+
+  use super::*;
+  use crate::io::{CsvTransactionsReader, TransactionsReader};
+
+  #[tokio::test]
+  async fn generate_readable_transactions() {
+    let config = GeneratorConfig {
+      transactions: 10_000,
+      clients: 10,
+      dispute_ratio: 0.1,
+      ..GeneratorConfig::default()
+    };
+    let expected: Vec<Transaction> = TransactionsGenerator::new(config.clone()).collect();
+    let mut out = Vec::<u8>::new();
+
+    TransactionsGenerator::new(config)
+      .write_csv(&mut out)
+      .await
+      .unwrap();
+
+    let mut reader = CsvTransactionsReader::new(out.as_slice());
+    let transactions: Vec<Transaction> = reader
+      .read_transactions()
+      .map(|transaction| transaction.unwrap())
+      .collect()
+      .await;
+    assert_eq!(transactions, expected);
+    assert!(transactions
+      .iter()
+      .all(|transaction| (1..=10).contains(&transaction.client_id())));
+    let count = |type_name: &str| {
+      transactions
+        .iter()
+        .filter(|transaction| transaction.type_name() == type_name)
+        .count()
+    };
+    assert!(count("dispute") > 0);
+    assert!(count("resolve") > 0);
+    assert!(count("chargeback") > 0);
+    assert!(count("deposit") > count("withdrawal"));
+  }
+}
@@ -0,0 +1,111 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+use sha2::{Digest, Sha256};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+
+const READ_BUFFER_SIZE: usize = 64 * 1024;
+
+/// Compute the fingerprint of an input file as the hex encoded SHA-256 of its contents.
+pub async fn fingerprint_file<P: AsRef<Path>>(path: P) -> Result<String> {
+  let mut file = tokio::fs::File::open(path).await?;
+  let mut hasher = Sha256::new();
+  let mut buffer = vec![0u8; READ_BUFFER_SIZE];
+  loop {
+    let size = file.read(&mut buffer).await?;
+    if size == 0 {
+      break;
+    }
+    hasher.update(&buffer[..size]);
+  }
+  Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// The history of the inputs already processed, stored as a file with one fingerprint per line.
+pub struct InputHistory {
+  path: PathBuf,
+}
+
+impl InputHistory {
+  pub fn new<P: Into<PathBuf>>(path: P) -> Self {
+    Self { path: path.into() }
+  }
+
+  /// Whether an input with the same fingerprint was already recorded. A missing history file is considered empty.
+  pub async fn contains(&self, fingerprint: &str) -> Result<bool> {
+    let file = match tokio::fs::File::open(&self.path).await {
+      Ok(file) => file,
+      Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(false),
+      Err(err) => return Err(err.into()),
+    };
+
+    let mut lines = BufReader::new(file).lines();
+    while let Some(line) = lines.next_line().await? {
+      if line.trim() == fingerprint {
+        return Ok(true);
+      }
+    }
+    Ok(false)
+  }
+
+  /// Record the fingerprint of a processed input, creating the history file if needed.
+  pub async fn record(&self, fingerprint: &str) -> Result<()> {
+    let mut file = tokio::fs::OpenOptions::new()
+      .create(true)
+      .append(true)
+      .open(&self.path)
+      .await?;
+    file
+      .write_all(format!("{}\n", fingerprint).as_bytes())
+      .await?;
+    file.flush().await?;
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+
+  use super::*;
+
+  fn temp_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!(
+      "toy-payments-engine-{}-{}",
+      name,
+      std::process::id()
+    ))
+  }
+
+  #[tokio::test]
+  async fn fingerprint_file_contents() {
+    let path = temp_path("fingerprint");
+    tokio::fs::write(&path, "type,client,tx,amount\n")
+      .await
+      .unwrap();
+
+    let fingerprint = fingerprint_file(&path).await;
+    tokio::fs::remove_file(&path).await.unwrap();
+
+    assert_eq!(
+      fingerprint.unwrap(),
+      "0c4bb2c522b6691f4c8e807cc5ba1e464fb93b298a7c3fad6b54cf850a09a987"
+    );
+  }
+
+  #[tokio::test]
+  async fn input_history_record_and_contains() {
+    let path = temp_path("history");
+    let history = InputHistory::new(&path);
+
+    let before = history.contains("abc").await.unwrap();
+    history.record("abc").await.unwrap();
+    history.record("def").await.unwrap();
+    let after = history.contains("abc").await.unwrap();
+    let other = history.contains("ghi").await.unwrap();
+    tokio::fs::remove_file(&path).await.unwrap();
+
+    assert!(!before);
+    assert!(after);
+    assert!(!other);
+  }
+}
@@ -0,0 +1,225 @@
+use std::collections::{HashMap, HashSet};
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::Path;
+
+use anyhow::{anyhow, Result};
+use sha2::{Digest, Sha256};
+use tokio_stream::{Stream, StreamExt};
+
+use super::reader::{TransactionRecord, TransactionsReader};
+use crate::payments::Transaction;
+
+/// Interface for a store of the keys of the records already ingested, so they are not applied twice.
+pub trait IdempotencyStore: Send {
+  /// Record the key of an ingested record, returning whether it was not recorded before.
+  fn insert(&mut self, key: &str) -> Result<bool>;
+}
+
+/// An [`IdempotencyStore`] that only remembers the keys during the processing.
+#[derive(Debug, Default)]
+pub struct InMemoryIdempotencyStore(HashSet<String>);
+
+impl InMemoryIdempotencyStore {
+  pub fn new() -> Self {
+    Self::default()
+  }
+}
+
+impl IdempotencyStore for InMemoryIdempotencyStore {
+  fn insert(&mut self, key: &str) -> Result<bool> {
+    Ok(self.0.insert(key.to_string()))
+  }
+}
+
+/// An [`IdempotencyStore`] that keeps the keys in a file with one key per line, so they are remembered across runs.
+/// All the keys are loaded in memory when it is opened, and the new ones are appended to the file as soon as they are inserted.
+pub struct FileIdempotencyStore {
+  keys: HashSet<String>,
+  file: File,
+}
+
+impl FileIdempotencyStore {
+  /// Open the store, creating the file if needed.
+  pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+    let file = OpenOptions::new()
+      .create(true)
+      .read(true)
+      .append(true)
+      .open(path)?;
+    let keys = BufReader::new(&file)
+      .lines()
+      .map(|line| line.map(|line| line.trim().to_string()))
+      .filter(|line| !matches!(line, Ok(line) if line.is_empty()))
+      .collect::<std::io::Result<HashSet<String>>>()?;
+    Ok(Self { keys, file })
+  }
+}
+
+impl IdempotencyStore for FileIdempotencyStore {
+  fn insert(&mut self, key: &str) -> Result<bool> {
+    if self.keys.contains(key) {
+      return Ok(false);
+    }
+    self.file.write_all(format!("{}\n", key).as_bytes())?;
+    self.keys.insert(key.to_string());
+    Ok(true)
+  }
+}
+
+/// How the records are identified to detect the ones already ingested.
+#[derive(Debug, Clone, PartialEq)]
+pub enum IdempotencyKey {
+  /// The SHA-256 of the content of the transaction without the ID of the deposits, withdrawals, transfers and authorizations,
+  /// so the same input ingested again with new IDs is still detected. Identical transactions of an input are told apart
+  /// by counting them, but an identical transaction in another input (same client, amount and timestamp) is considered the same record.
+  Record,
+  /// The SHA-256 of the source (like the path of the input) and the line of the record, whatever its content.
+  /// The records whose line is unknown are never considered already ingested.
+  Position { source: String },
+}
+
+/// A [`TransactionsReader`] that rejects the records already ingested according to an [`IdempotencyStore`],
+/// so ingesting the same input twice by mistake doesn't apply its transactions twice.
+/// The rejected records are reported as read errors, and the ones that can't be read are always kept.
+pub struct IdempotentTransactionsReader<R> {
+  inner: R,
+  store: Box<dyn IdempotencyStore>,
+  key: IdempotencyKey,
+  occurrences: HashMap<String, u64>,
+}
+
+impl<R> IdempotentTransactionsReader<R>
+where
+  R: TransactionsReader,
+{
+  pub fn new(inner: R, store: Box<dyn IdempotencyStore>, key: IdempotencyKey) -> Self {
+    Self {
+      inner,
+      store,
+      key,
+      occurrences: HashMap::new(),
+    }
+  }
+}
+
+impl<R> TransactionsReader for IdempotentTransactionsReader<R>
+where
+  R: TransactionsReader,
+{
+  fn read_transactions<'a>(
+    &'a mut self,
+  ) -> Box<dyn Stream<Item = Result<Transaction>> + Unpin + 'a> {
+    Box::new(self.read_records().map(|record| record.transaction))
+  }
+
+  fn read_records<'a>(&'a mut self) -> Box<dyn Stream<Item = TransactionRecord> + Unpin + 'a> {
+    let store = &mut self.store;
+    let key = &self.key;
+    let occurrences = &mut self.occurrences;
+    Box::new(self.inner.read_records().map(move |mut record| {
+      let record_key = match (&record.transaction, key) {
+        (Ok(transaction), IdempotencyKey::Record) => {
+          let content = format!("{:?}", without_own_id(transaction.clone()));
+          let occurrence = occurrences.entry(content.clone()).or_default();
+          *occurrence += 1;
+          Some(hash(&format!("{}#{}", content, occurrence)))
+        }
+        (Ok(_), IdempotencyKey::Position { source }) => record
+          .line
+          .map(|line| hash(&format!("{}:{}", source, line))),
+        (Err(_), _) => None,
+      };
+      if let Some(record_key) = record_key {
+        record.transaction = match store.insert(&record_key) {
+          Ok(true) => record.transaction,
+          Ok(false) => Err(anyhow!("The record was already ingested")),
+          Err(err) => Err(anyhow!("Failed to record the ingested record: {}", err)),
+        };
+      }
+      record
+    }))
+  }
+}
+
+/// The transaction without the ID that it introduces, keeping the one that disputes, resolutions and the rest refer to.
+fn without_own_id(mut transaction: Transaction) -> Transaction {
+  match &mut transaction {
+    Transaction::Deposit { transaction_id, .. }
+    | Transaction::Withdrawal { transaction_id, .. }
+    | Transaction::Transfer { transaction_id, .. }
+    | Transaction::Authorize { transaction_id, .. } => *transaction_id = 0,
+    _ => {}
+  }
+  transaction
+}
+
+fn hash(content: &str) -> String {
+  format!("{:x}", Sha256::digest(content.as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+
+  use indoc::indoc;
+
+  use super::*;
+  use crate::io::CsvTransactionsReader;
+
+  const TRANSACTIONS: &str = indoc! { "
+    type,       client,   tx,  amount
+    deposit,         1,  101,     100
+    deposit,         1,  102,     100
+    dispute,         1,  101,
+  " };
+
+  const RENUMBERED: &str = indoc! { "
+    type,       client,   tx,  amount
+    deposit,         1,  201,     100
+    deposit,         1,  202,     100
+    deposit,         1,  203,     100
+    dispute,         1,  201,
+  " };
+
+  async fn ingest(
+    input: &str,
+    store: Box<dyn IdempotencyStore>,
+    key: IdempotencyKey,
+  ) -> Vec<Option<u64>> {
+    let mut reader =
+      IdempotentTransactionsReader::new(CsvTransactionsReader::new(input.as_bytes()), store, key);
+    reader
+      .read_records()
+      .filter(|record| record.transaction.is_ok())
+      .map(|record| record.line)
+      .collect()
+      .await
+  }
+
+  #[tokio::test]
+  async fn read_records_ingested_once() {
+    let path = std::env::temp_dir().join(format!(
+      "toy-payments-engine-idempotency-{}",
+      std::process::id()
+    ));
+    std::fs::remove_file(&path).ok();
+
+    let store = Box::new(FileIdempotencyStore::open(&path).unwrap());
+    let ingested = ingest(TRANSACTIONS, store, IdempotencyKey::Record).await;
+    assert_eq!(ingested, vec![Some(2), Some(3), Some(4)]);
+
+    // the same deposits with new IDs are detected, but not the extra one, nor the dispute of another transaction
+    let store = Box::new(FileIdempotencyStore::open(&path).unwrap());
+    let ingested = ingest(RENUMBERED, store, IdempotencyKey::Record).await;
+    assert_eq!(ingested, vec![Some(4), Some(5)]);
+    std::fs::remove_file(&path).unwrap();
+
+    let mut store = InMemoryIdempotencyStore::new();
+    let source = IdempotencyKey::Position {
+      source: "transactions.csv".to_string(),
+    };
+    store.insert(&hash("transactions.csv:3")).unwrap();
+    let ingested = ingest(TRANSACTIONS, Box::new(store), source).await;
+    assert_eq!(ingested, vec![Some(2), Some(4)]);
+  }
+}
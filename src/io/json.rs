@@ -0,0 +1,173 @@
+use anyhow::{bail, Result};
+use csv_async::StringRecord;
+use serde_json::Value;
+use tokio::io::{AsyncBufReadExt, AsyncRead, BufReader};
+use tokio_stream::Stream;
+
+use super::pipeline::{
+  DecodedTransactionsReader, RecordSource, SourceItem, TransactionDecoder, COLUMNS,
+};
+
+/// Implementation of [`TransactionsReader`](super::TransactionsReader) for newline delimited JSON,
+/// with one object per line whose fields are named like the columns of the CSV format.
+///
+/// The amounts can be given as strings to keep their exact precision. Blank lines are skipped.
+pub type NdjsonTransactionsReader<R> = DecodedTransactionsReader<JsonSource<R>, JsonDecoder>;
+
+impl<R> NdjsonTransactionsReader<R>
+where
+  R: AsyncRead + Unpin + Send + Sync,
+{
+  pub fn new(reader: R) -> Self {
+    Self::from_parts(JsonSource::new(reader), JsonDecoder)
+  }
+}
+
+/// A [`RecordSource`] of the JSON values of a newline delimited input, with the line of every value.
+/// A line that is not valid JSON is rejected, but a failure reading the input stops the reading.
+pub struct JsonSource<R>(BufReader<R>);
+
+impl<R> JsonSource<R>
+where
+  R: AsyncRead + Unpin + Send + Sync,
+{
+  pub fn new(reader: R) -> Self {
+    Self(BufReader::new(reader))
+  }
+}
+
+impl<R> RecordSource for JsonSource<R>
+where
+  R: AsyncRead + Unpin + Send + Sync,
+{
+  type Item = Value;
+
+  fn items<'a>(&'a mut self) -> Box<dyn Stream<Item = SourceItem<Self::Item>> + Unpin + 'a> {
+    Box::new(Box::pin(futures::stream::unfold(
+      (&mut self.0, 0u64, false),
+      |(reader, mut line, failed)| async move {
+        if failed {
+          return None;
+        }
+        let mut buffer = String::new();
+        loop {
+          buffer.clear();
+          line += 1;
+          match reader.read_line(&mut buffer).await {
+            Ok(0) => return None,
+            Ok(_) if buffer.trim().is_empty() => continue,
+            Ok(_) => {
+              let item = SourceItem {
+                line: Some(line),
+                item: serde_json::from_str(&buffer).map_err(anyhow::Error::from),
+              };
+              return Some((item, (reader, line, false)));
+            }
+            Err(err) => {
+              let item = SourceItem {
+                line: Some(line),
+                item: Err(err.into()),
+              };
+              return Some((item, (reader, line, true)));
+            }
+          }
+        }
+      },
+    )))
+  }
+}
+
+/// A [`TransactionDecoder`] of JSON objects, by the names of their fields.
+pub struct JsonDecoder;
+
+impl TransactionDecoder for JsonDecoder {
+  type Item = Value;
+
+  fn decode(&self, value: Self::Item) -> Result<StringRecord> {
+    let mut fields = match value {
+      Value::Object(fields) => fields,
+      _ => bail!("The JSON value is not an object"),
+    };
+    COLUMNS
+      .iter()
+      .map(|name| match fields.remove(*name) {
+        None | Some(Value::Null) => Ok(String::new()),
+        Some(Value::String(value)) => Ok(value),
+        Some(Value::Number(value)) => Ok(value.to_string()),
+        Some(Value::Bool(value)) => Ok(value.to_string()),
+        Some(_) => bail!("Unsupported value for the {} field", name),
+      })
+      .collect()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+
+  use indoc::indoc;
+  use rust_decimal_macros::dec;
+  use tokio_stream::StreamExt;
+
+  use super::*;
+  use crate::io::TransactionsReader;
+  use crate::payments::Transaction;
+
+  #[tokio::test]
+  async fn read_records() {
+    let input = indoc! {r#"
+      {"type": "deposit", "client": 1, "tx": 101, "amount": "10.5", "sub": 2}
+
+      {"type": "transfer", "client": 1, "tx": 102, "amount": 2, "to": 2}
+      [1, 2]
+      {"type": "dispute", "client": 1, "tx": 101, "amount": null
+      {"type": "dispute", "client": 1, "tx": 101, "timestamp": 1600000000}
+    "#};
+
+    let mut reader = NdjsonTransactionsReader::new(input.as_bytes());
+
+    let records: Vec<(Option<u64>, Option<String>, Option<Transaction>)> = reader
+      .read_records()
+      .map(|record| (record.line, record.raw, record.transaction.ok()))
+      .collect()
+      .await;
+
+    assert_eq!(
+      records,
+      vec![
+        (
+          Some(1),
+          Some("deposit,1,101,10.5,,,2".to_string()),
+          Some(Transaction::Deposit {
+            client_id: 1,
+            transaction_id: 101,
+            amount: dec!(10.5),
+            timestamp: None,
+            sub_account: 2,
+          })
+        ),
+        (
+          Some(3),
+          Some("transfer,1,102,2,2,,".to_string()),
+          Some(Transaction::Transfer {
+            from_client: 1,
+            to_client: 2,
+            transaction_id: 102,
+            amount: dec!(2),
+            timestamp: None,
+          })
+        ),
+        (Some(4), None, None),
+        (Some(5), None, None),
+        (
+          Some(6),
+          Some("dispute,1,101,,,1600000000,".to_string()),
+          Some(Transaction::Dispute {
+            client_id: 1,
+            transaction_id: 101,
+            timestamp: Some(1600000000),
+          })
+        ),
+      ]
+    );
+  }
+}
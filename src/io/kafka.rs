@@ -0,0 +1,139 @@
+use anyhow::{Context, Result};
+use csv_async::StringRecord;
+use rdkafka::config::ClientConfig;
+use rdkafka::consumer::{Consumer, StreamConsumer};
+use rdkafka::Message;
+use tokio_stream::Stream;
+
+use super::json::JsonDecoder;
+use super::pipeline::{DecodedTransactionsReader, RecordSource, SourceItem, TransactionDecoder};
+
+/// Implementation of [`TransactionsReader`](super::TransactionsReader) for the messages of a Kafka topic,
+/// so the engine can process the transactions as a continuous stream instead of a file.
+///
+/// The offset of a message is only committed once the transaction after it is asked for,
+/// which for the processors that process the transactions in order means once it was processed.
+/// So the transactions are processed at least once by the consumer group, even when the process is stopped.
+pub type KafkaTransactionsReader = DecodedTransactionsReader<KafkaSource, KafkaDecoder>;
+
+impl KafkaTransactionsReader {
+  /// Subscribe to the topic as a member of the consumer group, starting from its earliest message
+  /// when the group has no committed offset yet.
+  pub fn subscribe(brokers: &str, group_id: &str, topic: &str) -> Result<Self> {
+    let consumer: StreamConsumer = ClientConfig::new()
+      .set("bootstrap.servers", brokers)
+      .set("group.id", group_id)
+      .set("enable.auto.commit", "true")
+      .set("enable.auto.offset.store", "false")
+      .set("auto.offset.reset", "earliest")
+      .create()?;
+    consumer.subscribe(&[topic])?;
+    Ok(Self::from_parts(KafkaSource::new(consumer), KafkaDecoder))
+  }
+}
+
+/// A [`RecordSource`] of the payloads of the messages received by a consumer.
+///
+/// It stores the offset of every message once the next one is asked for, to be committed by the auto commit,
+/// so the consumer needs to be configured with `enable.auto.offset.store=false`.
+pub struct KafkaSource {
+  consumer: StreamConsumer,
+}
+
+impl KafkaSource {
+  pub fn new(consumer: StreamConsumer) -> Self {
+    Self { consumer }
+  }
+}
+
+/// Where a message was received from, to store its offset once it is processed.
+struct Position {
+  topic: String,
+  partition: i32,
+  offset: i64,
+}
+
+impl RecordSource for KafkaSource {
+  type Item = Vec<u8>;
+
+  fn items<'a>(&'a mut self) -> Box<dyn Stream<Item = SourceItem<Self::Item>> + Unpin + 'a> {
+    Box::new(Box::pin(futures::stream::unfold(
+      (&self.consumer, None),
+      |(consumer, processed): (&StreamConsumer, Option<Position>)| async move {
+        if let Some(position) = processed {
+          // it fails when the partition was revoked meanwhile, so its next owner processes the message again
+          if let Err(err) =
+            consumer.store_offset(&position.topic, position.partition, position.offset)
+          {
+            tracing::warn!(
+              topic = %position.topic,
+              partition = position.partition,
+              offset = position.offset,
+              error = %err,
+              "Offset not stored"
+            );
+          }
+        }
+        let (item, position) = match consumer.recv().await {
+          Ok(message) => {
+            let position = Position {
+              topic: message.topic().to_string(),
+              partition: message.partition(),
+              offset: message.offset(),
+            };
+            let payload = message.payload().map(<[u8]>::to_vec).unwrap_or_default();
+            (Ok(payload), Some(position))
+          }
+          Err(err) => (Err(err.into()), None),
+        };
+        Some((SourceItem { line: None, item }, (consumer, position)))
+      },
+    )))
+  }
+}
+
+/// A [`TransactionDecoder`] of the payloads of the messages, which are either a JSON object with the fields named like
+/// the columns of the CSV format, or a CSV record with the columns in the order of the CSV format, without header.
+pub struct KafkaDecoder;
+
+impl TransactionDecoder for KafkaDecoder {
+  type Item = Vec<u8>;
+
+  fn decode(&self, payload: Self::Item) -> Result<StringRecord> {
+    let payload = std::str::from_utf8(&payload).context("The payload is not UTF-8")?;
+    let payload = payload.trim();
+    if payload.starts_with('{') {
+      JsonDecoder.decode(serde_json::from_str(payload)?)
+    } else {
+      Ok(payload.split(',').collect())
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+
+  use super::*;
+
+  #[test]
+  fn decode_payloads() {
+    let record = KafkaDecoder
+      .decode(br#"{"type": "deposit", "client": 1, "tx": 101, "amount": "10.5"}"#.to_vec())
+      .unwrap();
+    assert_eq!(
+      record,
+      StringRecord::from(vec!["deposit", "1", "101", "10.5", "", "", ""])
+    );
+
+    let record = KafkaDecoder
+      .decode(b"transfer, 1, 102, 2, 2\n".to_vec())
+      .unwrap();
+    assert_eq!(
+      record,
+      StringRecord::from(vec!["transfer", " 1", " 102", " 2", " 2"])
+    );
+
+    assert!(KafkaDecoder.decode(vec![0xff, 0xfe]).is_err());
+    assert!(KafkaDecoder.decode(b"{\"type\": ".to_vec()).is_err());
+  }
+}
@@ -0,0 +1,122 @@
+use std::collections::BTreeSet;
+use std::convert::TryFrom;
+use std::path::Path;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use tokio_stream::{Stream, StreamExt};
+
+use super::account::AccountReport;
+use super::writer::AccountsReportWriter;
+use crate::payments::{self, ClientId, PaymentsEngineError};
+
+/// The name of the tree of the database where the accounts are exported.
+const ACCOUNTS_TREE: &str = "accounts";
+
+/// An implementation of [`AccountsReportWriter`] into an embedded [sled](https://docs.rs/sled) database,
+/// so the final state can be loaded directly by the caches of other services.
+///
+/// Every account is stored in the `accounts` tree, with the client ID in big endian as key (so they are sorted),
+/// and the same JSON object than the [`NdjsonAccountsReportWriter`](super::NdjsonAccountsReportWriter) as value.
+/// The whole report is applied in a single batch, which also removes the accounts of previous exports not in the report,
+/// so readers never see a partial export.
+pub struct SledAccountsReportWriter {
+  db: sled::Db,
+}
+
+impl SledAccountsReportWriter {
+  /// Open the database at the path, creating it if needed.
+  pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+    Ok(Self::new(sled::open(path)?))
+  }
+
+  pub fn new(db: sled::Db) -> Self {
+    Self { db }
+  }
+}
+
+#[async_trait]
+impl AccountsReportWriter for SledAccountsReportWriter {
+  async fn write_accounts_report<'a, S>(&'a mut self, report: S) -> Result<()>
+  where
+    S: Stream<Item = Result<payments::AccountReport, PaymentsEngineError>> + Send + 'a,
+  {
+    let tree = self.db.open_tree(ACCOUNTS_TREE)?;
+    let mut batch = sled::Batch::default();
+    let mut exported = BTreeSet::<ClientId>::new();
+    let mut report = Box::pin(report);
+    while let Some(account_report) = report.next().await {
+      let account_report = account_report?;
+      let client_id = account_report.client_id;
+      let value = serde_json::to_vec(&AccountReport::from(account_report))?;
+      batch.insert(&client_id.to_be_bytes(), value);
+      exported.insert(client_id);
+    }
+    for key in tree.iter().keys() {
+      let key = key?;
+      let stale = match <[u8; 2]>::try_from(key.as_ref()) {
+        Ok(client_id) => !exported.contains(&ClientId::from_be_bytes(client_id)),
+        Err(_) => true,
+      };
+      if stale {
+        batch.remove(key);
+      }
+    }
+
+    tree.apply_batch(batch)?;
+    tree.flush_async().await?;
+    tracing::info!(accounts = exported.len(), "Accounts report written");
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+
+  use rust_decimal_macros::dec;
+
+  use super::*;
+
+  #[tokio::test]
+  async fn write_accounts_report_into_sled() {
+    let db = sled::Config::new().temporary(true).open().unwrap();
+    let mut writer = SledAccountsReportWriter::new(db.clone());
+
+    let report = vec![
+      payments::AccountReport::new(1, dec!(100), dec!(10), dec!(110), false),
+      payments::AccountReport::new(2, dec!(90.5), dec!(0), dec!(90.5), true),
+    ];
+    writer
+      .write_accounts_report(payments::AccountsReportStream::iter(report))
+      .await
+      .unwrap();
+    let report = vec![payments::AccountReport::new(
+      2,
+      dec!(80.5),
+      dec!(0),
+      dec!(80.5),
+      true,
+    )];
+    writer
+      .write_accounts_report(payments::AccountsReportStream::iter(report))
+      .await
+      .unwrap();
+
+    let tree = db.open_tree(ACCOUNTS_TREE).unwrap();
+    let accounts: Vec<(Vec<u8>, String)> = tree
+      .iter()
+      .map(|entry| {
+        let (key, value) = entry.unwrap();
+        (key.to_vec(), String::from_utf8_lossy(&value).into_owned())
+      })
+      .collect();
+    assert_eq!(
+      accounts,
+      vec![(
+        vec![0, 2],
+        "{\"client\":2,\"available\":\"80.5\",\"held\":\"0\",\"total\":\"80.5\",\"locked\":true}"
+          .to_string()
+      )]
+    );
+  }
+}
@@ -0,0 +1,316 @@
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+/// How long a lease is valid without being renewed, so the lock of a process that crashed can be taken over.
+pub const LEASE_DURATION: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Error)]
+pub enum LockError {
+  #[error("The state {0} is locked by another process (pid {1}) until {2}, use --steal-lock to take it over")]
+  StateLockedByAnotherProcess(String, u32, u64),
+  #[error("The lock of the state {0} was taken over by another process (pid {1})")]
+  LockLost(String, u32),
+  #[error("Failed to lock the state {0}: {1}")]
+  Io(String, std::io::Error),
+}
+
+/// The content of a lock file: who holds the lock, and until when (in seconds since the Unix epoch).
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct Lease {
+  owner: String,
+  pid: u32,
+  expires: u64,
+}
+
+/// An advisory lock of the state kept in some path (like a write-ahead log or a snapshots directory),
+/// so two runs started at the same time (like overlapping cron jobs) can't write it concurrently.
+///
+/// The lock is the `<path>.lock` file, created atomically with a lease that has to be renewed before it expires.
+/// The lease of a process that crashed expires, so it can be taken over by the next run, while the lease of a running
+/// process can only be stolen explicitly. The holder that finds its lease taken over when renewing it
+/// has to stop writing the state (see [`StateLock::renew`]). The lock file is removed when the lock is dropped.
+///
+/// The lock is only advisory: it protects the state from other runs of the engine, not from other processes.
+#[derive(Debug)]
+pub struct StateLock {
+  path: PathBuf,
+  lease: Lease,
+}
+
+impl StateLock {
+  /// Lock the state of the path, failing with [`LockError::StateLockedByAnotherProcess`] when another process holds
+  /// a lease that didn't expire yet, unless it has to be stolen.
+  pub async fn acquire<P: AsRef<Path>>(state: P, steal: bool) -> Result<Self, LockError> {
+    let path = Self::path(state.as_ref());
+    let pid = std::process::id();
+    let lease = Lease {
+      owner: format!("{}-{}", pid, now_nanos()),
+      pid,
+      expires: now_secs() + LEASE_DURATION.as_secs(),
+    };
+    let lock = Self { path, lease };
+
+    // the lease is written into its own file first, and then linked as the lock file, which fails when it exists
+    let partial_path = lock.partial_path();
+    lock.write_lease(&partial_path).await?;
+    let linked = tokio::fs::hard_link(&partial_path, &lock.path).await;
+    tokio::fs::remove_file(&partial_path).await.ok();
+    match linked {
+      Ok(()) => return Ok(lock),
+      Err(err) if err.kind() == std::io::ErrorKind::AlreadyExists => {}
+      Err(err) => return Err(lock.io_error(err)),
+    }
+
+    match lock.read_lease().await? {
+      Some(current) if current.expires >= now_secs() && !steal => {
+        Err(LockError::StateLockedByAnotherProcess(
+          lock.path.display().to_string(),
+          current.pid,
+          current.expires,
+        ))
+      }
+      current => {
+        tracing::warn!(
+          path = %lock.path.display(),
+          pid = ?current.map(|lease| lease.pid),
+          stolen = steal,
+          "Taking over the lock of the state"
+        );
+        lock.replace_lease().await?;
+        // another process could have taken it over at the same time, and only the last one replacing it holds it
+        match lock.read_lease().await? {
+          Some(current) if current.owner != lock.lease.owner => {
+            Err(LockError::StateLockedByAnotherProcess(
+              lock.path.display().to_string(),
+              current.pid,
+              current.expires,
+            ))
+          }
+          _ => Ok(lock),
+        }
+      }
+    }
+  }
+
+  /// The lock file of the state, like `wal.csv.lock`.
+  pub fn path(state: &Path) -> PathBuf {
+    let mut path = state.as_os_str().to_owned();
+    path.push(".lock");
+    PathBuf::from(path)
+  }
+
+  /// Extend the lease of the lock, which has to be done more often than the [`LEASE_DURATION`].
+  /// It fails with [`LockError::LockLost`] when another process took the lock over,
+  /// and then the state must not be written anymore.
+  pub async fn renew(&self) -> Result<(), LockError> {
+    // only replaced while it is still ours, as another process could have stolen it since the last renewal
+    match self.read_lease().await? {
+      Some(current) if current.owner != self.lease.owner => Err(LockError::LockLost(
+        self.path.display().to_string(),
+        current.pid,
+      )),
+      _ => self.replace_lease().await,
+    }
+  }
+
+  /// Run the future while holding the lock, renewing its lease in the meantime. It fails with [`LockError::LockLost`]
+  /// as soon as another process takes the lock over, without waiting for the future, as the state can't be written anymore.
+  /// The lock is released once the future finishes.
+  pub async fn hold<F, T, E>(self, future: F) -> Result<T, E>
+  where
+    F: Future<Output = Result<T, E>>,
+    E: From<LockError>,
+  {
+    self.hold_renewing_every(LEASE_DURATION / 3, future).await
+  }
+
+  async fn hold_renewing_every<F, T, E>(self, interval: Duration, future: F) -> Result<T, E>
+  where
+    F: Future<Output = Result<T, E>>,
+    E: From<LockError>,
+  {
+    let renewal = async {
+      loop {
+        tokio::time::sleep(interval).await;
+        if let Err(err) = self.renew().await {
+          return err;
+        }
+      }
+    };
+    tokio::select! {
+      result = future => result,
+      err = renewal => {
+        tracing::error!(path = %self.path.display(), error = %err, "Lost the lock of the state");
+        Err(err.into())
+      }
+    }
+  }
+
+  /// Replace the lock file atomically with the lease extended.
+  async fn replace_lease(&self) -> Result<(), LockError> {
+    let lease = Lease {
+      expires: now_secs() + LEASE_DURATION.as_secs(),
+      ..self.lease.clone()
+    };
+    let partial_path = self.partial_path();
+    write_lease(&partial_path, &lease)
+      .await
+      .map_err(|err| self.io_error(err))?;
+    tokio::fs::rename(&partial_path, &self.path)
+      .await
+      .map_err(|err| self.io_error(err))
+  }
+
+  fn partial_path(&self) -> PathBuf {
+    let mut path = self.path.as_os_str().to_owned();
+    path.push(format!(".{}", self.lease.owner));
+    PathBuf::from(path)
+  }
+
+  async fn write_lease(&self, path: &Path) -> Result<(), LockError> {
+    write_lease(path, &self.lease)
+      .await
+      .map_err(|err| self.io_error(err))
+  }
+
+  /// The lease of the lock file, or `None` when it doesn't exist or can't be parsed.
+  async fn read_lease(&self) -> Result<Option<Lease>, LockError> {
+    match tokio::fs::read(&self.path).await {
+      Ok(content) => Ok(serde_json::from_slice(&content).ok()),
+      Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+      Err(err) => Err(self.io_error(err)),
+    }
+  }
+
+  fn io_error(&self, err: std::io::Error) -> LockError {
+    LockError::Io(self.path.display().to_string(), err)
+  }
+}
+
+impl Drop for StateLock {
+  fn drop(&mut self) {
+    let owned = std::fs::read(&self.path)
+      .ok()
+      .and_then(|content| serde_json::from_slice::<Lease>(&content).ok())
+      .map_or(false, |current| current.owner == self.lease.owner);
+    if owned {
+      std::fs::remove_file(&self.path).ok();
+    }
+  }
+}
+
+async fn write_lease(path: &Path, lease: &Lease) -> std::io::Result<()> {
+  let content = serde_json::to_vec(lease)?;
+  tokio::fs::write(path, content).await
+}
+
+fn now_secs() -> u64 {
+  SystemTime::now()
+    .duration_since(UNIX_EPOCH)
+    .map_or(0, |now| now.as_secs())
+}
+
+fn now_nanos() -> u128 {
+  SystemTime::now()
+    .duration_since(UNIX_EPOCH)
+    .map_or(0, |now| now.as_nanos())
+}
+
+#[cfg(test)]
+mod tests {
+
+  use super::*;
+
+  fn state_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!(
+      "toy-payments-engine-lock-{}-{}.csv",
+      name,
+      std::process::id()
+    ))
+  }
+
+  #[tokio::test]
+  async fn lock_the_state_once() {
+    let state = state_path("once");
+    tokio::fs::remove_file(StateLock::path(&state)).await.ok();
+
+    let lock = StateLock::acquire(&state, false).await.unwrap();
+    let locked = StateLock::acquire(&state, false).await;
+    assert!(matches!(
+      locked,
+      Err(LockError::StateLockedByAnotherProcess(_, pid, _)) if pid == std::process::id()
+    ));
+    lock.renew().await.unwrap();
+
+    drop(lock);
+    assert!(!StateLock::path(&state).exists());
+    let lock = StateLock::acquire(&state, false).await.unwrap();
+    drop(lock);
+  }
+
+  #[tokio::test]
+  async fn steal_the_lock() {
+    let state = state_path("steal");
+    tokio::fs::remove_file(StateLock::path(&state)).await.ok();
+
+    let first = StateLock::acquire(&state, false).await.unwrap();
+    let second = StateLock::acquire(&state, true).await.unwrap();
+
+    assert!(matches!(
+      first.renew().await,
+      Err(LockError::LockLost(_, _))
+    ));
+    // the lock is only removed by its current holder
+    drop(first);
+    assert!(StateLock::path(&state).exists());
+    second.renew().await.unwrap();
+    drop(second);
+    assert!(!StateLock::path(&state).exists());
+  }
+
+  #[tokio::test]
+  async fn fail_the_holder_once_the_lock_is_lost() {
+    let state = state_path("hold");
+    tokio::fs::remove_file(StateLock::path(&state)).await.ok();
+
+    let first = StateLock::acquire(&state, false).await.unwrap();
+    let second = StateLock::acquire(&state, true).await.unwrap();
+    let held = first
+      .hold_renewing_every(
+        Duration::from_millis(10),
+        futures::future::pending::<Result<(), LockError>>(),
+      )
+      .await;
+
+    assert!(matches!(held, Err(LockError::LockLost(_, pid)) if pid == std::process::id()));
+    let held = second
+      .hold_renewing_every(Duration::from_millis(10), async { Ok::<_, LockError>(42) })
+      .await;
+    assert_eq!(held.unwrap(), 42);
+    assert!(!StateLock::path(&state).exists());
+  }
+
+  #[tokio::test]
+  async fn take_over_an_expired_lease() {
+    let state = state_path("expired");
+    let path = StateLock::path(&state);
+    let expired = Lease {
+      owner: "crashed".to_string(),
+      pid: 1,
+      expires: now_secs() - 1,
+    };
+    write_lease(&path, &expired).await.unwrap();
+
+    let lock = StateLock::acquire(&state, false).await.unwrap();
+
+    assert_eq!(
+      serde_json::from_slice::<Lease>(&tokio::fs::read(&path).await.unwrap()).unwrap(),
+      lock.lease
+    );
+    drop(lock);
+  }
+}
@@ -0,0 +1,123 @@
+use std::collections::HashMap;
+
+use anyhow::Result;
+use serde::Deserialize;
+use tokio::io::AsyncRead;
+use tokio_stream::StreamExt;
+
+use crate::payments::ClientId;
+
+/// The fields of the client metadata that can be joined into the accounts report.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MetadataField {
+  Name,
+  Tier,
+}
+
+/// A deserializable entry of the metadata file
+#[derive(Debug, Deserialize)]
+struct ClientInfo {
+  client: ClientId,
+  name: Option<String>,
+  tier: Option<String>,
+}
+
+/// Descriptive information about the clients (like their name or tier) to join into the accounts report.
+///
+/// It is loaded from a CSV with the `client, name, tier` columns, and only the selected `fields` are joined.
+/// The metadata is only used when writing the report, so it never affects the processing of the payments.
+#[derive(Debug, Default, PartialEq)]
+pub struct ClientMetadata {
+  fields: Vec<MetadataField>,
+  clients: HashMap<ClientId, (String, String)>,
+}
+
+impl ClientMetadata {
+  pub async fn load<R>(reader: R, fields: Vec<MetadataField>) -> Result<Self>
+  where
+    R: AsyncRead + Unpin + Send + Sync,
+  {
+    let mut clients = HashMap::new();
+
+    let mut records = csv_async::AsyncReaderBuilder::new()
+      .create_reader(reader)
+      .into_records();
+    while let Some(record) = records.next().await {
+      let mut record = record?;
+      record.trim();
+      let info = record.deserialize::<ClientInfo>(None)?;
+      clients.insert(
+        info.client,
+        (info.name.unwrap_or_default(), info.tier.unwrap_or_default()),
+      );
+    }
+
+    Ok(Self { fields, clients })
+  }
+
+  /// The value of the field for the client, which is empty for unknown clients,
+  /// or `None` when the field was not selected to be joined.
+  pub fn field(&self, client_id: ClientId, field: MetadataField) -> Option<String> {
+    if !self.fields.contains(&field) {
+      return None;
+    }
+
+    let value = self
+      .clients
+      .get(&client_id)
+      .map(|(name, tier)| match field {
+        MetadataField::Name => name.clone(),
+        MetadataField::Tier => tier.clone(),
+      })
+      .unwrap_or_default();
+    Some(value)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+
+  use indoc::indoc;
+
+  use super::*;
+
+  const METADATA: &str = indoc! { "
+    client,  name,      tier
+         1,  Alice,     gold
+         2,  Bob,
+  " };
+
+  #[tokio::test]
+  async fn load_metadata_fields() {
+    let metadata = ClientMetadata::load(
+      METADATA.as_bytes(),
+      vec![MetadataField::Name, MetadataField::Tier],
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(
+      metadata.field(1, MetadataField::Name),
+      Some("Alice".to_string())
+    );
+    assert_eq!(
+      metadata.field(1, MetadataField::Tier),
+      Some("gold".to_string())
+    );
+    assert_eq!(metadata.field(2, MetadataField::Tier), Some(String::new()));
+    assert_eq!(metadata.field(3, MetadataField::Name), Some(String::new()));
+  }
+
+  #[tokio::test]
+  async fn load_metadata_selected_fields() {
+    let metadata = ClientMetadata::load(METADATA.as_bytes(), vec![MetadataField::Tier])
+      .await
+      .unwrap();
+
+    assert_eq!(metadata.field(1, MetadataField::Name), None);
+    assert_eq!(
+      metadata.field(1, MetadataField::Tier),
+      Some("gold".to_string())
+    );
+  }
+}
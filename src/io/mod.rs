@@ -1,16 +1,110 @@
 //! This module contains all the components needed to read and write data from files (specifically CSV)
 //!
 //! The [`reader`] module contains a reader of transactions from CSV and [`writer`] modules contains an account report writer into CSV.
-//! It would be possible to add new file formats by implementing the traits [`TransactionsReader`] and [`AccountsReportWriter`] respectively.
+//! The readers of the other formats are a [`DecodedTransactionsReader`] of a [`RecordSource`] and a [`TransactionDecoder`],
+//! so it would be possible to add new file formats by implementing them, or the traits [`TransactionsReader`] and [`AccountsReportWriter`] respectively.
 //!
-//! The [`account`] and [`transaction`] modules contain structs needed to serialize/deserialize data.
+//! The [`account`], [`transaction`] and [`reconciliation`] modules contain structs needed to serialize/deserialize data.
 //! They are intentionally duplicated from the domain model to decouple the IO details from the domain logic and allow their evolution independently.
 //!
 
 mod account;
+mod amount;
+mod api_keys;
+mod archive;
+mod audit;
+#[cfg(feature = "avro")]
+mod avro;
+mod baseline;
+mod bundle;
+mod checkpoints;
+mod chunked;
+mod digests;
+mod duplicates;
+mod generator;
+mod history;
+mod idempotency;
+#[cfg(feature = "json")]
+mod json;
+#[cfg(feature = "kafka")]
+mod kafka;
+#[cfg(feature = "kv")]
+mod kv;
+mod lock;
+mod metadata;
+mod normalization;
+mod pipeline;
+mod progress;
+#[cfg(feature = "protobuf")]
+mod protobuf;
+mod quarantine;
 mod reader;
+mod reconciliation;
+mod rejections;
+mod remapping;
+mod sampling;
+mod sorted;
+mod spill;
+mod statements;
 mod transaction;
 mod writer;
+#[cfg(feature = "xlsx")]
+mod xlsx;
 
-pub use reader::{CsvTransactionsReader, TransactionsReader};
-pub use writer::{AccountsReportWriter, CsvAccountsReportWriter};
+pub use account::{rounded_account_report, ReportSchema};
+pub use amount::AmountParser;
+pub use api_keys::{ApiKey, ApiKeys};
+pub use archive::archive_input;
+pub use audit::AuditLog;
+#[cfg(feature = "avro")]
+pub use avro::{AvroDecoder, AvroSchemas, AvroSource, AvroTransactionsReader, TRANSACTION_SCHEMA};
+pub use baseline::Baseline;
+pub use bundle::TarWriter;
+pub use checkpoints::{Checkpoints, DEFAULT_KEPT_CHECKPOINTS};
+pub use chunked::ChunkedCsvTransactionsReader;
+pub use digests::{DigestExchange, Divergence};
+pub use duplicates::{CsvDuplicatesSink, Duplicate, DuplicateDetector, DuplicatesSink};
+pub use generator::{GeneratorConfig, TransactionsGenerator};
+pub use history::{fingerprint_file, InputHistory};
+pub use idempotency::{
+  FileIdempotencyStore, IdempotencyKey, IdempotencyStore, IdempotentTransactionsReader,
+  InMemoryIdempotencyStore,
+};
+#[cfg(feature = "json")]
+pub use json::{JsonDecoder, JsonSource, NdjsonTransactionsReader};
+#[cfg(feature = "kafka")]
+pub use kafka::{KafkaDecoder, KafkaSource, KafkaTransactionsReader};
+pub use lock::{LockError, StateLock, LEASE_DURATION};
+pub use metadata::{ClientMetadata, MetadataField};
+pub use normalization::{Normalization, NormalizedTransactionsReader};
+pub use pipeline::{DecodedTransactionsReader, RecordSource, SourceItem, TransactionDecoder};
+pub use progress::ProgressFile;
+#[cfg(feature = "protobuf")]
+pub use protobuf::{
+  proto, ProtobufAccountsReportWriter, ProtobufDecoder, ProtobufSource, ProtobufTransactionsReader,
+};
+pub use quarantine::QuarantineSink;
+pub use reader::{
+  BalancesReader, CsvBalancesReader, CsvDecoder, CsvSource, CsvTransactionsReader,
+  TransactionRecord, TransactionsReader,
+};
+pub use rejections::{CsvErrorSink, ErrorSink, Rejection, RejectionReason};
+pub use remapping::{ClientIdRemapping, RemappedTransactionsReader};
+pub use sampling::{SampledTransactionsReader, Sampling};
+pub use sorted::SortedAccountsReportWriter;
+pub use spill::SpillingAccountsReportWriter;
+pub use statements::{CsvStatementsWriter, NdjsonStatementsWriter, StatementsWriter};
+pub use writer::{
+  AccountsReportWriter, BreaksReportWriter, CsvAccountsReportWriter, CsvBreaksReportWriter,
+  CsvOpenDisputesWriter, CsvSubAccountsWriter, CsvTransactionsHistoryWriter,
+  NdjsonAccountsReportWriter, OpenDisputesWriter, SubAccountsWriter, TeeAccountsReportWriter,
+  TransactionsHistoryWriter,
+};
+
+#[cfg(feature = "kv")]
+pub use kv::SledAccountsReportWriter;
+pub(crate) use transaction::log_record;
+#[cfg(feature = "arbitrary")]
+pub use transaction::{Transaction, TransactionType};
+#[cfg(feature = "xlsx")]
+pub use xlsx::{SpreadsheetSource, XlsxTransactionsReader};
@@ -0,0 +1,218 @@
+use std::ops::RangeInclusive;
+
+use anyhow::Result;
+use tokio_stream::{Stream, StreamExt};
+
+use super::reader::{TransactionRecord, TransactionsReader};
+use crate::payments::{ClientId, Transaction};
+
+/// How the transactions are normalized before reaching the engine, independently of the format they were read from.
+/// The default normalization keeps the transactions as they are.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Normalization {
+  /// The number of decimal places the amounts are rounded to (with banker's rounding), or `None` to keep them.
+  pub precision: Option<u32>,
+  /// The ranges of client IDs whose transactions are dropped, like the ones used for testing in production.
+  pub dropped_clients: Vec<RangeInclusive<ClientId>>,
+}
+
+impl Normalization {
+  /// Parse ranges of client IDs like `9000-9999,65535`, where every range can also be a single client ID.
+  pub fn parse_client_ranges(value: &str) -> Result<Vec<RangeInclusive<ClientId>>> {
+    value
+      .split(',')
+      .map(|range| {
+        let mut bounds = range.trim().splitn(2, '-');
+        let start = bounds
+          .next()
+          .unwrap_or_default()
+          .trim()
+          .parse::<ClientId>()?;
+        let end = match bounds.next() {
+          Some(end) => end.trim().parse::<ClientId>()?,
+          None => start,
+        };
+        Ok(start..=end)
+      })
+      .collect()
+  }
+
+  /// The normalized transaction, or `None` when it has to be dropped.
+  pub fn apply(&self, transaction: Transaction) -> Option<Transaction> {
+    let to_client = match transaction {
+      Transaction::Transfer { to_client, .. } => Some(to_client),
+      _ => None,
+    };
+    let client_id = transaction.client_id();
+    if self.is_dropped(client_id) || to_client.map_or(false, |to_client| self.is_dropped(to_client))
+    {
+      tracing::debug!(
+        client = client_id,
+        "Transaction dropped by the normalization"
+      );
+      return None;
+    }
+    match self.precision {
+      Some(precision) => Some(round_amount(transaction, precision)),
+      None => Some(transaction),
+    }
+  }
+
+  fn is_dropped(&self, client_id: ClientId) -> bool {
+    self
+      .dropped_clients
+      .iter()
+      .any(|range| range.contains(&client_id))
+  }
+}
+
+fn round_amount(mut transaction: Transaction, precision: u32) -> Transaction {
+  match &mut transaction {
+    Transaction::Deposit { amount, .. }
+    | Transaction::Withdrawal { amount, .. }
+    | Transaction::Transfer { amount, .. }
+    | Transaction::Authorize { amount, .. } => *amount = amount.round_dp(precision),
+    Transaction::Dispute { .. }
+    | Transaction::Resolve { .. }
+    | Transaction::Chargeback { .. }
+    | Transaction::Unlock { .. }
+    | Transaction::Reactivate { .. }
+    | Transaction::Capture { .. }
+    | Transaction::Void { .. } => {}
+  }
+  transaction
+}
+
+/// A [`TransactionsReader`] that applies a [`Normalization`] to the transactions read by another one.
+/// The records that can not be read are kept, so they can still be reported.
+pub struct NormalizedTransactionsReader<R> {
+  inner: R,
+  normalization: Normalization,
+}
+
+impl<R> NormalizedTransactionsReader<R>
+where
+  R: TransactionsReader,
+{
+  pub fn new(inner: R, normalization: Normalization) -> Self {
+    Self {
+      inner,
+      normalization,
+    }
+  }
+}
+
+impl<R> TransactionsReader for NormalizedTransactionsReader<R>
+where
+  R: TransactionsReader,
+{
+  fn read_transactions<'a>(
+    &'a mut self,
+  ) -> Box<dyn Stream<Item = Result<Transaction>> + Unpin + 'a> {
+    let normalization = &self.normalization;
+    Box::new(
+      self
+        .inner
+        .read_transactions()
+        .filter_map(move |maybe_transaction| match maybe_transaction {
+          Ok(transaction) => normalization.apply(transaction).map(Ok),
+          Err(err) => Some(Err(err)),
+        }),
+    )
+  }
+
+  fn read_records<'a>(&'a mut self) -> Box<dyn Stream<Item = TransactionRecord> + Unpin + 'a> {
+    let normalization = &self.normalization;
+    Box::new(self.inner.read_records().filter_map(move |record| {
+      let TransactionRecord {
+        line,
+        raw,
+        transaction,
+      } = record;
+      match transaction {
+        Ok(transaction) => normalization
+          .apply(transaction)
+          .map(|transaction| TransactionRecord {
+            line,
+            raw,
+            transaction: Ok(transaction),
+          }),
+        Err(err) => Some(TransactionRecord {
+          line,
+          raw,
+          transaction: Err(err),
+        }),
+      }
+    }))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+
+  use indoc::indoc;
+  use rust_decimal_macros::dec;
+
+  use super::*;
+  use crate::io::CsvTransactionsReader;
+
+  #[test]
+  fn parse_client_ranges() {
+    assert_eq!(
+      Normalization::parse_client_ranges("9000-9999, 65535").unwrap(),
+      vec![9000..=9999, 65535..=65535]
+    );
+    assert!(Normalization::parse_client_ranges("9000-abc").is_err());
+  }
+
+  #[tokio::test]
+  async fn read_normalized_transactions() {
+    let input = indoc! { "
+      type,       client,   tx,  amount,  to
+      deposit,         1,  101,  10.125,
+      deposit,      9001,  102,     100,
+      transfer,        1,  103,       1,  9002
+      withdrawal,    abc,  104,       1,
+      dispute,         1,  101,        ,
+    " };
+    let normalization = Normalization {
+      precision: Some(2),
+      dropped_clients: vec![9000..=9999],
+    };
+    let mut reader = NormalizedTransactionsReader::new(
+      CsvTransactionsReader::new(input.as_bytes()),
+      normalization,
+    );
+
+    let records: Vec<(Option<u64>, Option<Transaction>)> = reader
+      .read_records()
+      .map(|record| (record.line, record.transaction.ok()))
+      .collect()
+      .await;
+
+    assert_eq!(
+      records,
+      vec![
+        (
+          Some(2),
+          Some(Transaction::Deposit {
+            client_id: 1,
+            transaction_id: 101,
+            amount: dec!(10.12),
+            timestamp: None,
+            sub_account: 0,
+          })
+        ),
+        (Some(5), None),
+        (
+          Some(6),
+          Some(Transaction::Dispute {
+            client_id: 1,
+            transaction_id: 101,
+            timestamp: None,
+          })
+        ),
+      ]
+    );
+  }
+}
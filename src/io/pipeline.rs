@@ -0,0 +1,236 @@
+use anyhow::Result;
+use csv_async::StringRecord;
+use tokio_stream::{Stream, StreamExt};
+
+use super::amount::AmountParser;
+use super::reader::{TransactionRecord, TransactionsReader};
+use crate::payments::Transaction;
+
+/// The columns of the records decoded by a [`TransactionDecoder`], which are the ones of the CSV format.
+pub(super) const COLUMNS: [&str; 7] = ["type", "client", "tx", "amount", "to", "timestamp", "sub"];
+
+/// An item read by a [`RecordSource`], before it is decoded into a transaction.
+#[derive(Debug)]
+pub struct SourceItem<T> {
+  /// The line of the source where the item starts, when known.
+  pub line: Option<u64>,
+  pub item: Result<T>,
+}
+
+/// Interface for the first stage of the decoding pipeline, which reads the items of a format from an external source
+/// (like the rows of a CSV, JSON values or protobuf frames) without interpreting them.
+pub trait RecordSource {
+  type Item;
+
+  /// Read the items in the order they are found in the source.
+  /// The items that can't be read are yielded as `Err`, and the reading goes on with the next one when possible.
+  fn items<'a>(&'a mut self) -> Box<dyn Stream<Item = SourceItem<Self::Item>> + Unpin + 'a>;
+}
+
+/// Interface for the second stage of the decoding pipeline, which decodes the items of a [`RecordSource`]
+/// into a record with the [`COLUMNS`] of the CSV format, missing the ones not in the item.
+pub trait TransactionDecoder {
+  type Item;
+
+  fn decode(&self, item: Self::Item) -> Result<StringRecord>;
+}
+
+/// Implementation of [`TransactionsReader`] for any format, as a [`RecordSource`] of items decoded by a [`TransactionDecoder`].
+///
+/// Once decoded, the records of all the formats are trimmed and interpreted the same way, their errors are reported as
+/// unreadable records, and the items without a known line get their position in the source, starting at 1.
+/// So a new format only needs to implement how its items are read and decoded.
+pub struct DecodedTransactionsReader<S, D> {
+  pub(super) source: S,
+  pub(super) decoder: D,
+  pub(super) amount_parser: AmountParser,
+}
+
+impl<S, D> DecodedTransactionsReader<S, D>
+where
+  S: RecordSource,
+  D: TransactionDecoder<Item = S::Item>,
+{
+  pub fn from_parts(source: S, decoder: D) -> Self {
+    Self {
+      source,
+      decoder,
+      amount_parser: AmountParser::default(),
+    }
+  }
+}
+
+impl<S, D> DecodedTransactionsReader<S, D> {
+  pub fn with_amount_parser(mut self, amount_parser: AmountParser) -> Self {
+    self.amount_parser = amount_parser;
+    self
+  }
+}
+
+impl<S, D> TransactionsReader for DecodedTransactionsReader<S, D>
+where
+  S: RecordSource,
+  D: TransactionDecoder<Item = S::Item>,
+{
+  fn read_transactions<'a>(
+    &'a mut self,
+  ) -> Box<dyn Stream<Item = Result<Transaction>> + Unpin + 'a> {
+    Box::new(decode_transactions(
+      self.source.items(),
+      &self.decoder,
+      self.amount_parser,
+    ))
+  }
+
+  fn read_records<'a>(&'a mut self) -> Box<dyn Stream<Item = TransactionRecord> + Unpin + 'a> {
+    Box::new(decode_records(
+      self.source.items(),
+      &self.decoder,
+      self.amount_parser,
+    ))
+  }
+}
+
+/// Decode the items into transactions, skipping the raw records.
+pub(super) fn decode_transactions<'a, I, D>(
+  items: I,
+  decoder: &'a D,
+  amount_parser: AmountParser,
+) -> impl Stream<Item = Result<Transaction>> + Unpin + 'a
+where
+  I: Stream<Item = SourceItem<D::Item>> + Unpin + 'a,
+  D: TransactionDecoder,
+{
+  let mut position = 0u64;
+  items.map(move |item| {
+    position += 1;
+    let transaction = item
+      .item
+      .and_then(|item| decoder.decode(item))
+      .and_then(|record| transaction_from_record(record, &amount_parser));
+    log_unreadable(item.line.or(Some(position)), &transaction);
+    transaction
+  })
+}
+
+/// Decode the items into records, with their raw record being the decoded fields as they were read, joined with commas.
+pub(super) fn decode_records<'a, I, D>(
+  items: I,
+  decoder: &'a D,
+  amount_parser: AmountParser,
+) -> impl Stream<Item = TransactionRecord> + Unpin + 'a
+where
+  I: Stream<Item = SourceItem<D::Item>> + Unpin + 'a,
+  D: TransactionDecoder,
+{
+  let mut position = 0u64;
+  items.map(move |item| {
+    position += 1;
+    let line = item.line.or(Some(position));
+    let record = match item.item.and_then(|item| decoder.decode(item)) {
+      Ok(record) => TransactionRecord {
+        line,
+        raw: Some(record.iter().collect::<Vec<&str>>().join(",")),
+        transaction: transaction_from_record(record, &amount_parser),
+      },
+      Err(err) => TransactionRecord {
+        line,
+        raw: None,
+        transaction: Err(err),
+      },
+    };
+    log_unreadable(record.line, &record.transaction);
+    record
+  })
+}
+
+/// Log the records that can't be read, as the processors skip them.
+fn log_unreadable(line: Option<u64>, transaction: &Result<Transaction>) {
+  if let Err(err) = transaction {
+    tracing::warn!(line, error = %err, "Unreadable record");
+  }
+}
+
+/// Map a record with the `type, client, tx, amount` columns, the `to` column of transfers, the optional `timestamp` column,
+/// and the optional `sub` column with the sub-account, into a [`Transaction`].
+/// It is shared by all the formats, so they interpret the columns in the same way.
+pub(super) fn transaction_from_record(
+  mut record: StringRecord,
+  amount_parser: &AmountParser,
+) -> Result<Transaction> {
+  record.trim();
+  // the amount, the recipient of transfers, the timestamp and the sub-account are optional, so they can be omitted at the end of the record
+  if record.len() >= 3 {
+    while record.len() < COLUMNS.len() {
+      record.push_field("");
+    }
+  }
+  record
+    .deserialize::<super::transaction::Transaction>(None)
+    .map_err(anyhow::Error::from)
+    .and_then(|transaction| transaction.into_payments(amount_parser))
+}
+
+#[cfg(test)]
+mod tests {
+
+  use rust_decimal_macros::dec;
+
+  use super::*;
+
+  /// A format whose items are the fields of the record separated by semicolons, with its position as the line.
+  struct SemicolonSource(Vec<&'static str>);
+
+  impl RecordSource for SemicolonSource {
+    type Item = &'static str;
+
+    fn items<'a>(&'a mut self) -> Box<dyn Stream<Item = SourceItem<Self::Item>> + Unpin + 'a> {
+      Box::new(tokio_stream::iter(self.0.iter().map(|item| SourceItem {
+        line: None,
+        item: Ok(*item),
+      })))
+    }
+  }
+
+  struct SemicolonDecoder;
+
+  impl TransactionDecoder for SemicolonDecoder {
+    type Item = &'static str;
+
+    fn decode(&self, item: Self::Item) -> Result<StringRecord> {
+      Ok(item.split(';').collect())
+    }
+  }
+
+  #[tokio::test]
+  async fn read_records_of_a_new_format() {
+    let source = SemicolonSource(vec![" deposit; 1; 101; 10.5", "unknown;1", "dispute;1;101"]);
+    let mut reader = DecodedTransactionsReader::from_parts(source, SemicolonDecoder);
+
+    let records = reader.read_records().collect::<Vec<_>>().await;
+
+    assert_eq!(records.len(), 3);
+    assert_eq!(records[0].line, Some(1));
+    assert_eq!(records[0].raw.as_deref(), Some(" deposit, 1, 101, 10.5"));
+    assert_eq!(
+      records[0].transaction.as_ref().unwrap(),
+      &Transaction::Deposit {
+        client_id: 1,
+        transaction_id: 101,
+        amount: dec!(10.5),
+        timestamp: None,
+        sub_account: 0,
+      }
+    );
+    assert_eq!(records[1].line, Some(2));
+    assert!(records[1].transaction.is_err());
+    assert_eq!(
+      records[2].transaction.as_ref().unwrap(),
+      &Transaction::Dispute {
+        client_id: 1,
+        transaction_id: 101,
+        timestamp: None,
+      }
+    );
+  }
+}
@@ -0,0 +1,90 @@
+use std::io::SeekFrom;
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Result};
+use tokio::fs::{File, OpenOptions};
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+
+/// The number of records of an input already processed, kept in a sidecar file next to it,
+/// so a processing that crashed can be resumed from the next record instead of from the beginning.
+///
+/// The number is written with a fixed width, so every update overwrites the previous one in place.
+pub struct ProgressFile {
+  file: File,
+  processed: u64,
+}
+
+impl ProgressFile {
+  /// Open the progress of the input, starting from no records processed when it doesn't exist yet.
+  pub async fn open<P: AsRef<Path>>(input: P) -> Result<Self> {
+    let path = Self::path(input.as_ref());
+    let mut file = OpenOptions::new()
+      .read(true)
+      .write(true)
+      .create(true)
+      .truncate(false)
+      .open(&path)
+      .await?;
+    let mut content = String::new();
+    file.read_to_string(&mut content).await?;
+    let processed = match content.trim() {
+      "" => 0,
+      value => value
+        .parse::<u64>()
+        .map_err(|_| anyhow!("Invalid progress in {}: {}", path.display(), value))?,
+    };
+    Ok(Self { file, processed })
+  }
+
+  /// The sidecar file of the input, like `transactions.csv.progress`.
+  pub fn path(input: &Path) -> PathBuf {
+    let mut path = input.as_os_str().to_owned();
+    path.push(".progress");
+    PathBuf::from(path)
+  }
+
+  /// The number of records processed, counting from the first one of the input.
+  pub fn processed(&self) -> u64 {
+    self.processed
+  }
+
+  /// Record the number of records processed, which is flushed before returning.
+  pub async fn record(&mut self, processed: u64) -> Result<()> {
+    self.file.seek(SeekFrom::Start(0)).await?;
+    self
+      .file
+      .write_all(format!("{:020}\n", processed).as_bytes())
+      .await?;
+    self.file.flush().await?;
+    self.processed = processed;
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+
+  use super::*;
+
+  #[tokio::test]
+  async fn record_and_reopen_progress() {
+    let input = std::env::temp_dir().join(format!(
+      "toy-payments-engine-progress-{}.csv",
+      std::process::id()
+    ));
+    let path = ProgressFile::path(&input);
+    tokio::fs::remove_file(&path).await.ok();
+
+    let mut progress = ProgressFile::open(&input).await.unwrap();
+    assert_eq!(progress.processed(), 0);
+    progress.record(1_000).await.unwrap();
+    progress.record(1_001).await.unwrap();
+    drop(progress);
+
+    assert_eq!(ProgressFile::open(&input).await.unwrap().processed(), 1_001);
+
+    tokio::fs::write(&path, "garbage").await.unwrap();
+    assert!(ProgressFile::open(&input).await.is_err());
+    tokio::fs::remove_file(&path).await.unwrap();
+  }
+}
@@ -0,0 +1,432 @@
+use anyhow::{anyhow, bail, Result};
+use async_trait::async_trait;
+use csv_async::StringRecord;
+use prost::Message;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio_stream::{Stream, StreamExt};
+
+use super::account::{rounded_funds, with_max_precission};
+use super::amount::AmountParser;
+use super::pipeline::{
+  transaction_from_record, DecodedTransactionsReader, RecordSource, SourceItem, TransactionDecoder,
+};
+use super::writer::AccountsReportWriter;
+use crate::payments::{self, AccountReport, PaymentsEngineError};
+
+/// The types generated from the messages of `proto/payments.proto`.
+pub mod proto {
+  include!(concat!(env!("OUT_DIR"), "/payments.rs"));
+}
+
+/// The size of the biggest message accepted, which is far bigger than any valid transaction,
+/// so a corrupted length doesn't allocate an arbitrary amount of memory.
+const MAX_MESSAGE_SIZE: u64 = 64 * 1024;
+
+/// Implementation of [`TransactionsReader`] for a length delimited stream of protobuf [`proto::Transaction`] messages.
+///
+/// The messages are interpreted the same way than the records of the CSV format.
+/// A message that can't be decoded is rejected, but a corrupted length stops the reading,
+/// as the messages after it can't be found.
+pub type ProtobufTransactionsReader<R> =
+  DecodedTransactionsReader<ProtobufSource<R>, ProtobufDecoder>;
+
+impl<R> ProtobufTransactionsReader<R>
+where
+  R: AsyncRead + Unpin + Send + Sync,
+{
+  pub fn new(reader: R) -> Self {
+    Self::from_parts(ProtobufSource::new(reader), ProtobufDecoder)
+  }
+}
+
+/// A [`RecordSource`] of the frames of a length delimited stream of protobuf messages, without decoding them.
+pub struct ProtobufSource<R>(BufReader<R>);
+
+impl<R> ProtobufSource<R>
+where
+  R: AsyncRead + Unpin + Send + Sync,
+{
+  pub fn new(reader: R) -> Self {
+    Self(BufReader::new(reader))
+  }
+}
+
+impl<R> RecordSource for ProtobufSource<R>
+where
+  R: AsyncRead + Unpin + Send + Sync,
+{
+  type Item = Vec<u8>;
+
+  fn items<'a>(&'a mut self) -> Box<dyn Stream<Item = SourceItem<Self::Item>> + Unpin + 'a> {
+    Box::new(Box::pin(futures::stream::unfold(
+      (&mut self.0, false),
+      |(reader, failed)| async move {
+        if failed {
+          return None;
+        }
+        match read_delimited(reader).await {
+          Ok(Some(message)) => Some((
+            SourceItem {
+              line: None,
+              item: Ok(message),
+            },
+            (reader, false),
+          )),
+          Ok(None) => None,
+          Err(err) => Some((
+            SourceItem {
+              line: None,
+              item: Err(err),
+            },
+            (reader, true),
+          )),
+        }
+      },
+    )))
+  }
+}
+
+/// A [`TransactionDecoder`] of the frames of [`proto::Transaction`] messages.
+pub struct ProtobufDecoder;
+
+impl TransactionDecoder for ProtobufDecoder {
+  type Item = Vec<u8>;
+
+  fn decode(&self, message: Self::Item) -> Result<StringRecord> {
+    proto::Transaction::decode(message.as_slice())
+      .map_err(anyhow::Error::from)
+      .and_then(transaction_record)
+  }
+}
+
+/// The next message, or `None` at the end of the stream.
+async fn read_delimited<R>(reader: &mut R) -> Result<Option<Vec<u8>>>
+where
+  R: AsyncRead + Unpin,
+{
+  let mut size = 0u64;
+  for shift in (0..64).step_by(7) {
+    let byte = match reader.read_u8().await {
+      Ok(byte) => byte,
+      Err(err) if shift == 0 && err.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+      Err(err) => return Err(err.into()),
+    };
+    size |= u64::from(byte & 0x7f) << shift;
+    if byte & 0x80 == 0 {
+      if size > MAX_MESSAGE_SIZE {
+        bail!("Message of {} bytes is too big", size);
+      }
+      let mut message = vec![0u8; size as usize];
+      reader.read_exact(&mut message).await?;
+      return Ok(Some(message));
+    }
+  }
+  bail!("Invalid length of a message")
+}
+
+/// The fields of the message in the order of the columns of the CSV format.
+fn transaction_record(message: proto::Transaction) -> Result<StringRecord> {
+  let kind = match proto::TransactionType::from_i32(message.r#type) {
+    Some(proto::TransactionType::Deposit) => "deposit",
+    Some(proto::TransactionType::Withdrawal) => "withdrawal",
+    Some(proto::TransactionType::Dispute) => "dispute",
+    Some(proto::TransactionType::Resolve) => "resolve",
+    Some(proto::TransactionType::Chargeback) => "chargeback",
+    Some(proto::TransactionType::Transfer) => "transfer",
+    Some(proto::TransactionType::Unlock) => "unlock",
+    Some(proto::TransactionType::Authorize) => "authorize",
+    Some(proto::TransactionType::Capture) => "capture",
+    Some(proto::TransactionType::Void) => "void",
+    Some(proto::TransactionType::Reactivate) => "reactivate",
+    Some(proto::TransactionType::Unspecified) | None => {
+      return Err(anyhow!("Unknown transaction type: {}", message.r#type))
+    }
+  };
+  Ok(StringRecord::from(vec![
+    kind.to_string(),
+    message.client.to_string(),
+    message.tx.to_string(),
+    message.amount.unwrap_or_default(),
+    message.to.map(|to| to.to_string()).unwrap_or_default(),
+    message
+      .timestamp
+      .map(|timestamp| timestamp.to_string())
+      .unwrap_or_default(),
+    message.sub.map(|sub| sub.to_string()).unwrap_or_default(),
+  ]))
+}
+
+impl proto::Transaction {
+  /// The domain representation of the message, which is interpreted the same way than the records of the CSV format.
+  pub fn into_payments(self, amount_parser: &AmountParser) -> Result<payments::Transaction> {
+    transaction_record(self).and_then(|record| transaction_from_record(record, amount_parser))
+  }
+}
+
+impl From<&payments::Transaction> for proto::Transaction {
+  /// A conversion from the domain representation of a transaction, to produce the messages read by the engine.
+  fn from(transaction: &payments::Transaction) -> Self {
+    use payments::Transaction::*;
+
+    let (kind, amount, to, timestamp) = match *transaction {
+      Deposit {
+        amount, timestamp, ..
+      } => (
+        proto::TransactionType::Deposit,
+        Some(amount),
+        None,
+        timestamp,
+      ),
+      Withdrawal {
+        amount, timestamp, ..
+      } => (
+        proto::TransactionType::Withdrawal,
+        Some(amount),
+        None,
+        timestamp,
+      ),
+      Dispute { timestamp, .. } => (proto::TransactionType::Dispute, None, None, timestamp),
+      Resolve { .. } => (proto::TransactionType::Resolve, None, None, None),
+      Chargeback { .. } => (proto::TransactionType::Chargeback, None, None, None),
+      Transfer {
+        to_client,
+        amount,
+        timestamp,
+        ..
+      } => (
+        proto::TransactionType::Transfer,
+        Some(amount),
+        Some(u32::from(to_client)),
+        timestamp,
+      ),
+      Unlock { .. } => (proto::TransactionType::Unlock, None, None, None),
+      Authorize { amount, .. } => (proto::TransactionType::Authorize, Some(amount), None, None),
+      Capture { .. } => (proto::TransactionType::Capture, None, None, None),
+      Void { .. } => (proto::TransactionType::Void, None, None, None),
+      Reactivate { .. } => (proto::TransactionType::Reactivate, None, None, None),
+    };
+    proto::Transaction {
+      r#type: kind as i32,
+      client: u32::from(transaction.client_id()),
+      tx: transaction.transaction_id().unwrap_or_default(),
+      amount: amount.map(|amount| amount.to_string()),
+      to,
+      timestamp,
+      sub: match transaction.sub_account() {
+        payments::MAIN_SUB_ACCOUNT => None,
+        sub_account => Some(u32::from(sub_account)),
+      },
+    }
+  }
+}
+
+impl From<AccountReport> for proto::AccountReport {
+  /// A conversion between the domain representation of an account report into a message,
+  /// with the funds rounded the same way than in the other formats.
+  fn from(account_report: AccountReport) -> Self {
+    let (available, held, total) = rounded_funds(account_report.available, account_report.held);
+    proto::AccountReport {
+      client: u32::from(account_report.client_id),
+      available: available.to_string(),
+      held: held.to_string(),
+      total: total.to_string(),
+      locked: account_report.locked,
+      open_disputes: account_report.open_disputes as u64,
+      charged_back_total: with_max_precission(account_report.charged_back_total).to_string(),
+    }
+  }
+}
+
+/// An implementation of [`AccountsReportWriter`] for a length delimited stream of protobuf [`proto::AccountReport`] messages.
+pub struct ProtobufAccountsReportWriter<W>(W);
+
+impl<W> ProtobufAccountsReportWriter<W>
+where
+  W: AsyncWrite + Unpin + Send + Sync,
+{
+  pub fn new(writer: W) -> Self {
+    Self(writer)
+  }
+}
+
+#[async_trait]
+impl<W> AccountsReportWriter for ProtobufAccountsReportWriter<W>
+where
+  W: AsyncWrite + Unpin + Send + Sync,
+{
+  async fn write_accounts_report<'a, S>(&'a mut self, report: S) -> Result<()>
+  where
+    S: Stream<Item = Result<AccountReport, PaymentsEngineError>> + Send + 'a,
+  {
+    let mut report = Box::pin(report);
+
+    let mut accounts = 0usize;
+    let mut buffer = Vec::new();
+    while let Some(account_report) = report.next().await {
+      buffer.clear();
+      proto::AccountReport::from(account_report?).encode_length_delimited(&mut buffer)?;
+      self.0.write_all(&buffer).await?;
+      accounts += 1;
+    }
+    self.0.flush().await?;
+    tracing::info!(accounts, "Accounts report written");
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+
+  use rust_decimal_macros::dec;
+
+  use super::*;
+  use crate::io::TransactionsReader;
+  use crate::payments::AccountsReportStream;
+
+  fn encode(messages: &[proto::Transaction]) -> Vec<u8> {
+    let mut buffer = Vec::new();
+    for message in messages {
+      message.encode_length_delimited(&mut buffer).unwrap();
+    }
+    buffer
+  }
+
+  #[tokio::test]
+  async fn read_transactions() {
+    let transactions = [
+      payments::Transaction::Deposit {
+        client_id: 1,
+        transaction_id: 101,
+        amount: dec!(10.5),
+        timestamp: Some(1_600_000_000),
+        sub_account: 3,
+      },
+      payments::Transaction::Transfer {
+        from_client: 1,
+        to_client: 2,
+        transaction_id: 102,
+        amount: dec!(2),
+        timestamp: None,
+      },
+      payments::Transaction::Dispute {
+        client_id: 1,
+        transaction_id: 101,
+        timestamp: None,
+      },
+      payments::Transaction::Unlock { client_id: 1 },
+    ];
+    let mut messages: Vec<proto::Transaction> =
+      transactions.iter().map(proto::Transaction::from).collect();
+    messages.push(proto::Transaction {
+      client: 70_000,
+      ..messages[0].clone()
+    });
+    messages.push(proto::Transaction::default());
+    let input = encode(&messages);
+
+    let mut reader = ProtobufTransactionsReader::new(input.as_slice());
+    let records: Vec<(Option<u64>, Option<String>, Option<payments::Transaction>)> = reader
+      .read_records()
+      .map(|record| (record.line, record.raw, record.transaction.ok()))
+      .collect()
+      .await;
+
+    assert_eq!(
+      records,
+      vec![
+        (
+          Some(1),
+          Some("deposit,1,101,10.5,,1600000000,3".to_string()),
+          Some(transactions[0].clone())
+        ),
+        (
+          Some(2),
+          Some("transfer,1,102,2,2,,".to_string()),
+          Some(transactions[1].clone())
+        ),
+        (
+          Some(3),
+          Some("dispute,1,101,,,,".to_string()),
+          Some(transactions[2].clone())
+        ),
+        (
+          Some(4),
+          Some("unlock,1,0,,,,".to_string()),
+          Some(transactions[3].clone())
+        ),
+        (
+          Some(5),
+          Some("deposit,70000,101,10.5,,1600000000,3".to_string()),
+          None
+        ),
+        (Some(6), None, None),
+      ]
+    );
+  }
+
+  #[tokio::test]
+  async fn read_truncated_transactions() {
+    let mut input = encode(&[proto::Transaction::from(
+      &payments::Transaction::Chargeback {
+        client_id: 1,
+        transaction_id: 101,
+        timestamp: None,
+      },
+    )]);
+    input.extend_from_slice(&[0x10, 0x08]);
+
+    let mut reader = ProtobufTransactionsReader::new(input.as_slice());
+    let transactions: Vec<Result<payments::Transaction>> =
+      reader.read_transactions().collect().await;
+
+    assert_eq!(transactions.len(), 2);
+    assert!(transactions[0].is_ok());
+    assert!(transactions[1].is_err());
+  }
+
+  #[tokio::test]
+  async fn write_accounts_report_success() {
+    let mut buffer = Vec::<u8>::with_capacity(1024);
+    let mut writer = ProtobufAccountsReportWriter::new(&mut buffer);
+
+    let report = vec![
+      AccountReport::new(1, dec!(100), dec!(10), dec!(110), false),
+      AccountReport::new(2, dec!(90.12344), dec!(-10), dec!(80.12344), true),
+    ]
+    .into_iter();
+
+    writer
+      .write_accounts_report(AccountsReportStream::iter(report))
+      .await
+      .unwrap();
+
+    let mut input = buffer.as_slice();
+    let mut messages = Vec::new();
+    while !input.is_empty() {
+      messages.push(proto::AccountReport::decode_length_delimited(&mut input).unwrap());
+    }
+    assert_eq!(
+      messages,
+      vec![
+        proto::AccountReport {
+          client: 1,
+          available: "100".to_string(),
+          held: "10".to_string(),
+          total: "110".to_string(),
+          locked: false,
+          open_disputes: 0,
+          charged_back_total: "0".to_string(),
+        },
+        proto::AccountReport {
+          client: 2,
+          available: "90.1234".to_string(),
+          held: "-10".to_string(),
+          total: "80.1234".to_string(),
+          locked: true,
+          open_disputes: 0,
+          charged_back_total: "0".to_string(),
+        },
+      ]
+    );
+  }
+}
@@ -0,0 +1,171 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use serde::Serialize;
+use tokio::io::AsyncWrite;
+
+use super::rejections::{ErrorSink, Rejection, RejectionReason};
+
+/// A serializable record put in quarantine
+#[derive(Serialize)]
+struct QuarantinedRecord {
+  line: Option<u64>,
+  bytes: Option<usize>,
+  record: Option<String>,
+  error: String,
+}
+
+/// An implementation of [`ErrorSink`] that puts in quarantine the records that can't be read, so they can be inspected
+/// and fixed without stopping the processing. They are written as CSV with the `line, bytes, record, error` columns,
+/// with the raw record as it was read and its size in bytes, when available.
+///
+/// All the rejections are also written into the inner [`ErrorSink`], when given.
+/// The processing is aborted when more than the maximum number of records are put in quarantine,
+/// as so many of them most probably mean that the input is broken as a whole.
+pub struct QuarantineSink<W, E>
+where
+  W: AsyncWrite + Unpin,
+{
+  serializer: csv_async::AsyncSerializer<W>,
+  error_sink: Option<E>,
+  max_quarantined: Option<u64>,
+  quarantined: u64,
+}
+
+impl<W, E> QuarantineSink<W, E>
+where
+  W: AsyncWrite + Unpin + Send + Sync,
+  E: ErrorSink,
+{
+  pub fn new(writer: W, error_sink: Option<E>) -> Self {
+    Self {
+      serializer: csv_async::AsyncSerializer::from_writer(writer),
+      error_sink,
+      max_quarantined: None,
+      quarantined: 0,
+    }
+  }
+
+  pub fn with_max_quarantined(mut self, max_quarantined: u64) -> Self {
+    self.max_quarantined = Some(max_quarantined);
+    self
+  }
+
+  /// The number of records put in quarantine so far.
+  pub fn quarantined(&self) -> u64 {
+    self.quarantined
+  }
+}
+
+#[async_trait]
+impl<W, E> ErrorSink for QuarantineSink<W, E>
+where
+  W: AsyncWrite + Unpin + Send + Sync,
+  E: ErrorSink,
+{
+  async fn reject(&mut self, rejection: Rejection) -> Result<()> {
+    let quarantined = match &rejection.reason {
+      RejectionReason::Read(err) => Some(QuarantinedRecord {
+        line: rejection.line,
+        bytes: rejection.record.as_ref().map(String::len),
+        record: rejection.record.clone(),
+        error: err.to_string(),
+      }),
+      RejectionReason::Engine(_) => None,
+    };
+
+    if let Some(error_sink) = self.error_sink.as_mut() {
+      error_sink.reject(rejection).await?;
+    }
+
+    if let Some(quarantined) = quarantined {
+      tracing::warn!(line = quarantined.line, error = %quarantined.error, "Record put in quarantine");
+      self.serializer.serialize(quarantined).await?;
+      self.serializer.flush().await?;
+      self.quarantined += 1;
+      if let Some(max_quarantined) = self.max_quarantined {
+        if self.quarantined > max_quarantined {
+          anyhow::bail!(
+            "More than {} records were put in quarantine",
+            max_quarantined
+          );
+        }
+      }
+    }
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+
+  use indoc::indoc;
+
+  use super::*;
+  use crate::io::CsvErrorSink;
+  use crate::payments::PaymentsEngineError;
+
+  fn unreadable(line: u64, record: Option<&str>) -> Rejection {
+    Rejection {
+      line: Some(line),
+      record: record.map(str::to_string),
+      reason: RejectionReason::Read(anyhow::anyhow!("Invalid amount")),
+    }
+  }
+
+  #[tokio::test]
+  async fn quarantine_unreadable_records() {
+    let mut quarantine = Vec::<u8>::with_capacity(1024);
+    let mut errors = Vec::<u8>::with_capacity(1024);
+    let mut sink = QuarantineSink::new(&mut quarantine, Some(CsvErrorSink::new(&mut errors)));
+
+    sink
+      .reject(unreadable(2, Some("deposit,1,101,1e99999")))
+      .await
+      .unwrap();
+    sink
+      .reject(Rejection {
+        line: Some(3),
+        record: Some("withdrawal,1,102,5".to_string()),
+        reason: RejectionReason::Engine(PaymentsEngineError::NotEnoughAvailableFunds),
+      })
+      .await
+      .unwrap();
+    sink.reject(unreadable(4, None)).await.unwrap();
+    assert_eq!(sink.quarantined(), 2);
+    drop(sink);
+
+    assert_eq!(
+      String::from_utf8_lossy(quarantine.as_slice()),
+      indoc! { r#"
+        line,bytes,record,error
+        2,21,"deposit,1,101,1e99999",Invalid amount
+        4,,,Invalid amount
+      "# }
+    );
+    assert_eq!(
+      String::from_utf8_lossy(errors.as_slice()),
+      indoc! { r#"
+        line,record,stage,error
+        2,"deposit,1,101,1e99999",read,Invalid amount
+        3,"withdrawal,1,102,5",engine,Not enough available funds
+        4,,read,Invalid amount
+      "# }
+    );
+  }
+
+  #[tokio::test]
+  async fn abort_after_the_max_quarantined() {
+    let mut quarantine = Vec::<u8>::with_capacity(1024);
+    let mut sink = QuarantineSink::<_, CsvErrorSink<Vec<u8>>>::new(&mut quarantine, None)
+      .with_max_quarantined(1);
+
+    sink.reject(unreadable(1, Some("x"))).await.unwrap();
+    assert!(sink.reject(unreadable(2, Some("y"))).await.is_err());
+    drop(sink);
+
+    assert_eq!(
+      String::from_utf8_lossy(quarantine.as_slice()),
+      "line,bytes,record,error\n1,1,x,Invalid amount\n2,1,y,Invalid amount\n"
+    );
+  }
+}
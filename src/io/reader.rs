@@ -1,10 +1,13 @@
-use std::convert::TryFrom;
-
 use anyhow::Result;
+use csv_async::StringRecord;
 use tokio::io::AsyncRead;
 use tokio_stream::{Stream, StreamExt};
 
-use crate::payments::Transaction;
+use super::pipeline::{
+  decode_records, decode_transactions, DecodedTransactionsReader, RecordSource, SourceItem,
+  TransactionDecoder,
+};
+use crate::payments::{ExternalBalance, Transaction};
 
 /// Interface to read transactions from an external source
 pub trait TransactionsReader {
@@ -14,43 +17,163 @@ pub trait TransactionsReader {
   fn read_transactions<'a>(
     &'a mut self,
   ) -> Box<dyn Stream<Item = Result<Transaction>> + Unpin + 'a>;
+
+  /// Same as [`TransactionsReader::read_transactions`] but with the information about where every transaction comes from,
+  /// useful to report the rejected ones. By default, that information is unknown.
+  fn read_records<'a>(&'a mut self) -> Box<dyn Stream<Item = TransactionRecord> + Unpin + 'a> {
+    Box::new(
+      self
+        .read_transactions()
+        .map(|transaction| TransactionRecord {
+          line: None,
+          raw: None,
+          transaction,
+        }),
+    )
+  }
+}
+
+impl<R> TransactionsReader for Box<R>
+where
+  R: TransactionsReader + ?Sized,
+{
+  fn read_transactions<'a>(
+    &'a mut self,
+  ) -> Box<dyn Stream<Item = Result<Transaction>> + Unpin + 'a> {
+    (**self).read_transactions()
+  }
+
+  fn read_records<'a>(&'a mut self) -> Box<dyn Stream<Item = TransactionRecord> + Unpin + 'a> {
+    (**self).read_records()
+  }
+}
+
+/// A transaction read from an external source, with the information about where it comes from.
+#[derive(Debug)]
+pub struct TransactionRecord {
+  /// The line of the source where the record starts, when known.
+  pub line: Option<u64>,
+  /// The raw record as it was read, when available.
+  pub raw: Option<String>,
+  pub transaction: Result<Transaction>,
 }
 
-/// Implementation of [`TransactionsReader`] for the CSV format.
-pub struct CsvTransactionsReader<R>(R);
+/// Implementation of [`TransactionsReader`] for the CSV format, whose rows already have the columns expected by the [`CsvDecoder`].
+pub type CsvTransactionsReader<R> = DecodedTransactionsReader<CsvSource<R>, CsvDecoder>;
 
 impl<R> CsvTransactionsReader<R>
+where
+  R: AsyncRead + Unpin + Send + Sync,
+{
+  pub fn new(reader: R) -> Self {
+    Self::from_parts(CsvSource::new(reader), CsvDecoder)
+  }
+
+  /// Same as [`TransactionsReader::read_transactions`] but returning the concrete stream,
+  /// which avoids the dynamic dispatch when polling every transaction.
+  pub fn transactions(&mut self) -> impl Stream<Item = Result<Transaction>> + Unpin + '_ {
+    decode_transactions(self.source.rows(), &self.decoder, self.amount_parser)
+  }
+
+  /// Same as [`TransactionsReader::read_records`] but returning the concrete stream.
+  /// The raw record is the fields of the CSV record as they were read, joined with commas.
+  pub fn records(&mut self) -> impl Stream<Item = TransactionRecord> + Unpin + '_ {
+    decode_records(self.source.rows(), &self.decoder, self.amount_parser)
+  }
+}
+
+/// A [`RecordSource`] of the rows of a CSV input, with the line where every row starts.
+pub struct CsvSource<R>(R);
+
+impl<R> CsvSource<R>
 where
   R: AsyncRead + Unpin + Send + Sync,
 {
   pub fn new(reader: R) -> Self {
     Self(reader)
   }
+
+  fn rows(&mut self) -> impl Stream<Item = SourceItem<StringRecord>> + Unpin + '_ {
+    csv_async::AsyncReaderBuilder::new()
+      .flexible(true)
+      .create_reader(&mut self.0)
+      .into_records()
+      .map(|maybe_record| match maybe_record {
+        Ok(record) => SourceItem {
+          line: record.position().map(|position| position.line()),
+          item: Ok(record),
+        },
+        Err(err) => SourceItem {
+          line: err.position().map(|position| position.line()),
+          item: Err(anyhow::Error::from(err)),
+        },
+      })
+  }
 }
 
-impl<R> TransactionsReader for CsvTransactionsReader<R>
+impl<R> RecordSource for CsvSource<R>
 where
   R: AsyncRead + Unpin + Send + Sync,
 {
-  fn read_transactions<'a>(
+  type Item = StringRecord;
+
+  fn items<'a>(&'a mut self) -> Box<dyn Stream<Item = SourceItem<Self::Item>> + Unpin + 'a> {
+    Box::new(self.rows())
+  }
+}
+
+/// A [`TransactionDecoder`] for the sources whose rows already have the columns of the CSV format, like the spreadsheets.
+pub struct CsvDecoder;
+
+impl TransactionDecoder for CsvDecoder {
+  type Item = StringRecord;
+
+  fn decode(&self, record: Self::Item) -> Result<StringRecord> {
+    Ok(record)
+  }
+}
+
+/// Interface to read the balances of the client accounts from an external source
+pub trait BalancesReader {
+  /// Read balances and return an [`Stream`] of possibly successful balances.
+  /// Each item yielded by the stream is either `Ok` if the balance was read successfully,
+  /// or `Err` if there was any kind of problem (like wrong format).
+  fn read_balances<'a>(
     &'a mut self,
-  ) -> Box<dyn Stream<Item = Result<Transaction>> + Unpin + 'a> {
+  ) -> Box<dyn Stream<Item = Result<ExternalBalance>> + Unpin + 'a>;
+}
+
+/// Implementation of [`BalancesReader`] for the CSV format.
+pub struct CsvBalancesReader<R>(R);
+
+impl<R> CsvBalancesReader<R>
+where
+  R: AsyncRead + Unpin + Send + Sync,
+{
+  pub fn new(reader: R) -> Self {
+    Self(reader)
+  }
+}
+
+impl<R> BalancesReader for CsvBalancesReader<R>
+where
+  R: AsyncRead + Unpin + Send + Sync,
+{
+  fn read_balances<'a>(
+    &'a mut self,
+  ) -> Box<dyn Stream<Item = Result<ExternalBalance>> + Unpin + 'a> {
     Box::new(
       csv_async::AsyncReaderBuilder::new()
-        .flexible(true)
         .create_reader(&mut self.0)
         .into_records()
         .map(|maybe_record| {
           maybe_record
             .and_then(|mut record| {
               record.trim();
-              if record.len() == 3 {
-                record.push_field("");
-              }
-              record.deserialize::<super::transaction::Transaction>(None)
+              record.deserialize::<super::reconciliation::ExternalBalance>(None)
             })
+            .map(ExternalBalance::from)
             .map_err(anyhow::Error::from)
-            .and_then(Transaction::try_from)
         }),
     )
   }
@@ -94,14 +217,17 @@ mod tests {
   async fn read_transactions_success() {
     let input = indoc! { "
       type,       client,   tx,  amount
-      deposit,         1,  101,     100
+      deposit,         1,  101,     100,   , 1600000000
        withdrawal,     2,  102,    10.5
-      dispute,         1,  103,
+      dispute,         1,  103,        ,   , 1600086400
       resolve,         1,  104
       chargeback,      1,  105,
       dispute,         1,  106, 10.0
       resolve,         1,  107, 1.0
       chargeback,      1,  108, 10.0
+      transfer,        1,  109,  5.0, 2
+      unlock,          1,  110,
+      reactivate,      1,  111,
     " }
     .as_bytes();
 
@@ -120,37 +246,80 @@ mod tests {
           client_id: 1,
           transaction_id: 101,
           amount: dec!(100),
+          timestamp: Some(1600000000),
+          sub_account: 0,
         }),
         Ok(Transaction::Withdrawal {
           client_id: 2,
           transaction_id: 102,
           amount: dec!(10.5),
+          timestamp: None,
+          sub_account: 0,
         }),
         Ok(Transaction::Dispute {
           client_id: 1,
           transaction_id: 103,
+          timestamp: Some(1600086400),
         }),
         Ok(Transaction::Resolve {
           client_id: 1,
           transaction_id: 104,
+          timestamp: None,
         }),
         Ok(Transaction::Chargeback {
           client_id: 1,
           transaction_id: 105,
+          timestamp: None,
         }),
         Ok(Transaction::Dispute {
           client_id: 1,
           transaction_id: 106,
+          timestamp: None,
         }),
         Ok(Transaction::Resolve {
           client_id: 1,
           transaction_id: 107,
+          timestamp: None,
         }),
         Ok(Transaction::Chargeback {
           client_id: 1,
           transaction_id: 108,
-        })
+          timestamp: None,
+        }),
+        Ok(Transaction::Transfer {
+          from_client: 1,
+          to_client: 2,
+          transaction_id: 109,
+          amount: dec!(5.0),
+          timestamp: None,
+        }),
+        Ok(Transaction::Unlock { client_id: 1 }),
+        Ok(Transaction::Reactivate { client_id: 1 })
       ]
     )
   }
+
+  #[tokio::test]
+  async fn read_balances() {
+    let input = indoc! { "
+      client,  total
+           1,    100
+           2,   10.5
+           3
+    " }
+    .as_bytes();
+
+    let mut reader = CsvBalancesReader::new(input);
+
+    let balances = reader
+      .read_balances()
+      .map(|balance| balance.map_err(|err| err.to_string()))
+      .collect::<Vec<Result<ExternalBalance, String>>>()
+      .await;
+
+    assert_eq!(balances.len(), 3);
+    assert_eq!(balances[0], Ok(ExternalBalance::new(1, dec!(100))));
+    assert_eq!(balances[1], Ok(ExternalBalance::new(2, dec!(10.5))));
+    assert!(balances[2].is_err());
+  }
 }
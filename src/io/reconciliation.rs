@@ -0,0 +1,106 @@
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+
+use super::account::with_max_precission;
+use crate::payments::{self, BreakKind, ClientId};
+
+/// A deserializable external balance
+#[derive(Debug, Deserialize)]
+pub struct ExternalBalance {
+  #[serde(rename = "client")]
+  client_id: ClientId,
+
+  total: Decimal,
+}
+
+impl From<ExternalBalance> for payments::ExternalBalance {
+  fn from(balance: ExternalBalance) -> Self {
+    // rounded like the totals of the accounts report, so they are compared as they are written out
+    payments::ExternalBalance::new(balance.client_id, with_max_precission(balance.total))
+  }
+}
+
+/// The kinds of breaks supported by the writer
+#[derive(Debug, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Kind {
+  MissingInExternal,
+  MissingInEngine,
+  AmountMismatch,
+}
+
+impl From<BreakKind> for Kind {
+  fn from(kind: BreakKind) -> Self {
+    match kind {
+      BreakKind::MissingInExternal => Kind::MissingInExternal,
+      BreakKind::MissingInEngine => Kind::MissingInEngine,
+      BreakKind::AmountMismatch => Kind::AmountMismatch,
+    }
+  }
+}
+
+/// A reconciliation break used to serialize into a CSV file
+#[derive(Debug, PartialEq, Serialize)]
+pub struct Break {
+  client: ClientId,
+  kind: Kind,
+  engine_total: Option<Decimal>,
+  external_total: Option<Decimal>,
+  difference: Option<Decimal>,
+}
+
+impl From<payments::Break> for Break {
+  fn from(reconciliation_break: payments::Break) -> Self {
+    Break {
+      client: reconciliation_break.client_id,
+      kind: reconciliation_break.kind.into(),
+      engine_total: reconciliation_break.engine_total.map(with_max_precission),
+      external_total: reconciliation_break.external_total.map(with_max_precission),
+      difference: reconciliation_break.difference().map(with_max_precission),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+
+  use rust_decimal_macros::dec;
+
+  use super::*;
+
+  #[test]
+  fn payments_external_balance_from() {
+    let balance = ExternalBalance {
+      client_id: 1,
+      total: dec!(10.5),
+    };
+
+    assert_eq!(
+      payments::ExternalBalance::from(balance),
+      payments::ExternalBalance::new(1, dec!(10.5))
+    );
+  }
+
+  #[test]
+  fn from_payments_break() {
+    let payments_break = payments::Break::new(
+      1,
+      BreakKind::AmountMismatch,
+      Some(dec!(10.00005)),
+      Some(dec!(7.5)),
+    );
+
+    let reconciliation_break: Break = payments_break.into();
+
+    assert_eq!(
+      reconciliation_break,
+      Break {
+        client: 1,
+        kind: Kind::AmountMismatch,
+        engine_total: Some(dec!(10.0001)),
+        external_total: Some(dec!(7.5)),
+        difference: Some(dec!(2.5001)),
+      }
+    )
+  }
+}
@@ -0,0 +1,151 @@
+use std::fmt;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use serde::Serialize;
+use tokio::io::AsyncWrite;
+
+use crate::payments::PaymentsEngineError;
+
+/// Why a record was rejected.
+#[derive(Debug)]
+pub enum RejectionReason {
+  /// The record could not be read as a transaction (wrong format, invalid amount, ...).
+  Read(anyhow::Error),
+  /// The transaction was rejected by the payments engine.
+  Engine(PaymentsEngineError),
+}
+
+impl fmt::Display for RejectionReason {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      RejectionReason::Read(err) => write!(f, "{}", err),
+      RejectionReason::Engine(err) => write!(f, "{}", err),
+    }
+  }
+}
+
+/// A rejected record, with the information available about where it comes from.
+#[derive(Debug)]
+pub struct Rejection {
+  pub line: Option<u64>,
+  pub record: Option<String>,
+  pub reason: RejectionReason,
+}
+
+/// Interface for a destination of the rejected records, so they can be reconciled afterwards.
+#[async_trait]
+pub trait ErrorSink: Send {
+  async fn reject(&mut self, rejection: Rejection) -> Result<()>;
+}
+
+#[async_trait]
+impl<E> ErrorSink for Box<E>
+where
+  E: ErrorSink + ?Sized,
+{
+  async fn reject(&mut self, rejection: Rejection) -> Result<()> {
+    self.as_mut().reject(rejection).await
+  }
+}
+
+/// A serializable rejection
+#[derive(Serialize)]
+struct RejectedRecord {
+  line: Option<u64>,
+  record: Option<String>,
+  stage: &'static str,
+  error: String,
+}
+
+impl From<Rejection> for RejectedRecord {
+  fn from(rejection: Rejection) -> Self {
+    let stage = match rejection.reason {
+      RejectionReason::Read(_) => "read",
+      RejectionReason::Engine(_) => "engine",
+    };
+
+    RejectedRecord {
+      line: rejection.line,
+      record: rejection.record,
+      stage,
+      error: rejection.reason.to_string(),
+    }
+  }
+}
+
+/// An implementation of [`ErrorSink`] for the CSV format, with the `line, record, stage, error` columns.
+/// Every rejection is flushed as soon as it is written, so the file is complete even if the processing fails afterwards.
+pub struct CsvErrorSink<W>
+where
+  W: AsyncWrite + Unpin,
+{
+  serializer: csv_async::AsyncSerializer<W>,
+}
+
+impl<W> CsvErrorSink<W>
+where
+  W: AsyncWrite + Unpin + Send + Sync,
+{
+  pub fn new(writer: W) -> Self {
+    Self {
+      serializer: csv_async::AsyncSerializer::from_writer(writer),
+    }
+  }
+}
+
+#[async_trait]
+impl<W> ErrorSink for CsvErrorSink<W>
+where
+  W: AsyncWrite + Unpin + Send + Sync,
+{
+  async fn reject(&mut self, rejection: Rejection) -> Result<()> {
+    self
+      .serializer
+      .serialize(RejectedRecord::from(rejection))
+      .await?;
+    self.serializer.flush().await?;
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+
+  use indoc::indoc;
+
+  use super::*;
+
+  #[tokio::test]
+  async fn reject_into_csv() {
+    let mut buffer = Vec::<u8>::with_capacity(1024);
+    let mut sink = CsvErrorSink::new(&mut buffer);
+
+    sink
+      .reject(Rejection {
+        line: Some(2),
+        record: Some("deposit,1,101,abc".to_string()),
+        reason: RejectionReason::Read(anyhow::anyhow!("Invalid amount")),
+      })
+      .await
+      .unwrap();
+    sink
+      .reject(Rejection {
+        line: None,
+        record: None,
+        reason: RejectionReason::Engine(PaymentsEngineError::NotEnoughAvailableFunds),
+      })
+      .await
+      .unwrap();
+    drop(sink);
+
+    assert_eq!(
+      String::from_utf8_lossy(buffer.as_slice()),
+      indoc! { r#"
+        line,record,stage,error
+        2,"deposit,1,101,abc",read,Invalid amount
+        ,,engine,Not enough available funds
+      "# }
+    );
+  }
+}
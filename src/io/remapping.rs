@@ -0,0 +1,186 @@
+use std::collections::HashMap;
+
+use anyhow::Result;
+use serde::Deserialize;
+use tokio::io::AsyncRead;
+use tokio_stream::{Stream, StreamExt};
+
+use super::reader::{TransactionRecord, TransactionsReader};
+use crate::payments::{ClientId, Transaction};
+
+/// A deserializable entry of the remapping file
+#[derive(Debug, Deserialize)]
+struct Remapping {
+  source: Option<String>,
+  source_client: ClientId,
+  client: ClientId,
+}
+
+/// Mapping of the client IDs used by a source (like an acquirer with its own customer IDs) into the canonical ones.
+///
+/// It is loaded from a CSV with the `source, source_client, client` columns. Entries with an empty `source` apply to any source,
+/// but entries for the specific source take precedence. Client IDs without an entry are kept as they are.
+#[derive(Debug, Default, PartialEq)]
+pub struct ClientIdRemapping(HashMap<ClientId, ClientId>);
+
+impl ClientIdRemapping {
+  pub async fn load<R>(reader: R, source: Option<&str>) -> Result<Self>
+  where
+    R: AsyncRead + Unpin + Send + Sync,
+  {
+    let mut any_source = HashMap::new();
+    let mut same_source = HashMap::new();
+
+    let mut records = csv_async::AsyncReaderBuilder::new()
+      .create_reader(reader)
+      .into_records();
+    while let Some(record) = records.next().await {
+      let mut record = record?;
+      record.trim();
+      let remapping = record.deserialize::<Remapping>(None)?;
+      match remapping.source {
+        None => {
+          any_source.insert(remapping.source_client, remapping.client);
+        }
+        Some(remapping_source) if Some(remapping_source.as_str()) == source => {
+          same_source.insert(remapping.source_client, remapping.client);
+        }
+        Some(_) => {}
+      }
+    }
+
+    any_source.extend(same_source);
+    Ok(Self(any_source))
+  }
+
+  pub fn client_id(&self, source_client_id: ClientId) -> ClientId {
+    self
+      .0
+      .get(&source_client_id)
+      .copied()
+      .unwrap_or(source_client_id)
+  }
+}
+
+/// A [`TransactionsReader`] that remaps the client IDs of the transactions read by another one,
+/// to unify the client IDs of the sources that use their own IDs.
+pub struct RemappedTransactionsReader<R> {
+  inner: R,
+  remapping: ClientIdRemapping,
+}
+
+impl<R> RemappedTransactionsReader<R>
+where
+  R: TransactionsReader,
+{
+  pub fn new(inner: R, remapping: ClientIdRemapping) -> Self {
+    Self { inner, remapping }
+  }
+}
+
+impl<R> TransactionsReader for RemappedTransactionsReader<R>
+where
+  R: TransactionsReader,
+{
+  fn read_transactions<'a>(
+    &'a mut self,
+  ) -> Box<dyn Stream<Item = Result<Transaction>> + Unpin + 'a> {
+    let remapping = &self.remapping;
+    Box::new(
+      self
+        .inner
+        .read_transactions()
+        .map(move |maybe_transaction| {
+          maybe_transaction.map(|transaction| {
+            transaction.map_client_ids(|client_id| remapping.client_id(client_id))
+          })
+        }),
+    )
+  }
+
+  fn read_records<'a>(&'a mut self) -> Box<dyn Stream<Item = TransactionRecord> + Unpin + 'a> {
+    let remapping = &self.remapping;
+    Box::new(self.inner.read_records().map(move |mut record| {
+      record.transaction = record
+        .transaction
+        .map(|transaction| transaction.map_client_ids(|client_id| remapping.client_id(client_id)));
+      record
+    }))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+
+  use indoc::indoc;
+  use rust_decimal_macros::dec;
+
+  use super::*;
+  use crate::io::CsvTransactionsReader;
+
+  const REMAPPING: &str = indoc! { "
+    source,  source_client, client
+          ,             10,      1
+    bank-a,             10,      2
+    bank-b,             20,      3
+  " };
+
+  #[tokio::test]
+  async fn load_remapping_for_source() {
+    let remapping = ClientIdRemapping::load(REMAPPING.as_bytes(), Some("bank-a"))
+      .await
+      .unwrap();
+
+    assert_eq!(remapping.client_id(10), 2);
+    assert_eq!(remapping.client_id(20), 20);
+  }
+
+  #[tokio::test]
+  async fn load_remapping_without_source() {
+    let remapping = ClientIdRemapping::load(REMAPPING.as_bytes(), None)
+      .await
+      .unwrap();
+
+    assert_eq!(remapping.client_id(10), 1);
+    assert_eq!(remapping.client_id(20), 20);
+  }
+
+  #[tokio::test]
+  async fn read_remapped_transactions() {
+    let transactions = indoc! { "
+      type,       client,   tx,  amount
+      deposit,        10,  101,     100
+      dispute,        30,  101,
+    " }
+    .as_bytes();
+    let remapping = ClientIdRemapping::load(REMAPPING.as_bytes(), Some("bank-b"))
+      .await
+      .unwrap();
+    let mut reader =
+      RemappedTransactionsReader::new(CsvTransactionsReader::new(transactions), remapping);
+
+    let transactions = reader
+      .read_transactions()
+      .map(|tx| tx.map_err(|err| err.to_string()))
+      .collect::<Vec<Result<Transaction, String>>>()
+      .await;
+
+    assert_eq!(
+      transactions,
+      vec![
+        Ok(Transaction::Deposit {
+          client_id: 1,
+          transaction_id: 101,
+          amount: dec!(100),
+          timestamp: None,
+          sub_account: 0,
+        }),
+        Ok(Transaction::Dispute {
+          client_id: 30,
+          transaction_id: 101,
+          timestamp: None,
+        }),
+      ]
+    );
+  }
+}
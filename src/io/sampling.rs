@@ -0,0 +1,180 @@
+use std::collections::HashSet;
+
+use anyhow::Result;
+use tokio_stream::{Stream, StreamExt};
+
+use super::reader::{TransactionRecord, TransactionsReader};
+use crate::payments::{ClientId, Transaction};
+
+/// How to select a subset of the input, to estimate the outcome of a run before processing the whole input.
+///
+/// The selection is deterministic, so sampling the same input twice selects the same transactions.
+/// The transactions that can not be read are always kept, so the data problems can still be found.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Sampling {
+  /// The first `N` transactions of the input.
+  Head(usize),
+  /// All the transactions of a `fraction` (between 0 and 1) of the clients, chosen by hashing their ID with the `seed`.
+  /// Keeping all the transactions of the sampled clients means that their disputes still find the disputed transactions.
+  Fraction { fraction: f64, seed: u64 },
+  /// All the transactions of the first `N` clients found in the input.
+  Clients(usize),
+}
+
+impl Sampling {
+  fn sample<'a, T, F>(
+    self,
+    stream: Box<dyn Stream<Item = T> + Unpin + 'a>,
+    client_id: F,
+  ) -> Box<dyn Stream<Item = T> + Unpin + 'a>
+  where
+    T: 'a,
+    F: Fn(&T) -> Option<ClientId> + 'a,
+  {
+    match self {
+      Sampling::Head(size) => Box::new(stream.take(size)),
+      Sampling::Fraction { fraction, seed } => Box::new(stream.filter(move |item| {
+        client_id(item).map_or(true, |client_id| client_hash(seed, client_id) < fraction)
+      })),
+      Sampling::Clients(size) => {
+        let mut clients = HashSet::with_capacity(size);
+        Box::new(stream.filter(move |item| {
+          client_id(item).map_or(true, |client_id| {
+            clients.contains(&client_id) || (clients.len() < size && clients.insert(client_id))
+          })
+        }))
+      }
+    }
+  }
+}
+
+/// A uniform hash of the client ID into `[0, 1)`, stable across platforms and Rust versions (splitmix64).
+fn client_hash(seed: u64, client_id: ClientId) -> f64 {
+  let mut hash = seed ^ u64::from(client_id);
+  hash = hash.wrapping_add(0x9e37_79b9_7f4a_7c15);
+  hash = (hash ^ (hash >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+  hash = (hash ^ (hash >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+  hash ^= hash >> 31;
+  (hash >> 11) as f64 / (1u64 << 53) as f64
+}
+
+/// A [`TransactionsReader`] that only yields a [`Sampling`] of the transactions read by another one,
+/// to estimate the outcome of big runs.
+pub struct SampledTransactionsReader<R> {
+  inner: R,
+  sampling: Sampling,
+}
+
+impl<R> SampledTransactionsReader<R>
+where
+  R: TransactionsReader,
+{
+  pub fn new(inner: R, sampling: Sampling) -> Self {
+    Self { inner, sampling }
+  }
+}
+
+impl<R> TransactionsReader for SampledTransactionsReader<R>
+where
+  R: TransactionsReader,
+{
+  fn read_transactions<'a>(
+    &'a mut self,
+  ) -> Box<dyn Stream<Item = Result<Transaction>> + Unpin + 'a> {
+    self
+      .sampling
+      .sample(self.inner.read_transactions(), |maybe_transaction| {
+        maybe_transaction
+          .as_ref()
+          .ok()
+          .map(|transaction| transaction.client_id())
+      })
+  }
+
+  fn read_records<'a>(&'a mut self) -> Box<dyn Stream<Item = TransactionRecord> + Unpin + 'a> {
+    self.sampling.sample(self.inner.read_records(), |record| {
+      record
+        .transaction
+        .as_ref()
+        .ok()
+        .map(|transaction| transaction.client_id())
+    })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+
+  use indoc::indoc;
+
+  use super::*;
+  use crate::io::CsvTransactionsReader;
+
+  const TRANSACTIONS: &str = indoc! { "
+    type,       client,   tx,  amount
+    deposit,         1,  101,     100
+    deposit,         2,  201,      50
+    deposit,       abc,  301,      10
+    withdrawal,      1,  102,      30
+    deposit,         3,  302,      10
+    dispute,         2,  201,
+  " };
+
+  async fn sampled_clients(sampling: Sampling) -> Vec<Option<ClientId>> {
+    let mut reader = SampledTransactionsReader::new(
+      CsvTransactionsReader::new(TRANSACTIONS.as_bytes()),
+      sampling,
+    );
+    reader
+      .read_transactions()
+      .map(|maybe_transaction| {
+        maybe_transaction
+          .ok()
+          .map(|transaction| transaction.client_id())
+      })
+      .collect()
+      .await
+  }
+
+  #[tokio::test]
+  async fn read_head() {
+    assert_eq!(
+      sampled_clients(Sampling::Head(3)).await,
+      vec![Some(1), Some(2), None]
+    );
+  }
+
+  #[tokio::test]
+  async fn read_first_clients() {
+    assert_eq!(
+      sampled_clients(Sampling::Clients(2)).await,
+      vec![Some(1), Some(2), None, Some(1), Some(2)]
+    );
+  }
+
+  #[tokio::test]
+  async fn read_fraction_of_clients() {
+    let all = Sampling::Fraction {
+      fraction: 1.0,
+      seed: 7,
+    };
+    let none = Sampling::Fraction {
+      fraction: 0.0,
+      seed: 7,
+    };
+
+    assert_eq!(sampled_clients(all).await.len(), 6);
+    assert_eq!(sampled_clients(none).await, vec![None]);
+  }
+
+  #[test]
+  fn client_hash_is_deterministic_and_uniform() {
+    let sampled = (0..10_000u16)
+      .filter(|client_id| client_hash(42, *client_id) < 0.1)
+      .count();
+
+    assert_eq!(client_hash(42, 1), client_hash(42, 1));
+    assert_ne!(client_hash(42, 1), client_hash(43, 1));
+    assert!(sampled > 900 && sampled < 1100, "sampled {}", sampled);
+  }
+}
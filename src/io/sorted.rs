@@ -0,0 +1,89 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use tokio_stream::{Stream, StreamExt};
+
+use super::writer::AccountsReportWriter;
+use crate::payments::{AccountReport, AccountsReportStream, PaymentsEngineError, ReportOptions};
+
+/// An [`AccountsReportWriter`] that sorts the report with the [`ReportOptions`] before writing it into the inner writer,
+/// so different runs with the same input produce the same output.
+///
+/// The whole report is kept in memory to sort it. When there are no options, the report is passed through to the inner writer.
+pub struct SortedAccountsReportWriter<W> {
+  inner: W,
+  options: Option<ReportOptions>,
+}
+
+impl<W> SortedAccountsReportWriter<W>
+where
+  W: AccountsReportWriter,
+{
+  pub fn new(inner: W, options: Option<ReportOptions>) -> Self {
+    Self { inner, options }
+  }
+}
+
+#[async_trait]
+impl<W> AccountsReportWriter for SortedAccountsReportWriter<W>
+where
+  W: AccountsReportWriter,
+{
+  async fn write_accounts_report<'a, S>(&'a mut self, report: S) -> Result<()>
+  where
+    S: Stream<Item = Result<AccountReport, PaymentsEngineError>> + Send + 'a,
+  {
+    match self.options {
+      Some(options) => {
+        let mut report: Vec<AccountReport> = report.collect::<Result<_, _>>().await?;
+        options.sort(&mut report);
+        self
+          .inner
+          .write_accounts_report(AccountsReportStream::iter(report))
+          .await
+      }
+      None => self.inner.write_accounts_report(report).await,
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+
+  use indoc::indoc;
+  use rust_decimal_macros::dec;
+
+  use super::*;
+  use crate::io::CsvAccountsReportWriter;
+  use crate::payments::ReportSortKey;
+
+  #[tokio::test]
+  async fn write_sorted_accounts_report() {
+    let report = vec![
+      AccountReport::new(2, dec!(5), dec!(0), dec!(5), false),
+      AccountReport::new(3, dec!(1), dec!(0), dec!(1), true),
+      AccountReport::new(1, dec!(5), dec!(0), dec!(5), false),
+    ];
+    let options = ReportOptions {
+      sort_by: ReportSortKey::Total,
+      descending: true,
+    };
+    let mut buffer = Vec::<u8>::with_capacity(1024);
+    let mut writer =
+      SortedAccountsReportWriter::new(CsvAccountsReportWriter::new(&mut buffer), Some(options));
+
+    writer
+      .write_accounts_report(AccountsReportStream::iter(report))
+      .await
+      .unwrap();
+
+    assert_eq!(
+      String::from_utf8_lossy(buffer.as_slice()),
+      indoc! {"
+        client,available,held,total,locked
+        2,5,0,5,false
+        1,5,0,5,false
+        3,1,0,1,true
+      "}
+    );
+  }
+}
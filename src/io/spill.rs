@@ -0,0 +1,244 @@
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::Result;
+use async_trait::async_trait;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use tokio_stream::{Stream, StreamExt};
+
+use super::writer::AccountsReportWriter;
+use crate::payments::{AccountReport, AccountsReportStream, ClientId, PaymentsEngineError};
+
+/// An [`AccountsReportWriter`] that drains the report from the engine before writing it into a slow destination.
+///
+/// Up to `max_buffered` accounts are kept in memory, and the rest are spilled into a temporary file,
+/// so the memory used doesn't depend on the number of accounts. Once the whole report has been drained,
+/// the buffered accounts followed by the spilled ones are streamed into the inner writer.
+/// When `max_buffered` is `None`, the report is passed through to the inner writer.
+pub struct SpillingAccountsReportWriter<W> {
+  inner: W,
+  max_buffered: Option<usize>,
+  spill_dir: PathBuf,
+}
+
+impl<W> SpillingAccountsReportWriter<W>
+where
+  W: AccountsReportWriter,
+{
+  pub fn new(inner: W, max_buffered: Option<usize>, spill_dir: PathBuf) -> Self {
+    Self {
+      inner,
+      max_buffered,
+      spill_dir,
+    }
+  }
+
+  fn spill_path(&self) -> Result<PathBuf> {
+    let timestamp = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis();
+    Ok(self.spill_dir.join(format!(
+      "toy-payments-engine-spill-{}-{}.ndjson",
+      std::process::id(),
+      timestamp
+    )))
+  }
+}
+
+#[async_trait]
+impl<W> AccountsReportWriter for SpillingAccountsReportWriter<W>
+where
+  W: AccountsReportWriter,
+{
+  async fn write_accounts_report<'a, S>(&'a mut self, report: S) -> Result<()>
+  where
+    S: Stream<Item = Result<AccountReport, PaymentsEngineError>> + Send + 'a,
+  {
+    let max_buffered = match self.max_buffered {
+      Some(max_buffered) => max_buffered,
+      None => return self.inner.write_accounts_report(report).await,
+    };
+
+    let mut report = Box::pin(report.fuse());
+    let mut buffered = Vec::with_capacity(max_buffered);
+    while buffered.len() < max_buffered {
+      match report.next().await {
+        Some(account_report) => buffered.push(account_report?),
+        None => break,
+      }
+    }
+    let first_spilled = match report.next().await {
+      Some(account_report) => account_report,
+      None => {
+        return self
+          .inner
+          .write_accounts_report(AccountsReportStream::iter(buffered))
+          .await
+      }
+    };
+
+    let spill_path = self.spill_path()?;
+    let result = spill(&spill_path, tokio_stream::once(first_spilled).chain(report)).await;
+    let result = match result {
+      Ok(()) => write_with_spilled(&mut self.inner, buffered, &spill_path).await,
+      Err(err) => Err(err),
+    };
+    std::fs::remove_file(&spill_path).ok();
+    result
+  }
+}
+
+/// Serializable copy of the [`AccountReport`] used for the spilled accounts.
+#[derive(Serialize, Deserialize)]
+struct SpilledAccount {
+  client_id: ClientId,
+  available: Decimal,
+  held: Decimal,
+  total: Decimal,
+  locked: bool,
+  open_disputes: usize,
+  charged_back_total: Decimal,
+  fees: Decimal,
+  escrow_interest: Option<Decimal>,
+  exposure_alert: Option<bool>,
+  dormant: Option<bool>,
+}
+
+impl From<AccountReport> for SpilledAccount {
+  fn from(report: AccountReport) -> Self {
+    Self {
+      client_id: report.client_id,
+      available: report.available,
+      held: report.held,
+      total: report.total,
+      locked: report.locked,
+      open_disputes: report.open_disputes,
+      charged_back_total: report.charged_back_total,
+      fees: report.fees,
+      escrow_interest: report.escrow_interest,
+      exposure_alert: report.exposure_alert,
+      dormant: report.dormant,
+    }
+  }
+}
+
+impl From<SpilledAccount> for AccountReport {
+  fn from(spilled: SpilledAccount) -> Self {
+    let account_report = AccountReport::new(
+      spilled.client_id,
+      spilled.available,
+      spilled.held,
+      spilled.total,
+      spilled.locked,
+    )
+    .with_disputes(spilled.open_disputes, spilled.charged_back_total)
+    .with_fees(spilled.fees);
+
+    let account_report = match spilled.exposure_alert {
+      Some(exposure_alert) => account_report.with_exposure_alert(exposure_alert),
+      None => account_report,
+    };
+
+    let account_report = match spilled.dormant {
+      Some(dormant) => account_report.with_dormant(dormant),
+      None => account_report,
+    };
+
+    match spilled.escrow_interest {
+      Some(escrow_interest) => account_report.with_escrow_interest(escrow_interest),
+      None => account_report,
+    }
+  }
+}
+
+async fn spill<S>(path: &Path, mut report: S) -> Result<()>
+where
+  S: Stream<Item = Result<AccountReport, PaymentsEngineError>> + Unpin,
+{
+  let mut file = BufWriter::new(File::create(path)?);
+  while let Some(account_report) = report.next().await {
+    serde_json::to_writer(&mut file, &SpilledAccount::from(account_report?))?;
+    file.write_all(b"\n")?;
+  }
+  file.flush()?;
+  Ok(())
+}
+
+async fn write_with_spilled<W>(
+  writer: &mut W,
+  buffered: Vec<AccountReport>,
+  spill_path: &Path,
+) -> Result<()>
+where
+  W: AccountsReportWriter,
+{
+  // the report is an iterator of accounts, so the first error reading them back stops it, and is returned afterwards
+  let mut error = None;
+  let spilled = BufReader::new(File::open(spill_path)?)
+    .lines()
+    .scan(&mut error, |error, line| {
+      match line
+        .map_err(anyhow::Error::from)
+        .and_then(|line| serde_json::from_str::<SpilledAccount>(&line).map_err(anyhow::Error::from))
+      {
+        Ok(spilled) => Some(AccountReport::from(spilled)),
+        Err(err) => {
+          **error = Some(err);
+          None
+        }
+      }
+    });
+
+  writer
+    .write_accounts_report(AccountsReportStream::iter(
+      buffered.into_iter().chain(spilled),
+    ))
+    .await?;
+
+  match error {
+    Some(err) => Err(err),
+    None => Ok(()),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+
+  use rust_decimal_macros::dec;
+
+  use super::*;
+  use crate::io::CsvAccountsReportWriter;
+
+  fn report() -> Vec<AccountReport> {
+    (1..=5)
+      .map(|client_id| AccountReport::new(client_id, dec!(1.5), dec!(0), dec!(1.5), client_id == 3))
+      .collect()
+  }
+
+  async fn write_report(max_buffered: Option<usize>) -> String {
+    let mut buffer = Vec::<u8>::with_capacity(1024);
+    let mut writer = SpillingAccountsReportWriter::new(
+      CsvAccountsReportWriter::new(&mut buffer),
+      max_buffered,
+      std::env::temp_dir(),
+    );
+
+    writer
+      .write_accounts_report(AccountsReportStream::iter(report()))
+      .await
+      .unwrap();
+
+    String::from_utf8_lossy(buffer.as_slice()).to_string()
+  }
+
+  #[tokio::test]
+  async fn write_accounts_report_with_spill() {
+    let expected = write_report(None).await;
+
+    assert_eq!(write_report(Some(2)).await, expected);
+    assert_eq!(write_report(Some(0)).await, expected);
+    assert_eq!(write_report(Some(5)).await, expected);
+    assert_eq!(expected.lines().nth(3), Some("3,1.5,0,1.5,true"));
+  }
+}
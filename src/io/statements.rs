@@ -0,0 +1,179 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use rust_decimal::Decimal;
+use serde::Serialize;
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+
+use super::account::{rounded_funds, with_max_precission};
+use crate::payments::{ClientId, StatementLine, TransactionId};
+
+/// Interface for a writer of the statements of the clients, with their running balances.
+#[async_trait]
+pub trait StatementsWriter: Send {
+  /// Write the lines of the statements provided by the [`Iterator`], in the same order.
+  async fn write_statements<'a, T>(&'a mut self, lines: T) -> Result<()>
+  where
+    T: Iterator<Item = StatementLine> + Send + 'a;
+}
+
+/// A line of a statement used to serialize it, with the `total` derived from the rounded balances.
+#[derive(Debug, PartialEq, Serialize)]
+struct StatementEntry {
+  client: ClientId,
+  tx: Option<TransactionId>,
+  #[serde(rename = "type")]
+  kind: &'static str,
+  amount: Option<Decimal>,
+  available: Decimal,
+  held: Decimal,
+  total: Decimal,
+}
+
+impl From<StatementLine> for StatementEntry {
+  fn from(line: StatementLine) -> Self {
+    let (available, held, total) = rounded_funds(line.available, line.held);
+    StatementEntry {
+      client: line.client_id,
+      tx: line.transaction_id,
+      kind: line.type_name,
+      amount: line.amount.map(with_max_precission),
+      available,
+      held,
+      total,
+    }
+  }
+}
+
+/// An implementation of [`StatementsWriter`] for the CSV format, with the `client, tx, type, amount, available, held, total` columns.
+pub struct CsvStatementsWriter<W>(W);
+
+impl<W> CsvStatementsWriter<W>
+where
+  W: AsyncWrite + Unpin + Send + Sync,
+{
+  pub fn new(writer: W) -> Self {
+    Self(writer)
+  }
+}
+
+#[async_trait]
+impl<W> StatementsWriter for CsvStatementsWriter<W>
+where
+  W: AsyncWrite + Unpin + Send + Sync,
+{
+  async fn write_statements<'a, T>(&'a mut self, lines: T) -> Result<()>
+  where
+    T: Iterator<Item = StatementLine> + Send + 'a,
+  {
+    let mut serializer = csv_async::AsyncSerializer::from_writer(&mut self.0);
+    let mut count = 0usize;
+    for entry in lines.map(StatementEntry::from) {
+      serializer.serialize(entry).await?;
+      count += 1;
+    }
+    serializer.flush().await?;
+    tracing::info!(lines = count, "Statements written");
+    Ok(())
+  }
+}
+
+/// An implementation of [`StatementsWriter`] for newline delimited JSON, with one line of a statement per line.
+pub struct NdjsonStatementsWriter<W>(W);
+
+impl<W> NdjsonStatementsWriter<W>
+where
+  W: AsyncWrite + Unpin + Send + Sync,
+{
+  pub fn new(writer: W) -> Self {
+    Self(writer)
+  }
+}
+
+#[async_trait]
+impl<W> StatementsWriter for NdjsonStatementsWriter<W>
+where
+  W: AsyncWrite + Unpin + Send + Sync,
+{
+  async fn write_statements<'a, T>(&'a mut self, lines: T) -> Result<()>
+  where
+    T: Iterator<Item = StatementLine> + Send + 'a,
+  {
+    let mut count = 0usize;
+    for entry in lines.map(StatementEntry::from) {
+      let mut line = serde_json::to_vec(&entry)?;
+      line.push(b'\n');
+      self.0.write_all(&line).await?;
+      count += 1;
+    }
+    self.0.flush().await?;
+    tracing::info!(lines = count, "Statements written");
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+
+  use indoc::indoc;
+  use rust_decimal_macros::dec;
+
+  use super::*;
+
+  fn lines() -> Vec<StatementLine> {
+    vec![
+      StatementLine {
+        client_id: 1,
+        transaction_id: Some(101),
+        type_name: "deposit",
+        amount: Some(dec!(10.123456)),
+        available: dec!(10.123456),
+        held: dec!(0),
+      },
+      StatementLine {
+        client_id: 1,
+        transaction_id: None,
+        type_name: "unlock",
+        amount: None,
+        available: dec!(5),
+        held: dec!(2.5),
+      },
+    ]
+  }
+
+  #[tokio::test]
+  async fn write_statements_as_csv() {
+    let mut buffer = Vec::<u8>::new();
+
+    CsvStatementsWriter::new(&mut buffer)
+      .write_statements(lines().into_iter())
+      .await
+      .unwrap();
+
+    assert_eq!(
+      String::from_utf8(buffer).unwrap(),
+      indoc! {"
+        client,tx,type,amount,available,held,total
+        1,101,deposit,10.1235,10.1235,0,10.1235
+        1,,unlock,,5,2.5,7.5
+      "}
+    );
+  }
+
+  #[tokio::test]
+  async fn write_statements_as_ndjson() {
+    let mut buffer = Vec::<u8>::new();
+
+    NdjsonStatementsWriter::new(&mut buffer)
+      .write_statements(lines().into_iter())
+      .await
+      .unwrap();
+
+    assert_eq!(
+      String::from_utf8(buffer).unwrap(),
+      indoc! {r#"
+        {"client":1,"tx":101,"type":"deposit","amount":"10.1235","available":"10.1235","held":"0","total":"10.1235"}
+        {"client":1,"tx":null,"type":"unlock","amount":null,"available":"5","held":"2.5","total":"7.5"}
+      "#}
+    );
+  }
+}
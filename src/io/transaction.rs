@@ -1,12 +1,11 @@
-use std::convert::TryFrom;
-
-use rust_decimal::Decimal;
 use serde::Deserialize;
 
+use super::amount::AmountParser;
 use crate::payments;
 
 /// The types of transactions supported by the reader
 #[derive(Debug, Deserialize)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary, serde::Serialize))]
 #[serde(rename_all = "lowercase")]
 pub enum TransactionType {
   Deposit,
@@ -14,10 +13,21 @@ pub enum TransactionType {
   Dispute,
   Resolve,
   Chargeback,
+  Transfer,
+  /// Unlock an account locked by a chargeback. Its transaction ID is not used.
+  Unlock,
+  Authorize,
+  Capture,
+  Void,
+  /// Reactivate a dormant account. Its transaction ID is not used.
+  Reactivate,
 }
 
-/// A deserializable transaction
+/// A deserializable transaction.
+/// With the `arbitrary` feature, it can also be generated from arbitrary data and serialized back,
+/// so the fuzz targets can produce well-formed records to feed the readers.
 #[derive(Debug, Deserialize)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary, serde::Serialize))]
 pub struct Transaction {
   #[serde(rename = "type")]
   kind: TransactionType,
@@ -28,43 +38,241 @@ pub struct Transaction {
   #[serde(rename = "tx")]
   transaction_id: u32,
 
-  amount: Option<Decimal>,
-}
+  amount: Option<String>,
+
+  /// The recipient of a transfer, which is optional as the rest of transactions don't have it.
+  #[serde(rename = "to")]
+  to_client_id: Option<u16>,
 
-impl TryFrom<Transaction> for payments::Transaction {
-  type Error = anyhow::Error;
+  /// When the transaction happened, in seconds since the Unix epoch, which is optional for all the transactions.
+  #[serde(default)]
+  timestamp: Option<u64>,
+
+  /// The sub-account of the client addressed by deposits, withdrawals and authorizations, which is the main one when empty.
+  #[serde(default)]
+  sub: Option<u16>,
+}
 
-  fn try_from(transaction: Transaction) -> Result<Self, Self::Error> {
-    match transaction.kind {
-      TransactionType::Deposit => transaction
-        .amount
-        .map(|amount| payments::Transaction::Deposit {
-          client_id: transaction.client_id,
-          transaction_id: transaction.transaction_id,
+impl Transaction {
+  /// A conversion into the domain representation of a transaction, parsing the amount with the [`AmountParser`].
+  pub fn into_payments(
+    self,
+    amount_parser: &AmountParser,
+  ) -> anyhow::Result<payments::Transaction> {
+    match self.kind {
+      TransactionType::Deposit => {
+        let amount = parse_amount(self.amount, amount_parser)?;
+        Ok(payments::Transaction::Deposit {
+          client_id: self.client_id,
+          transaction_id: self.transaction_id,
           amount,
+          timestamp: self.timestamp,
+          sub_account: self.sub.unwrap_or(payments::MAIN_SUB_ACCOUNT),
         })
-        .ok_or_else(|| anyhow::anyhow!("Missing amount")),
-      TransactionType::Withdrawal => transaction
-        .amount
-        .map(|amount| payments::Transaction::Withdrawal {
-          client_id: transaction.client_id,
-          transaction_id: transaction.transaction_id,
+      }
+      TransactionType::Withdrawal => {
+        let amount = parse_amount(self.amount, amount_parser)?;
+        Ok(payments::Transaction::Withdrawal {
+          client_id: self.client_id,
+          transaction_id: self.transaction_id,
           amount,
+          timestamp: self.timestamp,
+          sub_account: self.sub.unwrap_or(payments::MAIN_SUB_ACCOUNT),
         })
-        .ok_or_else(|| anyhow::anyhow!("Missing amount")),
+      }
       TransactionType::Dispute => Ok(payments::Transaction::Dispute {
-        client_id: transaction.client_id,
-        transaction_id: transaction.transaction_id,
+        client_id: self.client_id,
+        transaction_id: self.transaction_id,
+        timestamp: self.timestamp,
       }),
       TransactionType::Resolve => Ok(payments::Transaction::Resolve {
-        client_id: transaction.client_id,
-        transaction_id: transaction.transaction_id,
+        client_id: self.client_id,
+        transaction_id: self.transaction_id,
+        timestamp: self.timestamp,
       }),
       TransactionType::Chargeback => Ok(payments::Transaction::Chargeback {
-        client_id: transaction.client_id,
-        transaction_id: transaction.transaction_id,
+        client_id: self.client_id,
+        transaction_id: self.transaction_id,
+        timestamp: self.timestamp,
+      }),
+      TransactionType::Transfer => {
+        let amount = parse_amount(self.amount, amount_parser)?;
+        let to_client = self
+          .to_client_id
+          .ok_or_else(|| anyhow::anyhow!("Missing recipient of the transfer"))?;
+        Ok(payments::Transaction::Transfer {
+          from_client: self.client_id,
+          to_client,
+          transaction_id: self.transaction_id,
+          amount,
+          timestamp: self.timestamp,
+        })
+      }
+      TransactionType::Unlock => Ok(payments::Transaction::Unlock {
+        client_id: self.client_id,
+      }),
+      TransactionType::Reactivate => Ok(payments::Transaction::Reactivate {
+        client_id: self.client_id,
       }),
+      TransactionType::Authorize => {
+        let amount = parse_amount(self.amount, amount_parser)?;
+        Ok(payments::Transaction::Authorize {
+          client_id: self.client_id,
+          transaction_id: self.transaction_id,
+          amount,
+          timestamp: self.timestamp,
+          sub_account: self.sub.unwrap_or(payments::MAIN_SUB_ACCOUNT),
+        })
+      }
+      TransactionType::Capture => Ok(payments::Transaction::Capture {
+        client_id: self.client_id,
+        transaction_id: self.transaction_id,
+        timestamp: self.timestamp,
+      }),
+      TransactionType::Void => Ok(payments::Transaction::Void {
+        client_id: self.client_id,
+        transaction_id: self.transaction_id,
+        timestamp: self.timestamp,
+      }),
+    }
+  }
+}
+
+fn parse_amount(
+  amount: Option<String>,
+  amount_parser: &AmountParser,
+) -> anyhow::Result<rust_decimal::Decimal> {
+  let amount = amount.ok_or_else(|| anyhow::anyhow!("Missing amount"))?;
+  amount_parser.parse(&amount).map_err(anyhow::Error::from)
+}
+
+/// The CSV record of a transaction, in the same format than the input.
+pub(crate) fn log_record(transaction: &payments::Transaction) -> String {
+  match *transaction {
+    payments::Transaction::Deposit {
+      client_id,
+      transaction_id,
+      amount,
+      timestamp,
+      sub_account,
+    } => format!(
+      "deposit,{},{},{},,{}{}\n",
+      client_id,
+      transaction_id,
+      amount,
+      optional(timestamp),
+      sub_account_column(sub_account)
+    ),
+    payments::Transaction::Withdrawal {
+      client_id,
+      transaction_id,
+      amount,
+      timestamp,
+      sub_account,
+    } => format!(
+      "withdrawal,{},{},{},,{}{}\n",
+      client_id,
+      transaction_id,
+      amount,
+      optional(timestamp),
+      sub_account_column(sub_account)
+    ),
+    payments::Transaction::Dispute {
+      client_id,
+      transaction_id,
+      timestamp,
+    } => format!(
+      "dispute,{},{},,,{}\n",
+      client_id,
+      transaction_id,
+      optional(timestamp)
+    ),
+    payments::Transaction::Resolve {
+      client_id,
+      transaction_id,
+      timestamp,
+    } => format!(
+      "resolve,{},{},,,{}\n",
+      client_id,
+      transaction_id,
+      optional(timestamp)
+    ),
+    payments::Transaction::Chargeback {
+      client_id,
+      transaction_id,
+      timestamp,
+    } => format!(
+      "chargeback,{},{},,,{}\n",
+      client_id,
+      transaction_id,
+      optional(timestamp)
+    ),
+    payments::Transaction::Transfer {
+      from_client,
+      to_client,
+      transaction_id,
+      amount,
+      timestamp,
+    } => format!(
+      "transfer,{},{},{},{},{}\n",
+      from_client,
+      transaction_id,
+      amount,
+      to_client,
+      optional(timestamp)
+    ),
+    payments::Transaction::Unlock { client_id } => format!("unlock,{},0,,,\n", client_id),
+    payments::Transaction::Reactivate { client_id } => {
+      format!("reactivate,{},0,,,\n", client_id)
     }
+    payments::Transaction::Authorize {
+      client_id,
+      transaction_id,
+      amount,
+      timestamp,
+      sub_account,
+    } => format!(
+      "authorize,{},{},{},,{}{}\n",
+      client_id,
+      transaction_id,
+      amount,
+      optional(timestamp),
+      sub_account_column(sub_account)
+    ),
+    payments::Transaction::Capture {
+      client_id,
+      transaction_id,
+      timestamp,
+    } => format!(
+      "capture,{},{},,,{}\n",
+      client_id,
+      transaction_id,
+      optional(timestamp)
+    ),
+    payments::Transaction::Void {
+      client_id,
+      transaction_id,
+      timestamp,
+    } => format!(
+      "void,{},{},,,{}\n",
+      client_id,
+      transaction_id,
+      optional(timestamp)
+    ),
+  }
+}
+
+fn optional(value: Option<u64>) -> String {
+  value.map(|value| value.to_string()).unwrap_or_default()
+}
+
+/// The `sub` column, which is only logged for the sub-accounts other than the main one,
+/// so the records of the main sub-accounts are the same than before it existed.
+fn sub_account_column(sub_account: payments::SubAccountId) -> String {
+  if sub_account == payments::MAIN_SUB_ACCOUNT {
+    String::new()
+  } else {
+    format!(",{}", sub_account)
   }
 }
 
@@ -76,19 +284,24 @@ mod tests {
   use super::*;
 
   #[test]
-  fn payments_transaction_try_from_success() {
+  fn payments_transaction_into_success() {
     let cases = vec![
       (
         Transaction {
           kind: TransactionType::Deposit,
           client_id: 1,
           transaction_id: 101,
-          amount: Some(dec!(100)),
+          amount: Some("100".to_string()),
+          to_client_id: None,
+          timestamp: None,
+          sub: None,
         },
         payments::Transaction::Deposit {
           client_id: 1,
           transaction_id: 101,
           amount: dec!(100),
+          timestamp: None,
+          sub_account: 0,
         },
       ),
       (
@@ -96,12 +309,17 @@ mod tests {
           kind: TransactionType::Withdrawal,
           client_id: 2,
           transaction_id: 102,
-          amount: Some(dec!(200)),
+          amount: Some("200".to_string()),
+          to_client_id: None,
+          timestamp: None,
+          sub: None,
         },
         payments::Transaction::Withdrawal {
           client_id: 2,
           transaction_id: 102,
           amount: dec!(200),
+          timestamp: None,
+          sub_account: 0,
         },
       ),
       (
@@ -110,10 +328,14 @@ mod tests {
           client_id: 3,
           transaction_id: 103,
           amount: None,
+          to_client_id: None,
+          timestamp: None,
+          sub: None,
         },
         payments::Transaction::Dispute {
           client_id: 3,
           transaction_id: 103,
+          timestamp: None,
         },
       ),
       (
@@ -122,10 +344,14 @@ mod tests {
           client_id: 4,
           transaction_id: 104,
           amount: None,
+          to_client_id: None,
+          timestamp: None,
+          sub: None,
         },
         payments::Transaction::Resolve {
           client_id: 4,
           transaction_id: 104,
+          timestamp: None,
         },
       ),
       (
@@ -134,37 +360,176 @@ mod tests {
           client_id: 5,
           transaction_id: 105,
           amount: None,
+          to_client_id: None,
+          timestamp: None,
+          sub: None,
         },
         payments::Transaction::Chargeback {
           client_id: 5,
           transaction_id: 105,
+          timestamp: None,
+        },
+      ),
+      (
+        Transaction {
+          kind: TransactionType::Transfer,
+          client_id: 6,
+          transaction_id: 106,
+          amount: Some("60".to_string()),
+          to_client_id: Some(7),
+          timestamp: None,
+          sub: None,
+        },
+        payments::Transaction::Transfer {
+          from_client: 6,
+          to_client: 7,
+          transaction_id: 106,
+          amount: dec!(60),
+          timestamp: None,
+        },
+      ),
+      (
+        Transaction {
+          kind: TransactionType::Unlock,
+          client_id: 8,
+          transaction_id: 108,
+          amount: None,
+          to_client_id: None,
+          timestamp: None,
+          sub: None,
+        },
+        payments::Transaction::Unlock { client_id: 8 },
+      ),
+      (
+        Transaction {
+          kind: TransactionType::Reactivate,
+          client_id: 8,
+          transaction_id: 0,
+          amount: None,
+          to_client_id: None,
+          timestamp: None,
+          sub: None,
+        },
+        payments::Transaction::Reactivate { client_id: 8 },
+      ),
+      (
+        Transaction {
+          kind: TransactionType::Authorize,
+          client_id: 9,
+          transaction_id: 109,
+          amount: Some("90".to_string()),
+          to_client_id: None,
+          timestamp: None,
+          sub: None,
+        },
+        payments::Transaction::Authorize {
+          client_id: 9,
+          transaction_id: 109,
+          amount: dec!(90),
+          timestamp: None,
+          sub_account: 0,
+        },
+      ),
+      (
+        Transaction {
+          kind: TransactionType::Capture,
+          client_id: 9,
+          transaction_id: 109,
+          amount: None,
+          to_client_id: None,
+          timestamp: None,
+          sub: None,
+        },
+        payments::Transaction::Capture {
+          client_id: 9,
+          transaction_id: 109,
+          timestamp: None,
+        },
+      ),
+      (
+        Transaction {
+          kind: TransactionType::Void,
+          client_id: 9,
+          transaction_id: 110,
+          amount: None,
+          to_client_id: None,
+          timestamp: None,
+          sub: None,
+        },
+        payments::Transaction::Void {
+          client_id: 9,
+          transaction_id: 110,
+          timestamp: None,
         },
       ),
     ];
 
     for (input, expected) in cases {
-      let tx = payments::Transaction::try_from(input);
+      let tx = input.into_payments(&AmountParser::default());
       assert!(tx.is_ok());
       assert_eq!(tx.unwrap(), expected);
     }
   }
 
   #[test]
-  fn payments_transaction_try_from_missing_amount() {
-    assert!(payments::Transaction::try_from(Transaction {
+  fn payments_transaction_into_missing_amount() {
+    assert!(Transaction {
       kind: TransactionType::Deposit,
       client_id: 1,
       transaction_id: 101,
       amount: None,
-    })
+      to_client_id: None,
+      timestamp: None,
+      sub: None,
+    }
+    .into_payments(&AmountParser::default())
     .is_err());
 
-    assert!(payments::Transaction::try_from(Transaction {
+    assert!(Transaction {
       kind: TransactionType::Withdrawal,
       client_id: 1,
       transaction_id: 101,
       amount: None,
-    })
+      to_client_id: None,
+      timestamp: None,
+      sub: None,
+    }
+    .into_payments(&AmountParser::default())
+    .is_err());
+  }
+
+  #[test]
+  fn payments_transaction_into_missing_recipient() {
+    assert!(Transaction {
+      kind: TransactionType::Transfer,
+      client_id: 1,
+      transaction_id: 101,
+      amount: Some("10".to_string()),
+      to_client_id: None,
+      timestamp: None,
+      sub: None,
+    }
+    .into_payments(&AmountParser::default())
     .is_err());
   }
+
+  #[test]
+  fn payments_transaction_with_strict_amount() {
+    let transaction = |amount: &str| Transaction {
+      kind: TransactionType::Deposit,
+      client_id: 1,
+      transaction_id: 101,
+      amount: Some(amount.to_string()),
+      to_client_id: None,
+      timestamp: None,
+      sub: None,
+    };
+
+    assert!(transaction("1.5")
+      .into_payments(&AmountParser::strict())
+      .is_ok());
+    assert!(transaction("1e3")
+      .into_payments(&AmountParser::strict())
+      .is_err());
+  }
 }
@@ -1,48 +1,463 @@
+use std::sync::Arc;
+
 use anyhow::Result;
 use async_trait::async_trait;
-use tokio::io::AsyncWrite;
-use tokio_stream::StreamExt;
+use futures::channel::mpsc;
+use futures::SinkExt;
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+use tokio_stream::{Stream, StreamExt};
 
-use crate::payments::AccountReport;
+use super::account::ReportSchema;
+use super::baseline::Baseline;
+use super::metadata::ClientMetadata;
+use crate::payments::{
+  AccountReport, AccountView, AccountsReportStream, Break, PaymentsEngineError, TransactionInfo,
+};
 
 /// Interface for an account report writer
-#[async_trait(?Send)]
-pub trait AccountsReportWriter {
-  /// Write the accounts information provided by the [`Iterator`] and return whether the operation was successful or not.
-  async fn write_accounts_report<'a, T>(&'a mut self, report: T) -> Result<()>
+#[async_trait]
+pub trait AccountsReportWriter: Send {
+  /// Write the accounts information provided by the [`Stream`] and return whether the operation was successful or not.
+  /// The accounts are pulled from the stream as they are written, so the engines don't need to collect the report first,
+  /// and the first error reading them stops the writing.
+  async fn write_accounts_report<'a, S>(&'a mut self, report: S) -> Result<()>
   where
-    T: Iterator<Item = AccountReport> + 'a;
+    S: Stream<Item = Result<AccountReport, PaymentsEngineError>> + Send + 'a;
 }
 
 /// An implementation of [`AccountsReportWriter`] for the CSV format.
-pub struct CsvAccountsReportWriter<W>(W);
+pub struct CsvAccountsReportWriter<W> {
+  writer: W,
+  schema: ReportSchema,
+  metadata: Option<Arc<ClientMetadata>>,
+  currency: Option<String>,
+  baseline: Option<Arc<Baseline>>,
+}
 
 impl<W> CsvAccountsReportWriter<W>
 where
   W: AsyncWrite + Unpin + Send + Sync,
 {
   pub fn new(writer: W) -> Self {
-    Self(writer)
+    Self::with_schema(writer, ReportSchema::default())
+  }
+
+  pub fn with_schema(writer: W, schema: ReportSchema) -> Self {
+    Self {
+      writer,
+      schema,
+      metadata: None,
+      currency: None,
+      baseline: None,
+    }
+  }
+
+  /// The client metadata to join into the report, which is only supported by the [`ReportSchema::V2`].
+  pub fn with_metadata(mut self, metadata: Option<Arc<ClientMetadata>>) -> Self {
+    self.metadata = metadata;
+    self
+  }
+
+  /// The currency of the funds, which is only reported by the [`ReportSchema::Extended`].
+  pub fn with_currency(mut self, currency: Option<String>) -> Self {
+    self.currency = currency;
+    self
+  }
+
+  /// The baseline of the accounts, to add the `available_change`, `held_change` and `newly_locked` columns at the end.
+  pub fn with_baseline(mut self, baseline: Option<Arc<Baseline>>) -> Self {
+    self.baseline = baseline;
+    self
   }
 }
 
-#[async_trait(?Send)]
+#[async_trait]
 impl<W> AccountsReportWriter for CsvAccountsReportWriter<W>
 where
   W: AsyncWrite + Unpin + Send + Sync,
 {
-  async fn write_accounts_report<'a, T>(&'a mut self, report: T) -> Result<()>
+  async fn write_accounts_report<'a, S>(&'a mut self, report: S) -> Result<()>
+  where
+    S: Stream<Item = Result<AccountReport, PaymentsEngineError>> + Send + 'a,
+  {
+    let mut report = Box::pin(report);
+
+    let mut serializer = csv_async::AsyncSerializer::from_writer(&mut self.writer);
+    let mut accounts = 0usize;
+    while let Some(account_report) = report.next().await {
+      let account_report = account_report?;
+      accounts += 1;
+      match self.schema {
+        ReportSchema::V1 => {
+          let account_report = super::account::AccountReport::from(account_report);
+          let account_report = match self.baseline.as_deref() {
+            Some(baseline) => account_report.with_baseline(baseline),
+            None => account_report,
+          };
+          serializer.serialize(account_report).await?
+        }
+        ReportSchema::V2 => {
+          let account_report = report_v2(
+            account_report,
+            self.metadata.as_deref(),
+            self.baseline.as_deref(),
+          );
+          serializer.serialize(account_report).await?
+        }
+        ReportSchema::Extended => {
+          let account_report = report_extended(
+            account_report,
+            self.currency.as_deref(),
+            self.baseline.as_deref(),
+          );
+          serializer.serialize(account_report).await?
+        }
+      }
+    }
+    serializer.flush().await?;
+    tracing::info!(accounts, "Accounts report written");
+    Ok(())
+  }
+}
+
+impl<W> CsvAccountsReportWriter<W>
+where
+  W: AsyncWrite + Unpin + Send + Sync,
+{
+  /// Write the report from the borrowed accounts of an [`InMemoryPaymentsEngine`](crate::payments::InMemoryPaymentsEngine).
+  /// The [`ReportSchema::V1`] is formatted directly from their funds, without building an [`AccountReport`] for every account.
+  pub async fn write_accounts_views<'a, T>(&mut self, views: T) -> Result<()>
+  where
+    T: Iterator<Item = AccountView<'a>>,
+  {
+    let mut serializer = csv_async::AsyncSerializer::from_writer(&mut self.writer);
+    let mut accounts = 0usize;
+    for view in views {
+      accounts += 1;
+      match self.schema {
+        ReportSchema::V1 => {
+          let account_report = super::account::AccountReport::from(view);
+          let account_report = match self.baseline.as_deref() {
+            Some(baseline) => account_report.with_baseline(baseline),
+            None => account_report,
+          };
+          serializer.serialize(account_report).await?
+        }
+        ReportSchema::V2 => {
+          let account_report = report_v2(
+            view.report(),
+            self.metadata.as_deref(),
+            self.baseline.as_deref(),
+          );
+          serializer.serialize(account_report).await?
+        }
+        ReportSchema::Extended => {
+          let account_report = report_extended(
+            view.report(),
+            self.currency.as_deref(),
+            self.baseline.as_deref(),
+          );
+          serializer.serialize(account_report).await?
+        }
+      }
+    }
+    serializer.flush().await?;
+    tracing::info!(accounts, "Accounts report written");
+    Ok(())
+  }
+}
+
+fn report_v2(
+  account_report: AccountReport,
+  metadata: Option<&ClientMetadata>,
+  baseline: Option<&Baseline>,
+) -> super::account::AccountReportV2 {
+  let account_report = super::account::AccountReportV2::from(account_report);
+  let account_report = match metadata {
+    Some(metadata) => account_report.with_metadata(metadata),
+    None => account_report,
+  };
+  match baseline {
+    Some(baseline) => account_report.with_baseline(baseline),
+    None => account_report,
+  }
+}
+
+fn report_extended(
+  account_report: AccountReport,
+  currency: Option<&str>,
+  baseline: Option<&Baseline>,
+) -> super::account::AccountReportExtended {
+  let account_report =
+    super::account::AccountReportExtended::from(account_report).with_currency(currency);
+  match baseline {
+    Some(baseline) => account_report.with_baseline(baseline),
+    None => account_report,
+  }
+}
+
+/// An implementation of [`AccountsReportWriter`] for newline delimited JSON, with one account per line.
+pub struct NdjsonAccountsReportWriter<W>(W);
+
+impl<W> NdjsonAccountsReportWriter<W>
+where
+  W: AsyncWrite + Unpin + Send + Sync,
+{
+  pub fn new(writer: W) -> Self {
+    Self(writer)
+  }
+}
+
+#[async_trait]
+impl<W> AccountsReportWriter for NdjsonAccountsReportWriter<W>
+where
+  W: AsyncWrite + Unpin + Send + Sync,
+{
+  async fn write_accounts_report<'a, S>(&'a mut self, report: S) -> Result<()>
+  where
+    S: Stream<Item = Result<AccountReport, PaymentsEngineError>> + Send + 'a,
+  {
+    let mut report = Box::pin(report);
+
+    let mut accounts = 0usize;
+    while let Some(account_report) = report.next().await {
+      let account_report = super::account::AccountReport::from(account_report?);
+      let mut line = serde_json::to_vec(&account_report)?;
+      line.push(b'\n');
+      self.0.write_all(&line).await?;
+      accounts += 1;
+    }
+    self.0.flush().await?;
+    tracing::info!(accounts, "Accounts report written");
+    Ok(())
+  }
+}
+
+/// Number of accounts that the faster writer of a [`TeeAccountsReportWriter`] can be ahead of the slower one.
+const TEE_CAPACITY: usize = 1024;
+
+/// An [`AccountsReportWriter`] that writes the same report into a primary writer and, optionally, into a secondary one.
+/// Both are written at the same time from a single pass over the report, which is never collected,
+/// so the report of millions of accounts is read once however many writers are chained.
+pub struct TeeAccountsReportWriter<A, B> {
+  primary: A,
+  secondary: Option<B>,
+}
+
+impl<A, B> TeeAccountsReportWriter<A, B>
+where
+  A: AccountsReportWriter,
+  B: AccountsReportWriter,
+{
+  pub fn new(primary: A, secondary: Option<B>) -> Self {
+    Self { primary, secondary }
+  }
+}
+
+#[async_trait]
+impl<A, B> AccountsReportWriter for TeeAccountsReportWriter<A, B>
+where
+  A: AccountsReportWriter,
+  B: AccountsReportWriter,
+{
+  async fn write_accounts_report<'a, S>(&'a mut self, report: S) -> Result<()>
+  where
+    S: Stream<Item = Result<AccountReport, PaymentsEngineError>> + Send + 'a,
+  {
+    let secondary = match self.secondary.as_mut() {
+      Some(secondary) => secondary,
+      None => return self.primary.write_accounts_report(report).await,
+    };
+    let (mut primary_sender, primary_report) = mpsc::channel(TEE_CAPACITY);
+    let (mut secondary_sender, secondary_report) = mpsc::channel(TEE_CAPACITY);
+    let forward = async move {
+      let mut report = Box::pin(report);
+      while let Some(account) = report.next().await {
+        let failed = account.is_err();
+        // a writer that failed drops its side of the channel, and the other one keeps receiving the accounts
+        primary_sender.send(account.clone()).await.ok();
+        secondary_sender.send(account).await.ok();
+        if failed {
+          break;
+        }
+      }
+    };
+    let (_, primary, secondary) = futures::join!(
+      forward,
+      self.primary.write_accounts_report(primary_report),
+      secondary.write_accounts_report(secondary_report)
+    );
+    primary.and(secondary)
+  }
+}
+
+/// Interface for a reconciliation breaks report writer
+#[async_trait]
+pub trait BreaksReportWriter: Send {
+  /// Write the breaks provided by the [`Iterator`] and return whether the operation was successful or not.
+  async fn write_breaks_report<'a, T>(&'a mut self, breaks: T) -> Result<()>
+  where
+    T: Iterator<Item = Break> + Send + 'a;
+}
+
+/// An implementation of [`BreaksReportWriter`] for the CSV format.
+pub struct CsvBreaksReportWriter<W>(W);
+
+impl<W> CsvBreaksReportWriter<W>
+where
+  W: AsyncWrite + Unpin + Send + Sync,
+{
+  pub fn new(writer: W) -> Self {
+    Self(writer)
+  }
+}
+
+#[async_trait]
+impl<W> BreaksReportWriter for CsvBreaksReportWriter<W>
+where
+  W: AsyncWrite + Unpin + Send + Sync,
+{
+  async fn write_breaks_report<'a, T>(&'a mut self, breaks: T) -> Result<()>
   where
-    T: Iterator<Item = AccountReport> + 'a,
+    T: Iterator<Item = Break> + Send + 'a,
   {
-    let mut report = Box::pin(tokio_stream::iter(
-      report.map(super::account::AccountReport::from),
+    let mut breaks = Box::pin(tokio_stream::iter(
+      breaks.map(super::reconciliation::Break::from),
     ));
 
     let mut serializer = csv_async::AsyncSerializer::from_writer(&mut self.0);
-    while let Some(account_report) = report.next().await {
-      serializer.serialize(account_report).await?;
+    while let Some(reconciliation_break) = breaks.next().await {
+      serializer.serialize(reconciliation_break).await?;
+    }
+    Ok(())
+  }
+}
+
+/// Interface for a transactions history writer
+#[async_trait]
+pub trait TransactionsHistoryWriter: Send {
+  /// Write the recorded transactions provided by the [`Iterator`] and return whether the operation was successful or not.
+  async fn write_transactions_history<'a, T>(&'a mut self, transactions: T) -> Result<()>
+  where
+    T: Iterator<Item = TransactionInfo> + Send + 'a;
+}
+
+/// An implementation of [`TransactionsHistoryWriter`] for the CSV format.
+pub struct CsvTransactionsHistoryWriter<W>(W);
+
+impl<W> CsvTransactionsHistoryWriter<W>
+where
+  W: AsyncWrite + Unpin + Send + Sync,
+{
+  pub fn new(writer: W) -> Self {
+    Self(writer)
+  }
+}
+
+#[async_trait]
+impl<W> TransactionsHistoryWriter for CsvTransactionsHistoryWriter<W>
+where
+  W: AsyncWrite + Unpin + Send + Sync,
+{
+  async fn write_transactions_history<'a, T>(&'a mut self, transactions: T) -> Result<()>
+  where
+    T: Iterator<Item = TransactionInfo> + Send + 'a,
+  {
+    let mut serializer = csv_async::AsyncSerializer::from_writer(&mut self.0);
+    let mut count = 0usize;
+    for transaction in transactions.map(super::account::TransactionEntry::from) {
+      serializer.serialize(transaction).await?;
+      count += 1;
+    }
+    serializer.flush().await?;
+    tracing::info!(transactions = count, "Transactions history written");
+    Ok(())
+  }
+}
+
+/// Interface for a writer of the open disputes report, with the accounts that have open disputes, how many and the funds they hold.
+#[async_trait]
+pub trait OpenDisputesWriter: Send {
+  /// Write the accounts provided by the [`Iterator`], which are expected to have open disputes,
+  /// and return whether the operation was successful or not.
+  async fn write_open_disputes<'a, T>(&'a mut self, accounts: T) -> Result<()>
+  where
+    T: Iterator<Item = AccountReport> + Send + 'a;
+}
+
+/// An implementation of [`OpenDisputesWriter`] for the CSV format, with the `client, open_disputes, held, locked` columns.
+pub struct CsvOpenDisputesWriter<W>(W);
+
+impl<W> CsvOpenDisputesWriter<W>
+where
+  W: AsyncWrite + Unpin + Send + Sync,
+{
+  pub fn new(writer: W) -> Self {
+    Self(writer)
+  }
+}
+
+#[async_trait]
+impl<W> OpenDisputesWriter for CsvOpenDisputesWriter<W>
+where
+  W: AsyncWrite + Unpin + Send + Sync,
+{
+  async fn write_open_disputes<'a, T>(&'a mut self, accounts: T) -> Result<()>
+  where
+    T: Iterator<Item = AccountReport> + Send + 'a,
+  {
+    let mut serializer = csv_async::AsyncSerializer::from_writer(&mut self.0);
+    let mut count = 0usize;
+    for account in accounts.map(super::account::OpenDisputesEntry::from) {
+      serializer.serialize(account).await?;
+      count += 1;
+    }
+    serializer.flush().await?;
+    tracing::info!(accounts = count, "Open disputes report written");
+    Ok(())
+  }
+}
+
+/// Interface to write the funds of the sub-accounts of the clients, addressed as `client:sub`.
+#[async_trait]
+pub trait SubAccountsWriter: Send {
+  /// Write the sub-accounts of the accounts provided by the [`Iterator`],
+  /// and return whether the operation was successful or not.
+  async fn write_sub_accounts<'a, T>(&'a mut self, accounts: T) -> Result<()>
+  where
+    T: Iterator<Item = AccountReport> + Send + 'a;
+}
+
+/// An implementation of [`SubAccountsWriter`] for the CSV format, with the `account, available, held, total, locked` columns,
+/// where the `account` is addressed as `client:sub`.
+pub struct CsvSubAccountsWriter<W>(W);
+
+impl<W> CsvSubAccountsWriter<W>
+where
+  W: AsyncWrite + Unpin + Send + Sync,
+{
+  pub fn new(writer: W) -> Self {
+    Self(writer)
+  }
+}
+
+#[async_trait]
+impl<W> SubAccountsWriter for CsvSubAccountsWriter<W>
+where
+  W: AsyncWrite + Unpin + Send + Sync,
+{
+  async fn write_sub_accounts<'a, T>(&'a mut self, accounts: T) -> Result<()>
+  where
+    T: Iterator<Item = AccountReport> + Send + 'a,
+  {
+    let mut serializer = csv_async::AsyncSerializer::from_writer(&mut self.0);
+    let mut count = 0usize;
+    for sub_account in accounts.flat_map(super::account::SubAccountEntry::from_account_report) {
+      serializer.serialize(sub_account).await?;
+      count += 1;
     }
+    serializer.flush().await?;
+    tracing::info!(sub_accounts = count, "Sub-accounts report written");
     Ok(())
   }
 }
@@ -50,11 +465,17 @@ where
 #[cfg(test)]
 mod tests {
 
+  use indoc::indoc;
   use rust_decimal_macros::dec;
   use std::io::Cursor;
   use std::iter;
 
   use super::*;
+  use crate::io::{Baseline, ClientMetadata, MetadataField};
+  use crate::payments::{
+    BreakKind, EngineConfig, InMemoryPaymentsEngine, PaymentsEngine, SyncPaymentsEngine,
+    Transaction,
+  };
 
   #[tokio::test]
   async fn write_accounts_report_fails() {
@@ -68,9 +489,89 @@ mod tests {
     ]
     .into_iter();
 
+    let result = writer
+      .write_accounts_report(AccountsReportStream::iter(report))
+      .await;
+
+    assert!(result.is_err());
+  }
+
+  #[tokio::test]
+  async fn write_accounts_report_stops_at_stream_error() {
+    let mut buffer = Vec::<u8>::with_capacity(1024);
+    let mut writer = NdjsonAccountsReportWriter::new(&mut buffer);
+
+    let report = tokio_stream::iter(vec![
+      Ok(AccountReport::new(1, dec!(100), dec!(10), dec!(110), false)),
+      Err(PaymentsEngineError::Database(
+        "connection closed".to_string(),
+      )),
+      Ok(AccountReport::new(2, dec!(90), dec!(-10), dec!(80), true)),
+    ]);
+
     let result = writer.write_accounts_report(report).await;
 
     assert!(result.is_err());
+    assert_eq!(
+      String::from_utf8_lossy(buffer.as_slice()),
+      "{\"client\":1,\"available\":\"100\",\"held\":\"10\",\"total\":\"110\",\"locked\":false}\n"
+    );
+  }
+
+  #[tokio::test]
+  async fn write_transactions_history_success() {
+    let mut engine = InMemoryPaymentsEngine::with_config(EngineConfig {
+      deterministic: true,
+      ..EngineConfig::default()
+    });
+    for transaction in [
+      Transaction::Deposit {
+        client_id: 2,
+        transaction_id: 201,
+        amount: dec!(90.12341),
+        timestamp: None,
+        sub_account: 0,
+      },
+      Transaction::Deposit {
+        client_id: 1,
+        transaction_id: 101,
+        amount: dec!(100),
+        timestamp: None,
+        sub_account: 0,
+      },
+      Transaction::Withdrawal {
+        client_id: 2,
+        transaction_id: 202,
+        amount: dec!(10),
+        timestamp: None,
+        sub_account: 0,
+      },
+      Transaction::Dispute {
+        client_id: 1,
+        transaction_id: 101,
+        timestamp: None,
+      },
+    ] {
+      engine.process_sync(transaction).unwrap();
+    }
+
+    let mut buffer = Vec::<u8>::with_capacity(1024);
+    let mut writer = CsvTransactionsHistoryWriter::new(&mut buffer);
+
+    let result = writer
+      .write_transactions_history(engine.transactions_report().unwrap())
+      .await;
+
+    assert!(result.is_ok());
+    assert_eq!(
+      String::from_utf8_lossy(buffer.as_slice()),
+      indoc! {"
+        client,tx,type,amount,state,in_dispute,disputed_at
+        1,101,deposit,100,disputed,true,0
+        2,201,deposit,90.1234,recorded,false,
+        2,202,withdrawal,10,recorded,false,
+      "}
+    );
   }
 
   #[tokio::test]
@@ -78,7 +579,9 @@ mod tests {
     let mut buffer = Vec::<u8>::with_capacity(1024);
     let mut writer = CsvAccountsReportWriter::new(&mut buffer);
 
-    let result = writer.write_accounts_report(iter::empty()).await;
+    let result = writer
+      .write_accounts_report(AccountsReportStream::iter(iter::empty()))
+      .await;
 
     assert!(result.is_ok());
     assert_eq!(String::from_utf8_lossy(buffer.as_slice()), "".to_string())
@@ -95,7 +598,9 @@ mod tests {
     ]
     .into_iter();
 
-    let result = writer.write_accounts_report(report).await;
+    let result = writer
+      .write_accounts_report(AccountsReportStream::iter(report))
+      .await;
 
     assert!(result.is_ok());
     assert_eq!(
@@ -103,4 +608,351 @@ mod tests {
       "client,available,held,total,locked\n1,100,10,110,false\n2,90,-10,80,true\n".to_string()
     )
   }
+
+  #[tokio::test]
+  async fn write_accounts_views_success() {
+    let mut engine = InMemoryPaymentsEngine::with_config(EngineConfig {
+      deterministic: true,
+      ..EngineConfig::default()
+    });
+    engine
+      .process_sync(Transaction::Deposit {
+        client_id: 2,
+        transaction_id: 201,
+        amount: dec!(90.12341),
+        timestamp: None,
+        sub_account: 0,
+      })
+      .unwrap();
+    engine
+      .process_sync(Transaction::Deposit {
+        client_id: 1,
+        transaction_id: 101,
+        amount: dec!(100),
+        timestamp: None,
+        sub_account: 0,
+      })
+      .unwrap();
+    engine
+      .process_sync(Transaction::Dispute {
+        client_id: 1,
+        transaction_id: 101,
+        timestamp: None,
+      })
+      .unwrap();
+
+    let mut buffer = Vec::<u8>::with_capacity(1024);
+    let mut writer = CsvAccountsReportWriter::new(&mut buffer);
+    let result = writer
+      .write_accounts_views(engine.accounts_report_ref())
+      .await;
+
+    assert!(result.is_ok());
+    assert_eq!(
+      String::from_utf8_lossy(buffer.as_slice()),
+      indoc! { "
+        client,available,held,total,locked
+        1,0,100,100,false
+        2,90.1234,0,90.1234,false
+      " }
+    );
+
+    let mut buffer = Vec::<u8>::with_capacity(1024);
+    let mut writer = CsvAccountsReportWriter::with_schema(&mut buffer, ReportSchema::V2);
+    let result = writer
+      .write_accounts_views(engine.accounts_report_ref())
+      .await;
+
+    assert!(result.is_ok());
+    assert_eq!(
+      String::from_utf8_lossy(buffer.as_slice()),
+      indoc! { "
+        schema_version,client,available,held,total,locked,status,open_disputes,charged_back_total
+        2,1,0,100,100,false,active,1,0
+        2,2,90.1234,0,90.1234,false,active,0,0
+      " }
+    );
+  }
+
+  #[tokio::test]
+  async fn write_accounts_report_from_spawned_task() {
+    let report = vec![AccountReport::new(1, dec!(100), dec!(10), dec!(110), false)];
+
+    let buffer = tokio::spawn(async move {
+      let mut buffer = Vec::<u8>::with_capacity(1024);
+      CsvAccountsReportWriter::new(&mut buffer)
+        .write_accounts_report(AccountsReportStream::iter(report))
+        .await
+        .map(|_| buffer)
+    })
+    .await
+    .unwrap()
+    .unwrap();
+
+    assert_eq!(
+      String::from_utf8_lossy(buffer.as_slice()),
+      "client,available,held,total,locked\n1,100,10,110,false\n".to_string()
+    )
+  }
+
+  #[tokio::test]
+  async fn write_accounts_report_v2_success() {
+    let mut buffer = Vec::<u8>::with_capacity(1024);
+    let mut writer = CsvAccountsReportWriter::with_schema(&mut buffer, ReportSchema::V2);
+
+    let report = vec![
+      AccountReport::new(1, dec!(100), dec!(10), dec!(110), false).with_disputes(1, dec!(0)),
+      AccountReport::new(2, dec!(90), dec!(0), dec!(90), true).with_disputes(0, dec!(20)),
+    ]
+    .into_iter();
+
+    let result = writer
+      .write_accounts_report(AccountsReportStream::iter(report))
+      .await;
+
+    assert!(result.is_ok());
+    assert_eq!(
+      String::from_utf8_lossy(buffer.as_slice()),
+      indoc! { "
+        schema_version,client,available,held,total,locked,status,open_disputes,charged_back_total
+        2,1,100,10,110,false,active,1,0
+        2,2,90,0,90,true,locked,0,20
+      " }
+      .to_string()
+    )
+  }
+
+  #[tokio::test]
+  async fn write_accounts_report_extended_success() {
+    let mut buffer = Vec::<u8>::with_capacity(1024);
+    let mut writer = CsvAccountsReportWriter::with_schema(&mut buffer, ReportSchema::Extended)
+      .with_currency(Some("USD".to_string()));
+
+    let report = vec![
+      AccountReport::new(1, dec!(75), dec!(25), dec!(100), false).with_fees(dec!(2.5)),
+      AccountReport::new(2, dec!(90), dec!(0), dec!(90), true).with_disputes(0, dec!(20)),
+    ]
+    .into_iter();
+
+    let result = writer
+      .write_accounts_report(AccountsReportStream::iter(report))
+      .await;
+
+    assert!(result.is_ok());
+    assert_eq!(
+      String::from_utf8_lossy(buffer.as_slice()),
+      indoc! { "
+        client,available,held,total,locked,status,currency,fees,risk_score
+        1,75,25,100,false,active,USD,2.5,0.25
+        2,90,0,90,true,locked,USD,0,1
+      " }
+      .to_string()
+    )
+  }
+
+  #[tokio::test]
+  async fn write_accounts_report_v2_with_metadata() {
+    let metadata = ClientMetadata::load(
+      "client,name,tier\n1,Alice,gold\n".as_bytes(),
+      vec![MetadataField::Name, MetadataField::Tier],
+    )
+    .await
+    .unwrap();
+
+    let mut buffer = Vec::<u8>::with_capacity(1024);
+    let mut writer = CsvAccountsReportWriter::with_schema(&mut buffer, ReportSchema::V2)
+      .with_metadata(Some(Arc::new(metadata)));
+
+    let report = vec![
+      AccountReport::new(1, dec!(100), dec!(10), dec!(110), false).with_disputes(1, dec!(0)),
+      AccountReport::new(2, dec!(90), dec!(0), dec!(90), true).with_disputes(0, dec!(20)),
+    ]
+    .into_iter();
+
+    let result = writer
+      .write_accounts_report(AccountsReportStream::iter(report))
+      .await;
+
+    assert!(result.is_ok());
+    assert_eq!(
+      String::from_utf8_lossy(buffer.as_slice()),
+      indoc! { "
+        schema_version,client,available,held,total,locked,status,open_disputes,charged_back_total,name,tier
+        2,1,100,10,110,false,active,1,0,Alice,gold
+        2,2,90,0,90,true,locked,0,20,,
+      " }
+      .to_string()
+    )
+  }
+
+  #[tokio::test]
+  async fn write_accounts_report_with_baseline() {
+    let mut payments_engine = InMemoryPaymentsEngine::new();
+    payments_engine
+      .process(Transaction::Deposit {
+        client_id: 1,
+        transaction_id: 1,
+        amount: dec!(50),
+        timestamp: None,
+        sub_account: 0,
+      })
+      .await
+      .unwrap();
+    let baseline = Baseline::from_snapshot(payments_engine.snapshot().unwrap()).unwrap();
+
+    let mut buffer = Vec::<u8>::with_capacity(1024);
+    let mut writer =
+      CsvAccountsReportWriter::new(&mut buffer).with_baseline(Some(Arc::new(baseline)));
+
+    let report = vec![
+      AccountReport::new(1, dec!(100), dec!(10), dec!(110), false),
+      AccountReport::new(2, dec!(90), dec!(0), dec!(90), true),
+    ]
+    .into_iter();
+
+    let result = writer
+      .write_accounts_report(AccountsReportStream::iter(report))
+      .await;
+
+    assert!(result.is_ok());
+    assert_eq!(
+      String::from_utf8_lossy(buffer.as_slice()),
+      indoc! { "
+        client,available,held,total,locked,available_change,held_change,newly_locked
+        1,100,10,110,false,50,10,false
+        2,90,0,90,true,90,0,true
+      " }
+      .to_string()
+    )
+  }
+
+  #[tokio::test]
+  async fn write_ndjson_accounts_report_success() {
+    let mut buffer = Vec::<u8>::with_capacity(1024);
+    let mut writer = NdjsonAccountsReportWriter::new(&mut buffer);
+
+    let report = vec![
+      AccountReport::new(1, dec!(100), dec!(10), dec!(110), false),
+      AccountReport::new(2, dec!(90.5), dec!(-10), dec!(80.5), true),
+    ]
+    .into_iter();
+
+    let result = writer
+      .write_accounts_report(AccountsReportStream::iter(report))
+      .await;
+
+    assert!(result.is_ok());
+    assert_eq!(
+      String::from_utf8_lossy(buffer.as_slice()),
+      "{\"client\":1,\"available\":\"100\",\"held\":\"10\",\"total\":\"110\",\"locked\":false}\n\
+       {\"client\":2,\"available\":\"90.5\",\"held\":\"-10\",\"total\":\"80.5\",\"locked\":true}\n"
+        .to_string()
+    )
+  }
+
+  #[tokio::test]
+  async fn write_tee_accounts_report_success() {
+    let mut csv_buffer = Vec::<u8>::with_capacity(1024);
+    let mut ndjson_buffer = Vec::<u8>::with_capacity(1024);
+    let mut writer = TeeAccountsReportWriter::new(
+      CsvAccountsReportWriter::new(&mut csv_buffer),
+      Some(NdjsonAccountsReportWriter::new(&mut ndjson_buffer)),
+    );
+
+    let report = vec![AccountReport::new(1, dec!(100), dec!(10), dec!(110), false)].into_iter();
+
+    let result = writer
+      .write_accounts_report(AccountsReportStream::iter(report))
+      .await;
+
+    assert!(result.is_ok());
+    assert_eq!(
+      String::from_utf8_lossy(csv_buffer.as_slice()),
+      "client,available,held,total,locked\n1,100,10,110,false\n".to_string()
+    );
+    assert_eq!(
+      String::from_utf8_lossy(ndjson_buffer.as_slice()),
+      "{\"client\":1,\"available\":\"100\",\"held\":\"10\",\"total\":\"110\",\"locked\":false}\n"
+        .to_string()
+    );
+  }
+
+  #[tokio::test]
+  async fn write_tee_accounts_report_larger_than_the_capacity() {
+    let mut csv_buffer = Vec::<u8>::new();
+    let mut ndjson_buffer = Vec::<u8>::new();
+    let mut writer = TeeAccountsReportWriter::new(
+      CsvAccountsReportWriter::new(&mut csv_buffer),
+      Some(NdjsonAccountsReportWriter::new(&mut ndjson_buffer)),
+    );
+
+    let accounts = 3 * TEE_CAPACITY as u16;
+    let report =
+      (1..=accounts).map(|client| AccountReport::new(client, dec!(1), dec!(0), dec!(1), false));
+
+    let result = writer
+      .write_accounts_report(AccountsReportStream::iter(report))
+      .await;
+
+    assert!(result.is_ok());
+    assert_eq!(
+      csv_buffer.split(|byte| *byte == b'\n').count(),
+      accounts as usize + 2
+    );
+    assert_eq!(
+      ndjson_buffer.split(|byte| *byte == b'\n').count(),
+      accounts as usize + 1
+    );
+  }
+
+  #[tokio::test]
+  async fn write_tee_accounts_report_failure() {
+    let mut csv_buffer = Vec::<u8>::new();
+    let mut ndjson_buffer = Vec::<u8>::new();
+    let mut writer = TeeAccountsReportWriter::new(
+      CsvAccountsReportWriter::new(&mut csv_buffer),
+      Some(NdjsonAccountsReportWriter::new(&mut ndjson_buffer)),
+    );
+
+    let report = tokio_stream::iter(vec![
+      Ok(AccountReport::new(1, dec!(100), dec!(10), dec!(110), false)),
+      Err(PaymentsEngineError::ClientNotFound(2)),
+      Ok(AccountReport::new(3, dec!(100), dec!(10), dec!(110), false)),
+    ]);
+
+    let result = writer.write_accounts_report(report).await;
+
+    assert!(result.is_err());
+    assert!(!String::from_utf8_lossy(ndjson_buffer.as_slice()).contains("\"client\":3"));
+  }
+
+  #[tokio::test]
+  async fn write_breaks_report_success() {
+    let mut buffer = Vec::<u8>::with_capacity(1024);
+    let mut writer = CsvBreaksReportWriter::new(&mut buffer);
+
+    let breaks = vec![
+      Break::new(
+        1,
+        BreakKind::AmountMismatch,
+        Some(dec!(10)),
+        Some(dec!(7.5)),
+      ),
+      Break::new(2, BreakKind::MissingInExternal, Some(dec!(20)), None),
+      Break::new(3, BreakKind::MissingInEngine, None, Some(dec!(30))),
+    ]
+    .into_iter();
+
+    let result = writer.write_breaks_report(breaks).await;
+
+    assert!(result.is_ok());
+    assert_eq!(
+      String::from_utf8_lossy(buffer.as_slice()),
+      "client,kind,engine_total,external_total,difference\n\
+       1,amount_mismatch,10,7.5,2.5\n\
+       2,missing_in_external,20,,\n\
+       3,missing_in_engine,,30,\n"
+        .to_string()
+    )
+  }
 }
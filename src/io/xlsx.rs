@@ -0,0 +1,43 @@
+use std::path::Path;
+
+use anyhow::Result;
+use calamine::{open_workbook_auto, DataType, Range, Reader};
+use csv_async::StringRecord;
+use tokio_stream::Stream;
+
+use super::pipeline::{DecodedTransactionsReader, RecordSource, SourceItem};
+use super::reader::CsvDecoder;
+
+/// Implementation of [`TransactionsReader`](super::TransactionsReader) for spreadsheets (xlsx, xls, ods).
+///
+/// Transactions are read from the first sheet, which must have the same columns than the CSV format,
+/// starting with a header row. Cells are interpreted the same way than the CSV fields.
+pub type XlsxTransactionsReader = DecodedTransactionsReader<SpreadsheetSource, CsvDecoder>;
+
+impl XlsxTransactionsReader {
+  /// Open the spreadsheet and load its first sheet.
+  /// Spreadsheets are not streamed, so the whole sheet is loaded into memory.
+  pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+    let mut workbook = open_workbook_auto(path)?;
+    let range = workbook
+      .worksheet_range_at(0)
+      .ok_or_else(|| anyhow::anyhow!("The spreadsheet has no sheets"))??;
+    Ok(Self::from_parts(SpreadsheetSource(range), CsvDecoder))
+  }
+}
+
+/// A [`RecordSource`] of the rows of the first sheet of a spreadsheet after its header, with the number of every row as its line.
+pub struct SpreadsheetSource(Range<DataType>);
+
+impl RecordSource for SpreadsheetSource {
+  type Item = StringRecord;
+
+  fn items<'a>(&'a mut self) -> Box<dyn Stream<Item = SourceItem<Self::Item>> + Unpin + 'a> {
+    Box::new(tokio_stream::iter(self.0.rows().enumerate().skip(1).map(
+      |(index, row)| SourceItem {
+        line: Some(index as u64 + 1),
+        item: Ok(row.iter().map(|cell| cell.to_string()).collect()),
+      },
+    )))
+  }
+}
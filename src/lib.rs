@@ -0,0 +1,20 @@
+//! A toy payments engine that processes deposits, withdrawals, disputes, resolves and chargebacks,
+//! and reports the resulting state of the client accounts.
+//!
+//! The engine can be embedded into other services through its three modules:
+//!
+//! - [`payments`] contains the domain logic to process transactions (see [`payments::InMemoryPaymentsEngine`]).
+//! - [`io`] contains the readers of transactions and the writers of reports for the supported formats.
+//! - [`processors`] glue together the readers, the engine and the writers (see [`processors::simple::run`]),
+//!   and open the engine selected at runtime (see [`processors::engine::with_engine`]).
+//!
+//! Only the synchronous engine is built without features (see [`payments::SyncPaymentsEngine`]).
+//! The `async` feature adds the [`payments::PaymentsEngine`] and its middlewares, `csv` adds the [`io`] module,
+//! and `processors` adds the [`processors`] module. The `cli` feature (enabled by default) builds the binary with all of them.
+//!
+
+#[cfg(feature = "csv")]
+pub mod io;
+pub mod payments;
+#[cfg(feature = "processors")]
+pub mod processors;
@@ -1,29 +1,1775 @@
-mod io;
-mod payments;
-mod processors;
+mod cli;
+mod config;
+mod resources;
+
+use std::io::Write;
+use std::str::FromStr;
+use std::sync::Arc;
 
 use anyhow::Result;
-use tokio::io::AsyncRead;
+use rust_decimal::Decimal;
+use tokio::io::{AsyncRead, AsyncWrite};
+
+use toy_payments_engine::io::{
+  archive_input, fingerprint_file, AccountsReportWriter, AmountParser, Baseline,
+  ChunkedCsvTransactionsReader, ClientIdRemapping, ClientMetadata, CsvAccountsReportWriter,
+  CsvBalancesReader, CsvBreaksReportWriter, CsvDuplicatesSink, CsvErrorSink, CsvOpenDisputesWriter,
+  CsvStatementsWriter, CsvSubAccountsWriter, CsvTransactionsHistoryWriter, CsvTransactionsReader,
+  DigestExchange, ErrorSink, FileIdempotencyStore, IdempotencyKey, IdempotencyStore,
+  IdempotentTransactionsReader, InputHistory, MetadataField, NdjsonAccountsReportWriter,
+  NdjsonStatementsWriter, NdjsonTransactionsReader, Normalization, NormalizedTransactionsReader,
+  ProgressFile, QuarantineSink, RemappedTransactionsReader, ReportSchema,
+  SampledTransactionsReader, SortedAccountsReportWriter, SpillingAccountsReportWriter,
+  TeeAccountsReportWriter, TransactionsGenerator, TransactionsReader,
+};
+use toy_payments_engine::payments::{
+  AccountFilter, ChannelEventListener, ChargebackFee, DormancyPolicy, DuplicatePolicy,
+  EngineConfig, EngineEvent, FilteredPaymentsEngine, InMemoryPaymentsEngine,
+  InvariantCheckingEngine, LimitsPolicy, ListeningPaymentsEngine, LockedAccountDisputePolicy,
+  PaymentsEngine, ReportOptions, ReportSortKey, SyntheticDisputePolicy, TransactionStore,
+  UnlockHeldFundsPolicy, ZeroAmountPolicy,
+};
+use toy_payments_engine::processors;
+use toy_payments_engine::processors::engine::{EngineBackend, EngineOptions, OpenedEngine};
+
+use cli::{Cli, Command, Engine, LogFormat, ReportFormat, Settings};
+use resources::Resources;
+
+/// Environment variable with the directory where to dump the accounts report on `SIGUSR1`.
+const DUMPS_DIR_VAR: &str = "DUMPS_DIR";
+
+/// Environment variables with the directory shared by the replicas to exchange the digests of their state,
+/// the name of this replica, and every how many records the digests are exchanged.
+const DIGESTS_DIR_VAR: &str = "DIGESTS_DIR";
+const REPLICA_NAME_VAR: &str = "REPLICA_NAME";
+const DIGEST_INTERVAL_VAR: &str = "DIGEST_INTERVAL";
+const DEFAULT_DIGEST_INTERVAL: u64 = 10_000;
+
+/// Environment variable with the path of the file where to write the rejected records.
+const ERRORS_FILE_VAR: &str = "ERRORS_FILE";
+
+/// Environment variables with the path of the file where to put the unreadable records in quarantine,
+/// and the maximum number of them before aborting the processing.
+const QUARANTINE_FILE_VAR: &str = "QUARANTINE_FILE";
+const MAX_QUARANTINED_VAR: &str = "MAX_QUARANTINED";
+
+/// Environment variable with the path of the file where to write the duplicated transactions.
+const DUPLICATES_FILE_VAR: &str = "DUPLICATES_FILE";
+
+/// Environment variable with the path of the file where to write the events of the engine.
+const EVENTS_FILE_VAR: &str = "EVENTS_FILE";
+
+/// Environment variable that enables checking the engine invariants after every transaction.
+const CHECK_INVARIANTS_VAR: &str = "CHECK_INVARIANTS";
+
+/// Environment variable with the path of the write-ahead log where to append the accepted transactions.
+const WAL_FILE_VAR: &str = "WAL_FILE";
+
+/// Environment variable with the number of transactions whose writes are coalesced before flushing them into a database,
+/// which needs the `WAL_FILE` to recover the transactions not flushed yet.
+#[cfg(any(feature = "sqlite", feature = "postgres"))]
+const WRITE_BATCH_SIZE_VAR: &str = "WRITE_BATCH_SIZE";
+
+/// Environment variable with the path of a Unix domain socket where to stream a copy of the accounts report.
+const REPORT_SOCKET_VAR: &str = "REPORT_SOCKET";
+
+/// Environment variable with the path of an embedded database where to export a copy of the accounts report.
+#[cfg(feature = "kv")]
+const REPORT_KV_DB_VAR: &str = "REPORT_KV_DB";
+
+/// Environment variables with the directory where to spill the settled transactions of the accounts,
+/// and the maximum number of them kept in memory (one million by default).
+#[cfg(feature = "kv")]
+const TRANSACTIONS_SPILL_DIR_VAR: &str = "TRANSACTIONS_SPILL_DIR";
+#[cfg(feature = "kv")]
+const TRANSACTIONS_MEMORY_VAR: &str = "TRANSACTIONS_MEMORY";
+
+/// Environment variable with the path of the history of the inputs already processed.
+const INPUT_HISTORY_VAR: &str = "INPUT_HISTORY";
+
+/// Environment variable with the directory where to archive a copy of the raw input before processing it.
+const ARCHIVE_DIR_VAR: &str = "ARCHIVE_DIR";
+
+/// Environment variable to only warn (`warn`) instead of refusing to process inputs already in the history.
+const DUPLICATE_INPUT_VAR: &str = "DUPLICATE_INPUT";
+
+/// Environment variable with the version of the schema of the accounts report (`v1`, `v2` or `extended`).
+const REPORT_SCHEMA_VAR: &str = "REPORT_SCHEMA";
+
+/// Environment variable with the path where to write a copy of the accounts report with the extended schema.
+const EXTENDED_REPORT_FILE_VAR: &str = "EXTENDED_REPORT_FILE";
+
+/// Environment variable with the currency of the funds reported by the extended schema.
+const REPORT_CURRENCY_VAR: &str = "REPORT_CURRENCY";
+
+/// Environment variable with the maximum number of accounts of the report to keep in memory before spilling them.
+const REPORT_BUFFER_ACCOUNTS_VAR: &str = "REPORT_BUFFER_ACCOUNTS";
+
+/// Environment variable with the order of the accounts report (`client` or `total`, optionally followed by `:desc`).
+const REPORT_SORT_VAR: &str = "REPORT_SORT";
+
+/// Environment variables with the path of the client IDs remapping file, and the tag of the source of the input.
+const CLIENT_ID_REMAPPING_VAR: &str = "CLIENT_ID_REMAPPING";
+const SOURCE_TAG_VAR: &str = "SOURCE_TAG";
+
+/// Environment variables with the path of the file with the keys of the records already ingested,
+/// and how the records are identified: by their content (`record`, by default) or their line in the input (`position`).
+const IDEMPOTENCY_STORE_VAR: &str = "IDEMPOTENCY_STORE";
+const IDEMPOTENCY_KEY_VAR: &str = "IDEMPOTENCY_KEY";
+
+/// Environment variables with the path of the client metadata file, and the comma separated fields to join from it.
+const CLIENT_METADATA_VAR: &str = "CLIENT_METADATA";
+const CLIENT_METADATA_FIELDS_VAR: &str = "CLIENT_METADATA_FIELDS";
+
+/// Environment variable with the path of the snapshot whose accounts the report is compared with.
+const BASELINE_SNAPSHOT_VAR: &str = "BASELINE_SNAPSHOT";
+
+/// Environment variables with the number of decimal places to round the amounts to, and the comma separated ranges
+/// of client IDs (like `9000-9999`) whose transactions are dropped, before they reach the engine (see [`Normalization`]).
+const NORMALIZE_PRECISION_VAR: &str = "NORMALIZE_PRECISION";
+const DROP_CLIENTS_VAR: &str = "DROP_CLIENTS";
+
+/// Environment variable with the number of partitions to process the transactions in parallel.
+const PARTITIONS_VAR: &str = "PARTITIONS";
+
+/// Environment variable with the size in bytes of the chunks to parse the input CSV in parallel.
+const PARSE_CHUNK_SIZE_VAR: &str = "PARSE_CHUNK_SIZE";
+
+/// Environment variables with the path of the API keys allowed to use the services, and of the log to audit them.
+#[cfg(feature = "http")]
+const API_KEYS_VAR: &str = "API_KEYS";
+#[cfg(feature = "http")]
+const AUDIT_LOG_VAR: &str = "AUDIT_LOG";
+
+/// Environment variables with the paths of the PEM encoded TLS certificate chain and private key of the services.
+#[cfg(feature = "http")]
+const TLS_CERT_VAR: &str = "TLS_CERT";
+#[cfg(feature = "http")]
+const TLS_KEY_VAR: &str = "TLS_KEY";
+
+/// Environment variable that enables exposing the metrics of the services in `/metrics`.
+#[cfg(feature = "http")]
+const METRICS_VAR: &str = "METRICS";
+
+/// Environment variable with the memory limit in bytes (or as a percentage of the memory limit of the container, like `80%`)
+/// past which the uploads of transactions are rejected with `429 Too Many Requests`.
+#[cfg(feature = "http")]
+const ADMISSION_MEMORY_LIMIT_VAR: &str = "ADMISSION_MEMORY_LIMIT";
+
+/// Environment variable that enables recording the timeline of the clients, exposed in `/accounts/{id}/timeline`.
+#[cfg(feature = "http")]
+const TIMELINE_VAR: &str = "TIMELINE";
+
+/// Environment variables with the directory where a service with `--engine persistent` snapshots its state,
+/// every how many minutes and transactions, and how many snapshots are kept.
+#[cfg(any(feature = "http", feature = "grpc"))]
+const SNAPSHOTS_DIR_VAR: &str = "SNAPSHOTS_DIR";
+#[cfg(any(feature = "http", feature = "grpc"))]
+const SNAPSHOT_INTERVAL_VAR: &str = "SNAPSHOT_INTERVAL";
+#[cfg(any(feature = "http", feature = "grpc"))]
+const SNAPSHOT_TRANSACTIONS_VAR: &str = "SNAPSHOT_TRANSACTIONS";
+#[cfg(any(feature = "http", feature = "grpc"))]
+const SNAPSHOTS_KEPT_VAR: &str = "SNAPSHOTS_KEPT";
+#[cfg(any(feature = "http", feature = "grpc"))]
+const DEFAULT_SNAPSHOT_INTERVAL: u64 = 5;
+
+/// Environment variable with the consumer group of the transactions read from a Kafka topic.
+#[cfg(feature = "kafka")]
+const KAFKA_GROUP_VAR: &str = "KAFKA_GROUP";
+#[cfg(feature = "kafka")]
+const DEFAULT_KAFKA_GROUP: &str = "toy-payments-engine";
+
+/// Environment variable with the level of the logs: `error`, `warn` (by default), `info`, `debug` or `trace`.
+const LOG_LEVEL_VAR: &str = "LOG_LEVEL";
+
+/// Environment variable with how to parse the amounts: `lenient` or `strict` (see [`AmountParser`]).
+const AMOUNTS_VAR: &str = "AMOUNTS";
+
+/// Environment variables used to configure the payments engine (see [`EngineConfig`]).
+const MAX_OPEN_DISPUTES_VAR: &str = "MAX_OPEN_DISPUTES";
+const DETERMINISTIC_REPORT_VAR: &str = "DETERMINISTIC_REPORT";
+const LOCKED_ACCOUNT_DISPUTES_VAR: &str = "LOCKED_ACCOUNT_DISPUTES";
+const ZERO_AMOUNTS_VAR: &str = "ZERO_AMOUNTS";
+const CHARGEBACK_FEE_VAR: &str = "CHARGEBACK_FEE";
+const CHARGEBACK_FEE_POLICY_VAR: &str = "CHARGEBACK_FEE_POLICY";
+const UNLOCK_HELD_FUNDS_VAR: &str = "UNLOCK_HELD_FUNDS";
+const SYNTHETIC_DISPUTES_VAR: &str = "SYNTHETIC_DISPUTES";
+const DUPLICATE_POLICY_VAR: &str = "DUPLICATE_POLICY";
+const ESCROW_INTEREST_RATE_VAR: &str = "ESCROW_INTEREST_RATE";
+const EXPOSURE_THRESHOLD_VAR: &str = "EXPOSURE_THRESHOLD";
+const AUTHORIZATION_EXPIRY_VAR: &str = "AUTHORIZATION_EXPIRY";
+const DISPUTE_WINDOW_DAYS_VAR: &str = "DISPUTE_WINDOW_DAYS";
+const DORMANCY_DAYS_VAR: &str = "DORMANCY_DAYS";
+const DORMANCY_POLICY_VAR: &str = "DORMANCY_POLICY";
+/// The TOML file with the withdrawal limits (see [`LimitsPolicy`]).
+const LIMITS_FILE_VAR: &str = "LIMITS_FILE";
+
+fn main() -> Result<()> {
+  let cli = Cli::parse()?;
+  init_logs(cli.log_format, &cli.settings)?;
+
+  let resources = get_resources(&cli);
+  if !matches!(cli.command, Command::Completions { .. }) {
+    tracing::info!(
+      cpus = resources.cpus,
+      memory = ?resources.memory,
+      "{}",
+      resources
+    );
+  }
+
+  tokio::runtime::Builder::new_multi_thread()
+    .worker_threads(resources.cpus)
+    .enable_all()
+    .build()?
+    .block_on(run(cli))
+}
+
+/// The resources available to the process, with as many CPUs as `--workers` when given,
+/// from which the sizes of what is kept in memory by default are derived.
+fn get_resources(cli: &Cli) -> Resources {
+  let mut resources = Resources::detect();
+  if let Some(workers) = cli.workers {
+    resources.cpus = workers.max(1);
+  }
+  resources
+}
+
+/// Write the logs into the stderr, as the stdout is used for the reports.
+fn init_logs(log_format: LogFormat, settings: &Settings) -> Result<()> {
+  let level = settings
+    .get(LOG_LEVEL_VAR)
+    .map(|level| tracing::Level::from_str(&level))
+    .transpose()
+    .map_err(|_| anyhow::anyhow!("Invalid {}", LOG_LEVEL_VAR))?
+    .unwrap_or(tracing::Level::WARN);
+
+  let subscriber = tracing_subscriber::fmt()
+    .with_max_level(level)
+    .with_writer(std::io::stderr);
+  match log_format {
+    LogFormat::Text => subscriber.try_init(),
+    LogFormat::Json => subscriber.json().try_init(),
+    LogFormat::Pretty => subscriber.pretty().try_init(),
+  }
+  .map_err(anyhow::Error::msg)
+}
+
+async fn run(cli: Cli) -> Result<()> {
+  let runs_engine = match cli.command {
+    Command::Process => true,
+    #[cfg(feature = "http")]
+    Command::Serve { .. } => true,
+    #[cfg(feature = "grpc")]
+    Command::ServeGrpc { .. } => true,
+    _ => false,
+  };
+  if cli.engine != Engine::Memory && !runs_engine {
+    anyhow::bail!("--engine can only be used to process or serve the transactions");
+  }
+  let reports_accounts = matches!(
+    cli.command,
+    Command::Process | Command::Disputes | Command::SubAccounts
+  );
+  if cli.filter != AccountFilter::default() && !reports_accounts {
+    anyhow::bail!(
+      "The account filters can only be used to process the transactions or report the open disputes or sub-accounts"
+    );
+  }
+
+  match &cli.command {
+    Command::Process => process(&cli).await,
+    Command::Reconcile {
+      balances,
+      tolerance,
+    } => reconcile(&cli, balances, *tolerance).await,
+    Command::History => history(&cli).await,
+    Command::Statements { json } => statements(&cli, *json).await,
+    Command::Repl => repl(&cli).await,
+    Command::Disputes => disputes(&cli).await,
+    Command::SubAccounts => sub_accounts(&cli).await,
+    Command::ExportBundle => export_bundle(&cli).await,
+    Command::Generate(config) => {
+      let out = get_report_async_write(cli.output.as_ref()).await?;
+      TransactionsGenerator::new(config.clone())
+        .write_csv(out)
+        .await
+    }
+    #[cfg(feature = "http")]
+    Command::Serve { address } => serve(&cli, address).await,
+    #[cfg(feature = "http")]
+    Command::ServeReport { address, snapshot } => serve_report(&cli, address, snapshot).await,
+    #[cfg(feature = "grpc")]
+    Command::ServeGrpc { address } => serve_grpc(&cli, address).await,
+    Command::Completions { shell } => cli::write_completions(shell, &mut std::io::stdout()),
+  }
+}
+
+/// Serve the payments engine through HTTP until the process is stopped.
+#[cfg(feature = "http")]
+async fn serve(cli: &Cli, address: &str) -> Result<()> {
+  let settings = &cli.settings;
+  let listener = tokio::net::TcpListener::bind(address).await?;
+  tracing::info!(address = %listener.local_addr()?, "Listening");
+  let engine_config = get_engine_config(cli)?;
+  let options = processors::http::ServeOptions::new()
+    .with_amount_parser(get_amount_parser(settings)?)
+    .with_report_schema(get_report_schema(settings)?)
+    .with_metadata(get_client_metadata(settings).await?)
+    .with_access_control(get_access_control(settings).await?)
+    .with_tls_acceptor(get_tls_acceptor(settings).await?)
+    .with_admission_control(get_admission_control(settings)?);
+  let engine_options =
+    get_engine_options(cli, engine_config)?.with_checkpoints(get_checkpoints(cli)?);
+  processors::engine::with_engine(engine_options, |payments_engine| {
+    serve_engine(listener, payments_engine.into_boxed(), options, settings)
+  })
+  .await
+}
+
+/// Serve the accounts report of a snapshot through HTTP until the process is stopped, rejecting any transaction.
+/// The snapshot is restored even when it was created under a different configuration, as no policy is applied.
+#[cfg(feature = "http")]
+async fn serve_report(cli: &Cli, address: &str, snapshot: &str) -> Result<()> {
+  let settings = &cli.settings;
+  let snapshot = tokio::fs::read(snapshot).await?;
+  let snapshot = toy_payments_engine::payments::Snapshot::read_from(snapshot.as_slice())?;
+  let mut payments_engine = InMemoryPaymentsEngine::with_config(get_engine_config(cli)?);
+  payments_engine.restore_with_config_change(snapshot)?;
+
+  let listener = tokio::net::TcpListener::bind(address).await?;
+  tracing::info!(address = %listener.local_addr()?, "Listening");
+  let options = processors::http::ServeOptions::new()
+    .with_report_schema(get_report_schema(settings)?)
+    .with_metadata(get_client_metadata(settings).await?)
+    .with_access_control(get_access_control(settings).await?)
+    .with_tls_acceptor(get_tls_acceptor(settings).await?)
+    .with_read_only(true);
+  serve_engine(listener, payments_engine, options, settings).await
+}
+
+#[cfg(feature = "http")]
+async fn serve_engine<P>(
+  listener: tokio::net::TcpListener,
+  payments_engine: P,
+  options: processors::http::ServeOptions,
+  settings: &Settings,
+) -> Result<()>
+where
+  P: PaymentsEngine + Send + 'static,
+{
+  if settings.enabled(TIMELINE_VAR) {
+    let timeline = Arc::new(std::sync::Mutex::new(
+      toy_payments_engine::payments::Statements::new(),
+    ));
+    let payments_engine = toy_payments_engine::payments::StatementsPaymentsEngine::new(
+      payments_engine,
+      timeline.clone(),
+    );
+    serve_metered(
+      listener,
+      payments_engine,
+      options.with_timeline(Some(timeline)),
+      settings,
+    )
+    .await
+  } else {
+    serve_metered(listener, payments_engine, options, settings).await
+  }
+}
+
+#[cfg(feature = "http")]
+async fn serve_metered<P>(
+  listener: tokio::net::TcpListener,
+  payments_engine: P,
+  options: processors::http::ServeOptions,
+  settings: &Settings,
+) -> Result<()>
+where
+  P: PaymentsEngine + Send + 'static,
+{
+  if settings.enabled(METRICS_VAR) {
+    let metrics = Arc::new(toy_payments_engine::payments::PrometheusMetrics::new());
+    let payments_engine =
+      toy_payments_engine::payments::MeteredPaymentsEngine::new(payments_engine, metrics.clone());
+    processors::http::serve(
+      listener,
+      payments_engine,
+      options.with_metrics(Some(metrics)),
+    )
+    .await
+  } else {
+    processors::http::serve(listener, payments_engine, options).await
+  }
+}
+
+/// Serve the payments engine through gRPC until the process is stopped.
+#[cfg(feature = "grpc")]
+async fn serve_grpc(cli: &Cli, address: &str) -> Result<()> {
+  let listener = tokio::net::TcpListener::bind(address).await?;
+  tracing::info!(address = %listener.local_addr()?, "Listening");
+  let engine_config = get_engine_config(cli)?;
+  let amount_parser = get_amount_parser(&cli.settings)?;
+  let engine_options =
+    get_engine_options(cli, engine_config)?.with_checkpoints(get_checkpoints(cli)?);
+  processors::engine::with_engine(engine_options, |payments_engine| {
+    let service = processors::grpc::PaymentsService::new(payments_engine.into_boxed())
+      .with_amount_parser(amount_parser);
+    processors::grpc::serve(listener, service)
+  })
+  .await
+}
+
+/// Load the API keys to authenticate the requests to the services, and open the audit log, if configured.
+/// The admission control of the uploads, when there is a memory limit for them.
+#[cfg(feature = "http")]
+fn get_admission_control(
+  settings: &Settings,
+) -> Result<Option<processors::http::AdmissionControl>> {
+  let memory_limit = match settings.get(ADMISSION_MEMORY_LIMIT_VAR) {
+    Some(limit) => match limit.strip_suffix('%') {
+      Some(percentage) => {
+        let percentage = percentage.trim().parse::<u64>()?;
+        let container_limit = Resources::detect().memory.ok_or_else(|| {
+          anyhow::anyhow!(
+            "{} can only be a percentage with a memory limit for the container",
+            ADMISSION_MEMORY_LIMIT_VAR
+          )
+        })?;
+        container_limit / 100 * percentage
+      }
+      None => limit.trim().parse::<u64>()?,
+    },
+    None => return Ok(None),
+  };
+  Ok(Some(processors::http::AdmissionControl::new(
+    memory_limit,
+    resources::memory_usage,
+  )))
+}
+
+#[cfg(feature = "http")]
+async fn get_access_control(
+  settings: &Settings,
+) -> Result<Option<processors::http::AccessControl>> {
+  let path = match settings.get(API_KEYS_VAR) {
+    Some(path) => path,
+    None if settings.contains(AUDIT_LOG_VAR) => {
+      anyhow::bail!("{} requires {}", AUDIT_LOG_VAR, API_KEYS_VAR)
+    }
+    None => return Ok(None),
+  };
+
+  let file = tokio::fs::File::open(path).await?;
+  let api_keys = toy_payments_engine::io::ApiKeys::load(file).await?;
+  let access_control = processors::http::AccessControl::new(api_keys);
+
+  match settings.get(AUDIT_LOG_VAR) {
+    Some(path) => {
+      let log = tokio::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .await?;
+      let log_started = log.metadata().await?.len() > 0;
+      let log: Box<dyn AsyncWrite + Unpin + Send + Sync> = Box::new(log);
+      let audit_log = if log_started {
+        toy_payments_engine::io::AuditLog::appending(log)
+      } else {
+        toy_payments_engine::io::AuditLog::new(log)
+      };
+      Ok(Some(access_control.with_audit_log(audit_log)))
+    }
+    None => Ok(Some(access_control)),
+  }
+}
+
+/// Create the acceptor of the TLS connections, if the certificate and private key are configured.
+#[cfg(feature = "http")]
+async fn get_tls_acceptor(settings: &Settings) -> Result<Option<processors::http::TlsAcceptor>> {
+  match (settings.get(TLS_CERT_VAR), settings.get(TLS_KEY_VAR)) {
+    (None, None) => Ok(None),
+    #[cfg(feature = "tls")]
+    (Some(certificates), Some(private_key)) => {
+      let certificates = tokio::fs::read(certificates).await?;
+      let private_key = tokio::fs::read(private_key).await?;
+      processors::http::tls_acceptor(&certificates, &private_key).map(Some)
+    }
+    #[cfg(feature = "tls")]
+    _ => anyhow::bail!("{} and {} must be set together", TLS_CERT_VAR, TLS_KEY_VAR),
+    #[cfg(not(feature = "tls"))]
+    _ => anyhow::bail!(
+      "{} and {} require the tls feature",
+      TLS_CERT_VAR,
+      TLS_KEY_VAR
+    ),
+  }
+}
+
+/// Process the transactions, refusing (or warning about) input files already processed when there is an input history.
+/// Only input files can be tracked, as the stdin can't be fingerprinted before processing it.
+async fn process(cli: &Cli) -> Result<()> {
+  let settings = &cli.settings;
+  let transactions_path = cli.input.as_ref();
+  let history = settings.get(INPUT_HISTORY_VAR).map(InputHistory::new);
+
+  let fingerprint = match (&history, transactions_path) {
+    (Some(history), Some(path)) => {
+      let fingerprint = fingerprint_file(path).await?;
+      if history.contains(&fingerprint).await? {
+        if settings
+          .get(DUPLICATE_INPUT_VAR)
+          .map_or(false, |value| value == "warn")
+        {
+          tracing::warn!(input = %path, "The input was already processed");
+        } else {
+          anyhow::bail!("The input {} was already processed", path);
+        }
+      }
+      Some(fingerprint)
+    }
+    _ => None,
+  };
+
+  // the archived copy is processed instead of the input, as the stdin can only be read once
+  let archived_path = match settings.get(ARCHIVE_DIR_VAR) {
+    Some(archive_dir) => {
+      let reader = get_transactions_async_read(transactions_path).await?;
+      let path = archive_input(reader, archive_dir.as_ref()).await?;
+      Some(path.to_string_lossy().into_owned())
+    }
+    None => None,
+  };
 
-use crate::io::{CsvAccountsReportWriter, CsvTransactionsReader};
-use payments::InMemoryPaymentsEngine;
+  process_transactions(cli, archived_path.as_ref().or(transactions_path)).await?;
 
-#[tokio::main]
-async fn main() -> Result<()> {
-  let reader = get_transactions_async_read().await?;
-  let transactions_reader = CsvTransactionsReader::new(reader);
-  let payments_engine = InMemoryPaymentsEngine::new();
-  let accounts_report_writer = CsvAccountsReportWriter::new(tokio::io::stdout());
+  if let (Some(history), Some(fingerprint)) = (history, fingerprint) {
+    history.record(&fingerprint).await?;
+  }
+  Ok(())
+}
+
+async fn process_transactions(cli: &Cli, transactions_path: Option<&String>) -> Result<()> {
+  let settings = &cli.settings;
+  let output = get_report_async_write(cli.output.as_ref()).await?;
+  let errors_file = settings.get(ERRORS_FILE_VAR);
+
+  match cli.format {
+    ReportFormat::Csv => {
+      let report_writer =
+        CsvAccountsReportWriter::with_schema(output, get_report_schema(settings)?)
+          .with_metadata(get_client_metadata(settings).await?)
+          .with_currency(settings.get(REPORT_CURRENCY_VAR))
+          .with_baseline(get_baseline(settings).await?);
+      process_transactions_into(
+        cli,
+        transactions_path,
+        errors_file.as_deref(),
+        report_writer,
+      )
+      .await
+    }
+    ReportFormat::Json => {
+      let report_writer = NdjsonAccountsReportWriter::new(output);
+      process_transactions_into(
+        cli,
+        transactions_path,
+        errors_file.as_deref(),
+        report_writer,
+      )
+      .await
+    }
+    #[cfg(feature = "protobuf")]
+    ReportFormat::Protobuf => {
+      let report_writer = toy_payments_engine::io::ProtobufAccountsReportWriter::new(output);
+      process_transactions_into(
+        cli,
+        transactions_path,
+        errors_file.as_deref(),
+        report_writer,
+      )
+      .await
+    }
+  }
+}
+
+async fn process_transactions_into<W>(
+  cli: &Cli,
+  transactions_path: Option<&String>,
+  errors_file: Option<&str>,
+  report_writer: W,
+) -> Result<()>
+where
+  W: AccountsReportWriter,
+{
+  let settings = &cli.settings;
+  if cli.resume {
+    if get_wal_path(cli)?.is_none() {
+      anyhow::bail!(
+        "--resume requires {} or --engine persistent to recover the state",
+        WAL_FILE_VAR
+      );
+    }
+    if transactions_path.is_none() {
+      anyhow::bail!("--resume requires the transactions to be read from a file");
+    }
+    for var in [DUMPS_DIR_VAR, DUPLICATES_FILE_VAR, DIGESTS_DIR_VAR] {
+      if settings.contains(var) {
+        anyhow::bail!("--resume can not be used with {}", var);
+      }
+    }
+  }
+
+  let engine_config = get_engine_config(cli)?;
+  tracing::info!(digest = %engine_config.digest(), "Engine configuration");
+  let accounts_report_writer = SortedAccountsReportWriter::new(
+    SpillingAccountsReportWriter::new(
+      TeeAccountsReportWriter::new(
+        TeeAccountsReportWriter::new(
+          TeeAccountsReportWriter::new(report_writer, get_report_socket_writer(settings).await?),
+          get_report_kv_writer(settings)?,
+        ),
+        get_extended_report_writer(settings).await?,
+      ),
+      settings
+        .get(REPORT_BUFFER_ACCOUNTS_VAR)
+        .map(|value| value.parse::<usize>())
+        .transpose()?,
+      std::env::temp_dir(),
+    ),
+    get_report_sort(settings)?,
+  );
+
+  let transactions_reader = get_transactions_reader(cli, transactions_path).await?;
+  // the records are only recorded as ingested once they reach the engine, so this is the last layer of the reader
+  let transactions_reader: BoxedTransactionsReader = match get_idempotency_store(settings)? {
+    Some(store) => Box::new(IdempotentTransactionsReader::new(
+      transactions_reader,
+      store,
+      get_idempotency_key(settings, transactions_path)?,
+    )),
+    None => transactions_reader,
+  };
+
+  let progress = match transactions_path.filter(|_| cli.resume) {
+    Some(path) => Some(ProgressFile::open(path).await?),
+    None => None,
+  };
+
+  // the engine options are checked first, as some of them can't be used with the partitions either
+  let engine_options = get_engine_options(cli, engine_config.clone())?;
+
+  if let Some(partitions) = settings.get(PARTITIONS_VAR) {
+    // every partition has its own engine, and they are only merged into the report at the end
+    let unsupported = [
+      DUPLICATES_FILE_VAR,
+      QUARANTINE_FILE_VAR,
+      DUMPS_DIR_VAR,
+      DIGESTS_DIR_VAR,
+      CHECK_INVARIANTS_VAR,
+      WAL_FILE_VAR,
+      EVENTS_FILE_VAR,
+    ];
+    for var in unsupported {
+      if settings.contains(var) {
+        anyhow::bail!("{} can not be used with {}", PARTITIONS_VAR, var);
+      }
+    }
+    if let Engine::Persistent { .. } = cli.engine {
+      anyhow::bail!(
+        "{} can not be used with --engine persistent",
+        PARTITIONS_VAR
+      );
+    }
+    let filter = cli.filter.clone();
+    let create_engine = move || {
+      FilteredPaymentsEngine::new(
+        InMemoryPaymentsEngine::with_config(engine_config.clone()),
+        filter.clone(),
+      )
+    };
+    let transactions_reader = NormalizedTransactionsReader::new(
+      transactions_reader,
+      get_normalization(settings)?.unwrap_or_default(),
+    );
+    let resources = get_resources(cli);
+    let partitions = match partitions.as_str() {
+      "auto" => resources.partitions(),
+      partitions => partitions.parse::<usize>()?,
+    };
+    return match open_error_sink(errors_file, settings, false).await? {
+      Some(error_sink) => {
+        processors::partitioned::run_with_errors(
+          transactions_reader,
+          partitions,
+          resources.channel_capacity(),
+          create_engine,
+          accounts_report_writer,
+          error_sink,
+        )
+        .await
+      }
+      None => {
+        processors::partitioned::run_with_capacity(
+          transactions_reader,
+          partitions,
+          resources.channel_capacity(),
+          create_engine,
+          accounts_report_writer,
+        )
+        .await
+      }
+    };
+  }
 
-  processors::simple::run(transactions_reader, payments_engine, accounts_report_writer).await
+  // the fastest path when no other feature is needed
+  let fastest = !settings.contains(DUMPS_DIR_VAR)
+    && !settings.contains(DIGESTS_DIR_VAR)
+    && !settings.enabled(CHECK_INVARIANTS_VAR)
+    && errors_file.is_none()
+    && !settings.contains(QUARANTINE_FILE_VAR)
+    && !settings.contains(MAX_QUARANTINED_VAR)
+    && get_normalization(settings)?.is_none()
+    && !settings.contains(DUPLICATES_FILE_VAR)
+    && !settings.contains(EVENTS_FILE_VAR);
+  processors::engine::with_engine(engine_options, |payments_engine| async move {
+    match payments_engine {
+      OpenedEngine::Memory(payments_engine) if fastest => {
+        let mut transactions_reader = transactions_reader;
+        let transactions = transactions_reader.read_transactions();
+        let payments_engine = FilteredPaymentsEngine::new(payments_engine, cli.filter.clone());
+        processors::generic::run(transactions, payments_engine, accounts_report_writer).await
+      }
+      payments_engine => {
+        run_engine(
+          transactions_reader,
+          payments_engine.into_boxed(),
+          &engine_config,
+          cli,
+          accounts_report_writer,
+          errors_file,
+          progress,
+        )
+        .await
+      }
+    }
+  })
+  .await
+}
+
+/// The options to open the engine selected by the command line, refusing the settings that can't be used with it.
+fn get_engine_options(cli: &Cli, engine_config: EngineConfig) -> Result<EngineOptions> {
+  let (backend, write_batch) = match &cli.engine {
+    Engine::Memory | Engine::Persistent { .. } => (EngineBackend::Memory, None),
+    #[cfg(feature = "sqlite")]
+    Engine::Sqlite { path } => (
+      EngineBackend::Sqlite { path: path.clone() },
+      check_database_engine(&cli.settings, "sqlite")?,
+    ),
+    #[cfg(feature = "postgres")]
+    Engine::Postgres { url } => (
+      EngineBackend::Postgres { url: url.clone() },
+      check_database_engine(&cli.settings, "postgres")?,
+    ),
+  };
+  Ok(
+    EngineOptions::new(backend, engine_config)
+      .with_transaction_store(get_transaction_store(cli, &get_resources(cli))?)
+      .with_wal_path(get_wal_path(cli)?)
+      .with_write_batch(write_batch)
+      .with_config_change(cli.allow_config_change)
+      .with_steal_lock(cli.steal_lock),
+  )
+}
+
+/// Refuse the options that only apply to the accounts kept in memory, when they are kept in a database instead,
+/// and return the size of the write batch, if any. The write-ahead log is only used to recover the transactions of the batches.
+#[cfg(any(feature = "sqlite", feature = "postgres"))]
+fn check_database_engine(settings: &Settings, engine: &str) -> Result<Option<usize>> {
+  if settings.contains(PARTITIONS_VAR) {
+    anyhow::bail!(
+      "--engine {} can not be used with {}",
+      engine,
+      PARTITIONS_VAR
+    );
+  }
+  let write_batch = settings
+    .get(WRITE_BATCH_SIZE_VAR)
+    .map(|size| size.parse::<usize>())
+    .transpose()?;
+  match write_batch {
+    Some(0) => anyhow::bail!("Invalid {}: 0", WRITE_BATCH_SIZE_VAR),
+    Some(_) if !settings.contains(WAL_FILE_VAR) => anyhow::bail!(
+      "{} requires {} to recover the transactions not flushed",
+      WRITE_BATCH_SIZE_VAR,
+      WAL_FILE_VAR
+    ),
+    None if settings.contains(WAL_FILE_VAR) => anyhow::bail!(
+      "--engine {} can only be used with {} to batch the writes with {}",
+      engine,
+      WAL_FILE_VAR,
+      WRITE_BATCH_SIZE_VAR
+    ),
+    _ => Ok(write_batch),
+  }
+}
+
+/// Process the transactions with the engine selected, notifying its events into the events file when there is one.
+/// The events are written by another task, which finishes once the engine is dropped at the end of the processing.
+async fn run_engine<R, P, W>(
+  transactions_reader: R,
+  payments_engine: P,
+  engine_config: &EngineConfig,
+  cli: &Cli,
+  accounts_report_writer: W,
+  errors_file: Option<&str>,
+  progress: Option<ProgressFile>,
+) -> Result<()>
+where
+  R: TransactionsReader,
+  P: PaymentsEngine + Send,
+  W: AccountsReportWriter,
+{
+  let events_file = match cli.settings.get(EVENTS_FILE_VAR) {
+    Some(events_file) => events_file,
+    None => {
+      return run_checked_engine(
+        transactions_reader,
+        payments_engine,
+        engine_config,
+        cli,
+        accounts_report_writer,
+        errors_file,
+        progress,
+      )
+      .await
+    }
+  };
+
+  let events_file = std::fs::File::create(events_file)?;
+  let (listener, events) = ChannelEventListener::new(get_resources(cli).channel_capacity());
+  // the processing waits for the events to be written when they are full, so they are written out of the runtime
+  let events_writer = tokio::task::spawn_blocking(move || write_events(events, events_file));
+  let result = run_checked_engine(
+    transactions_reader,
+    ListeningPaymentsEngine::new(payments_engine, Arc::new(listener)),
+    engine_config,
+    cli,
+    accounts_report_writer,
+    errors_file,
+    progress,
+  )
+  .await;
+  events_writer.await??;
+  result
+}
+
+/// Write the events as JSON, one per line, until all the listeners sending them are dropped.
+fn write_events<W>(events: std::sync::mpsc::Receiver<EngineEvent>, out: W) -> Result<()>
+where
+  W: Write,
+{
+  let mut out = std::io::BufWriter::new(out);
+  for event in events {
+    let mut line = serde_json::to_vec(&event)?;
+    line.push(b'\n');
+    out.write_all(&line)?;
+  }
+  out.flush()?;
+  Ok(())
+}
+
+/// Process the transactions with the engine selected, checking its invariants after every transaction when enabled,
+/// and reporting the accounts selected by the filter of the command line.
+async fn run_checked_engine<R, P, W>(
+  transactions_reader: R,
+  payments_engine: P,
+  engine_config: &EngineConfig,
+  cli: &Cli,
+  accounts_report_writer: W,
+  errors_file: Option<&str>,
+  progress: Option<ProgressFile>,
+) -> Result<()>
+where
+  R: TransactionsReader,
+  P: PaymentsEngine + Send,
+  W: AccountsReportWriter,
+{
+  let settings = &cli.settings;
+  let filter = &cli.filter;
+  if !settings.enabled(CHECK_INVARIANTS_VAR) {
+    return run_processor(
+      transactions_reader,
+      FilteredPaymentsEngine::new(payments_engine, filter.clone()),
+      accounts_report_writer,
+      errors_file,
+      progress,
+      settings,
+    )
+    .await;
+  }
+
+  if engine_config.escrow_interest_rate.is_some() {
+    anyhow::bail!(
+      "{} can not be used with {}",
+      CHECK_INVARIANTS_VAR,
+      ESCROW_INTEREST_RATE_VAR
+    );
+  }
+  if engine_config.exposure_threshold.is_some() {
+    anyhow::bail!(
+      "{} can not be used with {}",
+      CHECK_INVARIANTS_VAR,
+      EXPOSURE_THRESHOLD_VAR
+    );
+  }
+  if engine_config.dormancy.is_some() {
+    anyhow::bail!(
+      "{} can not be used with {}",
+      CHECK_INVARIANTS_VAR,
+      DORMANCY_DAYS_VAR
+    );
+  }
+  // the invariants are checked against the report of all the accounts, so it is filtered afterwards
+  let payments_engine = InvariantCheckingEngine::with_config(payments_engine, engine_config);
+  run_processor(
+    transactions_reader,
+    FilteredPaymentsEngine::new(payments_engine, filter.clone()),
+    accounts_report_writer,
+    errors_file,
+    progress,
+    settings,
+  )
+  .await
+}
+
+/// The write-ahead log where to persist the accounts kept in memory, given either by `--engine persistent` or the `WAL_FILE`.
+fn get_wal_path(cli: &Cli) -> Result<Option<String>> {
+  match &cli.engine {
+    Engine::Persistent { .. } if cli.settings.contains(WAL_FILE_VAR) => {
+      anyhow::bail!("--engine persistent can not be used with {}", WAL_FILE_VAR)
+    }
+    Engine::Persistent { path } => Ok(Some(path.clone())),
+    _ => Ok(cli.settings.get(WAL_FILE_VAR)),
+  }
+}
+
+/// The checkpoints where to snapshot the engine of a service as scheduled, when a directory is given for them.
+/// They are taken every 5 minutes unless another interval or a number of transactions is given.
+#[cfg(any(feature = "http", feature = "grpc"))]
+fn get_checkpoints(
+  cli: &Cli,
+) -> Result<
+  Option<(
+    toy_payments_engine::io::Checkpoints,
+    toy_payments_engine::payments::CheckpointSchedule,
+  )>,
+> {
+  let settings = &cli.settings;
+  let snapshots_dir = match settings.get(SNAPSHOTS_DIR_VAR) {
+    Some(snapshots_dir) => snapshots_dir,
+    None => return Ok(None),
+  };
+  if !matches!(cli.engine, Engine::Persistent { .. }) {
+    anyhow::bail!("{} requires --engine persistent", SNAPSHOTS_DIR_VAR);
+  }
+  let interval = settings
+    .get(SNAPSHOT_INTERVAL_VAR)
+    .map(|minutes| minutes.parse::<u64>())
+    .transpose()?
+    .map(|minutes| std::time::Duration::from_secs(minutes * 60));
+  let transactions = settings
+    .get(SNAPSHOT_TRANSACTIONS_VAR)
+    .map(|transactions| transactions.parse::<u64>())
+    .transpose()?;
+  let schedule = toy_payments_engine::payments::CheckpointSchedule {
+    interval: interval.or_else(|| {
+      transactions
+        .is_none()
+        .then(|| std::time::Duration::from_secs(DEFAULT_SNAPSHOT_INTERVAL * 60))
+    }),
+    transactions,
+  };
+  let kept = settings
+    .get(SNAPSHOTS_KEPT_VAR)
+    .map(|kept| kept.parse::<usize>())
+    .transpose()?
+    .unwrap_or(toy_payments_engine::io::DEFAULT_KEPT_CHECKPOINTS);
+  Ok(Some((
+    toy_payments_engine::io::Checkpoints::new(snapshots_dir, kept),
+    schedule,
+  )))
+}
+
+/// Run the processor for the options enabled, resuming the processing of the input when there is a [`ProgressFile`].
+async fn run_processor<R, P, W>(
+  transactions_reader: R,
+  payments_engine: P,
+  accounts_report_writer: W,
+  errors_file: Option<&str>,
+  progress: Option<ProgressFile>,
+  settings: &Settings,
+) -> Result<()>
+where
+  R: TransactionsReader,
+  P: PaymentsEngine,
+  W: AccountsReportWriter,
+{
+  let transactions_reader = NormalizedTransactionsReader::new(
+    transactions_reader,
+    get_normalization(settings)?.unwrap_or_default(),
+  );
+
+  if let Some(progress) = progress {
+    // the rejected records of the previous runs are kept
+    let mut error_sink = open_error_sink(errors_file, settings, true).await?;
+    return processors::resumable::run(
+      transactions_reader,
+      payments_engine,
+      accounts_report_writer,
+      progress,
+      error_sink
+        .as_mut()
+        .map(|sink| sink.as_mut() as &mut dyn ErrorSink),
+    )
+    .await;
+  }
+
+  if let Some(digests_dir) = settings.get(DIGESTS_DIR_VAR) {
+    let replica = settings
+      .get(REPLICA_NAME_VAR)
+      .ok_or_else(|| anyhow::anyhow!("{} requires {}", DIGESTS_DIR_VAR, REPLICA_NAME_VAR))?;
+    let interval = settings
+      .get(DIGEST_INTERVAL_VAR)
+      .map(|value| value.parse::<u64>())
+      .transpose()?
+      .unwrap_or(DEFAULT_DIGEST_INTERVAL);
+    return processors::replicated::run(
+      transactions_reader,
+      payments_engine,
+      accounts_report_writer,
+      DigestExchange::new(digests_dir, replica),
+      interval,
+    )
+    .await;
+  }
+
+  match settings.get(DUMPS_DIR_VAR) {
+    #[cfg(unix)]
+    Some(dumps_dir) => {
+      processors::dumping::run(
+        transactions_reader,
+        payments_engine,
+        accounts_report_writer,
+        get_dump_requests()?,
+        dumps_dir.into(),
+      )
+      .await
+    }
+    _ => match (
+      open_error_sink(errors_file, settings, false).await?,
+      settings.get(DUPLICATES_FILE_VAR),
+    ) {
+      (mut error_sink, Some(duplicates_file)) => {
+        let duplicates_sink =
+          CsvDuplicatesSink::new(tokio::fs::File::create(duplicates_file).await?);
+        processors::simple::run_with_duplicates(
+          transactions_reader,
+          payments_engine,
+          accounts_report_writer,
+          duplicates_sink,
+          error_sink
+            .as_mut()
+            .map(|sink| sink.as_mut() as &mut dyn ErrorSink),
+        )
+        .await
+      }
+      (Some(error_sink), None) => {
+        processors::simple::run_with_errors(
+          transactions_reader,
+          payments_engine,
+          accounts_report_writer,
+          error_sink,
+        )
+        .await
+      }
+      (None, None) => {
+        processors::simple::run(transactions_reader, payments_engine, accounts_report_writer).await
+      }
+    },
+  }
+}
+
+/// Open the [`ErrorSink`] for the rejected records, putting the unreadable ones in quarantine when there is a `QUARANTINE_FILE`.
+/// The files are appended to when resuming, instead of being created again.
+async fn open_error_sink(
+  errors_file: Option<&str>,
+  settings: &Settings,
+  append: bool,
+) -> Result<Option<Box<dyn ErrorSink>>> {
+  let error_sink = match errors_file {
+    Some(errors_file) => Some(Box::new(CsvErrorSink::new(
+      open_output_file(errors_file, append).await?,
+    )) as Box<dyn ErrorSink>),
+    None => None,
+  };
+  let max_quarantined = settings
+    .get(MAX_QUARANTINED_VAR)
+    .map(|value| value.parse::<u64>())
+    .transpose()?;
+
+  match settings.get(QUARANTINE_FILE_VAR) {
+    Some(quarantine_file) => {
+      let quarantine_sink = QuarantineSink::new(
+        open_output_file(&quarantine_file, append).await?,
+        error_sink,
+      );
+      Ok(Some(Box::new(match max_quarantined {
+        Some(max_quarantined) => quarantine_sink.with_max_quarantined(max_quarantined),
+        None => quarantine_sink,
+      })))
+    }
+    None if max_quarantined.is_some() => {
+      anyhow::bail!("{} requires {}", MAX_QUARANTINED_VAR, QUARANTINE_FILE_VAR)
+    }
+    None => Ok(error_sink),
+  }
+}
+
+async fn open_output_file(path: &str, append: bool) -> Result<tokio::fs::File> {
+  if append {
+    Ok(
+      tokio::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .await?,
+    )
+  } else {
+    Ok(tokio::fs::File::create(path).await?)
+  }
+}
+
+/// Build the engine configuration from the environment variables, using the defaults for the ones not defined.
+/// The limits of the configuration file are only used when there is no `LIMITS_FILE`.
+fn get_engine_config(cli: &Cli) -> Result<EngineConfig> {
+  let settings = &cli.settings;
+  let max_open_disputes = settings
+    .get(MAX_OPEN_DISPUTES_VAR)
+    .map(|value| value.parse::<usize>())
+    .transpose()?;
+
+  let deterministic = settings.enabled(DETERMINISTIC_REPORT_VAR);
+
+  let locked_account_dispute_policy = match settings.get(LOCKED_ACCOUNT_DISPUTES_VAR) {
+    Some(value) if value == "allow" => LockedAccountDisputePolicy::Allow,
+    Some(value) if value == "reject" => LockedAccountDisputePolicy::Reject,
+    Some(value) => anyhow::bail!("Invalid {}: {}", LOCKED_ACCOUNT_DISPUTES_VAR, value),
+    None => LockedAccountDisputePolicy::default(),
+  };
+
+  let synthetic_dispute_policy = match settings.get(SYNTHETIC_DISPUTES_VAR) {
+    Some(value) if value == "allow" => SyntheticDisputePolicy::Allow,
+    Some(value) if value == "reject" => SyntheticDisputePolicy::Reject,
+    Some(value) => anyhow::bail!("Invalid {}: {}", SYNTHETIC_DISPUTES_VAR, value),
+    None => SyntheticDisputePolicy::default(),
+  };
+
+  let zero_amount_policy = match settings.get(ZERO_AMOUNTS_VAR) {
+    Some(value) if value == "accept" => ZeroAmountPolicy::Accept,
+    Some(value) if value == "reject" => ZeroAmountPolicy::Reject,
+    Some(value) if value == "skip" => ZeroAmountPolicy::Skip,
+    Some(value) => anyhow::bail!("Invalid {}: {}", ZERO_AMOUNTS_VAR, value),
+    None => ZeroAmountPolicy::default(),
+  };
+
+  let allow_negative_available = match settings.get(CHARGEBACK_FEE_POLICY_VAR) {
+    Some(value) if value == "cap" => false,
+    Some(value) if value == "allow-negative" => true,
+    Some(value) => anyhow::bail!("Invalid {}: {}", CHARGEBACK_FEE_POLICY_VAR, value),
+    None => false,
+  };
+
+  let chargeback_fee = settings
+    .get(CHARGEBACK_FEE_VAR)
+    .map(|value| Decimal::from_str(&value))
+    .transpose()?
+    .map(|amount| ChargebackFee {
+      amount,
+      allow_negative_available,
+    });
+
+  let unlock_held_funds_policy = match settings.get(UNLOCK_HELD_FUNDS_VAR) {
+    Some(value) if value == "keep" => UnlockHeldFundsPolicy::Keep,
+    Some(value) if value == "release" => UnlockHeldFundsPolicy::Release,
+    Some(value) => anyhow::bail!("Invalid {}: {}", UNLOCK_HELD_FUNDS_VAR, value),
+    None => UnlockHeldFundsPolicy::default(),
+  };
+
+  let duplicate_policy = match settings.get(DUPLICATE_POLICY_VAR) {
+    Some(value) if value == "reject" => DuplicatePolicy::Reject,
+    Some(value) if value == "idempotent" => DuplicatePolicy::Idempotent,
+    Some(value) => anyhow::bail!("Invalid {}: {}", DUPLICATE_POLICY_VAR, value),
+    None => DuplicatePolicy::default(),
+  };
+
+  let escrow_interest_rate = settings
+    .get(ESCROW_INTEREST_RATE_VAR)
+    .map(|value| Decimal::from_str(&value))
+    .transpose()?;
+
+  let exposure_threshold = settings
+    .get(EXPOSURE_THRESHOLD_VAR)
+    .map(|value| Decimal::from_str(&value))
+    .transpose()?;
+
+  let authorization_expiry = settings
+    .get(AUTHORIZATION_EXPIRY_VAR)
+    .map(|value| value.parse::<u64>())
+    .transpose()?;
+
+  let dispute_window_days = settings
+    .get(DISPUTE_WINDOW_DAYS_VAR)
+    .map(|value| value.parse::<u64>())
+    .transpose()?;
+
+  let block_withdrawals = match settings.get(DORMANCY_POLICY_VAR) {
+    Some(value) if value == "flag" => false,
+    Some(value) if value == "block-withdrawals" => true,
+    Some(value) => anyhow::bail!("Invalid {}: {}", DORMANCY_POLICY_VAR, value),
+    None => false,
+  };
+
+  let dormancy = settings
+    .get(DORMANCY_DAYS_VAR)
+    .map(|value| value.parse::<u64>())
+    .transpose()?
+    .map(|days| DormancyPolicy {
+      days,
+      block_withdrawals,
+    });
+
+  let limits = match settings.get(LIMITS_FILE_VAR) {
+    Some(path) => Some(LimitsPolicy::load(path)?),
+    None => cli.config.limits.clone(),
+  };
+
+  Ok(EngineConfig {
+    max_open_disputes,
+    deterministic,
+    locked_account_dispute_policy,
+    zero_amount_policy,
+    chargeback_fee,
+    unlock_held_funds_policy,
+    escrow_interest_rate,
+    exposure_threshold,
+    authorization_expiry,
+    dispute_window_days,
+    limits,
+    duplicate_policy,
+    dormancy,
+    synthetic_dispute_policy,
+  })
+}
+
+/// Build the normalization of the transactions from the environment variables, if any of them is defined.
+fn get_normalization(settings: &Settings) -> Result<Option<Normalization>> {
+  let precision = settings
+    .get(NORMALIZE_PRECISION_VAR)
+    .map(|value| value.parse::<u32>())
+    .transpose()?;
+
+  let dropped_clients = settings
+    .get(DROP_CLIENTS_VAR)
+    .map(|value| Normalization::parse_client_ranges(&value))
+    .transpose()
+    .map_err(|_| anyhow::anyhow!("Invalid {}", DROP_CLIENTS_VAR))?
+    .unwrap_or_default();
+
+  if precision.is_none() && dropped_clients.is_empty() {
+    return Ok(None);
+  }
+  Ok(Some(Normalization {
+    precision,
+    dropped_clients,
+  }))
+}
+
+/// Load the client IDs remapping for the source of the input, if configured.
+async fn get_client_id_remapping(settings: &Settings) -> Result<Option<ClientIdRemapping>> {
+  match settings.get(CLIENT_ID_REMAPPING_VAR) {
+    Some(path) => {
+      let file = tokio::fs::File::open(path).await?;
+      let source = settings.get(SOURCE_TAG_VAR);
+      ClientIdRemapping::load(file, source.as_deref())
+        .await
+        .map(Some)
+    }
+    None => Ok(None),
+  }
+}
+
+fn get_idempotency_store(settings: &Settings) -> Result<Option<Box<dyn IdempotencyStore>>> {
+  settings
+    .get(IDEMPOTENCY_STORE_VAR)
+    .map(|path| {
+      FileIdempotencyStore::open(path).map(|store| Box::new(store) as Box<dyn IdempotencyStore>)
+    })
+    .transpose()
+}
+
+/// The positions of the records are identified by the `SOURCE_TAG` when given, or the path of the input otherwise.
+fn get_idempotency_key(
+  settings: &Settings,
+  transactions_path: Option<&String>,
+) -> Result<IdempotencyKey> {
+  match settings.get(IDEMPOTENCY_KEY_VAR) {
+    Some(value) if value == "position" => {
+      let source = settings
+        .get(SOURCE_TAG_VAR)
+        .or_else(|| transactions_path.cloned())
+        .ok_or_else(|| {
+          anyhow::anyhow!(
+            "{}=position requires an input file or a {}",
+            IDEMPOTENCY_KEY_VAR,
+            SOURCE_TAG_VAR
+          )
+        })?;
+      Ok(IdempotencyKey::Position { source })
+    }
+    Some(value) if value == "record" => Ok(IdempotencyKey::Record),
+    Some(value) => anyhow::bail!("Invalid {}: {}", IDEMPOTENCY_KEY_VAR, value),
+    None => Ok(IdempotencyKey::Record),
+  }
+}
+
+async fn get_client_metadata(settings: &Settings) -> Result<Option<Arc<ClientMetadata>>> {
+  let path = match settings.get(CLIENT_METADATA_VAR) {
+    Some(path) => path,
+    None => return Ok(None),
+  };
+
+  let fields = settings
+    .get(CLIENT_METADATA_FIELDS_VAR)
+    .unwrap_or_else(|| "name,tier".to_string());
+  let fields = fields
+    .split(',')
+    .map(|field| match field.trim() {
+      "name" => Ok(MetadataField::Name),
+      "tier" => Ok(MetadataField::Tier),
+      field => Err(anyhow::anyhow!(
+        "Invalid {}: {}",
+        CLIENT_METADATA_FIELDS_VAR,
+        field
+      )),
+    })
+    .collect::<Result<Vec<MetadataField>>>()?;
+
+  let file = tokio::fs::File::open(path).await?;
+  let metadata = ClientMetadata::load(file, fields).await?;
+  Ok(Some(Arc::new(metadata)))
+}
+
+async fn get_baseline(settings: &Settings) -> Result<Option<Arc<Baseline>>> {
+  match settings.get(BASELINE_SNAPSHOT_VAR) {
+    Some(path) => {
+      let file = tokio::fs::File::open(path).await?;
+      Ok(Some(Arc::new(Baseline::load(file).await?)))
+    }
+    None => Ok(None),
+  }
+}
+
+fn get_amount_parser(settings: &Settings) -> Result<AmountParser> {
+  match settings.get(AMOUNTS_VAR) {
+    Some(value) if value == "lenient" => Ok(AmountParser::Lenient),
+    Some(value) if value == "strict" => Ok(AmountParser::strict()),
+    Some(value) => anyhow::bail!("Invalid {}: {}", AMOUNTS_VAR, value),
+    None => Ok(AmountParser::default()),
+  }
+}
+
+fn get_report_schema(settings: &Settings) -> Result<ReportSchema> {
+  match settings.get(REPORT_SCHEMA_VAR) {
+    Some(value) if value == "v1" => Ok(ReportSchema::V1),
+    Some(value) if value == "v2" => Ok(ReportSchema::V2),
+    Some(value) if value == "extended" => Ok(ReportSchema::Extended),
+    Some(value) => anyhow::bail!("Invalid {}: {}", REPORT_SCHEMA_VAR, value),
+    None => Ok(ReportSchema::default()),
+  }
+}
+
+fn get_report_sort(settings: &Settings) -> Result<Option<ReportOptions>> {
+  let value = match settings.get(REPORT_SORT_VAR) {
+    Some(value) => value,
+    None => return Ok(None),
+  };
+  let (sort_by, descending) = match value.trim().splitn(2, ':').collect::<Vec<&str>>()[..] {
+    [sort_by] => (sort_by, false),
+    [sort_by, "desc"] => (sort_by, true),
+    [sort_by, "asc"] => (sort_by, false),
+    _ => anyhow::bail!("Invalid {}: {}", REPORT_SORT_VAR, value),
+  };
+  let sort_by = match sort_by {
+    "client" => ReportSortKey::ClientId,
+    "total" => ReportSortKey::Total,
+    _ => anyhow::bail!("Invalid {}: {}", REPORT_SORT_VAR, value),
+  };
+  Ok(Some(ReportOptions {
+    sort_by,
+    descending,
+  }))
+}
+
+/// Create the file where to write a copy of the accounts report with the extended schema, if configured,
+/// so the consumers can migrate to it while the original report is still written.
+async fn get_extended_report_writer(
+  settings: &Settings,
+) -> Result<Option<CsvAccountsReportWriter<ReportAsyncWrite>>> {
+  match settings.get(EXTENDED_REPORT_FILE_VAR) {
+    Some(path) => {
+      let output = get_report_async_write(Some(&path)).await?;
+      Ok(Some(
+        CsvAccountsReportWriter::with_schema(output, ReportSchema::Extended)
+          .with_currency(settings.get(REPORT_CURRENCY_VAR))
+          .with_baseline(get_baseline(settings).await?),
+      ))
+    }
+    None => Ok(None),
+  }
+}
+
+/// Connect to the Unix domain socket where to stream a copy of the accounts report as newline delimited JSON, if configured.
+#[cfg(unix)]
+async fn get_report_socket_writer(
+  settings: &Settings,
+) -> Result<Option<NdjsonAccountsReportWriter<tokio::net::UnixStream>>> {
+  match settings.get(REPORT_SOCKET_VAR) {
+    Some(path) => {
+      let socket = tokio::net::UnixStream::connect(path).await?;
+      Ok(Some(NdjsonAccountsReportWriter::new(socket)))
+    }
+    None => Ok(None),
+  }
+}
+
+#[cfg(not(unix))]
+async fn get_report_socket_writer(
+  _settings: &Settings,
+) -> Result<Option<NdjsonAccountsReportWriter<tokio::io::Sink>>> {
+  Ok(None)
+}
+
+/// Create the temporary database where to spill the settled transactions of the accounts, if configured.
+/// Unless `TRANSACTIONS_MEMORY` is given, the transactions kept in memory take up to a part of the memory limit.
+#[cfg(feature = "kv")]
+fn get_transaction_store(
+  cli: &Cli,
+  resources: &Resources,
+) -> Result<Option<Box<dyn TransactionStore>>> {
+  let settings = &cli.settings;
+  let spill_dir = match settings.get(TRANSACTIONS_SPILL_DIR_VAR) {
+    Some(spill_dir) => spill_dir,
+    None => return Ok(None),
+  };
+  if settings.contains(PARTITIONS_VAR) {
+    anyhow::bail!(
+      "{} can not be used with {}",
+      PARTITIONS_VAR,
+      TRANSACTIONS_SPILL_DIR_VAR
+    );
+  }
+  if !matches!(cli.engine, Engine::Memory | Engine::Persistent { .. }) {
+    anyhow::bail!(
+      "{} can only be used with the accounts kept in memory",
+      TRANSACTIONS_SPILL_DIR_VAR
+    );
+  }
+  let memory_capacity = settings
+    .get(TRANSACTIONS_MEMORY_VAR)
+    .map(|value| value.parse::<usize>())
+    .transpose()?
+    .unwrap_or_else(|| resources.transactions_memory());
+  let path = std::path::Path::new(&spill_dir).join(format!("transactions-{}", std::process::id()));
+  let store = toy_payments_engine::payments::SpillingTransactionStore::open(path, memory_capacity)?;
+  Ok(Some(Box::new(store)))
+}
+
+#[cfg(not(feature = "kv"))]
+fn get_transaction_store(
+  _cli: &Cli,
+  _resources: &Resources,
+) -> Result<Option<Box<dyn TransactionStore>>> {
+  Ok(None)
+}
+
+/// Open the embedded database where to export a copy of the accounts report, if configured.
+#[cfg(feature = "kv")]
+fn get_report_kv_writer(
+  settings: &Settings,
+) -> Result<Option<toy_payments_engine::io::SledAccountsReportWriter>> {
+  settings
+    .get(REPORT_KV_DB_VAR)
+    .map(toy_payments_engine::io::SledAccountsReportWriter::open)
+    .transpose()
+}
+
+#[cfg(not(feature = "kv"))]
+fn get_report_kv_writer(
+  _settings: &Settings,
+) -> Result<Option<NdjsonAccountsReportWriter<tokio::io::Sink>>> {
+  Ok(None)
+}
+
+/// A stream of requests to dump the accounts report, triggered by the `SIGUSR1` signal.
+#[cfg(unix)]
+fn get_dump_requests() -> Result<impl futures::Stream<Item = ()> + Unpin> {
+  use tokio::signal::unix::{signal, SignalKind};
+
+  let signals = signal(SignalKind::user_defined1())?;
+  Ok(Box::pin(futures::stream::unfold(
+    signals,
+    |mut signals| async move { signals.recv().await.map(|_| ((), signals)) },
+  )))
+}
+
+/// Reconcile the accounts resulting from processing the transactions from the stdin
+/// against the balances from the CSV file, and write the breaks into the stdout.
+async fn reconcile(cli: &Cli, balances_path: &str, tolerance: Decimal) -> Result<()> {
+  let transactions_reader = get_transactions_reader(cli, cli.input.as_ref()).await?;
+  let payments_engine = InMemoryPaymentsEngine::with_config(get_engine_config(cli)?);
+  let balances_reader = CsvBalancesReader::new(tokio::fs::File::open(balances_path).await?);
+  let breaks_report_writer =
+    CsvBreaksReportWriter::new(get_report_async_write(cli.output.as_ref()).await?);
+
+  processors::reconcile::run(
+    transactions_reader,
+    payments_engine,
+    balances_reader,
+    breaks_report_writer,
+    tolerance,
+  )
+  .await
+}
+
+async fn history(cli: &Cli) -> Result<()> {
+  let transactions_reader = get_transactions_reader(cli, cli.input.as_ref()).await?;
+  let payments_engine = InMemoryPaymentsEngine::with_config(get_engine_config(cli)?);
+  let history_writer =
+    CsvTransactionsHistoryWriter::new(get_report_async_write(cli.output.as_ref()).await?);
+
+  processors::history::run(transactions_reader, payments_engine, history_writer).await
+}
+
+async fn disputes(cli: &Cli) -> Result<()> {
+  let transactions_reader = get_transactions_reader(cli, cli.input.as_ref()).await?;
+  let payments_engine = InMemoryPaymentsEngine::with_config(get_engine_config(cli)?);
+  let open_disputes_writer =
+    CsvOpenDisputesWriter::new(get_report_async_write(cli.output.as_ref()).await?);
+
+  processors::disputes::run(
+    transactions_reader,
+    payments_engine,
+    cli.filter.clone(),
+    open_disputes_writer,
+  )
+  .await
+}
+
+async fn sub_accounts(cli: &Cli) -> Result<()> {
+  let transactions_reader = get_transactions_reader(cli, cli.input.as_ref()).await?;
+  let payments_engine = InMemoryPaymentsEngine::with_config(get_engine_config(cli)?);
+  let sub_accounts_writer =
+    CsvSubAccountsWriter::new(get_report_async_write(cli.output.as_ref()).await?);
+
+  processors::sub_accounts::run(
+    transactions_reader,
+    payments_engine,
+    cli.filter.clone(),
+    sub_accounts_writer,
+  )
+  .await
+}
+
+async fn statements(cli: &Cli, json: bool) -> Result<()> {
+  let transactions_reader = get_transactions_reader(cli, cli.input.as_ref()).await?;
+  let payments_engine = InMemoryPaymentsEngine::with_config(get_engine_config(cli)?);
+  let output = get_report_async_write(cli.output.as_ref()).await?;
+
+  if json {
+    let statements_writer = NdjsonStatementsWriter::new(output);
+    processors::statements::run(transactions_reader, payments_engine, statements_writer).await
+  } else {
+    let statements_writer = CsvStatementsWriter::new(output);
+    processors::statements::run(transactions_reader, payments_engine, statements_writer).await
+  }
+}
+
+/// Process the transactions of the input, and answer the queries read from the stdin until it is closed.
+async fn repl(cli: &Cli) -> Result<()> {
+  let transactions_path = cli.input.as_ref().ok_or_else(|| {
+    anyhow::anyhow!("The repl needs an input file, as the queries are read from the stdin")
+  })?;
+  let transactions_reader = get_transactions_reader(cli, Some(transactions_path)).await?;
+  let payments_engine = InMemoryPaymentsEngine::with_config(get_engine_config(cli)?);
+  let queries = tokio::io::BufReader::new(tokio::io::stdin());
+  let output = get_report_async_write(cli.output.as_ref()).await?;
+
+  processors::repl::run(transactions_reader, payments_engine, queries, output).await
+}
+
+/// Process the transactions and write the audit bundle of the run, with the fingerprint of the input when it is a file.
+async fn export_bundle(cli: &Cli) -> Result<()> {
+  let fingerprint = match &cli.input {
+    Some(path)
+      if tokio::fs::metadata(path)
+        .await
+        .map_or(false, |metadata| metadata.is_file()) =>
+    {
+      Some(fingerprint_file(path).await?)
+    }
+    _ => None,
+  };
+  let input = processors::bundle::BundleInput {
+    path: cli.input.clone(),
+    fingerprint,
+  };
+  let transactions_reader = get_transactions_reader(cli, cli.input.as_ref()).await?;
+  let payments_engine = InMemoryPaymentsEngine::with_config(get_engine_config(cli)?);
+  let output = get_report_async_write(cli.output.as_ref()).await?;
+
+  processors::bundle::run(transactions_reader, payments_engine, input, output).await
+}
+
+type BoxedTransactionsReader = Box<dyn TransactionsReader>;
+
+/// The reader of the transactions of the input, with the layers enabled by the options, whatever its format.
+async fn get_transactions_reader(
+  cli: &Cli,
+  transactions_path: Option<&String>,
+) -> Result<BoxedTransactionsReader> {
+  let transactions_reader = get_format_reader(&cli.settings, transactions_path).await?;
+  // the client IDs are remapped first, so the sampling of the clients works with the canonical ones
+  let transactions_reader: BoxedTransactionsReader =
+    match get_client_id_remapping(&cli.settings).await? {
+      Some(remapping) => Box::new(RemappedTransactionsReader::new(
+        transactions_reader,
+        remapping,
+      )),
+      None => transactions_reader,
+    };
+  Ok(match cli.sampling {
+    Some(sampling) => Box::new(SampledTransactionsReader::new(
+      transactions_reader,
+      sampling,
+    )),
+    None => transactions_reader,
+  })
+}
+
+/// The reader of the transactions for the format of the input, which is recognised by the extension of its file (CSV by default).
+/// The format only decides how the transactions are read, so the rest of the options apply to all of them.
+async fn get_format_reader(
+  settings: &Settings,
+  transactions_path: Option<&String>,
+) -> Result<BoxedTransactionsReader> {
+  let amount_parser = get_amount_parser(settings)?;
+
+  #[cfg(feature = "xlsx")]
+  if let Some(path) = transactions_path.filter(|path| is_spreadsheet(path)) {
+    let transactions_reader = toy_payments_engine::io::XlsxTransactionsReader::open(path)?
+      .with_amount_parser(amount_parser);
+    return Ok(Box::new(transactions_reader));
+  }
+
+  #[cfg(feature = "kafka")]
+  if let Some((brokers, topic)) = transactions_path.and_then(|path| kafka_topic(path)) {
+    let group_id = settings
+      .get(KAFKA_GROUP_VAR)
+      .unwrap_or_else(|| DEFAULT_KAFKA_GROUP.to_string());
+    let transactions_reader =
+      toy_payments_engine::io::KafkaTransactionsReader::subscribe(brokers, &group_id, topic)?
+        .with_amount_parser(amount_parser);
+    return Ok(Box::new(transactions_reader));
+  }
+
+  let reader = get_transactions_async_read(transactions_path).await?;
+  let path = transactions_path.map_or("", String::as_str);
+
+  #[cfg(feature = "avro")]
+  if is_avro(path) {
+    let transactions_reader = toy_payments_engine::io::AvroTransactionsReader::load(reader)
+      .await?
+      .with_amount_parser(amount_parser);
+    return Ok(Box::new(transactions_reader));
+  }
+
+  #[cfg(feature = "protobuf")]
+  if is_protobuf(path) {
+    let transactions_reader = toy_payments_engine::io::ProtobufTransactionsReader::new(reader)
+      .with_amount_parser(amount_parser);
+    return Ok(Box::new(transactions_reader));
+  }
+
+  if is_ndjson(path) {
+    let transactions_reader =
+      NdjsonTransactionsReader::new(reader).with_amount_parser(amount_parser);
+    return Ok(Box::new(transactions_reader));
+  }
+
+  if let Some(chunk_size) = settings.get(PARSE_CHUNK_SIZE_VAR) {
+    let transactions_reader =
+      ChunkedCsvTransactionsReader::load(reader, chunk_size.parse::<usize>()?)
+        .await?
+        .with_amount_parser(amount_parser);
+    return Ok(Box::new(transactions_reader));
+  }
+
+  Ok(Box::new(
+    CsvTransactionsReader::new(reader).with_amount_parser(amount_parser),
+  ))
+}
+
+/// The brokers and the topic of an input like `kafka://localhost:9092/transactions`.
+#[cfg(feature = "kafka")]
+fn kafka_topic(path: &str) -> Option<(&str, &str)> {
+  path
+    .strip_prefix("kafka://")
+    .and_then(|address| address.split_once('/'))
+}
+
+/// Spreadsheets are recognised by the extension of the file.
+#[cfg(feature = "xlsx")]
+fn is_spreadsheet(path: &str) -> bool {
+  let path = path.to_lowercase();
+  path.ends_with(".xlsx") || path.ends_with(".xls") || path.ends_with(".ods")
+}
+
+/// Avro object container files are recognised by the extension of the file.
+#[cfg(feature = "avro")]
+fn is_avro(path: &str) -> bool {
+  path.to_lowercase().ends_with(".avro")
+}
+
+/// Length delimited protobuf messages are recognised by the extension of the file.
+#[cfg(feature = "protobuf")]
+fn is_protobuf(path: &str) -> bool {
+  let path = path.to_lowercase();
+  path.ends_with(".pb") || path.ends_with(".binpb")
+}
+
+/// Newline delimited JSON is recognised by the extension of the file.
+fn is_ndjson(path: &str) -> bool {
+  let path = path.to_lowercase();
+  path.ends_with(".ndjson") || path.ends_with(".jsonl")
 }
 
 type TransactionsAsyncRead = Box<dyn AsyncRead + Unpin + Send + Sync>;
 
 /// This allows to use either a file if the path is specified in the command line,
 /// or the stdin otherwise, which might be more convenient for pipe the data.
-async fn get_transactions_async_read() -> Result<TransactionsAsyncRead> {
-  match std::env::args().nth(1) {
+async fn get_transactions_async_read(path: Option<&String>) -> Result<TransactionsAsyncRead> {
+  match path {
     Some(path) => tokio::fs::File::open(path)
       .await
       .map(|file| Box::new(file) as TransactionsAsyncRead)
@@ -31,3 +1777,15 @@ async fn get_transactions_async_read() -> Result<TransactionsAsyncRead> {
     None => Ok(Box::new(tokio::io::stdin()) as TransactionsAsyncRead),
   }
 }
+
+type ReportAsyncWrite = Box<dyn AsyncWrite + Unpin + Send + Sync>;
+
+async fn get_report_async_write(path: Option<&String>) -> Result<ReportAsyncWrite> {
+  match path {
+    Some(path) => tokio::fs::File::create(path)
+      .await
+      .map(|file| Box::new(file) as ReportAsyncWrite)
+      .map_err(anyhow::Error::from),
+    None => Ok(Box::new(tokio::io::stdout()) as ReportAsyncWrite),
+  }
+}
@@ -1,21 +1,156 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 
 use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
 
-use super::{transaction::TransactionId, ClientId};
+use super::transaction::{SubAccountId, TransactionId, MAIN_SUB_ACCOUNT};
+use super::ClientId;
 
 /// This represents the state of a client account while processing transactions
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(from = "AccountState")]
 pub struct Account {
   pub locked: bool,
+  /// The funds of the client, which are the ones of all its sub-accounts.
   pub funds: Funds,
+  /// The funds of the sub-accounts other than the [`MAIN_SUB_ACCOUNT`], which has the rest of the funds of the client.
+  pub sub_accounts: BTreeMap<SubAccountId, Funds>,
   pub transactions: HashMap<TransactionId, TransactionState>,
+  /// The fees charged by the chargebacks, by the ID of the transaction charged back,
+  /// so they don't take any of the IDs that the client can still send.
+  #[serde(skip_serializing_if = "HashMap::is_empty")]
+  pub fees: HashMap<TransactionId, TransactionState>,
+  /// The number of transactions in dispute, kept by the engine as they are disputed, resolved and charged back.
+  /// It is not serialized, but counted again from the transactions when the account is deserialized.
+  #[serde(skip_serializing)]
+  pub open_disputes: usize,
 }
 
 impl Account {
   pub fn transaction_exists(&self, transaction_id: &TransactionId) -> bool {
     self.transactions.contains_key(transaction_id)
   }
+
+  /// Whether the disputes of the ID refer to the fee charged by its chargeback, which they do
+  /// once it is charged back with a fee, when the fees can be disputed.
+  pub fn disputes_fee(&self, transaction_id: TransactionId, fees_disputable: bool) -> bool {
+    fees_disputable
+      && self.fees.contains_key(&transaction_id)
+      && self
+        .transactions
+        .get(&transaction_id)
+        .map_or(false, |transaction| transaction.charged_back())
+  }
+
+  /// The transaction that the disputes of the ID refer to, which is either the transaction itself
+  /// or the fee charged by its chargeback (see [`Account::disputes_fee`]).
+  pub fn disputed_transaction(
+    &self,
+    transaction_id: TransactionId,
+    fees_disputable: bool,
+  ) -> Option<&TransactionState> {
+    if self.disputes_fee(transaction_id, fees_disputable) {
+      self.fees.get(&transaction_id)
+    } else {
+      self.transactions.get(&transaction_id)
+    }
+  }
+
+  /// All the transactions recorded by the account, including the fees charged to it.
+  pub fn all_transactions(&self) -> impl Iterator<Item = &TransactionState> {
+    self.transactions.values().chain(self.fees.values())
+  }
+
+  /// The funds of a sub-account, or `None` if they can't be represented.
+  pub fn sub_account_funds(&self, sub_account: SubAccountId) -> Option<Funds> {
+    if sub_account != MAIN_SUB_ACCOUNT {
+      return Some(
+        self
+          .sub_accounts
+          .get(&sub_account)
+          .cloned()
+          .unwrap_or_else(Funds::zero),
+      );
+    }
+    let mut funds = self.funds.clone();
+    for sub_account_funds in self.sub_accounts.values() {
+      funds.available = funds.available.checked_sub(sub_account_funds.available)?;
+      funds.held = funds.held.checked_sub(sub_account_funds.held)?;
+    }
+    Some(funds)
+  }
+
+  /// The report of the funds of all the sub-accounts, starting with the main one,
+  /// or none when the client only has the main one. The funds that can't be represented are left out.
+  pub fn sub_accounts_report(&self) -> Vec<SubAccountReport> {
+    if self.sub_accounts.is_empty() {
+      return Vec::new();
+    }
+    std::iter::once(MAIN_SUB_ACCOUNT)
+      .chain(self.sub_accounts.keys().copied())
+      .filter_map(|sub_account| {
+        let funds = self.sub_account_funds(sub_account)?;
+        Some(SubAccountReport {
+          sub_account,
+          total: funds.available.checked_add(funds.held)?,
+          available: funds.available,
+          held: funds.held,
+        })
+      })
+      .collect()
+  }
+
+  /// Replace the funds of the client with the ones changed by a transaction of the sub-account,
+  /// which gets the same change. They are not changed when the funds of the sub-account would overflow.
+  pub fn update_funds(&mut self, sub_account: SubAccountId, funds: Funds) -> Option<()> {
+    if sub_account != MAIN_SUB_ACCOUNT {
+      let mut sub_account_funds = self.sub_account_funds(sub_account)?;
+      sub_account_funds.update(
+        sub_account_funds
+          .available
+          .checked_add(funds.available.checked_sub(self.funds.available)?)?,
+        sub_account_funds
+          .held
+          .checked_add(funds.held.checked_sub(self.funds.held)?)?,
+      )?;
+      self.sub_accounts.insert(sub_account, sub_account_funds);
+    }
+    self.funds = funds;
+    Some(())
+  }
+
+  /// The funds held plus the amounts of the open disputes, which saturates when it overflows, as it is only reported.
+  pub fn exposure(&self) -> Decimal {
+    self
+      .all_transactions()
+      .filter(|transaction| transaction.in_dispute())
+      .fold(self.funds.held, |exposure, transaction| {
+        exposure
+          .checked_add(transaction.amount)
+          .unwrap_or(Decimal::MAX)
+      })
+  }
+
+  /// The amount of the fees charged to the account, without the ones refunded by a chargeback, or `None` when it overflows.
+  pub fn fees_total(&self) -> Option<Decimal> {
+    self
+      .fees
+      .values()
+      .filter(|transaction| !transaction.charged_back())
+      .try_fold(Decimal::ZERO, |total, transaction| {
+        total.checked_add(transaction.amount)
+      })
+  }
+
+  /// The amount of the transactions charged back, or `None` when it overflows.
+  pub fn charged_back_total(&self) -> Option<Decimal> {
+    self
+      .all_transactions()
+      .filter(|transaction| transaction.charged_back())
+      .try_fold(Decimal::ZERO, |total, transaction| {
+        total.checked_add(transaction.amount)
+      })
+  }
 }
 
 impl Default for Account {
@@ -23,39 +158,269 @@ impl Default for Account {
     Self {
       locked: false,
       funds: Funds::zero(),
+      sub_accounts: BTreeMap::default(),
       transactions: HashMap::default(),
+      fees: HashMap::default(),
+      open_disputes: 0,
+    }
+  }
+}
+
+/// The serialized fields of an [`Account`], from which the open disputes are counted.
+#[derive(Deserialize)]
+struct AccountState {
+  locked: bool,
+  funds: Funds,
+  #[serde(default)]
+  sub_accounts: BTreeMap<SubAccountId, Funds>,
+  transactions: HashMap<TransactionId, TransactionState>,
+  #[serde(default)]
+  fees: HashMap<TransactionId, TransactionState>,
+}
+
+impl From<AccountState> for Account {
+  fn from(state: AccountState) -> Self {
+    let open_disputes = state
+      .transactions
+      .values()
+      .chain(state.fees.values())
+      .filter(|transaction| transaction.in_dispute())
+      .count();
+    Self {
+      locked: state.locked,
+      funds: state.funds,
+      sub_accounts: state.sub_accounts,
+      transactions: state.transactions,
+      fees: state.fees,
+      open_disputes,
+    }
+  }
+}
+
+/// The kinds of transactions recorded by an account, which determine how they are disputed.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum TransactionKind {
+  Deposit,
+  Withdrawal,
+  /// Transfers are recorded by the sender to detect duplicates, but they can not be disputed.
+  Transfer,
+  /// Authorizations hold their amount until they are captured, becoming a [`TransactionKind::Deposit`],
+  /// or voided. They can not be disputed before being captured.
+  Authorization,
+  /// Voided authorizations are kept to detect duplicates, but they can not be disputed nor captured.
+  VoidedAuthorization,
+  /// Fees assessed by the engine, like the chargeback fee, are recorded as synthetic transactions,
+  /// apart from the ones of the client. They can only be disputed when the
+  /// [`SyntheticDisputePolicy`](super::SyntheticDisputePolicy) allows it, by the ID of the transaction charged back.
+  Fee,
+}
+
+impl TransactionKind {
+  pub fn is_disputable(&self) -> bool {
+    matches!(self, TransactionKind::Deposit | TransactionKind::Withdrawal)
+  }
+
+  /// Whether the transaction took its amount from the account, so charging it back refunds it.
+  pub fn is_debit(&self) -> bool {
+    matches!(self, TransactionKind::Withdrawal | TransactionKind::Fee)
+  }
+}
+
+/// The lifecycle of a recorded transaction regarding its disputes:
+///
+/// ```text
+/// Recorded -> Disputed -> Resolved -> Disputed -> ...
+///                      -> ChargedBack
+/// ```
+///
+/// Resolved transactions can be disputed again, while charged back transactions are kept
+/// in their terminal state as evidence, but they can not be disputed anymore.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum DisputeState {
+  Recorded,
+  Disputed,
+  Resolved,
+  ChargedBack,
+}
+
+impl DisputeState {
+  /// The state after disputing the transaction, or `None` if it can not be disputed from this state.
+  pub fn dispute(self) -> Option<Self> {
+    match self {
+      DisputeState::Recorded | DisputeState::Resolved => Some(DisputeState::Disputed),
+      DisputeState::Disputed | DisputeState::ChargedBack => None,
+    }
+  }
+
+  /// The state after resolving the dispute, or `None` if the transaction is not disputed.
+  pub fn resolve(self) -> Option<Self> {
+    match self {
+      DisputeState::Disputed => Some(DisputeState::Resolved),
+      _ => None,
+    }
+  }
+
+  /// The state after charging back the dispute, or `None` if the transaction is not disputed.
+  pub fn charge_back(self) -> Option<Self> {
+    match self {
+      DisputeState::Disputed => Some(DisputeState::ChargedBack),
+      _ => None,
     }
   }
 }
 
 /// This represents the state of a recorded transaction.
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct TransactionState {
-  /// The `amount` will be positive for deposits and negative for withdrawals.
+  /// The `kind` of the transaction.
+  pub kind: TransactionKind,
+  /// The `amount` of the transaction, which is always positive.
   pub amount: Decimal,
-  /// The `in_dispute` will tell whether the transaction is being disputed or not.
-  pub in_dispute: bool,
+  /// The `state` of the transaction in its [`DisputeState`] lifecycle.
+  pub state: DisputeState,
+  /// The `disputed_at` tells when the current dispute started, in the clock of the engine.
+  #[serde(default)]
+  pub disputed_at: u64,
+  /// The `expires_at` tells when an authorization can not be captured anymore, in the clock of the engine.
+  #[serde(default)]
+  pub expires_at: Option<u64>,
+  /// The `timestamp` of the transaction, in seconds since the Unix epoch, if known.
+  #[serde(default)]
+  pub timestamp: Option<u64>,
+  /// The `sub_account` of the client whose funds are changed by the transaction and its disputes.
+  #[serde(default)]
+  pub sub_account: SubAccountId,
 }
 
 impl TransactionState {
   #[cfg(test)]
   pub fn from_dispute(amount: Decimal) -> Self {
     Self {
+      kind: TransactionKind::Deposit,
       amount,
-      in_dispute: true,
+      state: DisputeState::Disputed,
+      disputed_at: 0,
+      expires_at: None,
+      timestamp: None,
+      sub_account: MAIN_SUB_ACCOUNT,
     }
   }
 
+  #[cfg(test)]
+  pub fn from_chargeback(amount: Decimal) -> Self {
+    Self {
+      kind: TransactionKind::Deposit,
+      amount,
+      state: DisputeState::ChargedBack,
+      disputed_at: 0,
+      expires_at: None,
+      timestamp: None,
+      sub_account: MAIN_SUB_ACCOUNT,
+    }
+  }
+
+  /// A deposit of the amount.
   pub fn from_amount(amount: Decimal) -> Self {
+    Self::new(TransactionKind::Deposit, amount)
+  }
+
+  pub fn from_withdrawal(amount: Decimal) -> Self {
+    Self::new(TransactionKind::Withdrawal, amount)
+  }
+
+  pub fn from_transfer(amount: Decimal) -> Self {
+    Self::new(TransactionKind::Transfer, amount)
+  }
+
+  /// A fee of the amount assessed by the engine.
+  pub fn from_fee(amount: Decimal) -> Self {
+    Self::new(TransactionKind::Fee, amount)
+  }
+
+  /// An authorization of the amount, which can be captured until it expires, if ever.
+  pub fn from_authorization(amount: Decimal, expires_at: Option<u64>) -> Self {
+    Self {
+      expires_at,
+      ..Self::new(TransactionKind::Authorization, amount)
+    }
+  }
+
+  /// The same transaction with its timestamp.
+  pub fn with_timestamp(self, timestamp: Option<u64>) -> Self {
+    Self { timestamp, ..self }
+  }
+
+  /// The same transaction of a sub-account of the client.
+  pub fn with_sub_account(self, sub_account: SubAccountId) -> Self {
+    Self {
+      sub_account,
+      ..self
+    }
+  }
+
+  fn new(kind: TransactionKind, amount: Decimal) -> Self {
     Self {
+      kind,
       amount,
-      in_dispute: false,
+      state: DisputeState::Recorded,
+      disputed_at: 0,
+      expires_at: None,
+      timestamp: None,
+      sub_account: MAIN_SUB_ACCOUNT,
+    }
+  }
+
+  /// Whether the transaction is being disputed.
+  pub fn in_dispute(&self) -> bool {
+    self.state == DisputeState::Disputed
+  }
+
+  /// Whether the transaction was reversed by a chargeback.
+  pub fn charged_back(&self) -> bool {
+    self.state == DisputeState::ChargedBack
+  }
+}
+
+/// Information about a transaction recorded by an account, as returned by the point lookups of the engine,
+/// and by the transactions report.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TransactionInfo {
+  pub client_id: ClientId,
+  pub transaction_id: TransactionId,
+  pub kind: TransactionKind,
+  pub amount: Decimal,
+  pub state: DisputeState,
+  /// When the last dispute of the transaction started, in the clock of the engine, or `None` if it was never disputed.
+  pub disputed_at: Option<u64>,
+}
+
+impl TransactionInfo {
+  pub fn new(
+    client_id: ClientId,
+    transaction_id: TransactionId,
+    transaction: &TransactionState,
+  ) -> Self {
+    Self {
+      client_id,
+      transaction_id,
+      kind: transaction.kind,
+      amount: transaction.amount,
+      state: transaction.state,
+      disputed_at: match transaction.state {
+        DisputeState::Recorded => None,
+        _ => Some(transaction.disputed_at),
+      },
     }
   }
+
+  /// Whether the transaction is being disputed.
+  pub fn in_dispute(&self) -> bool {
+    self.state == DisputeState::Disputed
+  }
 }
 
 /// Representation of the different states in which funds can be, either available or in held.
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Funds {
   pub available: Decimal,
   pub held: Decimal,
@@ -74,6 +439,86 @@ impl Funds {
     }
   }
 
+  /// Add the amount to the available funds.
+  ///
+  /// All the changes of the funds use checked arithmetic, returning `None` without changing them
+  /// when the available, held or total funds would overflow.
+  pub fn credit(&mut self, amount: Decimal) -> Option<()> {
+    self.update(self.available.checked_add(amount)?, self.held)
+  }
+
+  /// The available plus the held funds. [`Funds::update`] only accepts the funds whose total can be represented,
+  /// but the funds built otherwise saturate instead of overflowing.
+  pub fn total(&self) -> Decimal {
+    self
+      .available
+      .checked_add(self.held)
+      .unwrap_or(Decimal::MAX)
+  }
+
+  /// Take the amount from the available funds.
+  pub fn debit(&mut self, amount: Decimal) -> Option<()> {
+    self.update(self.available.checked_sub(amount)?, self.held)
+  }
+
+  /// Hold the amount of a disputed transaction. Deposits move it from the available funds,
+  /// while withdrawals hold the potential refund without touching the available funds.
+  pub fn hold(&mut self, kind: TransactionKind, amount: Decimal) -> Option<()> {
+    let available = if kind == TransactionKind::Deposit {
+      self.available.checked_sub(amount)?
+    } else {
+      self.available
+    };
+    self.update(available, self.held.checked_add(amount)?)
+  }
+
+  /// Release the amount held by a resolved dispute, undoing [`Funds::hold`].
+  pub fn release(&mut self, kind: TransactionKind, amount: Decimal) -> Option<()> {
+    let available = if kind == TransactionKind::Deposit {
+      self.available.checked_add(amount)?
+    } else {
+      self.available
+    };
+    self.update(available, self.held.checked_sub(amount)?)
+  }
+
+  /// Remove the amount held by a charged back dispute. Deposits are reversed,
+  /// while withdrawals and fees are refunded into the available funds.
+  pub fn charge_back(&mut self, kind: TransactionKind, amount: Decimal) -> Option<()> {
+    let available = if kind.is_debit() {
+      self.available.checked_add(amount)?
+    } else {
+      self.available
+    };
+    self.update(available, self.held.checked_sub(amount)?)
+  }
+
+  /// Hold the amount of an authorization, increasing the total funds until it is captured or voided.
+  pub fn authorize(&mut self, amount: Decimal) -> Option<()> {
+    self.update(self.available, self.held.checked_add(amount)?)
+  }
+
+  /// Make the amount held by an authorization available.
+  pub fn capture(&mut self, amount: Decimal) -> Option<()> {
+    self.update(
+      self.available.checked_add(amount)?,
+      self.held.checked_sub(amount)?,
+    )
+  }
+
+  /// Remove the amount held by a voided authorization.
+  pub fn void(&mut self, amount: Decimal) -> Option<()> {
+    self.update(self.available, self.held.checked_sub(amount)?)
+  }
+
+  /// Replace the funds, as long as their total can be represented, so it can always be reported.
+  fn update(&mut self, available: Decimal, held: Decimal) -> Option<()> {
+    available.checked_add(held)?;
+    self.available = available;
+    self.held = held;
+    Some(())
+  }
+
   #[cfg(test)]
   pub fn available(available: Decimal) -> Self {
     Self {
@@ -91,6 +536,21 @@ pub struct AccountReport {
   pub held: Decimal,
   pub total: Decimal,
   pub locked: bool,
+  pub open_disputes: usize,
+  pub charged_back_total: Decimal,
+  /// The amount of the fees charged to the client, like the chargeback fees.
+  pub fees: Decimal,
+  /// The escrow interest accrued by the held funds of the open disputes, when it is tracked.
+  pub escrow_interest: Option<Decimal>,
+  /// Whether the exposure of the client exceeds the threshold, when it is watched.
+  pub exposure_alert: Option<bool>,
+  /// Whether the account is dormant, when the dormancy is tracked.
+  pub dormant: Option<bool>,
+  /// The exposure of the client and its threshold, when it is watched.
+  /// Only the alert is kept by the reports that are written and read back.
+  pub exposure: Option<Exposure>,
+  /// The funds of every sub-account of the client, which are only reported when it has others than the main one.
+  pub sub_accounts: Vec<SubAccountReport>,
 }
 
 impl AccountReport {
@@ -107,8 +567,83 @@ impl AccountReport {
       held,
       total,
       locked,
+      open_disputes: 0,
+      charged_back_total: Decimal::ZERO,
+      fees: Decimal::ZERO,
+      escrow_interest: None,
+      exposure_alert: None,
+      dormant: None,
+      exposure: None,
+      sub_accounts: Vec::new(),
     }
   }
+
+  /// Add the information about the disputes, which is only reported by the newer report formats.
+  pub fn with_disputes(mut self, open_disputes: usize, charged_back_total: Decimal) -> Self {
+    self.open_disputes = open_disputes;
+    self.charged_back_total = charged_back_total;
+    self
+  }
+
+  /// Add the fees charged to the client, which are only reported by the extended report format.
+  pub fn with_fees(mut self, fees: Decimal) -> Self {
+    self.fees = fees;
+    self
+  }
+
+  /// Add the escrow interest accrued by the held funds, which is only reported when it is tracked.
+  pub fn with_escrow_interest(mut self, escrow_interest: Decimal) -> Self {
+    self.escrow_interest = Some(escrow_interest);
+    self
+  }
+
+  /// Flag whether the exposure exceeds the threshold, which is only reported when it is watched.
+  pub fn with_exposure_alert(mut self, exposure_alert: bool) -> Self {
+    self.exposure_alert = Some(exposure_alert);
+    self
+  }
+
+  /// Flag whether the account is dormant, which is only reported when the dormancy is tracked.
+  pub fn with_dormant(mut self, dormant: bool) -> Self {
+    self.dormant = Some(dormant);
+    self
+  }
+
+  /// Add the exposure of the client, flagging whether it exceeds its threshold.
+  pub fn with_exposure(mut self, exposure: Exposure) -> Self {
+    self.exposure_alert = Some(exposure.exceeded());
+    self.exposure = Some(exposure);
+    self
+  }
+
+  /// Add the funds of the sub-accounts, which are only reported when the client has others than the main one.
+  pub fn with_sub_accounts(mut self, sub_accounts: Vec<SubAccountReport>) -> Self {
+    self.sub_accounts = sub_accounts;
+    self
+  }
+}
+
+/// The exposure of a client to its disputes: the funds held plus the amounts of the open disputes,
+/// watched against the [`EngineConfig::exposure_threshold`](super::EngineConfig::exposure_threshold).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Exposure {
+  pub amount: Decimal,
+  pub threshold: Decimal,
+}
+
+impl Exposure {
+  pub fn exceeded(&self) -> bool {
+    self.amount > self.threshold
+  }
+}
+
+/// The funds of a sub-account of a client, as they are reported along with the ones of the client.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct SubAccountReport {
+  pub sub_account: SubAccountId,
+  pub available: Decimal,
+  pub held: Decimal,
+  pub total: Decimal,
 }
 
 #[cfg(test)]
@@ -131,25 +666,236 @@ mod tests {
     assert!(!account.transaction_exists(&202));
   }
 
+  #[test]
+  fn account_disputed_transaction() {
+    let account = Account {
+      transactions: vec![
+        (101, TransactionState::from_chargeback(dec!(10))),
+        (102, TransactionState::from_amount(dec!(20))),
+      ]
+      .into_iter()
+      .collect(),
+      fees: vec![(101, TransactionState::from_fee(dec!(1)))]
+        .into_iter()
+        .collect(),
+      ..Account::default()
+    };
+
+    assert_eq!(
+      account.disputed_transaction(101, true),
+      Some(&TransactionState::from_fee(dec!(1)))
+    );
+    assert_eq!(
+      account.disputed_transaction(101, false),
+      Some(&TransactionState::from_chargeback(dec!(10)))
+    );
+    assert_eq!(
+      account.disputed_transaction(102, true),
+      Some(&TransactionState::from_amount(dec!(20)))
+    );
+    assert_eq!(account.disputed_transaction(103, true), None);
+  }
+
+  #[test]
+  fn account_open_disputes_deserialized() {
+    let account = Account {
+      transactions: vec![
+        (101, TransactionState::from_amount(dec!(10))),
+        (102, TransactionState::from_dispute(dec!(20))),
+        (103, TransactionState::from_dispute(dec!(30))),
+      ]
+      .into_iter()
+      .collect(),
+      ..Account::default()
+    };
+
+    let serialized = serde_json::to_string(&account).unwrap();
+    assert!(!serialized.contains("open_disputes"));
+    assert!(!serialized.contains("fees"));
+
+    let account: Account = serde_json::from_str(&serialized).unwrap();
+    assert_eq!(account.open_disputes, 2);
+
+    let account = Account {
+      fees: vec![(
+        101,
+        TransactionState {
+          state: DisputeState::Disputed,
+          ..TransactionState::from_fee(dec!(1))
+        },
+      )]
+      .into_iter()
+      .collect(),
+      ..account
+    };
+    let serialized = serde_json::to_string(&account).unwrap();
+    let account: Account = serde_json::from_str(&serialized).unwrap();
+    assert_eq!(account.open_disputes, 3);
+  }
+
+  #[test]
+  fn account_exposure() {
+    let account = Account {
+      funds: Funds::new(dec!(50), dec!(30)),
+      transactions: vec![
+        (101, TransactionState::from_amount(dec!(10))),
+        (102, TransactionState::from_dispute(dec!(20))),
+        (103, TransactionState::from_chargeback(dec!(30))),
+      ]
+      .into_iter()
+      .collect(),
+      open_disputes: 1,
+      ..Account::default()
+    };
+
+    assert_eq!(account.exposure(), dec!(50));
+    assert_eq!(Account::default().exposure(), dec!(0));
+  }
+
+  #[test]
+  fn account_charged_back_total() {
+    let account = Account {
+      transactions: vec![
+        (101, TransactionState::from_chargeback(dec!(10))),
+        (102, TransactionState::from_dispute(dec!(20))),
+        (103, TransactionState::from_chargeback(dec!(30))),
+      ]
+      .into_iter()
+      .collect(),
+      open_disputes: 1,
+      ..Account::default()
+    };
+
+    assert_eq!(account.charged_back_total(), Some(dec!(40)));
+    assert_eq!(Account::default().charged_back_total(), Some(dec!(0)));
+
+    let account = Account {
+      transactions: vec![
+        (101, TransactionState::from_chargeback(Decimal::MAX)),
+        (102, TransactionState::from_chargeback(dec!(1))),
+      ]
+      .into_iter()
+      .collect(),
+      ..Account::default()
+    };
+
+    assert_eq!(account.charged_back_total(), None);
+  }
+
   #[test]
   fn transaction_state_constructors() {
     assert_eq!(
       TransactionState::from_dispute(dec!(10)),
       TransactionState {
+        kind: TransactionKind::Deposit,
+        amount: dec!(10),
+        state: DisputeState::Disputed,
+        disputed_at: 0,
+        expires_at: None,
+        timestamp: None,
+        sub_account: MAIN_SUB_ACCOUNT,
+      }
+    );
+
+    assert_eq!(
+      TransactionState::from_chargeback(dec!(10)),
+      TransactionState {
+        kind: TransactionKind::Deposit,
         amount: dec!(10),
-        in_dispute: true
+        state: DisputeState::ChargedBack,
+        disputed_at: 0,
+        expires_at: None,
+        timestamp: None,
+        sub_account: MAIN_SUB_ACCOUNT,
       }
     );
 
     assert_eq!(
       TransactionState::from_amount(dec!(10)),
       TransactionState {
+        kind: TransactionKind::Deposit,
         amount: dec!(10),
-        in_dispute: false
+        state: DisputeState::Recorded,
+        disputed_at: 0,
+        expires_at: None,
+        timestamp: None,
+        sub_account: MAIN_SUB_ACCOUNT,
       }
     );
   }
 
+  #[test]
+  fn dispute_state_transitions() {
+    use DisputeState::*;
+
+    assert_eq!(Recorded.dispute(), Some(Disputed));
+    assert_eq!(Resolved.dispute(), Some(Disputed));
+    assert_eq!(Disputed.dispute(), None);
+    assert_eq!(ChargedBack.dispute(), None);
+
+    assert_eq!(Disputed.resolve(), Some(Resolved));
+    assert_eq!(Disputed.charge_back(), Some(ChargedBack));
+    for state in [Recorded, Resolved, ChargedBack].iter() {
+      assert_eq!(state.resolve(), None);
+      assert_eq!(state.charge_back(), None);
+    }
+  }
+
+  #[test]
+  fn funds_authorizations() {
+    let mut funds = Funds::new(dec!(100), dec!(0));
+
+    funds.authorize(dec!(30)).unwrap();
+    assert_eq!(funds, Funds::new(dec!(100), dec!(30)));
+    funds.capture(dec!(10)).unwrap();
+    assert_eq!(funds, Funds::new(dec!(110), dec!(20)));
+    funds.void(dec!(20)).unwrap();
+    assert_eq!(funds, Funds::new(dec!(110), dec!(0)));
+  }
+
+  #[test]
+  fn funds_disputes_by_kind() {
+    let mut funds = Funds::new(dec!(100), dec!(0));
+
+    funds.hold(TransactionKind::Deposit, dec!(10)).unwrap();
+    assert_eq!(funds, Funds::new(dec!(90), dec!(10)));
+    funds.release(TransactionKind::Deposit, dec!(10)).unwrap();
+    assert_eq!(funds, Funds::new(dec!(100), dec!(0)));
+    funds.hold(TransactionKind::Deposit, dec!(10)).unwrap();
+    funds
+      .charge_back(TransactionKind::Deposit, dec!(10))
+      .unwrap();
+    assert_eq!(funds, Funds::new(dec!(90), dec!(0)));
+
+    funds.hold(TransactionKind::Withdrawal, dec!(20)).unwrap();
+    assert_eq!(funds, Funds::new(dec!(90), dec!(20)));
+    funds
+      .release(TransactionKind::Withdrawal, dec!(20))
+      .unwrap();
+    assert_eq!(funds, Funds::new(dec!(90), dec!(0)));
+    funds.hold(TransactionKind::Withdrawal, dec!(20)).unwrap();
+    funds
+      .charge_back(TransactionKind::Withdrawal, dec!(20))
+      .unwrap();
+    assert_eq!(funds, Funds::new(dec!(110), dec!(0)));
+  }
+
+  #[test]
+  fn funds_overflow() {
+    let mut funds = Funds::new(Decimal::MAX, dec!(0));
+
+    assert_eq!(funds.credit(dec!(1)), None);
+    assert_eq!(funds.hold(TransactionKind::Withdrawal, dec!(1)), None);
+    assert_eq!(funds, Funds::new(Decimal::MAX, dec!(0)));
+
+    assert_eq!(funds.hold(TransactionKind::Deposit, dec!(1)), Some(()));
+    assert_eq!(funds, Funds::new(Decimal::MAX - dec!(1), dec!(1)));
+
+    let mut funds = Funds::new(Decimal::MIN, dec!(0));
+    assert_eq!(funds.debit(dec!(1)), None);
+    assert_eq!(funds, Funds::new(Decimal::MIN, dec!(0)));
+  }
+
   #[test]
   fn funds_constructors() {
     assert_eq!(
@@ -187,6 +933,33 @@ mod tests {
         held: dec!(10),
         total: dec!(110),
         locked: true,
+        open_disputes: 0,
+        charged_back_total: dec!(0),
+        fees: dec!(0),
+        escrow_interest: None,
+        exposure_alert: None,
+        dormant: None,
+        exposure: None,
+        sub_accounts: Vec::new(),
+      }
+    );
+
+    assert_eq!(
+      AccountReport::new(1, dec!(100), dec!(10), dec!(110), true).with_disputes(2, dec!(5)),
+      AccountReport {
+        client_id: 1,
+        available: dec!(100),
+        held: dec!(10),
+        total: dec!(110),
+        locked: true,
+        open_disputes: 2,
+        charged_back_total: dec!(5),
+        fees: dec!(0),
+        escrow_interest: None,
+        exposure_alert: None,
+        dormant: None,
+        exposure: None,
+        sub_accounts: Vec::new(),
       }
     )
   }
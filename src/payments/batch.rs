@@ -0,0 +1,264 @@
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+#[cfg(feature = "postgres")]
+use super::transaction::TransactionId;
+use super::{
+  account::Account,
+  config::EngineConfig,
+  engine::{InMemoryPaymentsEngine, Result},
+  limits::RecentActivity,
+  snapshot::Snapshot,
+  transaction::ClientId,
+};
+
+/// The writes of an engine backed by a database, coalesced in memory until they are flushed with batched statements.
+///
+/// It caches the accounts of the clients of the transactions processed since the last flush, loading them from the database
+/// only the first time, so the transactions are applied to the cached accounts and only their latest state is written.
+/// It also counts the transactions applied, which is the position of the write-ahead log whose transactions are all
+/// in the database once they are flushed, so only the ones after it have to be replayed after a crash.
+///
+/// The accounts are not locked in the database while they are cached, so it can only have one writer.
+pub(crate) struct WriteBatch {
+  size: usize,
+  position: u64,
+  pending: usize,
+  /// The clients loaded from the database, including the ones without an account, and the clock with them.
+  loaded: HashSet<ClientId>,
+  cached: Snapshot,
+  changed: BTreeSet<ClientId>,
+  /// The IDs of the transactions recorded by the accounts, with their type.
+  #[cfg(feature = "postgres")]
+  recorded: Vec<(ClientId, TransactionId, &'static str)>,
+}
+
+impl WriteBatch {
+  /// Coalesce the writes of up to `size` transactions, continuing from the position of the log already in the database.
+  pub(crate) fn new(size: usize, position: u64) -> Self {
+    Self {
+      size: size.max(1),
+      position,
+      pending: 0,
+      loaded: HashSet::new(),
+      cached: Snapshot {
+        config_digest: String::new(),
+        accounts: HashMap::new(),
+        clock: 0,
+        activity: HashMap::new(),
+      },
+      changed: BTreeSet::new(),
+      #[cfg(feature = "postgres")]
+      recorded: Vec::new(),
+    }
+  }
+
+  /// The position of the write-ahead log with the transactions applied, flushed or not.
+  pub(crate) fn position(&self) -> u64 {
+    self.position
+  }
+
+  /// The clients whose accounts are not cached yet, which have to be loaded from the database.
+  pub(crate) fn missing(&self, clients: &[ClientId]) -> Vec<ClientId> {
+    let mut missing: Vec<ClientId> = clients
+      .iter()
+      .copied()
+      .filter(|client_id| !self.loaded.contains(client_id))
+      .collect();
+    missing.sort_unstable();
+    missing.dedup();
+    missing
+  }
+
+  /// Cache the accounts of the clients loaded from the database, which have none when they are not in the snapshot.
+  /// The clock is only taken from the first snapshot, as the cached one may be ahead of the database.
+  pub(crate) fn insert(&mut self, clients: &[ClientId], mut loaded: Snapshot) {
+    if self.loaded.is_empty() {
+      self.cached.config_digest = loaded.config_digest.clone();
+      self.cached.clock = loaded.clock;
+    }
+    for client_id in clients {
+      if self.loaded.insert(*client_id) {
+        if let Some(account) = loaded.accounts.remove(client_id) {
+          self.cached.accounts.insert(*client_id, account);
+        }
+        if let Some(activity) = loaded.activity.remove(client_id) {
+          self.cached.activity.insert(*client_id, activity);
+        }
+      }
+    }
+  }
+
+  /// An engine with the cached accounts of the clients, which have to be loaded first.
+  pub(crate) fn engine(
+    &self,
+    config: &EngineConfig,
+    clients: &[ClientId],
+  ) -> Result<InMemoryPaymentsEngine> {
+    let snapshot = Snapshot {
+      config_digest: self.cached.config_digest.clone(),
+      accounts: clients
+        .iter()
+        .filter_map(|client_id| {
+          let account = self.cached.accounts.get(client_id)?;
+          Some((*client_id, account.clone()))
+        })
+        .collect(),
+      clock: self.cached.clock,
+      activity: clients
+        .iter()
+        .filter_map(|client_id| {
+          let activity = self.cached.activity.get(client_id)?;
+          Some((*client_id, activity.clone()))
+        })
+        .collect(),
+    };
+    let mut engine = InMemoryPaymentsEngine::with_config(config.clone());
+    engine.restore_with_config_change(snapshot)?;
+    Ok(engine)
+  }
+
+  /// Cache the accounts of the clients after a transaction was applied to them, so they are written by the next flush.
+  pub(crate) fn update(
+    &mut self,
+    engine: &InMemoryPaymentsEngine,
+    clients: &[ClientId],
+  ) -> Result<()> {
+    let mut snapshot = engine.snapshot()?;
+    for client_id in clients {
+      if let Some(account) = snapshot.accounts.remove(client_id) {
+        self.cached.accounts.insert(*client_id, account);
+      }
+      if let Some(activity) = snapshot.activity.remove(client_id) {
+        self.cached.activity.insert(*client_id, activity);
+      }
+      self.changed.insert(*client_id);
+    }
+    self.cached.clock = snapshot.clock;
+    self.pending += 1;
+    self.position += 1;
+    Ok(())
+  }
+
+  /// Keep the ID of a transaction recorded by the account of the client, to be inserted by the next flush.
+  #[cfg(feature = "postgres")]
+  pub(crate) fn record(
+    &mut self,
+    client_id: ClientId,
+    transaction_id: TransactionId,
+    type_name: &'static str,
+  ) {
+    self.recorded.push((client_id, transaction_id, type_name));
+  }
+
+  /// The IDs of the transactions recorded since the last flush.
+  #[cfg(feature = "postgres")]
+  pub(crate) fn recorded(&self) -> &[(ClientId, TransactionId, &'static str)] {
+    &self.recorded
+  }
+
+  /// Whether the batch has as many transactions as its size, so it has to be flushed.
+  pub(crate) fn is_full(&self) -> bool {
+    self.pending >= self.size
+  }
+
+  /// Whether there is nothing to flush.
+  pub(crate) fn is_empty(&self) -> bool {
+    self.pending == 0
+  }
+
+  /// The accounts changed since the last flush, in ascending order of client ID.
+  pub(crate) fn changed_accounts(&self) -> Vec<(ClientId, &Account)> {
+    self
+      .changed
+      .iter()
+      .filter_map(|client_id| Some((*client_id, self.cached.accounts.get(client_id)?)))
+      .collect()
+  }
+
+  /// The recent activity of the clients changed since the last flush, in ascending order of client ID.
+  pub(crate) fn changed_activity(&self) -> Vec<(ClientId, &RecentActivity)> {
+    self
+      .changed
+      .iter()
+      .filter_map(|client_id| Some((*client_id, self.cached.activity.get(client_id)?)))
+      .collect()
+  }
+
+  /// The clock of the engine after the transactions applied.
+  pub(crate) fn clock(&self) -> u64 {
+    self.cached.clock
+  }
+
+  /// Forget the cached accounts once they are flushed, so they are loaded again by the next transactions.
+  pub(crate) fn clear(&mut self) {
+    self.pending = 0;
+    self.loaded.clear();
+    self.cached.accounts.clear();
+    self.cached.activity.clear();
+    self.changed.clear();
+    #[cfg(feature = "postgres")]
+    self.recorded.clear();
+  }
+}
+
+#[cfg(test)]
+mod tests {
+
+  use rust_decimal::Decimal;
+  use rust_decimal_macros::dec;
+
+  use super::*;
+  use crate::payments::{PaymentsEngine, SyncPaymentsEngine, Transaction};
+
+  fn deposit(client_id: ClientId, transaction_id: u32) -> Transaction {
+    Transaction::Deposit {
+      client_id,
+      transaction_id,
+      amount: dec!(10),
+      timestamp: None,
+      sub_account: 0,
+    }
+  }
+
+  #[test]
+  fn coalesce_the_changes_of_the_accounts() {
+    let config = EngineConfig::default();
+    let mut stored = InMemoryPaymentsEngine::new();
+    stored.process_sync(deposit(1, 101)).unwrap();
+    let mut batch = WriteBatch::new(3, 7);
+
+    assert_eq!(batch.missing(&[2, 1, 2]), vec![1, 2]);
+    batch.insert(&[1, 2], stored.snapshot().unwrap());
+    assert!(batch.missing(&[1, 2]).is_empty());
+    for (client_id, transaction_id) in vec![(1, 102), (2, 201), (1, 103)] {
+      let mut engine = batch.engine(&config, &[client_id]).unwrap();
+      engine
+        .process_sync(deposit(client_id, transaction_id))
+        .unwrap();
+      batch.update(&engine, &[client_id]).unwrap();
+    }
+
+    assert!(batch.is_full());
+    assert_eq!(batch.position(), 10);
+    let changed: Vec<(ClientId, Decimal)> = batch
+      .changed_accounts()
+      .into_iter()
+      .map(|(client_id, account)| (client_id, account.funds.total()))
+      .collect();
+    assert_eq!(changed, vec![(1, dec!(30)), (2, dec!(10))]);
+    assert_eq!(
+      batch
+        .engine(&config, &[1])
+        .unwrap()
+        .account(1)
+        .unwrap()
+        .total,
+      dec!(30)
+    );
+
+    batch.clear();
+    assert!(batch.is_empty());
+    assert_eq!(batch.position(), 10);
+    assert_eq!(batch.missing(&[1]), vec![1]);
+  }
+}
@@ -0,0 +1,214 @@
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+
+use super::{
+  AccountFilter, AccountReport, AccountsReportIter, AccountsReportStream, ClientId,
+  InMemoryPaymentsEngine, PaymentsEngine, PaymentsEngineError, Transaction, TransactionId,
+  TransactionInfo, TransactionsReportIter,
+};
+use crate::io::Checkpoints;
+
+/// When the [`CheckpointingPaymentsEngine`] takes a new snapshot: after some time, or after some transactions,
+/// whatever comes first. Nothing is taken when no transaction was processed since the last snapshot.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct CheckpointSchedule {
+  pub interval: Option<Duration>,
+  pub transactions: Option<u64>,
+}
+
+impl CheckpointSchedule {
+  fn is_due(&self, pending: u64, elapsed: Duration) -> bool {
+    pending > 0
+      && (self
+        .transactions
+        .map_or(false, |transactions| pending >= transactions)
+        || self.interval.map_or(false, |interval| elapsed >= interval))
+  }
+}
+
+/// A [`PaymentsEngine`] middleware that snapshots the state of an [`InMemoryPaymentsEngine`] into some [`Checkpoints`]
+/// as scheduled, so it can be recovered without replaying the whole write-ahead log.
+///
+/// It has to be wrapped by the [`WalPaymentsEngine`](super::WalPaymentsEngine), so every transaction it processes
+/// is a record of the log, and every snapshot is taken at the position of the log that the recovery has to replay from
+/// (see [`replay_after`](super::replay_after)). The schedule is checked after every transaction, so an idle engine
+/// doesn't take snapshots, and the transactions since the last one are only recovered from the log.
+///
+/// Failing to take a snapshot doesn't reject the transaction, as the log still has it, and it is retried as scheduled.
+pub struct CheckpointingPaymentsEngine {
+  inner: InMemoryPaymentsEngine,
+  checkpoints: Checkpoints,
+  schedule: CheckpointSchedule,
+  position: u64,
+  pending: u64,
+  last_checkpoint: Instant,
+}
+
+impl CheckpointingPaymentsEngine {
+  /// Take snapshots of the engine, whose state is the one of the log at the position.
+  pub fn new(
+    inner: InMemoryPaymentsEngine,
+    checkpoints: Checkpoints,
+    schedule: CheckpointSchedule,
+    position: u64,
+  ) -> Self {
+    Self {
+      inner,
+      checkpoints,
+      schedule,
+      position,
+      pending: 0,
+      last_checkpoint: Instant::now(),
+    }
+  }
+
+  /// The position of the log whose state the engine has, which is the number of transactions processed.
+  pub fn position(&self) -> u64 {
+    self.position
+  }
+
+  async fn checkpoint(&mut self) {
+    let written = match self.inner.snapshot() {
+      Ok(snapshot) => self.checkpoints.write(self.position, &snapshot).await,
+      Err(err) => Err(err.into()),
+    };
+    match written {
+      Ok(path) => {
+        tracing::info!(position = self.position, path = %path.display(), "Snapshot taken")
+      }
+      Err(err) => {
+        tracing::warn!(error = %err, position = self.position, "Failed to take a snapshot")
+      }
+    }
+    self.pending = 0;
+    self.last_checkpoint = Instant::now();
+  }
+}
+
+#[async_trait]
+impl PaymentsEngine for CheckpointingPaymentsEngine {
+  async fn process(
+    &mut self,
+    transaction: Transaction,
+  ) -> core::result::Result<(), PaymentsEngineError> {
+    let result = self.inner.process(transaction).await;
+    // every transaction is a record of the log, even the ones rejected, which fail the replay anyway
+    self.position += 1;
+    self.pending += 1;
+    if self
+      .schedule
+      .is_due(self.pending, self.last_checkpoint.elapsed())
+    {
+      self.checkpoint().await;
+    }
+    result
+  }
+
+  fn validate(&self, transaction: &Transaction) -> core::result::Result<(), PaymentsEngineError> {
+    self.inner.validate(transaction)
+  }
+
+  fn accounts_report(&self) -> AccountsReportIter {
+    self.inner.accounts_report()
+  }
+
+  fn accounts_matching(&self, filter: AccountFilter) -> AccountsReportIter {
+    self.inner.accounts_matching(filter)
+  }
+
+  fn accounts_report_stream(&self) -> AccountsReportStream {
+    self.inner.accounts_report_stream()
+  }
+
+  fn account(&self, client_id: ClientId) -> Option<AccountReport> {
+    self.inner.account(client_id)
+  }
+
+  fn transaction(
+    &self,
+    client_id: ClientId,
+    transaction_id: TransactionId,
+  ) -> core::result::Result<Option<TransactionInfo>, PaymentsEngineError> {
+    self.inner.transaction(client_id, transaction_id)
+  }
+
+  fn transactions_report(
+    &self,
+  ) -> core::result::Result<TransactionsReportIter, PaymentsEngineError> {
+    self.inner.transactions_report()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+
+  use rust_decimal_macros::dec;
+
+  use super::*;
+  use crate::payments::{replay_after, WalPaymentsEngine};
+
+  fn deposit(transaction_id: TransactionId) -> Transaction {
+    Transaction::Deposit {
+      client_id: 1,
+      transaction_id,
+      amount: dec!(10),
+      timestamp: None,
+      sub_account: 0,
+    }
+  }
+
+  #[test]
+  fn schedule_by_transactions_or_time() {
+    let schedule = CheckpointSchedule {
+      interval: Some(Duration::from_secs(60)),
+      transactions: Some(3),
+    };
+
+    assert!(!schedule.is_due(0, Duration::from_secs(120)));
+    assert!(!schedule.is_due(2, Duration::from_secs(10)));
+    assert!(schedule.is_due(3, Duration::from_secs(10)));
+    assert!(schedule.is_due(1, Duration::from_secs(60)));
+    assert!(!CheckpointSchedule::default().is_due(100, Duration::from_secs(3600)));
+  }
+
+  #[tokio::test]
+  async fn recover_from_the_latest_snapshot_and_the_log() {
+    let dir = std::env::temp_dir().join(format!(
+      "toy-payments-engine-checkpointing-{}",
+      std::process::id()
+    ));
+    tokio::fs::remove_dir_all(&dir).await.ok();
+    let schedule = CheckpointSchedule {
+      interval: None,
+      transactions: Some(2),
+    };
+
+    let mut log = Vec::<u8>::new();
+    let mut engine = WalPaymentsEngine::new(
+      CheckpointingPaymentsEngine::new(
+        InMemoryPaymentsEngine::new(),
+        Checkpoints::new(&dir, 2),
+        schedule,
+        0,
+      ),
+      &mut log,
+    );
+    for transaction_id in 1..=5 {
+      engine.process(deposit(transaction_id)).await.unwrap();
+    }
+    drop(engine);
+
+    let (position, snapshot) = Checkpoints::new(&dir, 2).latest().await.unwrap().unwrap();
+    let mut recovered = InMemoryPaymentsEngine::new();
+    recovered.restore(snapshot).unwrap();
+    let replayed = replay_after(log.as_slice(), &mut recovered, position as usize)
+      .await
+      .unwrap();
+    tokio::fs::remove_dir_all(&dir).await.unwrap();
+
+    assert_eq!(position, 4);
+    assert_eq!(replayed, 1);
+    assert_eq!(recovered.account(1).unwrap().total, dec!(50));
+  }
+}
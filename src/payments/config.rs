@@ -0,0 +1,290 @@
+use rust_decimal::Decimal;
+use sha2::{Digest, Sha256};
+
+use super::limits::LimitsPolicy;
+
+/// Configuration of the policies applied by the [`InMemoryPaymentsEngine`](super::InMemoryPaymentsEngine).
+///
+/// The default configuration doesn't enforce any limit.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct EngineConfig {
+  /// Maximum number of disputes that a client can have open at the same time, or `None` for no limit.
+  pub max_open_disputes: Option<usize>,
+
+  /// Whether the accounts report is generated in ascending order of client ID.
+  /// Useful to compare reports from different runs, at the cost of sorting all the accounts.
+  pub deterministic: bool,
+
+  /// What to do with disputes on accounts that have been locked by a chargeback.
+  pub locked_account_dispute_policy: LockedAccountDisputePolicy,
+
+  /// What to do with deposits and withdrawals of a zero amount.
+  pub zero_amount_policy: ZeroAmountPolicy,
+
+  /// Fee assessed to the account when one of its transactions is charged back, or `None` for no fee.
+  pub chargeback_fee: Option<ChargebackFee>,
+
+  /// What to do with the funds still held by open disputes when a locked account is unlocked.
+  pub unlock_held_funds_policy: UnlockHeldFundsPolicy,
+
+  /// Escrow interest accrued by the held funds of a dispute for every full day they are held, or `None` to not track it.
+  /// The days are counted with the timestamps of the transactions, so the interest is the same when replaying them,
+  /// and the transactions without a timestamp happen at the latest timestamp seen.
+  /// The interest is settled on resolves and chargebacks, and only credited when the client wins the dispute.
+  pub escrow_interest_rate: Option<Decimal>,
+
+  /// Maximum exposure of a client, which is the amount of their held funds (including the disputed withdrawals)
+  /// plus the amounts of their open disputes, or `None` to not watch it.
+  /// The accounts exceeding it are flagged in the report as an early warning of risky disputes,
+  /// and an [`EngineEvent::ExposureExceeded`](super::EngineEvent::ExposureExceeded) is emitted when they cross it.
+  /// It doesn't change how the transactions are processed, so it is not part of the [`EngineConfig::digest`].
+  pub exposure_threshold: Option<Decimal>,
+
+  /// Number of seconds after an authorization during which it can be captured, or `None` for authorizations that never expire.
+  /// The seconds are counted with the timestamps of the transactions, as the escrow interest days are. Expired authorizations keep their funds held until they are voided.
+  pub authorization_expiry: Option<u64>,
+
+  /// Maximum number of days between a deposit or withdrawal and its dispute, or `None` for no limit.
+  /// It is only enforced when both the transaction and the dispute have a timestamp.
+  pub dispute_window_days: Option<u64>,
+
+  /// Limits on the withdrawals of every client, or `None` for no limits.
+  pub limits: Option<LimitsPolicy>,
+
+  /// What to do with resolves and chargebacks of transactions already resolved or charged back.
+  pub duplicate_policy: DuplicatePolicy,
+
+  /// Days without activity after which an account is dormant, and whether its withdrawals are blocked, or `None` to not track it.
+  pub dormancy: Option<DormancyPolicy>,
+
+  /// What to do with disputes on the synthetic transactions generated by the engine, like the chargeback fees.
+  pub synthetic_dispute_policy: SyntheticDisputePolicy,
+}
+
+impl EngineConfig {
+  /// Whether the engine tracks the latest timestamp of the accepted transactions as its clock, which is only needed by some policies.
+  pub(crate) fn ticks_clock(&self) -> bool {
+    self.escrow_interest_rate.is_some()
+      || self.authorization_expiry.is_some()
+      || self.dormancy.is_some()
+  }
+
+  /// The hex encoded SHA-256 of a canonical representation of the configuration.
+  /// Equivalent configurations have the same digest (like fees of `15` and `15.00`),
+  /// so it can be used to detect changes of the policies between runs that continue the same processing.
+  pub fn digest(&self) -> String {
+    let chargeback_fee = self.chargeback_fee.map(|fee| {
+      format!(
+        "{}:{}",
+        fee.amount.normalize(),
+        fee.allow_negative_available
+      )
+    });
+    let escrow_interest_rate = self.escrow_interest_rate.map(|rate| rate.normalize());
+    let limits = self.limits.as_ref().map(|limits| {
+      format!(
+        "{:?}:{:?}:{:?}",
+        limits.max_withdrawal.map(|amount| amount.normalize()),
+        limits
+          .max_daily_withdrawals
+          .map(|amount| amount.normalize()),
+        limits.max_transactions_per_minute,
+      )
+    });
+    let canonical = format!(
+      "max_open_disputes={:?};deterministic={};locked_account_dispute_policy={:?};zero_amount_policy={:?};chargeback_fee={:?};unlock_held_funds_policy={:?};escrow_interest_rate={:?};authorization_expiry={:?};dispute_window_days={:?};limits={:?};duplicate_policy={:?};dormancy={:?};synthetic_dispute_policy={:?}",
+      self.max_open_disputes,
+      self.deterministic,
+      self.locked_account_dispute_policy,
+      self.zero_amount_policy,
+      chargeback_fee,
+      self.unlock_held_funds_policy,
+      escrow_interest_rate,
+      self.authorization_expiry,
+      self.dispute_window_days,
+      limits,
+      self.duplicate_policy,
+      self.dormancy,
+      self.synthetic_dispute_policy,
+    );
+
+    let mut hasher = Sha256::new();
+    hasher.update(canonical.as_bytes());
+    format!("{:x}", hasher.finalize())
+  }
+}
+
+/// Policy for disputes on accounts that have been locked by a chargeback.
+/// Deposits and withdrawals are always rejected for locked accounts.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LockedAccountDisputePolicy {
+  /// Reject the disputes with [`PaymentsEngineError::AccountLocked`](super::PaymentsEngineError::AccountLocked).
+  Reject,
+  /// Allow disputing and resolving earlier transactions, so they can still be investigated.
+  Allow,
+}
+
+impl Default for LockedAccountDisputePolicy {
+  fn default() -> Self {
+    LockedAccountDisputePolicy::Reject
+  }
+}
+
+/// Policy for disputes on the synthetic transactions generated by the engine, like the chargeback fees.
+/// The fees are disputed by the ID of the transaction whose chargeback charged them, once it is charged back.
+/// Disputing a fee holds its amount as the disputes of withdrawals do, and charging it back refunds it without another fee.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SyntheticDisputePolicy {
+  /// Reject the disputes with [`PaymentsEngineError::TransactionNotDisputable`](super::PaymentsEngineError::TransactionNotDisputable).
+  Reject,
+  /// Allow disputing them as any other debit of the account.
+  Allow,
+}
+
+impl Default for SyntheticDisputePolicy {
+  fn default() -> Self {
+    SyntheticDisputePolicy::Reject
+  }
+}
+
+/// Policy for deposits and withdrawals of a zero amount.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ZeroAmountPolicy {
+  /// Accept them. They don't change the funds, but zero deposits are recorded and can be disputed.
+  Accept,
+  /// Reject them with [`PaymentsEngineError::ZeroAmount`](super::PaymentsEngineError::ZeroAmount).
+  Reject,
+  /// Ignore them successfully, without recording them nor creating the account.
+  Skip,
+}
+
+impl Default for ZeroAmountPolicy {
+  fn default() -> Self {
+    ZeroAmountPolicy::Accept
+  }
+}
+
+/// Policy for the funds held by the open disputes of an account when it is unlocked.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UnlockHeldFundsPolicy {
+  /// Keep the funds held, so the open disputes can still be resolved or charged back.
+  Keep,
+  /// Resolve all the open disputes, restoring their held funds the same way than a resolve.
+  Release,
+}
+
+impl Default for UnlockHeldFundsPolicy {
+  fn default() -> Self {
+    UnlockHeldFundsPolicy::Keep
+  }
+}
+
+/// Policy for resolves and chargebacks that were already applied, like the ones retried by an upstream system.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DuplicatePolicy {
+  /// Reject them with [`PaymentsEngineError::TransactionNotDisputed`](super::PaymentsEngineError::TransactionNotDisputed).
+  Reject,
+  /// Accept them successfully without any change, when the transaction is still resolved or charged back.
+  /// A resolve of a transaction disputed again is applied to the new dispute.
+  Idempotent,
+}
+
+impl Default for DuplicatePolicy {
+  fn default() -> Self {
+    DuplicatePolicy::Reject
+  }
+}
+
+/// Fee assessed to an account when one of its transactions is charged back.
+/// It is taken from the available funds, and recorded with the charged back transaction.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ChargebackFee {
+  pub amount: Decimal,
+  /// Whether the fee can leave the available funds negative. Otherwise, it is capped to the available funds.
+  pub allow_negative_available: bool,
+}
+
+impl ChargebackFee {
+  /// The part of the fee that can be charged to an account with the given available funds.
+  pub fn charge(&self, available: Decimal) -> Decimal {
+    if self.allow_negative_available {
+      self.amount
+    } else {
+      self.amount.min(available.max(Decimal::ZERO))
+    }
+  }
+}
+
+/// Policy for the accounts without activity for a number of days, modeling the dormancy rules of the regulators.
+///
+/// The days are counted with the timestamps of the transactions, as the escrow interest days are,
+/// from the last transaction accepted for the client (the sender of the transfers) while the account was active.
+/// Once dormant, only a [`Transaction::Reactivate`](super::Transaction::Reactivate) makes the account active again,
+/// and the accounts without any transaction since the dormancy is tracked are never dormant.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DormancyPolicy {
+  pub days: u64,
+  /// Whether the withdrawals and transfers out of the dormant accounts are rejected. Otherwise, they are only flagged in the report.
+  pub block_withdrawals: bool,
+}
+
+#[cfg(test)]
+mod tests {
+
+  use rust_decimal_macros::dec;
+
+  use super::*;
+
+  #[test]
+  fn chargeback_fee_charge() {
+    let capped = ChargebackFee {
+      amount: dec!(15),
+      allow_negative_available: false,
+    };
+    let uncapped = ChargebackFee {
+      allow_negative_available: true,
+      ..capped
+    };
+
+    assert_eq!(capped.charge(dec!(100)), dec!(15));
+    assert_eq!(capped.charge(dec!(10)), dec!(10));
+    assert_eq!(capped.charge(dec!(-5)), dec!(0));
+    assert_eq!(uncapped.charge(dec!(10)), dec!(15));
+  }
+
+  #[test]
+  fn config_digest() {
+    let config = EngineConfig {
+      chargeback_fee: Some(ChargebackFee {
+        amount: dec!(15),
+        allow_negative_available: false,
+      }),
+      ..EngineConfig::default()
+    };
+    let equivalent = EngineConfig {
+      chargeback_fee: Some(ChargebackFee {
+        amount: dec!(15.00),
+        allow_negative_available: false,
+      }),
+      ..EngineConfig::default()
+    };
+    let different = EngineConfig {
+      zero_amount_policy: ZeroAmountPolicy::Reject,
+      ..config.clone()
+    };
+    let dormant = EngineConfig {
+      dormancy: Some(DormancyPolicy {
+        days: 365,
+        block_withdrawals: false,
+      }),
+      ..config.clone()
+    };
+
+    assert_eq!(config.digest().len(), 64);
+    assert_eq!(config.digest(), equivalent.digest());
+    assert_ne!(config.digest(), different.digest());
+    assert_ne!(config.digest(), dormant.digest());
+    assert!(dormant.ticks_clock());
+    assert_ne!(config.digest(), EngineConfig::default().digest());
+  }
+}
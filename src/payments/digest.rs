@@ -0,0 +1,159 @@
+use std::collections::BTreeMap;
+use std::ops::RangeInclusive;
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use super::{account::AccountReport, transaction::ClientId};
+
+/// Number of consecutive client IDs whose accounts are hashed together into a bucket.
+const BUCKET_CLIENTS: u32 = 256;
+
+/// A Merkle-style digest of the accounts of an engine, to detect the replicas that diverged
+/// while processing the same transactions.
+///
+/// The accounts are hashed into buckets of consecutive client IDs, and the buckets into the root,
+/// so equal roots mean equal accounts, and the buckets that differ tell which clients to look at.
+/// Only the funds and the lock of the accounts are hashed, with their amounts normalized.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StateDigest {
+  /// The number of records read when the digest was taken, as the digests are only comparable at the same position.
+  pub position: u64,
+  /// The hex encoded SHA-256 of the hashes of the buckets.
+  pub root: String,
+  /// The hex encoded SHA-256 of the accounts of every bucket with some account, by the first client ID of the bucket.
+  pub buckets: BTreeMap<ClientId, String>,
+}
+
+impl StateDigest {
+  /// The digest of the accounts of a report, like [`PaymentsEngine::accounts_report`](super::PaymentsEngine::accounts_report),
+  /// which doesn't need to be sorted.
+  pub fn of<I>(position: u64, accounts: I) -> Self
+  where
+    I: IntoIterator<Item = AccountReport>,
+  {
+    let mut leaves: BTreeMap<ClientId, BTreeMap<ClientId, String>> = BTreeMap::new();
+    for account in accounts {
+      let leaf = format!(
+        "{},{},{},{}\n",
+        account.client_id,
+        account.available.normalize(),
+        account.held.normalize(),
+        account.locked
+      );
+      leaves
+        .entry(bucket(account.client_id))
+        .or_default()
+        .insert(account.client_id, leaf);
+    }
+
+    let buckets: BTreeMap<ClientId, String> = leaves
+      .into_iter()
+      .map(|(bucket, leaves)| (bucket, sha256(leaves.values())))
+      .collect();
+    let root = sha256(
+      buckets
+        .iter()
+        .map(|(bucket, hash)| format!("{}:{}\n", bucket, hash)),
+    );
+
+    Self {
+      position,
+      root,
+      buckets,
+    }
+  }
+
+  /// The ranges of client IDs whose accounts differ from the ones of another digest, in ascending order.
+  pub fn diverging_clients(&self, other: &StateDigest) -> Vec<RangeInclusive<ClientId>> {
+    if self.root == other.root {
+      return Vec::new();
+    }
+    let mut buckets: Vec<ClientId> = self
+      .buckets
+      .keys()
+      .chain(other.buckets.keys())
+      .copied()
+      .filter(|bucket| self.buckets.get(bucket) != other.buckets.get(bucket))
+      .collect();
+    buckets.sort_unstable();
+    buckets.dedup();
+    buckets
+      .into_iter()
+      .map(|bucket| bucket..=bucket.saturating_add((BUCKET_CLIENTS - 1) as ClientId))
+      .collect()
+  }
+}
+
+/// The first client ID of the bucket of a client.
+fn bucket(client_id: ClientId) -> ClientId {
+  client_id - client_id % BUCKET_CLIENTS as ClientId
+}
+
+fn sha256<I, T>(parts: I) -> String
+where
+  I: IntoIterator<Item = T>,
+  T: AsRef<[u8]>,
+{
+  let mut hasher = Sha256::new();
+  for part in parts {
+    hasher.update(part.as_ref());
+  }
+  format!("{:x}", hasher.finalize())
+}
+
+#[cfg(test)]
+mod tests {
+
+  use rust_decimal_macros::dec;
+
+  use super::*;
+
+  #[test]
+  fn equal_accounts_have_equal_digests() {
+    let accounts = vec![
+      AccountReport::new(1, dec!(10), dec!(0), dec!(10), false),
+      AccountReport::new(300, dec!(5.50), dec!(1), dec!(6.5), true),
+    ];
+    let digest = StateDigest::of(2, accounts.clone());
+    let reversed = StateDigest::of(
+      2,
+      vec![
+        AccountReport::new(300, dec!(5.5), dec!(1.0), dec!(6.5), true),
+        AccountReport::new(1, dec!(10), dec!(0), dec!(10), false),
+      ],
+    );
+
+    assert_eq!(digest, reversed);
+    assert_eq!(
+      digest.buckets.keys().copied().collect::<Vec<_>>(),
+      vec![0, 256]
+    );
+    assert!(digest.diverging_clients(&reversed).is_empty());
+  }
+
+  #[test]
+  fn diverging_clients_of_the_buckets_that_differ() {
+    let digest = StateDigest::of(
+      3,
+      vec![
+        AccountReport::new(1, dec!(10), dec!(0), dec!(10), false),
+        AccountReport::new(300, dec!(5), dec!(0), dec!(5), false),
+      ],
+    );
+    let other = StateDigest::of(
+      3,
+      vec![
+        AccountReport::new(1, dec!(10), dec!(0), dec!(10), false),
+        AccountReport::new(300, dec!(5), dec!(0), dec!(5), true),
+        AccountReport::new(65535, dec!(1), dec!(0), dec!(1), false),
+      ],
+    );
+
+    assert_ne!(digest.root, other.root);
+    assert_eq!(
+      digest.diverging_clients(&other),
+      vec![256..=511, 65280..=65535]
+    );
+  }
+}
@@ -1,19 +1,42 @@
 use std::collections::HashMap;
+#[cfg(feature = "async")]
+use std::pin::Pin;
+#[cfg(feature = "async")]
+use std::task::{Context, Poll};
 
+#[cfg(feature = "async")]
 use async_trait::async_trait;
 use rust_decimal::Decimal;
 use thiserror::Error;
+#[cfg(feature = "async")]
+use tokio_stream::Stream;
 
 use super::{
-  account::{Account, AccountReport, TransactionState},
-  transaction::{ClientId, Transaction, TransactionId},
+  account::{
+    Account, AccountReport, DisputeState, Exposure, Funds, TransactionInfo, TransactionKind,
+    TransactionState,
+  },
+  config::{
+    DuplicatePolicy, EngineConfig, LockedAccountDisputePolicy, SyntheticDisputePolicy,
+    UnlockHeldFundsPolicy, ZeroAmountPolicy,
+  },
+  filter::AccountFilter,
+  limits::RecentActivity,
+  snapshot::Snapshot,
+  store::{is_settled, TransactionStore},
+  transaction::{ClientId, SubAccountId, Transaction, TransactionId, MAIN_SUB_ACCOUNT},
 };
 
+#[cfg(feature = "async")]
+use super::report::ReportOptions;
+
 pub type Result<T> = core::result::Result<T, PaymentsEngineError>;
 
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+
 /// Possible errors that can happen while processing transactions.
 /// We are dealing with sensible information, so it is important to be as detailed as possible.
-/// It could be observed through metrics or logs, or better used as events for a fraud system.
+/// It could be observed through metrics or logs, or better used as events for a fraud system (see [`EventListener`](super::EventListener)).
 #[derive(Debug, Clone, Error, PartialEq)]
 pub enum PaymentsEngineError {
   #[error("Account is locked: {0}")]
@@ -22,6 +45,9 @@ pub enum PaymentsEngineError {
   #[error("Invalid negative amount")]
   NegativeAmount,
 
+  #[error("Invalid zero amount")]
+  ZeroAmount,
+
   #[error("Not enough available funds")]
   NotEnoughAvailableFunds,
 
@@ -42,52 +68,376 @@ pub enum PaymentsEngineError {
 
   #[error("Disputed more than available")]
   DisputedMoreThanAvailable,
+
+  #[error("Too many open disputes for client {0}")]
+  TooManyOpenDisputes(ClientId),
+
+  #[error("Transaction {1} for client {0} was charged back")]
+  TransactionChargedBack(ClientId, TransactionId),
+
+  #[error("Transfer from client {0} to itself")]
+  SelfTransfer(ClientId),
+
+  #[error("Transaction {1} for client {0} can not be disputed")]
+  TransactionNotDisputable(ClientId, TransactionId),
+
+  #[error("Account is not locked: {0}")]
+  AccountNotLocked(ClientId),
+
+  #[error("Write-ahead log failed: {0}")]
+  WriteAheadLog(String),
+
+  #[error("Database failed: {0}")]
+  Database(String),
+
+  #[error("Configuration changed since the snapshot with digest {0}")]
+  ConfigChanged(String),
+
+  #[error("Snapshot format version {0} is not supported")]
+  SnapshotVersionMismatch(u16),
+
+  #[error("Snapshot failed: {0}")]
+  Snapshot(String),
+
+  #[error("Arithmetic overflow")]
+  ArithmeticOverflow,
+
+  #[error("Transfer from client {0} to client {1} of a different shard")]
+  CrossShardTransfer(ClientId, ClientId),
+
+  #[error("Shard {0} of the engine failed")]
+  ShardFailed(usize),
+
+  #[error("Transaction {1} for client {0} is not an authorization")]
+  NotAnAuthorization(ClientId, TransactionId),
+
+  #[error("Authorization {1} for client {0} expired")]
+  AuthorizationExpired(ClientId, TransactionId),
+
+  #[error("Transaction {1} for client {0} can not be disputed anymore")]
+  DisputeWindowExpired(ClientId, TransactionId),
+
+  #[error("Transaction store failed: {0}")]
+  TransactionStore(String),
+
+  #[error("Withdrawal {1} for client {0} exceeds the limit")]
+  WithdrawalLimitExceeded(ClientId, TransactionId),
+
+  #[error("Withdrawal {1} for client {0} exceeds the daily limit")]
+  DailyWithdrawalLimitExceeded(ClientId, TransactionId),
+
+  #[error("Too many transactions per minute for client {0}")]
+  VelocityLimitExceeded(ClientId),
+
+  #[error("Account is dormant: {0}")]
+  AccountDormant(ClientId),
+
+  #[error("Account is not dormant: {0}")]
+  AccountNotDormant(ClientId),
+}
+
+impl PaymentsEngineError {
+  /// The kind of the error, without the details, so the errors can be aggregated (for example, in metrics).
+  pub fn kind(&self) -> &'static str {
+    match self {
+      PaymentsEngineError::AccountLocked(_) => "account_locked",
+      PaymentsEngineError::NegativeAmount => "negative_amount",
+      PaymentsEngineError::ZeroAmount => "zero_amount",
+      PaymentsEngineError::NotEnoughAvailableFunds => "not_enough_available_funds",
+      PaymentsEngineError::DuplicatedTransaction(_) => "duplicated_transaction",
+      PaymentsEngineError::ClientNotFound(_) => "client_not_found",
+      PaymentsEngineError::TransactionNotFound(_) => "transaction_not_found",
+      PaymentsEngineError::TransactionAlreadyDisputed(_, _) => "transaction_already_disputed",
+      PaymentsEngineError::TransactionNotDisputed(_, _) => "transaction_not_disputed",
+      PaymentsEngineError::DisputedMoreThanAvailable => "disputed_more_than_available",
+      PaymentsEngineError::TooManyOpenDisputes(_) => "too_many_open_disputes",
+      PaymentsEngineError::TransactionChargedBack(_, _) => "transaction_charged_back",
+      PaymentsEngineError::SelfTransfer(_) => "self_transfer",
+      PaymentsEngineError::TransactionNotDisputable(_, _) => "transaction_not_disputable",
+      PaymentsEngineError::AccountNotLocked(_) => "account_not_locked",
+      PaymentsEngineError::WriteAheadLog(_) => "write_ahead_log",
+      PaymentsEngineError::Database(_) => "database",
+      PaymentsEngineError::ConfigChanged(_) => "config_changed",
+      PaymentsEngineError::SnapshotVersionMismatch(_) => "snapshot_version_mismatch",
+      PaymentsEngineError::Snapshot(_) => "snapshot",
+      PaymentsEngineError::ArithmeticOverflow => "arithmetic_overflow",
+      PaymentsEngineError::CrossShardTransfer(_, _) => "cross_shard_transfer",
+      PaymentsEngineError::ShardFailed(_) => "shard_failed",
+      PaymentsEngineError::NotAnAuthorization(_, _) => "not_an_authorization",
+      PaymentsEngineError::AuthorizationExpired(_, _) => "authorization_expired",
+      PaymentsEngineError::DisputeWindowExpired(_, _) => "dispute_window_expired",
+      PaymentsEngineError::TransactionStore(_) => "transaction_store",
+      PaymentsEngineError::WithdrawalLimitExceeded(_, _) => "withdrawal_limit_exceeded",
+      PaymentsEngineError::DailyWithdrawalLimitExceeded(_, _) => "daily_withdrawal_limit_exceeded",
+      PaymentsEngineError::VelocityLimitExceeded(_) => "velocity_limit_exceeded",
+      PaymentsEngineError::AccountDormant(_) => "account_dormant",
+      PaymentsEngineError::AccountNotDormant(_) => "account_not_dormant",
+    }
+  }
 }
 
 /// Interface implemented by payments processors
+#[cfg(feature = "async")]
 #[async_trait]
 pub trait PaymentsEngine {
   /// Operation called to process a transaction. It will return whether or not succeeded and detailed information about the error.
   /// The operation is `async` to allow interaction of the engine with external systems involving IO (database, file system, ...)
   async fn process(&mut self, transaction: Transaction) -> Result<()>;
+  /// Perform all the checks that [`PaymentsEngine::process`] would do for the transaction, but without applying it.
+  /// It allows to predict whether a transaction would succeed without mutating the state.
+  fn validate(&self, transaction: &Transaction) -> Result<()>;
   /// It will return an [`Iterator`] of [`AccountReport`] useful to generate account reports.
   fn accounts_report(&self) -> AccountsReportIter;
+  /// Same as [`PaymentsEngine::accounts_report`] but only for the accounts selected by the [`AccountFilter`].
+  /// The filter is evaluated by the engine, so it can avoid looking into accounts that are not selected.
+  fn accounts_matching(&self, filter: AccountFilter) -> AccountsReportIter;
+  /// Same as [`PaymentsEngine::accounts_report`] but as a [`Stream`], so the engines that read the accounts from
+  /// external systems can produce them as they are read, instead of collecting the whole report first.
+  fn accounts_report_stream(&self) -> AccountsReportStream {
+    AccountsReportStream::from(self.accounts_report())
+  }
+  /// Same as [`PaymentsEngine::accounts_report`] but in the order of the [`ReportOptions`],
+  /// which needs to collect the whole report before returning it.
+  fn sorted_accounts_report(&self, options: &ReportOptions) -> AccountsReportIter {
+    let mut report: Vec<AccountReport> = self.accounts_report().collect();
+    options.sort(&mut report);
+    AccountsReportIter::new(report.into_iter())
+  }
+  /// The report of a single account, or `None` when the client has no account.
+  fn account(&self, client_id: ClientId) -> Option<AccountReport>;
+  /// The information about a transaction recorded by the account of the client, or `None` when it is unknown.
+  /// It fails when the transaction can't be read from where the engine keeps it.
+  fn transaction(
+    &self,
+    client_id: ClientId,
+    transaction_id: TransactionId,
+  ) -> Result<Option<TransactionInfo>>;
+  /// It will return an [`Iterator`] with the [`TransactionInfo`] of every transaction recorded by the accounts,
+  /// useful to export the ledger behind the balances of the accounts report.
+  /// It fails when the transactions can't be read from where the engine keeps them.
+  fn transactions_report(&self) -> Result<TransactionsReportIter>;
+}
+
+/// Synchronous interface implemented by the payments processors that don't need any IO to process transactions.
+///
+/// [`PaymentsEngine::process`] returns a boxed future (because of `async_trait`), which means one allocation
+/// and one dynamic dispatch per transaction. Engines implementing this trait can be driven without that cost
+/// by processors like [`processors::generic`](crate::processors::generic).
+pub trait SyncPaymentsEngine {
+  /// Same as [`PaymentsEngine::process`] but without the `async` overhead.
+  fn process_sync(&mut self, transaction: Transaction) -> Result<()>;
 }
 
 /// Implementation of the [`PaymentsEngine`] that uses memory to store accounts information and transactions.
 #[derive(Debug)]
 pub struct InMemoryPaymentsEngine {
+  config: EngineConfig,
   accounts: HashMap<ClientId, Account>,
+  /// The latest timestamp of the accepted transactions, which is only tracked when the escrow interest, the authorizations
+  /// expiry or the dormancy need it.
+  clock: u64,
+  /// Where the settled transactions are moved out of the accounts, when they are not kept in the accounts themselves.
+  store: Option<Box<dyn TransactionStore>>,
+  /// The recent transactions of the clients, which are only tracked when the limits or the dormancy need them.
+  activity: HashMap<ClientId, RecentActivity>,
 }
 
 impl InMemoryPaymentsEngine {
   pub fn new() -> Self {
+    Self::with_config(EngineConfig::default())
+  }
+
+  pub fn with_config(config: EngineConfig) -> Self {
     Self {
+      config,
       accounts: HashMap::default(),
+      clock: 0,
+      store: None,
+      activity: HashMap::default(),
+    }
+  }
+
+  /// Move the settled transactions out of the accounts into the [`TransactionStore`], which bounds the memory used by
+  /// the accounts when the store keeps them on disk. The transactions are moved back into their account when they are needed.
+  pub fn with_transaction_store(mut self, store: Box<dyn TransactionStore>) -> Self {
+    self.store = Some(store);
+    self
+  }
+
+  /// A borrowed view of every account, to write big reports directly from the state of the engine.
+  /// Unlike [`PaymentsEngine::accounts_report`], the disputes and the rest of the [`AccountReport`] are only computed when asked for.
+  pub fn accounts_report_ref(&self) -> Box<dyn Iterator<Item = AccountView<'_>> + Send + '_> {
+    let views = self
+      .accounts
+      .iter()
+      .map(move |(client_id, account)| AccountView {
+        engine: self,
+        client_id: *client_id,
+        account,
+      });
+
+    if self.config.deterministic {
+      let mut views: Vec<AccountView> = views.collect();
+      views.sort_by_key(|view| view.client_id);
+      Box::new(views.into_iter())
+    } else {
+      Box::new(views)
+    }
+  }
+
+  /// A copy of the state of all the accounts, which can be serialized to checkpoint the processing.
+  /// It includes the transactions moved into the [`TransactionStore`], so it fails when they can't be read.
+  pub fn snapshot(&self) -> Result<Snapshot> {
+    let mut accounts = self.accounts.clone();
+    for stored in self.stored_transactions() {
+      let (client_id, transaction_id, transaction) = stored?;
+      if let Some(account) = accounts.get_mut(&client_id) {
+        account.transactions.insert(transaction_id, transaction);
+      }
+    }
+    Ok(Snapshot {
+      config_digest: self.config.digest(),
+      accounts,
+      clock: self.clock,
+      activity: self.activity.clone(),
+    })
+  }
+
+  /// Replace the state of all the accounts with the one of a [`Snapshot`], keeping the configuration of the engine.
+  /// The processing can then be resumed from the transactions that came after the snapshot.
+  /// The snapshot is refused with [`PaymentsEngineError::ConfigChanged`] when it was created under a different configuration.
+  pub fn restore(&mut self, snapshot: Snapshot) -> Result<()> {
+    if snapshot.config_digest != self.config.digest() {
+      return Err(PaymentsEngineError::ConfigChanged(snapshot.config_digest));
+    }
+    self.restore_with_config_change(snapshot)
+  }
+
+  /// Same as [`InMemoryPaymentsEngine::restore`] but allowing snapshots created under a different configuration.
+  /// The settled transactions of the snapshot are moved into the [`TransactionStore`] as their accounts are used,
+  /// so it fails when the previous ones can't be removed from the store.
+  pub fn restore_with_config_change(&mut self, snapshot: Snapshot) -> Result<()> {
+    if let Some(store) = &mut self.store {
+      store.clear()?;
+    }
+    self.accounts = snapshot.accounts;
+    self.clock = snapshot.clock;
+    self.activity = snapshot.activity;
+    Ok(())
+  }
+
+  /// The digest of the configuration of the engine (see [`EngineConfig::digest`]).
+  pub fn config_digest(&self) -> String {
+    self.config.digest()
+  }
+
+  /// The configuration of the engine.
+  pub fn config(&self) -> &EngineConfig {
+    &self.config
+  }
+
+  /// Move the transaction referred by the transaction being processed back into its account, if it was stored.
+  fn load_stored(&mut self, transaction: &Transaction) -> Result<()> {
+    let (store, transaction_id) = match (&mut self.store, transaction.transaction_id()) {
+      (Some(store), Some(transaction_id)) => (store, transaction_id),
+      _ => return Ok(()),
+    };
+    let client_id = transaction.client_id();
+    if let Some(account) = self.accounts.get_mut(&client_id) {
+      if !account.transaction_exists(&transaction_id) {
+        if let Some(stored) = store.remove(client_id, transaction_id)? {
+          account.transactions.insert(transaction_id, stored);
+        }
+      }
+    }
+    Ok(())
+  }
+
+  /// Move the settled transactions of the accounts of the clients into the store.
+  fn store_settled(&mut self, clients: &[ClientId]) -> Result<()> {
+    let store = match &mut self.store {
+      Some(store) => store,
+      None => return Ok(()),
+    };
+    for client_id in clients {
+      if let Some(account) = self.accounts.get_mut(client_id) {
+        let settled: Vec<TransactionId> = account
+          .transactions
+          .iter()
+          .filter(|(_, transaction)| is_settled(transaction))
+          .map(|(transaction_id, _)| *transaction_id)
+          .collect();
+        for transaction_id in settled {
+          if let Some(transaction) = account.transactions.remove(&transaction_id) {
+            store.insert(*client_id, transaction_id, transaction)?;
+          }
+        }
+      }
+    }
+    Ok(())
+  }
+
+  /// The transaction referred by the transaction being validated, when it is in the store instead of in its account.
+  fn find_stored(&self, transaction: &Transaction) -> Result<Option<TransactionState>> {
+    match (&self.store, transaction.transaction_id()) {
+      (Some(store), Some(transaction_id)) => {
+        let client_id = transaction.client_id();
+        match self.accounts.get(&client_id) {
+          Some(account) if !account.transaction_exists(&transaction_id) => {
+            store.get(client_id, transaction_id)
+          }
+          _ => Ok(None),
+        }
+      }
+      _ => Ok(None),
     }
   }
 
+  /// All the transactions in the store, failing for the ones that can't be read.
+  fn stored_transactions(
+    &self,
+  ) -> impl Iterator<Item = Result<(ClientId, TransactionId, TransactionState)>> + Send + '_ {
+    self.store.iter().flat_map(|store| store.transactions())
+  }
+
   fn deposit(
     &mut self,
     client_id: ClientId,
     transaction_id: TransactionId,
     amount: Decimal,
+    timestamp: Option<u64>,
+    sub_account: SubAccountId,
   ) -> Result<()> {
-    if amount < Decimal::ZERO {
-      Err(PaymentsEngineError::NegativeAmount)
-    } else {
+    self.check_deposit(client_id, transaction_id, amount)?;
+    if !self.skips_amount(amount) {
       let account = self.get_or_create_account(client_id);
-      if account.locked {
-        Err(PaymentsEngineError::AccountLocked(client_id))
-      } else if account.transaction_exists(&transaction_id) {
+      let funds = checked_funds(&account.funds, |funds| funds.credit(amount))?;
+      update_funds(account, sub_account, funds)?;
+      account.transactions.insert(
+        transaction_id,
+        TransactionState::from_amount(amount)
+          .with_timestamp(timestamp)
+          .with_sub_account(sub_account),
+      );
+    }
+    Ok(())
+  }
+
+  fn check_deposit(
+    &self,
+    client_id: ClientId,
+    transaction_id: TransactionId,
+    amount: Decimal,
+  ) -> Result<()> {
+    self.check_amount(amount)?;
+    match self.accounts.get(&client_id) {
+      _ if self.skips_amount(amount) => Ok(()),
+      Some(account) if account.locked => Err(PaymentsEngineError::AccountLocked(client_id)),
+      Some(account) if account.transaction_exists(&transaction_id) => {
         Err(PaymentsEngineError::DuplicatedTransaction(transaction_id))
-      } else {
-        account.funds.available += amount;
-        account
-          .transactions
-          .insert(transaction_id, TransactionState::from_amount(amount));
-        Ok(())
       }
+      Some(account) => checked_funds(&account.funds, |funds| funds.credit(amount)).map(|_| ()),
+      None => Ok(()),
     }
   }
 
@@ -96,731 +446,3959 @@ impl InMemoryPaymentsEngine {
     client_id: ClientId,
     transaction_id: TransactionId,
     amount: Decimal,
+    timestamp: Option<u64>,
+    sub_account: SubAccountId,
   ) -> Result<()> {
-    if amount < Decimal::ZERO {
-      Err(PaymentsEngineError::NegativeAmount)
-    } else {
-      let account = self
-        .accounts
-        .get_mut(&client_id)
-        .ok_or(PaymentsEngineError::ClientNotFound(client_id))?;
-
-      if account.locked {
-        Err(PaymentsEngineError::AccountLocked(client_id))
-      } else if account.transaction_exists(&transaction_id) {
-        Err(PaymentsEngineError::DuplicatedTransaction(transaction_id))
-      } else if account.funds.available < amount {
-        Err(PaymentsEngineError::NotEnoughAvailableFunds)
-      } else {
-        account.funds.available -= amount;
-        Ok(())
-      }
+    self.check_withdrawal(client_id, transaction_id, amount, sub_account)?;
+    self.check_dormancy(client_id, timestamp)?;
+    self.check_limits(client_id, transaction_id, amount, timestamp)?;
+    if !self.skips_amount(amount) {
+      let account = self.get_account_mut(client_id)?;
+      let funds = checked_funds(&account.funds, |funds| funds.debit(amount))?;
+      update_funds(account, sub_account, funds)?;
+      account.transactions.insert(
+        transaction_id,
+        TransactionState::from_withdrawal(amount)
+          .with_timestamp(timestamp)
+          .with_sub_account(sub_account),
+      );
     }
+    Ok(())
   }
 
-  fn dispute(&mut self, client_id: ClientId, transaction_id: TransactionId) -> Result<()> {
-    let account = self
-      .accounts
-      .get_mut(&client_id)
-      .ok_or(PaymentsEngineError::ClientNotFound(client_id))?;
+  /// A withdrawal is limited by the available funds of its sub-account, even if the client has more in others.
+  fn check_withdrawal(
+    &self,
+    client_id: ClientId,
+    transaction_id: TransactionId,
+    amount: Decimal,
+    sub_account: SubAccountId,
+  ) -> Result<()> {
+    self.check_amount(amount)?;
+    if self.skips_amount(amount) {
+      return Ok(());
+    }
 
+    let account = self.get_account(client_id)?;
     if account.locked {
       Err(PaymentsEngineError::AccountLocked(client_id))
+    } else if account.transaction_exists(&transaction_id) {
+      Err(PaymentsEngineError::DuplicatedTransaction(transaction_id))
+    } else if sub_account_funds(account, sub_account)?.available < amount {
+      Err(PaymentsEngineError::NotEnoughAvailableFunds)
     } else {
-      let transaction = account
-        .transactions
-        .get_mut(&transaction_id)
-        .ok_or(PaymentsEngineError::TransactionNotFound(transaction_id))?;
-
-      if transaction.in_dispute {
-        Err(PaymentsEngineError::TransactionAlreadyDisputed(
-          client_id,
-          transaction_id,
-        ))
-      } else if transaction.amount > account.funds.available {
-        Err(PaymentsEngineError::DisputedMoreThanAvailable)
-      } else {
-        transaction.in_dispute = true;
-        account.funds.available -= transaction.amount;
-        account.funds.held += transaction.amount;
-        Ok(())
-      }
+      checked_funds(&account.funds, |funds| funds.debit(amount)).map(|_| ())
     }
   }
 
-  fn resolve(&mut self, client_id: ClientId, transaction_id: TransactionId) -> Result<()> {
-    let account = self
-      .accounts
-      .get_mut(&client_id)
-      .ok_or(PaymentsEngineError::ClientNotFound(client_id))?;
-
-    let transaction = account
-      .transactions
-      .get_mut(&transaction_id)
-      .ok_or(PaymentsEngineError::TransactionNotFound(transaction_id))?;
-
-    if !transaction.in_dispute {
-      Err(PaymentsEngineError::TransactionNotDisputed(
+  /// Check the withdrawal, or the transfer out of the sender, against the [`LimitsPolicy`](super::LimitsPolicy), if any.
+  fn check_limits(
+    &self,
+    client_id: ClientId,
+    transaction_id: TransactionId,
+    amount: Decimal,
+    timestamp: Option<u64>,
+  ) -> Result<()> {
+    let limits = match &self.config.limits {
+      Some(limits) if !self.skips_amount(amount) => limits,
+      _ => return Ok(()),
+    };
+    if limits.max_withdrawal.map_or(false, |max| amount > max) {
+      return Err(PaymentsEngineError::WithdrawalLimitExceeded(
+        client_id,
+        transaction_id,
+      ));
+    }
+    // the windows are over the timestamps, so the withdrawals without one are only checked against the maximum amount
+    let timestamp = match timestamp {
+      Some(timestamp) => timestamp,
+      None => return Ok(()),
+    };
+    // a client without recent activity has not withdrawn anything yet
+    let no_activity = RecentActivity::default();
+    let activity = self.activity.get(&client_id).unwrap_or(&no_activity);
+    if limits.max_daily_withdrawals.map_or(false, |max| {
+      activity.daily_withdrawals(timestamp) + amount > max
+    }) {
+      Err(PaymentsEngineError::DailyWithdrawalLimitExceeded(
         client_id,
         transaction_id,
       ))
+    } else if limits.max_transactions_per_minute.map_or(false, |max| {
+      activity.transactions_per_minute(timestamp) >= max
+    }) {
+      Err(PaymentsEngineError::VelocityLimitExceeded(client_id))
     } else {
-      transaction.in_dispute = false;
-      account.funds.available += transaction.amount;
-      account.funds.held -= transaction.amount;
       Ok(())
     }
   }
 
-  fn chargeback(&mut self, client_id: ClientId, transaction_id: TransactionId) -> Result<()> {
-    let account = self
-      .accounts
-      .get_mut(&client_id)
-      .ok_or(PaymentsEngineError::ClientNotFound(client_id))?;
+  /// The client, timestamp and withdrawn amount that the transaction would add to the recent activity, when the limits need it.
+  /// Only the deposits, withdrawals, transfers (withdrawn by the sender) and disputes are part of the activity.
+  fn recent_activity(&self, transaction: &Transaction) -> Option<(ClientId, u64, Option<Decimal>)> {
+    let tracks_activity = self
+      .config
+      .limits
+      .as_ref()
+      .map_or(false, |limits| limits.tracks_activity());
+    let timestamp = transaction.timestamp().filter(|_| tracks_activity)?;
+    let withdrawal = match *transaction {
+      Transaction::Withdrawal { amount, .. } | Transaction::Transfer { amount, .. } => Some(amount),
+      Transaction::Deposit { .. } | Transaction::Dispute { .. } => None,
+      _ => return None,
+    };
+    Some((transaction.client_id(), timestamp, withdrawal))
+  }
 
-    let amount = {
-      let transaction = account
-        .transactions
-        .get(&transaction_id)
-        .ok_or(PaymentsEngineError::TransactionNotFound(transaction_id))?;
+  /// Check that the withdrawal, or the transfer out of the sender, is not blocked by the [`DormancyPolicy`](super::DormancyPolicy), if any.
+  fn check_dormancy(&self, client_id: ClientId, timestamp: Option<u64>) -> Result<()> {
+    let blocks_withdrawals = self
+      .config
+      .dormancy
+      .map_or(false, |dormancy| dormancy.block_withdrawals);
+    if blocks_withdrawals && self.is_dormant(client_id, self.now(timestamp)) {
+      Err(PaymentsEngineError::AccountDormant(client_id))
+    } else {
+      Ok(())
+    }
+  }
 
-      if !transaction.in_dispute {
-        Err(PaymentsEngineError::TransactionNotDisputed(
-          client_id,
-          transaction_id,
-        ))
-      } else {
-        Ok(transaction.amount)
-      }
-    }?;
+  /// Whether the client was not active for the days of the [`DormancyPolicy`](super::DormancyPolicy) up to the time.
+  fn is_dormant(&self, client_id: ClientId, now: u64) -> bool {
+    match (self.config.dormancy, self.activity.get(&client_id)) {
+      (Some(dormancy), Some(activity)) => activity.dormant(now, dormancy.days),
+      _ => false,
+    }
+  }
 
-    account.locked = true;
-    account.funds.held -= amount;
-    account.transactions.remove(&transaction_id);
+  /// The client that the transaction would make active, when the dormancy needs it.
+  /// The transactions of a dormant account don't make it active, except for the reactivations.
+  fn active_client(&self, transaction: &Transaction, now: u64) -> Option<ClientId> {
+    self.config.dormancy?;
+    let client_id = transaction.client_id();
+    let reactivation = matches!(transaction, Transaction::Reactivate { .. });
+    if reactivation || !self.is_dormant(client_id, now) {
+      Some(client_id)
+    } else {
+      None
+    }
+  }
 
-    Ok(())
+  /// A reactivation is only accepted for the dormant accounts, which it makes active by being processed.
+  fn check_reactivate(&self, client_id: ClientId) -> Result<()> {
+    self.get_account(client_id)?;
+    if self.is_dormant(client_id, self.clock) {
+      Ok(())
+    } else {
+      Err(PaymentsEngineError::AccountNotDormant(client_id))
+    }
   }
 
-  fn get_or_create_account(&mut self, client_id: ClientId) -> &mut Account {
-    self
-      .accounts
-      .entry(client_id)
-      .or_insert_with(Account::default)
+  /// Transfer funds between two accounts. All the checks are done before changing any of them,
+  /// so either both the sender is debited and the recipient credited, or nothing changes.
+  fn transfer(
+    &mut self,
+    from_client: ClientId,
+    to_client: ClientId,
+    transaction_id: TransactionId,
+    amount: Decimal,
+    timestamp: Option<u64>,
+  ) -> Result<()> {
+    self.check_transfer(from_client, to_client, transaction_id, amount)?;
+    self.check_dormancy(from_client, timestamp)?;
+    self.check_limits(from_client, transaction_id, amount, timestamp)?;
+    if !self.skips_amount(amount) {
+      let from_funds = checked_funds(&self.get_account(from_client)?.funds, |funds| {
+        funds.debit(amount)
+      })?;
+      let to_account = self.get_or_create_account(to_client);
+      to_account.funds = checked_funds(&to_account.funds, |funds| funds.credit(amount))?;
+      let from_account = self.get_account_mut(from_client)?;
+      from_account.funds = from_funds;
+      from_account.transactions.insert(
+        transaction_id,
+        TransactionState::from_transfer(amount).with_timestamp(timestamp),
+      );
+    }
+    Ok(())
   }
 
-  fn accounts_report_iter(&self) -> impl Iterator<Item = AccountReport> + '_ {
-    self.accounts.iter().map(|(client_id, account)| {
-      let total = account.funds.available + account.funds.held;
-      AccountReport::new(
-        *client_id,
-        account.funds.available,
-        account.funds.held,
-        total,
-        account.locked,
-      )
-    })
+  /// A transfer is checked as a withdrawal from the sender, and the recipient must not be locked.
+  /// The limits of the sender are checked apart, like for the withdrawals.
+  fn check_transfer(
+    &self,
+    from_client: ClientId,
+    to_client: ClientId,
+    transaction_id: TransactionId,
+    amount: Decimal,
+  ) -> Result<()> {
+    if from_client == to_client {
+      return Err(PaymentsEngineError::SelfTransfer(from_client));
+    }
+
+    self.check_withdrawal(from_client, transaction_id, amount, MAIN_SUB_ACCOUNT)?;
+    match self.accounts.get(&to_client) {
+      _ if self.skips_amount(amount) => Ok(()),
+      Some(account) if account.locked => Err(PaymentsEngineError::AccountLocked(to_client)),
+      Some(account) => checked_funds(&account.funds, |funds| funds.credit(amount)).map(|_| ()),
+      None => Ok(()),
+    }
   }
-}
 
-#[async_trait]
-impl PaymentsEngine for InMemoryPaymentsEngine {
-  async fn process(&mut self, transaction: Transaction) -> Result<()> {
-    match transaction {
-      Transaction::Deposit {
-        client_id,
-        transaction_id,
-        amount,
-      } => self.deposit(client_id, transaction_id, amount),
-      Transaction::Withdrawal {
-        client_id,
-        transaction_id,
-        amount,
-      } => self.withdrawal(client_id, transaction_id, amount),
-      Transaction::Dispute {
-        client_id,
-        transaction_id,
-      } => self.dispute(client_id, transaction_id),
-      Transaction::Resolve {
-        client_id,
+  /// Hold the amount of an authorization, which is checked as a deposit, until it is captured or voided.
+  fn authorize(
+    &mut self,
+    client_id: ClientId,
+    transaction_id: TransactionId,
+    amount: Decimal,
+    timestamp: Option<u64>,
+    sub_account: SubAccountId,
+  ) -> Result<()> {
+    self.check_deposit(client_id, transaction_id, amount)?;
+    if !self.skips_amount(amount) {
+      let now = self.now(timestamp);
+      let expires_at = self
+        .config
+        .authorization_expiry
+        .map(|expiry| now.saturating_add(expiry));
+      let account = self.get_or_create_account(client_id);
+      let funds = checked_funds(&account.funds, |funds| funds.authorize(amount))?;
+      update_funds(account, sub_account, funds)?;
+      account.transactions.insert(
         transaction_id,
-      } => self.resolve(client_id, transaction_id),
-      Transaction::Chargeback {
+        TransactionState::from_authorization(amount, expires_at).with_sub_account(sub_account),
+      );
+    }
+    Ok(())
+  }
+
+  /// Make the funds of the authorization available, which turns it into a deposit that can be disputed.
+  fn capture(
+    &mut self,
+    client_id: ClientId,
+    transaction_id: TransactionId,
+    timestamp: Option<u64>,
+  ) -> Result<()> {
+    self.check_capture(client_id, transaction_id, timestamp)?;
+    let account = self.get_account_mut(client_id)?;
+    let transaction = get_transaction(account, transaction_id)?;
+    let sub_account = transaction.sub_account;
+    let funds = checked_funds(&account.funds, |funds| funds.capture(transaction.amount))?;
+    update_funds(account, sub_account, funds)?;
+    if let Some(transaction) = account.transactions.get_mut(&transaction_id) {
+      transaction.kind = TransactionKind::Deposit;
+      transaction.expires_at = None;
+    }
+    Ok(())
+  }
+
+  /// A capture is rejected for locked accounts, as deposits are, and for authorizations expired at the time of the capture.
+  fn check_capture(
+    &self,
+    client_id: ClientId,
+    transaction_id: TransactionId,
+    timestamp: Option<u64>,
+  ) -> Result<()> {
+    let account = self.get_account(client_id)?;
+    let transaction = check_authorization(account, client_id, transaction_id)?;
+    if account.locked {
+      Err(PaymentsEngineError::AccountLocked(client_id))
+    } else if transaction
+      .expires_at
+      .map_or(false, |expires_at| self.now(timestamp) >= expires_at)
+    {
+      Err(PaymentsEngineError::AuthorizationExpired(
         client_id,
         transaction_id,
-      } => self.chargeback(client_id, transaction_id),
+      ))
+    } else {
+      checked_funds(&account.funds, |funds| funds.capture(transaction.amount)).map(|_| ())
     }
   }
 
-  fn accounts_report(&self) -> AccountsReportIter {
-    AccountsReportIter::new(self.accounts_report_iter())
+  /// Remove the funds held by an authorization that was not captured, even if it expired or the account is locked.
+  fn void(&mut self, client_id: ClientId, transaction_id: TransactionId) -> Result<()> {
+    self.check_void(client_id, transaction_id)?;
+    let account = self.get_account_mut(client_id)?;
+    let transaction = get_transaction(account, transaction_id)?;
+    let sub_account = transaction.sub_account;
+    let funds = checked_funds(&account.funds, |funds| funds.void(transaction.amount))?;
+    update_funds(account, sub_account, funds)?;
+    if let Some(transaction) = account.transactions.get_mut(&transaction_id) {
+      transaction.kind = TransactionKind::VoidedAuthorization;
+      transaction.expires_at = None;
+    }
+    Ok(())
   }
-}
 
-pub struct AccountsReportIter<'a>(Box<dyn Iterator<Item = AccountReport> + 'a>);
+  fn check_void(&self, client_id: ClientId, transaction_id: TransactionId) -> Result<()> {
+    let account = self.get_account(client_id)?;
+    let transaction = check_authorization(account, client_id, transaction_id)?;
+    checked_funds(&account.funds, |funds| funds.void(transaction.amount)).map(|_| ())
+  }
 
-impl<'a> AccountsReportIter<'a> {
-  pub(crate) fn new<T>(iter: T) -> Self
-  where
-    T: Iterator<Item = AccountReport> + 'a,
-  {
-    Self(Box::new(iter))
+  /// Check the amount of deposits and withdrawals, which can't be negative, nor zero depending on the policy.
+  fn check_amount(&self, amount: Decimal) -> Result<()> {
+    if amount < Decimal::ZERO {
+      Err(PaymentsEngineError::NegativeAmount)
+    } else if amount.is_zero() && self.config.zero_amount_policy == ZeroAmountPolicy::Reject {
+      Err(PaymentsEngineError::ZeroAmount)
+    } else {
+      Ok(())
+    }
   }
-}
 
-impl<'a> Iterator for AccountsReportIter<'a> {
-  type Item = AccountReport;
+  fn skips_amount(&self, amount: Decimal) -> bool {
+    amount.is_zero() && self.config.zero_amount_policy == ZeroAmountPolicy::Skip
+  }
 
-  fn next(&mut self) -> Option<Self::Item> {
-    self.0.next()
+  fn dispute(
+    &mut self,
+    client_id: ClientId,
+    transaction_id: TransactionId,
+    timestamp: Option<u64>,
+  ) -> Result<()> {
+    self.check_dispute(client_id, transaction_id, timestamp)?;
+    let clock = self.now(timestamp);
+    let fees_disputable = self.fees_disputable();
+    let account = self.get_account_mut(client_id)?;
+    let transaction = get_disputed(account, transaction_id, fees_disputable)?;
+    let funds = held_funds(account, transaction)?;
+    let sub_account = transaction.sub_account;
+    update_funds(account, sub_account, funds)?;
+    transition(
+      account,
+      client_id,
+      transaction_id,
+      fees_disputable,
+      DisputeState::dispute,
+    )?
+    .disputed_at = clock;
+    Ok(())
   }
-}
 
-#[cfg(test)]
-mod tests {
+  fn check_dispute(
+    &self,
+    client_id: ClientId,
+    transaction_id: TransactionId,
+    timestamp: Option<u64>,
+  ) -> Result<()> {
+    let account = self.get_account(client_id)?;
 
-  use std::collections::HashSet;
+    let too_many_open_disputes = self
+      .config
+      .max_open_disputes
+      .map_or(false, |max_open_disputes| {
+        account.open_disputes >= max_open_disputes
+      });
 
-  use rust_decimal_macros::dec;
+    let reject_locked =
+      self.config.locked_account_dispute_policy == LockedAccountDisputePolicy::Reject;
+
+    if account.locked && reject_locked {
+      Err(PaymentsEngineError::AccountLocked(client_id))
+    } else {
+      let transaction = get_disputed(account, transaction_id, self.fees_disputable())?;
+
+      if !self.is_disputable(transaction.kind) {
+        Err(PaymentsEngineError::TransactionNotDisputable(
+          client_id,
+          transaction_id,
+        ))
+      } else if transaction.state.dispute().is_none() {
+        Err(transition_error(
+          client_id,
+          transaction_id,
+          transaction.state,
+        ))
+      } else if self.dispute_window_expired(transaction, timestamp) {
+        Err(PaymentsEngineError::DisputeWindowExpired(
+          client_id,
+          transaction_id,
+        ))
+      } else if too_many_open_disputes {
+        Err(PaymentsEngineError::TooManyOpenDisputes(client_id))
+      } else if transaction.kind == TransactionKind::Deposit
+        && transaction.amount > sub_account_funds(account, transaction.sub_account)?.available
+      {
+        Err(PaymentsEngineError::DisputedMoreThanAvailable)
+      } else {
+        held_funds(account, transaction).map(|_| ())
+      }
+    }
+  }
+
+  /// Whether the kind of transaction can be disputed, which for the synthetic ones depends on the [`SyntheticDisputePolicy`].
+  fn is_disputable(&self, kind: TransactionKind) -> bool {
+    kind.is_disputable() || (kind == TransactionKind::Fee && self.fees_disputable())
+  }
+
+  /// Whether the fees can be disputed, by the ID of the transaction whose chargeback charged them.
+  fn fees_disputable(&self) -> bool {
+    self.config.synthetic_dispute_policy == SyntheticDisputePolicy::Allow
+  }
+
+  fn resolve(
+    &mut self,
+    client_id: ClientId,
+    transaction_id: TransactionId,
+    timestamp: Option<u64>,
+  ) -> Result<()> {
+    if self.is_retried(client_id, transaction_id, DisputeState::Resolved) {
+      return Ok(());
+    }
+    self.check_disputed(client_id, transaction_id)?;
+    let funds = self.resolved_funds(
+      self.get_account(client_id)?,
+      transaction_id,
+      self.now(timestamp),
+    )?;
+    let fees_disputable = self.fees_disputable();
+    let account = self.get_account_mut(client_id)?;
+    let sub_account = get_disputed(account, transaction_id, fees_disputable)?.sub_account;
+    update_funds(account, sub_account, funds)?;
+    transition(
+      account,
+      client_id,
+      transaction_id,
+      fees_disputable,
+      DisputeState::resolve,
+    )?;
+    Ok(())
+  }
+
+  fn check_resolve(
+    &self,
+    client_id: ClientId,
+    transaction_id: TransactionId,
+    timestamp: Option<u64>,
+  ) -> Result<()> {
+    if self.is_retried(client_id, transaction_id, DisputeState::Resolved) {
+      return Ok(());
+    }
+    self.check_disputed(client_id, transaction_id)?;
+    self
+      .resolved_funds(
+        self.get_account(client_id)?,
+        transaction_id,
+        self.now(timestamp),
+      )
+      .map(|_| ())
+  }
+
+  /// The funds after resolving the dispute at the time, crediting the escrow interest when the client wins it.
+  fn resolved_funds(
+    &self,
+    account: &Account,
+    transaction_id: TransactionId,
+    now: u64,
+  ) -> Result<Funds> {
+    let transaction = get_disputed(account, transaction_id, self.fees_disputable())?;
+    let interest = self.escrow_interest(transaction, now)?;
+    checked_funds(&account.funds, |funds| {
+      funds.release(transaction.kind, transaction.amount)?;
+      if transaction.kind == TransactionKind::Deposit {
+        funds.credit(interest)?;
+      }
+      Some(())
+    })
+  }
+
+  fn chargeback(
+    &mut self,
+    client_id: ClientId,
+    transaction_id: TransactionId,
+    timestamp: Option<u64>,
+  ) -> Result<()> {
+    if self.is_retried(client_id, transaction_id, DisputeState::ChargedBack) {
+      return Ok(());
+    }
+    self.check_disputed(client_id, transaction_id)?;
+    let (funds, fee) = self.charged_back_funds(
+      self.get_account(client_id)?,
+      transaction_id,
+      self.now(timestamp),
+    )?;
+    let fees_disputable = self.fees_disputable();
+    let account = self.get_account_mut(client_id)?;
+    let sub_account = get_disputed(account, transaction_id, fees_disputable)?.sub_account;
+    update_funds(account, sub_account, funds)?;
+    transition(
+      account,
+      client_id,
+      transaction_id,
+      fees_disputable,
+      DisputeState::charge_back,
+    )?;
+    if !fee.is_zero() {
+      account.fees.insert(
+        transaction_id,
+        TransactionState::from_fee(fee)
+          .with_timestamp(timestamp)
+          .with_sub_account(sub_account),
+      );
+    }
+    account.locked = true;
+    Ok(())
+  }
+
+  fn check_chargeback(
+    &self,
+    client_id: ClientId,
+    transaction_id: TransactionId,
+    timestamp: Option<u64>,
+  ) -> Result<()> {
+    if self.is_retried(client_id, transaction_id, DisputeState::ChargedBack) {
+      return Ok(());
+    }
+    self.check_disputed(client_id, transaction_id)?;
+    self
+      .charged_back_funds(
+        self.get_account(client_id)?,
+        transaction_id,
+        self.now(timestamp),
+      )
+      .map(|_| ())
+  }
+
+  /// Whether a resolve or chargeback that leaves the transaction in the `applied` state was already applied,
+  /// so it is accepted without changes by the [`DuplicatePolicy::Idempotent`].
+  /// A retried chargeback is still accepted once the fee that it charged is recorded, unless the fee is being disputed.
+  fn is_retried(
+    &self,
+    client_id: ClientId,
+    transaction_id: TransactionId,
+    applied: DisputeState,
+  ) -> bool {
+    let account = match self.get_account(client_id) {
+      Ok(account) if self.config.duplicate_policy == DuplicatePolicy::Idempotent => account,
+      _ => return false,
+    };
+    match (
+      account.transactions.get(&transaction_id),
+      account.disputed_transaction(transaction_id, self.fees_disputable()),
+    ) {
+      (Some(transaction), Some(disputed)) => {
+        disputed.state == applied || (transaction.state == applied && !disputed.in_dispute())
+      }
+      _ => false,
+    }
+  }
+
+  /// The funds after charging back the dispute at the time, crediting the escrow interest when the client wins it,
+  /// and taking the chargeback fee, which is returned along with them to be recorded as a synthetic transaction.
+  fn charged_back_funds(
+    &self,
+    account: &Account,
+    transaction_id: TransactionId,
+    now: u64,
+  ) -> Result<(Funds, Decimal)> {
+    let transaction = get_disputed(account, transaction_id, self.fees_disputable())?;
+    account
+      .charged_back_total()
+      .and_then(|total| total.checked_add(transaction.amount))
+      .ok_or(PaymentsEngineError::ArithmeticOverflow)?;
+    let interest = self.escrow_interest(transaction, now)?;
+    let chargeback_fee = self.config.chargeback_fee;
+    let mut fee = Decimal::ZERO;
+    let funds = checked_funds(&account.funds, |funds| {
+      funds.charge_back(transaction.kind, transaction.amount)?;
+      if transaction.kind.is_debit() {
+        funds.credit(interest)?;
+      }
+      // the fee of a transaction is only charged once, so charging back a fee doesn't charge another one
+      if transaction.kind != TransactionKind::Fee {
+        fee = chargeback_fee.map_or(Decimal::ZERO, |fee| fee.charge(funds.available));
+      }
+      funds.debit(fee)
+    })?;
+    Ok((funds, fee))
+  }
+
+  /// Unlock an account locked by a chargeback. Depending on the [`UnlockHeldFundsPolicy`],
+  /// the open disputes are kept, or resolved releasing their held funds.
+  fn unlock(&mut self, client_id: ClientId) -> Result<()> {
+    self.check_unlock(client_id)?;
+    let release_held_funds = self.config.unlock_held_funds_policy == UnlockHeldFundsPolicy::Release;
+    if release_held_funds {
+      let released = self.released_funds(self.get_account(client_id)?)?;
+      let account = self.get_account_mut(client_id)?;
+      for transaction in account
+        .transactions
+        .values_mut()
+        .chain(account.fees.values_mut())
+      {
+        if let Some(resolved) = transaction.state.resolve() {
+          transaction.state = resolved;
+        }
+      }
+      account.open_disputes = 0;
+      account.funds = released.funds;
+      account.sub_accounts = released.sub_accounts;
+    }
+    self.get_account_mut(client_id)?.locked = false;
+    Ok(())
+  }
+
+  fn check_unlock(&self, client_id: ClientId) -> Result<()> {
+    let account = self.get_account(client_id)?;
+    if !account.locked {
+      Err(PaymentsEngineError::AccountNotLocked(client_id))
+    } else if self.config.unlock_held_funds_policy == UnlockHeldFundsPolicy::Release {
+      self.released_funds(account).map(|_| ())
+    } else {
+      Ok(())
+    }
+  }
+
+  /// The funds after resolving all the open disputes of the account, as an account without transactions,
+  /// so they are released from the sub-account of every transaction.
+  fn released_funds(&self, account: &Account) -> Result<Account> {
+    let mut released = Account {
+      funds: account.funds.clone(),
+      sub_accounts: account.sub_accounts.clone(),
+      ..Account::default()
+    };
+    for transaction in account.all_transactions() {
+      if transaction.in_dispute() {
+        let interest = self.escrow_interest(transaction, self.clock)?;
+        let funds = checked_funds(&released.funds, |funds| {
+          funds.release(transaction.kind, transaction.amount)?;
+          if transaction.kind == TransactionKind::Deposit {
+            funds.credit(interest)?;
+          }
+          Some(())
+        })?;
+        update_funds(&mut released, transaction.sub_account, funds)?;
+      }
+    }
+    Ok(released)
+  }
+
+  /// The escrow interest accrued by the held funds of a disputed transaction until the time.
+  fn escrow_interest(&self, transaction: &TransactionState, now: u64) -> Result<Decimal> {
+    escrow_interest(self.config.escrow_interest_rate, now, transaction)
+      .ok_or(PaymentsEngineError::ArithmeticOverflow)
+  }
+
+  /// The time of a transaction with the timestamp, which is the clock of the engine when it has none,
+  /// as the clock never goes back.
+  fn now(&self, timestamp: Option<u64>) -> u64 {
+    timestamp.map_or(self.clock, |timestamp| timestamp.max(self.clock))
+  }
+
+  /// Whether the dispute raised at the timestamp is too late for the transaction, according to the dispute window.
+  /// It can't be known without both timestamps, in which case the dispute is allowed.
+  fn dispute_window_expired(&self, transaction: &TransactionState, timestamp: Option<u64>) -> bool {
+    match (
+      self.config.dispute_window_days,
+      transaction.timestamp,
+      timestamp,
+    ) {
+      (Some(days), Some(transaction_timestamp), Some(timestamp)) => {
+        timestamp.saturating_sub(transaction_timestamp) > days.saturating_mul(SECONDS_PER_DAY)
+      }
+      _ => false,
+    }
+  }
+
+  /// Check that the transaction is being disputed, as required to resolve it or charge it back.
+  fn check_disputed(&self, client_id: ClientId, transaction_id: TransactionId) -> Result<()> {
+    let account = self.get_account(client_id)?;
+    let transaction = get_disputed(account, transaction_id, self.fees_disputable())?;
+
+    if !transaction.in_dispute() {
+      Err(PaymentsEngineError::TransactionNotDisputed(
+        client_id,
+        transaction_id,
+      ))
+    } else {
+      Ok(())
+    }
+  }
+
+  fn check(&self, transaction: &Transaction) -> Result<()> {
+    match *transaction {
+      Transaction::Deposit {
+        client_id,
+        transaction_id,
+        amount,
+        ..
+      } => self.check_deposit(client_id, transaction_id, amount),
+      Transaction::Withdrawal {
+        client_id,
+        transaction_id,
+        amount,
+        timestamp,
+        sub_account,
+      } => self
+        .check_withdrawal(client_id, transaction_id, amount, sub_account)
+        .and_then(|_| self.check_dormancy(client_id, timestamp))
+        .and_then(|_| self.check_limits(client_id, transaction_id, amount, timestamp)),
+      Transaction::Dispute {
+        client_id,
+        transaction_id,
+        timestamp,
+      } => self.check_dispute(client_id, transaction_id, timestamp),
+      Transaction::Resolve {
+        client_id,
+        transaction_id,
+        timestamp,
+      } => self.check_resolve(client_id, transaction_id, timestamp),
+      Transaction::Chargeback {
+        client_id,
+        transaction_id,
+        timestamp,
+      } => self.check_chargeback(client_id, transaction_id, timestamp),
+      Transaction::Transfer {
+        from_client,
+        to_client,
+        transaction_id,
+        amount,
+        timestamp,
+      } => self
+        .check_transfer(from_client, to_client, transaction_id, amount)
+        .and_then(|_| self.check_dormancy(from_client, timestamp))
+        .and_then(|_| self.check_limits(from_client, transaction_id, amount, timestamp)),
+      Transaction::Unlock { client_id } => self.check_unlock(client_id),
+      Transaction::Reactivate { client_id } => self.check_reactivate(client_id),
+      Transaction::Authorize {
+        client_id,
+        transaction_id,
+        amount,
+        ..
+      } => self.check_deposit(client_id, transaction_id, amount),
+      Transaction::Capture {
+        client_id,
+        transaction_id,
+        timestamp,
+      } => self.check_capture(client_id, transaction_id, timestamp),
+      Transaction::Void {
+        client_id,
+        transaction_id,
+        ..
+      } => self.check_void(client_id, transaction_id),
+    }
+  }
+
+  fn get_account(&self, client_id: ClientId) -> Result<&Account> {
+    self
+      .accounts
+      .get(&client_id)
+      .ok_or(PaymentsEngineError::ClientNotFound(client_id))
+  }
+
+  fn get_account_mut(&mut self, client_id: ClientId) -> Result<&mut Account> {
+    self
+      .accounts
+      .get_mut(&client_id)
+      .ok_or(PaymentsEngineError::ClientNotFound(client_id))
+  }
+
+  fn get_or_create_account(&mut self, client_id: ClientId) -> &mut Account {
+    self
+      .accounts
+      .entry(client_id)
+      .or_insert_with(Account::default)
+  }
+
+  fn accounts_report_iter(
+    &self,
+    filter: AccountFilter,
+  ) -> impl Iterator<Item = AccountReport> + '_ {
+    self
+      .accounts
+      .iter()
+      .filter(move |(client_id, account)| filter.matches(**client_id, account))
+      .map(move |(client_id, account)| self.account_report(*client_id, account))
+  }
+
+  pub(crate) fn account_report(&self, client_id: ClientId, account: &Account) -> AccountReport {
+    let total = account.funds.total();
+    let account_report = AccountReport::new(
+      client_id,
+      account.funds.available,
+      account.funds.held,
+      total,
+      account.locked,
+    )
+    // the chargebacks overflowing the total are rejected, so it only saturates for the accounts built otherwise
+    .with_disputes(
+      account.open_disputes,
+      account.charged_back_total().unwrap_or(Decimal::MAX),
+    )
+    .with_fees(account.fees_total().unwrap_or(Decimal::MAX))
+    .with_sub_accounts(account.sub_accounts_report());
+
+    let account_report = match self.config.exposure_threshold {
+      Some(threshold) => account_report.with_exposure(Exposure {
+        amount: account.exposure(),
+        threshold,
+      }),
+      None => account_report,
+    };
+
+    let account_report = match self.config.dormancy {
+      Some(_) => account_report.with_dormant(self.is_dormant(client_id, self.clock)),
+      None => account_report,
+    };
+
+    if self.config.escrow_interest_rate.is_some() {
+      // the report can't fail, so the interest saturates when it overflows
+      let escrow_interest = account
+        .all_transactions()
+        .filter(|transaction| transaction.in_dispute())
+        .try_fold(Decimal::ZERO, |total, transaction| {
+          total.checked_add(self.escrow_interest(transaction, self.clock).ok()?)
+        })
+        .unwrap_or(Decimal::MAX);
+      account_report.with_escrow_interest(escrow_interest)
+    } else {
+      account_report
+    }
+  }
+}
+
+fn get_transaction(account: &Account, transaction_id: TransactionId) -> Result<&TransactionState> {
+  account
+    .transactions
+    .get(&transaction_id)
+    .ok_or(PaymentsEngineError::TransactionNotFound(transaction_id))
+}
+
+/// The transaction that the disputes of the ID refer to (see [`Account::disputed_transaction`]).
+fn get_disputed(
+  account: &Account,
+  transaction_id: TransactionId,
+  fees_disputable: bool,
+) -> Result<&TransactionState> {
+  account
+    .disputed_transaction(transaction_id, fees_disputable)
+    .ok_or(PaymentsEngineError::TransactionNotFound(transaction_id))
+}
+
+/// Check that the transaction is an authorization that was neither captured nor voided.
+fn check_authorization(
+  account: &Account,
+  client_id: ClientId,
+  transaction_id: TransactionId,
+) -> Result<&TransactionState> {
+  let transaction = get_transaction(account, transaction_id)?;
+  if transaction.kind == TransactionKind::Authorization {
+    Ok(transaction)
+  } else {
+    Err(PaymentsEngineError::NotAnAuthorization(
+      client_id,
+      transaction_id,
+    ))
+  }
+}
+
+/// The simple interest accrued by the held funds of a disputed transaction for every full day since the dispute started.
+/// When the client wins the dispute (resolving a deposit or charging back a withdrawal), the interest is credited to them,
+/// otherwise it goes to the counterparty, which is outside of the engine.
+/// It returns `None` when the interest overflows.
+fn escrow_interest(
+  rate: Option<Decimal>,
+  now: u64,
+  transaction: &TransactionState,
+) -> Option<Decimal> {
+  rate.map_or(Some(Decimal::ZERO), |rate| {
+    let elapsed = Decimal::from(now.saturating_sub(transaction.disputed_at) / SECONDS_PER_DAY);
+    transaction.amount.checked_mul(rate)?.checked_mul(elapsed)
+  })
+}
+
+/// Apply the change to a copy of the funds, which fails with [`PaymentsEngineError::ArithmeticOverflow`] when they overflow.
+/// The funds of an account are only replaced once all the changes of a transaction succeeded.
+fn checked_funds<F>(funds: &Funds, change: F) -> Result<Funds>
+where
+  F: FnOnce(&mut Funds) -> Option<()>,
+{
+  let mut funds = funds.clone();
+  change(&mut funds)
+    .map(|_| funds)
+    .ok_or(PaymentsEngineError::ArithmeticOverflow)
+}
+
+/// The funds of the sub-account of the client, which fail with [`PaymentsEngineError::ArithmeticOverflow`]
+/// when they can't be represented.
+fn sub_account_funds(account: &Account, sub_account: SubAccountId) -> Result<Funds> {
+  account
+    .sub_account_funds(sub_account)
+    .ok_or(PaymentsEngineError::ArithmeticOverflow)
+}
+
+/// Replace the funds of the account with the ones changed by a transaction of the sub-account, which gets the same change.
+/// It fails with [`PaymentsEngineError::ArithmeticOverflow`], without changing them, when the funds of the sub-account overflow.
+fn update_funds(account: &mut Account, sub_account: SubAccountId, funds: Funds) -> Result<()> {
+  account
+    .update_funds(sub_account, funds)
+    .ok_or(PaymentsEngineError::ArithmeticOverflow)
+}
+
+/// The funds after holding the amount of the disputed transaction.
+fn held_funds(account: &Account, transaction: &TransactionState) -> Result<Funds> {
+  checked_funds(&account.funds, |funds| {
+    funds.hold(transaction.kind, transaction.amount)
+  })
+}
+
+/// Move the transaction to its next [`DisputeState`], failing when it can't be reached from the current one,
+/// and keep the count of the open disputes of the account.
+fn transition<F>(
+  account: &mut Account,
+  client_id: ClientId,
+  transaction_id: TransactionId,
+  fees_disputable: bool,
+  next: F,
+) -> Result<&mut TransactionState>
+where
+  F: FnOnce(DisputeState) -> Option<DisputeState>,
+{
+  let transactions = if account.disputes_fee(transaction_id, fees_disputable) {
+    &mut account.fees
+  } else {
+    &mut account.transactions
+  };
+  let transaction = transactions
+    .get_mut(&transaction_id)
+    .ok_or(PaymentsEngineError::TransactionNotFound(transaction_id))?;
+  let was_in_dispute = transaction.in_dispute();
+  transaction.state = next(transaction.state)
+    .ok_or_else(|| transition_error(client_id, transaction_id, transaction.state))?;
+  match (was_in_dispute, transaction.in_dispute()) {
+    (false, true) => account.open_disputes += 1,
+    (true, false) => account.open_disputes = account.open_disputes.saturating_sub(1),
+    _ => {}
+  }
+  Ok(transaction)
+}
+
+/// The error for a transition that the [`DisputeState`] of the transaction doesn't allow.
+fn transition_error(
+  client_id: ClientId,
+  transaction_id: TransactionId,
+  state: DisputeState,
+) -> PaymentsEngineError {
+  match state {
+    DisputeState::Disputed => {
+      PaymentsEngineError::TransactionAlreadyDisputed(client_id, transaction_id)
+    }
+    DisputeState::ChargedBack => {
+      PaymentsEngineError::TransactionChargedBack(client_id, transaction_id)
+    }
+    DisputeState::Recorded | DisputeState::Resolved => {
+      PaymentsEngineError::TransactionNotDisputed(client_id, transaction_id)
+    }
+  }
+}
+
+impl Default for InMemoryPaymentsEngine {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+impl SyncPaymentsEngine for InMemoryPaymentsEngine {
+  fn process_sync(&mut self, transaction: Transaction) -> Result<()> {
+    let span = tracing::trace_span!(
+      "transaction",
+      r#type = transaction.type_name(),
+      client_id = transaction.client_id(),
+      tx_id = transaction.transaction_id(),
+    );
+    let _entered = span.enter();
+
+    if let Err(err) = self.load_stored(&transaction) {
+      tracing::error!(error = %err, "Transaction store failed");
+      return Err(err);
+    }
+    let clients = match self.store {
+      Some(_) => transaction.clients(),
+      None => Vec::new(),
+    };
+    let activity = self.recent_activity(&transaction);
+    let now = self.now(transaction.timestamp());
+    let active_client = self.active_client(&transaction, now);
+    let result = match transaction {
+      Transaction::Deposit {
+        client_id,
+        transaction_id,
+        amount,
+        timestamp,
+        sub_account,
+      } => self.deposit(client_id, transaction_id, amount, timestamp, sub_account),
+      Transaction::Withdrawal {
+        client_id,
+        transaction_id,
+        amount,
+        timestamp,
+        sub_account,
+      } => self.withdrawal(client_id, transaction_id, amount, timestamp, sub_account),
+      Transaction::Dispute {
+        client_id,
+        transaction_id,
+        timestamp,
+      } => self.dispute(client_id, transaction_id, timestamp),
+      Transaction::Resolve {
+        client_id,
+        transaction_id,
+        timestamp,
+      } => self.resolve(client_id, transaction_id, timestamp),
+      Transaction::Chargeback {
+        client_id,
+        transaction_id,
+        timestamp,
+      } => self.chargeback(client_id, transaction_id, timestamp),
+      Transaction::Transfer {
+        from_client,
+        to_client,
+        transaction_id,
+        amount,
+        timestamp,
+      } => self.transfer(from_client, to_client, transaction_id, amount, timestamp),
+      Transaction::Unlock { client_id } => self.unlock(client_id),
+      Transaction::Reactivate { client_id } => self.check_reactivate(client_id),
+      Transaction::Authorize {
+        client_id,
+        transaction_id,
+        amount,
+        timestamp,
+        sub_account,
+      } => self.authorize(client_id, transaction_id, amount, timestamp, sub_account),
+      Transaction::Capture {
+        client_id,
+        transaction_id,
+        timestamp,
+      } => self.capture(client_id, transaction_id, timestamp),
+      Transaction::Void {
+        client_id,
+        transaction_id,
+        ..
+      } => self.void(client_id, transaction_id),
+    };
+    if let (Ok(()), Some((client_id, timestamp, withdrawal))) = (&result, activity) {
+      self
+        .activity
+        .entry(client_id)
+        .or_default()
+        .record(timestamp, withdrawal);
+    }
+    if let (Ok(()), Some(client_id)) = (&result, active_client) {
+      self.activity.entry(client_id).or_default().touch(now);
+    }
+    match &result {
+      Ok(()) if self.config.ticks_clock() => self.clock = now,
+      Ok(()) => {}
+      Err(err) => tracing::warn!(error = %err, kind = err.kind(), "Transaction rejected"),
+    }
+    if let Err(err) = self.store_settled(&clients) {
+      tracing::error!(error = %err, "Transaction store failed");
+      return Err(err);
+    }
+    result
+  }
+}
+
+// the queries of the engine don't need the `async` feature, so they are implemented without the trait
+impl InMemoryPaymentsEngine {
+  /// Same as [`PaymentsEngine::validate`], which is also available without the `async` feature.
+  pub fn validate(&self, transaction: &Transaction) -> Result<()> {
+    match self.find_stored(transaction)? {
+      Some(stored) => {
+        // the stored transaction is checked on a copy of the accounts involved, as the engine can't be changed
+        let mut accounts = HashMap::new();
+        for client_id in transaction.clients() {
+          if let Some(account) = self.accounts.get(&client_id) {
+            accounts.insert(client_id, account.clone());
+          }
+        }
+        if let (Some(account), Some(transaction_id)) = (
+          accounts.get_mut(&transaction.client_id()),
+          transaction.transaction_id(),
+        ) {
+          account.transactions.insert(transaction_id, stored);
+        }
+        let activity = transaction
+          .clients()
+          .into_iter()
+          .filter_map(|client_id| {
+            let activity = self.activity.get(&client_id)?;
+            Some((client_id, activity.clone()))
+          })
+          .collect();
+        let engine = Self {
+          config: self.config.clone(),
+          accounts,
+          clock: self.clock,
+          store: None,
+          activity,
+        };
+        engine.check(transaction)
+      }
+      None => self.check(transaction),
+    }
+  }
+
+  /// Same as [`PaymentsEngine::accounts_report`].
+  pub fn accounts_report(&self) -> AccountsReportIter {
+    self.accounts_matching(AccountFilter::default())
+  }
+
+  /// Same as [`PaymentsEngine::accounts_matching`].
+  pub fn accounts_matching(&self, filter: AccountFilter) -> AccountsReportIter {
+    if self.config.deterministic {
+      let mut report: Vec<AccountReport> = self.accounts_report_iter(filter).collect();
+      report.sort_by_key(|account_report| account_report.client_id);
+      AccountsReportIter::new(report.into_iter())
+    } else {
+      AccountsReportIter::new(self.accounts_report_iter(filter))
+    }
+  }
+
+  /// Same as [`PaymentsEngine::account`].
+  pub fn account(&self, client_id: ClientId) -> Option<AccountReport> {
+    self
+      .accounts
+      .get(&client_id)
+      .map(|account| self.account_report(client_id, account))
+  }
+
+  /// Same as [`PaymentsEngine::transaction`].
+  pub fn transaction(
+    &self,
+    client_id: ClientId,
+    transaction_id: TransactionId,
+  ) -> Result<Option<TransactionInfo>> {
+    let account = match self.accounts.get(&client_id) {
+      Some(account) => account,
+      None => return Ok(None),
+    };
+    match (account.transactions.get(&transaction_id), &self.store) {
+      (Some(transaction), _) => Ok(Some(TransactionInfo::new(
+        client_id,
+        transaction_id,
+        transaction,
+      ))),
+      (None, Some(store)) => Ok(
+        store
+          .get(client_id, transaction_id)?
+          .map(|transaction| TransactionInfo::new(client_id, transaction_id, &transaction)),
+      ),
+      (None, None) => Ok(None),
+    }
+  }
+
+  /// Same as [`PaymentsEngine::transactions_report`].
+  pub fn transactions_report(&self) -> Result<TransactionsReportIter> {
+    // the stored transactions are read first, so a failure of the store is found before writing the report
+    let stored = self
+      .stored_transactions()
+      .map(|stored| {
+        stored.map(|(client_id, transaction_id, transaction)| {
+          TransactionInfo::new(client_id, transaction_id, &transaction)
+        })
+      })
+      .collect::<Result<Vec<TransactionInfo>>>()?;
+    let report = self
+      .accounts
+      .iter()
+      .flat_map(|(client_id, account)| {
+        account.transactions.iter().chain(account.fees.iter()).map(
+          move |(transaction_id, transaction)| {
+            TransactionInfo::new(*client_id, *transaction_id, transaction)
+          },
+        )
+      })
+      .chain(stored);
+    if self.config.deterministic {
+      let mut report: Vec<TransactionInfo> = report.collect();
+      // the fees are reported with the ID of the transaction charged back, after it
+      report.sort_by_key(|transaction| {
+        (
+          transaction.client_id,
+          transaction.transaction_id,
+          transaction.kind == TransactionKind::Fee,
+        )
+      });
+      Ok(TransactionsReportIter::new(report.into_iter()))
+    } else {
+      Ok(TransactionsReportIter::new(report))
+    }
+  }
+}
+
+#[cfg(feature = "async")]
+#[async_trait]
+impl PaymentsEngine for InMemoryPaymentsEngine {
+  async fn process(&mut self, transaction: Transaction) -> Result<()> {
+    self.process_sync(transaction)
+  }
+
+  fn validate(&self, transaction: &Transaction) -> Result<()> {
+    InMemoryPaymentsEngine::validate(self, transaction)
+  }
+
+  fn accounts_report(&self) -> AccountsReportIter {
+    InMemoryPaymentsEngine::accounts_report(self)
+  }
+
+  fn accounts_matching(&self, filter: AccountFilter) -> AccountsReportIter {
+    InMemoryPaymentsEngine::accounts_matching(self, filter)
+  }
+
+  fn account(&self, client_id: ClientId) -> Option<AccountReport> {
+    InMemoryPaymentsEngine::account(self, client_id)
+  }
+
+  fn transaction(
+    &self,
+    client_id: ClientId,
+    transaction_id: TransactionId,
+  ) -> Result<Option<TransactionInfo>> {
+    InMemoryPaymentsEngine::transaction(self, client_id, transaction_id)
+  }
+
+  fn transactions_report(&self) -> Result<TransactionsReportIter> {
+    InMemoryPaymentsEngine::transactions_report(self)
+  }
+}
+
+/// A [`PaymentsEngine`] chosen at runtime, like the one opened for the options of the command line.
+#[cfg(feature = "async")]
+pub type BoxedPaymentsEngine = Box<dyn PaymentsEngine + Send>;
+
+#[cfg(feature = "async")]
+#[async_trait]
+impl<P> PaymentsEngine for Box<P>
+where
+  P: PaymentsEngine + Send + ?Sized,
+{
+  async fn process(&mut self, transaction: Transaction) -> Result<()> {
+    (**self).process(transaction).await
+  }
+
+  fn validate(&self, transaction: &Transaction) -> Result<()> {
+    (**self).validate(transaction)
+  }
+
+  fn accounts_report(&self) -> AccountsReportIter {
+    (**self).accounts_report()
+  }
+
+  fn accounts_matching(&self, filter: AccountFilter) -> AccountsReportIter {
+    (**self).accounts_matching(filter)
+  }
+
+  fn accounts_report_stream(&self) -> AccountsReportStream {
+    (**self).accounts_report_stream()
+  }
+
+  fn sorted_accounts_report(&self, options: &ReportOptions) -> AccountsReportIter {
+    (**self).sorted_accounts_report(options)
+  }
+
+  fn account(&self, client_id: ClientId) -> Option<AccountReport> {
+    (**self).account(client_id)
+  }
+
+  fn transaction(
+    &self,
+    client_id: ClientId,
+    transaction_id: TransactionId,
+  ) -> Result<Option<TransactionInfo>> {
+    (**self).transaction(client_id, transaction_id)
+  }
+
+  fn transactions_report(&self) -> Result<TransactionsReportIter> {
+    (**self).transactions_report()
+  }
+}
+
+/// The accounts report of a [`PaymentsEngine`], which is `Send` so it can be written from a spawned task.
+pub struct AccountsReportIter<'a>(Box<dyn Iterator<Item = AccountReport> + Send + 'a>);
+
+impl<'a> AccountsReportIter<'a> {
+  pub(crate) fn new<T>(iter: T) -> Self
+  where
+    T: Iterator<Item = AccountReport> + Send + 'a,
+  {
+    Self(Box::new(iter))
+  }
+}
+
+impl<'a> Iterator for AccountsReportIter<'a> {
+  type Item = AccountReport;
+
+  fn next(&mut self) -> Option<Self::Item> {
+    self.0.next()
+  }
+}
+
+/// The transactions report of a [`PaymentsEngine`], which is `Send` so it can be written from a spawned task.
+pub struct TransactionsReportIter<'a>(Box<dyn Iterator<Item = TransactionInfo> + Send + 'a>);
+
+impl<'a> TransactionsReportIter<'a> {
+  pub(crate) fn new<T>(iter: T) -> Self
+  where
+    T: Iterator<Item = TransactionInfo> + Send + 'a,
+  {
+    Self(Box::new(iter))
+  }
+}
+
+impl<'a> Iterator for TransactionsReportIter<'a> {
+  type Item = TransactionInfo;
+
+  fn next(&mut self) -> Option<Self::Item> {
+    self.0.next()
+  }
+}
+
+/// The accounts report of a [`PaymentsEngine`] as a [`Stream`], where reading every account can fail.
+#[cfg(feature = "async")]
+pub struct AccountsReportStream<'a>(Pin<Box<dyn Stream<Item = Result<AccountReport>> + Send + 'a>>);
+
+#[cfg(feature = "async")]
+impl<'a> AccountsReportStream<'a> {
+  pub(crate) fn new<T>(stream: T) -> Self
+  where
+    T: Stream<Item = Result<AccountReport>> + Send + 'a,
+  {
+    Self(Box::pin(stream))
+  }
+
+  /// A stream of the accounts of a report that is already available, which can't fail.
+  pub fn iter<T>(report: T) -> Self
+  where
+    T: IntoIterator<Item = AccountReport>,
+    T::IntoIter: Send + 'a,
+  {
+    Self::new(tokio_stream::iter(report.into_iter().map(Ok)))
+  }
+}
+
+#[cfg(feature = "async")]
+impl<'a> From<AccountsReportIter<'a>> for AccountsReportStream<'a> {
+  fn from(report: AccountsReportIter<'a>) -> Self {
+    Self::iter(report)
+  }
+}
+
+#[cfg(feature = "async")]
+impl<'a> Stream for AccountsReportStream<'a> {
+  type Item = Result<AccountReport>;
+
+  fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+    self.0.as_mut().poll_next(cx)
+  }
+}
+
+/// A borrowed account of an [`InMemoryPaymentsEngine`], returned by [`InMemoryPaymentsEngine::accounts_report_ref`].
+#[derive(Debug, Clone, Copy)]
+pub struct AccountView<'a> {
+  engine: &'a InMemoryPaymentsEngine,
+  client_id: ClientId,
+  account: &'a Account,
+}
+
+impl<'a> AccountView<'a> {
+  pub fn client_id(&self) -> ClientId {
+    self.client_id
+  }
+
+  pub fn available(&self) -> Decimal {
+    self.account.funds.available
+  }
+
+  pub fn held(&self) -> Decimal {
+    self.account.funds.held
+  }
+
+  pub fn total(&self) -> Decimal {
+    self.account.funds.total()
+  }
+
+  pub fn locked(&self) -> bool {
+    self.account.locked
+  }
+
+  /// The full report of the account, which needs to go through all its transactions.
+  pub fn report(&self) -> AccountReport {
+    self.engine.account_report(self.client_id, self.account)
+  }
+}
+
+#[cfg(all(test, feature = "async"))]
+mod tests {
+
+  use std::collections::{BTreeMap, HashSet};
+
+  use rust_decimal_macros::dec;
 
   use super::*;
-  use crate::payments::account::Funds;
+  use crate::payments::account::{Funds, SubAccountReport};
+  use crate::payments::{
+    ChargebackFee, DormancyPolicy, InMemoryTransactionStore, LimitsPolicy, StoredTransactions,
+  };
+
+  #[tokio::test]
+  async fn process_deposit_negative_amount() {
+    let mut engine = InMemoryPaymentsEngine::new();
+    let transaction = Transaction::Deposit {
+      client_id: 1,
+      transaction_id: 101,
+      amount: dec!(-10),
+      timestamp: None,
+      sub_account: 0,
+    };
+
+    let result = engine.process(transaction).await;
+
+    assert_eq!(result, Err(PaymentsEngineError::NegativeAmount));
+  }
+
+  #[tokio::test]
+  async fn process_deposit_zero_amount_accepted() {
+    let mut engine = InMemoryPaymentsEngine::new();
+    let transaction = Transaction::Deposit {
+      client_id: 1,
+      transaction_id: 101,
+      amount: dec!(0),
+      timestamp: None,
+      sub_account: 0,
+    };
+
+    let result = engine.process(transaction).await;
+
+    assert_eq!(result, Ok(()));
+    assert_eq!(
+      engine.accounts.get(&1).unwrap(),
+      &Account {
+        locked: false,
+        funds: Funds::available(dec!(0)),
+        transactions: vec![(101, TransactionState::from_amount(dec!(0)))]
+          .into_iter()
+          .collect(),
+        open_disputes: 0,
+        sub_accounts: BTreeMap::new(),
+        fees: HashMap::new(),
+      }
+    );
+  }
+
+  #[tokio::test]
+  async fn process_deposit_zero_amount_rejected() {
+    let mut engine = InMemoryPaymentsEngine::with_config(EngineConfig {
+      zero_amount_policy: ZeroAmountPolicy::Reject,
+      ..EngineConfig::default()
+    });
+    let transaction = Transaction::Deposit {
+      client_id: 1,
+      transaction_id: 101,
+      amount: dec!(0),
+      timestamp: None,
+      sub_account: 0,
+    };
+
+    let result = engine.process(transaction).await;
+
+    assert_eq!(result, Err(PaymentsEngineError::ZeroAmount));
+    assert!(engine.accounts.is_empty());
+  }
+
+  #[tokio::test]
+  async fn process_deposit_zero_amount_skipped() {
+    let mut engine = InMemoryPaymentsEngine::with_config(EngineConfig {
+      zero_amount_policy: ZeroAmountPolicy::Skip,
+      ..EngineConfig::default()
+    });
+    let transaction = Transaction::Deposit {
+      client_id: 1,
+      transaction_id: 101,
+      amount: dec!(0),
+      timestamp: None,
+      sub_account: 0,
+    };
+
+    let result = engine.process(transaction).await;
+
+    assert_eq!(result, Ok(()));
+    assert!(engine.accounts.is_empty());
+  }
+
+  #[tokio::test]
+  async fn process_deposit_account_locked() {
+    let mut engine = InMemoryPaymentsEngine::new();
+    engine.accounts.insert(
+      1,
+      Account {
+        locked: true,
+        ..Account::default()
+      },
+    );
+    let transaction = Transaction::Deposit {
+      client_id: 1,
+      transaction_id: 101,
+      amount: dec!(20),
+      timestamp: None,
+      sub_account: 0,
+    };
+
+    let result = engine.process(transaction).await;
+
+    assert_eq!(result, Err(PaymentsEngineError::AccountLocked(1)));
+  }
+
+  #[tokio::test]
+  async fn process_deposit_transaction_exists() {
+    let mut engine = InMemoryPaymentsEngine::new();
+    engine.accounts.insert(
+      1,
+      Account {
+        locked: false,
+        funds: Funds::available(dec!(10)),
+        transactions: vec![(101, TransactionState::from_amount(dec!(10)))]
+          .into_iter()
+          .collect(),
+        open_disputes: 0,
+        sub_accounts: BTreeMap::new(),
+        fees: HashMap::new(),
+      },
+    );
+    let transaction = Transaction::Deposit {
+      client_id: 1,
+      transaction_id: 101,
+      amount: dec!(20),
+      timestamp: None,
+      sub_account: 0,
+    };
+
+    let result = engine.process(transaction).await;
+
+    assert_eq!(result, Err(PaymentsEngineError::DuplicatedTransaction(101)));
+  }
+
+  #[tokio::test]
+  async fn process_deposit_successfully() {
+    let mut engine = InMemoryPaymentsEngine::new();
+    engine.accounts.insert(1, Account::default());
+    let transaction = Transaction::Deposit {
+      client_id: 1,
+      transaction_id: 101,
+      amount: dec!(10),
+      timestamp: None,
+      sub_account: 0,
+    };
+
+    let result = engine.process(transaction).await;
+
+    assert_eq!(result, Ok(()));
+    assert_eq!(engine.accounts.len(), 1);
+    assert_eq!(
+      engine.accounts.get(&1).unwrap(),
+      &Account {
+        locked: false,
+        funds: Funds::available(dec!(10)),
+        transactions: vec![(101, TransactionState::from_amount(dec!(10)))]
+          .into_iter()
+          .collect(),
+        open_disputes: 0,
+        sub_accounts: BTreeMap::new(),
+        fees: HashMap::new(),
+      }
+    );
+  }
+
+  #[tokio::test]
+  async fn process_withdrawal_negative_amount() {
+    let mut engine = InMemoryPaymentsEngine::new();
+    let transaction = Transaction::Withdrawal {
+      client_id: 1,
+      transaction_id: 101,
+      amount: dec!(-10),
+      timestamp: None,
+      sub_account: 0,
+    };
+
+    let result = engine.process(transaction).await;
+
+    assert_eq!(result, Err(PaymentsEngineError::NegativeAmount));
+  }
+
+  #[tokio::test]
+  async fn process_withdrawal_zero_amount_rejected() {
+    let mut engine = InMemoryPaymentsEngine::with_config(EngineConfig {
+      zero_amount_policy: ZeroAmountPolicy::Reject,
+      ..EngineConfig::default()
+    });
+    engine.accounts.insert(1, Account::default());
+    let transaction = Transaction::Withdrawal {
+      client_id: 1,
+      transaction_id: 101,
+      amount: dec!(0),
+      timestamp: None,
+      sub_account: 0,
+    };
+
+    let result = engine.process(transaction).await;
+
+    assert_eq!(result, Err(PaymentsEngineError::ZeroAmount));
+  }
+
+  #[tokio::test]
+  async fn process_withdrawal_account_locked() {
+    let mut engine = InMemoryPaymentsEngine::new();
+    engine.accounts.insert(
+      1,
+      Account {
+        locked: true,
+        ..Account::default()
+      },
+    );
+    let transaction = Transaction::Withdrawal {
+      client_id: 1,
+      transaction_id: 101,
+      amount: dec!(20),
+      timestamp: None,
+      sub_account: 0,
+    };
+
+    let result = engine.process(transaction).await;
+
+    assert_eq!(result, Err(PaymentsEngineError::AccountLocked(1)));
+  }
+
+  #[tokio::test]
+  async fn process_withdrawal_transaction_exists() {
+    let mut engine = InMemoryPaymentsEngine::new();
+    engine.accounts.insert(
+      1,
+      Account {
+        locked: false,
+        funds: Funds::available(dec!(10)),
+        transactions: vec![(101, TransactionState::from_amount(dec!(10)))]
+          .into_iter()
+          .collect(),
+        open_disputes: 0,
+        sub_accounts: BTreeMap::new(),
+        fees: HashMap::new(),
+      },
+    );
+    let transaction = Transaction::Withdrawal {
+      client_id: 1,
+      transaction_id: 101,
+      amount: dec!(5),
+      timestamp: None,
+      sub_account: 0,
+    };
+
+    let result = engine.process(transaction).await;
+
+    assert_eq!(result, Err(PaymentsEngineError::DuplicatedTransaction(101)));
+  }
+
+  #[tokio::test]
+  async fn process_withdrawal_not_enough_available_funds() {
+    let mut engine = InMemoryPaymentsEngine::new();
+    engine.accounts.insert(
+      1,
+      Account {
+        locked: false,
+        funds: Funds::available(dec!(10)),
+        transactions: HashMap::default(),
+        open_disputes: 0,
+        sub_accounts: BTreeMap::new(),
+        fees: HashMap::new(),
+      },
+    );
+    let transaction1 = Transaction::Withdrawal {
+      client_id: 1,
+      transaction_id: 101,
+      amount: dec!(10),
+      timestamp: None,
+      sub_account: 0,
+    };
+    let transaction2 = Transaction::Withdrawal {
+      client_id: 1,
+      transaction_id: 102,
+      amount: dec!(0.2),
+      timestamp: None,
+      sub_account: 0,
+    };
+
+    let result = engine.process(transaction1).await;
+    assert!(result.is_ok());
+
+    let result = engine.process(transaction2).await;
+
+    assert_eq!(result, Err(PaymentsEngineError::NotEnoughAvailableFunds));
+  }
+
+  #[tokio::test]
+  async fn process_withdrawal_non_existing_client() {
+    let mut engine = InMemoryPaymentsEngine::new();
+    let transaction1 = Transaction::Withdrawal {
+      client_id: 1,
+      transaction_id: 101,
+      amount: dec!(10),
+      timestamp: None,
+      sub_account: 0,
+    };
+
+    let result = engine.process(transaction1).await;
+
+    assert_eq!(result, Err(PaymentsEngineError::ClientNotFound(1)));
+  }
+
+  #[tokio::test]
+  async fn process_withdrawal_successfully() {
+    let mut engine = InMemoryPaymentsEngine::new();
+    engine.accounts.insert(
+      1,
+      Account {
+        locked: false,
+        funds: Funds::available(dec!(100)),
+        transactions: HashMap::default(),
+        open_disputes: 0,
+        sub_accounts: BTreeMap::new(),
+        fees: HashMap::new(),
+      },
+    );
+    let transaction = Transaction::Withdrawal {
+      client_id: 1,
+      transaction_id: 101,
+      amount: dec!(10),
+      timestamp: None,
+      sub_account: 0,
+    };
+
+    let result = engine.process(transaction).await;
+
+    assert!(result.is_ok());
+    assert_eq!(engine.accounts.len(), 1);
+    assert_eq!(
+      engine.accounts.get(&1).unwrap(),
+      &Account {
+        locked: false,
+        funds: Funds::available(dec!(90)),
+        transactions: vec![(101, TransactionState::from_withdrawal(dec!(10)))]
+          .into_iter()
+          .collect(),
+        open_disputes: 0,
+        sub_accounts: BTreeMap::new(),
+        fees: HashMap::new(),
+      }
+    );
+  }
+
+  #[tokio::test]
+  async fn process_dispute_non_existing_client() {
+    let mut engine = InMemoryPaymentsEngine::new();
+    let transaction = Transaction::Dispute {
+      client_id: 1,
+      transaction_id: 101,
+      timestamp: None,
+    };
+
+    let result = engine.process(transaction).await;
+
+    assert_eq!(result, Err(PaymentsEngineError::ClientNotFound(1)));
+  }
+
+  #[tokio::test]
+  async fn process_dispute_account_locked() {
+    let mut engine = InMemoryPaymentsEngine::new();
+    engine.accounts.insert(
+      1,
+      Account {
+        locked: true,
+        ..Account::default()
+      },
+    );
+    let transaction = Transaction::Dispute {
+      client_id: 1,
+      transaction_id: 101,
+      timestamp: None,
+    };
+
+    let result = engine.process(transaction).await;
+
+    assert_eq!(result, Err(PaymentsEngineError::AccountLocked(1)));
+  }
+
+  #[tokio::test]
+  async fn process_dispute_account_locked_allowed() {
+    let mut engine = InMemoryPaymentsEngine::with_config(EngineConfig {
+      locked_account_dispute_policy: LockedAccountDisputePolicy::Allow,
+      ..EngineConfig::default()
+    });
+    engine.accounts.insert(
+      1,
+      Account {
+        locked: true,
+        funds: Funds::available(dec!(100)),
+        transactions: vec![(101, TransactionState::from_amount(dec!(10)))]
+          .into_iter()
+          .collect(),
+        open_disputes: 0,
+        sub_accounts: BTreeMap::new(),
+        fees: HashMap::new(),
+      },
+    );
+    let dispute = Transaction::Dispute {
+      client_id: 1,
+      transaction_id: 101,
+      timestamp: None,
+    };
+    let resolve = Transaction::Resolve {
+      client_id: 1,
+      transaction_id: 101,
+      timestamp: None,
+    };
+    let deposit = Transaction::Deposit {
+      client_id: 1,
+      transaction_id: 102,
+      amount: dec!(10),
+      timestamp: None,
+      sub_account: 0,
+    };
+
+    assert_eq!(engine.process(dispute).await, Ok(()));
+    assert_eq!(
+      engine.accounts.get(&1).unwrap().funds,
+      Funds::new(dec!(90), dec!(10))
+    );
+    assert_eq!(engine.process(resolve).await, Ok(()));
+    assert_eq!(
+      engine.process(deposit).await,
+      Err(PaymentsEngineError::AccountLocked(1))
+    );
+  }
+
+  #[tokio::test]
+  async fn process_dispute_charged_back() {
+    let mut engine = InMemoryPaymentsEngine::with_config(EngineConfig {
+      locked_account_dispute_policy: LockedAccountDisputePolicy::Allow,
+      ..EngineConfig::default()
+    });
+    engine.accounts.insert(
+      1,
+      Account {
+        locked: true,
+        funds: Funds::available(dec!(100)),
+        transactions: vec![(101, TransactionState::from_chargeback(dec!(10)))]
+          .into_iter()
+          .collect(),
+        open_disputes: 0,
+        sub_accounts: BTreeMap::new(),
+        fees: HashMap::new(),
+      },
+    );
+    let transaction = Transaction::Dispute {
+      client_id: 1,
+      transaction_id: 101,
+      timestamp: None,
+    };
+
+    let result = engine.process(transaction).await;
+
+    assert_eq!(
+      result,
+      Err(PaymentsEngineError::TransactionChargedBack(1, 101))
+    );
+  }
+
+  #[tokio::test]
+  async fn process_dispute_non_existing_transaction() {
+    let mut engine = InMemoryPaymentsEngine::new();
+    engine.accounts.insert(
+      1,
+      Account {
+        locked: false,
+        funds: Funds::available(dec!(100)),
+        transactions: HashMap::default(),
+        open_disputes: 0,
+        sub_accounts: BTreeMap::new(),
+        fees: HashMap::new(),
+      },
+    );
+    let transaction = Transaction::Dispute {
+      client_id: 1,
+      transaction_id: 101,
+      timestamp: None,
+    };
+
+    let result = engine.process(transaction).await;
+
+    assert_eq!(result, Err(PaymentsEngineError::TransactionNotFound(101)));
+  }
+
+  #[tokio::test]
+  async fn process_dispute_already_disputed() {
+    let mut engine = InMemoryPaymentsEngine::new();
+    engine.accounts.insert(
+      1,
+      Account {
+        locked: false,
+        funds: Funds::available(dec!(100)),
+        transactions: vec![(101, TransactionState::from_dispute(dec!(10)))]
+          .into_iter()
+          .collect(),
+        open_disputes: 1,
+        sub_accounts: BTreeMap::new(),
+        fees: HashMap::new(),
+      },
+    );
+    let transaction = Transaction::Dispute {
+      client_id: 1,
+      transaction_id: 101,
+      timestamp: None,
+    };
+
+    let result = engine.process(transaction).await;
+
+    assert_eq!(
+      result,
+      Err(PaymentsEngineError::TransactionAlreadyDisputed(1, 101))
+    );
+  }
+
+  #[tokio::test]
+  async fn process_repeated_disputes() {
+    let mut engine = InMemoryPaymentsEngine::new();
+    let deposit = Transaction::Deposit {
+      client_id: 1,
+      transaction_id: 101,
+      amount: dec!(10),
+      timestamp: None,
+      sub_account: 0,
+    };
+    let dispute = Transaction::Dispute {
+      client_id: 1,
+      transaction_id: 101,
+      timestamp: None,
+    };
+    let state =
+      |engine: &InMemoryPaymentsEngine| engine.transaction(1, 101).unwrap().unwrap().state;
+
+    assert_eq!(engine.process(deposit).await, Ok(()));
+    assert_eq!(state(&engine), DisputeState::Recorded);
+    assert_eq!(engine.process(dispute.clone()).await, Ok(()));
+    assert_eq!(state(&engine), DisputeState::Disputed);
+    let resolve = Transaction::Resolve {
+      client_id: 1,
+      transaction_id: 101,
+      timestamp: None,
+    };
+    assert_eq!(engine.process(resolve).await, Ok(()));
+    assert_eq!(state(&engine), DisputeState::Resolved);
+    assert_eq!(engine.process(dispute.clone()).await, Ok(()));
+    assert_eq!(state(&engine), DisputeState::Disputed);
+    let chargeback = Transaction::Chargeback {
+      client_id: 1,
+      transaction_id: 101,
+      timestamp: None,
+    };
+    assert_eq!(engine.process(chargeback).await, Ok(()));
+    assert_eq!(state(&engine), DisputeState::ChargedBack);
+
+    engine
+      .process(Transaction::Unlock { client_id: 1 })
+      .await
+      .unwrap();
+    assert_eq!(
+      engine.process(dispute).await,
+      Err(PaymentsEngineError::TransactionChargedBack(1, 101))
+    );
+    assert_eq!(
+      engine.account(1),
+      Some(AccountReport::new(1, dec!(0), dec!(0), dec!(0), false).with_disputes(0, dec!(10)))
+    );
+  }
+
+  #[tokio::test]
+  async fn process_disputes_within_the_window() {
+    let mut engine = InMemoryPaymentsEngine::with_config(EngineConfig {
+      dispute_window_days: Some(30),
+      ..EngineConfig::default()
+    });
+    let deposit = |transaction_id, timestamp| Transaction::Deposit {
+      client_id: 1,
+      transaction_id,
+      amount: dec!(10),
+      timestamp,
+      sub_account: 0,
+    };
+    let dispute = |transaction_id, timestamp| Transaction::Dispute {
+      client_id: 1,
+      transaction_id,
+      timestamp,
+    };
+    let day = 24 * 60 * 60;
+
+    engine.process(deposit(101, Some(0))).await.unwrap();
+    engine.process(deposit(102, Some(0))).await.unwrap();
+    engine.process(deposit(103, None)).await.unwrap();
+
+    assert_eq!(engine.process(dispute(101, Some(30 * day))).await, Ok(()));
+    assert_eq!(
+      engine.process(dispute(102, Some(30 * day + 1))).await,
+      Err(PaymentsEngineError::DisputeWindowExpired(1, 102))
+    );
+    assert_eq!(engine.process(dispute(102, None)).await, Ok(()));
+    assert_eq!(engine.process(dispute(103, Some(365 * day))).await, Ok(()));
+    assert_eq!(
+      engine.account(1),
+      Some(AccountReport::new(1, dec!(0), dec!(30), dec!(30), false).with_disputes(3, dec!(0)))
+    );
+  }
+
+  #[tokio::test]
+  async fn process_withdrawals_within_the_limits() {
+    let mut engine = InMemoryPaymentsEngine::with_config(EngineConfig {
+      limits: Some(LimitsPolicy {
+        max_withdrawal: Some(dec!(50)),
+        max_daily_withdrawals: Some(dec!(80)),
+        max_transactions_per_minute: Some(3),
+      }),
+      ..EngineConfig::default()
+    });
+    let withdrawal = |transaction_id, amount, timestamp| Transaction::Withdrawal {
+      client_id: 1,
+      transaction_id,
+      amount,
+      timestamp,
+      sub_account: 0,
+    };
+    let day = 24 * 60 * 60;
+
+    engine
+      .process(Transaction::Deposit {
+        client_id: 1,
+        transaction_id: 101,
+        amount: dec!(1000),
+        timestamp: Some(0),
+        sub_account: 0,
+      })
+      .await
+      .unwrap();
+
+    assert_eq!(
+      engine.process(withdrawal(102, dec!(60), None)).await,
+      Err(PaymentsEngineError::WithdrawalLimitExceeded(1, 102))
+    );
+    assert_eq!(
+      engine.process(withdrawal(103, dec!(50), Some(10))).await,
+      Ok(())
+    );
+    assert_eq!(
+      engine.process(withdrawal(104, dec!(20), Some(20))).await,
+      Ok(())
+    );
+    assert_eq!(
+      engine.process(withdrawal(105, dec!(5), Some(30))).await,
+      Err(PaymentsEngineError::VelocityLimitExceeded(1))
+    );
+    assert_eq!(
+      engine.process(withdrawal(106, dec!(20), Some(120))).await,
+      Err(PaymentsEngineError::DailyWithdrawalLimitExceeded(1, 106))
+    );
+    assert_eq!(
+      engine.process(withdrawal(107, dec!(10), Some(120))).await,
+      Ok(())
+    );
+    // the withdrawals without a timestamp are out of the windows
+    assert_eq!(
+      engine.process(withdrawal(108, dec!(50), None)).await,
+      Ok(())
+    );
+    assert_eq!(
+      engine
+        .process(withdrawal(109, dec!(50), Some(day + 10)))
+        .await,
+      Ok(())
+    );
+    assert_eq!(
+      engine.account(1),
+      Some(AccountReport::new(1, dec!(820), dec!(0), dec!(820), false))
+    );
+  }
+
+  #[tokio::test]
+  async fn process_first_withdrawal_over_the_daily_limit() {
+    let mut engine = InMemoryPaymentsEngine::with_config(EngineConfig {
+      limits: Some(LimitsPolicy {
+        max_daily_withdrawals: Some(dec!(80)),
+        ..LimitsPolicy::default()
+      }),
+      ..EngineConfig::default()
+    });
+
+    // the deposit has no timestamp, so the client has no recent activity
+    engine
+      .process(Transaction::Deposit {
+        client_id: 1,
+        transaction_id: 101,
+        amount: dec!(100),
+        timestamp: None,
+        sub_account: 0,
+      })
+      .await
+      .unwrap();
+
+    assert_eq!(
+      engine
+        .process(Transaction::Withdrawal {
+          client_id: 1,
+          transaction_id: 102,
+          amount: dec!(90),
+          timestamp: Some(10),
+          sub_account: 0,
+        })
+        .await,
+      Err(PaymentsEngineError::DailyWithdrawalLimitExceeded(1, 102))
+    );
+  }
+
+  #[tokio::test]
+  async fn process_withdrawals_of_dormant_accounts() {
+    let mut engine = InMemoryPaymentsEngine::with_config(EngineConfig {
+      dormancy: Some(DormancyPolicy {
+        days: 30,
+        block_withdrawals: true,
+      }),
+      ..EngineConfig::default()
+    });
+    let deposit = |client_id, transaction_id, timestamp| Transaction::Deposit {
+      client_id,
+      transaction_id,
+      amount: dec!(100),
+      timestamp,
+      sub_account: 0,
+    };
+    let withdrawal = Transaction::Withdrawal {
+      client_id: 1,
+      transaction_id: 103,
+      amount: dec!(10),
+      timestamp: None,
+      sub_account: 0,
+    };
+
+    engine.process(deposit(1, 101, Some(0))).await.unwrap();
+    // the clock advances with the deposit of another client, so the first one becomes dormant
+    engine
+      .process(deposit(2, 102, Some(30 * SECONDS_PER_DAY)))
+      .await
+      .unwrap();
+    assert_eq!(engine.account(1).unwrap().dormant, Some(true));
+    assert_eq!(engine.account(2).unwrap().dormant, Some(false));
+
+    assert_eq!(
+      engine.validate(&withdrawal),
+      Err(PaymentsEngineError::AccountDormant(1))
+    );
+    assert_eq!(
+      engine
+        .process(Transaction::Transfer {
+          from_client: 1,
+          to_client: 2,
+          transaction_id: 104,
+          amount: dec!(10),
+          timestamp: None,
+        })
+        .await,
+      Err(PaymentsEngineError::AccountDormant(1))
+    );
+
+    // the deposits are accepted, but they don't reactivate the account
+    engine.process(deposit(1, 105, None)).await.unwrap();
+    assert_eq!(engine.account(1).unwrap().dormant, Some(true));
+
+    assert_eq!(
+      engine
+        .process(Transaction::Reactivate { client_id: 2 })
+        .await,
+      Err(PaymentsEngineError::AccountNotDormant(2))
+    );
+    engine
+      .process(Transaction::Reactivate { client_id: 1 })
+      .await
+      .unwrap();
+    assert_eq!(engine.account(1).unwrap().dormant, Some(false));
+    engine.process(withdrawal).await.unwrap();
+    assert_eq!(engine.account(1).unwrap().available, dec!(190),);
+  }
+
+  #[tokio::test]
+  async fn process_transfers_over_the_limits() {
+    let mut engine = InMemoryPaymentsEngine::with_config(EngineConfig {
+      limits: Some(LimitsPolicy {
+        max_withdrawal: Some(dec!(50)),
+        max_daily_withdrawals: Some(dec!(80)),
+        ..LimitsPolicy::default()
+      }),
+      ..EngineConfig::default()
+    });
+    let transfer = |transaction_id, amount, timestamp| Transaction::Transfer {
+      from_client: 1,
+      to_client: 2,
+      transaction_id,
+      amount,
+      timestamp,
+    };
+
+    engine
+      .process(Transaction::Deposit {
+        client_id: 1,
+        transaction_id: 101,
+        amount: dec!(1000),
+        timestamp: Some(0),
+        sub_account: 0,
+      })
+      .await
+      .unwrap();
+
+    assert_eq!(
+      engine.process(transfer(102, dec!(60), None)).await,
+      Err(PaymentsEngineError::WithdrawalLimitExceeded(1, 102))
+    );
+    assert_eq!(
+      engine.process(transfer(103, dec!(50), Some(10))).await,
+      Ok(())
+    );
+    // the transfer counts in the daily window of the sender, like a withdrawal
+    let withdrawal = Transaction::Withdrawal {
+      client_id: 1,
+      transaction_id: 104,
+      amount: dec!(40),
+      timestamp: Some(20),
+      sub_account: 0,
+    };
+    assert_eq!(
+      engine.validate(&withdrawal),
+      Err(PaymentsEngineError::DailyWithdrawalLimitExceeded(1, 104))
+    );
+    assert_eq!(
+      engine.process(withdrawal).await,
+      Err(PaymentsEngineError::DailyWithdrawalLimitExceeded(1, 104))
+    );
+    assert_eq!(
+      engine.process(transfer(105, dec!(40), Some(30))).await,
+      Err(PaymentsEngineError::DailyWithdrawalLimitExceeded(1, 105))
+    );
+    assert_eq!(
+      engine.account(1),
+      Some(AccountReport::new(1, dec!(950), dec!(0), dec!(950), false))
+    );
+    assert_eq!(
+      engine.account(2),
+      Some(AccountReport::new(2, dec!(50), dec!(0), dec!(50), false))
+    );
+  }
+
+  #[tokio::test]
+  async fn process_dispute_more_than_available() {
+    let mut engine = InMemoryPaymentsEngine::new();
+    engine.accounts.insert(
+      1,
+      Account {
+        locked: false,
+        funds: Funds::available(dec!(90)),
+        transactions: vec![(101, TransactionState::from_amount(dec!(100)))]
+          .into_iter()
+          .collect(),
+        open_disputes: 0,
+        sub_accounts: BTreeMap::new(),
+        fees: HashMap::new(),
+      },
+    );
+    let transaction = Transaction::Dispute {
+      client_id: 1,
+      transaction_id: 101,
+      timestamp: None,
+    };
+
+    let result = engine.process(transaction).await;
+
+    assert!(result.is_err());
+    assert_eq!(result, Err(PaymentsEngineError::DisputedMoreThanAvailable),);
+  }
+
+  #[tokio::test]
+  async fn process_dispute_too_many_open_disputes() {
+    let mut engine = InMemoryPaymentsEngine::with_config(EngineConfig {
+      max_open_disputes: Some(1),
+      ..EngineConfig::default()
+    });
+    engine.accounts.insert(
+      1,
+      Account {
+        locked: false,
+        funds: Funds::new(dec!(100), dec!(10)),
+        transactions: vec![
+          (101, TransactionState::from_dispute(dec!(10))),
+          (102, TransactionState::from_amount(dec!(20))),
+        ]
+        .into_iter()
+        .collect(),
+        open_disputes: 1,
+        sub_accounts: BTreeMap::new(),
+        fees: HashMap::new(),
+      },
+    );
+    let transaction = Transaction::Dispute {
+      client_id: 1,
+      transaction_id: 102,
+      timestamp: None,
+    };
+
+    let result = engine.process(transaction).await;
+
+    assert_eq!(result, Err(PaymentsEngineError::TooManyOpenDisputes(1)));
+  }
+
+  #[tokio::test]
+  async fn process_dispute_below_max_open_disputes() {
+    let mut engine = InMemoryPaymentsEngine::with_config(EngineConfig {
+      max_open_disputes: Some(2),
+      ..EngineConfig::default()
+    });
+    engine.accounts.insert(
+      1,
+      Account {
+        locked: false,
+        funds: Funds::new(dec!(100), dec!(10)),
+        transactions: vec![
+          (101, TransactionState::from_dispute(dec!(10))),
+          (102, TransactionState::from_amount(dec!(20))),
+        ]
+        .into_iter()
+        .collect(),
+        open_disputes: 1,
+        sub_accounts: BTreeMap::new(),
+        fees: HashMap::new(),
+      },
+    );
+    let transaction = Transaction::Dispute {
+      client_id: 1,
+      transaction_id: 102,
+      timestamp: None,
+    };
+
+    let result = engine.process(transaction).await;
+
+    assert!(result.is_ok());
+    assert_eq!(engine.accounts.get(&1).unwrap().open_disputes, 2);
+  }
+
+  #[tokio::test]
+  async fn process_dispute_successfully() {
+    let mut engine = InMemoryPaymentsEngine::new();
+    engine.accounts.insert(
+      1,
+      Account {
+        locked: false,
+        funds: Funds::available(dec!(110)),
+        transactions: vec![(101, TransactionState::from_amount(dec!(10)))]
+          .into_iter()
+          .collect(),
+        open_disputes: 0,
+        sub_accounts: BTreeMap::new(),
+        fees: HashMap::new(),
+      },
+    );
+    let transaction = Transaction::Dispute {
+      client_id: 1,
+      transaction_id: 101,
+      timestamp: None,
+    };
+
+    let result = engine.process(transaction).await;
+
+    assert!(result.is_ok());
+    assert_eq!(
+      engine.accounts.get(&1).unwrap(),
+      &Account {
+        locked: false,
+        funds: Funds::new(dec!(100), dec!(10)),
+        transactions: vec![(101, TransactionState::from_dispute(dec!(10)))]
+          .into_iter()
+          .collect(),
+        open_disputes: 1,
+        sub_accounts: BTreeMap::new(),
+        fees: HashMap::new(),
+      }
+    );
+  }
+
+  #[tokio::test]
+  async fn process_dispute_not_disputable() {
+    let mut engine = InMemoryPaymentsEngine::new();
+    engine.accounts.insert(
+      1,
+      Account {
+        locked: false,
+        funds: Funds::available(dec!(90)),
+        transactions: vec![(101, TransactionState::from_transfer(dec!(10)))]
+          .into_iter()
+          .collect(),
+        open_disputes: 0,
+        sub_accounts: BTreeMap::new(),
+        fees: HashMap::new(),
+      },
+    );
+    let transaction = Transaction::Dispute {
+      client_id: 1,
+      transaction_id: 101,
+      timestamp: None,
+    };
+
+    let result = engine.process(transaction).await;
+
+    assert_eq!(
+      result,
+      Err(PaymentsEngineError::TransactionNotDisputable(1, 101))
+    );
+  }
+
+  #[tokio::test]
+  async fn process_dispute_withdrawal() {
+    let mut engine = InMemoryPaymentsEngine::new();
+    engine.accounts.insert(
+      1,
+      Account {
+        locked: false,
+        funds: Funds::available(dec!(5)),
+        transactions: vec![(101, TransactionState::from_withdrawal(dec!(10)))]
+          .into_iter()
+          .collect(),
+        open_disputes: 0,
+        sub_accounts: BTreeMap::new(),
+        fees: HashMap::new(),
+      },
+    );
+    let transaction = Transaction::Dispute {
+      client_id: 1,
+      transaction_id: 101,
+      timestamp: None,
+    };
+
+    let result = engine.process(transaction).await;
+
+    assert_eq!(result, Ok(()));
+    assert_eq!(
+      engine.accounts.get(&1).unwrap(),
+      &Account {
+        locked: false,
+        funds: Funds::new(dec!(5), dec!(10)),
+        transactions: vec![(
+          101,
+          TransactionState {
+            state: DisputeState::Disputed,
+            ..TransactionState::from_withdrawal(dec!(10))
+          }
+        )]
+        .into_iter()
+        .collect(),
+        open_disputes: 1,
+        sub_accounts: BTreeMap::new(),
+        fees: HashMap::new(),
+      }
+    );
+  }
+
+  #[tokio::test]
+  async fn process_resolve_withdrawal() {
+    let mut engine = InMemoryPaymentsEngine::new();
+    engine.accounts.insert(
+      1,
+      Account {
+        locked: false,
+        funds: Funds::new(dec!(5), dec!(10)),
+        transactions: vec![(
+          101,
+          TransactionState {
+            state: DisputeState::Disputed,
+            ..TransactionState::from_withdrawal(dec!(10))
+          },
+        )]
+        .into_iter()
+        .collect(),
+        open_disputes: 1,
+        sub_accounts: BTreeMap::new(),
+        fees: HashMap::new(),
+      },
+    );
+    let transaction = Transaction::Resolve {
+      client_id: 1,
+      transaction_id: 101,
+      timestamp: None,
+    };
+
+    let result = engine.process(transaction).await;
+
+    assert_eq!(result, Ok(()));
+    assert_eq!(
+      engine.accounts.get(&1).unwrap().funds,
+      Funds::available(dec!(5))
+    );
+  }
 
   #[tokio::test]
-  async fn process_deposit_negative_amount() {
+  async fn process_chargeback_withdrawal() {
     let mut engine = InMemoryPaymentsEngine::new();
-    let transaction = Transaction::Deposit {
+    engine.accounts.insert(
+      1,
+      Account {
+        locked: false,
+        funds: Funds::new(dec!(5), dec!(10)),
+        transactions: vec![(
+          101,
+          TransactionState {
+            state: DisputeState::Disputed,
+            ..TransactionState::from_withdrawal(dec!(10))
+          },
+        )]
+        .into_iter()
+        .collect(),
+        open_disputes: 1,
+        sub_accounts: BTreeMap::new(),
+        fees: HashMap::new(),
+      },
+    );
+    let transaction = Transaction::Chargeback {
       client_id: 1,
       transaction_id: 101,
-      amount: dec!(-10),
+      timestamp: None,
     };
 
     let result = engine.process(transaction).await;
 
-    assert_eq!(result, Err(PaymentsEngineError::NegativeAmount));
+    assert_eq!(result, Ok(()));
+    let account = engine.accounts.get(&1).unwrap();
+    assert!(account.locked);
+    assert_eq!(account.funds, Funds::available(dec!(15)));
   }
 
   #[tokio::test]
-  async fn process_deposit_account_locked() {
+  async fn process_resolve_non_existing_client() {
+    let mut engine = InMemoryPaymentsEngine::new();
+    let transaction = Transaction::Resolve {
+      client_id: 1,
+      transaction_id: 101,
+      timestamp: None,
+    };
+
+    let result = engine.process(transaction).await;
+
+    assert_eq!(result, Err(PaymentsEngineError::ClientNotFound(1)));
+  }
+
+  #[tokio::test]
+  async fn process_resolve_non_existing_transaction() {
     let mut engine = InMemoryPaymentsEngine::new();
     engine.accounts.insert(
       1,
       Account {
-        locked: true,
-        ..Account::default()
+        locked: false,
+        funds: Funds::available(dec!(100)),
+        transactions: HashMap::default(),
+        open_disputes: 0,
+        sub_accounts: BTreeMap::new(),
+        fees: HashMap::new(),
       },
     );
-    let transaction = Transaction::Deposit {
+    let transaction = Transaction::Resolve {
       client_id: 1,
       transaction_id: 101,
-      amount: dec!(20),
+      timestamp: None,
     };
 
     let result = engine.process(transaction).await;
 
-    assert_eq!(result, Err(PaymentsEngineError::AccountLocked(1)));
+    assert_eq!(result, Err(PaymentsEngineError::TransactionNotFound(101)));
   }
 
   #[tokio::test]
-  async fn process_deposit_transaction_exists() {
+  async fn process_resolve_not_disputed() {
     let mut engine = InMemoryPaymentsEngine::new();
     engine.accounts.insert(
       1,
       Account {
         locked: false,
-        funds: Funds::available(dec!(10)),
+        funds: Funds::available(dec!(100)),
         transactions: vec![(101, TransactionState::from_amount(dec!(10)))]
           .into_iter()
           .collect(),
+        open_disputes: 0,
+        sub_accounts: BTreeMap::new(),
+        fees: HashMap::new(),
       },
     );
-    let transaction = Transaction::Deposit {
+    let transaction = Transaction::Resolve {
       client_id: 1,
       transaction_id: 101,
-      amount: dec!(20),
+      timestamp: None,
     };
 
     let result = engine.process(transaction).await;
 
-    assert_eq!(result, Err(PaymentsEngineError::DuplicatedTransaction(101)));
+    assert_eq!(
+      result,
+      Err(PaymentsEngineError::TransactionNotDisputed(1, 101))
+    );
   }
 
   #[tokio::test]
-  async fn process_deposit_successfully() {
+  async fn process_resolve_successfully() {
     let mut engine = InMemoryPaymentsEngine::new();
-    engine.accounts.insert(1, Account::default());
-    let transaction = Transaction::Deposit {
+    engine.accounts.insert(
+      1,
+      Account {
+        locked: false,
+        funds: Funds::new(dec!(100), dec!(10)),
+        transactions: vec![(101, TransactionState::from_dispute(dec!(10)))]
+          .into_iter()
+          .collect(),
+        open_disputes: 1,
+        sub_accounts: BTreeMap::new(),
+        fees: HashMap::new(),
+      },
+    );
+    let transaction = Transaction::Resolve {
       client_id: 1,
       transaction_id: 101,
-      amount: dec!(10),
+      timestamp: None,
     };
 
     let result = engine.process(transaction).await;
 
-    assert_eq!(result, Ok(()));
-    assert_eq!(engine.accounts.len(), 1);
+    assert!(result.is_ok());
     assert_eq!(
       engine.accounts.get(&1).unwrap(),
       &Account {
         locked: false,
-        funds: Funds::available(dec!(10)),
-        transactions: vec![(101, TransactionState::from_amount(dec!(10)))]
-          .into_iter()
-          .collect(),
+        funds: Funds::available(dec!(110)),
+        transactions: vec![(
+          101,
+          TransactionState {
+            state: DisputeState::Resolved,
+            ..TransactionState::from_amount(dec!(10))
+          }
+        )]
+        .into_iter()
+        .collect(),
+        open_disputes: 0,
+        sub_accounts: BTreeMap::new(),
+        fees: HashMap::new(),
       }
     );
   }
 
   #[tokio::test]
-  async fn process_withdrawal_negative_amount() {
+  async fn process_chargeback_non_existing_client() {
     let mut engine = InMemoryPaymentsEngine::new();
-    let transaction = Transaction::Withdrawal {
+    let transaction = Transaction::Chargeback {
       client_id: 1,
       transaction_id: 101,
-      amount: dec!(-10),
+      timestamp: None,
     };
 
     let result = engine.process(transaction).await;
 
-    assert_eq!(result, Err(PaymentsEngineError::NegativeAmount));
+    assert_eq!(result, Err(PaymentsEngineError::ClientNotFound(1)));
   }
 
   #[tokio::test]
-  async fn process_withdrawal_account_locked() {
+  async fn process_chargeback_non_existing_transaction() {
+    let mut engine = InMemoryPaymentsEngine::new();
+    engine.accounts.insert(
+      1,
+      Account {
+        locked: false,
+        funds: Funds::available(dec!(100)),
+        transactions: HashMap::default(),
+        open_disputes: 0,
+        sub_accounts: BTreeMap::new(),
+        fees: HashMap::new(),
+      },
+    );
+    let transaction = Transaction::Chargeback {
+      client_id: 1,
+      transaction_id: 101,
+      timestamp: None,
+    };
+
+    let result = engine.process(transaction).await;
+
+    assert_eq!(result, Err(PaymentsEngineError::TransactionNotFound(101)));
+  }
+
+  #[tokio::test]
+  async fn process_chargeback_not_disputed() {
+    let mut engine = InMemoryPaymentsEngine::new();
+    engine.accounts.insert(
+      1,
+      Account {
+        locked: false,
+        funds: Funds::available(dec!(100)),
+        transactions: vec![(101, TransactionState::from_amount(dec!(10)))]
+          .into_iter()
+          .collect(),
+        open_disputes: 0,
+        sub_accounts: BTreeMap::new(),
+        fees: HashMap::new(),
+      },
+    );
+    let transaction = Transaction::Chargeback {
+      client_id: 1,
+      transaction_id: 101,
+      timestamp: None,
+    };
+
+    let result = engine.process(transaction).await;
+
+    assert_eq!(
+      result,
+      Err(PaymentsEngineError::TransactionNotDisputed(1, 101))
+    );
+  }
+
+  #[tokio::test]
+  async fn process_chargeback_successfully() {
     let mut engine = InMemoryPaymentsEngine::new();
     engine.accounts.insert(
       1,
       Account {
+        locked: false,
+        funds: Funds::new(dec!(100), dec!(10)),
+        transactions: vec![(101, TransactionState::from_dispute(dec!(10)))]
+          .into_iter()
+          .collect(),
+        open_disputes: 1,
+        sub_accounts: BTreeMap::new(),
+        fees: HashMap::new(),
+      },
+    );
+    let transaction = Transaction::Chargeback {
+      client_id: 1,
+      transaction_id: 101,
+      timestamp: None,
+    };
+
+    let result = engine.process(transaction).await;
+
+    assert!(result.is_ok());
+    assert_eq!(
+      engine.accounts.get(&1).unwrap(),
+      &Account {
         locked: true,
-        ..Account::default()
+        funds: Funds::available(dec!(100)),
+        transactions: vec![(101, TransactionState::from_chargeback(dec!(10)))]
+          .into_iter()
+          .collect(),
+        open_disputes: 0,
+        sub_accounts: BTreeMap::new(),
+        fees: HashMap::new(),
+      }
+    );
+  }
+
+  #[tokio::test]
+  async fn process_chargeback_with_fee() {
+    let cases = vec![
+      (dec!(100), false, dec!(85), dec!(15)),
+      (dec!(5), false, dec!(0), dec!(5)),
+      (dec!(5), true, dec!(-10), dec!(15)),
+      (dec!(0), false, dec!(0), dec!(0)),
+    ];
+
+    for (available, allow_negative_available, expected_available, expected_fee) in cases {
+      let mut engine = InMemoryPaymentsEngine::with_config(EngineConfig {
+        chargeback_fee: Some(ChargebackFee {
+          amount: dec!(15),
+          allow_negative_available,
+        }),
+        ..EngineConfig::default()
+      });
+      engine.accounts.insert(
+        1,
+        Account {
+          locked: false,
+          funds: Funds::new(available, dec!(10)),
+          transactions: vec![(101, TransactionState::from_dispute(dec!(10)))]
+            .into_iter()
+            .collect(),
+          open_disputes: 1,
+          sub_accounts: BTreeMap::new(),
+          fees: HashMap::new(),
+        },
+      );
+      let transaction = Transaction::Chargeback {
+        client_id: 1,
+        transaction_id: 101,
+        timestamp: None,
+      };
+
+      let result = engine.process(transaction).await;
+
+      // the fee is recorded as a synthetic transaction by the ID of the transaction charged back
+      let mut expected_fees = HashMap::new();
+      if !expected_fee.is_zero() {
+        expected_fees.insert(101, TransactionState::from_fee(expected_fee));
+      }
+      assert!(result.is_ok());
+      assert_eq!(
+        engine.accounts.get(&1).unwrap(),
+        &Account {
+          locked: true,
+          funds: Funds::available(expected_available),
+          transactions: vec![(101, TransactionState::from_chargeback(dec!(10)))]
+            .into_iter()
+            .collect(),
+          open_disputes: 0,
+          sub_accounts: BTreeMap::new(),
+          fees: expected_fees,
+        }
+      );
+    }
+  }
+
+  #[tokio::test]
+  async fn report_the_chargeback_fee_as_a_transaction() {
+    let mut engine = InMemoryPaymentsEngine::with_config(EngineConfig {
+      chargeback_fee: Some(ChargebackFee {
+        amount: dec!(15),
+        allow_negative_available: false,
+      }),
+      ..EngineConfig::default()
+    });
+    let transactions = vec![
+      Transaction::Deposit {
+        client_id: 1,
+        transaction_id: 101,
+        amount: dec!(100),
+        timestamp: None,
+        sub_account: 0,
+      },
+      Transaction::Deposit {
+        client_id: 1,
+        transaction_id: 102,
+        amount: dec!(50),
+        timestamp: None,
+        sub_account: 0,
+      },
+      Transaction::Dispute {
+        client_id: 1,
+        transaction_id: 101,
+        timestamp: None,
+      },
+      Transaction::Chargeback {
+        client_id: 1,
+        transaction_id: 101,
+        timestamp: None,
+      },
+    ];
+    for transaction in transactions {
+      engine.process(transaction).await.unwrap();
+    }
+
+    let fee = TransactionInfo {
+      client_id: 1,
+      transaction_id: 101,
+      kind: TransactionKind::Fee,
+      amount: dec!(15),
+      state: DisputeState::Recorded,
+      disputed_at: None,
+    };
+    assert_eq!(
+      engine
+        .transactions_report()
+        .unwrap()
+        .filter(|transaction| transaction.kind == TransactionKind::Fee)
+        .collect::<Vec<_>>(),
+      vec![fee]
+    );
+    // the fee doesn't take any ID that the client can send
+    assert_eq!(
+      engine.transaction(1, 101).unwrap().map(|info| info.kind),
+      Some(TransactionKind::Deposit)
+    );
+    assert_eq!(engine.transaction(1, TransactionId::MAX).unwrap(), None);
+    assert_eq!(
+      engine.account(1),
+      Some(
+        AccountReport::new(1, dec!(35), dec!(0), dec!(35), true)
+          .with_disputes(0, dec!(100))
+          .with_fees(dec!(15))
+      )
+    );
+    // a later transaction of the client can take any ID
+    let deposit = Transaction::Deposit {
+      client_id: 1,
+      transaction_id: TransactionId::MAX,
+      amount: dec!(10),
+      timestamp: None,
+      sub_account: 0,
+    };
+    engine
+      .process(Transaction::Unlock { client_id: 1 })
+      .await
+      .unwrap();
+    assert_eq!(engine.process(deposit).await, Ok(()));
+  }
+
+  #[tokio::test]
+  async fn dispute_the_chargeback_fee_depending_on_the_policy() {
+    for synthetic_dispute_policy in vec![
+      SyntheticDisputePolicy::Reject,
+      SyntheticDisputePolicy::Allow,
+    ] {
+      let mut engine = InMemoryPaymentsEngine::with_config(EngineConfig {
+        chargeback_fee: Some(ChargebackFee {
+          amount: dec!(15),
+          allow_negative_available: false,
+        }),
+        locked_account_dispute_policy: LockedAccountDisputePolicy::Allow,
+        synthetic_dispute_policy,
+        ..EngineConfig::default()
+      });
+      let transactions = vec![
+        Transaction::Deposit {
+          client_id: 1,
+          transaction_id: 101,
+          amount: dec!(100),
+          timestamp: None,
+          sub_account: 0,
+        },
+        Transaction::Deposit {
+          client_id: 1,
+          transaction_id: 102,
+          amount: dec!(50),
+          timestamp: None,
+          sub_account: 0,
+        },
+        Transaction::Dispute {
+          client_id: 1,
+          transaction_id: 101,
+          timestamp: None,
+        },
+        Transaction::Chargeback {
+          client_id: 1,
+          transaction_id: 101,
+          timestamp: None,
+        },
+      ];
+      for transaction in transactions {
+        engine.process(transaction).await.unwrap();
+      }
+
+      // the fee is disputed by the ID of the transaction charged back
+      let result = engine
+        .process(Transaction::Dispute {
+          client_id: 1,
+          transaction_id: 101,
+          timestamp: None,
+        })
+        .await;
+
+      match synthetic_dispute_policy {
+        SyntheticDisputePolicy::Reject => {
+          assert_eq!(
+            result,
+            Err(PaymentsEngineError::TransactionChargedBack(1, 101))
+          );
+        }
+        SyntheticDisputePolicy::Allow => {
+          assert!(result.is_ok());
+          // the fee is held as a disputed withdrawal, and refunded when it is charged back
+          assert_eq!(
+            engine.account(1),
+            Some(
+              AccountReport::new(1, dec!(35), dec!(15), dec!(50), true)
+                .with_disputes(1, dec!(100))
+                .with_fees(dec!(15))
+            )
+          );
+          let result = engine
+            .process(Transaction::Chargeback {
+              client_id: 1,
+              transaction_id: 101,
+              timestamp: None,
+            })
+            .await;
+          assert!(result.is_ok());
+          assert_eq!(
+            engine
+              .transactions_report()
+              .unwrap()
+              .filter(|transaction| transaction.kind == TransactionKind::Fee)
+              .map(|fee| fee.state)
+              .collect::<Vec<_>>(),
+            vec![DisputeState::ChargedBack]
+          );
+          // charging back the fee doesn't charge another one
+          assert_eq!(
+            engine.account(1),
+            Some(
+              AccountReport::new(1, dec!(50), dec!(0), dec!(50), true)
+                .with_disputes(0, dec!(115))
+                .with_fees(dec!(0))
+            )
+          );
+          assert_eq!(
+            engine
+              .process(Transaction::Dispute {
+                client_id: 1,
+                transaction_id: 101,
+                timestamp: None,
+              })
+              .await,
+            Err(PaymentsEngineError::TransactionChargedBack(1, 101))
+          );
+        }
+      }
+    }
+  }
+
+  #[tokio::test]
+  async fn process_disputes_with_escrow_interest() {
+    let mut engine = InMemoryPaymentsEngine::with_config(EngineConfig {
+      escrow_interest_rate: Some(dec!(0.01)),
+      ..EngineConfig::default()
+    });
+    let day = |days: u64| Some(1_600_000_000 + days * SECONDS_PER_DAY);
+    let transactions = vec![
+      Transaction::Deposit {
+        client_id: 1,
+        transaction_id: 101,
+        amount: dec!(100),
+        timestamp: day(0),
+        sub_account: 0,
+      },
+      Transaction::Dispute {
+        client_id: 1,
+        transaction_id: 101,
+        timestamp: day(0),
+      },
+      Transaction::Deposit {
+        client_id: 1,
+        transaction_id: 102,
+        amount: dec!(10),
+        timestamp: day(2),
+        sub_account: 0,
+      },
+      // the transactions without a timestamp happen at the latest one
+      Transaction::Deposit {
+        client_id: 1,
+        transaction_id: 103,
+        amount: dec!(0.5),
+        timestamp: None,
+        sub_account: 0,
+      },
+    ];
+    for transaction in transactions {
+      engine.process(transaction).await.unwrap();
+    }
+
+    let report: Vec<AccountReport> = engine.accounts_report().collect();
+    assert_eq!(
+      report,
+      vec![
+        AccountReport::new(1, dec!(10.5), dec!(100), dec!(110.5), false)
+          .with_disputes(1, dec!(0))
+          .with_escrow_interest(dec!(2))
+      ]
+    );
+
+    // the client wins the dispute of the deposit, so the interest is credited
+    let transactions = vec![
+      Transaction::Resolve {
+        client_id: 1,
+        transaction_id: 101,
+        timestamp: day(3),
+      },
+      Transaction::Dispute {
+        client_id: 1,
+        transaction_id: 102,
+        timestamp: day(3),
+      },
+      Transaction::Chargeback {
+        client_id: 1,
+        transaction_id: 102,
+        timestamp: day(5),
+      },
+    ];
+    for transaction in transactions {
+      engine.process(transaction).await.unwrap();
+    }
+
+    let report: Vec<AccountReport> = engine.accounts_report().collect();
+    assert_eq!(
+      report,
+      vec![
+        AccountReport::new(1, dec!(103.5), dec!(0), dec!(103.5), true)
+          .with_disputes(0, dec!(10))
+          .with_escrow_interest(dec!(0))
+      ]
+    );
+  }
+
+  #[tokio::test]
+  async fn process_disputes_with_exposure_threshold() {
+    let mut engine = InMemoryPaymentsEngine::with_config(EngineConfig {
+      exposure_threshold: Some(dec!(100)),
+      ..EngineConfig::default()
+    });
+    let transactions = vec![
+      Transaction::Deposit {
+        client_id: 1,
+        transaction_id: 101,
+        amount: dec!(100),
+        timestamp: None,
+        sub_account: 0,
+      },
+      Transaction::Deposit {
+        client_id: 1,
+        transaction_id: 102,
+        amount: dec!(40),
+        timestamp: None,
+        sub_account: 0,
+      },
+      Transaction::Withdrawal {
+        client_id: 1,
+        transaction_id: 103,
+        amount: dec!(30),
+        timestamp: None,
+        sub_account: 0,
       },
+      Transaction::Dispute {
+        client_id: 1,
+        transaction_id: 103,
+        timestamp: None,
+      },
+    ];
+    for transaction in transactions {
+      engine.process(transaction).await.unwrap();
+    }
+
+    // the disputed withdrawal is held, and its amount is open in dispute
+    assert_eq!(
+      engine.account(1).unwrap().exposure,
+      Some(Exposure {
+        amount: dec!(60),
+        threshold: dec!(100)
+      })
     );
-    let transaction = Transaction::Withdrawal {
-      client_id: 1,
-      transaction_id: 101,
-      amount: dec!(20),
-    };
+    assert_eq!(engine.account(1).unwrap().exposure_alert, Some(false));
 
-    let result = engine.process(transaction).await;
+    // the disputed withdrawal is still held, so the exposure of both disputes exceeds the threshold
+    engine
+      .process(Transaction::Dispute {
+        client_id: 1,
+        transaction_id: 102,
+        timestamp: None,
+      })
+      .await
+      .unwrap();
 
-    assert_eq!(result, Err(PaymentsEngineError::AccountLocked(1)));
+    assert_eq!(
+      engine.account(1),
+      Some(
+        AccountReport::new(1, dec!(70), dec!(70), dec!(140), false)
+          .with_disputes(2, dec!(0))
+          .with_exposure(Exposure {
+            amount: dec!(140),
+            threshold: dec!(100)
+          })
+      )
+    );
   }
 
   #[tokio::test]
-  async fn process_withdrawal_transaction_exists() {
+  async fn process_chargeback_twice() {
     let mut engine = InMemoryPaymentsEngine::new();
     engine.accounts.insert(
       1,
       Account {
-        locked: false,
-        funds: Funds::available(dec!(10)),
-        transactions: vec![(101, TransactionState::from_amount(dec!(10)))]
+        locked: true,
+        funds: Funds::available(dec!(100)),
+        transactions: vec![(101, TransactionState::from_chargeback(dec!(10)))]
           .into_iter()
           .collect(),
+        open_disputes: 0,
+        sub_accounts: BTreeMap::new(),
+        fees: HashMap::new(),
       },
     );
-    let transaction = Transaction::Withdrawal {
+    let transaction = Transaction::Chargeback {
       client_id: 1,
       transaction_id: 101,
-      amount: dec!(5),
+      timestamp: None,
     };
 
     let result = engine.process(transaction).await;
 
-    assert_eq!(result, Err(PaymentsEngineError::DuplicatedTransaction(101)));
+    assert_eq!(
+      result,
+      Err(PaymentsEngineError::TransactionNotDisputed(1, 101))
+    );
+    assert_eq!(
+      engine.accounts.get(&1).unwrap().funds,
+      Funds::available(dec!(100))
+    );
   }
 
   #[tokio::test]
-  async fn process_withdrawal_not_enough_available_funds() {
-    let mut engine = InMemoryPaymentsEngine::new();
+  async fn process_retried_resolve_and_chargeback_idempotently() {
+    let mut engine = InMemoryPaymentsEngine::with_config(EngineConfig {
+      duplicate_policy: DuplicatePolicy::Idempotent,
+      ..EngineConfig::default()
+    });
     engine.accounts.insert(
       1,
       Account {
         locked: false,
-        funds: Funds::available(dec!(10)),
-        transactions: HashMap::default(),
+        funds: Funds::new(dec!(100), dec!(30)),
+        transactions: vec![
+          (101, TransactionState::from_amount(dec!(10))),
+          (102, TransactionState::from_dispute(dec!(20))),
+          (103, TransactionState::from_dispute(dec!(10))),
+        ]
+        .into_iter()
+        .collect(),
+        open_disputes: 2,
+        sub_accounts: BTreeMap::new(),
+        fees: HashMap::new(),
       },
     );
-    let transaction1 = Transaction::Withdrawal {
+    let resolve = Transaction::Resolve {
       client_id: 1,
-      transaction_id: 101,
-      amount: dec!(10),
+      transaction_id: 102,
+      timestamp: None,
     };
-    let transaction2 = Transaction::Withdrawal {
+    let chargeback = Transaction::Chargeback {
       client_id: 1,
-      transaction_id: 102,
-      amount: dec!(0.2),
+      transaction_id: 103,
+      timestamp: None,
     };
 
-    let result = engine.process(transaction1).await;
-    assert!(result.is_ok());
+    assert_eq!(engine.process(resolve.clone()).await, Ok(()));
+    assert_eq!(engine.process(chargeback.clone()).await, Ok(()));
+    let funds = engine.accounts.get(&1).unwrap().funds.clone();
+    assert_eq!(funds, Funds::available(dec!(120)));
 
-    let result = engine.process(transaction2).await;
+    assert_eq!(engine.validate(&resolve), Ok(()));
+    assert_eq!(engine.process(resolve).await, Ok(()));
+    assert_eq!(engine.validate(&chargeback), Ok(()));
+    assert_eq!(engine.process(chargeback).await, Ok(()));
+    assert_eq!(engine.accounts.get(&1).unwrap().funds, funds);
 
-    assert_eq!(result, Err(PaymentsEngineError::NotEnoughAvailableFunds));
+    let result = engine
+      .process(Transaction::Resolve {
+        client_id: 1,
+        transaction_id: 101,
+        timestamp: None,
+      })
+      .await;
+    assert_eq!(
+      result,
+      Err(PaymentsEngineError::TransactionNotDisputed(1, 101))
+    );
   }
 
   #[tokio::test]
-  async fn process_withdrawal_non_existing_client() {
-    let mut engine = InMemoryPaymentsEngine::new();
-    let transaction1 = Transaction::Withdrawal {
-      client_id: 1,
-      transaction_id: 101,
-      amount: dec!(10),
-    };
+  async fn process_unlock() {
+    let cases = vec![
+      (
+        UnlockHeldFundsPolicy::Keep,
+        Funds::new(dec!(50), dec!(20)),
+        DisputeState::Disputed,
+      ),
+      (
+        UnlockHeldFundsPolicy::Release,
+        Funds::available(dec!(70)),
+        DisputeState::Resolved,
+      ),
+    ];
 
-    let result = engine.process(transaction1).await;
+    for (unlock_held_funds_policy, expected_funds, expected_state) in cases {
+      let mut engine = InMemoryPaymentsEngine::with_config(EngineConfig {
+        unlock_held_funds_policy,
+        ..EngineConfig::default()
+      });
+      engine.accounts.insert(
+        1,
+        Account {
+          locked: true,
+          funds: Funds::new(dec!(50), dec!(20)),
+          transactions: vec![
+            (101, TransactionState::from_chargeback(dec!(10))),
+            (102, TransactionState::from_dispute(dec!(20))),
+          ]
+          .into_iter()
+          .collect(),
+          open_disputes: 1,
+          sub_accounts: BTreeMap::new(),
+          fees: HashMap::new(),
+        },
+      );
 
-    assert_eq!(result, Err(PaymentsEngineError::ClientNotFound(1)));
+      let result = engine.process(Transaction::Unlock { client_id: 1 }).await;
+
+      assert!(result.is_ok());
+      let account = engine.accounts.get(&1).unwrap();
+      assert!(!account.locked);
+      assert_eq!(account.funds, expected_funds);
+      assert_eq!(
+        account.transactions.get(&102).unwrap().state,
+        expected_state
+      );
+      assert!(account.transactions.get(&101).unwrap().charged_back());
+    }
   }
 
   #[tokio::test]
-  async fn process_withdrawal_successfully() {
+  async fn process_unlock_not_locked() {
     let mut engine = InMemoryPaymentsEngine::new();
-    engine.accounts.insert(
-      1,
-      Account {
-        locked: false,
-        funds: Funds::available(dec!(100)),
-        transactions: HashMap::default(),
-      },
-    );
-    let transaction = Transaction::Withdrawal {
-      client_id: 1,
-      transaction_id: 101,
-      amount: dec!(10),
-    };
-
-    let result = engine.process(transaction).await;
+    engine.accounts.insert(1, Account::default());
 
-    assert!(result.is_ok());
-    assert_eq!(engine.accounts.len(), 1);
     assert_eq!(
-      engine.accounts.get(&1).unwrap(),
-      &Account {
-        locked: false,
-        funds: Funds::available(dec!(90)),
-        transactions: HashMap::new(),
-      }
+      engine.process(Transaction::Unlock { client_id: 1 }).await,
+      Err(PaymentsEngineError::AccountNotLocked(1))
+    );
+    assert_eq!(
+      engine.process(Transaction::Unlock { client_id: 2 }).await,
+      Err(PaymentsEngineError::ClientNotFound(2))
     );
   }
 
   #[tokio::test]
-  async fn process_dispute_non_existing_client() {
+  async fn process_authorize_and_capture() {
     let mut engine = InMemoryPaymentsEngine::new();
-    let transaction = Transaction::Dispute {
+    let authorize = Transaction::Authorize {
+      client_id: 1,
+      transaction_id: 101,
+      amount: dec!(10),
+      timestamp: None,
+      sub_account: 0,
+    };
+    let capture = Transaction::Capture {
+      client_id: 1,
+      transaction_id: 101,
+      timestamp: None,
+    };
+    let dispute = Transaction::Dispute {
       client_id: 1,
       transaction_id: 101,
+      timestamp: None,
     };
 
-    let result = engine.process(transaction).await;
+    assert_eq!(engine.process(authorize.clone()).await, Ok(()));
+    assert_eq!(
+      engine.account(1),
+      Some(AccountReport::new(1, dec!(0), dec!(10), dec!(10), false))
+    );
+    assert_eq!(
+      engine.process(authorize).await,
+      Err(PaymentsEngineError::DuplicatedTransaction(101))
+    );
+    assert_eq!(
+      engine.process(dispute.clone()).await,
+      Err(PaymentsEngineError::TransactionNotDisputable(1, 101))
+    );
 
-    assert_eq!(result, Err(PaymentsEngineError::ClientNotFound(1)));
+    assert_eq!(engine.process(capture.clone()).await, Ok(()));
+    assert_eq!(
+      engine.account(1),
+      Some(AccountReport::new(1, dec!(10), dec!(0), dec!(10), false))
+    );
+    assert_eq!(
+      engine.transaction(1, 101).unwrap().map(|info| info.kind),
+      Some(TransactionKind::Deposit)
+    );
+    assert_eq!(
+      engine.process(capture).await,
+      Err(PaymentsEngineError::NotAnAuthorization(1, 101))
+    );
+    assert_eq!(engine.process(dispute).await, Ok(()));
   }
 
   #[tokio::test]
-  async fn process_dispute_account_locked() {
+  async fn process_void() {
     let mut engine = InMemoryPaymentsEngine::new();
-    engine.accounts.insert(
-      1,
-      Account {
-        locked: true,
-        ..Account::default()
-      },
-    );
-    let transaction = Transaction::Dispute {
+    let void = Transaction::Void {
       client_id: 1,
       transaction_id: 101,
+      timestamp: None,
+    };
+    let authorize = Transaction::Authorize {
+      client_id: 1,
+      transaction_id: 101,
+      amount: dec!(10),
+      timestamp: None,
+      sub_account: 0,
     };
 
-    let result = engine.process(transaction).await;
-
-    assert_eq!(result, Err(PaymentsEngineError::AccountLocked(1)));
-  }
-
-  #[tokio::test]
-  async fn process_dispute_non_existing_transaction() {
-    let mut engine = InMemoryPaymentsEngine::new();
-    engine.accounts.insert(
-      1,
-      Account {
-        locked: false,
-        funds: Funds::available(dec!(100)),
-        transactions: HashMap::default(),
-      },
+    assert_eq!(
+      engine.process(void.clone()).await,
+      Err(PaymentsEngineError::ClientNotFound(1))
     );
-    let transaction = Transaction::Dispute {
+    assert_eq!(engine.process(authorize).await, Ok(()));
+    assert_eq!(engine.process(void.clone()).await, Ok(()));
+    assert_eq!(
+      engine.account(1),
+      Some(AccountReport::new(1, dec!(0), dec!(0), dec!(0), false))
+    );
+    assert_eq!(
+      engine.process(void).await,
+      Err(PaymentsEngineError::NotAnAuthorization(1, 101))
+    );
+    let capture = Transaction::Capture {
       client_id: 1,
       transaction_id: 101,
+      timestamp: None,
     };
-
-    let result = engine.process(transaction).await;
-
-    assert_eq!(result, Err(PaymentsEngineError::TransactionNotFound(101)));
+    assert_eq!(
+      engine.process(capture).await,
+      Err(PaymentsEngineError::NotAnAuthorization(1, 101))
+    );
   }
 
   #[tokio::test]
-  async fn process_dispute_already_disputed() {
-    let mut engine = InMemoryPaymentsEngine::new();
-    engine.accounts.insert(
-      1,
-      Account {
-        locked: false,
-        funds: Funds::available(dec!(100)),
-        transactions: vec![(101, TransactionState::from_dispute(dec!(10)))]
-          .into_iter()
-          .collect(),
-      },
-    );
-    let transaction = Transaction::Dispute {
+  async fn process_capture_expired_authorization() {
+    let mut engine = InMemoryPaymentsEngine::with_config(EngineConfig {
+      authorization_expiry: Some(60),
+      ..EngineConfig::default()
+    });
+    let authorize = |transaction_id, timestamp| Transaction::Authorize {
       client_id: 1,
-      transaction_id: 101,
+      transaction_id,
+      amount: dec!(10),
+      timestamp,
+      sub_account: 0,
+    };
+    let capture = |transaction_id, timestamp| Transaction::Capture {
+      client_id: 1,
+      transaction_id,
+      timestamp,
     };
 
-    let result = engine.process(transaction).await;
+    assert_eq!(engine.process(authorize(101, Some(1000))).await, Ok(()));
+    assert_eq!(engine.process(capture(101, Some(1059))).await, Ok(()));
 
+    assert_eq!(engine.process(authorize(102, Some(1100))).await, Ok(()));
     assert_eq!(
-      result,
-      Err(PaymentsEngineError::TransactionAlreadyDisputed(1, 101))
+      engine.process(capture(102, Some(1160))).await,
+      Err(PaymentsEngineError::AuthorizationExpired(1, 102))
+    );
+    // the authorizations without a timestamp happen at the latest one
+    assert_eq!(engine.process(authorize(103, None)).await, Ok(()));
+    assert_eq!(
+      engine.process(capture(103, Some(1200))).await,
+      Err(PaymentsEngineError::AuthorizationExpired(1, 103))
+    );
+    // the clock advances with the timestamps of the transactions of any client
+    let deposit = Transaction::Deposit {
+      client_id: 2,
+      transaction_id: 201,
+      amount: dec!(1),
+      timestamp: Some(1300),
+      sub_account: 0,
+    };
+    assert_eq!(engine.process(deposit).await, Ok(()));
+    assert_eq!(
+      engine.process(capture(103, None)).await,
+      Err(PaymentsEngineError::AuthorizationExpired(1, 103))
+    );
+    assert_eq!(
+      engine
+        .process(Transaction::Void {
+          client_id: 1,
+          transaction_id: 102,
+          timestamp: Some(1300),
+        })
+        .await,
+      Ok(())
+    );
+    assert_eq!(
+      engine.account(1),
+      Some(AccountReport::new(1, dec!(10), dec!(10), dec!(20), false))
     );
   }
 
   #[tokio::test]
-  async fn process_dispute_more_than_available() {
+  async fn process_transfer_to_itself() {
     let mut engine = InMemoryPaymentsEngine::new();
     engine.accounts.insert(
       1,
       Account {
-        locked: false,
-        funds: Funds::available(dec!(90)),
-        transactions: vec![(101, TransactionState::from_amount(dec!(100)))]
-          .into_iter()
-          .collect(),
+        funds: Funds::available(dec!(100)),
+        ..Account::default()
       },
     );
-    let transaction = Transaction::Dispute {
-      client_id: 1,
+    let transaction = Transaction::Transfer {
+      from_client: 1,
+      to_client: 1,
       transaction_id: 101,
+      amount: dec!(10),
+      timestamp: None,
     };
 
     let result = engine.process(transaction).await;
 
-    assert!(result.is_err());
-    assert_eq!(result, Err(PaymentsEngineError::DisputedMoreThanAvailable),);
+    assert_eq!(result, Err(PaymentsEngineError::SelfTransfer(1)));
   }
 
   #[tokio::test]
-  async fn process_dispute_successfully() {
+  async fn process_transfer_not_enough_available_funds() {
     let mut engine = InMemoryPaymentsEngine::new();
     engine.accounts.insert(
       1,
       Account {
-        locked: false,
-        funds: Funds::available(dec!(110)),
-        transactions: vec![(101, TransactionState::from_amount(dec!(10)))]
-          .into_iter()
-          .collect(),
+        funds: Funds::available(dec!(10)),
+        ..Account::default()
       },
     );
-    let transaction = Transaction::Dispute {
-      client_id: 1,
+    let transaction = Transaction::Transfer {
+      from_client: 1,
+      to_client: 2,
       transaction_id: 101,
+      amount: dec!(20),
+      timestamp: None,
     };
 
     let result = engine.process(transaction).await;
 
-    assert!(result.is_ok());
+    assert_eq!(result, Err(PaymentsEngineError::NotEnoughAvailableFunds));
+    assert_eq!(engine.accounts.len(), 1);
     assert_eq!(
-      engine.accounts.get(&1).unwrap(),
-      &Account {
-        locked: false,
-        funds: Funds::new(dec!(100), dec!(10)),
-        transactions: vec![(101, TransactionState::from_dispute(dec!(10)))]
-          .into_iter()
-          .collect(),
-      }
+      engine.accounts.get(&1).unwrap().funds,
+      Funds::available(dec!(10))
     );
   }
 
   #[tokio::test]
-  async fn process_resolve_non_existing_client() {
-    let mut engine = InMemoryPaymentsEngine::new();
-    let transaction = Transaction::Resolve {
-      client_id: 1,
-      transaction_id: 101,
-    };
-
-    let result = engine.process(transaction).await;
-
-    assert_eq!(result, Err(PaymentsEngineError::ClientNotFound(1)));
-  }
-
-  #[tokio::test]
-  async fn process_resolve_non_existing_transaction() {
+  async fn process_transfer_sender_locked() {
     let mut engine = InMemoryPaymentsEngine::new();
     engine.accounts.insert(
       1,
       Account {
-        locked: false,
+        locked: true,
         funds: Funds::available(dec!(100)),
-        transactions: HashMap::default(),
+        ..Account::default()
       },
     );
-    let transaction = Transaction::Resolve {
-      client_id: 1,
+    let transaction = Transaction::Transfer {
+      from_client: 1,
+      to_client: 2,
       transaction_id: 101,
+      amount: dec!(10),
+      timestamp: None,
     };
 
     let result = engine.process(transaction).await;
 
-    assert_eq!(result, Err(PaymentsEngineError::TransactionNotFound(101)));
+    assert_eq!(result, Err(PaymentsEngineError::AccountLocked(1)));
   }
 
   #[tokio::test]
-  async fn process_resolve_not_disputed() {
+  async fn process_transfer_recipient_locked() {
     let mut engine = InMemoryPaymentsEngine::new();
     engine.accounts.insert(
       1,
       Account {
-        locked: false,
         funds: Funds::available(dec!(100)),
-        transactions: vec![(101, TransactionState::from_amount(dec!(10)))]
-          .into_iter()
-          .collect(),
+        ..Account::default()
       },
     );
-    let transaction = Transaction::Resolve {
-      client_id: 1,
+    engine.accounts.insert(
+      2,
+      Account {
+        locked: true,
+        ..Account::default()
+      },
+    );
+    let transaction = Transaction::Transfer {
+      from_client: 1,
+      to_client: 2,
       transaction_id: 101,
+      amount: dec!(10),
+      timestamp: None,
     };
 
     let result = engine.process(transaction).await;
 
+    assert_eq!(result, Err(PaymentsEngineError::AccountLocked(2)));
     assert_eq!(
-      result,
-      Err(PaymentsEngineError::TransactionNotDisputed(1, 101))
+      engine.accounts.get(&1).unwrap().funds,
+      Funds::available(dec!(100))
     );
+    assert_eq!(engine.accounts.get(&2).unwrap().funds, Funds::zero());
   }
 
   #[tokio::test]
-  async fn process_resolve_successfully() {
+  async fn process_transfer_successfully() {
     let mut engine = InMemoryPaymentsEngine::new();
     engine.accounts.insert(
       1,
       Account {
-        locked: false,
-        funds: Funds::new(dec!(100), dec!(10)),
-        transactions: vec![(101, TransactionState::from_dispute(dec!(10)))]
-          .into_iter()
-          .collect(),
+        funds: Funds::available(dec!(100)),
+        ..Account::default()
       },
     );
-    let transaction = Transaction::Resolve {
-      client_id: 1,
+    let transaction = Transaction::Transfer {
+      from_client: 1,
+      to_client: 2,
       transaction_id: 101,
+      amount: dec!(10),
+      timestamp: None,
     };
 
     let result = engine.process(transaction).await;
 
-    assert!(result.is_ok());
+    assert_eq!(result, Ok(()));
     assert_eq!(
       engine.accounts.get(&1).unwrap(),
       &Account {
         locked: false,
-        funds: Funds::available(dec!(110)),
-        transactions: vec![(101, TransactionState::from_amount(dec!(10)))]
+        funds: Funds::available(dec!(90)),
+        transactions: vec![(101, TransactionState::from_transfer(dec!(10)))]
           .into_iter()
           .collect(),
+        open_disputes: 0,
+        sub_accounts: BTreeMap::new(),
+        fees: HashMap::new(),
+      }
+    );
+    assert_eq!(
+      engine.accounts.get(&2).unwrap(),
+      &Account {
+        funds: Funds::available(dec!(10)),
+        ..Account::default()
       }
     );
   }
 
   #[tokio::test]
-  async fn process_chargeback_non_existing_client() {
+  async fn process_sub_accounts() {
     let mut engine = InMemoryPaymentsEngine::new();
-    let transaction = Transaction::Chargeback {
-      client_id: 1,
-      transaction_id: 101,
-    };
-
-    let result = engine.process(transaction).await;
+    let transactions = vec![
+      (
+        Transaction::Deposit {
+          client_id: 1,
+          transaction_id: 101,
+          amount: dec!(100),
+          timestamp: None,
+          sub_account: 0,
+        },
+        Ok(()),
+      ),
+      (
+        Transaction::Deposit {
+          client_id: 1,
+          transaction_id: 102,
+          amount: dec!(50),
+          timestamp: None,
+          sub_account: 1,
+        },
+        Ok(()),
+      ),
+      // the withdrawals are limited by the funds of their sub-account
+      (
+        Transaction::Withdrawal {
+          client_id: 1,
+          transaction_id: 103,
+          amount: dec!(60),
+          timestamp: None,
+          sub_account: 1,
+        },
+        Err(PaymentsEngineError::NotEnoughAvailableFunds),
+      ),
+      (
+        Transaction::Withdrawal {
+          client_id: 1,
+          transaction_id: 104,
+          amount: dec!(20),
+          timestamp: None,
+          sub_account: 1,
+        },
+        Ok(()),
+      ),
+      (
+        Transaction::Dispute {
+          client_id: 1,
+          transaction_id: 102,
+          timestamp: None,
+        },
+        Err(PaymentsEngineError::DisputedMoreThanAvailable),
+      ),
+      // the transfers move the funds of the main sub-accounts
+      (
+        Transaction::Transfer {
+          from_client: 1,
+          to_client: 2,
+          transaction_id: 105,
+          amount: dec!(90),
+          timestamp: None,
+        },
+        Ok(()),
+      ),
+      (
+        Transaction::Withdrawal {
+          client_id: 1,
+          transaction_id: 106,
+          amount: dec!(20),
+          timestamp: None,
+          sub_account: 0,
+        },
+        Err(PaymentsEngineError::NotEnoughAvailableFunds),
+      ),
+      (
+        Transaction::Authorize {
+          client_id: 1,
+          transaction_id: 107,
+          amount: dec!(40),
+          timestamp: None,
+          sub_account: 2,
+        },
+        Ok(()),
+      ),
+      (
+        Transaction::Capture {
+          client_id: 1,
+          transaction_id: 107,
+          timestamp: None,
+        },
+        Ok(()),
+      ),
+      (
+        Transaction::Dispute {
+          client_id: 1,
+          transaction_id: 107,
+          timestamp: None,
+        },
+        Ok(()),
+      ),
+      (
+        Transaction::Chargeback {
+          client_id: 1,
+          transaction_id: 107,
+          timestamp: None,
+        },
+        Ok(()),
+      ),
+      // the lock is of the whole client
+      (
+        Transaction::Deposit {
+          client_id: 1,
+          transaction_id: 108,
+          amount: dec!(5),
+          timestamp: None,
+          sub_account: 1,
+        },
+        Err(PaymentsEngineError::AccountLocked(1)),
+      ),
+    ];
+    for (transaction, expected) in transactions {
+      assert_eq!(engine.process(transaction).await, expected);
+    }
 
-    assert_eq!(result, Err(PaymentsEngineError::ClientNotFound(1)));
+    let mut report: Vec<AccountReport> = engine.accounts_report().collect();
+    report.sort_by_key(|account_report| account_report.client_id);
+    let sub_account = |sub_account, available| SubAccountReport {
+      sub_account,
+      available,
+      held: dec!(0),
+      total: available,
+    };
+    assert_eq!(
+      report,
+      vec![
+        AccountReport::new(1, dec!(40), dec!(0), dec!(40), true)
+          .with_disputes(0, dec!(40))
+          .with_sub_accounts(vec![
+            sub_account(0, dec!(10)),
+            sub_account(1, dec!(30)),
+            sub_account(2, dec!(0)),
+          ]),
+        AccountReport::new(2, dec!(90), dec!(0), dec!(90), false),
+      ]
+    );
   }
 
   #[tokio::test]
-  async fn process_chargeback_non_existing_transaction() {
-    let mut engine = InMemoryPaymentsEngine::new();
+  async fn process_arithmetic_overflow() {
+    let mut engine = InMemoryPaymentsEngine::with_config(EngineConfig {
+      escrow_interest_rate: Some(dec!(2)),
+      ..EngineConfig::default()
+    });
     engine.accounts.insert(
       1,
       Account {
-        locked: false,
-        funds: Funds::available(dec!(100)),
-        transactions: HashMap::default(),
+        funds: Funds::available(Decimal::MAX),
+        ..Account::default()
       },
     );
-    let transaction = Transaction::Chargeback {
-      client_id: 1,
-      transaction_id: 101,
-    };
+    engine.accounts.insert(
+      2,
+      Account {
+        funds: Funds::available(dec!(10)),
+        transactions: vec![(201, TransactionState::from_dispute(Decimal::MAX))]
+          .into_iter()
+          .collect(),
+        open_disputes: 1,
+        ..Account::default()
+      },
+    );
+    let accounts = engine.accounts.clone();
 
-    let result = engine.process(transaction).await;
+    let transactions = vec![
+      Transaction::Deposit {
+        client_id: 1,
+        transaction_id: 101,
+        amount: dec!(1),
+        timestamp: None,
+        sub_account: 0,
+      },
+      Transaction::Transfer {
+        from_client: 2,
+        to_client: 1,
+        transaction_id: 202,
+        amount: dec!(1),
+        timestamp: None,
+      },
+      // both the released funds and their escrow interest overflow
+      Transaction::Resolve {
+        client_id: 2,
+        transaction_id: 201,
+        timestamp: Some(SECONDS_PER_DAY),
+      },
+    ];
+    for transaction in transactions {
+      assert_eq!(
+        engine.validate(&transaction),
+        Err(PaymentsEngineError::ArithmeticOverflow)
+      );
+      assert_eq!(
+        engine.process(transaction).await,
+        Err(PaymentsEngineError::ArithmeticOverflow)
+      );
+    }
 
-    assert_eq!(result, Err(PaymentsEngineError::TransactionNotFound(101)));
+    assert_eq!(engine.accounts, accounts);
   }
 
   #[tokio::test]
-  async fn process_chargeback_not_disputed() {
+  async fn process_resolve_with_the_charged_back_total_at_its_maximum() {
     let mut engine = InMemoryPaymentsEngine::new();
     engine.accounts.insert(
       1,
       Account {
-        locked: false,
-        funds: Funds::available(dec!(100)),
-        transactions: vec![(101, TransactionState::from_amount(dec!(10)))]
-          .into_iter()
-          .collect(),
+        funds: Funds::new(dec!(0), dec!(10)),
+        transactions: vec![
+          (101, TransactionState::from_chargeback(Decimal::MAX)),
+          (102, TransactionState::from_dispute(dec!(10))),
+        ]
+        .into_iter()
+        .collect(),
+        open_disputes: 1,
+        ..Account::default()
       },
     );
-    let transaction = Transaction::Chargeback {
+    let resolve = Transaction::Resolve {
       client_id: 1,
-      transaction_id: 101,
+      transaction_id: 102,
+      timestamp: None,
     };
 
-    let result = engine.process(transaction).await;
-
-    assert_eq!(
-      result,
-      Err(PaymentsEngineError::TransactionNotDisputed(1, 101))
-    );
+    assert_eq!(engine.validate(&resolve), Ok(()));
+    assert_eq!(engine.process(resolve).await, Ok(()));
+    assert_eq!(engine.accounts[&1].funds, Funds::new(dec!(10), dec!(0)));
   }
 
-  #[tokio::test]
-  async fn process_chargeback_successfully() {
+  #[test]
+  fn validate_without_changing_the_state() {
     let mut engine = InMemoryPaymentsEngine::new();
     engine.accounts.insert(
       1,
       Account {
         locked: false,
-        funds: Funds::new(dec!(100), dec!(10)),
-        transactions: vec![(101, TransactionState::from_dispute(dec!(10)))]
+        funds: Funds::available(dec!(100)),
+        transactions: vec![(101, TransactionState::from_amount(dec!(100)))]
           .into_iter()
           .collect(),
+        open_disputes: 0,
+        sub_accounts: BTreeMap::new(),
+        fees: HashMap::new(),
       },
     );
-    let transaction = Transaction::Chargeback {
-      client_id: 1,
-      transaction_id: 101,
-    };
 
-    let result = engine.process(transaction).await;
+    let cases = vec![
+      (
+        Transaction::Deposit {
+          client_id: 2,
+          transaction_id: 201,
+          amount: dec!(10),
+          timestamp: None,
+          sub_account: 0,
+        },
+        Ok(()),
+      ),
+      (
+        Transaction::Withdrawal {
+          client_id: 1,
+          transaction_id: 102,
+          amount: dec!(200),
+          timestamp: None,
+          sub_account: 0,
+        },
+        Err(PaymentsEngineError::NotEnoughAvailableFunds),
+      ),
+      (
+        Transaction::Dispute {
+          client_id: 1,
+          transaction_id: 101,
+          timestamp: None,
+        },
+        Ok(()),
+      ),
+      (
+        Transaction::Resolve {
+          client_id: 1,
+          transaction_id: 101,
+          timestamp: None,
+        },
+        Err(PaymentsEngineError::TransactionNotDisputed(1, 101)),
+      ),
+      (
+        Transaction::Chargeback {
+          client_id: 3,
+          transaction_id: 301,
+          timestamp: None,
+        },
+        Err(PaymentsEngineError::ClientNotFound(3)),
+      ),
+    ];
 
-    assert!(result.is_ok());
+    for (transaction, expected) in cases {
+      assert_eq!(engine.validate(&transaction), expected);
+    }
+
+    assert_eq!(engine.accounts.len(), 1);
     assert_eq!(
       engine.accounts.get(&1).unwrap(),
       &Account {
-        locked: true,
+        locked: false,
         funds: Funds::available(dec!(100)),
-        transactions: HashMap::default(),
+        transactions: vec![(101, TransactionState::from_amount(dec!(100)))]
+          .into_iter()
+          .collect(),
+        open_disputes: 0,
+        sub_accounts: BTreeMap::new(),
+        fees: HashMap::new(),
       }
     );
   }
@@ -845,6 +4423,9 @@ mod tests {
         transactions: vec![(101, TransactionState::from_dispute(dec!(10)))]
           .into_iter()
           .collect(),
+        open_disputes: 1,
+        sub_accounts: BTreeMap::new(),
+        fees: HashMap::new(),
       },
     );
     engine.accounts.insert(
@@ -869,7 +4450,7 @@ mod tests {
     assert_eq!(
       report,
       vec![
-        AccountReport::new(1, dec!(100), dec!(0), dec!(100), false),
+        AccountReport::new(1, dec!(100), dec!(0), dec!(100), false).with_disputes(1, dec!(0)),
         AccountReport::new(2, dec!(200), dec!(-10), dec!(190), false),
         AccountReport::new(3, dec!(300), dec!(0), dec!(300), true),
       ]
@@ -877,4 +4458,369 @@ mod tests {
       .collect()
     );
   }
+
+  #[test]
+  fn accounts_report_deterministic() {
+    let mut engine = InMemoryPaymentsEngine::with_config(EngineConfig {
+      deterministic: true,
+      ..EngineConfig::default()
+    });
+    for client_id in [3, 1, 4, 2].iter() {
+      engine.accounts.insert(
+        *client_id,
+        Account {
+          funds: Funds::available(dec!(10)),
+          ..Account::default()
+        },
+      );
+    }
+
+    let report: Vec<AccountReport> = engine.accounts_report().collect();
+
+    assert_eq!(
+      report,
+      vec![
+        AccountReport::new(1, dec!(10), dec!(0), dec!(10), false),
+        AccountReport::new(2, dec!(10), dec!(0), dec!(10), false),
+        AccountReport::new(3, dec!(10), dec!(0), dec!(10), false),
+        AccountReport::new(4, dec!(10), dec!(0), dec!(10), false),
+      ]
+    );
+  }
+
+  #[test]
+  fn accounts_report_ref() {
+    let mut engine = InMemoryPaymentsEngine::with_config(EngineConfig {
+      deterministic: true,
+      ..EngineConfig::default()
+    });
+    engine.accounts.insert(
+      2,
+      Account {
+        locked: true,
+        funds: Funds::new(dec!(200), dec!(-10)),
+        ..Account::default()
+      },
+    );
+    engine.accounts.insert(
+      1,
+      Account {
+        locked: false,
+        funds: Funds::available(dec!(100)),
+        transactions: vec![(101, TransactionState::from_dispute(dec!(10)))]
+          .into_iter()
+          .collect(),
+        open_disputes: 1,
+        sub_accounts: BTreeMap::new(),
+        fees: HashMap::new(),
+      },
+    );
+
+    let views: Vec<AccountView> = engine.accounts_report_ref().collect();
+
+    assert_eq!(
+      views
+        .iter()
+        .map(|view| (
+          view.client_id(),
+          view.available(),
+          view.held(),
+          view.total(),
+          view.locked()
+        ))
+        .collect::<Vec<_>>(),
+      vec![
+        (1, dec!(100), dec!(0), dec!(100), false),
+        (2, dec!(200), dec!(-10), dec!(190), true),
+      ]
+    );
+    assert_eq!(
+      views.iter().map(AccountView::report).collect::<Vec<_>>(),
+      engine.accounts_report().collect::<Vec<_>>()
+    );
+  }
+
+  #[test]
+  fn accounts_matching_filter() {
+    let mut engine = InMemoryPaymentsEngine::new();
+    engine.accounts.insert(
+      1,
+      Account {
+        locked: true,
+        funds: Funds::available(dec!(100)),
+        ..Account::default()
+      },
+    );
+    engine.accounts.insert(
+      2,
+      Account {
+        locked: true,
+        funds: Funds::available(dec!(5)),
+        ..Account::default()
+      },
+    );
+    engine.accounts.insert(
+      3,
+      Account {
+        locked: false,
+        funds: Funds::available(dec!(100)),
+        ..Account::default()
+      },
+    );
+
+    let filter = AccountFilter {
+      locked_only: true,
+      min_total: Some(dec!(10)),
+      ..AccountFilter::default()
+    };
+    let report: Vec<AccountReport> = engine.accounts_matching(filter).collect();
+
+    assert_eq!(
+      report,
+      vec![AccountReport::new(1, dec!(100), dec!(0), dec!(100), true)]
+    );
+  }
+
+  #[test]
+  fn account_and_transaction_lookups() {
+    let mut engine = InMemoryPaymentsEngine::new();
+    engine.accounts.insert(
+      1,
+      Account {
+        funds: Funds::new(dec!(80), dec!(20)),
+        transactions: vec![
+          (101, TransactionState::from_amount(dec!(80))),
+          (102, TransactionState::from_dispute(dec!(20))),
+        ]
+        .into_iter()
+        .collect(),
+        open_disputes: 1,
+        ..Account::default()
+      },
+    );
+
+    assert_eq!(
+      engine.account(1),
+      Some(AccountReport::new(1, dec!(80), dec!(20), dec!(100), false).with_disputes(1, dec!(0)))
+    );
+    assert_eq!(engine.account(2), None);
+
+    assert_eq!(
+      engine.transaction(1, 102).unwrap(),
+      Some(TransactionInfo {
+        client_id: 1,
+        transaction_id: 102,
+        kind: TransactionKind::Deposit,
+        amount: dec!(20),
+        state: DisputeState::Disputed,
+        disputed_at: Some(0),
+      })
+    );
+    assert_eq!(engine.transaction(1, 103).unwrap(), None);
+    assert_eq!(engine.transaction(2, 101).unwrap(), None);
+  }
+
+  #[tokio::test]
+  async fn process_with_transaction_store() {
+    let config = EngineConfig {
+      deterministic: true,
+      ..EngineConfig::default()
+    };
+    let mut engine = InMemoryPaymentsEngine::with_config(config.clone())
+      .with_transaction_store(Box::new(InMemoryTransactionStore::new()));
+    let mut reference = InMemoryPaymentsEngine::with_config(config);
+
+    let transactions = vec![
+      Transaction::Deposit {
+        client_id: 1,
+        transaction_id: 101,
+        amount: dec!(100),
+        timestamp: None,
+        sub_account: 0,
+      },
+      Transaction::Deposit {
+        client_id: 1,
+        transaction_id: 102,
+        amount: dec!(20),
+        timestamp: None,
+        sub_account: 0,
+      },
+      Transaction::Withdrawal {
+        client_id: 1,
+        transaction_id: 101,
+        amount: dec!(1),
+        timestamp: None,
+        sub_account: 0,
+      },
+      Transaction::Authorize {
+        client_id: 2,
+        transaction_id: 201,
+        amount: dec!(5),
+        timestamp: None,
+        sub_account: 0,
+      },
+      Transaction::Transfer {
+        from_client: 1,
+        to_client: 2,
+        transaction_id: 103,
+        amount: dec!(10),
+        timestamp: None,
+      },
+      Transaction::Dispute {
+        client_id: 1,
+        transaction_id: 101,
+        timestamp: None,
+      },
+      Transaction::Resolve {
+        client_id: 1,
+        transaction_id: 101,
+        timestamp: None,
+      },
+      Transaction::Dispute {
+        client_id: 1,
+        transaction_id: 102,
+        timestamp: None,
+      },
+      Transaction::Capture {
+        client_id: 2,
+        transaction_id: 201,
+        timestamp: None,
+      },
+      Transaction::Dispute {
+        client_id: 1,
+        transaction_id: 101,
+        timestamp: None,
+      },
+      Transaction::Chargeback {
+        client_id: 1,
+        transaction_id: 102,
+        timestamp: None,
+      },
+      Transaction::Dispute {
+        client_id: 2,
+        transaction_id: 201,
+        timestamp: None,
+      },
+    ];
+    for transaction in transactions {
+      assert_eq!(
+        engine.validate(&transaction),
+        reference.validate(&transaction)
+      );
+      assert_eq!(
+        engine.process(transaction.clone()).await,
+        reference.process(transaction).await
+      );
+    }
+
+    // only the transactions needed by the reports are kept in the accounts
+    let in_accounts: HashSet<(ClientId, TransactionId)> = engine
+      .accounts
+      .iter()
+      .flat_map(|(client_id, account)| {
+        account
+          .transactions
+          .keys()
+          .map(move |transaction_id| (*client_id, *transaction_id))
+      })
+      .collect();
+    assert_eq!(in_accounts, vec![(1, 102), (2, 201)].into_iter().collect());
+    assert_eq!(
+      engine.accounts_report().collect::<Vec<_>>(),
+      reference.accounts_report().collect::<Vec<_>>()
+    );
+    assert_eq!(
+      engine.transactions_report().unwrap().collect::<Vec<_>>(),
+      reference.transactions_report().unwrap().collect::<Vec<_>>()
+    );
+    assert_eq!(engine.transaction(1, 103), reference.transaction(1, 103));
+    assert_eq!(engine.snapshot().unwrap(), reference.snapshot().unwrap());
+  }
+
+  /// A [`TransactionStore`] that keeps the transactions until it is broken, and then fails to read them.
+  #[derive(Debug, Default)]
+  struct BreakingTransactionStore {
+    inner: InMemoryTransactionStore,
+    broken: bool,
+  }
+
+  impl BreakingTransactionStore {
+    fn check(&self) -> Result<()> {
+      if self.broken {
+        Err(PaymentsEngineError::TransactionStore("broken".to_string()))
+      } else {
+        Ok(())
+      }
+    }
+  }
+
+  impl TransactionStore for BreakingTransactionStore {
+    fn insert(
+      &mut self,
+      client_id: ClientId,
+      transaction_id: TransactionId,
+      transaction: TransactionState,
+    ) -> Result<()> {
+      self.check()?;
+      self.inner.insert(client_id, transaction_id, transaction)
+    }
+
+    fn remove(
+      &mut self,
+      client_id: ClientId,
+      transaction_id: TransactionId,
+    ) -> Result<Option<TransactionState>> {
+      self.check()?;
+      self.inner.remove(client_id, transaction_id)
+    }
+
+    fn get(
+      &self,
+      client_id: ClientId,
+      transaction_id: TransactionId,
+    ) -> Result<Option<TransactionState>> {
+      self.check()?;
+      self.inner.get(client_id, transaction_id)
+    }
+
+    fn transactions(&self) -> StoredTransactions<'_> {
+      match self.check() {
+        Ok(()) => self.inner.transactions(),
+        Err(err) => Box::new(std::iter::once(Err(err))),
+      }
+    }
+
+    fn clear(&mut self) -> Result<()> {
+      self.check()?;
+      self.inner.clear()
+    }
+  }
+
+  #[tokio::test]
+  async fn transaction_store_failures() {
+    let mut engine = InMemoryPaymentsEngine::new()
+      .with_transaction_store(Box::new(BreakingTransactionStore::default()));
+    engine
+      .process(Transaction::Deposit {
+        client_id: 1,
+        transaction_id: 101,
+        amount: dec!(10),
+        timestamp: None,
+        sub_account: 0,
+      })
+      .await
+      .unwrap();
+    let snapshot = engine.snapshot().unwrap();
+
+    engine.store = Some(Box::new(BreakingTransactionStore {
+      broken: true,
+      ..BreakingTransactionStore::default()
+    }));
+    let failure = PaymentsEngineError::TransactionStore("broken".to_string());
+
+    assert_eq!(engine.transaction(1, 101), Err(failure.clone()));
+    assert!(engine.transactions_report().is_err());
+    assert_eq!(engine.snapshot(), Err(failure.clone()));
+    assert_eq!(engine.restore_with_config_change(snapshot), Err(failure));
+  }
 }
@@ -0,0 +1,791 @@
+use std::sync::{mpsc, Arc};
+
+use async_trait::async_trait;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+
+use super::{
+  account::{AccountReport, Exposure, TransactionInfo},
+  engine::{
+    AccountsReportIter, AccountsReportStream, PaymentsEngine, PaymentsEngineError, Result,
+    TransactionsReportIter,
+  },
+  filter::AccountFilter,
+  transaction::{ClientId, Transaction, TransactionId},
+};
+
+/// What happened to the accounts when processing a transaction, shared by all the consumers of the events
+/// (the [`EventListener`]s and the [`Metrics`](super::Metrics)) so they agree on one schema.
+///
+/// They are serialized with the name of the event in snake case in the `event` field (like `"event": "dispute_opened"`),
+/// and those names are stable, so the events can be stored or sent to other systems.
+/// The authorizations, their captures and voids, and the unlocks don't have events of their own.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum EngineEvent {
+  /// The first transaction accepted for a client, which created its account.
+  AccountCreated { client_id: ClientId },
+  /// Funds deposited into an account, or received by the recipient of a transfer.
+  Deposited {
+    client_id: ClientId,
+    transaction_id: TransactionId,
+    amount: Decimal,
+  },
+  /// Funds withdrawn from an account, or sent by the sender of a transfer.
+  Withdrew {
+    client_id: ClientId,
+    transaction_id: TransactionId,
+    amount: Decimal,
+  },
+  DisputeOpened {
+    client_id: ClientId,
+    transaction_id: TransactionId,
+  },
+  DisputeResolved {
+    client_id: ClientId,
+    transaction_id: TransactionId,
+  },
+  ChargedBack {
+    client_id: ClientId,
+    transaction_id: TransactionId,
+  },
+  /// An account that was not locked before processing a transaction, and it is after it.
+  Locked { client_id: ClientId },
+  /// The exposure of a client crossed its threshold when processing a transaction (see [`Exposure`]).
+  ExposureExceeded {
+    client_id: ClientId,
+    exposure: Decimal,
+    threshold: Decimal,
+  },
+  /// A transaction rejected by the engine, with the kind of the error (see [`PaymentsEngineError::kind`](super::PaymentsEngineError::kind)).
+  Rejected {
+    client_id: ClientId,
+    transaction_id: Option<TransactionId>,
+    transaction_type: String,
+    amount: Option<Decimal>,
+    error: String,
+  },
+}
+
+impl EngineEvent {
+  /// The name of the event, as it is serialized.
+  pub fn name(&self) -> &'static str {
+    match self {
+      EngineEvent::AccountCreated { .. } => "account_created",
+      EngineEvent::Deposited { .. } => "deposited",
+      EngineEvent::Withdrew { .. } => "withdrew",
+      EngineEvent::DisputeOpened { .. } => "dispute_opened",
+      EngineEvent::DisputeResolved { .. } => "dispute_resolved",
+      EngineEvent::ChargedBack { .. } => "charged_back",
+      EngineEvent::Locked { .. } => "locked",
+      EngineEvent::ExposureExceeded { .. } => "exposure_exceeded",
+      EngineEvent::Rejected { .. } => "rejected",
+    }
+  }
+
+  /// The events caused by processing a transaction with the engine, given the state of its accounts before processing it.
+  /// The accounts created come first, then the changes of the transaction and the exposures exceeded, and the accounts locked last.
+  /// A transaction accepted without changing the transaction it records or refers to causes no events,
+  /// like a retried resolve or chargeback with the [`DuplicatePolicy::Idempotent`](super::DuplicatePolicy::Idempotent),
+  /// or a zero amount skipped with the [`ZeroAmountPolicy::Skip`](super::ZeroAmountPolicy::Skip).
+  pub(crate) fn caused_by<E>(
+    engine: &E,
+    before: AccountsBefore,
+    transaction: &Transaction,
+    result: &Result<()>,
+  ) -> Vec<Self>
+  where
+    E: PaymentsEngine + ?Sized,
+  {
+    if let Err(err) = result {
+      return vec![EngineEvent::Rejected {
+        client_id: transaction.client_id(),
+        transaction_id: transaction.transaction_id(),
+        transaction_type: transaction.type_name().to_string(),
+        amount: transaction.amount(),
+        error: err.kind().to_string(),
+      }];
+    }
+
+    if let Some(transaction_before) = before.transaction {
+      if recorded(engine, transaction) == Some(transaction_before) {
+        return Vec::new();
+      }
+    }
+
+    let after: Vec<(ClientId, Option<AccountState>)> = before
+      .accounts
+      .iter()
+      .map(|(client_id, _)| (*client_id, AccountState::of(engine, *client_id)))
+      .collect();
+
+    let mut events: Vec<Self> = before
+      .accounts
+      .iter()
+      .zip(after.iter())
+      .filter(|((_, before), (_, after))| before.is_none() && after.is_some())
+      .map(|(_, (client_id, _))| EngineEvent::AccountCreated {
+        client_id: *client_id,
+      })
+      .collect();
+
+    match *transaction {
+      Transaction::Deposit {
+        client_id,
+        transaction_id,
+        amount,
+        ..
+      } => events.push(EngineEvent::Deposited {
+        client_id,
+        transaction_id,
+        amount,
+      }),
+      Transaction::Withdrawal {
+        client_id,
+        transaction_id,
+        amount,
+        ..
+      } => events.push(EngineEvent::Withdrew {
+        client_id,
+        transaction_id,
+        amount,
+      }),
+      Transaction::Transfer {
+        from_client,
+        to_client,
+        transaction_id,
+        amount,
+        ..
+      } => {
+        events.push(EngineEvent::Withdrew {
+          client_id: from_client,
+          transaction_id,
+          amount,
+        });
+        events.push(EngineEvent::Deposited {
+          client_id: to_client,
+          transaction_id,
+          amount,
+        });
+      }
+      Transaction::Dispute {
+        client_id,
+        transaction_id,
+        ..
+      } => events.push(EngineEvent::DisputeOpened {
+        client_id,
+        transaction_id,
+      }),
+      Transaction::Resolve {
+        client_id,
+        transaction_id,
+        ..
+      } => events.push(EngineEvent::DisputeResolved {
+        client_id,
+        transaction_id,
+      }),
+      Transaction::Chargeback {
+        client_id,
+        transaction_id,
+        ..
+      } => events.push(EngineEvent::ChargedBack {
+        client_id,
+        transaction_id,
+      }),
+      Transaction::Unlock { .. }
+      | Transaction::Reactivate { .. }
+      | Transaction::Authorize { .. }
+      | Transaction::Capture { .. }
+      | Transaction::Void { .. } => {}
+    }
+
+    for ((_, before), (client_id, after)) in before.accounts.iter().zip(after.iter()) {
+      let exceeded_before = before.map_or(false, |before| before.exposure_exceeded());
+      if let Some(exposure) = after.and_then(|after| after.exposure) {
+        if exposure.exceeded() && !exceeded_before {
+          events.push(EngineEvent::ExposureExceeded {
+            client_id: *client_id,
+            exposure: exposure.amount,
+            threshold: exposure.threshold,
+          });
+        }
+      }
+    }
+
+    events.extend(
+      before
+        .accounts
+        .iter()
+        .zip(after.iter())
+        .filter(|((_, before), (_, after))| {
+          !before.map_or(false, |before| before.locked) && after.map_or(false, |after| after.locked)
+        })
+        .map(|(_, (client_id, _))| EngineEvent::Locked {
+          client_id: *client_id,
+        }),
+    );
+    events
+  }
+}
+
+/// The state of the accounts of a transaction before processing it, and the transaction it records or refers to,
+/// so the events it caused can be told afterwards with [`EngineEvent::caused_by`].
+pub(crate) struct AccountsBefore {
+  accounts: Vec<(ClientId, Option<AccountState>)>,
+  transaction: Option<Option<TransactionInfo>>,
+}
+
+impl AccountsBefore {
+  pub(crate) fn of<E>(engine: &E, transaction: &Transaction) -> Self
+  where
+    E: PaymentsEngine + ?Sized,
+  {
+    Self {
+      accounts: transaction
+        .clients()
+        .into_iter()
+        .map(|client_id| (client_id, AccountState::of(engine, client_id)))
+        .collect(),
+      transaction: recorded(engine, transaction),
+    }
+  }
+}
+
+/// The transaction recorded with the ID of a transaction, if any, or `None` when it has no ID or it can't be looked up.
+fn recorded<E>(engine: &E, transaction: &Transaction) -> Option<Option<TransactionInfo>>
+where
+  E: PaymentsEngine + ?Sized,
+{
+  let transaction_id = transaction.transaction_id()?;
+  engine
+    .transaction(transaction.client_id(), transaction_id)
+    .ok()
+}
+
+/// Whether the account of a client is locked, and its exposure when it is watched.
+#[derive(Clone, Copy)]
+struct AccountState {
+  locked: bool,
+  exposure: Option<Exposure>,
+}
+
+impl AccountState {
+  /// The state of the account of a client, or `None` when it doesn't exist.
+  fn of<E>(engine: &E, client_id: ClientId) -> Option<Self>
+  where
+    E: PaymentsEngine + ?Sized,
+  {
+    engine.account(client_id).map(|account| Self {
+      locked: account.locked,
+      exposure: account.exposure,
+    })
+  }
+
+  fn exposure_exceeded(&self) -> bool {
+    self.exposure.map_or(false, |exposure| exposure.exceeded())
+  }
+}
+
+/// Interface for the systems interested in what happens to the accounts, like a fraud or risk detection system.
+/// All the methods do nothing by default, so the listeners only implement the ones they care about.
+pub trait EventListener: Send + Sync {
+  /// An event caused by a transaction processed by the engine, in the order they happened.
+  fn on_event(&self, _event: &EngineEvent) {}
+
+  /// A transaction processed successfully by the engine, notified before its events.
+  fn on_transaction_accepted(&self, _transaction: &Transaction) {}
+
+  /// A transaction rejected by the engine, with the reason why, notified before its [`EngineEvent::Rejected`].
+  fn on_transaction_rejected(&self, _transaction: &Transaction, _error: &PaymentsEngineError) {}
+
+  /// A disputed transaction charged back successfully, notified along with its [`EngineEvent::ChargedBack`].
+  fn on_chargeback(&self, _client_id: ClientId, _transaction_id: TransactionId) {}
+
+  /// An account that was not locked before processing a transaction, and it is after it,
+  /// notified along with its [`EngineEvent::Locked`].
+  fn on_account_locked(&self, _client_id: ClientId) {}
+}
+
+/// An [`EventListener`] that sends the events into a channel, so they can be consumed by another thread.
+/// The channel is bounded, so a slow consumer blocks the processing instead of keeping all the events in memory.
+/// As the thread processing the transactions is blocked while the channel is full, the events have to be consumed
+/// out of the async runtime (like in [`tokio::task::spawn_blocking`]). They are dropped once the receiver is closed.
+#[derive(Debug, Clone)]
+pub struct ChannelEventListener(mpsc::SyncSender<EngineEvent>);
+
+impl ChannelEventListener {
+  /// The listener and the receiver of its events, which keeps up to `capacity` events not received yet.
+  pub fn new(capacity: usize) -> (Self, mpsc::Receiver<EngineEvent>) {
+    let (sender, receiver) = mpsc::sync_channel(capacity);
+    (Self(sender), receiver)
+  }
+}
+
+impl EventListener for ChannelEventListener {
+  fn on_event(&self, event: &EngineEvent) {
+    self.0.send(event.clone()).ok();
+  }
+}
+
+/// A [`PaymentsEngine`] middleware that notifies an [`EventListener`] about the [`EngineEvent`]s of every transaction
+/// processed by the inner engine.
+///
+/// The accounts involved in a transaction are looked up before and after processing it to detect when they are created
+/// or get locked, whatever the reason (a chargeback, or any rule of the inner engine).
+pub struct ListeningPaymentsEngine<E> {
+  inner: E,
+  listener: Arc<dyn EventListener>,
+}
+
+impl<E> ListeningPaymentsEngine<E>
+where
+  E: PaymentsEngine,
+{
+  pub fn new(inner: E, listener: Arc<dyn EventListener>) -> Self {
+    Self { inner, listener }
+  }
+}
+
+#[async_trait]
+impl<E> PaymentsEngine for ListeningPaymentsEngine<E>
+where
+  E: PaymentsEngine + Send,
+{
+  async fn process(&mut self, transaction: Transaction) -> Result<()> {
+    let before = AccountsBefore::of(&self.inner, &transaction);
+    let result = self.inner.process(transaction.clone()).await;
+    match &result {
+      Ok(()) => self.listener.on_transaction_accepted(&transaction),
+      Err(err) => self.listener.on_transaction_rejected(&transaction, err),
+    }
+    for event in EngineEvent::caused_by(&self.inner, before, &transaction, &result) {
+      self.listener.on_event(&event);
+      match event {
+        EngineEvent::ChargedBack {
+          client_id,
+          transaction_id,
+        } => self.listener.on_chargeback(client_id, transaction_id),
+        EngineEvent::Locked { client_id } => self.listener.on_account_locked(client_id),
+        _ => {}
+      }
+    }
+    result
+  }
+
+  fn validate(&self, transaction: &Transaction) -> Result<()> {
+    self.inner.validate(transaction)
+  }
+
+  fn accounts_report(&self) -> AccountsReportIter {
+    self.inner.accounts_report()
+  }
+
+  fn accounts_matching(&self, filter: AccountFilter) -> AccountsReportIter {
+    self.inner.accounts_matching(filter)
+  }
+
+  fn accounts_report_stream(&self) -> AccountsReportStream {
+    self.inner.accounts_report_stream()
+  }
+
+  fn account(&self, client_id: ClientId) -> Option<AccountReport> {
+    self.inner.account(client_id)
+  }
+
+  fn transaction(
+    &self,
+    client_id: ClientId,
+    transaction_id: TransactionId,
+  ) -> Result<Option<TransactionInfo>> {
+    self.inner.transaction(client_id, transaction_id)
+  }
+
+  fn transactions_report(&self) -> Result<TransactionsReportIter> {
+    self.inner.transactions_report()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+
+  use rust_decimal_macros::dec;
+
+  use super::*;
+  use crate::payments::{DuplicatePolicy, EngineConfig, InMemoryPaymentsEngine, ZeroAmountPolicy};
+
+  #[tokio::test]
+  async fn process_notifies_events() {
+    let (listener, events) = ChannelEventListener::new(64);
+    let mut engine =
+      ListeningPaymentsEngine::new(InMemoryPaymentsEngine::new(), Arc::new(listener));
+
+    let transactions = vec![
+      Transaction::Deposit {
+        client_id: 1,
+        transaction_id: 101,
+        amount: dec!(10),
+        timestamp: None,
+        sub_account: 0,
+      },
+      Transaction::Deposit {
+        client_id: 2,
+        transaction_id: 102,
+        amount: dec!(5),
+        timestamp: None,
+        sub_account: 0,
+      },
+      Transaction::Transfer {
+        from_client: 2,
+        to_client: 1,
+        transaction_id: 103,
+        amount: dec!(4),
+        timestamp: None,
+      },
+      Transaction::Dispute {
+        client_id: 1,
+        transaction_id: 101,
+        timestamp: None,
+      },
+      Transaction::Chargeback {
+        client_id: 1,
+        transaction_id: 101,
+        timestamp: None,
+      },
+    ];
+    for transaction in transactions {
+      assert!(engine.process(transaction).await.is_ok());
+    }
+    let withdrawal = Transaction::Withdrawal {
+      client_id: 1,
+      transaction_id: 104,
+      amount: dec!(1),
+      timestamp: None,
+      sub_account: 0,
+    };
+    assert!(engine.process(withdrawal).await.is_err());
+    drop(engine);
+
+    let received: Vec<EngineEvent> = events.iter().collect();
+    assert_eq!(
+      received,
+      vec![
+        EngineEvent::AccountCreated { client_id: 1 },
+        EngineEvent::Deposited {
+          client_id: 1,
+          transaction_id: 101,
+          amount: dec!(10)
+        },
+        EngineEvent::AccountCreated { client_id: 2 },
+        EngineEvent::Deposited {
+          client_id: 2,
+          transaction_id: 102,
+          amount: dec!(5)
+        },
+        EngineEvent::Withdrew {
+          client_id: 2,
+          transaction_id: 103,
+          amount: dec!(4)
+        },
+        EngineEvent::Deposited {
+          client_id: 1,
+          transaction_id: 103,
+          amount: dec!(4)
+        },
+        EngineEvent::DisputeOpened {
+          client_id: 1,
+          transaction_id: 101
+        },
+        EngineEvent::ChargedBack {
+          client_id: 1,
+          transaction_id: 101
+        },
+        EngineEvent::Locked { client_id: 1 },
+        EngineEvent::Rejected {
+          client_id: 1,
+          transaction_id: Some(104),
+          transaction_type: "withdrawal".to_string(),
+          amount: Some(dec!(1)),
+          error: "account_locked".to_string(),
+        },
+      ]
+    );
+  }
+
+  #[derive(Default)]
+  struct RecordedListener(std::sync::Mutex<Vec<String>>);
+
+  impl EventListener for RecordedListener {
+    fn on_transaction_accepted(&self, transaction: &Transaction) {
+      let mut calls = self.0.lock().unwrap();
+      calls.push(format!("accepted {:?}", transaction.transaction_id()));
+    }
+
+    fn on_transaction_rejected(&self, transaction: &Transaction, error: &PaymentsEngineError) {
+      let mut calls = self.0.lock().unwrap();
+      calls.push(format!(
+        "rejected {:?} {}",
+        transaction.transaction_id(),
+        error.kind()
+      ));
+    }
+
+    fn on_chargeback(&self, client_id: ClientId, transaction_id: TransactionId) {
+      let mut calls = self.0.lock().unwrap();
+      calls.push(format!("chargeback {} {}", client_id, transaction_id));
+    }
+
+    fn on_account_locked(&self, client_id: ClientId) {
+      let mut calls = self.0.lock().unwrap();
+      calls.push(format!("locked {}", client_id));
+    }
+  }
+
+  #[tokio::test]
+  async fn process_notifies_transactions_chargebacks_and_locked_accounts() {
+    let listener = Arc::new(RecordedListener::default());
+    let mut engine = ListeningPaymentsEngine::new(InMemoryPaymentsEngine::new(), listener.clone());
+
+    let transactions = vec![
+      Transaction::Deposit {
+        client_id: 1,
+        transaction_id: 101,
+        amount: dec!(10),
+        timestamp: None,
+        sub_account: 0,
+      },
+      Transaction::Dispute {
+        client_id: 1,
+        transaction_id: 101,
+        timestamp: None,
+      },
+      Transaction::Chargeback {
+        client_id: 1,
+        transaction_id: 101,
+        timestamp: None,
+      },
+      Transaction::Deposit {
+        client_id: 1,
+        transaction_id: 102,
+        amount: dec!(5),
+        timestamp: None,
+        sub_account: 0,
+      },
+    ];
+    for transaction in transactions {
+      engine.process(transaction).await.ok();
+    }
+
+    assert_eq!(
+      *listener.0.lock().unwrap(),
+      vec![
+        "accepted Some(101)",
+        "accepted Some(101)",
+        "accepted Some(101)",
+        "chargeback 1 101",
+        "locked 1",
+        "rejected Some(102) account_locked",
+      ]
+    );
+  }
+
+  #[tokio::test]
+  async fn process_notifies_no_events_for_transactions_without_changes() {
+    let (listener, events) = ChannelEventListener::new(64);
+    let config = EngineConfig {
+      duplicate_policy: DuplicatePolicy::Idempotent,
+      zero_amount_policy: ZeroAmountPolicy::Skip,
+      ..EngineConfig::default()
+    };
+    let mut engine = ListeningPaymentsEngine::new(
+      InMemoryPaymentsEngine::with_config(config),
+      Arc::new(listener),
+    );
+    let deposit = |transaction_id, amount| Transaction::Deposit {
+      client_id: 1,
+      transaction_id,
+      amount,
+      timestamp: None,
+      sub_account: 0,
+    };
+    let dispute = |transaction_id| Transaction::Dispute {
+      client_id: 1,
+      transaction_id,
+      timestamp: None,
+    };
+    let resolve = Transaction::Resolve {
+      client_id: 1,
+      transaction_id: 101,
+      timestamp: None,
+    };
+    let chargeback = Transaction::Chargeback {
+      client_id: 1,
+      transaction_id: 103,
+      timestamp: None,
+    };
+
+    let transactions = vec![
+      deposit(101, dec!(10)),
+      deposit(102, dec!(0)),
+      dispute(101),
+      resolve.clone(),
+      resolve,
+      deposit(103, dec!(5)),
+      dispute(103),
+      chargeback.clone(),
+      chargeback,
+    ];
+    for transaction in transactions {
+      assert_eq!(engine.process(transaction).await, Ok(()));
+    }
+    drop(engine);
+
+    let received: Vec<EngineEvent> = events.iter().collect();
+    assert_eq!(
+      received,
+      vec![
+        EngineEvent::AccountCreated { client_id: 1 },
+        EngineEvent::Deposited {
+          client_id: 1,
+          transaction_id: 101,
+          amount: dec!(10)
+        },
+        EngineEvent::DisputeOpened {
+          client_id: 1,
+          transaction_id: 101
+        },
+        EngineEvent::DisputeResolved {
+          client_id: 1,
+          transaction_id: 101
+        },
+        EngineEvent::Deposited {
+          client_id: 1,
+          transaction_id: 103,
+          amount: dec!(5)
+        },
+        EngineEvent::DisputeOpened {
+          client_id: 1,
+          transaction_id: 103
+        },
+        EngineEvent::ChargedBack {
+          client_id: 1,
+          transaction_id: 103
+        },
+        EngineEvent::Locked { client_id: 1 },
+      ]
+    );
+  }
+
+  #[tokio::test]
+  async fn process_notifies_the_exposures_exceeded() {
+    let (listener, events) = ChannelEventListener::new(64);
+    let config = EngineConfig {
+      exposure_threshold: Some(dec!(15)),
+      ..EngineConfig::default()
+    };
+    let mut engine = ListeningPaymentsEngine::new(
+      InMemoryPaymentsEngine::with_config(config),
+      Arc::new(listener),
+    );
+    let deposit = |transaction_id| Transaction::Deposit {
+      client_id: 1,
+      transaction_id,
+      amount: dec!(10),
+      timestamp: None,
+      sub_account: 0,
+    };
+    let dispute = |transaction_id| Transaction::Dispute {
+      client_id: 1,
+      transaction_id,
+      timestamp: None,
+    };
+
+    let transactions = vec![
+      deposit(101),
+      deposit(102),
+      dispute(101),
+      dispute(102),
+      Transaction::Resolve {
+        client_id: 1,
+        transaction_id: 101,
+        timestamp: None,
+      },
+    ];
+    for transaction in transactions {
+      assert_eq!(engine.process(transaction).await, Ok(()));
+    }
+    drop(engine);
+
+    let received: Vec<EngineEvent> = events.iter().collect();
+    // the exposure is only notified when it crosses the threshold, and not while it stays over it
+    assert_eq!(
+      received[3..],
+      [
+        EngineEvent::DisputeOpened {
+          client_id: 1,
+          transaction_id: 101
+        },
+        EngineEvent::ExposureExceeded {
+          client_id: 1,
+          exposure: dec!(20),
+          threshold: dec!(15)
+        },
+        EngineEvent::DisputeOpened {
+          client_id: 1,
+          transaction_id: 102
+        },
+        EngineEvent::DisputeResolved {
+          client_id: 1,
+          transaction_id: 101
+        },
+      ]
+    );
+  }
+
+  #[test]
+  fn serialize_events_with_stable_tags() {
+    let events = [
+      EngineEvent::Deposited {
+        client_id: 1,
+        transaction_id: 101,
+        amount: dec!(10.5),
+      },
+      EngineEvent::DisputeOpened {
+        client_id: 1,
+        transaction_id: 101,
+      },
+      EngineEvent::ExposureExceeded {
+        client_id: 1,
+        exposure: dec!(20),
+        threshold: dec!(15),
+      },
+      EngineEvent::Rejected {
+        client_id: 2,
+        transaction_id: None,
+        transaction_type: "unlock".to_string(),
+        amount: None,
+        error: "account_not_locked".to_string(),
+      },
+    ];
+
+    let serialized: Vec<String> = events
+      .iter()
+      .map(|event| serde_json::to_string(event).unwrap())
+      .collect();
+    assert_eq!(
+      serialized,
+      vec![
+        r#"{"event":"deposited","client_id":1,"transaction_id":101,"amount":"10.5"}"#,
+        r#"{"event":"dispute_opened","client_id":1,"transaction_id":101}"#,
+        r#"{"event":"exposure_exceeded","client_id":1,"exposure":"20","threshold":"15"}"#,
+        r#"{"event":"rejected","client_id":2,"transaction_id":null,"transaction_type":"unlock","amount":null,"error":"account_not_locked"}"#,
+      ]
+    );
+    for (event, serialized) in events.iter().zip(serialized.iter()) {
+      assert!(serialized.contains(&format!(r#""event":"{}""#, event.name())));
+      assert_eq!(
+        &serde_json::from_str::<EngineEvent>(serialized).unwrap(),
+        event
+      );
+    }
+  }
+}
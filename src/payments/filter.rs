@@ -0,0 +1,351 @@
+use std::ops::RangeInclusive;
+use std::str::FromStr;
+
+use anyhow::{anyhow, bail};
+#[cfg(feature = "async")]
+use async_trait::async_trait;
+use rust_decimal::Decimal;
+
+use super::{account::Account, transaction::ClientId};
+#[cfg(feature = "async")]
+use super::{
+  account::{AccountReport, TransactionInfo},
+  engine::{
+    AccountsReportIter, AccountsReportStream, PaymentsEngine, Result, SyncPaymentsEngine,
+    TransactionsReportIter,
+  },
+  transaction::{Transaction, TransactionId},
+};
+
+/// Predicates used to select a subset of the accounts from a payments engine.
+///
+/// All the predicates need to be satisfied for an account to be selected,
+/// and the default filter selects all the accounts.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct AccountFilter {
+  /// Select only the accounts that are locked.
+  pub locked_only: bool,
+  /// Select only the accounts with some transaction in dispute.
+  pub has_open_disputes: bool,
+  /// Select only the accounts with a total balance greater or equal than this.
+  pub min_total: Option<Decimal>,
+  /// Select only the accounts with a total balance lower or equal than this.
+  pub max_total: Option<Decimal>,
+  /// Select only the accounts with a client ID in this range.
+  pub client_ids: Option<RangeInclusive<ClientId>>,
+}
+
+impl AccountFilter {
+  /// Set the predicate of the parameter from its value as text, like they are given by the options of the command line
+  /// and the query of the HTTP API. The parameters are `locked_only`, `has_open_disputes` (`true` or `false`),
+  /// `min_total`, `max_total` and `client_ids`, which is an inclusive range like `1-100`, or a single client ID.
+  pub fn with_param(mut self, name: &str, value: &str) -> anyhow::Result<Self> {
+    match name {
+      "locked_only" => self.locked_only = value.parse()?,
+      "has_open_disputes" => self.has_open_disputes = value.parse()?,
+      "min_total" => self.min_total = Some(Decimal::from_str(value)?),
+      "max_total" => self.max_total = Some(Decimal::from_str(value)?),
+      "client_ids" => self.client_ids = Some(parse_client_ids(value)?),
+      _ => bail!("Unknown account filter: {}", name),
+    }
+    Ok(self)
+  }
+
+  /// Whether the account is selected. The total of an account that overflows is never within the bounds of the total,
+  /// so the account is only selected when there are none.
+  pub(crate) fn matches(&self, client_id: ClientId, account: &Account) -> bool {
+    let total = account.funds.available.checked_add(account.funds.held);
+    let total_at_least = |min_total| total.map_or(false, |total| total >= min_total);
+    let total_at_most = |max_total| total.map_or(false, |total| total <= max_total);
+    (!self.locked_only || account.locked)
+      && (!self.has_open_disputes || account.open_disputes > 0)
+      && self.min_total.map_or(true, total_at_least)
+      && self.max_total.map_or(true, total_at_most)
+      && self
+        .client_ids
+        .as_ref()
+        .map_or(true, |client_ids| client_ids.contains(&client_id))
+  }
+}
+
+fn parse_client_ids(value: &str) -> anyhow::Result<RangeInclusive<ClientId>> {
+  let invalid = || anyhow!("Invalid range of client IDs: {}", value);
+  match value.split_once('-') {
+    Some((start, end)) => {
+      let start = start.trim().parse().map_err(|_| invalid())?;
+      let end = end.trim().parse().map_err(|_| invalid())?;
+      Ok(start..=end)
+    }
+    None => {
+      let client_id = value.trim().parse().map_err(|_| invalid())?;
+      Ok(client_id..=client_id)
+    }
+  }
+}
+
+/// A [`PaymentsEngine`] middleware whose accounts report only contains the accounts selected by the [`AccountFilter`],
+/// so any processor writes the report of a subset of the accounts, evaluated by the inner engine.
+/// The rest of the operations are not affected by the filter.
+#[cfg(feature = "async")]
+pub struct FilteredPaymentsEngine<E> {
+  inner: E,
+  filter: AccountFilter,
+}
+
+#[cfg(feature = "async")]
+impl<E> FilteredPaymentsEngine<E>
+where
+  E: PaymentsEngine,
+{
+  pub fn new(inner: E, filter: AccountFilter) -> Self {
+    Self { inner, filter }
+  }
+}
+
+#[cfg(feature = "async")]
+#[async_trait]
+impl<E> PaymentsEngine for FilteredPaymentsEngine<E>
+where
+  E: PaymentsEngine + Send,
+{
+  async fn process(&mut self, transaction: Transaction) -> Result<()> {
+    self.inner.process(transaction).await
+  }
+
+  fn validate(&self, transaction: &Transaction) -> Result<()> {
+    self.inner.validate(transaction)
+  }
+
+  fn accounts_report(&self) -> AccountsReportIter {
+    self.inner.accounts_matching(self.filter.clone())
+  }
+
+  fn accounts_matching(&self, filter: AccountFilter) -> AccountsReportIter {
+    self.inner.accounts_matching(filter)
+  }
+
+  fn accounts_report_stream(&self) -> AccountsReportStream {
+    AccountsReportStream::from(self.accounts_report())
+  }
+
+  fn account(&self, client_id: ClientId) -> Option<AccountReport> {
+    self.inner.account(client_id)
+  }
+
+  fn transaction(
+    &self,
+    client_id: ClientId,
+    transaction_id: TransactionId,
+  ) -> Result<Option<TransactionInfo>> {
+    self.inner.transaction(client_id, transaction_id)
+  }
+
+  fn transactions_report(&self) -> Result<TransactionsReportIter> {
+    self.inner.transactions_report()
+  }
+}
+
+#[cfg(feature = "async")]
+impl<E> SyncPaymentsEngine for FilteredPaymentsEngine<E>
+where
+  E: SyncPaymentsEngine,
+{
+  fn process_sync(&mut self, transaction: Transaction) -> Result<()> {
+    self.inner.process_sync(transaction)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+
+  use rust_decimal_macros::dec;
+
+  use super::*;
+  use crate::payments::account::{Funds, TransactionState};
+  use crate::payments::{EngineConfig, InMemoryPaymentsEngine};
+
+  #[test]
+  fn default_matches_all() {
+    let filter = AccountFilter::default();
+
+    assert!(filter.matches(1, &Account::default()));
+    assert!(filter.matches(
+      2,
+      &Account {
+        locked: true,
+        ..Account::default()
+      }
+    ));
+  }
+
+  #[test]
+  fn matches_locked_only() {
+    let filter = AccountFilter {
+      locked_only: true,
+      ..AccountFilter::default()
+    };
+
+    assert!(!filter.matches(1, &Account::default()));
+    assert!(filter.matches(
+      1,
+      &Account {
+        locked: true,
+        ..Account::default()
+      }
+    ));
+  }
+
+  #[test]
+  fn matches_has_open_disputes() {
+    let filter = AccountFilter {
+      has_open_disputes: true,
+      ..AccountFilter::default()
+    };
+
+    assert!(!filter.matches(
+      1,
+      &Account {
+        transactions: vec![(101, TransactionState::from_amount(dec!(10)))]
+          .into_iter()
+          .collect(),
+        ..Account::default()
+      }
+    ));
+    assert!(filter.matches(
+      1,
+      &Account {
+        transactions: vec![(101, TransactionState::from_dispute(dec!(10)))]
+          .into_iter()
+          .collect(),
+        open_disputes: 1,
+        ..Account::default()
+      }
+    ));
+  }
+
+  #[test]
+  fn matches_total_range() {
+    let filter = AccountFilter {
+      min_total: Some(dec!(10)),
+      max_total: Some(dec!(20)),
+      ..AccountFilter::default()
+    };
+
+    let cases = vec![
+      (Funds::available(dec!(9.99)), false),
+      (Funds::available(dec!(10)), true),
+      (Funds::new(dec!(5), dec!(10)), true),
+      (Funds::available(dec!(20)), true),
+      (Funds::new(dec!(15), dec!(5.01)), false),
+    ];
+
+    for (funds, expected) in cases {
+      let account = Account {
+        funds,
+        ..Account::default()
+      };
+      assert_eq!(filter.matches(1, &account), expected);
+    }
+  }
+
+  #[test]
+  fn matches_client_ids() {
+    let filter = AccountFilter {
+      client_ids: Some(2..=3),
+      ..AccountFilter::default()
+    };
+
+    assert!(!filter.matches(1, &Account::default()));
+    assert!(filter.matches(2, &Account::default()));
+    assert!(filter.matches(3, &Account::default()));
+    assert!(!filter.matches(4, &Account::default()));
+  }
+
+  #[test]
+  fn matches_overflowing_total() {
+    let account = Account {
+      funds: Funds::new(Decimal::MAX, Decimal::MAX),
+      ..Account::default()
+    };
+
+    assert!(AccountFilter::default().matches(1, &account));
+    assert!(!AccountFilter {
+      min_total: Some(dec!(10)),
+      ..AccountFilter::default()
+    }
+    .matches(1, &account));
+  }
+
+  #[test]
+  fn with_params() {
+    let filter = AccountFilter::default()
+      .with_param("locked_only", "true")
+      .and_then(|filter| filter.with_param("has_open_disputes", "false"))
+      .and_then(|filter| filter.with_param("min_total", "10.5"))
+      .and_then(|filter| filter.with_param("max_total", "20"))
+      .and_then(|filter| filter.with_param("client_ids", "2 - 3"))
+      .unwrap();
+
+    assert_eq!(
+      filter,
+      AccountFilter {
+        locked_only: true,
+        has_open_disputes: false,
+        min_total: Some(dec!(10.5)),
+        max_total: Some(dec!(20)),
+        client_ids: Some(2..=3),
+      }
+    );
+    assert_eq!(
+      AccountFilter::default()
+        .with_param("client_ids", "7")
+        .unwrap()
+        .client_ids,
+      Some(7..=7)
+    );
+    assert!(AccountFilter::default()
+      .with_param("locked_only", "yes")
+      .is_err());
+    assert!(AccountFilter::default()
+      .with_param("client_ids", "1-x")
+      .is_err());
+    assert!(AccountFilter::default().with_param("unknown", "1").is_err());
+  }
+
+  #[cfg(feature = "async")]
+  #[tokio::test]
+  async fn filtered_engine_reports_the_selected_accounts() {
+    let mut engine = FilteredPaymentsEngine::new(
+      InMemoryPaymentsEngine::with_config(EngineConfig {
+        deterministic: true,
+        ..EngineConfig::default()
+      }),
+      AccountFilter {
+        min_total: Some(dec!(10)),
+        ..AccountFilter::default()
+      },
+    );
+    for (client_id, amount) in [(1, dec!(5)), (2, dec!(10)), (3, dec!(15))] {
+      let deposit = Transaction::Deposit {
+        client_id,
+        transaction_id: client_id.into(),
+        amount,
+        timestamp: None,
+        sub_account: 0,
+      };
+      engine.process(deposit).await.unwrap();
+    }
+
+    let client_ids = |report: AccountsReportIter| {
+      report
+        .map(|account_report| account_report.client_id)
+        .collect::<Vec<_>>()
+    };
+    assert_eq!(client_ids(engine.accounts_report()), vec![2, 3]);
+    assert_eq!(
+      client_ids(engine.accounts_matching(AccountFilter::default())),
+      vec![1, 2, 3]
+    );
+    assert!(engine.account(1).is_some());
+  }
+}
@@ -0,0 +1,1044 @@
+use std::collections::{HashMap, HashSet};
+
+use async_trait::async_trait;
+use rust_decimal::Decimal;
+
+use super::{
+  account::{AccountReport, Funds, SubAccountReport, TransactionInfo, TransactionKind},
+  config::{ChargebackFee, DuplicatePolicy, EngineConfig, UnlockHeldFundsPolicy},
+  engine::{
+    AccountsReportIter, AccountsReportStream, PaymentsEngine, Result, TransactionsReportIter,
+  },
+  filter::AccountFilter,
+  transaction::{ClientId, Transaction, TransactionId},
+};
+
+/// A [`PaymentsEngine`] middleware that checks the global invariants after every processed transaction,
+/// and panics with a detailed diff as soon as any of them is violated.
+///
+/// It compares the accounts report before and after every transaction with the one expected from the transaction semantics:
+/// - a rejected transaction doesn't change any account
+/// - deposits and withdrawals only change the available funds of the client by their amount
+/// - disputes and resolves move the amount of the original deposit between the available and held funds, keeping the total,
+///   while the ones of withdrawals and fees hold the amount to be refunded, without changing the available funds
+/// - chargebacks remove the amount of the original transaction from the held funds, refunding it into the available funds
+///   for withdrawals and fees, and lock the account (and charge the chargeback fee from the available funds, when configured,
+///   unless it is a fee being charged back), after which the disputes of the transaction refer to its fee
+/// - transfers move their amount from the available funds of the sender to the ones of the recipient
+/// - authorizations hold their amount, which captures make available and voids remove
+/// - unlocks only clear the lock, unless they release the funds held by the open disputes as resolves would do
+/// - the total is always the sum of the available and held funds, and the held funds are never negative
+/// - the funds of the sub-accounts, when they are reported, add up to the ones of the client
+/// - the funds of a locked account only change by disputing, resolving or charging back its transactions, voiding its authorizations,
+///   or unlocking it
+/// - the available funds only decrease below zero by disputing a deposit that was already spent, or by charging a chargeback fee
+/// - [`PaymentsEngine::validate`] predicts the same result that processing the transaction returns
+///
+/// This is expensive, as it generates the whole accounts report twice per transaction,
+/// so it is only meant to guard refactors of the engine semantics in tests and debug runs.
+/// It is also exported as [`DebugPaymentsEngine`], to wrap the engines in the tests of other crates.
+pub struct InvariantCheckingEngine<E> {
+  inner: E,
+  transactions: HashMap<(ClientId, TransactionId), (TransactionKind, Decimal)>,
+  disputed: HashSet<(ClientId, TransactionId)>,
+  chargeback_fee: Option<ChargebackFee>,
+  unlock_held_funds_policy: UnlockHeldFundsPolicy,
+  duplicate_policy: DuplicatePolicy,
+}
+
+/// The [`InvariantCheckingEngine`] under the name used to debug the engines in tests:
+///
+/// ```
+/// use toy_payments_engine::payments::{DebugPaymentsEngine, InMemoryPaymentsEngine};
+///
+/// let engine = DebugPaymentsEngine::new(InMemoryPaymentsEngine::new());
+/// ```
+pub type DebugPaymentsEngine<E> = InvariantCheckingEngine<E>;
+
+impl<E> InvariantCheckingEngine<E>
+where
+  E: PaymentsEngine,
+{
+  pub fn new(inner: E) -> Self {
+    Self {
+      inner,
+      transactions: HashMap::default(),
+      disputed: HashSet::default(),
+      chargeback_fee: None,
+      unlock_held_funds_policy: UnlockHeldFundsPolicy::default(),
+      duplicate_policy: DuplicatePolicy::default(),
+    }
+  }
+
+  /// Wrap an engine created with the configuration, expecting the chargeback fee and the policies that it configures.
+  pub fn with_config(inner: E, config: &EngineConfig) -> Self {
+    Self::new(inner)
+      .with_chargeback_fee(config.chargeback_fee)
+      .with_unlock_held_funds_policy(config.unlock_held_funds_policy)
+      .with_duplicate_policy(config.duplicate_policy)
+  }
+
+  /// The chargeback fee configured in the inner engine, so it is expected when charging back transactions.
+  pub fn with_chargeback_fee(mut self, chargeback_fee: Option<ChargebackFee>) -> Self {
+    self.chargeback_fee = chargeback_fee;
+    self
+  }
+
+  /// The unlock policy configured in the inner engine, so it is expected when unlocking accounts.
+  pub fn with_unlock_held_funds_policy(
+    mut self,
+    unlock_held_funds_policy: UnlockHeldFundsPolicy,
+  ) -> Self {
+    self.unlock_held_funds_policy = unlock_held_funds_policy;
+    self
+  }
+
+  /// The duplicate policy configured in the inner engine, so the retried resolves and chargebacks are expected to change nothing.
+  pub fn with_duplicate_policy(mut self, duplicate_policy: DuplicatePolicy) -> Self {
+    self.duplicate_policy = duplicate_policy;
+    self
+  }
+
+  /// Whether an accepted resolve or chargeback of the transaction is a retry of one already applied.
+  fn is_retried(&self, client_id: ClientId, transaction_id: TransactionId) -> bool {
+    self.duplicate_policy == DuplicatePolicy::Idempotent
+      && !self.disputed.contains(&(client_id, transaction_id))
+  }
+
+  fn snapshot(&self) -> HashMap<ClientId, AccountReport> {
+    self
+      .inner
+      .accounts_report()
+      .map(|account_report| (account_report.client_id, account_report))
+      .collect()
+  }
+
+  fn disputed_transaction(
+    &self,
+    client_id: ClientId,
+    transaction_id: TransactionId,
+  ) -> (TransactionKind, Decimal) {
+    *self
+      .transactions
+      .get(&(client_id, transaction_id))
+      .unwrap_or_else(|| {
+        panic!(
+          "Invariant violated: transaction {} for client {} was disputed without being processed",
+          transaction_id, client_id
+        )
+      })
+  }
+
+  fn authorized_amount(&self, client_id: ClientId, transaction_id: TransactionId) -> Decimal {
+    match self.transactions.get(&(client_id, transaction_id)) {
+      Some((TransactionKind::Authorization, amount)) => *amount,
+      _ => panic!(
+        "Invariant violated: authorization {} for client {} was settled without being processed",
+        transaction_id, client_id
+      ),
+    }
+  }
+
+  fn apply_expected(
+    &mut self,
+    accounts: &mut HashMap<ClientId, AccountReport>,
+    transaction: &Transaction,
+  ) {
+    match *transaction {
+      Transaction::Deposit {
+        client_id,
+        transaction_id,
+        amount,
+        ..
+      } => {
+        self.transactions.insert(
+          (client_id, transaction_id),
+          (TransactionKind::Deposit, amount),
+        );
+        let account = get_or_create_account(accounts, client_id);
+        account.available += amount;
+        account.total += amount;
+      }
+      Transaction::Withdrawal {
+        client_id,
+        transaction_id,
+        amount,
+        ..
+      } => {
+        self.transactions.insert(
+          (client_id, transaction_id),
+          (TransactionKind::Withdrawal, amount),
+        );
+        let account = get_or_create_account(accounts, client_id);
+        account.available -= amount;
+        account.total -= amount;
+      }
+      Transaction::Dispute {
+        client_id,
+        transaction_id,
+        ..
+      } => {
+        let (kind, amount) = self.disputed_transaction(client_id, transaction_id);
+        self.disputed.insert((client_id, transaction_id));
+        let account = get_or_create_account(accounts, client_id);
+        update_funds(account, |funds| funds.hold(kind, amount));
+        account.open_disputes += 1;
+      }
+      Transaction::Resolve {
+        client_id,
+        transaction_id,
+        ..
+      } if self.is_retried(client_id, transaction_id) => {}
+      Transaction::Resolve {
+        client_id,
+        transaction_id,
+        ..
+      } => {
+        let (kind, amount) = self.disputed_transaction(client_id, transaction_id);
+        self.disputed.remove(&(client_id, transaction_id));
+        let account = get_or_create_account(accounts, client_id);
+        update_funds(account, |funds| funds.release(kind, amount));
+        account.open_disputes -= 1;
+      }
+      Transaction::Chargeback {
+        client_id,
+        transaction_id,
+        ..
+      } if self.is_retried(client_id, transaction_id) => {}
+      Transaction::Chargeback {
+        client_id,
+        transaction_id,
+        ..
+      } => {
+        let (kind, amount) = self.disputed_transaction(client_id, transaction_id);
+        self.disputed.remove(&(client_id, transaction_id));
+        // charging back a fee doesn't charge another one
+        let chargeback_fee = match kind {
+          TransactionKind::Fee => None,
+          _ => self.chargeback_fee,
+        };
+        let account = get_or_create_account(accounts, client_id);
+        update_funds(account, |funds| funds.charge_back(kind, amount));
+        if kind == TransactionKind::Fee {
+          account.fees -= amount;
+        }
+        let fee = chargeback_fee.map_or(Decimal::ZERO, |fee| fee.charge(account.available));
+        account.total -= fee;
+        account.available -= fee;
+        account.fees += fee;
+        account.locked = true;
+        account.open_disputes -= 1;
+        account.charged_back_total += amount;
+        if !fee.is_zero() {
+          // the disputes of the transaction charged back refer to its fee from now on
+          self
+            .transactions
+            .insert((client_id, transaction_id), (TransactionKind::Fee, fee));
+        }
+      }
+      Transaction::Transfer {
+        from_client,
+        to_client,
+        transaction_id,
+        amount,
+        ..
+      } => {
+        self.transactions.insert(
+          (from_client, transaction_id),
+          (TransactionKind::Transfer, amount),
+        );
+        let from_account = get_or_create_account(accounts, from_client);
+        from_account.available -= amount;
+        from_account.total -= amount;
+        let to_account = get_or_create_account(accounts, to_client);
+        to_account.available += amount;
+        to_account.total += amount;
+      }
+      Transaction::Unlock { client_id } => {
+        let mut released = Vec::new();
+        if self.unlock_held_funds_policy == UnlockHeldFundsPolicy::Release {
+          let transactions = &self.transactions;
+          self
+            .disputed
+            .retain(|&(disputed_client_id, transaction_id)| {
+              if disputed_client_id == client_id {
+                released.push(transactions[&(client_id, transaction_id)]);
+              }
+              disputed_client_id != client_id
+            });
+        }
+        let account = get_or_create_account(accounts, client_id);
+        for (kind, amount) in released {
+          update_funds(account, |funds| funds.release(kind, amount));
+          account.open_disputes -= 1;
+        }
+        account.locked = false;
+      }
+      // the dormancy is not modeled, as it doesn't change the funds
+      Transaction::Reactivate { .. } => {}
+      Transaction::Authorize {
+        client_id,
+        transaction_id,
+        amount,
+        ..
+      } => {
+        self.transactions.insert(
+          (client_id, transaction_id),
+          (TransactionKind::Authorization, amount),
+        );
+        let account = get_or_create_account(accounts, client_id);
+        update_funds(account, |funds| funds.authorize(amount));
+      }
+      Transaction::Capture {
+        client_id,
+        transaction_id,
+        ..
+      } => {
+        let amount = self.authorized_amount(client_id, transaction_id);
+        self.transactions.insert(
+          (client_id, transaction_id),
+          (TransactionKind::Deposit, amount),
+        );
+        let account = get_or_create_account(accounts, client_id);
+        update_funds(account, |funds| funds.capture(amount));
+      }
+      Transaction::Void {
+        client_id,
+        transaction_id,
+        ..
+      } => {
+        let amount = self.authorized_amount(client_id, transaction_id);
+        self.transactions.insert(
+          (client_id, transaction_id),
+          (TransactionKind::VoidedAuthorization, amount),
+        );
+        let account = get_or_create_account(accounts, client_id);
+        update_funds(account, |funds| funds.void(amount));
+      }
+    }
+  }
+}
+
+fn get_or_create_account(
+  accounts: &mut HashMap<ClientId, AccountReport>,
+  client_id: ClientId,
+) -> &mut AccountReport {
+  accounts.entry(client_id).or_insert_with(|| {
+    AccountReport::new(
+      client_id,
+      Decimal::ZERO,
+      Decimal::ZERO,
+      Decimal::ZERO,
+      false,
+    )
+  })
+}
+
+/// Apply the expected change of the funds of a dispute, keeping the total as their sum.
+fn update_funds<F>(account: &mut AccountReport, f: F)
+where
+  F: FnOnce(&mut Funds) -> Option<()>,
+{
+  let mut funds = Funds {
+    available: account.available,
+    held: account.held,
+  };
+  f(&mut funds).unwrap_or_else(|| {
+    panic!(
+      "Invariant violated: the expected funds of client {} overflowed",
+      account.client_id
+    )
+  });
+  account.available = funds.available;
+  account.held = funds.held;
+  account.total = funds.available + funds.held;
+}
+
+/// The report of the client without the funds of its sub-accounts, which are not expected from the transactions,
+/// but only checked to add up to the ones of the client.
+fn client_funds(account: &AccountReport) -> AccountReport {
+  account.clone().with_sub_accounts(Vec::new())
+}
+
+fn sub_accounts_add_up(account: &AccountReport) -> bool {
+  let sum = |funds: fn(&SubAccountReport) -> Decimal| {
+    account
+      .sub_accounts
+      .iter()
+      .try_fold(Decimal::ZERO, |sum, sub_account| {
+        sum.checked_add(funds(sub_account))
+      })
+  };
+  account.sub_accounts.is_empty()
+    || (sum(|sub_account| sub_account.available) == Some(account.available)
+      && sum(|sub_account| sub_account.held) == Some(account.held))
+}
+
+/// Describe the differences between the expected and the actual accounts, sorted by client ID.
+fn diff(
+  expected: &HashMap<ClientId, AccountReport>,
+  actual: &HashMap<ClientId, AccountReport>,
+) -> Vec<String> {
+  let mut client_ids: Vec<ClientId> = expected.keys().chain(actual.keys()).copied().collect();
+  client_ids.sort_unstable();
+  client_ids.dedup();
+
+  client_ids
+    .into_iter()
+    .filter_map(|client_id| {
+      let expected_account = expected.get(&client_id);
+      let actual_account = actual.get(&client_id);
+      let inconsistent = actual_account.map_or(false, |account| {
+        account.total != account.available + account.held
+          || account.held < Decimal::ZERO
+          || !sub_accounts_add_up(account)
+      });
+      if expected_account.map(client_funds) != actual_account.map(client_funds) || inconsistent {
+        Some(format!(
+          "client {}: expected {:?}, found {:?}",
+          client_id, expected_account, actual_account
+        ))
+      } else {
+        None
+      }
+    })
+    .collect()
+}
+
+/// Describe the changes of the accounts that no transaction is allowed to make, whatever the model of the expected accounts says:
+/// changing the funds of a locked account other than by its disputes, voids or unlocking it, and decreasing the available funds
+/// below zero other than by a dispute or a chargeback fee.
+fn forbidden_changes(
+  before: &HashMap<ClientId, AccountReport>,
+  after: &HashMap<ClientId, AccountReport>,
+  transaction: &Transaction,
+  charges_fee: bool,
+) -> Vec<String> {
+  let mut client_ids: Vec<ClientId> = after.keys().copied().collect();
+  client_ids.sort_unstable();
+
+  let mut changes = Vec::new();
+  for client_id in client_ids {
+    let account = &after[&client_id];
+    let previous = before.get(&client_id);
+    let own_transaction = transaction.client_id() == client_id;
+
+    let locked_changed = previous.map_or(false, |previous| {
+      previous.locked
+        && (previous.available != account.available
+          || previous.held != account.held
+          || previous.locked != account.locked)
+    });
+    let changes_locked = own_transaction
+      && matches!(
+        transaction,
+        Transaction::Dispute { .. }
+          | Transaction::Resolve { .. }
+          | Transaction::Chargeback { .. }
+          | Transaction::Void { .. }
+          | Transaction::Unlock { .. }
+      );
+    if locked_changed && !changes_locked {
+      changes.push(format!(
+        "client {}: the locked account changed from {:?} to {:?}",
+        client_id, previous, account
+      ));
+    }
+
+    let previous_available = previous.map_or(Decimal::ZERO, |previous| previous.available);
+    let became_negative =
+      account.available < Decimal::ZERO && account.available < previous_available;
+    let makes_negative = own_transaction
+      && match transaction {
+        Transaction::Dispute { .. } => true,
+        Transaction::Chargeback { .. } => charges_fee,
+        _ => false,
+      };
+    if became_negative && !makes_negative {
+      changes.push(format!(
+        "client {}: the available funds decreased from {} to {}",
+        client_id, previous_available, account.available
+      ));
+    }
+  }
+  changes
+}
+
+#[async_trait]
+impl<E> PaymentsEngine for InvariantCheckingEngine<E>
+where
+  E: PaymentsEngine + Send,
+{
+  async fn process(&mut self, transaction: Transaction) -> Result<()> {
+    let prediction = self.inner.validate(&transaction);
+    let before = self.snapshot();
+    let mut expected = before.clone();
+    let result = self.inner.process(transaction.clone()).await;
+    if prediction != result {
+      panic!(
+        "Invariant violated: the validation of {:?} predicted {:?} but processing it returned {:?}",
+        transaction, prediction, result
+      );
+    }
+    if result.is_ok() {
+      self.apply_expected(&mut expected, &transaction);
+    }
+
+    let actual = self.snapshot();
+    let mut differences = diff(&expected, &actual);
+    differences.extend(forbidden_changes(
+      &before,
+      &actual,
+      &transaction,
+      self.chargeback_fee.is_some(),
+    ));
+    if !differences.is_empty() {
+      panic!(
+        "Invariant violated after processing {:?} with result {:?}:\n{}",
+        transaction,
+        result,
+        differences.join("\n")
+      );
+    }
+
+    result
+  }
+
+  fn validate(&self, transaction: &Transaction) -> Result<()> {
+    self.inner.validate(transaction)
+  }
+
+  fn accounts_report(&self) -> AccountsReportIter {
+    self.inner.accounts_report()
+  }
+
+  fn accounts_matching(&self, filter: AccountFilter) -> AccountsReportIter {
+    self.inner.accounts_matching(filter)
+  }
+
+  fn accounts_report_stream(&self) -> AccountsReportStream {
+    self.inner.accounts_report_stream()
+  }
+
+  fn account(&self, client_id: ClientId) -> Option<AccountReport> {
+    self.inner.account(client_id)
+  }
+
+  fn transaction(
+    &self,
+    client_id: ClientId,
+    transaction_id: TransactionId,
+  ) -> Result<Option<TransactionInfo>> {
+    self.inner.transaction(client_id, transaction_id)
+  }
+
+  fn transactions_report(&self) -> Result<TransactionsReportIter> {
+    self.inner.transactions_report()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+
+  use rust_decimal_macros::dec;
+
+  use super::*;
+  use crate::payments::{
+    EngineConfig, InMemoryPaymentsEngine, LockedAccountDisputePolicy, PaymentsEngineError,
+    SyntheticDisputePolicy,
+  };
+
+  #[tokio::test]
+  async fn process_keeps_invariants() {
+    let mut engine = InvariantCheckingEngine::new(InMemoryPaymentsEngine::new());
+
+    let transactions = vec![
+      (
+        Transaction::Deposit {
+          client_id: 1,
+          transaction_id: 101,
+          amount: dec!(100),
+          timestamp: None,
+          sub_account: 0,
+        },
+        Ok(()),
+      ),
+      (
+        Transaction::Withdrawal {
+          client_id: 1,
+          transaction_id: 102,
+          amount: dec!(200),
+          timestamp: None,
+          sub_account: 0,
+        },
+        Err(PaymentsEngineError::NotEnoughAvailableFunds),
+      ),
+      (
+        Transaction::Deposit {
+          client_id: 1,
+          transaction_id: 103,
+          amount: dec!(10),
+          timestamp: None,
+          sub_account: 0,
+        },
+        Ok(()),
+      ),
+      (
+        Transaction::Withdrawal {
+          client_id: 1,
+          transaction_id: 104,
+          amount: dec!(5),
+          timestamp: None,
+          sub_account: 0,
+        },
+        Ok(()),
+      ),
+      (
+        Transaction::Transfer {
+          from_client: 1,
+          to_client: 2,
+          transaction_id: 105,
+          amount: dec!(200),
+          timestamp: None,
+        },
+        Err(PaymentsEngineError::NotEnoughAvailableFunds),
+      ),
+      (
+        Transaction::Dispute {
+          client_id: 1,
+          transaction_id: 104,
+          timestamp: None,
+        },
+        Ok(()),
+      ),
+      (
+        Transaction::Resolve {
+          client_id: 1,
+          transaction_id: 104,
+          timestamp: None,
+        },
+        Ok(()),
+      ),
+      (
+        Transaction::Dispute {
+          client_id: 1,
+          transaction_id: 103,
+          timestamp: None,
+        },
+        Ok(()),
+      ),
+      (
+        Transaction::Resolve {
+          client_id: 1,
+          transaction_id: 103,
+          timestamp: None,
+        },
+        Ok(()),
+      ),
+      (
+        Transaction::Dispute {
+          client_id: 1,
+          transaction_id: 103,
+          timestamp: None,
+        },
+        Ok(()),
+      ),
+      (
+        Transaction::Chargeback {
+          client_id: 1,
+          transaction_id: 103,
+          timestamp: None,
+        },
+        Ok(()),
+      ),
+    ];
+
+    for (transaction, expected) in transactions {
+      assert_eq!(engine.process(transaction).await, expected);
+    }
+
+    let report: Vec<AccountReport> = engine.accounts_report().collect();
+    assert_eq!(
+      report,
+      vec![AccountReport::new(1, dec!(95), dec!(0), dec!(95), true).with_disputes(0, dec!(10))]
+    );
+  }
+
+  #[tokio::test]
+  async fn process_keeps_invariants_with_authorizations() {
+    let mut engine = InvariantCheckingEngine::new(InMemoryPaymentsEngine::new());
+
+    let transactions = vec![
+      Transaction::Authorize {
+        client_id: 1,
+        transaction_id: 101,
+        amount: dec!(100),
+        timestamp: None,
+        sub_account: 1,
+      },
+      Transaction::Authorize {
+        client_id: 1,
+        transaction_id: 102,
+        amount: dec!(30),
+        timestamp: None,
+        sub_account: 0,
+      },
+      Transaction::Capture {
+        client_id: 1,
+        transaction_id: 101,
+        timestamp: None,
+      },
+      Transaction::Void {
+        client_id: 1,
+        transaction_id: 102,
+        timestamp: None,
+      },
+      Transaction::Dispute {
+        client_id: 1,
+        transaction_id: 101,
+        timestamp: None,
+      },
+    ];
+
+    for transaction in transactions {
+      assert_eq!(engine.process(transaction).await, Ok(()));
+    }
+
+    let report: Vec<AccountReport> = engine.accounts_report().collect();
+    assert_eq!(
+      report,
+      vec![AccountReport::new(1, dec!(0), dec!(100), dec!(100), false)
+        .with_disputes(1, dec!(0))
+        .with_sub_accounts(vec![
+          SubAccountReport {
+            sub_account: 0,
+            available: dec!(0),
+            held: dec!(0),
+            total: dec!(0),
+          },
+          SubAccountReport {
+            sub_account: 1,
+            available: dec!(0),
+            held: dec!(100),
+            total: dec!(100),
+          },
+        ])]
+    );
+  }
+
+  #[tokio::test]
+  async fn process_keeps_invariants_with_chargeback_fee() {
+    let chargeback_fee = Some(ChargebackFee {
+      amount: dec!(15),
+      allow_negative_available: false,
+    });
+    let mut engine =
+      InvariantCheckingEngine::new(InMemoryPaymentsEngine::with_config(EngineConfig {
+        chargeback_fee,
+        ..EngineConfig::default()
+      }))
+      .with_chargeback_fee(chargeback_fee);
+
+    let transactions = vec![
+      Transaction::Deposit {
+        client_id: 1,
+        transaction_id: 101,
+        amount: dec!(100),
+        timestamp: None,
+        sub_account: 0,
+      },
+      Transaction::Deposit {
+        client_id: 1,
+        transaction_id: 102,
+        amount: dec!(10),
+        timestamp: None,
+        sub_account: 0,
+      },
+      Transaction::Dispute {
+        client_id: 1,
+        transaction_id: 101,
+        timestamp: None,
+      },
+      Transaction::Chargeback {
+        client_id: 1,
+        transaction_id: 101,
+        timestamp: None,
+      },
+    ];
+
+    for transaction in transactions {
+      assert_eq!(engine.process(transaction).await, Ok(()));
+    }
+
+    let report: Vec<AccountReport> = engine.accounts_report().collect();
+    assert_eq!(
+      report,
+      vec![AccountReport::new(1, dec!(0), dec!(0), dec!(0), true)
+        .with_disputes(0, dec!(100))
+        .with_fees(dec!(10))]
+    );
+  }
+
+  #[tokio::test]
+  async fn process_keeps_invariants_disputing_the_chargeback_fee() {
+    let config = EngineConfig {
+      chargeback_fee: Some(ChargebackFee {
+        amount: dec!(15),
+        allow_negative_available: false,
+      }),
+      locked_account_dispute_policy: LockedAccountDisputePolicy::Allow,
+      synthetic_dispute_policy: SyntheticDisputePolicy::Allow,
+      ..EngineConfig::default()
+    };
+    let mut engine = InvariantCheckingEngine::with_config(
+      InMemoryPaymentsEngine::with_config(config.clone()),
+      &config,
+    );
+
+    let transactions = vec![
+      Transaction::Deposit {
+        client_id: 1,
+        transaction_id: 101,
+        amount: dec!(100),
+        timestamp: None,
+        sub_account: 0,
+      },
+      Transaction::Deposit {
+        client_id: 1,
+        transaction_id: 102,
+        amount: dec!(50),
+        timestamp: None,
+        sub_account: 0,
+      },
+      Transaction::Dispute {
+        client_id: 1,
+        transaction_id: 101,
+        timestamp: None,
+      },
+      Transaction::Chargeback {
+        client_id: 1,
+        transaction_id: 101,
+        timestamp: None,
+      },
+      Transaction::Dispute {
+        client_id: 1,
+        transaction_id: 101,
+        timestamp: None,
+      },
+      Transaction::Chargeback {
+        client_id: 1,
+        transaction_id: 101,
+        timestamp: None,
+      },
+    ];
+
+    for transaction in transactions {
+      assert_eq!(engine.process(transaction).await, Ok(()));
+    }
+
+    let report: Vec<AccountReport> = engine.accounts_report().collect();
+    assert_eq!(
+      report,
+      vec![AccountReport::new(1, dec!(50), dec!(0), dec!(50), true)
+        .with_disputes(0, dec!(115))
+        .with_fees(dec!(0))]
+    );
+  }
+
+  #[tokio::test]
+  async fn process_keeps_invariants_with_unlock() {
+    let mut engine =
+      InvariantCheckingEngine::new(InMemoryPaymentsEngine::with_config(EngineConfig {
+        unlock_held_funds_policy: UnlockHeldFundsPolicy::Release,
+        ..EngineConfig::default()
+      }))
+      .with_unlock_held_funds_policy(UnlockHeldFundsPolicy::Release);
+
+    let transactions = vec![
+      (
+        Transaction::Unlock { client_id: 1 },
+        Err(PaymentsEngineError::ClientNotFound(1)),
+      ),
+      (
+        Transaction::Deposit {
+          client_id: 1,
+          transaction_id: 101,
+          amount: dec!(100),
+          timestamp: None,
+          sub_account: 0,
+        },
+        Ok(()),
+      ),
+      (
+        Transaction::Deposit {
+          client_id: 1,
+          transaction_id: 102,
+          amount: dec!(10),
+          timestamp: None,
+          sub_account: 0,
+        },
+        Ok(()),
+      ),
+      (
+        Transaction::Withdrawal {
+          client_id: 1,
+          transaction_id: 103,
+          amount: dec!(5),
+          timestamp: None,
+          sub_account: 0,
+        },
+        Ok(()),
+      ),
+      (
+        Transaction::Dispute {
+          client_id: 1,
+          transaction_id: 102,
+          timestamp: None,
+        },
+        Ok(()),
+      ),
+      (
+        Transaction::Dispute {
+          client_id: 1,
+          transaction_id: 103,
+          timestamp: None,
+        },
+        Ok(()),
+      ),
+      (
+        Transaction::Chargeback {
+          client_id: 1,
+          transaction_id: 102,
+          timestamp: None,
+        },
+        Ok(()),
+      ),
+      (Transaction::Unlock { client_id: 1 }, Ok(())),
+      (
+        Transaction::Unlock { client_id: 1 },
+        Err(PaymentsEngineError::AccountNotLocked(1)),
+      ),
+      (
+        Transaction::Deposit {
+          client_id: 1,
+          transaction_id: 104,
+          amount: dec!(5),
+          timestamp: None,
+          sub_account: 0,
+        },
+        Ok(()),
+      ),
+    ];
+
+    for (transaction, expected_result) in transactions {
+      assert_eq!(engine.process(transaction).await, expected_result);
+    }
+
+    let report: Vec<AccountReport> = engine.accounts_report().collect();
+    assert_eq!(
+      report,
+      vec![AccountReport::new(1, dec!(100), dec!(0), dec!(100), false).with_disputes(0, dec!(10))]
+    );
+  }
+
+  /// An engine that accepts withdrawals without changing the funds
+  struct WrongWithdrawalsEngine(InMemoryPaymentsEngine);
+
+  #[async_trait]
+  impl PaymentsEngine for WrongWithdrawalsEngine {
+    async fn process(&mut self, transaction: Transaction) -> Result<()> {
+      match transaction {
+        Transaction::Withdrawal { .. } => Ok(()),
+        _ => self.0.process(transaction).await,
+      }
+    }
+
+    fn validate(&self, transaction: &Transaction) -> Result<()> {
+      self.0.validate(transaction)
+    }
+
+    fn accounts_report(&self) -> AccountsReportIter {
+      self.0.accounts_report()
+    }
+
+    fn accounts_matching(&self, filter: AccountFilter) -> AccountsReportIter {
+      self.0.accounts_matching(filter)
+    }
+
+    fn account(&self, client_id: ClientId) -> Option<AccountReport> {
+      self.0.account(client_id)
+    }
+
+    fn transaction(
+      &self,
+      client_id: ClientId,
+      transaction_id: TransactionId,
+    ) -> Result<Option<TransactionInfo>> {
+      self.0.transaction(client_id, transaction_id)
+    }
+
+    fn transactions_report(&self) -> Result<TransactionsReportIter> {
+      self.0.transactions_report()
+    }
+  }
+
+  #[tokio::test]
+  #[should_panic(expected = "Invariant violated")]
+  async fn process_panics_on_violated_invariants() {
+    let mut engine =
+      InvariantCheckingEngine::new(WrongWithdrawalsEngine(InMemoryPaymentsEngine::new()));
+
+    let deposit = Transaction::Deposit {
+      client_id: 1,
+      transaction_id: 101,
+      amount: dec!(100),
+      timestamp: None,
+      sub_account: 0,
+    };
+    let withdrawal = Transaction::Withdrawal {
+      client_id: 1,
+      transaction_id: 102,
+      amount: dec!(20),
+      timestamp: None,
+      sub_account: 0,
+    };
+
+    engine.process(deposit).await.ok();
+    engine.process(withdrawal).await.ok();
+  }
+
+  #[test]
+  fn forbidden_changes_of_locked_and_negative_accounts() {
+    let accounts = |account_report: AccountReport| {
+      let mut accounts = HashMap::new();
+      accounts.insert(account_report.client_id, account_report);
+      accounts
+    };
+    let locked = accounts(AccountReport::new(1, dec!(10), dec!(0), dec!(10), true));
+    let deposited = accounts(AccountReport::new(1, dec!(15), dec!(0), dec!(15), true));
+    let spent = accounts(AccountReport::new(1, dec!(-10), dec!(20), dec!(10), false));
+    let deposit = Transaction::Deposit {
+      client_id: 1,
+      transaction_id: 101,
+      amount: dec!(5),
+      timestamp: None,
+      sub_account: 0,
+    };
+    let dispute = Transaction::Dispute {
+      client_id: 1,
+      transaction_id: 101,
+      timestamp: None,
+    };
+
+    assert_eq!(
+      forbidden_changes(&locked, &deposited, &deposit, false).len(),
+      1
+    );
+    assert!(forbidden_changes(&locked, &locked, &deposit, false).is_empty());
+    assert!(forbidden_changes(&HashMap::new(), &spent, &dispute, false).is_empty());
+    assert_eq!(
+      forbidden_changes(&HashMap::new(), &spent, &deposit, false).len(),
+      1
+    );
+  }
+}
@@ -0,0 +1,177 @@
+use std::collections::VecDeque;
+use std::path::Path;
+
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+
+const SECONDS_PER_MINUTE: u64 = 60;
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+
+/// Limits on the withdrawals of every client, enforced by the [`InMemoryPaymentsEngine`](super::InMemoryPaymentsEngine)
+/// after the rest of the checks of a withdrawal, or of a transfer, which counts as a withdrawal of the sender. They can be loaded from a TOML file like:
+///
+/// ```toml
+/// max_withdrawal = 1000
+/// max_daily_withdrawals = "5000.50"
+/// max_transactions_per_minute = 10
+/// ```
+///
+/// The daily and per minute limits are rolling windows over the timestamps of the transactions,
+/// so they are only enforced on the withdrawals with a timestamp, and only count the transactions with one.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LimitsPolicy {
+  /// Maximum amount of a single withdrawal.
+  pub max_withdrawal: Option<Decimal>,
+
+  /// Maximum amount withdrawn by a client in the 24 hours up to a withdrawal, including it.
+  pub max_daily_withdrawals: Option<Decimal>,
+
+  /// Maximum number of transactions of a client (deposits, withdrawals, transfers and disputes) in the minute up to a withdrawal, including it.
+  pub max_transactions_per_minute: Option<usize>,
+}
+
+impl LimitsPolicy {
+  pub fn from_toml(content: &str) -> anyhow::Result<Self> {
+    toml::from_str(content).map_err(anyhow::Error::from)
+  }
+
+  pub fn load<P: AsRef<Path>>(path: P) -> anyhow::Result<Self> {
+    Self::from_toml(&std::fs::read_to_string(path)?)
+  }
+
+  /// Whether the recent activity of the clients needs to be tracked to enforce the limits.
+  pub(crate) fn tracks_activity(&self) -> bool {
+    self.max_daily_withdrawals.is_some() || self.max_transactions_per_minute.is_some()
+  }
+}
+
+/// The transactions of a client inside the windows of the [`LimitsPolicy`], only tracked when it has any of them,
+/// and the last time the client was active, only tracked for the [`DormancyPolicy`](super::DormancyPolicy).
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub(crate) struct RecentActivity {
+  /// The timestamps and amounts of the withdrawals in the last day.
+  withdrawals: VecDeque<(u64, Decimal)>,
+  /// The timestamps of the transactions in the last minute.
+  transactions: VecDeque<u64>,
+  /// The time of the last transaction accepted while the account was active.
+  #[serde(default)]
+  last_active: Option<u64>,
+}
+
+impl RecentActivity {
+  /// Record an accepted transaction, forgetting the ones that fell out of the windows.
+  /// Timestamps out of order are counted as long as they are inside the windows.
+  pub(crate) fn record(&mut self, timestamp: u64, withdrawal: Option<Decimal>) {
+    let expired = |instant: u64, seconds: u64| instant.saturating_add(seconds) <= timestamp;
+    while matches!(self.withdrawals.front(), Some((withdrawn_at, _)) if expired(*withdrawn_at, SECONDS_PER_DAY))
+    {
+      self.withdrawals.pop_front();
+    }
+    while matches!(self.transactions.front(), Some(processed_at) if expired(*processed_at, SECONDS_PER_MINUTE))
+    {
+      self.transactions.pop_front();
+    }
+    if let Some(amount) = withdrawal {
+      self.withdrawals.push_back((timestamp, amount));
+    }
+    self.transactions.push_back(timestamp);
+  }
+
+  /// Record that the client was active at the time, which never goes back.
+  pub(crate) fn touch(&mut self, now: u64) {
+    self.last_active = Some(
+      self
+        .last_active
+        .map_or(now, |last_active| last_active.max(now)),
+    );
+  }
+
+  /// Whether the client was not active in the given days up to the time, which it can't be without any activity.
+  pub(crate) fn dormant(&self, now: u64, days: u64) -> bool {
+    self.last_active.map_or(false, |last_active| {
+      last_active.saturating_add(days.saturating_mul(SECONDS_PER_DAY)) <= now
+    })
+  }
+
+  /// The amount withdrawn in the day up to the timestamp.
+  pub(crate) fn daily_withdrawals(&self, timestamp: u64) -> Decimal {
+    self
+      .withdrawals
+      .iter()
+      .filter(|(withdrawn_at, _)| within(*withdrawn_at, timestamp, SECONDS_PER_DAY))
+      .map(|(_, amount)| *amount)
+      .sum()
+  }
+
+  /// The number of transactions in the minute up to the timestamp.
+  pub(crate) fn transactions_per_minute(&self, timestamp: u64) -> usize {
+    self
+      .transactions
+      .iter()
+      .filter(|processed_at| within(**processed_at, timestamp, SECONDS_PER_MINUTE))
+      .count()
+  }
+}
+
+/// Whether the instant is inside the window of the given seconds that ends at the timestamp.
+fn within(instant: u64, timestamp: u64, seconds: u64) -> bool {
+  instant <= timestamp && instant.saturating_add(seconds) > timestamp
+}
+
+#[cfg(test)]
+mod tests {
+
+  use indoc::indoc;
+  use rust_decimal_macros::dec;
+
+  use super::*;
+
+  #[test]
+  fn load_limits_from_toml() {
+    let limits = LimitsPolicy::from_toml(indoc! {r#"
+      max_withdrawal = "1000.50"
+      max_daily_withdrawals = 5000
+      max_transactions_per_minute = 10
+    "#})
+    .unwrap();
+
+    assert_eq!(
+      limits,
+      LimitsPolicy {
+        max_withdrawal: Some(dec!(1000.50)),
+        max_daily_withdrawals: Some(dec!(5000)),
+        max_transactions_per_minute: Some(10),
+      }
+    );
+    assert!(LimitsPolicy::from_toml("max_withdrawals = \"10\"").is_err());
+  }
+
+  #[test]
+  fn recent_activity_windows() {
+    let mut activity = RecentActivity::default();
+    activity.record(1_000, Some(dec!(10)));
+    activity.record(1_030, None);
+    activity.record(1_059, Some(dec!(5)));
+
+    assert_eq!(activity.transactions_per_minute(1_059), 3);
+    assert_eq!(activity.transactions_per_minute(1_060), 2);
+    assert_eq!(activity.daily_withdrawals(1_000 + SECONDS_PER_DAY), dec!(5));
+
+    activity.record(1_000 + SECONDS_PER_DAY, Some(dec!(1)));
+    assert_eq!(activity.withdrawals.len(), 2);
+    assert_eq!(activity.transactions.len(), 1);
+  }
+
+  #[test]
+  fn recent_activity_dormancy() {
+    let mut activity = RecentActivity::default();
+    assert!(!activity.dormant(u64::MAX, 1));
+
+    activity.touch(1_000);
+    activity.touch(500);
+    assert!(!activity.dormant(1_000 + SECONDS_PER_DAY - 1, 1));
+    assert!(activity.dormant(1_000 + SECONDS_PER_DAY, 1));
+    assert!(!activity.dormant(u64::MAX, u64::MAX));
+  }
+}
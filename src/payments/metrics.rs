@@ -0,0 +1,175 @@
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+
+use super::{
+  account::{AccountReport, TransactionInfo},
+  engine::{
+    AccountsReportIter, AccountsReportStream, PaymentsEngine, Result, TransactionsReportIter,
+  },
+  events::{AccountsBefore, EngineEvent},
+  filter::AccountFilter,
+  transaction::{ClientId, Transaction, TransactionId},
+};
+
+/// Interface for a destination of the metrics emitted while processing transactions,
+/// like the [`PrometheusMetrics`](super::PrometheusMetrics).
+pub trait Metrics: Send + Sync {
+  /// A transaction processed by the engine, with the [`EngineEvent`]s it caused and how long it took to process it.
+  /// The rejected transactions only cause an [`EngineEvent::Rejected`].
+  fn transaction_processed(
+    &self,
+    transaction: &Transaction,
+    events: &[EngineEvent],
+    latency: Duration,
+  );
+
+  /// A record that could not be read as a transaction, so it never reached the engine.
+  fn record_unreadable(&self);
+}
+
+/// A [`PaymentsEngine`] middleware that emits into the [`Metrics`] every transaction processed by the inner engine,
+/// with its events like the [`ListeningPaymentsEngine`](super::ListeningPaymentsEngine).
+pub struct MeteredPaymentsEngine<E> {
+  inner: E,
+  metrics: Arc<dyn Metrics>,
+}
+
+impl<E> MeteredPaymentsEngine<E>
+where
+  E: PaymentsEngine,
+{
+  pub fn new(inner: E, metrics: Arc<dyn Metrics>) -> Self {
+    Self { inner, metrics }
+  }
+}
+
+#[async_trait]
+impl<E> PaymentsEngine for MeteredPaymentsEngine<E>
+where
+  E: PaymentsEngine + Send,
+{
+  async fn process(&mut self, transaction: Transaction) -> Result<()> {
+    let before = AccountsBefore::of(&self.inner, &transaction);
+    let start = Instant::now();
+    let result = self.inner.process(transaction.clone()).await;
+    let latency = start.elapsed();
+    let events = EngineEvent::caused_by(&self.inner, before, &transaction, &result);
+    self
+      .metrics
+      .transaction_processed(&transaction, &events, latency);
+    result
+  }
+
+  fn validate(&self, transaction: &Transaction) -> Result<()> {
+    self.inner.validate(transaction)
+  }
+
+  fn accounts_report(&self) -> AccountsReportIter {
+    self.inner.accounts_report()
+  }
+
+  fn accounts_matching(&self, filter: AccountFilter) -> AccountsReportIter {
+    self.inner.accounts_matching(filter)
+  }
+
+  fn accounts_report_stream(&self) -> AccountsReportStream {
+    self.inner.accounts_report_stream()
+  }
+
+  fn account(&self, client_id: ClientId) -> Option<AccountReport> {
+    self.inner.account(client_id)
+  }
+
+  fn transaction(
+    &self,
+    client_id: ClientId,
+    transaction_id: TransactionId,
+  ) -> Result<Option<TransactionInfo>> {
+    self.inner.transaction(client_id, transaction_id)
+  }
+
+  fn transactions_report(&self) -> Result<TransactionsReportIter> {
+    self.inner.transactions_report()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+
+  use std::sync::Mutex;
+
+  use rust_decimal_macros::dec;
+
+  use super::*;
+  use crate::payments::InMemoryPaymentsEngine;
+
+  #[derive(Default)]
+  struct RecordedMetrics(Mutex<Vec<(Transaction, Vec<EngineEvent>)>>);
+
+  impl Metrics for RecordedMetrics {
+    fn transaction_processed(
+      &self,
+      transaction: &Transaction,
+      events: &[EngineEvent],
+      _latency: Duration,
+    ) {
+      let mut processed = self.0.lock().unwrap();
+      processed.push((transaction.clone(), events.to_vec()));
+    }
+
+    fn record_unreadable(&self) {}
+  }
+
+  #[tokio::test]
+  async fn process_emits_metrics() {
+    let metrics = Arc::new(RecordedMetrics::default());
+    let mut engine = MeteredPaymentsEngine::new(InMemoryPaymentsEngine::new(), metrics.clone());
+
+    let deposit = Transaction::Deposit {
+      client_id: 1,
+      transaction_id: 101,
+      amount: dec!(10),
+      timestamp: None,
+      sub_account: 0,
+    };
+    let withdrawal = Transaction::Withdrawal {
+      client_id: 1,
+      transaction_id: 102,
+      amount: dec!(20),
+      timestamp: None,
+      sub_account: 0,
+    };
+
+    assert!(engine.process(deposit.clone()).await.is_ok());
+    assert!(engine.process(withdrawal.clone()).await.is_err());
+
+    assert_eq!(
+      *metrics.0.lock().unwrap(),
+      vec![
+        (
+          deposit,
+          vec![
+            EngineEvent::AccountCreated { client_id: 1 },
+            EngineEvent::Deposited {
+              client_id: 1,
+              transaction_id: 101,
+              amount: dec!(10)
+            }
+          ]
+        ),
+        (
+          withdrawal,
+          vec![EngineEvent::Rejected {
+            client_id: 1,
+            transaction_id: Some(102),
+            transaction_type: "withdrawal".to_string(),
+            amount: Some(dec!(20)),
+            error: "not_enough_available_funds".to_string(),
+          }]
+        ),
+      ]
+    );
+  }
+}
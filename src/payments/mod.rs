@@ -1,16 +1,108 @@
 //! This module contains the domain logic to process transactions
 //!
 //! The [`InMemoryPaymentsEngine`] is a dummy implementation of a [`PaymentsEngine`] that uses memory to store accounts information and transactions.
+//! The [`MeteredPaymentsEngine`] emits the result and latency of every transaction into some [`Metrics`],
+//! like the [`PrometheusMetrics`] rendered to be scraped by Prometheus.
+//! The [`ListeningPaymentsEngine`] notifies an [`EventListener`] about the [`EngineEvent`]s of the transactions, like the deposits, the chargebacks, the locked accounts or the rejections.
+//! The [`FilteredPaymentsEngine`] only reports the accounts selected by an [`AccountFilter`].
+//! The [`Statements`] follow the running balances of every client through the transactions accepted by an engine,
+//! recorded by the [`StatementsPaymentsEngine`] to query the timeline of a client while processing.
+//! The [`WalPaymentsEngine`] appends the accepted transactions into a write-ahead log, from which the state can be rebuilt with [`replay`].
+//! With the `csv` feature, the `CheckpointingPaymentsEngine` snapshots the state as scheduled, so only the log after the latest snapshot is replayed.
+//! The [`StateDigest`] of the accounts tells whether the replicas processing the same transactions diverged, and for which clients.
+//! The [`ShardedPaymentsEngine`] splits the accounts into shards of [`InMemoryPaymentsEngine`], every one processing its transactions in parallel in its own thread.
+//! The settled transactions of the accounts can be moved into a [`TransactionStore`], like the `SpillingTransactionStore`
+//! (with the `kv` feature) that bounds the memory used by keeping them on disk.
+//! With the `sqlite` feature, the `SqlitePaymentsEngine` keeps the accounts in a SQLite database, processing every transaction in a database transaction.
+//! With the `postgres` feature, the `PostgresPaymentsEngine` keeps them in a PostgreSQL database that several instances can share.
+//! Both can coalesce their writes in memory, flushing them in batches whose transactions are recovered from a write-ahead log after a crash.
+//!
+//! Without the `async` feature only the [`InMemoryPaymentsEngine`] is built, driven through the [`SyncPaymentsEngine`],
+//! so embedding the domain logic doesn't need tokio. The [`PaymentsEngine`] trait and all its middlewares need the feature.
 //
 
 mod account;
+#[cfg(any(feature = "sqlite", feature = "postgres"))]
+mod batch;
+#[cfg(feature = "csv")]
+mod checkpoints;
+mod config;
+mod digest;
 mod engine;
+#[cfg(feature = "async")]
+mod events;
+mod filter;
+#[cfg(feature = "async")]
+mod invariants;
+mod limits;
+#[cfg(feature = "async")]
+mod metrics;
+#[cfg(feature = "postgres")]
+mod postgres;
+#[cfg(feature = "async")]
+mod prometheus;
+mod reconciliation;
+mod report;
+#[cfg(feature = "async")]
+mod sharded;
+mod snapshot;
+#[cfg(feature = "sqlite")]
+mod sqlite;
+#[cfg(feature = "async")]
+mod statements;
+mod store;
 mod transaction;
+#[cfg(feature = "async")]
+mod wal;
 
-pub(crate) use account::AccountReport;
+pub use account::{
+  AccountReport, DisputeState, Exposure, SubAccountReport, TransactionInfo, TransactionKind,
+};
 
 #[cfg(test)]
 pub(crate) use engine::Result as EngineResult;
 
-pub use engine::{AccountsReportIter, InMemoryPaymentsEngine, PaymentsEngine, PaymentsEngineError};
-pub use transaction::{ClientId, Transaction, TransactionId};
+pub use config::{
+  ChargebackFee, DormancyPolicy, DuplicatePolicy, EngineConfig, LockedAccountDisputePolicy,
+  SyntheticDisputePolicy, UnlockHeldFundsPolicy, ZeroAmountPolicy,
+};
+pub use digest::StateDigest;
+pub use engine::{
+  AccountView, AccountsReportIter, InMemoryPaymentsEngine, PaymentsEngineError, SyncPaymentsEngine,
+  TransactionsReportIter,
+};
+pub use filter::AccountFilter;
+pub use limits::LimitsPolicy;
+pub use reconciliation::{reconcile, Break, BreakKind, ExternalBalance};
+pub use report::{ReportOptions, ReportSortKey};
+pub use snapshot::{Snapshot, SNAPSHOT_FORMAT_VERSION};
+pub use store::{InMemoryTransactionStore, StoredTransactions, TransactionStore};
+pub use transaction::{ClientId, SubAccountId, Transaction, TransactionId, MAIN_SUB_ACCOUNT};
+
+#[cfg(feature = "csv")]
+pub use checkpoints::{CheckpointSchedule, CheckpointingPaymentsEngine};
+#[cfg(feature = "async")]
+pub use engine::{AccountsReportStream, BoxedPaymentsEngine, PaymentsEngine};
+#[cfg(feature = "async")]
+pub use events::{ChannelEventListener, EngineEvent, EventListener, ListeningPaymentsEngine};
+#[cfg(feature = "async")]
+pub use filter::FilteredPaymentsEngine;
+#[cfg(feature = "async")]
+pub use invariants::{DebugPaymentsEngine, InvariantCheckingEngine};
+#[cfg(feature = "async")]
+pub use metrics::{MeteredPaymentsEngine, Metrics};
+#[cfg(feature = "async")]
+pub use prometheus::{PrometheusMetrics, StreamStats, ThroughputBucket};
+#[cfg(feature = "async")]
+pub use sharded::ShardedPaymentsEngine;
+#[cfg(feature = "async")]
+pub use statements::{StatementLine, Statements, StatementsPaymentsEngine};
+#[cfg(feature = "async")]
+pub use wal::{replay, replay_after, WalPaymentsEngine};
+
+#[cfg(feature = "postgres")]
+pub use postgres::PostgresPaymentsEngine;
+#[cfg(feature = "sqlite")]
+pub use sqlite::SqlitePaymentsEngine;
+#[cfg(feature = "kv")]
+pub use store::SpillingTransactionStore;
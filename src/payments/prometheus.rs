@@ -0,0 +1,644 @@
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use rust_decimal::Decimal;
+use serde::Serialize;
+
+use super::{
+  events::EngineEvent,
+  metrics::Metrics,
+  transaction::{ClientId, Transaction},
+};
+
+/// The number of buckets of the histograms, besides the one of all the observations.
+const BUCKETS: usize = 6;
+
+/// The upper bounds of the buckets of the processing latency, in seconds.
+const LATENCY_BUCKETS: [f64; BUCKETS] = [0.00001, 0.0001, 0.001, 0.01, 0.1, 1.0];
+
+/// The upper bounds of the buckets of the end-to-end latency, from the timestamp of the records to their processing, in seconds.
+const END_TO_END_BUCKETS: [f64; BUCKETS] = [1.0, 10.0, 60.0, 600.0, 3600.0, 86400.0];
+
+/// The number of clients with the most rejected transactions that are exposed, to keep the number of series bounded.
+const TOP_REJECTED_CLIENTS: usize = 10;
+
+/// The buckets of the throughput that are kept, dropping the oldest ones: a day of minutes of the wall clock,
+/// and a month of hours of the timestamps of the transactions.
+const MINUTE_BUCKETS: usize = 24 * 60;
+const INPUT_HOUR_BUCKETS: usize = 31 * 24;
+
+const SECONDS_PER_MINUTE: u64 = 60;
+const SECONDS_PER_HOUR: u64 = 60 * 60;
+
+/// An implementation of [`Metrics`] that renders them in the text format of Prometheus:
+/// - `payments_transactions_processed_total` counts the accepted transactions by `type`
+/// - `payments_transactions_rejected_total` counts the rejected transactions by `type` and `error` kind
+/// - `payments_events_total` counts the [`EngineEvent`]s caused by the transactions by `event`
+/// - `payments_records_unreadable_total` counts the records that could not be read as transactions
+/// - `payments_transaction_processing_seconds` is an histogram of the processing latency by `type`
+/// - `payments_client_rejected_transactions` and `payments_client_rejected_amount` are the number and the amount
+///   of the rejected transactions of the clients with the most of them, by `client`, so the data quality issues
+///   concentrated in a few clients stand out
+/// - `payments_watermark_timestamp_seconds` is the latest timestamp of the processed transactions, and
+///   `payments_consumer_lag_seconds` how far behind the wall clock it was when processed, to alert on falling behind a stream
+/// - `payments_end_to_end_latency_seconds` is an histogram of the time from the timestamp of the transactions to their processing
+///
+/// The stream metrics only count the transactions with a timestamp, and are also summarized by [`PrometheusMetrics::stream_stats`].
+#[derive(Debug, Default)]
+pub struct PrometheusMetrics(Mutex<State>);
+
+#[derive(Debug, Default)]
+struct State {
+  processed: BTreeMap<&'static str, u64>,
+  rejected: BTreeMap<(&'static str, String), u64>,
+  events: BTreeMap<&'static str, u64>,
+  unreadable: u64,
+  latency: BTreeMap<&'static str, Histogram>,
+  rejected_clients: HashMap<ClientId, ClientRejections>,
+  watermark: Option<u64>,
+  consumer_lag: Option<f64>,
+  end_to_end: Histogram,
+  per_minute: BTreeMap<u64, ThroughputBucket>,
+  per_input_hour: BTreeMap<u64, ThroughputBucket>,
+}
+
+impl State {
+  /// Observe a transaction with the timestamp processed at the time, both in seconds since the Unix epoch.
+  /// The time of timestamps in the future is zero, as the clocks of the sources can be ahead.
+  fn observe_timestamp(&mut self, timestamp: u64, now: f64) {
+    let watermark = self
+      .watermark
+      .map_or(timestamp, |watermark| watermark.max(timestamp));
+    self.watermark = Some(watermark);
+    self.consumer_lag = Some((now - watermark as f64).max(0.0));
+    self
+      .end_to_end
+      .observe(&END_TO_END_BUCKETS, (now - timestamp as f64).max(0.0));
+  }
+
+  /// Count a transaction in the minute of the wall clock it was processed, and in the hour of its timestamp, if any.
+  fn observe_throughput(&mut self, rejected: bool, now: u64, timestamp: Option<u64>) {
+    count_throughput(
+      &mut self.per_minute,
+      MINUTE_BUCKETS,
+      now - now % SECONDS_PER_MINUTE,
+      rejected,
+    );
+    if let Some(timestamp) = timestamp {
+      count_throughput(
+        &mut self.per_input_hour,
+        INPUT_HOUR_BUCKETS,
+        timestamp - timestamp % SECONDS_PER_HOUR,
+        rejected,
+      );
+    }
+  }
+}
+
+/// Count a transaction in the bucket that starts at the time, dropping the oldest bucket when there are too many.
+fn count_throughput(
+  buckets: &mut BTreeMap<u64, ThroughputBucket>,
+  limit: usize,
+  start: u64,
+  rejected: bool,
+) {
+  let bucket = buckets.entry(start).or_insert_with(|| ThroughputBucket {
+    start,
+    ..ThroughputBucket::default()
+  });
+  if rejected {
+    bucket.rejected += 1;
+  } else {
+    bucket.processed += 1;
+  }
+  if buckets.len() > limit {
+    let oldest = buckets.keys().next().copied();
+    if let Some(oldest) = oldest {
+      buckets.remove(&oldest);
+    }
+  }
+}
+
+/// A summary of the processing of a stream of transactions, as returned by `GET /stats`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct StreamStats {
+  /// The number of transactions accepted.
+  pub processed: u64,
+  /// The number of transactions rejected.
+  pub rejected: u64,
+  /// The number of records that could not be read as transactions.
+  pub unreadable: u64,
+  /// The latest timestamp of the processed transactions, if any had one.
+  pub watermark: Option<u64>,
+  /// How many seconds the watermark was behind the wall clock when it was processed.
+  pub consumer_lag_seconds: Option<f64>,
+  /// The average time from the timestamp of the transactions to their processing, in seconds.
+  pub end_to_end_latency_seconds: Option<f64>,
+  /// The transactions by minute of the wall clock, so the slowdowns of a run can be correlated with its input.
+  pub per_minute: Vec<ThroughputBucket>,
+  /// The transactions with a timestamp by its hour.
+  pub per_input_hour: Vec<ThroughputBucket>,
+}
+
+/// The number of transactions processed and rejected in a period of time.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize)]
+pub struct ThroughputBucket {
+  /// When the period starts, in seconds since the Unix epoch.
+  pub start: u64,
+  pub processed: u64,
+  pub rejected: u64,
+}
+
+/// The rejected transactions of a client.
+#[derive(Debug, Default, Clone, Copy)]
+struct ClientRejections {
+  count: u64,
+  amount: Decimal,
+}
+
+#[derive(Debug, Default)]
+struct Histogram {
+  /// The number of observations that fall in every bucket, which are accumulated when rendered.
+  buckets: [u64; BUCKETS],
+  sum: f64,
+  count: u64,
+}
+
+impl Histogram {
+  fn observe(&mut self, bounds: &[f64; BUCKETS], value: f64) {
+    if let Some(bucket) = bounds.iter().position(|bound| value <= *bound) {
+      self.buckets[bucket] += 1;
+    }
+    self.sum += value;
+    self.count += 1;
+  }
+
+  /// Write the series of the histogram with the labels, which are either empty or end with a comma.
+  fn render(&self, out: &mut String, name: &str, labels: &str, bounds: &[f64; BUCKETS]) {
+    let mut cumulative = 0;
+    for (bound, count) in bounds.iter().zip(self.buckets.iter()) {
+      cumulative += count;
+      writeln!(
+        out,
+        "{}_bucket{{{}le=\"{}\"}} {}",
+        name, labels, bound, cumulative
+      )
+      .ok();
+    }
+    writeln!(
+      out,
+      "{}_bucket{{{}le=\"+Inf\"}} {}",
+      name, labels, self.count
+    )
+    .ok();
+    let labels = labels.trim_end_matches(',');
+    let labels = if labels.is_empty() {
+      String::new()
+    } else {
+      format!("{{{}}}", labels)
+    };
+    writeln!(out, "{}_sum{} {}", name, labels, self.sum).ok();
+    writeln!(out, "{}_count{} {}", name, labels, self.count).ok();
+  }
+}
+
+impl PrometheusMetrics {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// The current value of all the metrics, as they are exposed to be scraped.
+  pub fn render(&self) -> String {
+    let state = self.0.lock().unwrap_or_else(|err| err.into_inner());
+    let mut out = String::new();
+
+    out.push_str("# TYPE payments_transactions_processed_total counter\n");
+    for (transaction_type, count) in state.processed.iter() {
+      writeln!(
+        out,
+        "payments_transactions_processed_total{{type=\"{}\"}} {}",
+        transaction_type, count
+      )
+      .ok();
+    }
+
+    out.push_str("# TYPE payments_transactions_rejected_total counter\n");
+    for ((transaction_type, error), count) in state.rejected.iter() {
+      writeln!(
+        out,
+        "payments_transactions_rejected_total{{type=\"{}\",error=\"{}\"}} {}",
+        transaction_type, error, count
+      )
+      .ok();
+    }
+
+    out.push_str("# TYPE payments_events_total counter\n");
+    for (event, count) in state.events.iter() {
+      writeln!(
+        out,
+        "payments_events_total{{event=\"{}\"}} {}",
+        event, count
+      )
+      .ok();
+    }
+
+    out.push_str("# TYPE payments_records_unreadable_total counter\n");
+    writeln!(
+      out,
+      "payments_records_unreadable_total {}",
+      state.unreadable
+    )
+    .ok();
+
+    let mut rejected_clients: Vec<(ClientId, ClientRejections)> = state
+      .rejected_clients
+      .iter()
+      .map(|(client_id, rejections)| (*client_id, *rejections))
+      .collect();
+
+    out.push_str("# TYPE payments_client_rejected_transactions gauge\n");
+    rejected_clients.sort_by_key(|(client_id, rejections)| (Reverse(rejections.count), *client_id));
+    for (client_id, rejections) in rejected_clients.iter().take(TOP_REJECTED_CLIENTS) {
+      writeln!(
+        out,
+        "payments_client_rejected_transactions{{client=\"{}\"}} {}",
+        client_id, rejections.count
+      )
+      .ok();
+    }
+
+    out.push_str("# TYPE payments_client_rejected_amount gauge\n");
+    rejected_clients
+      .sort_by_key(|(client_id, rejections)| (Reverse(rejections.amount), *client_id));
+    for (client_id, rejections) in rejected_clients
+      .iter()
+      .filter(|(_, rejections)| !rejections.amount.is_zero())
+      .take(TOP_REJECTED_CLIENTS)
+    {
+      writeln!(
+        out,
+        "payments_client_rejected_amount{{client=\"{}\"}} {}",
+        client_id, rejections.amount
+      )
+      .ok();
+    }
+
+    out.push_str("# TYPE payments_transaction_processing_seconds histogram\n");
+    for (transaction_type, histogram) in state.latency.iter() {
+      histogram.render(
+        &mut out,
+        "payments_transaction_processing_seconds",
+        &format!("type=\"{}\",", transaction_type),
+        &LATENCY_BUCKETS,
+      );
+    }
+
+    out.push_str("# TYPE payments_watermark_timestamp_seconds gauge\n");
+    if let Some(watermark) = state.watermark {
+      writeln!(out, "payments_watermark_timestamp_seconds {}", watermark).ok();
+    }
+
+    out.push_str("# TYPE payments_consumer_lag_seconds gauge\n");
+    if let Some(consumer_lag) = state.consumer_lag {
+      writeln!(out, "payments_consumer_lag_seconds {}", consumer_lag).ok();
+    }
+
+    out.push_str("# TYPE payments_end_to_end_latency_seconds histogram\n");
+    state.end_to_end.render(
+      &mut out,
+      "payments_end_to_end_latency_seconds",
+      "",
+      &END_TO_END_BUCKETS,
+    );
+
+    out
+  }
+
+  /// A summary of the processing, with the stream metrics of the transactions with a timestamp.
+  pub fn stream_stats(&self) -> StreamStats {
+    let state = self.0.lock().unwrap_or_else(|err| err.into_inner());
+    let end_to_end = &state.end_to_end;
+    StreamStats {
+      processed: state.processed.values().sum(),
+      rejected: state.rejected.values().sum(),
+      unreadable: state.unreadable,
+      watermark: state.watermark,
+      consumer_lag_seconds: state.consumer_lag,
+      end_to_end_latency_seconds: Some(end_to_end.sum / end_to_end.count as f64)
+        .filter(|_| end_to_end.count > 0),
+      per_minute: state.per_minute.values().copied().collect(),
+      per_input_hour: state.per_input_hour.values().copied().collect(),
+    }
+  }
+}
+
+impl Metrics for PrometheusMetrics {
+  fn transaction_processed(
+    &self,
+    transaction: &Transaction,
+    events: &[EngineEvent],
+    latency: Duration,
+  ) {
+    let transaction_type = transaction.type_name();
+    let mut state = self.0.lock().unwrap_or_else(|err| err.into_inner());
+    let rejection = events.iter().find_map(|event| match event {
+      EngineEvent::Rejected {
+        client_id,
+        amount,
+        error,
+        ..
+      } => Some((client_id, amount, error)),
+      _ => None,
+    });
+    match rejection {
+      None => *state.processed.entry(transaction_type).or_default() += 1,
+      Some((client_id, amount, error)) => {
+        *state
+          .rejected
+          .entry((transaction_type, error.clone()))
+          .or_default() += 1;
+        let rejections = state.rejected_clients.entry(*client_id).or_default();
+        rejections.count += 1;
+        rejections.amount = rejections
+          .amount
+          .checked_add(amount.unwrap_or_default().abs())
+          .unwrap_or(Decimal::MAX);
+      }
+    }
+    for event in events {
+      *state.events.entry(event.name()).or_default() += 1;
+    }
+    state
+      .latency
+      .entry(transaction_type)
+      .or_default()
+      .observe(&LATENCY_BUCKETS, latency.as_secs_f64());
+    let now = SystemTime::now()
+      .duration_since(UNIX_EPOCH)
+      .unwrap_or_default();
+    state.observe_throughput(rejection.is_some(), now.as_secs(), transaction.timestamp());
+    if let Some(timestamp) = transaction.timestamp() {
+      state.observe_timestamp(timestamp, now.as_secs_f64());
+    }
+  }
+
+  fn record_unreadable(&self) {
+    let mut state = self.0.lock().unwrap_or_else(|err| err.into_inner());
+    state.unreadable += 1;
+  }
+}
+
+#[cfg(test)]
+mod tests {
+
+  use indoc::indoc;
+  use rust_decimal_macros::dec;
+
+  use super::*;
+
+  #[test]
+  fn render_metrics() {
+    let metrics = PrometheusMetrics::new();
+    let deposit = Transaction::Deposit {
+      client_id: 1,
+      transaction_id: 101,
+      amount: dec!(10),
+      timestamp: None,
+      sub_account: 0,
+    };
+    let dispute = Transaction::Dispute {
+      client_id: 1,
+      transaction_id: 102,
+      timestamp: None,
+    };
+
+    let deposited = EngineEvent::Deposited {
+      client_id: 1,
+      transaction_id: 101,
+      amount: dec!(10),
+    };
+    let rejected = EngineEvent::Rejected {
+      client_id: 1,
+      transaction_id: Some(102),
+      transaction_type: "dispute".to_string(),
+      amount: None,
+      error: "transaction_not_found".to_string(),
+    };
+
+    metrics.transaction_processed(
+      &deposit,
+      &[
+        EngineEvent::AccountCreated { client_id: 1 },
+        deposited.clone(),
+      ],
+      Duration::from_nanos(7_812_500),
+    );
+    metrics.transaction_processed(&deposit, &[deposited], Duration::from_millis(500));
+    metrics.transaction_processed(&dispute, &[rejected], Duration::from_secs(2));
+    metrics.record_unreadable();
+
+    assert_eq!(
+      metrics.render(),
+      indoc! { r#"
+        # TYPE payments_transactions_processed_total counter
+        payments_transactions_processed_total{type="deposit"} 2
+        # TYPE payments_transactions_rejected_total counter
+        payments_transactions_rejected_total{type="dispute",error="transaction_not_found"} 1
+        # TYPE payments_events_total counter
+        payments_events_total{event="account_created"} 1
+        payments_events_total{event="deposited"} 2
+        payments_events_total{event="rejected"} 1
+        # TYPE payments_records_unreadable_total counter
+        payments_records_unreadable_total 1
+        # TYPE payments_client_rejected_transactions gauge
+        payments_client_rejected_transactions{client="1"} 1
+        # TYPE payments_client_rejected_amount gauge
+        # TYPE payments_transaction_processing_seconds histogram
+        payments_transaction_processing_seconds_bucket{type="deposit",le="0.00001"} 0
+        payments_transaction_processing_seconds_bucket{type="deposit",le="0.0001"} 0
+        payments_transaction_processing_seconds_bucket{type="deposit",le="0.001"} 0
+        payments_transaction_processing_seconds_bucket{type="deposit",le="0.01"} 1
+        payments_transaction_processing_seconds_bucket{type="deposit",le="0.1"} 1
+        payments_transaction_processing_seconds_bucket{type="deposit",le="1"} 2
+        payments_transaction_processing_seconds_bucket{type="deposit",le="+Inf"} 2
+        payments_transaction_processing_seconds_sum{type="deposit"} 0.5078125
+        payments_transaction_processing_seconds_count{type="deposit"} 2
+        payments_transaction_processing_seconds_bucket{type="dispute",le="0.00001"} 0
+        payments_transaction_processing_seconds_bucket{type="dispute",le="0.0001"} 0
+        payments_transaction_processing_seconds_bucket{type="dispute",le="0.001"} 0
+        payments_transaction_processing_seconds_bucket{type="dispute",le="0.01"} 0
+        payments_transaction_processing_seconds_bucket{type="dispute",le="0.1"} 0
+        payments_transaction_processing_seconds_bucket{type="dispute",le="1"} 0
+        payments_transaction_processing_seconds_bucket{type="dispute",le="+Inf"} 1
+        payments_transaction_processing_seconds_sum{type="dispute"} 2
+        payments_transaction_processing_seconds_count{type="dispute"} 1
+        # TYPE payments_watermark_timestamp_seconds gauge
+        # TYPE payments_consumer_lag_seconds gauge
+        # TYPE payments_end_to_end_latency_seconds histogram
+        payments_end_to_end_latency_seconds_bucket{le="1"} 0
+        payments_end_to_end_latency_seconds_bucket{le="10"} 0
+        payments_end_to_end_latency_seconds_bucket{le="60"} 0
+        payments_end_to_end_latency_seconds_bucket{le="600"} 0
+        payments_end_to_end_latency_seconds_bucket{le="3600"} 0
+        payments_end_to_end_latency_seconds_bucket{le="86400"} 0
+        payments_end_to_end_latency_seconds_bucket{le="+Inf"} 0
+        payments_end_to_end_latency_seconds_sum 0
+        payments_end_to_end_latency_seconds_count 0
+      "# }
+    );
+  }
+
+  #[test]
+  fn render_stream_metrics() {
+    let metrics = PrometheusMetrics::new();
+    {
+      let mut state = metrics.0.lock().unwrap();
+      state.observe_timestamp(1_000, 1_005.0);
+      // the watermark doesn't go back with the transactions out of order
+      state.observe_timestamp(900, 1_200.0);
+      state.observe_timestamp(2_000, 1_500.0);
+      state.processed.insert("deposit", 3);
+    }
+
+    let rendered = metrics.render();
+    let stream_metrics: Vec<&str> = rendered
+      .lines()
+      .skip_while(|line| !line.contains("payments_watermark_timestamp_seconds"))
+      .collect();
+    assert_eq!(
+      stream_metrics,
+      vec![
+        "# TYPE payments_watermark_timestamp_seconds gauge",
+        "payments_watermark_timestamp_seconds 2000",
+        "# TYPE payments_consumer_lag_seconds gauge",
+        "payments_consumer_lag_seconds 0",
+        "# TYPE payments_end_to_end_latency_seconds histogram",
+        "payments_end_to_end_latency_seconds_bucket{le=\"1\"} 1",
+        "payments_end_to_end_latency_seconds_bucket{le=\"10\"} 2",
+        "payments_end_to_end_latency_seconds_bucket{le=\"60\"} 2",
+        "payments_end_to_end_latency_seconds_bucket{le=\"600\"} 3",
+        "payments_end_to_end_latency_seconds_bucket{le=\"3600\"} 3",
+        "payments_end_to_end_latency_seconds_bucket{le=\"86400\"} 3",
+        "payments_end_to_end_latency_seconds_bucket{le=\"+Inf\"} 3",
+        "payments_end_to_end_latency_seconds_sum 305",
+        "payments_end_to_end_latency_seconds_count 3",
+      ]
+    );
+
+    assert_eq!(
+      metrics.stream_stats(),
+      StreamStats {
+        processed: 3,
+        rejected: 0,
+        unreadable: 0,
+        watermark: Some(2_000),
+        consumer_lag_seconds: Some(0.0),
+        end_to_end_latency_seconds: Some(305.0 / 3.0),
+        per_minute: vec![],
+        per_input_hour: vec![],
+      }
+    );
+  }
+
+  #[test]
+  fn count_the_throughput_by_minute_and_input_hour() {
+    let metrics = PrometheusMetrics::new();
+    {
+      let mut state = metrics.0.lock().unwrap();
+      state.observe_throughput(false, 60, Some(3_599));
+      state.observe_throughput(true, 119, Some(3_600));
+      state.observe_throughput(false, 180, None);
+      for minute in 1..MINUTE_BUCKETS as u64 {
+        state.observe_throughput(false, 180 + minute * 60, None);
+      }
+    }
+
+    let stats = metrics.stream_stats();
+    assert_eq!(stats.per_minute.len(), MINUTE_BUCKETS);
+    // the oldest minutes are dropped
+    assert_eq!(
+      stats.per_minute[0],
+      ThroughputBucket {
+        start: 180,
+        processed: 1,
+        rejected: 0,
+      }
+    );
+    assert_eq!(
+      stats.per_input_hour,
+      vec![
+        ThroughputBucket {
+          start: 0,
+          processed: 1,
+          rejected: 0,
+        },
+        ThroughputBucket {
+          start: 3_600,
+          processed: 0,
+          rejected: 1,
+        },
+      ]
+    );
+  }
+
+  #[test]
+  fn render_top_rejected_clients() {
+    let metrics = PrometheusMetrics::new();
+    for client_id in 1..=12u16 {
+      let withdrawal = Transaction::Withdrawal {
+        client_id,
+        transaction_id: client_id.into(),
+        amount: client_id.into(),
+        timestamp: None,
+        sub_account: 0,
+      };
+      let events = [EngineEvent::Rejected {
+        client_id,
+        transaction_id: Some(client_id.into()),
+        transaction_type: "withdrawal".to_string(),
+        amount: Some(client_id.into()),
+        error: "not_enough_available_funds".to_string(),
+      }];
+      let times = if client_id == 3 { 3 } else { 1 };
+      for _ in 0..times {
+        metrics.transaction_processed(&withdrawal, &events, Duration::from_secs(0));
+      }
+    }
+
+    let rendered = metrics.render();
+    let lines = |name: &str| -> Vec<String> {
+      rendered
+        .lines()
+        .filter(|line| line.starts_with(&format!("{}{{", name)))
+        .map(str::to_string)
+        .collect()
+    };
+
+    let by_count: Vec<String> = vec![3, 1, 2, 4, 5, 6, 7, 8, 9, 10]
+      .into_iter()
+      .map(|client| {
+        format!(
+          "payments_client_rejected_transactions{{client=\"{}\"}} {}",
+          client,
+          if client == 3 { 3 } else { 1 }
+        )
+      })
+      .collect();
+    assert_eq!(lines("payments_client_rejected_transactions"), by_count);
+
+    let by_amount: Vec<String> = vec![(12, 12), (11, 11), (10, 10), (3, 9), (9, 9)]
+      .into_iter()
+      .chain((4..=8).rev().map(|client| (client, client)))
+      .map(|(client, amount)| {
+        format!(
+          "payments_client_rejected_amount{{client=\"{}\"}} {}",
+          client, amount
+        )
+      })
+      .collect();
+    assert_eq!(lines("payments_client_rejected_amount"), by_amount);
+  }
+}
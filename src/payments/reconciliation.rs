@@ -0,0 +1,213 @@
+use std::collections::HashMap;
+
+use rust_decimal::Decimal;
+
+use super::{account::AccountReport, ClientId};
+
+/// Balance of a client account as reported by an external source (like a bank statement).
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExternalBalance {
+  pub client_id: ClientId,
+  pub total: Decimal,
+}
+
+impl ExternalBalance {
+  pub fn new(client_id: ClientId, total: Decimal) -> Self {
+    Self { client_id, total }
+  }
+}
+
+/// The different ways in which the accounts report and the external balances can disagree.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BreakKind {
+  /// The client has an account in the engine but no external balance.
+  MissingInExternal,
+  /// The client has an external balance but no account in the engine.
+  MissingInEngine,
+  /// The totals differ more than the allowed tolerance.
+  AmountMismatch,
+}
+
+/// A disagreement found while reconciling the accounts report against the external balances.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Break {
+  pub client_id: ClientId,
+  pub kind: BreakKind,
+  pub engine_total: Option<Decimal>,
+  pub external_total: Option<Decimal>,
+}
+
+impl Break {
+  pub fn new(
+    client_id: ClientId,
+    kind: BreakKind,
+    engine_total: Option<Decimal>,
+    external_total: Option<Decimal>,
+  ) -> Self {
+    Self {
+      client_id,
+      kind,
+      engine_total,
+      external_total,
+    }
+  }
+
+  /// The difference between the engine and the external totals, when both are known and it doesn't overflow.
+  pub fn difference(&self) -> Option<Decimal> {
+    self
+      .engine_total
+      .zip(self.external_total)
+      .and_then(|(engine_total, external_total)| engine_total.checked_sub(external_total))
+  }
+}
+
+/// Match the accounts report with the external balances per client, and return the breaks sorted by client ID.
+///
+/// Totals are considered to match when their absolute difference is not greater than the `tolerance`,
+/// and they are a mismatch when the difference overflows.
+/// If the external balances contain the same client more than once, the last balance is used.
+pub fn reconcile<R, B>(report: R, balances: B, tolerance: Decimal) -> Vec<Break>
+where
+  R: Iterator<Item = AccountReport>,
+  B: IntoIterator<Item = ExternalBalance>,
+{
+  let mut external_totals: HashMap<ClientId, Decimal> = balances
+    .into_iter()
+    .map(|balance| (balance.client_id, balance.total))
+    .collect();
+
+  let mut breaks = Vec::new();
+
+  for account_report in report {
+    match external_totals.remove(&account_report.client_id) {
+      Some(external_total) => {
+        let matching = account_report
+          .total
+          .checked_sub(external_total)
+          .map_or(false, |difference| difference.abs() <= tolerance);
+        if !matching {
+          breaks.push(Break::new(
+            account_report.client_id,
+            BreakKind::AmountMismatch,
+            Some(account_report.total),
+            Some(external_total),
+          ));
+        }
+      }
+      None => breaks.push(Break::new(
+        account_report.client_id,
+        BreakKind::MissingInExternal,
+        Some(account_report.total),
+        None,
+      )),
+    }
+  }
+
+  breaks.extend(
+    external_totals
+      .into_iter()
+      .map(|(client_id, external_total)| {
+        Break::new(
+          client_id,
+          BreakKind::MissingInEngine,
+          None,
+          Some(external_total),
+        )
+      }),
+  );
+
+  breaks.sort_by_key(|b| b.client_id);
+  breaks
+}
+
+#[cfg(test)]
+mod tests {
+
+  use rust_decimal_macros::dec;
+
+  use super::*;
+
+  #[test]
+  fn break_difference() {
+    assert_eq!(
+      Break::new(
+        1,
+        BreakKind::AmountMismatch,
+        Some(dec!(10)),
+        Some(dec!(7.5))
+      )
+      .difference(),
+      Some(dec!(2.5))
+    );
+    assert_eq!(
+      Break::new(1, BreakKind::MissingInExternal, Some(dec!(10)), None).difference(),
+      None
+    );
+    assert_eq!(
+      Break::new(
+        1,
+        BreakKind::AmountMismatch,
+        Some(Decimal::MIN),
+        Some(Decimal::MAX)
+      )
+      .difference(),
+      None
+    );
+  }
+
+  #[test]
+  fn reconcile_without_breaks() {
+    let report = vec![
+      AccountReport::new(1, dec!(100), dec!(0), dec!(100), false),
+      AccountReport::new(2, dec!(10), dec!(5), dec!(15), true),
+    ];
+    let balances = vec![
+      ExternalBalance::new(2, dec!(15)),
+      ExternalBalance::new(1, dec!(100)),
+    ];
+
+    let breaks = reconcile(report.into_iter(), balances, Decimal::ZERO);
+
+    assert_eq!(breaks, vec![]);
+  }
+
+  #[test]
+  fn reconcile_with_breaks() {
+    let report = vec![
+      AccountReport::new(1, dec!(100), dec!(0), dec!(100), false),
+      AccountReport::new(2, dec!(10), dec!(5), dec!(15), false),
+      AccountReport::new(3, dec!(30), dec!(0), dec!(30), false),
+      AccountReport::new(5, dec!(50), dec!(0), dec!(50), false),
+      AccountReport::new(6, Decimal::MIN, dec!(0), Decimal::MIN, false),
+    ];
+    let balances = vec![
+      ExternalBalance::new(1, dec!(100.01)),
+      ExternalBalance::new(2, dec!(15.02)),
+      ExternalBalance::new(4, dec!(40)),
+      ExternalBalance::new(5, dec!(50)),
+      ExternalBalance::new(6, Decimal::MAX),
+    ];
+
+    let breaks = reconcile(report.into_iter(), balances, dec!(0.01));
+
+    assert_eq!(
+      breaks,
+      vec![
+        Break::new(
+          2,
+          BreakKind::AmountMismatch,
+          Some(dec!(15)),
+          Some(dec!(15.02))
+        ),
+        Break::new(3, BreakKind::MissingInExternal, Some(dec!(30)), None),
+        Break::new(4, BreakKind::MissingInEngine, None, Some(dec!(40))),
+        Break::new(
+          6,
+          BreakKind::AmountMismatch,
+          Some(Decimal::MIN),
+          Some(Decimal::MAX)
+        ),
+      ]
+    );
+  }
+}
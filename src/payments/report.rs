@@ -0,0 +1,82 @@
+use super::account::AccountReport;
+
+/// The key to sort the accounts report by.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ReportSortKey {
+  ClientId,
+  /// The total funds of the accounts, with the ties sorted by client ID.
+  Total,
+}
+
+impl Default for ReportSortKey {
+  fn default() -> Self {
+    ReportSortKey::ClientId
+  }
+}
+
+/// How to order the accounts report, so it can be compared between runs (for example, to reconcile them with a diff).
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ReportOptions {
+  pub sort_by: ReportSortKey,
+  pub descending: bool,
+}
+
+impl ReportOptions {
+  /// Sort the accounts of the report.
+  pub fn sort(&self, report: &mut [AccountReport]) {
+    report.sort_by(|a, b| {
+      let ordering = match self.sort_by {
+        ReportSortKey::ClientId => a.client_id.cmp(&b.client_id),
+        ReportSortKey::Total => a
+          .total
+          .cmp(&b.total)
+          .then_with(|| a.client_id.cmp(&b.client_id)),
+      };
+      if self.descending {
+        ordering.reverse()
+      } else {
+        ordering
+      }
+    });
+  }
+}
+
+#[cfg(test)]
+mod tests {
+
+  use rust_decimal_macros::dec;
+
+  use super::*;
+
+  #[test]
+  fn sort_accounts_report() {
+    let mut report = vec![
+      AccountReport::new(3, dec!(10), dec!(0), dec!(10), false),
+      AccountReport::new(1, dec!(20), dec!(0), dec!(20), false),
+      AccountReport::new(2, dec!(10), dec!(0), dec!(10), false),
+    ];
+    let client_ids = |report: &[AccountReport]| -> Vec<u16> {
+      report
+        .iter()
+        .map(|account_report| account_report.client_id)
+        .collect()
+    };
+
+    ReportOptions::default().sort(&mut report);
+    assert_eq!(client_ids(&report), vec![1, 2, 3]);
+
+    let by_total = ReportOptions {
+      sort_by: ReportSortKey::Total,
+      descending: false,
+    };
+    by_total.sort(&mut report);
+    assert_eq!(client_ids(&report), vec![2, 3, 1]);
+
+    let by_total_descending = ReportOptions {
+      descending: true,
+      ..by_total
+    };
+    by_total_descending.sort(&mut report);
+    assert_eq!(client_ids(&report), vec![1, 3, 2]);
+  }
+}
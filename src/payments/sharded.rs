@@ -0,0 +1,495 @@
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError};
+
+use async_trait::async_trait;
+use tokio::sync::mpsc::{self, error::TrySendError};
+
+use super::{
+  account::{AccountReport, TransactionInfo},
+  config::EngineConfig,
+  engine::{
+    AccountsReportIter, InMemoryPaymentsEngine, PaymentsEngine, PaymentsEngineError, Result,
+    SyncPaymentsEngine, TransactionsReportIter,
+  },
+  filter::AccountFilter,
+  transaction::{ClientId, Transaction, TransactionId},
+};
+
+/// Maximum number of transactions waiting to be processed by every shard, and of rejections waiting to be taken, by default.
+const CHANNEL_CAPACITY: usize = 1024;
+
+/// A transaction rejected by a shard, with the reason.
+type Rejection = (Transaction, PaymentsEngineError);
+
+/// A [`PaymentsEngine`] that splits the accounts into shards, with the same semantics than the [`partitioned`](crate::processors::partitioned) processor,
+/// but behind the interface of a single engine, so any processor can use it:
+/// - the accounts are split by their `client_id` into `shards`, every one with its own [`InMemoryPaymentsEngine`]
+/// - every shard processes its transactions in its own thread, fed through a channel, and [`PaymentsEngine::process`]
+///   returns as soon as the transaction is queued, so the shards process their transactions in parallel
+/// - the transactions rejected by the shards are collected, to be taken with [`ShardedPaymentsEngine::rejections`],
+///   up to the capacity of the shards, as the ones over it are only logged by the engines of the shards
+/// - the reports and lookups wait for the shards to process the queued transactions, and merge their results
+///
+/// As [`PaymentsEngine::process`] doesn't wait for the shards, the middlewares wrapping this engine
+/// (like the [`MeteredPaymentsEngine`](super::MeteredPaymentsEngine)) only see the rejections of the transactions that can't be queued:
+/// the transfers between clients of different shards, which can't be applied atomically,
+/// and the transactions of a shard that failed ([`PaymentsEngineError::ShardFailed`]).
+/// The reports still include the accounts of a failed shard as they were when it failed.
+pub struct ShardedPaymentsEngine {
+  config: EngineConfig,
+  shards: Vec<Shard>,
+  rejections: mpsc::Receiver<Rejection>,
+}
+
+struct Shard {
+  sender: mpsc::Sender<Transaction>,
+  engine: Arc<Mutex<InMemoryPaymentsEngine>>,
+  state: Arc<ShardState>,
+}
+
+/// How many transactions are queued into a shard, or in process, and whether it failed,
+/// so the reports can wait for the shard to be idle.
+#[derive(Default)]
+struct ShardState {
+  progress: Mutex<Progress>,
+  idle: Condvar,
+}
+
+#[derive(Default)]
+struct Progress {
+  pending: usize,
+  failed: bool,
+}
+
+impl ShardState {
+  fn progress(&self) -> MutexGuard<Progress> {
+    self.progress.lock().unwrap_or_else(PoisonError::into_inner)
+  }
+
+  fn finish(&self) {
+    let mut progress = self.progress();
+    progress.pending = progress.pending.saturating_sub(1);
+    if progress.pending == 0 {
+      self.idle.notify_all();
+    }
+  }
+
+  fn wait_idle(&self) {
+    let mut progress = self.progress();
+    while progress.pending > 0 && !progress.failed {
+      progress = self
+        .idle
+        .wait(progress)
+        .unwrap_or_else(PoisonError::into_inner);
+    }
+  }
+}
+
+/// The loop of a shard, which rejects the transactions still queued when it stops,
+/// either because the engine was dropped or because processing a transaction panicked.
+struct Worker {
+  shard: usize,
+  receiver: mpsc::Receiver<Transaction>,
+  engine: Arc<Mutex<InMemoryPaymentsEngine>>,
+  state: Arc<ShardState>,
+  rejections: mpsc::Sender<Rejection>,
+}
+
+impl Worker {
+  fn run(&mut self) {
+    while let Some(transaction) = self.receiver.blocking_recv() {
+      let result = match self.engine.lock() {
+        Ok(mut engine) => engine.process_sync(transaction.clone()),
+        Err(_) => {
+          self.reject(transaction, PaymentsEngineError::ShardFailed(self.shard));
+          return;
+        }
+      };
+      if let Err(err) = result {
+        self.reject(transaction, err);
+      }
+      self.state.finish();
+    }
+  }
+
+  /// Keep the rejection to be taken, unless the rejections already kept are full, as they are not being taken.
+  fn reject(&self, transaction: Transaction, err: PaymentsEngineError) {
+    if let Err(TrySendError::Full(_)) = self.rejections.try_send((transaction, err)) {
+      tracing::warn!(
+        shard = self.shard,
+        "Rejection dropped, as the rejections of the shards are not being taken"
+      );
+    }
+  }
+}
+
+impl Drop for Worker {
+  fn drop(&mut self) {
+    self.receiver.close();
+    while let Ok(transaction) = self.receiver.try_recv() {
+      self.reject(transaction, PaymentsEngineError::ShardFailed(self.shard));
+    }
+    let mut progress = self.state.progress();
+    progress.failed = true;
+    progress.pending = 0;
+    self.state.idle.notify_all();
+  }
+}
+
+impl ShardedPaymentsEngine {
+  /// Start the threads of the shards, which stop when the engine is dropped.
+  pub fn new(shards: usize, config: EngineConfig) -> Self {
+    Self::with_capacity(shards, CHANNEL_CAPACITY, config)
+  }
+
+  /// Same as [`ShardedPaymentsEngine::new`] but with the maximum number of transactions waiting to be processed by every shard,
+  /// which is also the number of rejections of every shard kept until they are taken.
+  pub fn with_capacity(shards: usize, capacity: usize, config: EngineConfig) -> Self {
+    let shards = shards.max(1);
+    let capacity = capacity.max(1);
+    let (rejections_sender, rejections) = mpsc::channel(capacity.saturating_mul(shards));
+    let shards = (0..shards)
+      .map(|shard| {
+        let (sender, receiver) = mpsc::channel::<Transaction>(capacity);
+        let engine = Arc::new(Mutex::new(InMemoryPaymentsEngine::with_config(
+          config.clone(),
+        )));
+        let state = Arc::new(ShardState::default());
+        let mut worker = Worker {
+          shard,
+          receiver,
+          engine: engine.clone(),
+          state: state.clone(),
+          rejections: rejections_sender.clone(),
+        };
+        // processing is CPU bound, so the shards don't take the threads of the async tasks
+        tokio::task::spawn_blocking(move || worker.run());
+        Shard {
+          sender,
+          engine,
+          state,
+        }
+      })
+      .collect();
+
+    Self {
+      config,
+      shards,
+      rejections,
+    }
+  }
+
+  /// The transactions rejected by the shards since the last call, once they have processed all the queued transactions.
+  /// The rejections of every client are in the order of its transactions, but the ones of different shards are interleaved.
+  pub fn rejections(&mut self) -> Vec<(Transaction, PaymentsEngineError)> {
+    for shard in &self.shards {
+      shard.state.wait_idle();
+    }
+    let mut rejections = Vec::new();
+    while let Ok(rejection) = self.rejections.try_recv() {
+      rejections.push(rejection);
+    }
+    rejections
+  }
+
+  fn shard_of(&self, client_id: ClientId) -> usize {
+    client_id as usize % self.shards.len()
+  }
+
+  /// The shard that processes the transaction, unless it is a transfer to a client of another shard.
+  fn route(&self, transaction: &Transaction) -> Result<usize> {
+    let shard = self.shard_of(transaction.client_id());
+    if let Transaction::Transfer {
+      from_client,
+      to_client,
+      ..
+    } = *transaction
+    {
+      if self.shard_of(to_client) != shard {
+        return Err(PaymentsEngineError::CrossShardTransfer(
+          from_client,
+          to_client,
+        ));
+      }
+    }
+    Ok(shard)
+  }
+
+  /// The engine of the shard, once it has processed all the queued transactions.
+  /// The engine of a failed shard is still returned, with the state it had when it failed.
+  fn shard_engine(&self, shard: usize) -> MutexGuard<InMemoryPaymentsEngine> {
+    let shard = &self.shards[shard];
+    shard.state.wait_idle();
+    shard.engine.lock().unwrap_or_else(PoisonError::into_inner)
+  }
+}
+
+#[async_trait]
+impl PaymentsEngine for ShardedPaymentsEngine {
+  async fn process(&mut self, transaction: Transaction) -> Result<()> {
+    let shard = self.route(&transaction)?;
+    let Shard {
+      sender,
+      engine,
+      state,
+    } = &self.shards[shard];
+
+    {
+      let mut progress = state.progress();
+      if progress.failed || engine.is_poisoned() {
+        return Err(PaymentsEngineError::ShardFailed(shard));
+      }
+      progress.pending += 1;
+    }
+    sender.send(transaction).await.map_err(|_| {
+      state.finish();
+      PaymentsEngineError::ShardFailed(shard)
+    })
+  }
+
+  fn validate(&self, transaction: &Transaction) -> Result<()> {
+    let shard = self.route(transaction)?;
+    self.shard_engine(shard).validate(transaction)
+  }
+
+  fn accounts_report(&self) -> AccountsReportIter {
+    self.accounts_matching(AccountFilter::default())
+  }
+
+  fn accounts_matching(&self, filter: AccountFilter) -> AccountsReportIter {
+    let mut report = Vec::new();
+    for shard in 0..self.shards.len() {
+      report.extend(self.shard_engine(shard).accounts_matching(filter.clone()));
+    }
+    if self.config.deterministic {
+      report.sort_by_key(|account_report| account_report.client_id);
+    }
+    AccountsReportIter::new(report.into_iter())
+  }
+
+  fn account(&self, client_id: ClientId) -> Option<AccountReport> {
+    self
+      .shard_engine(self.shard_of(client_id))
+      .account(client_id)
+  }
+
+  fn transaction(
+    &self,
+    client_id: ClientId,
+    transaction_id: TransactionId,
+  ) -> Result<Option<TransactionInfo>> {
+    self
+      .shard_engine(self.shard_of(client_id))
+      .transaction(client_id, transaction_id)
+  }
+
+  fn transactions_report(&self) -> Result<TransactionsReportIter> {
+    let mut report = Vec::new();
+    for shard in 0..self.shards.len() {
+      report.extend(self.shard_engine(shard).transactions_report()?);
+    }
+    if self.config.deterministic {
+      report.sort_by_key(|transaction| (transaction.client_id, transaction.transaction_id));
+    }
+    Ok(TransactionsReportIter::new(report.into_iter()))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+
+  use rust_decimal::Decimal;
+  use rust_decimal_macros::dec;
+
+  use super::*;
+
+  fn transactions() -> Vec<Transaction> {
+    let mut transactions = Vec::new();
+    for transaction_id in 10..=1000u32 {
+      let client_id = (transaction_id % 17) as ClientId;
+      let amount = Decimal::from(transaction_id % 50);
+      transactions.push(match transaction_id % 5 {
+        0 => Transaction::Withdrawal {
+          client_id,
+          transaction_id,
+          amount,
+          timestamp: None,
+          sub_account: 0,
+        },
+        1 => Transaction::Dispute {
+          client_id,
+          transaction_id: transaction_id - 5,
+          timestamp: None,
+        },
+        2 => Transaction::Chargeback {
+          client_id,
+          transaction_id: transaction_id - 6,
+          timestamp: None,
+        },
+        _ => Transaction::Deposit {
+          client_id,
+          transaction_id,
+          amount,
+          timestamp: None,
+          sub_account: 0,
+        },
+      });
+    }
+    transactions
+  }
+
+  #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+  async fn process_the_same_than_a_single_engine() {
+    let config = EngineConfig {
+      deterministic: true,
+      ..EngineConfig::default()
+    };
+    let mut engine = ShardedPaymentsEngine::new(4, config.clone());
+    let mut reference = InMemoryPaymentsEngine::with_config(config);
+
+    let mut expected_rejections = Vec::new();
+    for transaction in transactions() {
+      assert_eq!(engine.process(transaction.clone()).await, Ok(()));
+      if let Err(err) = reference.process(transaction.clone()).await {
+        expected_rejections.push((transaction, err));
+      }
+    }
+
+    assert_eq!(
+      engine.accounts_report().collect::<Vec<_>>(),
+      reference.accounts_report().collect::<Vec<_>>()
+    );
+    assert_eq!(engine.account(3), reference.account(3));
+    assert_eq!(engine.transaction(3, 3), reference.transaction(3, 3));
+
+    // the rejections of every client keep their order, so a stable sort by client makes them comparable
+    let mut rejections = engine.rejections();
+    rejections.sort_by_key(|(transaction, _)| transaction.client_id());
+    expected_rejections.sort_by_key(|(transaction, _)| transaction.client_id());
+    assert!(!rejections.is_empty());
+    assert_eq!(rejections, expected_rejections);
+    assert!(engine.rejections().is_empty());
+  }
+
+  #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+  async fn process_transfers_within_a_shard() {
+    let mut engine = ShardedPaymentsEngine::new(2, EngineConfig::default());
+
+    for client_id in 1..=3 {
+      let deposit = Transaction::Deposit {
+        client_id,
+        transaction_id: client_id as TransactionId,
+        amount: dec!(100),
+        timestamp: None,
+        sub_account: 0,
+      };
+      engine.process(deposit).await.unwrap();
+    }
+    let transfer = |from_client, to_client, transaction_id| Transaction::Transfer {
+      from_client,
+      to_client,
+      transaction_id,
+      amount: dec!(10),
+      timestamp: None,
+    };
+
+    assert_eq!(engine.process(transfer(1, 3, 4)).await, Ok(()));
+    assert_eq!(
+      engine.validate(&transfer(1, 2, 5)),
+      Err(PaymentsEngineError::CrossShardTransfer(1, 2))
+    );
+    assert_eq!(
+      engine.process(transfer(1, 2, 5)).await,
+      Err(PaymentsEngineError::CrossShardTransfer(1, 2))
+    );
+
+    let balances: Vec<(ClientId, Decimal)> = (1..=3)
+      .map(|client_id| (client_id, engine.account(client_id).unwrap().available))
+      .collect();
+    assert_eq!(
+      balances,
+      vec![(1, dec!(90)), (2, dec!(100)), (3, dec!(110))]
+    );
+  }
+
+  #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+  async fn collect_the_rejections_of_the_shards() {
+    let mut engine = ShardedPaymentsEngine::new(2, EngineConfig::default());
+    let deposit = Transaction::Deposit {
+      client_id: 1,
+      transaction_id: 1,
+      amount: dec!(5),
+      timestamp: None,
+      sub_account: 0,
+    };
+    let withdrawal = Transaction::Withdrawal {
+      client_id: 1,
+      transaction_id: 2,
+      amount: dec!(10),
+      timestamp: None,
+      sub_account: 0,
+    };
+
+    assert_eq!(engine.process(deposit).await, Ok(()));
+    assert_eq!(engine.process(withdrawal.clone()).await, Ok(()));
+    assert_eq!(
+      engine.rejections(),
+      vec![(withdrawal, PaymentsEngineError::NotEnoughAvailableFunds)]
+    );
+    assert_eq!(engine.account(1).unwrap().available, dec!(5));
+  }
+
+  #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+  async fn keep_the_rejections_up_to_the_capacity() {
+    let mut engine = ShardedPaymentsEngine::with_capacity(1, 2, EngineConfig::default());
+    let withdrawal = |transaction_id| Transaction::Withdrawal {
+      client_id: 1,
+      transaction_id,
+      amount: dec!(10),
+      timestamp: None,
+      sub_account: 0,
+    };
+
+    for transaction_id in 1..=5 {
+      assert_eq!(engine.process(withdrawal(transaction_id)).await, Ok(()));
+    }
+    assert_eq!(
+      engine.rejections(),
+      vec![
+        (withdrawal(1), PaymentsEngineError::ClientNotFound(1)),
+        (withdrawal(2), PaymentsEngineError::ClientNotFound(1)),
+      ]
+    );
+    assert!(engine.rejections().is_empty());
+  }
+
+  #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+  async fn reject_the_transactions_of_a_failed_shard() {
+    let mut engine = ShardedPaymentsEngine::new(2, EngineConfig::default());
+    let deposit = |client_id, transaction_id| Transaction::Deposit {
+      client_id,
+      transaction_id,
+      amount: dec!(10),
+      timestamp: None,
+      sub_account: 0,
+    };
+    engine.process(deposit(1, 1)).await.unwrap();
+    assert_eq!(engine.account(1).unwrap().available, dec!(10));
+
+    let shard_engine = engine.shards[1].engine.clone();
+    std::thread::spawn(move || {
+      let _engine = shard_engine.lock().unwrap();
+      panic!("Injected panic");
+    })
+    .join()
+    .ok();
+
+    assert_eq!(
+      engine.process(deposit(3, 2)).await,
+      Err(PaymentsEngineError::ShardFailed(1))
+    );
+    assert_eq!(engine.process(deposit(2, 3)).await, Ok(()));
+    assert_eq!(engine.accounts_report().count(), 2);
+    assert_eq!(engine.account(1).unwrap().available, dec!(10));
+    assert!(engine.rejections().is_empty());
+  }
+}
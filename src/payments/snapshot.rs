@@ -0,0 +1,333 @@
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::io::{Read, Write};
+
+use serde::{Deserialize, Serialize};
+
+use super::{
+  account::Account,
+  engine::{PaymentsEngineError, Result},
+  limits::RecentActivity,
+  transaction::ClientId,
+};
+
+/// The bytes that every snapshot container starts with.
+const MAGIC: &[u8; 8] = b"TPESNAP\0";
+
+/// The version of the layout of the snapshot containers, which is only increased when the older crates can't read them anymore.
+/// The fields added to the snapshot don't need a new version, as the older crates ignore them and the newer ones default them.
+pub const SNAPSHOT_FORMAT_VERSION: u16 = 1;
+
+/// The compressions of the payload of the snapshot containers.
+const UNCOMPRESSED: u8 = 0;
+const ZSTD: u8 = 1;
+
+/// A copy of the state of the accounts of an [`InMemoryPaymentsEngine`](super::InMemoryPaymentsEngine),
+/// including the transactions recorded to detect duplicates and resolve disputes.
+///
+/// It can be serialized to checkpoint long running jobs, so they can be resumed after a crash
+/// with [`InMemoryPaymentsEngine::restore`](super::InMemoryPaymentsEngine::restore)
+/// without replaying the whole input. It records the digest of the configuration of the engine
+/// (see [`EngineConfig::digest`](super::EngineConfig::digest)), so it is only restored under the same policies.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Snapshot {
+  pub(super) config_digest: String,
+  pub(super) accounts: HashMap<ClientId, Account>,
+  #[serde(default)]
+  pub(super) clock: u64,
+  /// The recent activity of the clients, only tracked to enforce the [`LimitsPolicy`](super::LimitsPolicy).
+  #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+  pub(super) activity: HashMap<ClientId, RecentActivity>,
+}
+
+impl Snapshot {
+  /// The digest of the configuration of the engine that created the snapshot.
+  pub fn config_digest(&self) -> &str {
+    &self.config_digest
+  }
+
+  /// Write the snapshot into a versioned container, so it can be restored by later versions of the crate.
+  /// The container has the [`MAGIC`] bytes, the [`SNAPSHOT_FORMAT_VERSION`], the compression of the payload,
+  /// the length and bytes of the configuration digest, and the payload itself, which is the snapshot serialized as JSON.
+  /// The payload is compressed with zstd when the `compressed-snapshots` feature is enabled.
+  pub fn write_to<W: Write>(&self, mut writer: W) -> Result<()> {
+    let payload = serde_json::to_vec(self).map_err(snapshot_error)?;
+    let (compression, payload) = compress(payload)?;
+    let digest = self.config_digest.as_bytes();
+    let digest_len = u16::try_from(digest.len()).map_err(snapshot_error)?;
+
+    writer.write_all(MAGIC).map_err(snapshot_error)?;
+    writer
+      .write_all(&SNAPSHOT_FORMAT_VERSION.to_be_bytes())
+      .map_err(snapshot_error)?;
+    writer.write_all(&[compression]).map_err(snapshot_error)?;
+    writer
+      .write_all(&digest_len.to_be_bytes())
+      .map_err(snapshot_error)?;
+    writer.write_all(digest).map_err(snapshot_error)?;
+    writer.write_all(&payload).map_err(snapshot_error)?;
+    writer.flush().map_err(snapshot_error)
+  }
+
+  /// Read a snapshot written by [`Snapshot::write_to`] with the same or an older format version.
+  /// Containers of newer versions are refused with [`PaymentsEngineError::SnapshotVersionMismatch`].
+  pub fn read_from<R: Read>(mut reader: R) -> Result<Self> {
+    let mut magic = [0u8; 8];
+    reader.read_exact(&mut magic).map_err(snapshot_error)?;
+    if &magic != MAGIC {
+      return Err(PaymentsEngineError::Snapshot(
+        "not a snapshot container".to_string(),
+      ));
+    }
+
+    let mut version = [0u8; 2];
+    reader.read_exact(&mut version).map_err(snapshot_error)?;
+    let version = u16::from_be_bytes(version);
+    if version == 0 || version > SNAPSHOT_FORMAT_VERSION {
+      return Err(PaymentsEngineError::SnapshotVersionMismatch(version));
+    }
+
+    let mut compression = [0u8; 1];
+    reader
+      .read_exact(&mut compression)
+      .map_err(snapshot_error)?;
+    let mut digest_len = [0u8; 2];
+    reader.read_exact(&mut digest_len).map_err(snapshot_error)?;
+    let mut digest = vec![0u8; usize::from(u16::from_be_bytes(digest_len))];
+    reader.read_exact(&mut digest).map_err(snapshot_error)?;
+    let mut payload = Vec::new();
+    reader.read_to_end(&mut payload).map_err(snapshot_error)?;
+
+    let payload = decompress(compression[0], payload)?;
+    let snapshot: Snapshot = serde_json::from_slice(&payload).map_err(snapshot_error)?;
+    if snapshot.config_digest.as_bytes() != digest.as_slice() {
+      return Err(PaymentsEngineError::Snapshot(
+        "the digest of the container doesn't match its payload".to_string(),
+      ));
+    }
+    Ok(snapshot)
+  }
+}
+
+fn snapshot_error<E: ToString>(err: E) -> PaymentsEngineError {
+  PaymentsEngineError::Snapshot(err.to_string())
+}
+
+#[cfg(feature = "compressed-snapshots")]
+fn compress(payload: Vec<u8>) -> Result<(u8, Vec<u8>)> {
+  zstd::encode_all(payload.as_slice(), 0)
+    .map(|compressed| (ZSTD, compressed))
+    .map_err(snapshot_error)
+}
+
+#[cfg(not(feature = "compressed-snapshots"))]
+fn compress(payload: Vec<u8>) -> Result<(u8, Vec<u8>)> {
+  Ok((UNCOMPRESSED, payload))
+}
+
+fn decompress(compression: u8, payload: Vec<u8>) -> Result<Vec<u8>> {
+  match compression {
+    UNCOMPRESSED => Ok(payload),
+    #[cfg(feature = "compressed-snapshots")]
+    ZSTD => zstd::decode_all(payload.as_slice()).map_err(snapshot_error),
+    #[cfg(not(feature = "compressed-snapshots"))]
+    ZSTD => Err(PaymentsEngineError::Snapshot(
+      "the payload is compressed with zstd, which needs the `compressed-snapshots` feature"
+        .to_string(),
+    )),
+    other => Err(PaymentsEngineError::Snapshot(format!(
+      "unknown compression of the payload: {}",
+      other
+    ))),
+  }
+}
+
+#[cfg(all(test, feature = "async"))]
+mod tests {
+
+  use rust_decimal_macros::dec;
+
+  use super::*;
+  use crate::payments::{
+    AccountReport, EngineConfig, InMemoryPaymentsEngine, PaymentsEngine, PaymentsEngineError,
+    Transaction,
+  };
+
+  /// An uncompressed container of the version with the payload, as written by other versions of the crate.
+  fn container(version: u16, payload: &str) -> Vec<u8> {
+    let digest = EngineConfig::default().digest();
+    let mut bytes = MAGIC.to_vec();
+    bytes.extend_from_slice(&version.to_be_bytes());
+    bytes.push(UNCOMPRESSED);
+    bytes.extend_from_slice(&(digest.len() as u16).to_be_bytes());
+    bytes.extend_from_slice(digest.as_bytes());
+    bytes.extend_from_slice(payload.replace("{digest}", &digest).as_bytes());
+    bytes
+  }
+
+  #[tokio::test]
+  async fn read_written_snapshot_container() {
+    let mut engine = InMemoryPaymentsEngine::new();
+    engine
+      .process(Transaction::Deposit {
+        client_id: 1,
+        transaction_id: 101,
+        amount: dec!(10),
+        timestamp: None,
+        sub_account: 0,
+      })
+      .await
+      .unwrap();
+    let snapshot = engine.snapshot().unwrap();
+
+    let mut bytes = Vec::new();
+    snapshot.write_to(&mut bytes).unwrap();
+
+    assert!(bytes.starts_with(MAGIC));
+    assert_eq!(Snapshot::read_from(bytes.as_slice()), Ok(snapshot));
+  }
+
+  #[test]
+  fn read_snapshot_container_with_unknown_fields() {
+    let bytes = container(
+      SNAPSHOT_FORMAT_VERSION,
+      r#"{"config_digest":"{digest}","accounts":{},"clock":5,"added_later":true}"#,
+    );
+
+    let snapshot = Snapshot::read_from(bytes.as_slice()).unwrap();
+
+    assert_eq!(snapshot.clock, 5);
+    assert!(snapshot.accounts.is_empty());
+  }
+
+  #[test]
+  fn read_invalid_snapshot_containers() {
+    let payload = r#"{"config_digest":"{digest}","accounts":{}}"#;
+
+    assert_eq!(
+      Snapshot::read_from(container(SNAPSHOT_FORMAT_VERSION + 1, payload).as_slice()),
+      Err(PaymentsEngineError::SnapshotVersionMismatch(
+        SNAPSHOT_FORMAT_VERSION + 1
+      ))
+    );
+    assert_eq!(
+      Snapshot::read_from(&b"{\"config_digest\":\"\"}"[..]),
+      Err(PaymentsEngineError::Snapshot(
+        "not a snapshot container".to_string()
+      ))
+    );
+    assert_eq!(
+      Snapshot::read_from(
+        container(
+          SNAPSHOT_FORMAT_VERSION,
+          r#"{"config_digest":"other","accounts":{}}"#
+        )
+        .as_slice()
+      ),
+      Err(PaymentsEngineError::Snapshot(
+        "the digest of the container doesn't match its payload".to_string()
+      ))
+    );
+  }
+
+  #[tokio::test]
+  async fn restore_serialized_snapshot() {
+    let mut engine = InMemoryPaymentsEngine::new();
+    let transactions = vec![
+      Transaction::Deposit {
+        client_id: 1,
+        transaction_id: 101,
+        amount: dec!(100.5),
+        timestamp: None,
+        sub_account: 0,
+      },
+      Transaction::Deposit {
+        client_id: 2,
+        transaction_id: 201,
+        amount: dec!(20),
+        timestamp: None,
+        sub_account: 0,
+      },
+      Transaction::Dispute {
+        client_id: 2,
+        transaction_id: 201,
+        timestamp: None,
+      },
+    ];
+    for transaction in transactions {
+      engine.process(transaction).await.unwrap();
+    }
+
+    let checkpoint = serde_json::to_string(&engine.snapshot().unwrap()).unwrap();
+    let mut restored = InMemoryPaymentsEngine::new();
+    restored
+      .restore(serde_json::from_str(&checkpoint).unwrap())
+      .unwrap();
+
+    assert_eq!(restored.snapshot(), engine.snapshot());
+    assert_eq!(
+      restored
+        .process(Transaction::Deposit {
+          client_id: 1,
+          transaction_id: 101,
+          amount: dec!(1),
+          timestamp: None,
+          sub_account: 0,
+        })
+        .await,
+      Err(PaymentsEngineError::DuplicatedTransaction(101))
+    );
+    assert_eq!(
+      restored
+        .process(Transaction::Chargeback {
+          client_id: 2,
+          transaction_id: 201,
+          timestamp: None,
+        })
+        .await,
+      Ok(())
+    );
+
+    let mut report: Vec<AccountReport> = restored.accounts_report().collect();
+    report.sort_by_key(|account_report| account_report.client_id);
+    assert_eq!(
+      report,
+      vec![
+        AccountReport::new(1, dec!(100.5), dec!(0), dec!(100.5), false),
+        AccountReport::new(2, dec!(0), dec!(0), dec!(0), true).with_disputes(0, dec!(20)),
+      ]
+    );
+  }
+
+  #[tokio::test]
+  async fn restore_with_different_config() {
+    let mut engine = InMemoryPaymentsEngine::new();
+    engine
+      .process(Transaction::Deposit {
+        client_id: 1,
+        transaction_id: 101,
+        amount: dec!(10),
+        timestamp: None,
+        sub_account: 0,
+      })
+      .await
+      .unwrap();
+    let snapshot = engine.snapshot().unwrap();
+
+    let mut restored = InMemoryPaymentsEngine::with_config(EngineConfig {
+      max_open_disputes: Some(1),
+      ..EngineConfig::default()
+    });
+
+    assert_eq!(
+      restored.restore(snapshot.clone()),
+      Err(PaymentsEngineError::ConfigChanged(
+        snapshot.config_digest().to_string()
+      ))
+    );
+    assert_eq!(restored.accounts_report().count(), 0);
+
+    restored.restore_with_config_change(snapshot).unwrap();
+    assert_eq!(restored.accounts_report().count(), 1);
+  }
+}
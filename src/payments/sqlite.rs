@@ -0,0 +1,658 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::path::Path;
+
+use async_trait::async_trait;
+use futures::{future, stream, StreamExt};
+use sqlx::sqlite::{
+  SqliteConnectOptions, SqliteConnection, SqlitePool, SqlitePoolOptions, SqliteRow,
+};
+use sqlx::Row;
+use tokio::sync::Mutex;
+
+use super::{
+  account::{Account, AccountReport, TransactionInfo},
+  batch::WriteBatch,
+  config::EngineConfig,
+  engine::{
+    AccountsReportIter, AccountsReportStream, InMemoryPaymentsEngine, PaymentsEngine,
+    PaymentsEngineError, Result, SyncPaymentsEngine, TransactionsReportIter,
+  },
+  filter::AccountFilter,
+  limits::RecentActivity,
+  snapshot::Snapshot,
+  transaction::{ClientId, Transaction, TransactionId},
+};
+
+const SCHEMA: &[&str] = &[
+  "CREATE TABLE IF NOT EXISTS accounts (client_id INTEGER PRIMARY KEY, account TEXT NOT NULL)",
+  "CREATE TABLE IF NOT EXISTS engine (key TEXT PRIMARY KEY, value TEXT NOT NULL)",
+  "CREATE TABLE IF NOT EXISTS activity (client_id INTEGER PRIMARY KEY, activity TEXT NOT NULL)",
+];
+
+/// The maximum number of rows written by every statement of a batch, below the limit of variables of SQLite.
+const BATCH_ROWS: usize = 400;
+
+/// A [`PaymentsEngine`] that keeps the accounts in a SQLite database, with the same policies than the [`InMemoryPaymentsEngine`].
+///
+/// Every [`PaymentsEngine::process`] runs inside its own database transaction, which loads the accounts involved,
+/// applies the transaction to them, and stores them back only when it is accepted. The transaction is started as
+/// `IMMEDIATE`, so several processors can share the database, and a restart continues from the last accepted transaction.
+/// The digest of the configuration is stored with the accounts, and opening the database with a different one is refused.
+///
+/// With a write batch (see [`SqlitePaymentsEngine::with_write_batch`]), the accounts are cached in memory instead,
+/// and their writes are flushed together once every batch of transactions.
+///
+/// The lookups and the reports read from the database blocking the thread, so it needs to run in a multi-threaded runtime.
+pub struct SqlitePaymentsEngine {
+  pool: SqlitePool,
+  config: EngineConfig,
+  batch: Option<Mutex<WriteBatch>>,
+}
+
+impl SqlitePaymentsEngine {
+  /// Open the database at the path, creating it if needed.
+  pub async fn open<P: AsRef<Path>>(path: P, config: EngineConfig) -> anyhow::Result<Self> {
+    let options = SqliteConnectOptions::new()
+      .filename(path)
+      .create_if_missing(true);
+    let pool = SqlitePoolOptions::new().connect_with(options).await?;
+    Self::new(pool, config).await
+  }
+
+  /// Use the database of the pool, creating the tables if needed.
+  pub async fn new(pool: SqlitePool, config: EngineConfig) -> anyhow::Result<Self> {
+    for statement in SCHEMA {
+      sqlx::query(statement).execute(&pool).await?;
+    }
+
+    let config_digest = config.digest();
+    let stored_digest: Option<String> =
+      sqlx::query_scalar("SELECT value FROM engine WHERE key = 'config_digest'")
+        .fetch_optional(&pool)
+        .await?;
+    match stored_digest {
+      Some(stored_digest) if stored_digest != config_digest => {
+        return Err(PaymentsEngineError::ConfigChanged(stored_digest).into());
+      }
+      Some(_) => {}
+      None => {
+        sqlx::query("INSERT INTO engine (key, value) VALUES ('config_digest', ?)")
+          .bind(config_digest)
+          .execute(&pool)
+          .await?;
+      }
+    }
+
+    Ok(Self {
+      pool,
+      config,
+      batch: None,
+    })
+  }
+
+  /// Coalesce the writes of the accounts in memory, and flush them into the database in a single database transaction
+  /// with batched statements once every `size` transactions, and before reporting all the accounts.
+  ///
+  /// The position of the write-ahead log reached by the transactions flushed is stored with them (see [`SqlitePaymentsEngine::position`]),
+  /// as the ones processed since the last flush are lost when the process stops, and have to be replayed from the log.
+  /// Failing to flush doesn't reject the transaction, which is kept in the batch and flushed with the next one.
+  /// The accounts are not locked while they are cached, so the database can't be shared with other processes.
+  pub async fn with_write_batch(mut self, size: usize) -> anyhow::Result<Self> {
+    let position = self.position().await?;
+    self.batch = Some(Mutex::new(WriteBatch::new(size, position)));
+    Ok(self)
+  }
+
+  /// The position of the write-ahead log whose transactions are all in the database, as flushed by the write batches.
+  pub async fn position(&self) -> anyhow::Result<u64> {
+    let position: Option<String> =
+      sqlx::query_scalar("SELECT value FROM engine WHERE key = 'position'")
+        .fetch_optional(&self.pool)
+        .await?;
+    Ok(
+      position
+        .map(|position| position.parse::<u64>())
+        .transpose()?
+        .unwrap_or_default(),
+    )
+  }
+
+  /// Load the accounts of the clients into an engine, or all of them when no clients are given.
+  async fn load(
+    &self,
+    conn: &mut SqliteConnection,
+    clients: Option<&[ClientId]>,
+  ) -> sqlx::Result<InMemoryPaymentsEngine> {
+    let mut engine = InMemoryPaymentsEngine::with_config(self.config.clone());
+    engine
+      .restore_with_config_change(self.load_snapshot(conn, clients).await?)
+      .map_err(|err| sqlx::Error::Decode(err.into()))?;
+    Ok(engine)
+  }
+
+  /// Load the accounts of the clients, or all of them when no clients are given.
+  /// The recent activity of the clients is loaded too, as the limits of the configuration may need it.
+  async fn load_snapshot(
+    &self,
+    conn: &mut SqliteConnection,
+    clients: Option<&[ClientId]>,
+  ) -> sqlx::Result<Snapshot> {
+    let rows = fetch_rows(conn, "accounts", "account", clients).await?;
+    let mut accounts = HashMap::with_capacity(rows.len());
+    for row in rows {
+      let (client_id, account) = decode_account(&row)?;
+      accounts.insert(client_id, account);
+    }
+
+    let rows = fetch_rows(conn, "activity", "activity", clients).await?;
+    let mut activity = HashMap::with_capacity(rows.len());
+    for row in rows {
+      let client_id: ClientId = row.try_get("client_id")?;
+      let recent: String = row.try_get("activity")?;
+      let recent: RecentActivity =
+        serde_json::from_str(&recent).map_err(|err| sqlx::Error::Decode(err.into()))?;
+      activity.insert(client_id, recent);
+    }
+
+    let clock: Option<String> = sqlx::query_scalar("SELECT value FROM engine WHERE key = 'clock'")
+      .fetch_optional(&mut *conn)
+      .await?;
+    let clock = clock
+      .map(|clock| clock.parse::<u64>())
+      .transpose()
+      .map_err(|err| sqlx::Error::Decode(err.into()))?
+      .unwrap_or_default();
+
+    Ok(Snapshot {
+      config_digest: self.config.digest(),
+      accounts,
+      clock,
+      activity,
+    })
+  }
+
+  /// Store the accounts and the recent activity of the clients, and the clock of the engine.
+  async fn store(
+    conn: &mut SqliteConnection,
+    engine: &InMemoryPaymentsEngine,
+    clients: &[ClientId],
+  ) -> sqlx::Result<()> {
+    let snapshot = engine
+      .snapshot()
+      .map_err(|err| sqlx::Error::Decode(err.into()))?;
+    for client_id in clients {
+      if let Some(account) = snapshot.accounts.get(client_id) {
+        let account =
+          serde_json::to_string(account).map_err(|err| sqlx::Error::Decode(err.into()))?;
+        sqlx::query(
+          "INSERT INTO accounts (client_id, account) VALUES (?, ?)
+           ON CONFLICT (client_id) DO UPDATE SET account = excluded.account",
+        )
+        .bind(*client_id)
+        .bind(account)
+        .execute(&mut *conn)
+        .await?;
+      }
+      if let Some(activity) = snapshot.activity.get(client_id) {
+        let activity =
+          serde_json::to_string(activity).map_err(|err| sqlx::Error::Decode(err.into()))?;
+        sqlx::query(
+          "INSERT INTO activity (client_id, activity) VALUES (?, ?)
+           ON CONFLICT (client_id) DO UPDATE SET activity = excluded.activity",
+        )
+        .bind(*client_id)
+        .bind(activity)
+        .execute(&mut *conn)
+        .await?;
+      }
+    }
+    sqlx::query(
+      "INSERT INTO engine (key, value) VALUES ('clock', ?)
+       ON CONFLICT (key) DO UPDATE SET value = excluded.value",
+    )
+    .bind(snapshot.clock.to_string())
+    .execute(&mut *conn)
+    .await?;
+    Ok(())
+  }
+
+  async fn apply(&self, conn: &mut SqliteConnection, transaction: Transaction) -> Result<()> {
+    let clients = transaction.clients();
+    let mut engine = self
+      .load(conn, Some(&clients))
+      .await
+      .map_err(database_error)?;
+    engine.process_sync(transaction)?;
+    Self::store(conn, &engine, &clients)
+      .await
+      .map_err(database_error)
+  }
+
+  /// An engine with the accounts of the clients cached by the write batch, loading the ones that are not cached yet.
+  async fn load_batched(
+    &self,
+    conn: &mut SqliteConnection,
+    batch: &mut WriteBatch,
+    clients: &[ClientId],
+  ) -> Result<InMemoryPaymentsEngine> {
+    let missing = batch.missing(clients);
+    if !missing.is_empty() {
+      let loaded = self
+        .load_snapshot(conn, Some(&missing))
+        .await
+        .map_err(database_error)?;
+      batch.insert(&missing, loaded);
+    }
+    batch.engine(&self.config, clients)
+  }
+
+  async fn apply_batched(&self, batch: &mut WriteBatch, transaction: Transaction) -> Result<()> {
+    let mut conn = self.pool.acquire().await.map_err(database_error)?;
+    let clients = transaction.clients();
+    let mut engine = self.load_batched(&mut conn, batch, &clients).await?;
+    engine.process_sync(transaction)?;
+    batch.update(&engine, &clients)?;
+    if batch.is_full() {
+      if let Err(err) = Self::flush(&mut conn, batch).await {
+        tracing::warn!(error = %err, position = batch.position(), "Failed to flush the write batch");
+      }
+    }
+    Ok(())
+  }
+
+  /// Write the accounts changed by the batch, and the position of the log they reach, in a single database transaction.
+  async fn flush(conn: &mut SqliteConnection, batch: &mut WriteBatch) -> sqlx::Result<()> {
+    if batch.is_empty() {
+      return Ok(());
+    }
+    sqlx::query("BEGIN IMMEDIATE").execute(&mut *conn).await?;
+    let result = Self::store_batch(conn, batch).await;
+    let end = if result.is_ok() { "COMMIT" } else { "ROLLBACK" };
+    sqlx::query(end).execute(&mut *conn).await?;
+    result?;
+    batch.clear();
+    Ok(())
+  }
+
+  async fn store_batch(conn: &mut SqliteConnection, batch: &WriteBatch) -> sqlx::Result<()> {
+    for rows in batch.changed_accounts().chunks(BATCH_ROWS) {
+      let query = format!(
+        "INSERT INTO accounts (client_id, account) VALUES {}
+         ON CONFLICT (client_id) DO UPDATE SET account = excluded.account",
+        vec!["(?, ?)"; rows.len()].join(", ")
+      );
+      let mut query = sqlx::query(&query);
+      for (client_id, account) in rows {
+        let account =
+          serde_json::to_string(account).map_err(|err| sqlx::Error::Decode(err.into()))?;
+        query = query.bind(*client_id).bind(account);
+      }
+      query.execute(&mut *conn).await?;
+    }
+    for rows in batch.changed_activity().chunks(BATCH_ROWS) {
+      let query = format!(
+        "INSERT INTO activity (client_id, activity) VALUES {}
+         ON CONFLICT (client_id) DO UPDATE SET activity = excluded.activity",
+        vec!["(?, ?)"; rows.len()].join(", ")
+      );
+      let mut query = sqlx::query(&query);
+      for (client_id, activity) in rows {
+        let activity =
+          serde_json::to_string(activity).map_err(|err| sqlx::Error::Decode(err.into()))?;
+        query = query.bind(*client_id).bind(activity);
+      }
+      query.execute(&mut *conn).await?;
+    }
+    sqlx::query(
+      "INSERT INTO engine (key, value) VALUES ('clock', ?), ('position', ?)
+       ON CONFLICT (key) DO UPDATE SET value = excluded.value",
+    )
+    .bind(batch.clock().to_string())
+    .bind(batch.position().to_string())
+    .execute(&mut *conn)
+    .await?;
+    Ok(())
+  }
+
+  /// Flush the write batch, if any, so the database has all the accounts.
+  async fn flush_batch(&self, conn: &mut SqliteConnection) -> sqlx::Result<()> {
+    match &self.batch {
+      Some(batch) => Self::flush(conn, &mut *batch.lock().await).await,
+      None => Ok(()),
+    }
+  }
+
+  /// An engine with the accounts of the clients (or all of them), read blocking the thread.
+  /// The accounts of the clients are read from the write batch, if any, which is flushed before reading all of them.
+  fn load_blocking(&self, clients: Option<&[ClientId]>) -> Result<InMemoryPaymentsEngine> {
+    block_on(async {
+      let mut conn = self.pool.acquire().await.map_err(database_error)?;
+      match (&self.batch, clients) {
+        (Some(batch), Some(clients)) => {
+          let mut batch = batch.lock().await;
+          self.load_batched(&mut conn, &mut batch, clients).await
+        }
+        _ => {
+          self.flush_batch(&mut conn).await.map_err(database_error)?;
+          self.load(&mut conn, clients).await.map_err(database_error)
+        }
+      }
+    })
+  }
+}
+
+/// Fetch the rows of the table for the clients, or all of them when no clients are given.
+async fn fetch_rows(
+  conn: &mut SqliteConnection,
+  table: &str,
+  column: &str,
+  clients: Option<&[ClientId]>,
+) -> sqlx::Result<Vec<SqliteRow>> {
+  match clients {
+    Some(clients) => {
+      let query = format!(
+        "SELECT client_id, {} FROM {} WHERE client_id = ?",
+        column, table
+      );
+      let mut rows = Vec::with_capacity(clients.len());
+      for client_id in clients {
+        let row = sqlx::query(&query)
+          .bind(*client_id)
+          .fetch_optional(&mut *conn)
+          .await?;
+        rows.extend(row);
+      }
+      Ok(rows)
+    }
+    None => {
+      let query = format!("SELECT client_id, {} FROM {}", column, table);
+      sqlx::query(&query).fetch_all(&mut *conn).await
+    }
+  }
+}
+
+fn decode_account(row: &SqliteRow) -> sqlx::Result<(ClientId, Account)> {
+  let client_id: ClientId = row.try_get("client_id")?;
+  let account: String = row.try_get("account")?;
+  let account: Account =
+    serde_json::from_str(&account).map_err(|err| sqlx::Error::Decode(err.into()))?;
+  Ok((client_id, account))
+}
+
+fn database_error(err: sqlx::Error) -> PaymentsEngineError {
+  PaymentsEngineError::Database(err.to_string())
+}
+
+fn block_on<F: Future>(future: F) -> F::Output {
+  tokio::task::block_in_place(|| tokio::runtime::Handle::current().block_on(future))
+}
+
+#[async_trait]
+impl PaymentsEngine for SqlitePaymentsEngine {
+  async fn process(&mut self, transaction: Transaction) -> Result<()> {
+    if let Some(batch) = &self.batch {
+      return self
+        .apply_batched(&mut *batch.lock().await, transaction)
+        .await;
+    }
+
+    let mut conn = self.pool.acquire().await.map_err(database_error)?;
+    sqlx::query("BEGIN IMMEDIATE")
+      .execute(&mut conn)
+      .await
+      .map_err(database_error)?;
+
+    let result = self.apply(&mut conn, transaction).await;
+    let end = if result.is_ok() { "COMMIT" } else { "ROLLBACK" };
+    sqlx::query(end)
+      .execute(&mut conn)
+      .await
+      .map_err(database_error)?;
+    result
+  }
+
+  fn validate(&self, transaction: &Transaction) -> Result<()> {
+    let clients = transaction.clients();
+    self.load_blocking(Some(&clients))?.validate(transaction)
+  }
+
+  fn accounts_report(&self) -> AccountsReportIter {
+    self.accounts_matching(AccountFilter::default())
+  }
+
+  fn accounts_matching(&self, filter: AccountFilter) -> AccountsReportIter {
+    let engine = self
+      .load_blocking(None)
+      .expect("The database of the engine failed");
+    let report: Vec<AccountReport> = engine.accounts_matching(filter).collect();
+    AccountsReportIter::new(report.into_iter())
+  }
+
+  fn accounts_report_stream(&self) -> AccountsReportStream {
+    let engine = async move {
+      let mut conn = self.pool.acquire().await?;
+      self.flush_batch(&mut conn).await?;
+      // no accounts are loaded, but the clock is needed to report them
+      self.load(&mut conn, Some(&[])).await
+    };
+    let report = stream::once(engine)
+      .map(move |engine| match engine {
+        Ok(engine) => sqlx::query("SELECT client_id, account FROM accounts ORDER BY client_id")
+          .fetch(&self.pool)
+          .map(move |row| {
+            let (client_id, account) = decode_account(&row?)?;
+            Ok(engine.account_report(client_id, &account))
+          })
+          .left_stream(),
+        Err(err) => stream::once(future::ready(Err(err))).right_stream(),
+      })
+      .flatten()
+      .map(|account_report| account_report.map_err(database_error));
+    AccountsReportStream::new(report)
+  }
+
+  fn account(&self, client_id: ClientId) -> Option<AccountReport> {
+    self
+      .load_blocking(Some(&[client_id]))
+      .expect("The database of the engine failed")
+      .account(client_id)
+  }
+
+  fn transaction(
+    &self,
+    client_id: ClientId,
+    transaction_id: TransactionId,
+  ) -> Result<Option<TransactionInfo>> {
+    self
+      .load_blocking(Some(&[client_id]))?
+      .transaction(client_id, transaction_id)
+  }
+
+  fn transactions_report(&self) -> Result<TransactionsReportIter> {
+    let report: Vec<TransactionInfo> = self.load_blocking(None)?.transactions_report()?.collect();
+    Ok(TransactionsReportIter::new(report.into_iter()))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+
+  use std::path::PathBuf;
+
+  use rust_decimal_macros::dec;
+
+  use super::*;
+  use futures::TryStreamExt;
+
+  fn temp_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!(
+      "toy-payments-engine-{}-{}.db",
+      name,
+      std::process::id()
+    ))
+  }
+
+  fn transactions() -> Vec<Transaction> {
+    vec![
+      Transaction::Deposit {
+        client_id: 1,
+        transaction_id: 101,
+        amount: dec!(100),
+        timestamp: None,
+        sub_account: 0,
+      },
+      Transaction::Deposit {
+        client_id: 2,
+        transaction_id: 201,
+        amount: dec!(50),
+        timestamp: None,
+        sub_account: 0,
+      },
+      Transaction::Withdrawal {
+        client_id: 1,
+        transaction_id: 102,
+        amount: dec!(200),
+        timestamp: None,
+        sub_account: 0,
+      },
+      Transaction::Transfer {
+        from_client: 1,
+        to_client: 2,
+        transaction_id: 103,
+        amount: dec!(30),
+        timestamp: None,
+      },
+      Transaction::Dispute {
+        client_id: 2,
+        transaction_id: 201,
+        timestamp: None,
+      },
+      Transaction::Deposit {
+        client_id: 2,
+        transaction_id: 201,
+        amount: dec!(1),
+        timestamp: None,
+        sub_account: 0,
+      },
+    ]
+  }
+
+  #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+  async fn process_the_same_than_in_memory() {
+    let pool = SqlitePoolOptions::new()
+      .max_connections(1)
+      .connect("sqlite::memory:")
+      .await
+      .unwrap();
+    let mut engine = SqlitePaymentsEngine::new(pool, EngineConfig::default())
+      .await
+      .unwrap();
+    let mut reference = InMemoryPaymentsEngine::new();
+
+    for transaction in transactions() {
+      assert_eq!(
+        engine.validate(&transaction),
+        reference.validate(&transaction)
+      );
+      assert_eq!(
+        engine.process(transaction.clone()).await,
+        reference.process(transaction).await
+      );
+    }
+
+    let mut report: Vec<AccountReport> = engine.accounts_report().collect();
+    report.sort_by_key(|account_report| account_report.client_id);
+    let mut expected_report: Vec<AccountReport> = reference.accounts_report().collect();
+    expected_report.sort_by_key(|account_report| account_report.client_id);
+    assert_eq!(report, expected_report);
+    let streamed_report: Vec<AccountReport> =
+      engine.accounts_report_stream().try_collect().await.unwrap();
+    assert_eq!(streamed_report, expected_report);
+    assert_eq!(engine.transaction(2, 201), reference.transaction(2, 201));
+  }
+
+  #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+  async fn continue_after_reopening() {
+    let path = temp_path("sqlite-reopen");
+    let config = EngineConfig {
+      max_open_disputes: Some(1),
+      ..EngineConfig::default()
+    };
+
+    let mut engine = SqlitePaymentsEngine::open(&path, config.clone())
+      .await
+      .unwrap();
+    for transaction in transactions() {
+      engine.process(transaction).await.ok();
+    }
+    drop(engine);
+
+    assert!(SqlitePaymentsEngine::open(&path, EngineConfig::default())
+      .await
+      .is_err());
+    let mut engine = SqlitePaymentsEngine::open(&path, config).await.unwrap();
+    assert_eq!(
+      engine.account(1),
+      Some(AccountReport::new(1, dec!(70), dec!(0), dec!(70), false))
+    );
+    assert_eq!(
+      engine
+        .process(Transaction::Deposit {
+          client_id: 1,
+          transaction_id: 101,
+          amount: dec!(1),
+          timestamp: None,
+          sub_account: 0,
+        })
+        .await,
+      Err(PaymentsEngineError::DuplicatedTransaction(101))
+    );
+
+    std::fs::remove_file(&path).unwrap();
+  }
+
+  #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+  async fn flush_the_writes_in_batches() {
+    let path = temp_path("sqlite-batch");
+    let mut engine = SqlitePaymentsEngine::open(&path, EngineConfig::default())
+      .await
+      .unwrap()
+      .with_write_batch(3)
+      .await
+      .unwrap();
+    let mut reference = InMemoryPaymentsEngine::new();
+
+    for transaction in transactions() {
+      assert_eq!(
+        engine.validate(&transaction),
+        reference.validate(&transaction)
+      );
+      assert_eq!(
+        engine.process(transaction.clone()).await,
+        reference.process(transaction).await
+      );
+    }
+
+    // the dispute, which is the fourth transaction accepted, is only in the batch
+    let other = SqlitePaymentsEngine::open(&path, EngineConfig::default())
+      .await
+      .unwrap();
+    assert_eq!(engine.account(2), reference.account(2));
+    assert_eq!(other.position().await.unwrap(), 3);
+    assert_eq!(
+      other.account(2),
+      Some(AccountReport::new(2, dec!(80), dec!(0), dec!(80), false))
+    );
+
+    let mut report: Vec<AccountReport> = engine.accounts_report().collect();
+    report.sort_by_key(|account_report| account_report.client_id);
+    let mut expected_report: Vec<AccountReport> = reference.accounts_report().collect();
+    expected_report.sort_by_key(|account_report| account_report.client_id);
+    assert_eq!(report, expected_report);
+    assert_eq!(other.position().await.unwrap(), 4);
+    assert_eq!(other.account(2), reference.account(2));
+
+    std::fs::remove_file(&path).unwrap();
+  }
+}
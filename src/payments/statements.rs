@@ -0,0 +1,274 @@
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+
+use async_trait::async_trait;
+use rust_decimal::Decimal;
+
+use super::{
+  account::{AccountReport, TransactionInfo},
+  engine::{
+    AccountsReportIter, AccountsReportStream, PaymentsEngine, Result, TransactionsReportIter,
+  },
+  filter::AccountFilter,
+  transaction::{ClientId, Transaction, TransactionId},
+};
+
+/// A line of the statement of a client, with the balances of the account right after the transaction.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StatementLine {
+  pub client_id: ClientId,
+  /// The ID of the transaction, which unlocks don't have.
+  pub transaction_id: Option<TransactionId>,
+  /// The type of the transaction, as it is named in the input.
+  pub type_name: &'static str,
+  /// The amount of the transaction, or of the one it refers to for disputes, resolves, chargebacks, captures and voids.
+  pub amount: Option<Decimal>,
+  pub available: Decimal,
+  pub held: Decimal,
+}
+
+/// The statements of the clients, built from the transactions accepted by a [`PaymentsEngine`] in the order they are processed.
+///
+/// Every transaction adds a line to the statement of all the clients whose accounts it can change
+/// (both the sender and the recipient for transfers), so the running balances of every client can be followed line by line.
+#[derive(Debug, Default)]
+pub struct Statements {
+  lines: BTreeMap<ClientId, Vec<StatementLine>>,
+}
+
+impl Statements {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Record a transaction that was just accepted by the engine, with the balances of its accounts after it.
+  /// It fails when the engine can't read the transaction referred by a dispute, a resolve or a chargeback.
+  pub fn record<P>(&mut self, payments_engine: &P, transaction: &Transaction) -> Result<()>
+  where
+    P: PaymentsEngine + ?Sized,
+  {
+    for client_id in transaction.clients() {
+      let account = match payments_engine.account(client_id) {
+        Some(account) => account,
+        None => continue,
+      };
+      let amount = match *transaction {
+        Transaction::Deposit { amount, .. }
+        | Transaction::Withdrawal { amount, .. }
+        | Transaction::Transfer { amount, .. }
+        | Transaction::Authorize { amount, .. } => Some(amount),
+        Transaction::Unlock { .. } | Transaction::Reactivate { .. } => None,
+        _ => match transaction.transaction_id() {
+          Some(transaction_id) => payments_engine
+            .transaction(client_id, transaction_id)?
+            .map(|info| info.amount),
+          None => None,
+        },
+      };
+      self
+        .lines
+        .entry(client_id)
+        .or_default()
+        .push(StatementLine {
+          client_id,
+          transaction_id: transaction.transaction_id(),
+          type_name: transaction.type_name(),
+          amount,
+          available: account.available,
+          held: account.held,
+        });
+    }
+    Ok(())
+  }
+
+  /// The lines of the statements in ascending order of client ID, and in the order they were processed for every client.
+  pub fn into_lines(self) -> impl Iterator<Item = StatementLine> {
+    self.lines.into_iter().flat_map(|(_, lines)| lines)
+  }
+
+  /// The timeline of a client, with the lines of its statement in the order they were processed,
+  /// to follow how the balances got to their current value.
+  pub fn timeline(&self, client_id: ClientId) -> &[StatementLine] {
+    self
+      .lines
+      .get(&client_id)
+      .map(Vec::as_slice)
+      .unwrap_or_default()
+  }
+}
+
+/// A [`PaymentsEngine`] middleware that records every transaction accepted by the inner engine into some [`Statements`],
+/// which are shared to query the timeline of the clients while the engine keeps processing transactions.
+pub struct StatementsPaymentsEngine<E> {
+  inner: E,
+  statements: Arc<Mutex<Statements>>,
+}
+
+impl<E> StatementsPaymentsEngine<E>
+where
+  E: PaymentsEngine,
+{
+  pub fn new(inner: E, statements: Arc<Mutex<Statements>>) -> Self {
+    Self { inner, statements }
+  }
+}
+
+#[async_trait]
+impl<E> PaymentsEngine for StatementsPaymentsEngine<E>
+where
+  E: PaymentsEngine + Send,
+{
+  async fn process(&mut self, transaction: Transaction) -> Result<()> {
+    self.inner.process(transaction.clone()).await?;
+    // the transaction is already applied, so failing to record it doesn't reject it
+    if let Ok(mut statements) = self.statements.lock() {
+      if let Err(err) = statements.record(&self.inner, &transaction) {
+        tracing::error!(error = %err, "Statement not recorded");
+      }
+    }
+    Ok(())
+  }
+
+  fn validate(&self, transaction: &Transaction) -> Result<()> {
+    self.inner.validate(transaction)
+  }
+
+  fn accounts_report(&self) -> AccountsReportIter {
+    self.inner.accounts_report()
+  }
+
+  fn accounts_matching(&self, filter: AccountFilter) -> AccountsReportIter {
+    self.inner.accounts_matching(filter)
+  }
+
+  fn accounts_report_stream(&self) -> AccountsReportStream {
+    self.inner.accounts_report_stream()
+  }
+
+  fn account(&self, client_id: ClientId) -> Option<AccountReport> {
+    self.inner.account(client_id)
+  }
+
+  fn transaction(
+    &self,
+    client_id: ClientId,
+    transaction_id: TransactionId,
+  ) -> Result<Option<TransactionInfo>> {
+    self.inner.transaction(client_id, transaction_id)
+  }
+
+  fn transactions_report(&self) -> Result<TransactionsReportIter> {
+    self.inner.transactions_report()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+
+  use rust_decimal_macros::dec;
+
+  use super::*;
+  use crate::payments::{InMemoryPaymentsEngine, SyncPaymentsEngine};
+
+  #[test]
+  fn record_running_balances() {
+    let mut engine = InMemoryPaymentsEngine::new();
+    let mut statements = Statements::new();
+    let transactions = [
+      Transaction::Deposit {
+        client_id: 2,
+        transaction_id: 201,
+        amount: dec!(5),
+        timestamp: None,
+        sub_account: 0,
+      },
+      Transaction::Deposit {
+        client_id: 1,
+        transaction_id: 101,
+        amount: dec!(10),
+        timestamp: None,
+        sub_account: 0,
+      },
+      Transaction::Dispute {
+        client_id: 1,
+        transaction_id: 101,
+        timestamp: None,
+      },
+      Transaction::Resolve {
+        client_id: 1,
+        transaction_id: 101,
+        timestamp: None,
+      },
+      Transaction::Transfer {
+        from_client: 1,
+        to_client: 2,
+        transaction_id: 102,
+        amount: dec!(3),
+        timestamp: None,
+      },
+    ];
+    for transaction in transactions {
+      engine.process_sync(transaction.clone()).unwrap();
+      statements.record(&engine, &transaction).unwrap();
+    }
+
+    let line = |client_id, transaction_id, type_name, amount, available, held| StatementLine {
+      client_id,
+      transaction_id: Some(transaction_id),
+      type_name,
+      amount: Some(amount),
+      available,
+      held,
+    };
+    assert_eq!(
+      statements.into_lines().collect::<Vec<_>>(),
+      vec![
+        line(1, 101, "deposit", dec!(10), dec!(10), dec!(0)),
+        line(1, 101, "dispute", dec!(10), dec!(0), dec!(10)),
+        line(1, 101, "resolve", dec!(10), dec!(10), dec!(0)),
+        line(1, 102, "transfer", dec!(3), dec!(7), dec!(0)),
+        line(2, 201, "deposit", dec!(5), dec!(5), dec!(0)),
+        line(2, 102, "transfer", dec!(3), dec!(8), dec!(0)),
+      ]
+    );
+  }
+
+  #[tokio::test]
+  async fn record_accepted_transactions_into_timeline() {
+    let statements = Arc::new(Mutex::new(Statements::new()));
+    let mut engine =
+      StatementsPaymentsEngine::new(InMemoryPaymentsEngine::new(), statements.clone());
+    let deposit = Transaction::Deposit {
+      client_id: 1,
+      transaction_id: 101,
+      amount: dec!(10),
+      timestamp: None,
+      sub_account: 0,
+    };
+    let withdrawal = |transaction_id, amount| Transaction::Withdrawal {
+      client_id: 1,
+      transaction_id,
+      amount,
+      timestamp: None,
+      sub_account: 0,
+    };
+
+    engine.process(deposit).await.unwrap();
+    assert!(engine.process(withdrawal(102, dec!(20))).await.is_err());
+    engine.process(withdrawal(103, dec!(4))).await.unwrap();
+
+    let statements = statements.lock().unwrap();
+    assert_eq!(
+      statements
+        .timeline(1)
+        .iter()
+        .map(|line| (line.transaction_id, line.type_name, line.available))
+        .collect::<Vec<_>>(),
+      vec![
+        (Some(101), "deposit", dec!(10)),
+        (Some(103), "withdrawal", dec!(6)),
+      ]
+    );
+    assert!(statements.timeline(2).is_empty());
+  }
+}
@@ -0,0 +1,317 @@
+use std::collections::HashMap;
+use std::fmt::Debug;
+#[cfg(feature = "kv")]
+use std::path::Path;
+
+#[cfg(feature = "kv")]
+use super::engine::PaymentsEngineError;
+use super::{
+  account::{DisputeState, TransactionKind, TransactionState},
+  engine::Result,
+  transaction::{ClientId, TransactionId},
+};
+
+/// The transactions of a [`TransactionStore`], with the client that recorded them.
+pub type StoredTransactions<'a> =
+  Box<dyn Iterator<Item = Result<(ClientId, TransactionId, TransactionState)>> + Send + 'a>;
+
+/// Where an [`InMemoryPaymentsEngine`](super::InMemoryPaymentsEngine) keeps the settled transactions of the accounts,
+/// which are only needed again to detect duplicates, or when they are disputed.
+///
+/// The accounts keep the transactions that are disputed, charged back or pending to be captured, as the reports need them,
+/// so the memory used by the accounts depends on those instead of on the size of the input.
+pub trait TransactionStore: Debug + Send + Sync {
+  /// Keep the transaction, replacing any previous one with the same IDs.
+  fn insert(
+    &mut self,
+    client_id: ClientId,
+    transaction_id: TransactionId,
+    transaction: TransactionState,
+  ) -> Result<()>;
+
+  /// Take the transaction out of the store, if it is there.
+  fn remove(
+    &mut self,
+    client_id: ClientId,
+    transaction_id: TransactionId,
+  ) -> Result<Option<TransactionState>>;
+
+  fn get(
+    &self,
+    client_id: ClientId,
+    transaction_id: TransactionId,
+  ) -> Result<Option<TransactionState>>;
+
+  /// All the transactions of the store, in no particular order.
+  fn transactions(&self) -> StoredTransactions<'_>;
+
+  /// Remove all the transactions.
+  fn clear(&mut self) -> Result<()>;
+}
+
+/// Whether the transaction can be moved into a [`TransactionStore`], which is when nothing but a dispute can change it.
+pub(crate) fn is_settled(transaction: &TransactionState) -> bool {
+  // the fees are kept by the accounts, as they are reported
+  transaction.kind != TransactionKind::Authorization
+    && transaction.kind != TransactionKind::Fee
+    && matches!(
+      transaction.state,
+      DisputeState::Recorded | DisputeState::Resolved
+    )
+}
+
+/// A [`TransactionStore`] that keeps all the transactions in memory.
+#[derive(Debug, Default)]
+pub struct InMemoryTransactionStore {
+  transactions: HashMap<(ClientId, TransactionId), TransactionState>,
+}
+
+impl InMemoryTransactionStore {
+  pub fn new() -> Self {
+    Self::default()
+  }
+}
+
+impl TransactionStore for InMemoryTransactionStore {
+  fn insert(
+    &mut self,
+    client_id: ClientId,
+    transaction_id: TransactionId,
+    transaction: TransactionState,
+  ) -> Result<()> {
+    self
+      .transactions
+      .insert((client_id, transaction_id), transaction);
+    Ok(())
+  }
+
+  fn remove(
+    &mut self,
+    client_id: ClientId,
+    transaction_id: TransactionId,
+  ) -> Result<Option<TransactionState>> {
+    Ok(self.transactions.remove(&(client_id, transaction_id)))
+  }
+
+  fn get(
+    &self,
+    client_id: ClientId,
+    transaction_id: TransactionId,
+  ) -> Result<Option<TransactionState>> {
+    Ok(self.transactions.get(&(client_id, transaction_id)).cloned())
+  }
+
+  fn transactions(&self) -> StoredTransactions<'_> {
+    Box::new(
+      self
+        .transactions
+        .iter()
+        .map(|((client_id, transaction_id), transaction)| {
+          Ok((*client_id, *transaction_id, transaction.clone()))
+        }),
+    )
+  }
+
+  fn clear(&mut self) -> Result<()> {
+    self.transactions.clear();
+    Ok(())
+  }
+}
+
+/// A [`TransactionStore`] that keeps up to a number of transactions in memory, and spills them into an embedded
+/// [sled](https://docs.rs/sled) database on disk when it is full, so the memory used is bounded whatever the size of the input.
+///
+/// The transactions in memory are spilled all at once in a single batch, which keeps the writes sequential.
+/// The database is temporary, and it is removed when the store is dropped.
+#[cfg(feature = "kv")]
+#[derive(Debug)]
+pub struct SpillingTransactionStore {
+  memory: HashMap<(ClientId, TransactionId), TransactionState>,
+  memory_capacity: usize,
+  disk: sled::Db,
+}
+
+#[cfg(feature = "kv")]
+impl SpillingTransactionStore {
+  /// Create the temporary database at the path, keeping up to `memory_capacity` transactions in memory.
+  pub fn open<P: AsRef<Path>>(path: P, memory_capacity: usize) -> anyhow::Result<Self> {
+    let disk = sled::Config::new()
+      .path(path.as_ref())
+      .temporary(true)
+      .open()?;
+    Ok(Self {
+      memory: HashMap::new(),
+      memory_capacity: memory_capacity.max(1),
+      disk,
+    })
+  }
+
+  fn spill(&mut self) -> Result<()> {
+    let mut batch = sled::Batch::default();
+    for ((client_id, transaction_id), transaction) in self.memory.drain() {
+      let value = serde_json::to_vec(&transaction).map_err(store_error)?;
+      batch.insert(&disk_key(client_id, transaction_id), value);
+    }
+    self.disk.apply_batch(batch).map_err(store_error)
+  }
+}
+
+#[cfg(feature = "kv")]
+impl TransactionStore for SpillingTransactionStore {
+  fn insert(
+    &mut self,
+    client_id: ClientId,
+    transaction_id: TransactionId,
+    transaction: TransactionState,
+  ) -> Result<()> {
+    if self.memory.len() >= self.memory_capacity {
+      self.spill()?;
+    }
+    // a previous version on disk would be found after the one in memory is removed
+    self
+      .disk
+      .remove(disk_key(client_id, transaction_id))
+      .map_err(store_error)?;
+    self.memory.insert((client_id, transaction_id), transaction);
+    Ok(())
+  }
+
+  fn remove(
+    &mut self,
+    client_id: ClientId,
+    transaction_id: TransactionId,
+  ) -> Result<Option<TransactionState>> {
+    match self.memory.remove(&(client_id, transaction_id)) {
+      Some(transaction) => Ok(Some(transaction)),
+      None => self
+        .disk
+        .remove(disk_key(client_id, transaction_id))
+        .map_err(store_error)?
+        .map(|value| serde_json::from_slice(&value).map_err(store_error))
+        .transpose(),
+    }
+  }
+
+  fn get(
+    &self,
+    client_id: ClientId,
+    transaction_id: TransactionId,
+  ) -> Result<Option<TransactionState>> {
+    match self.memory.get(&(client_id, transaction_id)) {
+      Some(transaction) => Ok(Some(transaction.clone())),
+      None => self
+        .disk
+        .get(disk_key(client_id, transaction_id))
+        .map_err(store_error)?
+        .map(|value| serde_json::from_slice(&value).map_err(store_error))
+        .transpose(),
+    }
+  }
+
+  fn transactions(&self) -> StoredTransactions<'_> {
+    let memory = self
+      .memory
+      .iter()
+      .map(|((client_id, transaction_id), transaction)| {
+        Ok((*client_id, *transaction_id, transaction.clone()))
+      });
+    let disk = self.disk.iter().map(|entry| {
+      let (key, value) = entry.map_err(store_error)?;
+      let (client_id, transaction_id) = parse_disk_key(&key)?;
+      let transaction = serde_json::from_slice(&value).map_err(store_error)?;
+      Ok((client_id, transaction_id, transaction))
+    });
+    Box::new(memory.chain(disk))
+  }
+
+  fn clear(&mut self) -> Result<()> {
+    self.memory.clear();
+    self.disk.clear().map_err(store_error)
+  }
+}
+
+/// The key of a transaction on disk, with the client ID and the transaction ID in big endian.
+#[cfg(feature = "kv")]
+fn disk_key(client_id: ClientId, transaction_id: TransactionId) -> [u8; 6] {
+  let mut key = [0u8; 6];
+  key[..2].copy_from_slice(&client_id.to_be_bytes());
+  key[2..].copy_from_slice(&transaction_id.to_be_bytes());
+  key
+}
+
+#[cfg(feature = "kv")]
+fn parse_disk_key(key: &[u8]) -> Result<(ClientId, TransactionId)> {
+  if key.len() != 6 {
+    return Err(PaymentsEngineError::TransactionStore(format!(
+      "Invalid key of {} bytes",
+      key.len()
+    )));
+  }
+  let client_id = ClientId::from_be_bytes([key[0], key[1]]);
+  let transaction_id = TransactionId::from_be_bytes([key[2], key[3], key[4], key[5]]);
+  Ok((client_id, transaction_id))
+}
+
+#[cfg(feature = "kv")]
+fn store_error<E: std::fmt::Display>(err: E) -> PaymentsEngineError {
+  PaymentsEngineError::TransactionStore(err.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+
+  use rust_decimal_macros::dec;
+
+  use super::*;
+
+  fn check_store<S: TransactionStore>(mut store: S) {
+    for transaction_id in 1..=10 {
+      store
+        .insert(
+          1,
+          transaction_id,
+          TransactionState::from_amount(transaction_id.into()),
+        )
+        .unwrap();
+    }
+    store
+      .insert(1, 3, TransactionState::from_withdrawal(dec!(30)))
+      .unwrap();
+
+    assert_eq!(
+      store.get(1, 3).unwrap(),
+      Some(TransactionState::from_withdrawal(dec!(30)))
+    );
+    assert_eq!(
+      store.remove(1, 4).unwrap(),
+      Some(TransactionState::from_amount(dec!(4)))
+    );
+    assert_eq!(store.get(1, 4).unwrap(), None);
+    assert_eq!(store.remove(2, 1).unwrap(), None);
+
+    let mut transactions: Vec<(ClientId, TransactionId, TransactionState)> =
+      store.transactions().collect::<Result<_>>().unwrap();
+    transactions.sort_by_key(|(client_id, transaction_id, _)| (*client_id, *transaction_id));
+    assert_eq!(transactions.len(), 9);
+    assert_eq!(
+      transactions[2],
+      (1, 3, TransactionState::from_withdrawal(dec!(30)))
+    );
+
+    store.clear().unwrap();
+    assert_eq!(store.transactions().count(), 0);
+  }
+
+  #[test]
+  fn in_memory_store() {
+    check_store(InMemoryTransactionStore::new());
+  }
+
+  #[cfg(feature = "kv")]
+  #[test]
+  fn spilling_store() {
+    let path =
+      std::env::temp_dir().join(format!("toy-payments-engine-spill-{}", std::process::id()));
+    check_store(SpillingTransactionStore::open(&path, 3).unwrap());
+  }
+}
@@ -6,6 +6,12 @@ pub type ClientId = u16;
 /// Alias for a transaction ID
 pub type TransactionId = u32;
 
+/// Alias for the ID of a sub-account of a client, like the wallets of a product (main, savings, rewards, ...)
+pub type SubAccountId = u16;
+
+/// The sub-account of the transactions that don't address any, which also gets the funds of the transfers.
+pub const MAIN_SUB_ACCOUNT: SubAccountId = 0;
+
 /// Representation of the transactions types supported by a payments engine.
 #[derive(Debug, Clone, PartialEq)]
 pub enum Transaction {
@@ -13,22 +19,211 @@ pub enum Transaction {
     client_id: ClientId,
     transaction_id: TransactionId,
     amount: Decimal,
+    /// When the transaction happened, in seconds since the Unix epoch, if known.
+    timestamp: Option<u64>,
+    /// The sub-account whose funds are changed, which is the [`MAIN_SUB_ACCOUNT`] when not addressed.
+    sub_account: SubAccountId,
   },
   Withdrawal {
     client_id: ClientId,
     transaction_id: TransactionId,
     amount: Decimal,
+    /// When the transaction happened, in seconds since the Unix epoch, if known.
+    timestamp: Option<u64>,
+    /// The sub-account whose funds are changed, which is the [`MAIN_SUB_ACCOUNT`] when not addressed.
+    sub_account: SubAccountId,
   },
   Dispute {
     client_id: ClientId,
     transaction_id: TransactionId,
+    /// When the dispute was raised, in seconds since the Unix epoch, if known.
+    timestamp: Option<u64>,
   },
   Resolve {
     client_id: ClientId,
     transaction_id: TransactionId,
+    /// When the dispute was resolved, in seconds since the Unix epoch, if known.
+    timestamp: Option<u64>,
   },
   Chargeback {
     client_id: ClientId,
     transaction_id: TransactionId,
+    /// When the dispute was charged back, in seconds since the Unix epoch, if known.
+    timestamp: Option<u64>,
+  },
+  /// Move funds from the available funds of a client to another one, atomically.
+  /// Transfers can not be disputed.
+  Transfer {
+    from_client: ClientId,
+    to_client: ClientId,
+    transaction_id: TransactionId,
+    amount: Decimal,
+    /// When the funds were transferred, in seconds since the Unix epoch, if known.
+    timestamp: Option<u64>,
+  },
+  /// Administrative reinstatement of an account locked by a chargeback.
+  Unlock { client_id: ClientId },
+  /// Reinstatement of an account that became dormant (see [`DormancyPolicy`](super::DormancyPolicy)).
+  Reactivate { client_id: ClientId },
+  /// First phase of a two-phase deposit, which holds the amount until it is captured or voided.
+  Authorize {
+    client_id: ClientId,
+    transaction_id: TransactionId,
+    amount: Decimal,
+    /// When the funds were authorized, in seconds since the Unix epoch, if known.
+    timestamp: Option<u64>,
+    /// The sub-account whose funds are changed, which is the [`MAIN_SUB_ACCOUNT`] when not addressed.
+    sub_account: SubAccountId,
+  },
+  /// Make the funds held by an authorization available, as a deposit of its amount.
+  Capture {
+    client_id: ClientId,
+    transaction_id: TransactionId,
+    /// When the authorization was captured, in seconds since the Unix epoch, if known.
+    timestamp: Option<u64>,
   },
+  /// Cancel an authorization that was not captured, removing the funds it holds.
+  Void {
+    client_id: ClientId,
+    transaction_id: TransactionId,
+    /// When the authorization was voided, in seconds since the Unix epoch, if known.
+    timestamp: Option<u64>,
+  },
+}
+
+impl Transaction {
+  /// The type of the transaction, as it is named in the input.
+  pub fn type_name(&self) -> &'static str {
+    match self {
+      Transaction::Deposit { .. } => "deposit",
+      Transaction::Withdrawal { .. } => "withdrawal",
+      Transaction::Dispute { .. } => "dispute",
+      Transaction::Resolve { .. } => "resolve",
+      Transaction::Chargeback { .. } => "chargeback",
+      Transaction::Transfer { .. } => "transfer",
+      Transaction::Unlock { .. } => "unlock",
+      Transaction::Reactivate { .. } => "reactivate",
+      Transaction::Authorize { .. } => "authorize",
+      Transaction::Capture { .. } => "capture",
+      Transaction::Void { .. } => "void",
+    }
+  }
+
+  /// The client that originates the transaction, which is the sender for transfers.
+  pub fn client_id(&self) -> ClientId {
+    match *self {
+      Transaction::Deposit { client_id, .. }
+      | Transaction::Withdrawal { client_id, .. }
+      | Transaction::Dispute { client_id, .. }
+      | Transaction::Resolve { client_id, .. }
+      | Transaction::Chargeback { client_id, .. }
+      | Transaction::Unlock { client_id }
+      | Transaction::Reactivate { client_id }
+      | Transaction::Authorize { client_id, .. }
+      | Transaction::Capture { client_id, .. }
+      | Transaction::Void { client_id, .. } => client_id,
+      Transaction::Transfer { from_client, .. } => from_client,
+    }
+  }
+
+  /// The clients whose accounts can be changed by the transaction, which are both the sender and the recipient for transfers.
+  pub fn clients(&self) -> Vec<ClientId> {
+    match *self {
+      Transaction::Transfer {
+        from_client,
+        to_client,
+        ..
+      } => vec![from_client, to_client],
+      _ => vec![self.client_id()],
+    }
+  }
+
+  /// The ID of the transaction, which unlocks and reactivations don't have.
+  pub fn transaction_id(&self) -> Option<TransactionId> {
+    match *self {
+      Transaction::Deposit { transaction_id, .. }
+      | Transaction::Withdrawal { transaction_id, .. }
+      | Transaction::Dispute { transaction_id, .. }
+      | Transaction::Resolve { transaction_id, .. }
+      | Transaction::Chargeback { transaction_id, .. }
+      | Transaction::Transfer { transaction_id, .. }
+      | Transaction::Authorize { transaction_id, .. }
+      | Transaction::Capture { transaction_id, .. }
+      | Transaction::Void { transaction_id, .. } => Some(transaction_id),
+      Transaction::Unlock { .. } | Transaction::Reactivate { .. } => None,
+    }
+  }
+
+  /// The amount of the transaction, which only the deposits, withdrawals, transfers and authorizations have.
+  pub fn amount(&self) -> Option<Decimal> {
+    match *self {
+      Transaction::Deposit { amount, .. }
+      | Transaction::Withdrawal { amount, .. }
+      | Transaction::Transfer { amount, .. }
+      | Transaction::Authorize { amount, .. } => Some(amount),
+      Transaction::Dispute { .. }
+      | Transaction::Resolve { .. }
+      | Transaction::Chargeback { .. }
+      | Transaction::Unlock { .. }
+      | Transaction::Reactivate { .. }
+      | Transaction::Capture { .. }
+      | Transaction::Void { .. } => None,
+    }
+  }
+
+  /// The sub-account of the client whose funds are changed by the transaction.
+  /// Disputes, captures and voids change the sub-account of the transaction they refer to, so they don't address any,
+  /// and transfers move funds between the main sub-accounts.
+  pub fn sub_account(&self) -> SubAccountId {
+    match *self {
+      Transaction::Deposit { sub_account, .. }
+      | Transaction::Withdrawal { sub_account, .. }
+      | Transaction::Authorize { sub_account, .. } => sub_account,
+      _ => MAIN_SUB_ACCOUNT,
+    }
+  }
+
+  /// When the transaction happened, which unlocks and reactivations can't have.
+  pub fn timestamp(&self) -> Option<u64> {
+    match *self {
+      Transaction::Deposit { timestamp, .. }
+      | Transaction::Withdrawal { timestamp, .. }
+      | Transaction::Dispute { timestamp, .. }
+      | Transaction::Resolve { timestamp, .. }
+      | Transaction::Chargeback { timestamp, .. }
+      | Transaction::Transfer { timestamp, .. }
+      | Transaction::Authorize { timestamp, .. }
+      | Transaction::Capture { timestamp, .. }
+      | Transaction::Void { timestamp, .. } => timestamp,
+      Transaction::Unlock { .. } | Transaction::Reactivate { .. } => None,
+    }
+  }
+
+  /// The same transaction but with all its clients mapped into other ones.
+  pub fn map_client_ids<F>(mut self, f: F) -> Self
+  where
+    F: Fn(ClientId) -> ClientId,
+  {
+    match &mut self {
+      Transaction::Deposit { client_id, .. }
+      | Transaction::Withdrawal { client_id, .. }
+      | Transaction::Dispute { client_id, .. }
+      | Transaction::Resolve { client_id, .. }
+      | Transaction::Chargeback { client_id, .. }
+      | Transaction::Unlock { client_id }
+      | Transaction::Reactivate { client_id }
+      | Transaction::Authorize { client_id, .. }
+      | Transaction::Capture { client_id, .. }
+      | Transaction::Void { client_id, .. } => *client_id = f(*client_id),
+      Transaction::Transfer {
+        from_client,
+        to_client,
+        ..
+      } => {
+        *from_client = f(*from_client);
+        *to_client = f(*to_client);
+      }
+    }
+    self
+  }
 }
@@ -0,0 +1,304 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt};
+use tokio_stream::StreamExt;
+
+use super::{
+  AccountFilter, AccountReport, AccountsReportIter, AccountsReportStream, ClientId, PaymentsEngine,
+  PaymentsEngineError, Transaction, TransactionId, TransactionInfo, TransactionsReportIter,
+};
+use crate::io::{log_record, CsvTransactionsReader};
+
+const HEADER: &str = "type,client,tx,amount,to,timestamp,sub\n";
+
+/// A [`PaymentsEngine`] middleware that appends every accepted transaction to a write-ahead log before applying it,
+/// so the state of the engine can be rebuilt from the log with [`replay`].
+///
+/// The transactions are validated with [`PaymentsEngine::validate`] to know whether they would be accepted,
+/// and only the accepted ones are written and flushed into the log, before being processed by the inner engine.
+/// The log has the same CSV format than the input, so it can also be audited or processed as any other input.
+/// When the log can not be written, the transaction is rejected with [`PaymentsEngineError::WriteAheadLog`] without applying it.
+pub struct WalPaymentsEngine<E, W> {
+  inner: E,
+  log: W,
+  header_pending: bool,
+}
+
+impl<E, W> WalPaymentsEngine<E, W>
+where
+  E: PaymentsEngine + Send,
+  W: AsyncWrite + Unpin + Send,
+{
+  /// Start a new log, which will begin with the CSV header.
+  pub fn new(inner: E, log: W) -> Self {
+    Self {
+      inner,
+      log,
+      header_pending: true,
+    }
+  }
+
+  /// Keep appending to an existing log, which already has the CSV header.
+  pub fn appending(inner: E, log: W) -> Self {
+    Self {
+      inner,
+      log,
+      header_pending: false,
+    }
+  }
+
+  async fn append(&mut self, transaction: &Transaction) -> std::io::Result<()> {
+    let mut record = String::new();
+    if self.header_pending {
+      record.push_str(HEADER);
+    }
+    record.push_str(&log_record(transaction));
+    self.log.write_all(record.as_bytes()).await?;
+    self.log.flush().await?;
+    self.header_pending = false;
+    Ok(())
+  }
+}
+
+#[async_trait]
+impl<E, W> PaymentsEngine for WalPaymentsEngine<E, W>
+where
+  E: PaymentsEngine + Send,
+  W: AsyncWrite + Unpin + Send,
+{
+  async fn process(
+    &mut self,
+    transaction: Transaction,
+  ) -> core::result::Result<(), PaymentsEngineError> {
+    self.inner.validate(&transaction)?;
+    self
+      .append(&transaction)
+      .await
+      .map_err(|err| PaymentsEngineError::WriteAheadLog(err.to_string()))?;
+    self.inner.process(transaction).await
+  }
+
+  fn validate(&self, transaction: &Transaction) -> core::result::Result<(), PaymentsEngineError> {
+    self.inner.validate(transaction)
+  }
+
+  fn accounts_report(&self) -> AccountsReportIter {
+    self.inner.accounts_report()
+  }
+
+  fn accounts_matching(&self, filter: AccountFilter) -> AccountsReportIter {
+    self.inner.accounts_matching(filter)
+  }
+
+  fn accounts_report_stream(&self) -> AccountsReportStream {
+    self.inner.accounts_report_stream()
+  }
+
+  fn account(&self, client_id: ClientId) -> Option<AccountReport> {
+    self.inner.account(client_id)
+  }
+
+  fn transaction(
+    &self,
+    client_id: ClientId,
+    transaction_id: TransactionId,
+  ) -> core::result::Result<Option<TransactionInfo>, PaymentsEngineError> {
+    self.inner.transaction(client_id, transaction_id)
+  }
+
+  fn transactions_report(
+    &self,
+  ) -> core::result::Result<TransactionsReportIter, PaymentsEngineError> {
+    self.inner.transactions_report()
+  }
+}
+
+/// Rebuild the state of an engine by processing all the transactions of a write-ahead log, returning how many were replayed.
+/// All the transactions in the log were accepted when they were written, so any rejection means that the engine
+/// diverged from the one that wrote the log (like having a different configuration), and the replay fails.
+pub async fn replay<R, E>(log: R, engine: &mut E) -> Result<usize>
+where
+  R: AsyncRead + Unpin + Send + Sync,
+  E: PaymentsEngine,
+{
+  replay_after(log, engine, 0).await
+}
+
+/// Same as [`replay`] but skipping the first transactions of the log, already applied to the engine,
+/// like when it was restored from a snapshot taken at that position.
+pub async fn replay_after<R, E>(log: R, engine: &mut E, position: usize) -> Result<usize>
+where
+  R: AsyncRead + Unpin + Send + Sync,
+  E: PaymentsEngine,
+{
+  let mut reader = CsvTransactionsReader::new(log);
+  let mut transactions = reader.transactions().skip(position);
+  let mut replayed = 0;
+  while let Some(transaction) = transactions.next().await {
+    let transaction = transaction?;
+    engine.process(transaction.clone()).await.map_err(|err| {
+      anyhow::anyhow!(
+        "Replayed transaction {:?} was rejected: {}",
+        transaction,
+        err
+      )
+    })?;
+    replayed += 1;
+  }
+  Ok(replayed)
+}
+
+#[cfg(test)]
+mod tests {
+
+  use indoc::indoc;
+  use rust_decimal_macros::dec;
+
+  use super::*;
+  use crate::payments::InMemoryPaymentsEngine;
+
+  #[tokio::test]
+  async fn log_accepted_transactions_and_replay() {
+    let mut log = Vec::<u8>::new();
+    let mut engine = WalPaymentsEngine::new(InMemoryPaymentsEngine::new(), &mut log);
+
+    let transactions = vec![
+      (
+        Transaction::Deposit {
+          client_id: 1,
+          transaction_id: 101,
+          amount: dec!(100.5),
+          timestamp: None,
+          sub_account: 0,
+        },
+        Ok(()),
+      ),
+      (
+        Transaction::Withdrawal {
+          client_id: 1,
+          transaction_id: 102,
+          amount: dec!(200),
+          timestamp: None,
+          sub_account: 0,
+        },
+        Err(PaymentsEngineError::NotEnoughAvailableFunds),
+      ),
+      (
+        Transaction::Deposit {
+          client_id: 2,
+          transaction_id: 201,
+          amount: dec!(10),
+          timestamp: Some(1600000000),
+          sub_account: 0,
+        },
+        Ok(()),
+      ),
+      (
+        Transaction::Dispute {
+          client_id: 2,
+          transaction_id: 201,
+          timestamp: Some(1600086400),
+        },
+        Ok(()),
+      ),
+      (
+        Transaction::Chargeback {
+          client_id: 2,
+          transaction_id: 201,
+          timestamp: None,
+        },
+        Ok(()),
+      ),
+      (Transaction::Unlock { client_id: 2 }, Ok(())),
+      (
+        Transaction::Transfer {
+          from_client: 1,
+          to_client: 2,
+          transaction_id: 103,
+          amount: dec!(20),
+          timestamp: None,
+        },
+        Ok(()),
+      ),
+      (
+        Transaction::Authorize {
+          client_id: 1,
+          transaction_id: 104,
+          amount: dec!(30),
+          timestamp: None,
+          sub_account: 0,
+        },
+        Ok(()),
+      ),
+      (
+        Transaction::Capture {
+          client_id: 1,
+          transaction_id: 104,
+          timestamp: None,
+        },
+        Ok(()),
+      ),
+      (
+        Transaction::Authorize {
+          client_id: 1,
+          transaction_id: 105,
+          amount: dec!(40),
+          timestamp: None,
+          sub_account: 0,
+        },
+        Ok(()),
+      ),
+      (
+        Transaction::Void {
+          client_id: 1,
+          transaction_id: 105,
+          timestamp: None,
+        },
+        Ok(()),
+      ),
+    ];
+    for (transaction, expected_result) in transactions {
+      assert_eq!(engine.process(transaction).await, expected_result);
+    }
+    let mut expected_report: Vec<AccountReport> = engine.accounts_report().collect();
+    expected_report.sort_by_key(|account_report| account_report.client_id);
+    drop(engine);
+
+    assert_eq!(
+      String::from_utf8_lossy(&log),
+      indoc! { "
+        type,client,tx,amount,to,timestamp,sub
+        deposit,1,101,100.5,,
+        deposit,2,201,10,,1600000000
+        dispute,2,201,,,1600086400
+        chargeback,2,201,,,
+        unlock,2,0,,,
+        transfer,1,103,20,2,
+        authorize,1,104,30,,
+        capture,1,104,,,
+        authorize,1,105,40,,
+        void,1,105,,,
+      " }
+    );
+
+    let mut replayed_engine = InMemoryPaymentsEngine::new();
+    let replayed = replay(log.as_slice(), &mut replayed_engine).await.unwrap();
+
+    assert_eq!(replayed, 10);
+    let mut report: Vec<AccountReport> = replayed_engine.accounts_report().collect();
+    report.sort_by_key(|account_report| account_report.client_id);
+    assert_eq!(report, expected_report);
+  }
+
+  #[tokio::test]
+  async fn replay_fails_on_rejections() {
+    let log = indoc! { "
+      type,client,tx,amount,to
+      deposit,1,101,10,
+      withdrawal,1,102,20,
+    " };
+
+    let mut engine = InMemoryPaymentsEngine::new();
+    assert!(replay(log.as_bytes(), &mut engine).await.is_err());
+  }
+}
@@ -0,0 +1,160 @@
+use std::convert::TryFrom;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::time::Duration;
+
+/// Smallest and largest number of transactions of a batch by default.
+const DEFAULT_MIN_SIZE: usize = 1;
+const DEFAULT_MAX_SIZE: usize = 1024;
+/// The latency budget of every transaction by default, from being read to being processed.
+const DEFAULT_LATENCY_BUDGET: Duration = Duration::from_millis(10);
+/// Number of transactions added to the size of the batches every time the budget is met.
+const ADDITIVE_INCREASE: usize = 32;
+/// A partial batch is never kept for less than this, even when the engine spends all the budget.
+const MIN_FLUSH_INTERVAL: Duration = Duration::from_millis(1);
+
+/// The size of the batches sent to a worker and how long a partial batch waits before being flushed,
+/// adapted to the latency observed in the worker and the batches waiting for it (AIMD).
+///
+/// Every transaction has a latency budget. While the batches are processed within the budget, including the time
+/// waiting behind the batches already queued, the size grows additively. As soon as they don't, it is halved,
+/// so a burst that slows down the engine is absorbed with smaller batches instead of a longer queue.
+/// What is left of the budget after processing a batch is how long a partial batch waits to be filled,
+/// so the records of a slow input don't wait for a batch that never fills.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AdaptiveBatching {
+  min_size: usize,
+  max_size: usize,
+  latency_budget: Duration,
+  size: usize,
+  flush_interval: Duration,
+}
+
+impl AdaptiveBatching {
+  pub fn new(min_size: usize, max_size: usize, latency_budget: Duration) -> Self {
+    let min_size = min_size.max(1);
+    let max_size = max_size.max(min_size);
+    Self {
+      min_size,
+      max_size,
+      latency_budget,
+      size: ADDITIVE_INCREASE.max(min_size).min(max_size),
+      flush_interval: latency_budget.max(MIN_FLUSH_INTERVAL),
+    }
+  }
+
+  /// The number of transactions of the next batch.
+  pub fn size(&self) -> usize {
+    self.size
+  }
+
+  /// How long a partial batch waits to be filled before being flushed.
+  pub fn flush_interval(&self) -> Duration {
+    self.flush_interval
+  }
+
+  /// The largest size of the batches.
+  pub fn max_size(&self) -> usize {
+    self.max_size
+  }
+
+  /// Adapt to the `latency` of the last batch processed, with `queued` batches still waiting for the worker.
+  pub fn observe(&mut self, latency: Duration, queued: usize) {
+    let waiting = latency * u32::try_from(queued.saturating_add(1)).unwrap_or(u32::MAX);
+    if waiting > self.latency_budget {
+      self.size = (self.size / 2).max(self.min_size);
+    } else {
+      self.size = (self.size + ADDITIVE_INCREASE).min(self.max_size);
+    }
+    self.flush_interval = self
+      .latency_budget
+      .checked_sub(latency)
+      .unwrap_or_default()
+      .max(MIN_FLUSH_INTERVAL);
+  }
+}
+
+impl Default for AdaptiveBatching {
+  fn default() -> Self {
+    Self::new(DEFAULT_MIN_SIZE, DEFAULT_MAX_SIZE, DEFAULT_LATENCY_BUDGET)
+  }
+}
+
+/// What a worker reports back to the reader about its batches, to adapt the [`AdaptiveBatching`] of the partition.
+#[derive(Debug, Default)]
+pub(crate) struct BatchFeedback {
+  latency_micros: AtomicU64,
+  queued: AtomicUsize,
+}
+
+impl BatchFeedback {
+  /// A batch was sent to the worker.
+  pub fn sent(&self) {
+    self.queued.fetch_add(1, Ordering::Relaxed);
+  }
+
+  /// A batch was received by the worker.
+  pub fn received(&self) {
+    self.queued.fetch_sub(1, Ordering::Relaxed);
+  }
+
+  /// A batch was processed by the worker.
+  pub fn processed(&self, latency: Duration) {
+    let micros = u64::try_from(latency.as_micros()).unwrap_or(u64::MAX);
+    self.latency_micros.store(micros, Ordering::Relaxed);
+  }
+
+  /// The latency of the last batch processed, and the number of batches waiting for the worker.
+  pub fn observed(&self) -> (Duration, usize) {
+    (
+      Duration::from_micros(self.latency_micros.load(Ordering::Relaxed)),
+      self.queued.load(Ordering::Relaxed),
+    )
+  }
+}
+
+#[cfg(test)]
+mod test {
+
+  use super::*;
+
+  #[test]
+  fn grows_additively_within_the_budget() {
+    let mut batching = AdaptiveBatching::new(1, 100, Duration::from_millis(10));
+    assert_eq!(batching.size(), 32);
+
+    batching.observe(Duration::from_millis(2), 0);
+    assert_eq!(batching.size(), 64);
+    assert_eq!(batching.flush_interval(), Duration::from_millis(8));
+
+    batching.observe(Duration::from_millis(2), 1);
+    batching.observe(Duration::from_millis(2), 1);
+    assert_eq!(batching.size(), 100);
+  }
+
+  #[test]
+  fn halves_over_the_budget() {
+    let mut batching = AdaptiveBatching::new(4, 100, Duration::from_millis(10));
+
+    // the batch itself fits into the budget, but not after waiting for the queued ones
+    batching.observe(Duration::from_millis(4), 2);
+    assert_eq!(batching.size(), 16);
+    assert_eq!(batching.flush_interval(), Duration::from_millis(6));
+
+    batching.observe(Duration::from_millis(20), 0);
+    batching.observe(Duration::from_millis(20), 0);
+    batching.observe(Duration::from_millis(20), 0);
+    assert_eq!(batching.size(), 4);
+    assert_eq!(batching.flush_interval(), MIN_FLUSH_INTERVAL);
+  }
+
+  #[test]
+  fn feedback_of_the_worker() {
+    let feedback = BatchFeedback::default();
+    feedback.sent();
+    feedback.sent();
+    feedback.received();
+    feedback.processed(Duration::from_micros(1500));
+
+    assert_eq!(feedback.observed(), (Duration::from_micros(1500), 1));
+  }
+}
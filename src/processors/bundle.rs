@@ -0,0 +1,237 @@
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::Result;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use tokio::io::AsyncWrite;
+use tokio_stream::StreamExt;
+
+use super::simple::process_transactions;
+use crate::io::{
+  AccountsReportWriter, CsvAccountsReportWriter, CsvOpenDisputesWriter, OpenDisputesWriter,
+  TarWriter, TransactionsReader,
+};
+use crate::payments::{
+  InMemoryPaymentsEngine, MeteredPaymentsEngine, PaymentsEngine, PrometheusMetrics,
+  WalPaymentsEngine,
+};
+
+/// The input of the run, as described by the manifest of the bundle.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct BundleInput {
+  /// The path of the input, which is not given when it was read from the stdin.
+  pub path: Option<String>,
+  /// The hex encoded SHA-256 of the input (see [`fingerprint_file`](crate::io::fingerprint_file)).
+  pub fingerprint: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct Manifest<'a> {
+  version: &'a str,
+  created: u64,
+  input: Option<&'a str>,
+  input_fingerprint: Option<&'a str>,
+  config_digest: String,
+  files: Vec<ManifestFile<'a>>,
+}
+
+#[derive(Debug, Serialize)]
+struct ManifestFile<'a> {
+  name: &'a str,
+  size: usize,
+  sha256: String,
+}
+
+/// This processor writes an audit bundle of the processing of the transactions: a tar archive with every file
+/// that an auditor needs to check the results without asking for them one by one. It
+/// - reads and processes transactions the same way than the [`simple`](super::simple) processor
+/// - records the accepted transactions into an audit log with the format of the write-ahead log,
+///   so the run can be replayed (see [`replay`](crate::payments::replay)) under the same configuration
+/// - writes the accounts report and the open disputes from a single pass over the accounts
+/// - writes the archive with
+///   - `manifest.json`: the version of the crate, when the bundle was created, the input and its fingerprint,
+///     the digest of the configuration, and the size and SHA-256 of every other file
+///   - `config.txt`: the digest and the settings of the configuration of the engine
+///   - `stats.json`: the [`StreamStats`](crate::payments::StreamStats) of the run
+///   - `accounts.csv`: the accounts report
+///   - `disputes.csv`: the accounts with open disputes, like the `disputes` command
+///   - `audit.csv`: the audit log of the accepted transactions
+///   - `input.sha256`: the fingerprint of the input in the format of `sha256sum`, when it was read from a file
+///
+pub async fn run<R, W>(
+  mut transactions_reader: R,
+  payments_engine: InMemoryPaymentsEngine,
+  input: BundleInput,
+  output: W,
+) -> Result<()>
+where
+  R: TransactionsReader,
+  W: AsyncWrite + Unpin,
+{
+  let config = format!(
+    "digest: {}\n\n{:#?}\n",
+    payments_engine.config_digest(),
+    payments_engine.config()
+  );
+  let config_digest = payments_engine.config_digest();
+  let metrics = Arc::new(PrometheusMetrics::new());
+  let mut audit_log = Vec::<u8>::new();
+  let mut accounts = Vec::<u8>::new();
+  let mut disputes = Vec::<u8>::new();
+
+  {
+    // the rejected transactions are metered, but only the accepted ones reach the audit log
+    let mut payments_engine = MeteredPaymentsEngine::new(
+      WalPaymentsEngine::new(payments_engine, &mut audit_log),
+      metrics.clone(),
+    );
+    process_transactions(
+      &mut transactions_reader,
+      &mut payments_engine,
+      Some(metrics.as_ref()),
+    )
+    .await;
+    // the accounts with open disputes are kept from the same pass over the accounts than the report
+    let mut open_disputes = Vec::new();
+    let report = payments_engine.accounts_report_stream().map(|account| {
+      if let Ok(account) = &account {
+        if account.open_disputes > 0 {
+          open_disputes.push(account.clone());
+        }
+      }
+      account
+    });
+    CsvAccountsReportWriter::new(&mut accounts)
+      .write_accounts_report(report)
+      .await?;
+    CsvOpenDisputesWriter::new(&mut disputes)
+      .write_open_disputes(open_disputes.into_iter())
+      .await?;
+  }
+
+  let stats = serde_json::to_vec_pretty(&metrics.stream_stats())?;
+  let fingerprint = match (&input.path, &input.fingerprint) {
+    (Some(path), Some(fingerprint)) => Some(format!("{}  {}\n", fingerprint, path)),
+    _ => None,
+  };
+  let mut files: Vec<(&str, &[u8])> = vec![
+    ("config.txt", config.as_bytes()),
+    ("stats.json", stats.as_slice()),
+    ("accounts.csv", accounts.as_slice()),
+    ("disputes.csv", disputes.as_slice()),
+    ("audit.csv", audit_log.as_slice()),
+  ];
+  if let Some(fingerprint) = &fingerprint {
+    files.push(("input.sha256", fingerprint.as_bytes()));
+  }
+
+  let created = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+  let manifest = Manifest {
+    version: env!("CARGO_PKG_VERSION"),
+    created,
+    input: input.path.as_deref(),
+    input_fingerprint: input.fingerprint.as_deref(),
+    config_digest,
+    files: files
+      .iter()
+      .map(|&(name, content)| ManifestFile {
+        name,
+        size: content.len(),
+        sha256: format!("{:x}", Sha256::digest(content)),
+      })
+      .collect(),
+  };
+  let manifest = serde_json::to_vec_pretty(&manifest)?;
+
+  let mut tar = TarWriter::new(output);
+  tar.append("manifest.json", &manifest, created).await?;
+  for (name, content) in files {
+    tar.append(name, content, created).await?;
+  }
+  tar.finish().await?;
+  Ok(())
+}
+
+#[cfg(test)]
+mod test {
+
+  use indoc::indoc;
+
+  use super::*;
+  use crate::io::CsvTransactionsReader;
+
+  /// The files of a tar archive, by name.
+  fn files(archive: &[u8]) -> Vec<(String, String)> {
+    let mut files = Vec::new();
+    let mut offset = 0;
+    while archive[offset] != 0 {
+      let header = &archive[offset..offset + 512];
+      let name_end = header.iter().position(|byte| *byte == 0).unwrap();
+      let name = String::from_utf8_lossy(&header[..name_end]).to_string();
+      let size = usize::from_str_radix(std::str::from_utf8(&header[124..135]).unwrap(), 8).unwrap();
+      let content = &archive[offset + 512..offset + 512 + size];
+      files.push((name, String::from_utf8_lossy(content).to_string()));
+      offset += 512 + (size + 511) / 512 * 512;
+    }
+    files
+  }
+
+  #[tokio::test]
+  async fn run_successfully() {
+    let transactions = indoc! { "
+      type,       client,   tx,  amount
+      deposit,         1,  101,     100
+      deposit,         2,  201,      30
+      withdrawal,      1,  102,     500
+      dispute,         2,  201,
+    " }
+    .as_bytes();
+    let input = BundleInput {
+      path: Some("transactions.csv".to_string()),
+      fingerprint: Some("abc".to_string()),
+    };
+
+    let mut buffer = Vec::<u8>::with_capacity(8192);
+
+    let result = run(
+      CsvTransactionsReader::new(transactions),
+      InMemoryPaymentsEngine::new(),
+      input,
+      &mut buffer,
+    )
+    .await;
+
+    assert!(result.is_ok());
+    let files = files(&buffer);
+    let names: Vec<&str> = files.iter().map(|(name, _)| name.as_str()).collect();
+    assert_eq!(
+      names,
+      vec![
+        "manifest.json",
+        "config.txt",
+        "stats.json",
+        "accounts.csv",
+        "disputes.csv",
+        "audit.csv",
+        "input.sha256",
+      ]
+    );
+    let manifest: serde_json::Value = serde_json::from_str(&files[0].1).unwrap();
+    assert_eq!(manifest["input_fingerprint"], "abc");
+    assert_eq!(manifest["files"].as_array().unwrap().len(), 6);
+    let stats: serde_json::Value = serde_json::from_str(&files[2].1).unwrap();
+    assert_eq!(stats["processed"], 3);
+    assert_eq!(stats["rejected"], 1);
+    assert_eq!(
+      files[5].1,
+      indoc! { "
+        type,client,tx,amount,to,timestamp,sub
+        deposit,1,101,100,,
+        deposit,2,201,30,,
+        dispute,2,201,,,
+      " }
+    );
+    assert_eq!(files[6].1, "abc  transactions.csv\n");
+  }
+}
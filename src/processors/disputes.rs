@@ -0,0 +1,90 @@
+use anyhow::Result;
+
+use super::simple::process_transactions;
+use crate::io::{OpenDisputesWriter, TransactionsReader};
+use crate::payments::{AccountFilter, PaymentsEngine};
+
+/// This processor reports the accounts with open disputes, to follow up the disputes not resolved yet. It
+/// - reads and processes transactions the same way than the [`simple`](super::simple) processor
+/// - writes the accounts with some transaction in dispute, with how many and the funds they hold, using an [`OpenDisputesWriter`]
+///
+/// The accounts can be further selected by the `filter`, which always selects the accounts with open disputes.
+///
+pub async fn run<R, P, W>(
+  mut transactions_reader: R,
+  mut payments_engine: P,
+  filter: AccountFilter,
+  mut open_disputes_writer: W,
+) -> Result<()>
+where
+  R: TransactionsReader,
+  P: PaymentsEngine,
+  W: OpenDisputesWriter,
+{
+  process_transactions(&mut transactions_reader, &mut payments_engine, None).await;
+
+  let filter = AccountFilter {
+    has_open_disputes: true,
+    ..filter
+  };
+  open_disputes_writer
+    .write_open_disputes(payments_engine.accounts_matching(filter))
+    .await
+}
+
+#[cfg(test)]
+mod test {
+
+  use indoc::indoc;
+
+  use super::*;
+  use crate::io::{CsvOpenDisputesWriter, CsvTransactionsReader};
+  use crate::payments::{EngineConfig, InMemoryPaymentsEngine};
+
+  #[tokio::test]
+  async fn run_successfully() {
+    let transactions = indoc! { "
+      type,       client,   tx,  amount
+      deposit,         1,  101,     100
+      deposit,         1,  102,      20
+      deposit,         1,  103,    10.5
+      dispute,         1,  102,
+      dispute,         1,  103,
+      deposit,         2,  201,      30
+      dispute,         2,  201,
+      resolve,         2,  201,
+      deposit,         3,  301,      40
+      dispute,         3,  301,
+      chargeback,      3,  301,
+      deposit,         4,  401,      50
+      deposit,         4,  402,       5
+      dispute,         4,  401,
+      dispute,         4,  402,
+      chargeback,      4,  402,
+    " }
+    .as_bytes();
+
+    let mut buffer = Vec::<u8>::with_capacity(1024);
+
+    let result = run(
+      CsvTransactionsReader::new(transactions),
+      InMemoryPaymentsEngine::with_config(EngineConfig {
+        deterministic: true,
+        ..EngineConfig::default()
+      }),
+      AccountFilter::default(),
+      CsvOpenDisputesWriter::new(&mut buffer),
+    )
+    .await;
+
+    assert!(result.is_ok());
+    assert_eq!(
+      String::from_utf8_lossy(buffer.as_slice()),
+      indoc! {"
+        client,open_disputes,held,locked
+        1,2,30.5,false
+        4,1,50,true
+      "}
+    )
+  }
+}
@@ -0,0 +1,128 @@
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::Result;
+use tokio_stream::{Stream, StreamExt};
+
+use crate::io::{AccountsReportWriter, CsvAccountsReportWriter, TransactionsReader};
+use crate::payments::PaymentsEngine;
+
+/// This processor works the same way than the [`simple`](super::simple) one,
+/// but it also dumps the current accounts report every time that a dump is requested,
+/// allowing to inspect the in-flight state without stopping the processing.
+///
+/// Every item from `dump_requests` writes the report as CSV into a new timestamped file inside `dumps_dir`.
+/// Pending dump requests are always served before processing the next transaction.
+/// The binary uses it to trigger the dumps with the `SIGUSR1` signal.
+///
+/// Failing to write a dump doesn't stop the processing.
+///
+pub async fn run<R, P, W, D>(
+  mut transactions_reader: R,
+  mut payments_engine: P,
+  mut accounts_report_writer: W,
+  mut dump_requests: D,
+  dumps_dir: PathBuf,
+) -> Result<()>
+where
+  R: TransactionsReader,
+  P: PaymentsEngine,
+  W: AccountsReportWriter,
+  D: Stream<Item = ()> + Unpin,
+{
+  let mut transactions = transactions_reader.read_transactions();
+
+  loop {
+    tokio::select! {
+      biased;
+
+      Some(()) = dump_requests.next() => {
+        if let Err(err) = dump_accounts_report(&payments_engine, &dumps_dir).await {
+          tracing::warn!(error = %err, "Failed to dump the accounts report");
+        }
+      }
+      maybe_transaction = transactions.next() => match maybe_transaction {
+        Some(Ok(transaction)) => {
+          payments_engine.process(transaction).await.ok();
+        }
+        Some(Err(_)) => {}
+        None => break,
+      },
+    }
+  }
+
+  accounts_report_writer
+    .write_accounts_report(payments_engine.accounts_report_stream())
+    .await
+}
+
+async fn dump_accounts_report<P>(payments_engine: &P, dumps_dir: &Path) -> Result<PathBuf>
+where
+  P: PaymentsEngine,
+{
+  let timestamp = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis();
+  let path = dumps_dir.join(format!("accounts-{}.csv", timestamp));
+  let file = tokio::fs::File::create(&path).await?;
+  CsvAccountsReportWriter::new(file)
+    .write_accounts_report(payments_engine.accounts_report_stream())
+    .await?;
+  Ok(path)
+}
+
+#[cfg(test)]
+mod test {
+
+  use indoc::indoc;
+
+  use super::*;
+  use crate::io::CsvTransactionsReader;
+  use crate::payments::InMemoryPaymentsEngine;
+
+  #[tokio::test]
+  async fn run_with_dump_requests() {
+    let dumps_dir =
+      std::env::temp_dir().join(format!("toy-payments-engine-dumps-{}", std::process::id()));
+    tokio::fs::create_dir_all(&dumps_dir).await.unwrap();
+
+    let transactions = indoc! { "
+      type,       client,   tx,  amount
+      deposit,         1,  101,     100
+      deposit,         2,  102,      20
+    " }
+    .as_bytes();
+
+    let mut buffer = Vec::<u8>::with_capacity(1024);
+
+    let result = run(
+      CsvTransactionsReader::new(transactions),
+      InMemoryPaymentsEngine::new(),
+      CsvAccountsReportWriter::new(&mut buffer),
+      tokio_stream::iter(vec![()]),
+      dumps_dir.clone(),
+    )
+    .await;
+
+    let dumps = std::fs::read_dir(&dumps_dir).unwrap().count();
+    std::fs::remove_dir_all(&dumps_dir).unwrap();
+
+    assert!(result.is_ok());
+    assert!(!buffer.is_empty());
+    assert_eq!(dumps, 1);
+  }
+
+  #[tokio::test]
+  async fn dump_accounts_report_into_file() {
+    let dumps_dir = std::env::temp_dir();
+    let payments_engine = InMemoryPaymentsEngine::new();
+
+    let path = dump_accounts_report(&payments_engine, &dumps_dir)
+      .await
+      .unwrap();
+
+    let content = tokio::fs::read_to_string(&path).await.unwrap();
+    tokio::fs::remove_file(&path).await.unwrap();
+
+    assert!(path.starts_with(&dumps_dir));
+    assert_eq!(content, "");
+  }
+}
@@ -0,0 +1,409 @@
+use std::future::Future;
+
+use anyhow::Result;
+
+use crate::io::{Checkpoints, StateLock};
+use crate::payments::{
+  BoxedPaymentsEngine, CheckpointSchedule, CheckpointingPaymentsEngine, EngineConfig,
+  InMemoryPaymentsEngine, PaymentsEngine, TransactionStore, WalPaymentsEngine,
+};
+
+/// Where the payments engine keeps the accounts.
+#[derive(Debug, Clone, PartialEq)]
+pub enum EngineBackend {
+  /// The accounts are kept in memory, and they are only persisted with a write-ahead log.
+  Memory,
+  /// The accounts are kept in the SQLite database of the path.
+  #[cfg(feature = "sqlite")]
+  Sqlite { path: String },
+  /// The accounts are kept in the PostgreSQL database of the URL.
+  #[cfg(feature = "postgres")]
+  Postgres { url: String },
+}
+
+/// The options to open a payments engine with [`with_engine`].
+pub struct EngineOptions {
+  backend: EngineBackend,
+  config: EngineConfig,
+  transaction_store: Option<Box<dyn TransactionStore>>,
+  wal_path: Option<String>,
+  #[cfg_attr(not(any(feature = "sqlite", feature = "postgres")), allow(dead_code))]
+  write_batch: Option<usize>,
+  checkpoints: Option<(Checkpoints, CheckpointSchedule)>,
+  allow_config_change: bool,
+  steal_lock: bool,
+}
+
+impl EngineOptions {
+  pub fn new(backend: EngineBackend, config: EngineConfig) -> Self {
+    Self {
+      backend,
+      config,
+      transaction_store: None,
+      wal_path: None,
+      write_batch: None,
+      checkpoints: None,
+      allow_config_change: false,
+      steal_lock: false,
+    }
+  }
+
+  /// Move the settled transactions of the accounts kept in memory into the store (see [`InMemoryPaymentsEngine::with_transaction_store`]).
+  pub fn with_transaction_store(mut self, store: Option<Box<dyn TransactionStore>>) -> Self {
+    self.transaction_store = store;
+    self
+  }
+
+  /// Persist the accepted transactions into the write-ahead log of the path, rebuilding the state from it when it exists.
+  /// The log is locked while the engine runs, so no other process can write it at the same time (see [`StateLock`]).
+  pub fn with_wal_path(mut self, wal_path: Option<String>) -> Self {
+    self.wal_path = wal_path;
+    self
+  }
+
+  /// Coalesce the writes of this number of transactions into a database before flushing them.
+  /// The transactions not flushed yet are only recovered after a crash when there is a write-ahead log.
+  pub fn with_write_batch(mut self, size: Option<usize>) -> Self {
+    self.write_batch = size;
+    self
+  }
+
+  /// Restore the latest snapshot of the checkpoints before replaying the write-ahead log of the accounts kept in memory,
+  /// and take new snapshots as scheduled while the engine is running.
+  pub fn with_checkpoints(
+    mut self,
+    checkpoints: Option<(Checkpoints, CheckpointSchedule)>,
+  ) -> Self {
+    self.checkpoints = checkpoints;
+    self
+  }
+
+  /// Continue the write-ahead log even when it was started with a different configuration.
+  pub fn with_config_change(mut self, allow: bool) -> Self {
+    self.allow_config_change = allow;
+    self
+  }
+
+  /// Take the lock of the write-ahead log over even when another process holds it.
+  pub fn with_steal_lock(mut self, steal: bool) -> Self {
+    self.steal_lock = steal;
+    self
+  }
+}
+
+/// The payments engine opened for the [`EngineOptions`].
+pub enum OpenedEngine {
+  /// The accounts are only kept in memory, so the engine can be driven synchronously (see [`generic`](super::generic)).
+  Memory(InMemoryPaymentsEngine),
+  /// Any other engine.
+  Boxed(BoxedPaymentsEngine),
+}
+
+impl OpenedEngine {
+  pub fn into_boxed(self) -> BoxedPaymentsEngine {
+    match self {
+      OpenedEngine::Memory(payments_engine) => Box::new(payments_engine),
+      OpenedEngine::Boxed(payments_engine) => payments_engine,
+    }
+  }
+}
+
+/// Open the payments engine for the options, and run it until the future returned by `run` finishes.
+///
+/// When there is a write-ahead log, its lock is held from before replaying it until the future finishes,
+/// and the run fails as soon as the lock is lost, as the state can't be written anymore.
+pub async fn with_engine<F, Fut, T>(options: EngineOptions, run: F) -> Result<T>
+where
+  F: FnOnce(OpenedEngine) -> Fut,
+  Fut: Future<Output = Result<T>>,
+{
+  let allow_config_change = options.allow_config_change;
+  let steal_lock = options.steal_lock;
+  match options.backend {
+    EngineBackend::Memory => {
+      let payments_engine = InMemoryPaymentsEngine::with_config(options.config);
+      let payments_engine = match options.transaction_store {
+        Some(store) => payments_engine.with_transaction_store(store),
+        None => payments_engine,
+      };
+      let wal_path = match options.wal_path {
+        Some(wal_path) => wal_path,
+        None => return run(OpenedEngine::Memory(payments_engine)).await,
+      };
+      let checkpoints = options.checkpoints;
+      let lock = StateLock::acquire(&wal_path, steal_lock).await?;
+      lock
+        .hold(async move {
+          let payments_engine: BoxedPaymentsEngine = match checkpoints {
+            Some((checkpoints, schedule)) => Box::new(
+              open_checkpointed_wal_engine(
+                payments_engine,
+                wal_path,
+                allow_config_change,
+                checkpoints,
+                schedule,
+              )
+              .await?,
+            ),
+            None => {
+              let config_digest = payments_engine.config_digest();
+              Box::new(
+                continue_wal(
+                  payments_engine,
+                  config_digest,
+                  wal_path,
+                  allow_config_change,
+                  0,
+                )
+                .await?,
+              )
+            }
+          };
+          run(OpenedEngine::Boxed(payments_engine)).await
+        })
+        .await
+    }
+    #[cfg(feature = "sqlite")]
+    EngineBackend::Sqlite { path } => {
+      let payments_engine =
+        crate::payments::SqlitePaymentsEngine::open(path, options.config.clone()).await?;
+      match options.write_batch {
+        Some(size) => {
+          let payments_engine = payments_engine.with_write_batch(size).await?;
+          let position = payments_engine.position().await?;
+          with_database_engine(
+            payments_engine,
+            position,
+            options.config.digest(),
+            options.wal_path,
+            allow_config_change,
+            steal_lock,
+            run,
+          )
+          .await
+        }
+        None => run(OpenedEngine::Boxed(Box::new(payments_engine))).await,
+      }
+    }
+    #[cfg(feature = "postgres")]
+    EngineBackend::Postgres { url } => {
+      let payments_engine =
+        crate::payments::PostgresPaymentsEngine::connect(&url, options.config.clone()).await?;
+      match options.write_batch {
+        Some(size) => {
+          let payments_engine = payments_engine.with_write_batch(size).await?;
+          let position = payments_engine.position().await?;
+          with_database_engine(
+            payments_engine,
+            position,
+            options.config.digest(),
+            options.wal_path,
+            allow_config_change,
+            steal_lock,
+            run,
+          )
+          .await
+        }
+        None => run(OpenedEngine::Boxed(Box::new(payments_engine))).await,
+      }
+    }
+  }
+}
+
+/// Run an engine backed by a database whose writes are batched. The write-ahead log is replayed after the position
+/// flushed into the database, as the transactions after it were lost with the last run.
+#[cfg(any(feature = "sqlite", feature = "postgres"))]
+async fn with_database_engine<P, F, Fut, T>(
+  payments_engine: P,
+  position: u64,
+  config_digest: String,
+  wal_path: Option<String>,
+  allow_config_change: bool,
+  steal_lock: bool,
+  run: F,
+) -> Result<T>
+where
+  P: PaymentsEngine + Send + 'static,
+  F: FnOnce(OpenedEngine) -> Fut,
+  Fut: Future<Output = Result<T>>,
+{
+  let wal_path = match wal_path {
+    Some(wal_path) => wal_path,
+    None => return run(OpenedEngine::Boxed(Box::new(payments_engine))).await,
+  };
+  let lock = StateLock::acquire(&wal_path, steal_lock).await?;
+  lock
+    .hold(async move {
+      let payments_engine = continue_wal(
+        payments_engine,
+        config_digest,
+        wal_path,
+        allow_config_change,
+        position as usize,
+      )
+      .await?;
+      run(OpenedEngine::Boxed(Box::new(payments_engine))).await
+    })
+    .await
+}
+
+/// Restore the latest snapshot of the checkpoints before continuing the write-ahead log, so only the log after it
+/// is replayed, and take new snapshots as scheduled while the engine is running.
+async fn open_checkpointed_wal_engine(
+  mut payments_engine: InMemoryPaymentsEngine,
+  wal_path: String,
+  allow_config_change: bool,
+  checkpoints: Checkpoints,
+  schedule: CheckpointSchedule,
+) -> Result<WalPaymentsEngine<CheckpointingPaymentsEngine, tokio::fs::File>> {
+  let config_digest = payments_engine.config_digest();
+  let position = match checkpoints.latest().await? {
+    Some((position, snapshot)) => {
+      if allow_config_change {
+        payments_engine.restore_with_config_change(snapshot)?;
+      } else {
+        payments_engine.restore(snapshot)?;
+      }
+      tracing::info!(position, "Snapshot restored");
+      position
+    }
+    None => 0,
+  };
+  let payments_engine =
+    CheckpointingPaymentsEngine::new(payments_engine, checkpoints, schedule, position);
+  continue_wal(
+    payments_engine,
+    config_digest,
+    wal_path,
+    allow_config_change,
+    position as usize,
+  )
+  .await
+}
+
+/// Replay the write-ahead log after the position into the engine, and keep appending the accepted transactions to it.
+/// The digest of the engine configuration is kept next to the log, and continuing the log with a different configuration
+/// is refused unless it is explicitly allowed.
+async fn continue_wal<E>(
+  mut payments_engine: E,
+  config_digest: String,
+  wal_path: String,
+  allow_config_change: bool,
+  position: usize,
+) -> Result<WalPaymentsEngine<E, tokio::fs::File>>
+where
+  E: PaymentsEngine + Send,
+{
+  let digest_path = format!("{}.digest", wal_path);
+
+  let mut log_started = false;
+  match tokio::fs::File::open(&wal_path).await {
+    Ok(log) => {
+      log_started = log.metadata().await?.len() > 0;
+      if let Ok(log_digest) = tokio::fs::read_to_string(&digest_path).await {
+        if log_started && log_digest.trim() != config_digest && !allow_config_change {
+          anyhow::bail!(
+            "The write-ahead log was started with the configuration digest {} but the current one is {} (use --allow-config-change to continue it)",
+            log_digest.trim(),
+            config_digest
+          );
+        }
+      }
+      let replayed = crate::payments::replay_after(log, &mut payments_engine, position).await?;
+      tracing::info!(replayed, "Write-ahead log replayed");
+    }
+    Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+    Err(err) => return Err(err.into()),
+  }
+
+  tokio::fs::write(&digest_path, format!("{}\n", config_digest)).await?;
+  let log = tokio::fs::OpenOptions::new()
+    .create(true)
+    .append(true)
+    .open(&wal_path)
+    .await?;
+  if log_started {
+    Ok(WalPaymentsEngine::appending(payments_engine, log))
+  } else {
+    Ok(WalPaymentsEngine::new(payments_engine, log))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  use rust_decimal_macros::dec;
+
+  use crate::payments::{ClientId, Transaction, TransactionId};
+
+  const CLIENT_ID: ClientId = 1;
+
+  fn deposit(transaction_id: TransactionId) -> Transaction {
+    Transaction::Deposit {
+      client_id: CLIENT_ID,
+      transaction_id,
+      amount: dec!(10),
+      timestamp: None,
+      sub_account: 0,
+    }
+  }
+
+  #[tokio::test]
+  async fn with_engine_in_memory() {
+    let options = EngineOptions::new(EngineBackend::Memory, EngineConfig::default());
+    let available = with_engine(options, |payments_engine| async move {
+      let mut payments_engine = match payments_engine {
+        OpenedEngine::Memory(payments_engine) => payments_engine,
+        OpenedEngine::Boxed(_) => anyhow::bail!("Expected an engine in memory"),
+      };
+      PaymentsEngine::process(&mut payments_engine, deposit(1)).await?;
+      Ok(PaymentsEngine::account(&payments_engine, CLIENT_ID).map(|account| account.available))
+    })
+    .await
+    .unwrap();
+
+    assert_eq!(available, Some(dec!(10)));
+  }
+
+  #[tokio::test]
+  async fn with_engine_replays_the_wal() {
+    let wal_path = std::env::temp_dir().join(format!(
+      "toy-payments-engine-engine-{}.wal",
+      std::process::id()
+    ));
+    tokio::fs::remove_file(&wal_path).await.ok();
+    let wal_path = wal_path.to_string_lossy().into_owned();
+    let options = || {
+      EngineOptions::new(EngineBackend::Memory, EngineConfig::default())
+        .with_wal_path(Some(wal_path.clone()))
+    };
+
+    with_engine(options(), |payments_engine| async move {
+      let mut payments_engine = payments_engine.into_boxed();
+      payments_engine.process(deposit(1)).await?;
+      payments_engine.process(deposit(2)).await?;
+      Ok(())
+    })
+    .await
+    .unwrap();
+
+    let available = with_engine(options(), |payments_engine| async move {
+      let payments_engine = payments_engine.into_boxed();
+      Ok(
+        payments_engine
+          .account(CLIENT_ID)
+          .map(|account| account.available),
+      )
+    })
+    .await
+    .unwrap();
+
+    assert_eq!(available, Some(dec!(20)));
+    assert!(!StateLock::path(std::path::Path::new(&wal_path)).exists());
+    tokio::fs::remove_file(&wal_path).await.ok();
+    tokio::fs::remove_file(format!("{}.digest", wal_path))
+      .await
+      .ok();
+  }
+}
@@ -0,0 +1,269 @@
+use std::convert::TryFrom;
+use std::pin::Pin;
+use std::sync::Arc;
+
+use anyhow::Result;
+use tokio::net::TcpListener;
+use tokio::sync::Mutex;
+use tokio_stream::wrappers::TcpListenerStream;
+use tokio_stream::{Stream, StreamExt};
+use tonic::transport::Server;
+use tonic::{Request, Response, Status};
+
+use crate::io::proto::payments_server::{Payments, PaymentsServer};
+use crate::io::proto::{
+  AccountReport, GetAccountRequest, StreamAccountsReportRequest, SubmitTransactionResponse,
+  Transaction,
+};
+use crate::io::AmountParser;
+use crate::payments::{ClientId, PaymentsEngine};
+
+/// This processor serves the payments engine through gRPC, with the `Payments` service of `proto/payments.proto`
+/// implemented by a [`PaymentsService`]. It runs until the listener fails.
+pub async fn serve<P>(listener: TcpListener, service: PaymentsService<P>) -> Result<()>
+where
+  P: PaymentsEngine + Send + 'static,
+{
+  Server::builder()
+    .add_service(PaymentsServer::new(service))
+    .serve_with_incoming(TcpListenerStream::new(listener))
+    .await?;
+  Ok(())
+}
+
+/// The `Payments` service backed by any [`PaymentsEngine`], so the engine can be embedded as a microservice:
+/// - `SubmitTransaction` processes a transaction, interpreted the same way than the records of the CSV format,
+///   and answers whether the engine accepted it, with the kind of error when it was rejected
+/// - `GetAccount` returns the report of the account of a client
+/// - `StreamAccountsReport` streams the report of all the accounts
+///
+/// The requests are processed one at a time, so the accounts reports are always consistent with the transactions
+/// already answered. It can also be added to any other tonic server with [`PaymentsServer::new`].
+pub struct PaymentsService<P> {
+  payments_engine: Arc<Mutex<P>>,
+  amount_parser: AmountParser,
+}
+
+impl<P> PaymentsService<P> {
+  pub fn new(payments_engine: P) -> Self {
+    Self {
+      payments_engine: Arc::new(Mutex::new(payments_engine)),
+      amount_parser: AmountParser::default(),
+    }
+  }
+
+  pub fn with_amount_parser(mut self, amount_parser: AmountParser) -> Self {
+    self.amount_parser = amount_parser;
+    self
+  }
+}
+
+#[tonic::async_trait]
+impl<P> Payments for PaymentsService<P>
+where
+  P: PaymentsEngine + Send + 'static,
+{
+  async fn submit_transaction(
+    &self,
+    request: Request<Transaction>,
+  ) -> Result<Response<SubmitTransactionResponse>, Status> {
+    let transaction = request
+      .into_inner()
+      .into_payments(&self.amount_parser)
+      .map_err(|err| Status::invalid_argument(err.to_string()))?;
+    let result = self.payments_engine.lock().await.process(transaction).await;
+    let response = match result {
+      Ok(()) => SubmitTransactionResponse {
+        accepted: true,
+        error: None,
+        message: None,
+      },
+      Err(err) => SubmitTransactionResponse {
+        accepted: false,
+        error: Some(err.kind().to_string()),
+        message: Some(err.to_string()),
+      },
+    };
+    Ok(Response::new(response))
+  }
+
+  async fn get_account(
+    &self,
+    request: Request<GetAccountRequest>,
+  ) -> Result<Response<AccountReport>, Status> {
+    let client = request.into_inner().client;
+    let client_id = ClientId::try_from(client)
+      .map_err(|_| Status::invalid_argument(format!("Invalid client: {}", client)))?;
+    match self.payments_engine.lock().await.account(client_id) {
+      Some(account_report) => Ok(Response::new(AccountReport::from(account_report))),
+      None => Err(Status::not_found(format!(
+        "The client {} has no account",
+        client_id
+      ))),
+    }
+  }
+
+  type StreamAccountsReportStream =
+    Pin<Box<dyn Stream<Item = Result<AccountReport, Status>> + Send + Sync + 'static>>;
+
+  async fn stream_accounts_report(
+    &self,
+    _request: Request<StreamAccountsReportRequest>,
+  ) -> Result<Response<Self::StreamAccountsReportStream>, Status> {
+    // the report is collected while the engine is locked, so it is not affected by the transactions submitted meanwhile
+    let payments_engine = self.payments_engine.lock().await;
+    let mut accounts_report = payments_engine.accounts_report_stream();
+    let mut report = Vec::new();
+    while let Some(account_report) = accounts_report.next().await {
+      report.push(match account_report {
+        Ok(account_report) => Ok(AccountReport::from(account_report)),
+        Err(err) => Err(Status::internal(err.to_string())),
+      });
+    }
+    Ok(Response::new(Box::pin(tokio_stream::iter(report))))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+
+  use rust_decimal_macros::dec;
+  use tonic::Code;
+
+  use super::*;
+  use crate::io::proto::TransactionType;
+  use crate::payments::{self, InMemoryPaymentsEngine};
+
+  fn transaction(transaction: payments::Transaction) -> Request<Transaction> {
+    Request::new(Transaction::from(&transaction))
+  }
+
+  #[tokio::test]
+  async fn submit_transactions() {
+    let service = PaymentsService::new(InMemoryPaymentsEngine::new());
+
+    let response = service
+      .submit_transaction(transaction(payments::Transaction::Deposit {
+        client_id: 1,
+        transaction_id: 101,
+        amount: dec!(10),
+        timestamp: None,
+        sub_account: 0,
+      }))
+      .await
+      .unwrap();
+    assert_eq!(
+      response.into_inner(),
+      SubmitTransactionResponse {
+        accepted: true,
+        error: None,
+        message: None,
+      }
+    );
+
+    let response = service
+      .submit_transaction(transaction(payments::Transaction::Withdrawal {
+        client_id: 1,
+        transaction_id: 102,
+        amount: dec!(20),
+        timestamp: None,
+        sub_account: 0,
+      }))
+      .await
+      .unwrap();
+    assert_eq!(
+      response.into_inner(),
+      SubmitTransactionResponse {
+        accepted: false,
+        error: Some("not_enough_available_funds".to_string()),
+        message: Some("Not enough available funds".to_string()),
+      }
+    );
+
+    let status = service
+      .submit_transaction(Request::new(Transaction {
+        r#type: TransactionType::Deposit as i32,
+        client: 1,
+        tx: 103,
+        amount: Some("ten".to_string()),
+        ..Transaction::default()
+      }))
+      .await
+      .unwrap_err();
+    assert_eq!(status.code(), Code::InvalidArgument);
+  }
+
+  #[tokio::test]
+  async fn get_accounts() {
+    let service = PaymentsService::new(InMemoryPaymentsEngine::new());
+    service
+      .submit_transaction(transaction(payments::Transaction::Deposit {
+        client_id: 2,
+        transaction_id: 101,
+        amount: dec!(10.12345),
+        timestamp: None,
+        sub_account: 0,
+      }))
+      .await
+      .unwrap();
+
+    let response = service
+      .get_account(Request::new(GetAccountRequest { client: 2 }))
+      .await
+      .unwrap();
+    assert_eq!(
+      response.into_inner(),
+      AccountReport {
+        client: 2,
+        available: "10.1235".to_string(),
+        held: "0".to_string(),
+        total: "10.1235".to_string(),
+        locked: false,
+        open_disputes: 0,
+        charged_back_total: "0".to_string(),
+      }
+    );
+
+    let status = service
+      .get_account(Request::new(GetAccountRequest { client: 1 }))
+      .await
+      .unwrap_err();
+    assert_eq!(status.code(), Code::NotFound);
+
+    let status = service
+      .get_account(Request::new(GetAccountRequest { client: 70_000 }))
+      .await
+      .unwrap_err();
+    assert_eq!(status.code(), Code::InvalidArgument);
+  }
+
+  #[tokio::test]
+  async fn stream_accounts_report() {
+    let service = PaymentsService::new(InMemoryPaymentsEngine::new());
+    for client_id in 1..=3 {
+      service
+        .submit_transaction(transaction(payments::Transaction::Deposit {
+          client_id,
+          transaction_id: u32::from(client_id),
+          amount: dec!(5),
+          timestamp: None,
+          sub_account: 0,
+        }))
+        .await
+        .unwrap();
+    }
+
+    let report = service
+      .stream_accounts_report(Request::new(StreamAccountsReportRequest {}))
+      .await
+      .unwrap()
+      .into_inner();
+    let mut clients: Vec<u32> = report
+      .map(|account_report| account_report.unwrap().client)
+      .collect()
+      .await;
+    clients.sort_unstable();
+
+    assert_eq!(clients, vec![1, 2, 3]);
+  }
+}
@@ -0,0 +1,76 @@
+use anyhow::Result;
+
+use super::simple::process_transactions;
+use crate::io::{TransactionsHistoryWriter, TransactionsReader};
+use crate::payments::PaymentsEngine;
+
+/// This processor exports the ledger of the transactions behind the balances, for their audit. It
+/// - reads and processes transactions the same way than the [`simple`](super::simple) processor
+/// - writes every transaction recorded by the accounts, with its dispute state, using a [`TransactionsHistoryWriter`]
+///
+/// Only the transactions recorded by the accounts are written, so the disputes, resolutions and chargebacks
+/// are reflected in the state of the transactions they refer to, and the rejected transactions are not written.
+///
+pub async fn run<R, P, W>(
+  mut transactions_reader: R,
+  mut payments_engine: P,
+  mut history_writer: W,
+) -> Result<()>
+where
+  R: TransactionsReader,
+  P: PaymentsEngine,
+  W: TransactionsHistoryWriter,
+{
+  process_transactions(&mut transactions_reader, &mut payments_engine, None).await;
+
+  history_writer
+    .write_transactions_history(payments_engine.transactions_report()?)
+    .await
+}
+
+#[cfg(test)]
+mod test {
+
+  use indoc::indoc;
+
+  use super::*;
+  use crate::io::{CsvTransactionsHistoryWriter, CsvTransactionsReader};
+  use crate::payments::{EngineConfig, InMemoryPaymentsEngine};
+
+  #[tokio::test]
+  async fn run_successfully() {
+    let transactions = indoc! { "
+      type,       client,   tx,  amount
+      deposit,         1,  101,     100
+      deposit,         1,  102,      20
+      withdrawal,      1,  103,     500
+      dispute,         1,  102,
+      chargeback,      1,  102,
+      deposit,         2,  201,      30
+    " }
+    .as_bytes();
+
+    let mut buffer = Vec::<u8>::with_capacity(1024);
+
+    let result = run(
+      CsvTransactionsReader::new(transactions),
+      InMemoryPaymentsEngine::with_config(EngineConfig {
+        deterministic: true,
+        ..EngineConfig::default()
+      }),
+      CsvTransactionsHistoryWriter::new(&mut buffer),
+    )
+    .await;
+
+    assert!(result.is_ok());
+    assert_eq!(
+      String::from_utf8_lossy(buffer.as_slice()),
+      indoc! {"
+        client,tx,type,amount,state,in_dispute,disputed_at
+        1,101,deposit,100,recorded,false,
+        1,102,deposit,20,charged_back,false,0
+        2,201,deposit,30,recorded,false,
+      "}
+    )
+  }
+}
@@ -0,0 +1,1281 @@
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::future::Future;
+use std::rc::Rc;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use hyper::server::conn::Http;
+use hyper::service::service_fn;
+use hyper::{Body, Method, Request, Response, StatusCode};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::Mutex;
+use tokio::task::LocalSet;
+use tokio_stream::StreamExt;
+use tokio_util::io::StreamReader;
+
+use super::simple::{process_transactions, ProcessingSummary};
+use crate::io::{
+  AccountsReportWriter, AmountParser, ApiKeys, AuditLog, ClientMetadata, CsvAccountsReportWriter,
+  CsvStatementsWriter, CsvTransactionsReader, ReportSchema, StatementsWriter, TransactionsReader,
+};
+use crate::payments::{
+  AccountFilter, AccountsReportStream, ClientId, Metrics, PaymentsEngine, PrometheusMetrics,
+  Statements,
+};
+
+#[cfg(feature = "tls")]
+pub use tokio_rustls::TlsAcceptor;
+
+/// Without the `tls` feature the connections can not be encrypted, so there is no acceptor for them.
+#[cfg(not(feature = "tls"))]
+pub enum TlsAcceptor {}
+
+const TRANSACTIONS_PATH: &str = "/transactions";
+const ACCOUNTS_PATH: &str = "/accounts";
+const METRICS_PATH: &str = "/metrics";
+const STATS_PATH: &str = "/stats";
+const TIMELINE_SUFFIX: &str = "/timeline";
+
+/// The rate limits of the API keys are enforced over fixed windows of this duration.
+const RATE_LIMIT_WINDOW: Duration = Duration::from_secs(60);
+
+/// How long the clients are asked to wait before uploading again the transactions rejected under memory pressure, by default.
+const DEFAULT_RETRY_AFTER: Duration = Duration::from_secs(5);
+
+/// The audit log of the accepted transactions, written into any destination.
+pub type AuditLogWrite = AuditLog<Box<dyn AsyncWrite + Unpin + Send + Sync>>;
+
+/// This processor serves the payments engine through HTTP:
+/// - `POST /transactions` streams the CSV in the body of the request through a [`CsvTransactionsReader`]
+///   into the [`PaymentsEngine`], skipping any error the same way than the [`simple`](super::simple) processor.
+///   The records are processed as the chunks of the body arrive (like with `Transfer-Encoding: chunked`), without buffering it,
+///   and the [`ProcessingSummary`] of the body is returned as JSON once it ends.
+///   It answers `405 Method Not Allowed` when the service is read-only (see [`ServeOptions::with_read_only`]),
+///   and `429 Too Many Requests` with a `Retry-After` header when the [`AdmissionControl`] doesn't admit it
+/// - `GET /accounts` returns the accounts report as CSV, written with a [`CsvAccountsReportWriter`]
+///   with the `report_schema` (and the client `metadata` joined for the [`ReportSchema::V2`]).
+///   The query can select the accounts with the parameters of an [`AccountFilter`], like `/accounts?locked_only=true`
+/// - `GET /metrics` returns the [`PrometheusMetrics`], when they are given in the options
+/// - `GET /stats` returns the [`StreamStats`](crate::payments::StreamStats) of those metrics as JSON,
+///   with the watermark, the consumer lag and the end-to-end latency of the transactions with a timestamp
+/// - `GET /accounts/{id}/timeline` returns the timeline of the client as CSV, with every transaction applied
+///   to its account and the running balances after it, when the [`Statements`] are given in the options
+///
+/// The requests are processed one at a time, so the transactions of every request are processed in order,
+/// and the accounts report is always consistent with the requests already answered.
+/// The connections are served from a [`LocalSet`], as the transactions readers are not `Send`.
+///
+/// With an [`AccessControl`], the requests need the `Authorization: Bearer <key>` header with one of its API keys,
+/// and with a [`TlsAcceptor`] (available with the `tls` feature), the connections are encrypted.
+/// The metrics of the engine are only emitted when it is wrapped into a [`MeteredPaymentsEngine`](crate::payments::MeteredPaymentsEngine)
+/// with the same [`PrometheusMetrics`], while the processor counts the records that can't be read.
+/// In the same way, the timelines are only recorded when it is wrapped into a
+/// [`StatementsPaymentsEngine`](crate::payments::StatementsPaymentsEngine) with the same [`Statements`].
+///
+pub async fn serve<P>(
+  listener: TcpListener,
+  payments_engine: P,
+  options: ServeOptions,
+) -> Result<()>
+where
+  P: PaymentsEngine + 'static,
+{
+  let ServeOptions {
+    amount_parser,
+    report_schema,
+    metadata,
+    access_control,
+    tls_acceptor,
+    metrics,
+    read_only,
+    timeline,
+    admission_control,
+  } = options;
+  let payments_engine = Rc::new(Mutex::new(payments_engine));
+  let access_control = access_control.map(|access_control| Rc::new(Mutex::new(access_control)));
+  let tls_acceptor = tls_acceptor.map(Rc::new);
+  let admission_control = admission_control.map(Rc::new);
+
+  LocalSet::new()
+    .run_until(async move {
+      loop {
+        let (stream, _) = listener.accept().await?;
+        let payments_engine = payments_engine.clone();
+        let metadata = metadata.clone();
+        let access_control = access_control.clone();
+        let tls_acceptor = tls_acceptor.clone();
+        let metrics = metrics.clone();
+        let timeline = timeline.clone();
+        let admission_control = admission_control.clone();
+        let service = service_fn(move |request| {
+          let options = RequestOptions {
+            amount_parser,
+            report_schema,
+            metadata: metadata.clone(),
+            access_control: access_control.clone(),
+            metrics: metrics.clone(),
+            read_only,
+            timeline: timeline.clone(),
+            admission_control: admission_control.clone(),
+          };
+          handle(payments_engine.clone(), options, request)
+        });
+        tokio::task::spawn_local(async move {
+          // a failed connection doesn't affect the rest of them
+          if let Ok(connection) = accept(stream, tls_acceptor.as_deref()).await {
+            Http::new()
+              .with_executor(LocalExecutor)
+              .serve_connection(connection, service)
+              .await
+              .ok();
+          }
+        });
+      }
+    })
+    .await
+}
+
+/// The options of the service, which by default serves plain HTTP to everyone, and without metrics.
+#[derive(Default)]
+pub struct ServeOptions {
+  amount_parser: AmountParser,
+  report_schema: ReportSchema,
+  metadata: Option<Arc<ClientMetadata>>,
+  access_control: Option<AccessControl>,
+  tls_acceptor: Option<TlsAcceptor>,
+  metrics: Option<Arc<PrometheusMetrics>>,
+  read_only: bool,
+  timeline: Option<Arc<std::sync::Mutex<Statements>>>,
+  admission_control: Option<AdmissionControl>,
+}
+
+impl ServeOptions {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  pub fn with_amount_parser(mut self, amount_parser: AmountParser) -> Self {
+    self.amount_parser = amount_parser;
+    self
+  }
+
+  pub fn with_report_schema(mut self, report_schema: ReportSchema) -> Self {
+    self.report_schema = report_schema;
+    self
+  }
+
+  /// The client metadata to join into the report, which is only supported by the [`ReportSchema::V2`].
+  pub fn with_metadata(mut self, metadata: Option<Arc<ClientMetadata>>) -> Self {
+    self.metadata = metadata;
+    self
+  }
+
+  pub fn with_access_control(mut self, access_control: Option<AccessControl>) -> Self {
+    self.access_control = access_control;
+    self
+  }
+
+  pub fn with_tls_acceptor(mut self, tls_acceptor: Option<TlsAcceptor>) -> Self {
+    self.tls_acceptor = tls_acceptor;
+    self
+  }
+
+  /// The metrics exposed in `/metrics`, which also count the records that can't be read.
+  pub fn with_metrics(mut self, metrics: Option<Arc<PrometheusMetrics>>) -> Self {
+    self.metrics = metrics;
+    self
+  }
+
+  /// Reject the transactions posted, so the state of the engine (like the one restored from a snapshot)
+  /// can be explored without changing it.
+  pub fn with_read_only(mut self, read_only: bool) -> Self {
+    self.read_only = read_only;
+    self
+  }
+
+  pub fn with_admission_control(mut self, admission_control: Option<AdmissionControl>) -> Self {
+    self.admission_control = admission_control;
+    self
+  }
+
+  /// The statements exposed in `/accounts/{id}/timeline`.
+  pub fn with_timeline(mut self, timeline: Option<Arc<std::sync::Mutex<Statements>>>) -> Self {
+    self.timeline = timeline;
+    self
+  }
+}
+
+/// The options to read the transactions and write the report of every request.
+struct RequestOptions {
+  amount_parser: AmountParser,
+  report_schema: ReportSchema,
+  metadata: Option<Arc<ClientMetadata>>,
+  access_control: Option<Rc<Mutex<AccessControl>>>,
+  metrics: Option<Arc<PrometheusMetrics>>,
+  read_only: bool,
+  timeline: Option<Arc<std::sync::Mutex<Statements>>>,
+  admission_control: Option<Rc<AdmissionControl>>,
+}
+
+/// Admits the uploads of transactions only while the memory used stays under a limit, so under memory pressure
+/// the new uploads are rejected while the ones in flight are still processed, instead of running out of memory.
+///
+/// The cost of an upload is the size of its body (its `Content-Length`, when known), as the accounts and transactions
+/// it adds to the engine grow with it. An upload is admitted when the memory used plus its cost fits into the limit,
+/// or when the memory used can't be read.
+pub struct AdmissionControl {
+  memory_limit: u64,
+  memory_usage: Box<dyn Fn() -> Option<u64>>,
+  retry_after: Duration,
+}
+
+impl AdmissionControl {
+  /// The admission of the uploads while the memory returned by `memory_usage` (in bytes) is under the `memory_limit`.
+  pub fn new<F>(memory_limit: u64, memory_usage: F) -> Self
+  where
+    F: Fn() -> Option<u64> + 'static,
+  {
+    Self {
+      memory_limit,
+      memory_usage: Box::new(memory_usage),
+      retry_after: DEFAULT_RETRY_AFTER,
+    }
+  }
+
+  /// How long the clients are asked to wait before uploading again (5 seconds by default).
+  pub fn with_retry_after(mut self, retry_after: Duration) -> Self {
+    self.retry_after = retry_after;
+    self
+  }
+
+  /// Whether an upload with some cost is admitted, or how long to wait before retrying it.
+  fn admit(&self, cost: u64) -> core::result::Result<(), Duration> {
+    match (self.memory_usage)() {
+      Some(used) if used.saturating_add(cost) > self.memory_limit => {
+        tracing::warn!(
+          used,
+          cost,
+          limit = self.memory_limit,
+          "Upload rejected under memory pressure"
+        );
+        Err(self.retry_after)
+      }
+      _ => Ok(()),
+    }
+  }
+}
+
+/// The API keys allowed to use the service, with the state of their rate limits,
+/// and optionally an [`AuditLog`] of the transactions accepted from every key.
+pub struct AccessControl {
+  api_keys: ApiKeys,
+  audit_log: Option<AuditLogWrite>,
+  /// The start of the current window of every key, and the number of requests made since then.
+  windows: HashMap<String, (Instant, u32)>,
+}
+
+impl AccessControl {
+  pub fn new(api_keys: ApiKeys) -> Self {
+    Self {
+      api_keys,
+      audit_log: None,
+      windows: HashMap::new(),
+    }
+  }
+
+  pub fn with_audit_log(mut self, audit_log: AuditLogWrite) -> Self {
+    self.audit_log = Some(audit_log);
+    self
+  }
+
+  /// Authenticate the request with the API key of its `Authorization` header, and count it into the rate limit of the key.
+  /// It returns the name of the key, or the status to answer with when the request is not allowed.
+  fn authorize(
+    &mut self,
+    request: &Request<Body>,
+    now: Instant,
+  ) -> core::result::Result<String, StatusCode> {
+    let api_keys = &self.api_keys;
+    let api_key = request
+      .headers()
+      .get(hyper::header::AUTHORIZATION)
+      .and_then(|value| value.to_str().ok())
+      .and_then(|value| value.strip_prefix("Bearer "))
+      .and_then(|key| api_keys.get(key.trim()))
+      .ok_or(StatusCode::UNAUTHORIZED)?;
+
+    let window = self.windows.entry(api_key.name.clone()).or_insert((now, 0));
+    if now.duration_since(window.0) >= RATE_LIMIT_WINDOW {
+      *window = (now, 0);
+    }
+    if let Some(requests_per_minute) = api_key.requests_per_minute {
+      if window.1 >= requests_per_minute {
+        return Err(StatusCode::TOO_MANY_REQUESTS);
+      }
+    }
+    window.1 += 1;
+
+    Ok(api_key.name.clone())
+  }
+
+  /// Process the transactions submitted with the key, auditing the accepted ones before applying them when there is an audit log.
+  /// Failing to write the audit log stops the processing, so no transaction is applied without being audited.
+  async fn process<R, P>(
+    &mut self,
+    key_name: &str,
+    transactions_reader: &mut R,
+    payments_engine: &mut P,
+    metrics: Option<&dyn Metrics>,
+  ) -> std::io::Result<ProcessingSummary>
+  where
+    R: TransactionsReader,
+    P: PaymentsEngine,
+  {
+    let audit_log = match self.audit_log.as_mut() {
+      Some(audit_log) => audit_log,
+      None => {
+        return Ok(process_transactions(transactions_reader, payments_engine, metrics).await);
+      }
+    };
+
+    let mut transactions = transactions_reader.read_transactions();
+    let mut summary = ProcessingSummary::default();
+    while let Some(maybe_transaction) = transactions.next().await {
+      match maybe_transaction {
+        Ok(transaction) => {
+          let accepted = if payments_engine.validate(&transaction).is_ok() {
+            audit_log.append(key_name, &transaction).await?;
+            payments_engine.process(transaction).await.is_ok()
+          } else {
+            false
+          };
+          if accepted {
+            summary.accepted += 1;
+          } else {
+            summary.rejected += 1;
+          }
+        }
+        Err(_) => {
+          summary.unreadable += 1;
+          if let Some(metrics) = metrics {
+            metrics.record_unreadable();
+          }
+        }
+      }
+    }
+    Ok(summary)
+  }
+}
+
+/// A connection from a client, either encrypted or not.
+trait Connection: AsyncRead + AsyncWrite + Unpin {}
+
+impl<T> Connection for T where T: AsyncRead + AsyncWrite + Unpin {}
+
+/// Accept the connection, doing the TLS handshake when there is an acceptor.
+async fn accept(
+  stream: TcpStream,
+  tls_acceptor: Option<&TlsAcceptor>,
+) -> std::io::Result<Box<dyn Connection>> {
+  match tls_acceptor {
+    #[cfg(feature = "tls")]
+    Some(tls_acceptor) => Ok(Box::new(tls_acceptor.accept(stream).await?)),
+    #[cfg(not(feature = "tls"))]
+    Some(tls_acceptor) => match *tls_acceptor {},
+    None => Ok(Box::new(stream)),
+  }
+}
+
+/// Create the acceptor of the TLS connections from the PEM encoded certificate chain and PKCS8 private key.
+#[cfg(feature = "tls")]
+pub fn tls_acceptor(certificates: &[u8], private_key: &[u8]) -> Result<TlsAcceptor> {
+  use tokio_rustls::rustls::internal::pemfile;
+  use tokio_rustls::rustls::{NoClientAuth, ServerConfig};
+
+  let certificates =
+    pemfile::certs(&mut &*certificates).map_err(|_| anyhow::anyhow!("Invalid TLS certificates"))?;
+  let private_key = pemfile::pkcs8_private_keys(&mut &*private_key)
+    .map_err(|_| anyhow::anyhow!("Invalid TLS private key"))?
+    .into_iter()
+    .next()
+    .ok_or_else(|| anyhow::anyhow!("The TLS private key must be PKCS8 encoded"))?;
+
+  let mut config = ServerConfig::new(NoClientAuth::new());
+  config.set_single_cert(certificates, private_key)?;
+  Ok(TlsAcceptor::from(Arc::new(config)))
+}
+
+async fn handle<P>(
+  payments_engine: Rc<Mutex<P>>,
+  options: RequestOptions,
+  request: Request<Body>,
+) -> core::result::Result<Response<Body>, Infallible>
+where
+  P: PaymentsEngine,
+{
+  let key_name = match options.access_control.as_ref() {
+    Some(access_control) => {
+      let authorization = access_control
+        .lock()
+        .await
+        .authorize(&request, Instant::now());
+      match authorization {
+        Ok(key_name) => Some(key_name),
+        Err(status) => return Ok(status_response(status)),
+      }
+    }
+    None => None,
+  };
+
+  let retry_after = match (request.method(), request.uri().path()) {
+    (&Method::POST, TRANSACTIONS_PATH) => options
+      .admission_control
+      .as_ref()
+      .and_then(|admission_control| admission_control.admit(content_length(&request)).err()),
+    _ => None,
+  };
+
+  let response = match (request.method(), request.uri().path()) {
+    (&Method::POST, TRANSACTIONS_PATH) if options.read_only => {
+      status_response(StatusCode::METHOD_NOT_ALLOWED)
+    }
+    (&Method::POST, TRANSACTIONS_PATH) if retry_after.is_some() => {
+      let retry_after = retry_after.unwrap_or_default().as_secs().max(1);
+      Response::builder()
+        .status(StatusCode::TOO_MANY_REQUESTS)
+        .header(hyper::header::RETRY_AFTER, retry_after.to_string())
+        .body(Body::empty())
+        .unwrap_or_else(|_| status_response(StatusCode::INTERNAL_SERVER_ERROR))
+    }
+    (&Method::POST, TRANSACTIONS_PATH) => {
+      let body = request
+        .into_body()
+        .map(|chunk| chunk.map_err(|err| std::io::Error::new(std::io::ErrorKind::Other, err)));
+      let mut transactions_reader = CsvTransactionsReader::new(StreamReader::new(body))
+        .with_amount_parser(options.amount_parser);
+      let metrics = options
+        .metrics
+        .as_deref()
+        .map(|metrics| metrics as &dyn Metrics);
+      let mut payments_engine = payments_engine.lock().await;
+      let result = match (options.access_control.as_ref(), key_name) {
+        (Some(access_control), Some(key_name)) => {
+          access_control
+            .lock()
+            .await
+            .process(
+              &key_name,
+              &mut transactions_reader,
+              &mut *payments_engine,
+              metrics,
+            )
+            .await
+        }
+        _ => {
+          Ok(process_transactions(&mut transactions_reader, &mut *payments_engine, metrics).await)
+        }
+      };
+      match result.map(|summary| serde_json::to_vec(&summary)) {
+        Ok(Ok(summary)) => Response::builder()
+          .header(hyper::header::CONTENT_TYPE, "application/json")
+          .body(Body::from(summary))
+          .unwrap_or_else(|_| status_response(StatusCode::INTERNAL_SERVER_ERROR)),
+        _ => status_response(StatusCode::INTERNAL_SERVER_ERROR),
+      }
+    }
+    (&Method::GET, ACCOUNTS_PATH) => match account_filter(request.uri().query()) {
+      Ok(filter) => {
+        let mut buffer = Vec::<u8>::new();
+        let payments_engine = payments_engine.lock().await;
+        let report = match filter {
+          Some(filter) => AccountsReportStream::from(payments_engine.accounts_matching(filter)),
+          None => payments_engine.accounts_report_stream(),
+        };
+        let result = CsvAccountsReportWriter::with_schema(&mut buffer, options.report_schema)
+          .with_metadata(options.metadata)
+          .write_accounts_report(report)
+          .await;
+        match result {
+          Ok(()) => Response::builder()
+            .header(hyper::header::CONTENT_TYPE, "text/csv")
+            .body(Body::from(buffer))
+            .unwrap_or_else(|_| status_response(StatusCode::INTERNAL_SERVER_ERROR)),
+          Err(_) => status_response(StatusCode::INTERNAL_SERVER_ERROR),
+        }
+      }
+      Err(_) => status_response(StatusCode::BAD_REQUEST),
+    },
+    (&Method::GET, METRICS_PATH) => match options.metrics.as_ref() {
+      Some(metrics) => Response::builder()
+        .header(hyper::header::CONTENT_TYPE, "text/plain; version=0.0.4")
+        .body(Body::from(metrics.render()))
+        .unwrap_or_else(|_| status_response(StatusCode::INTERNAL_SERVER_ERROR)),
+      None => status_response(StatusCode::NOT_FOUND),
+    },
+    (&Method::GET, STATS_PATH) => match options.metrics.as_ref() {
+      Some(metrics) => match serde_json::to_vec(&metrics.stream_stats()) {
+        Ok(stats) => Response::builder()
+          .header(hyper::header::CONTENT_TYPE, "application/json")
+          .body(Body::from(stats))
+          .unwrap_or_else(|_| status_response(StatusCode::INTERNAL_SERVER_ERROR)),
+        Err(_) => status_response(StatusCode::INTERNAL_SERVER_ERROR),
+      },
+      None => status_response(StatusCode::NOT_FOUND),
+    },
+    (&Method::GET, path) if path.starts_with(ACCOUNTS_PATH) && path.ends_with(TIMELINE_SUFFIX) => {
+      match (timeline_client(path), options.timeline.as_ref()) {
+        (Some(client_id), Some(timeline)) => timeline_response(client_id, timeline).await,
+        (None, Some(_)) => status_response(StatusCode::BAD_REQUEST),
+        (_, None) => status_response(StatusCode::NOT_FOUND),
+      }
+    }
+    (_, TRANSACTIONS_PATH) | (_, ACCOUNTS_PATH) => status_response(StatusCode::METHOD_NOT_ALLOWED),
+    _ => status_response(StatusCode::NOT_FOUND),
+  };
+  Ok(response)
+}
+
+/// The [`AccountFilter`] with the parameters of the query, or `None` when there are none.
+fn account_filter(query: Option<&str>) -> Result<Option<AccountFilter>> {
+  let params = form_urlencoded::parse(query.unwrap_or_default().as_bytes());
+  let mut filter: Option<AccountFilter> = None;
+  for (name, value) in params {
+    filter = Some(filter.unwrap_or_default().with_param(&name, &value)?);
+  }
+  Ok(filter)
+}
+
+/// The size of the body of the request, or zero when it is not known in advance (like with `Transfer-Encoding: chunked`).
+fn content_length(request: &Request<Body>) -> u64 {
+  request
+    .headers()
+    .get(hyper::header::CONTENT_LENGTH)
+    .and_then(|value| value.to_str().ok())
+    .and_then(|value| value.parse().ok())
+    .unwrap_or_default()
+}
+
+/// The client of a `/accounts/{id}/timeline` path, or `None` when the ID is not valid.
+fn timeline_client(path: &str) -> Option<ClientId> {
+  path
+    .strip_prefix(ACCOUNTS_PATH)?
+    .strip_prefix('/')?
+    .strip_suffix(TIMELINE_SUFFIX)?
+    .parse()
+    .ok()
+}
+
+/// The timeline of the client as CSV, or `404 Not Found` when no transaction was applied to its account.
+async fn timeline_response(
+  client_id: ClientId,
+  timeline: &std::sync::Mutex<Statements>,
+) -> Response<Body> {
+  let lines = match timeline.lock() {
+    Ok(statements) => statements.timeline(client_id).to_vec(),
+    Err(_) => return status_response(StatusCode::INTERNAL_SERVER_ERROR),
+  };
+  if lines.is_empty() {
+    return status_response(StatusCode::NOT_FOUND);
+  }
+  let mut buffer = Vec::<u8>::new();
+  let result = CsvStatementsWriter::new(&mut buffer)
+    .write_statements(lines.into_iter())
+    .await;
+  match result {
+    Ok(()) => Response::builder()
+      .header(hyper::header::CONTENT_TYPE, "text/csv")
+      .body(Body::from(buffer))
+      .unwrap_or_else(|_| status_response(StatusCode::INTERNAL_SERVER_ERROR)),
+    Err(_) => status_response(StatusCode::INTERNAL_SERVER_ERROR),
+  }
+}
+
+fn status_response(status: StatusCode) -> Response<Body> {
+  let mut response = Response::new(Body::empty());
+  *response.status_mut() = status;
+  response
+}
+
+/// Executor for the background tasks of the connections, which spawns them into the current [`LocalSet`].
+#[derive(Clone, Copy)]
+struct LocalExecutor;
+
+impl<F> hyper::rt::Executor<F> for LocalExecutor
+where
+  F: Future + 'static,
+{
+  fn execute(&self, future: F) {
+    tokio::task::spawn_local(future);
+  }
+}
+
+#[cfg(test)]
+mod test {
+
+  use std::pin::Pin;
+  use std::task::{Context, Poll};
+
+  use indoc::indoc;
+
+  use super::*;
+  use crate::payments::{InMemoryPaymentsEngine, MeteredPaymentsEngine, StatementsPaymentsEngine};
+
+  async fn request(
+    payments_engine: &Rc<Mutex<InMemoryPaymentsEngine>>,
+    method: Method,
+    path: &str,
+    body: &'static str,
+  ) -> (StatusCode, String) {
+    authorized_request(payments_engine, None, None, method, path, body).await
+  }
+
+  async fn authorized_request(
+    payments_engine: &Rc<Mutex<InMemoryPaymentsEngine>>,
+    access_control: Option<&Rc<Mutex<AccessControl>>>,
+    key: Option<&str>,
+    method: Method,
+    path: &str,
+    body: &'static str,
+  ) -> (StatusCode, String) {
+    let options = RequestOptions {
+      amount_parser: AmountParser::default(),
+      report_schema: ReportSchema::default(),
+      metadata: None,
+      access_control: access_control.cloned(),
+      metrics: None,
+      read_only: false,
+      timeline: None,
+      admission_control: None,
+    };
+    send(payments_engine, options, key, method, path, body).await
+  }
+
+  async fn send<P: PaymentsEngine>(
+    payments_engine: &Rc<Mutex<P>>,
+    options: RequestOptions,
+    key: Option<&str>,
+    method: Method,
+    path: &str,
+    body: &'static str,
+  ) -> (StatusCode, String) {
+    let mut request = Request::builder().method(method).uri(path);
+    if let Some(key) = key {
+      request = request.header(hyper::header::AUTHORIZATION, format!("Bearer {}", key));
+    }
+    let request = request.body(Body::from(body)).unwrap();
+
+    let response = handle(payments_engine.clone(), options, request)
+      .await
+      .unwrap();
+
+    let status = response.status();
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    (status, String::from_utf8_lossy(&body).to_string())
+  }
+
+  fn summary(accepted: u64, rejected: u64, unreadable: u64) -> String {
+    format!(
+      r#"{{"accepted":{},"rejected":{},"unreadable":{}}}"#,
+      accepted, rejected, unreadable
+    )
+  }
+
+  #[tokio::test]
+  async fn handle_transactions_and_accounts() {
+    let payments_engine = Rc::new(Mutex::new(InMemoryPaymentsEngine::new()));
+
+    let first_batch = indoc! { "
+      type,       client,   tx,  amount
+      deposit,         1,  101,     100
+      deposit,         2,  201,      50
+    " };
+
+    let second_batch = indoc! { "
+      type,       client,   tx,  amount
+      withdrawal,      1,  102,      30
+      withdrawal,      2,  202,     100
+    " };
+
+    assert_eq!(
+      request(&payments_engine, Method::POST, "/transactions", first_batch).await,
+      (StatusCode::OK, summary(2, 0, 0))
+    );
+    assert_eq!(
+      request(
+        &payments_engine,
+        Method::POST,
+        "/transactions",
+        second_batch
+      )
+      .await,
+      (StatusCode::OK, summary(1, 1, 0))
+    );
+
+    let (status, report) = request(&payments_engine, Method::GET, "/accounts", "").await;
+    assert_eq!(status, StatusCode::OK);
+    let mut lines: Vec<&str> = report.lines().collect();
+    lines.sort_unstable();
+    assert_eq!(
+      lines,
+      vec![
+        "1,70,0,70,false",
+        "2,50,0,50,false",
+        "client,available,held,total,locked",
+      ]
+    );
+  }
+
+  #[tokio::test]
+  async fn handle_chunked_transactions() {
+    let payments_engine = Rc::new(Mutex::new(InMemoryPaymentsEngine::new()));
+    // the records are split across the chunks, which are read as they arrive
+    let chunks: Vec<std::io::Result<&'static str>> = vec![
+      Ok("type,client,tx,amount\ndeposit,1,1"),
+      Ok("01,100\nwithdrawal,1,102,30\nwrong\n"),
+      Ok("withdrawal,1,103,500\n"),
+    ];
+    let chunked_request = Request::builder()
+      .method(Method::POST)
+      .uri("/transactions")
+      .header(hyper::header::TRANSFER_ENCODING, "chunked")
+      .body(Body::wrap_stream(tokio_stream::iter(chunks)))
+      .unwrap();
+    let options = RequestOptions {
+      amount_parser: AmountParser::default(),
+      report_schema: ReportSchema::default(),
+      metadata: None,
+      access_control: None,
+      metrics: None,
+      read_only: false,
+      timeline: None,
+      admission_control: None,
+    };
+
+    let response = handle(payments_engine.clone(), options, chunked_request)
+      .await
+      .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    assert_eq!(String::from_utf8_lossy(&body), summary(2, 1, 1));
+    assert_eq!(
+      request(&payments_engine, Method::GET, "/accounts", "").await,
+      (
+        StatusCode::OK,
+        "client,available,held,total,locked\n1,70,0,70,false\n".to_string()
+      )
+    );
+  }
+
+  #[tokio::test]
+  async fn handle_accounts_with_filter() {
+    let payments_engine = Rc::new(Mutex::new(InMemoryPaymentsEngine::new()));
+
+    let transactions = indoc! { "
+      type,       client,   tx,  amount
+      deposit,         1,  101,     100
+      deposit,         2,  201,      50
+      deposit,         3,  301,      20
+    " };
+
+    assert_eq!(
+      request(
+        &payments_engine,
+        Method::POST,
+        "/transactions",
+        transactions
+      )
+      .await,
+      (StatusCode::OK, summary(3, 0, 0))
+    );
+
+    assert_eq!(
+      request(
+        &payments_engine,
+        Method::GET,
+        "/accounts?min_total=50&client_ids=2-3",
+        ""
+      )
+      .await,
+      (
+        StatusCode::OK,
+        "client,available,held,total,locked\n2,50,0,50,false\n".to_string()
+      )
+    );
+    assert_eq!(
+      request(
+        &payments_engine,
+        Method::GET,
+        "/accounts?min_total=many",
+        ""
+      )
+      .await
+      .0,
+      StatusCode::BAD_REQUEST
+    );
+  }
+
+  #[tokio::test]
+  async fn handle_unknown_requests() {
+    let payments_engine = Rc::new(Mutex::new(InMemoryPaymentsEngine::new()));
+
+    assert_eq!(
+      request(&payments_engine, Method::GET, "/transactions", "")
+        .await
+        .0,
+      StatusCode::METHOD_NOT_ALLOWED
+    );
+    assert_eq!(
+      request(&payments_engine, Method::GET, "/unknown", "")
+        .await
+        .0,
+      StatusCode::NOT_FOUND
+    );
+  }
+
+  #[tokio::test]
+  async fn handle_read_only_requests() {
+    let payments_engine = Rc::new(Mutex::new(InMemoryPaymentsEngine::new()));
+    request(
+      &payments_engine,
+      Method::POST,
+      "/transactions",
+      "type,client,tx,amount\ndeposit,1,1,10\n",
+    )
+    .await;
+    let options = || RequestOptions {
+      amount_parser: AmountParser::default(),
+      report_schema: ReportSchema::default(),
+      metadata: None,
+      access_control: None,
+      metrics: None,
+      read_only: true,
+      timeline: None,
+      admission_control: None,
+    };
+
+    assert_eq!(
+      send(
+        &payments_engine,
+        options(),
+        None,
+        Method::POST,
+        "/transactions",
+        "type,client,tx,amount\ndeposit,1,2,5.0\n"
+      )
+      .await,
+      (StatusCode::METHOD_NOT_ALLOWED, String::new())
+    );
+    assert_eq!(
+      send(
+        &payments_engine,
+        options(),
+        None,
+        Method::GET,
+        "/accounts",
+        ""
+      )
+      .await,
+      (
+        StatusCode::OK,
+        "client,available,held,total,locked\n1,10,0,10,false\n".to_string()
+      )
+    );
+  }
+
+  #[tokio::test]
+  async fn handle_uploads_under_memory_pressure() {
+    let payments_engine = Rc::new(Mutex::new(InMemoryPaymentsEngine::new()));
+    let memory_used = Rc::new(std::cell::Cell::new(90));
+    let options = || {
+      let memory_used = memory_used.clone();
+      RequestOptions {
+        amount_parser: AmountParser::default(),
+        report_schema: ReportSchema::default(),
+        metadata: None,
+        access_control: None,
+        metrics: None,
+        read_only: false,
+        timeline: None,
+        admission_control: Some(Rc::new(
+          AdmissionControl::new(100, move || Some(memory_used.get()))
+            .with_retry_after(Duration::from_secs(30)),
+        )),
+      }
+    };
+    let upload = |body: &'static str| {
+      Request::builder()
+        .method(Method::POST)
+        .uri("/transactions")
+        .header(hyper::header::CONTENT_LENGTH, body.len())
+        .body(Body::from(body))
+        .unwrap()
+    };
+
+    // the upload doesn't fit into the memory left
+    let response = handle(
+      payments_engine.clone(),
+      options(),
+      upload("type,client,tx,amount\ndeposit,1,1,10\n"),
+    )
+    .await
+    .unwrap();
+    assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(
+      response.headers().get(hyper::header::RETRY_AFTER).unwrap(),
+      "30"
+    );
+    // the rest of the requests are still served
+    assert_eq!(
+      send(
+        &payments_engine,
+        options(),
+        None,
+        Method::GET,
+        "/accounts",
+        ""
+      )
+      .await,
+      (StatusCode::OK, String::new())
+    );
+
+    memory_used.set(50);
+    let response = handle(
+      payments_engine.clone(),
+      options(),
+      upload("type,client,tx,amount\ndeposit,1,1,10\n"),
+    )
+    .await
+    .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+  }
+
+  #[tokio::test]
+  async fn handle_timeline() {
+    let timeline = Arc::new(std::sync::Mutex::new(Statements::new()));
+    let payments_engine = Rc::new(Mutex::new(StatementsPaymentsEngine::new(
+      InMemoryPaymentsEngine::new(),
+      timeline.clone(),
+    )));
+    let options = || RequestOptions {
+      amount_parser: AmountParser::default(),
+      report_schema: ReportSchema::default(),
+      metadata: None,
+      access_control: None,
+      metrics: None,
+      read_only: false,
+      timeline: Some(timeline.clone()),
+      admission_control: None,
+    };
+    let get = |path| send(&payments_engine, options(), None, Method::GET, path, "");
+
+    let transactions = indoc! { "
+      type,       client,   tx,  amount
+      deposit,         1,  101,     100
+      deposit,         2,  201,      50
+      withdrawal,      1,  102,     200
+      dispute,         1,  101,
+      resolve,         1,  101,
+    " };
+    assert_eq!(
+      send(
+        &payments_engine,
+        options(),
+        None,
+        Method::POST,
+        "/transactions",
+        transactions
+      )
+      .await,
+      (StatusCode::OK, summary(4, 1, 0))
+    );
+
+    assert_eq!(
+      get("/accounts/1/timeline").await,
+      (
+        StatusCode::OK,
+        indoc! { "
+          client,tx,type,amount,available,held,total
+          1,101,deposit,100,100,0,100
+          1,101,dispute,100,0,100,100
+          1,101,resolve,100,100,0,100
+        " }
+        .to_string()
+      )
+    );
+    assert_eq!(get("/accounts/3/timeline").await.0, StatusCode::NOT_FOUND);
+    assert_eq!(
+      get("/accounts/one/timeline").await.0,
+      StatusCode::BAD_REQUEST
+    );
+    assert_eq!(
+      request(
+        &Rc::new(Mutex::new(InMemoryPaymentsEngine::new())),
+        Method::GET,
+        "/accounts/1/timeline",
+        ""
+      )
+      .await
+      .0,
+      StatusCode::NOT_FOUND
+    );
+  }
+
+  #[tokio::test]
+  async fn handle_metrics() {
+    let metrics = Arc::new(PrometheusMetrics::new());
+    let payments_engine = Rc::new(Mutex::new(MeteredPaymentsEngine::new(
+      InMemoryPaymentsEngine::new(),
+      metrics.clone(),
+    )));
+    let options = || RequestOptions {
+      amount_parser: AmountParser::default(),
+      report_schema: ReportSchema::default(),
+      metadata: None,
+      access_control: None,
+      metrics: Some(metrics.clone()),
+      read_only: false,
+      timeline: None,
+      admission_control: None,
+    };
+
+    let transactions = indoc! { "
+      type,       client,   tx,  amount
+      deposit,         1,  101,     100
+      wrong
+      withdrawal,      1,  102,     200
+    " };
+    assert_eq!(
+      send(
+        &payments_engine,
+        options(),
+        None,
+        Method::POST,
+        "/transactions",
+        transactions
+      )
+      .await
+      .0,
+      StatusCode::OK
+    );
+
+    let (status, body) = send(
+      &payments_engine,
+      options(),
+      None,
+      Method::GET,
+      "/metrics",
+      "",
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, metrics.render());
+    assert!(body.contains("payments_transactions_processed_total{type=\"deposit\"} 1\n"));
+    assert!(body.contains(
+      "payments_transactions_rejected_total{type=\"withdrawal\",error=\"not_enough_available_funds\"} 1\n"
+    ));
+    assert!(body.contains("payments_records_unreadable_total 1\n"));
+
+    let (status, body) = send(&payments_engine, options(), None, Method::GET, "/stats", "").await;
+    assert_eq!(status, StatusCode::OK);
+    let mut stats: serde_json::Value = serde_json::from_str(&body).unwrap();
+    // the minute depends on the wall clock
+    let per_minute = stats["per_minute"].as_array_mut().unwrap();
+    assert_eq!(per_minute.len(), 1);
+    per_minute[0]["start"] = serde_json::Value::Null;
+    assert_eq!(
+      stats,
+      serde_json::json!({
+        "processed": 1,
+        "rejected": 1,
+        "unreadable": 1,
+        "watermark": null,
+        "consumer_lag_seconds": null,
+        "end_to_end_latency_seconds": null,
+        "per_minute": [{"start": null, "processed": 1, "rejected": 1}],
+        "per_input_hour": [],
+      })
+    );
+
+    let payments_engine = Rc::new(Mutex::new(InMemoryPaymentsEngine::new()));
+    assert_eq!(
+      request(&payments_engine, Method::GET, "/metrics", "")
+        .await
+        .0,
+      StatusCode::NOT_FOUND
+    );
+    assert_eq!(
+      request(&payments_engine, Method::GET, "/stats", "").await.0,
+      StatusCode::NOT_FOUND
+    );
+  }
+
+  /// A destination for the audit log that can be inspected while the access control owns it.
+  #[derive(Clone, Default)]
+  struct SharedBuffer(Arc<std::sync::Mutex<Vec<u8>>>);
+
+  impl AsyncWrite for SharedBuffer {
+    fn poll_write(
+      self: Pin<&mut Self>,
+      _cx: &mut Context<'_>,
+      buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+      self.0.lock().unwrap().extend_from_slice(buf);
+      Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+      Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+      Poll::Ready(Ok(()))
+    }
+  }
+
+  async fn access_control(audit: &SharedBuffer) -> Rc<Mutex<AccessControl>> {
+    let api_keys = ApiKeys::load(
+      indoc! { "
+        key,      name,        requests_per_minute
+        s3cr3t,   backoffice,
+        t0k3n,    partner,     2
+      " }
+      .as_bytes(),
+    )
+    .await
+    .unwrap();
+    let audit_log: AuditLogWrite = AuditLog::new(Box::new(audit.clone()));
+    Rc::new(Mutex::new(
+      AccessControl::new(api_keys).with_audit_log(audit_log),
+    ))
+  }
+
+  #[tokio::test]
+  async fn handle_authorized_transactions_with_audit() {
+    let payments_engine = Rc::new(Mutex::new(InMemoryPaymentsEngine::new()));
+    let audit = SharedBuffer::default();
+    let access_control = access_control(&audit).await;
+
+    let transactions = indoc! { "
+      type,       client,   tx,  amount
+      deposit,         1,  101,     100
+      withdrawal,      1,  102,     200
+    " };
+
+    assert_eq!(
+      authorized_request(
+        &payments_engine,
+        Some(&access_control),
+        None,
+        Method::POST,
+        "/transactions",
+        transactions
+      )
+      .await
+      .0,
+      StatusCode::UNAUTHORIZED
+    );
+    assert_eq!(
+      authorized_request(
+        &payments_engine,
+        Some(&access_control),
+        Some("backoffice"),
+        Method::GET,
+        "/accounts",
+        ""
+      )
+      .await
+      .0,
+      StatusCode::UNAUTHORIZED
+    );
+    assert_eq!(
+      authorized_request(
+        &payments_engine,
+        Some(&access_control),
+        Some("s3cr3t"),
+        Method::POST,
+        "/transactions",
+        transactions
+      )
+      .await
+      .0,
+      StatusCode::OK
+    );
+
+    // the rejected withdrawal is not audited
+    assert_eq!(
+      String::from_utf8_lossy(&audit.0.lock().unwrap()),
+      indoc! { "
+        key,type,client,tx,amount,to,timestamp
+        backoffice,deposit,1,101,100,,
+      " }
+    );
+  }
+
+  #[tokio::test]
+  async fn handle_rate_limited_requests() {
+    let payments_engine = Rc::new(Mutex::new(InMemoryPaymentsEngine::new()));
+    let audit = SharedBuffer::default();
+    let access_control = access_control(&audit).await;
+
+    let mut statuses = Vec::new();
+    for key in &["t0k3n", "t0k3n", "t0k3n", "s3cr3t"] {
+      let (status, _) = authorized_request(
+        &payments_engine,
+        Some(&access_control),
+        Some(key),
+        Method::GET,
+        "/accounts",
+        "",
+      )
+      .await;
+      statuses.push(status);
+    }
+
+    assert_eq!(
+      statuses,
+      vec![
+        StatusCode::OK,
+        StatusCode::OK,
+        StatusCode::TOO_MANY_REQUESTS,
+        StatusCode::OK
+      ]
+    );
+  }
+
+  #[tokio::test]
+  async fn authorize_resets_the_rate_limit_window() {
+    let audit = SharedBuffer::default();
+    let access_control = access_control(&audit).await;
+    let mut access_control = access_control.lock().await;
+    let request = || {
+      Request::builder()
+        .uri("/accounts")
+        .header(hyper::header::AUTHORIZATION, "Bearer t0k3n")
+        .body(Body::empty())
+        .unwrap()
+    };
+
+    let start = Instant::now();
+    let later = start + RATE_LIMIT_WINDOW;
+    assert_eq!(
+      access_control.authorize(&request(), start),
+      Ok("partner".to_string())
+    );
+    assert_eq!(
+      access_control.authorize(&request(), start),
+      Ok("partner".to_string())
+    );
+    assert_eq!(
+      access_control.authorize(&request(), start),
+      Err(StatusCode::TOO_MANY_REQUESTS)
+    );
+    assert_eq!(
+      access_control.authorize(&request(), later),
+      Ok("partner".to_string())
+    );
+  }
+}
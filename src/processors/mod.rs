@@ -1,4 +1,22 @@
 //! This module contains the processors that glue together the rest of the components and drives the payments processing steps.
 //!
 
+pub mod batching;
+pub mod bundle;
+pub mod disputes;
+pub mod dumping;
+pub mod engine;
+pub mod generic;
+#[cfg(feature = "grpc")]
+pub mod grpc;
+pub mod history;
+#[cfg(feature = "http")]
+pub mod http;
+pub mod partitioned;
+pub mod reconcile;
+pub mod repl;
+pub mod replicated;
+pub mod resumable;
 pub mod simple;
+pub mod statements;
+pub mod sub_accounts;
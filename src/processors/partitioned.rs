@@ -0,0 +1,486 @@
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Instant;
+
+use anyhow::Result;
+use tokio::sync::mpsc;
+use tokio_stream::StreamExt;
+
+use super::batching::{AdaptiveBatching, BatchFeedback};
+use crate::io::{
+  AccountsReportWriter, ErrorSink, Rejection, RejectionReason, TransactionRecord,
+  TransactionsReader,
+};
+use crate::payments::{AccountsReportStream, PaymentsEngine, PaymentsEngineError, Transaction};
+
+/// Maximum number of transactions waiting to be processed by every partition, and of rejections waiting to be written, by default.
+const CHANNEL_CAPACITY: usize = 1024;
+
+/// A transaction sent to a worker, along with its line and raw record, to report it when rejected.
+type Item = (Option<u64>, Option<String>, Transaction);
+
+/// This processor does the same than the [`simple`](super::simple) one, but processes the transactions in parallel:
+/// - the transactions are partitioned by their `client_id` into `partitions` workers
+/// - every worker is a task with its own [`PaymentsEngine`] (created with `create_engine`), fed through a channel
+///   with batches of transactions, whose size is adapted to the latency of the worker (see [`AdaptiveBatching`])
+/// - once all the transactions have been processed, the accounts reports of all the workers are merged
+///
+/// All the transactions of a client go to the same worker and keep their order, so the result is the same
+/// than processing them sequentially. The reports are merged in the order of the partitions,
+/// so the accounts are only sorted within every partition.
+/// Transfers between clients of different workers are rejected with a [`PaymentsEngineError::CrossShardTransfer`],
+/// as they can't be applied atomically, and logged as warnings.
+///
+/// When a worker fails (for example, because its engine panics), the whole run fails without writing any report,
+/// so it can be restarted from the same input.
+///
+pub async fn run<R, F, P, W>(
+  transactions_reader: R,
+  partitions: usize,
+  create_engine: F,
+  accounts_report_writer: W,
+) -> Result<()>
+where
+  R: TransactionsReader,
+  F: Fn() -> P,
+  P: PaymentsEngine + Send + Sync + 'static,
+  W: AccountsReportWriter,
+{
+  run_with_batching(
+    transactions_reader,
+    partitions,
+    CHANNEL_CAPACITY,
+    AdaptiveBatching::default(),
+    create_engine,
+    accounts_report_writer,
+  )
+  .await
+}
+
+/// Same as [`run`] but with the maximum number of transactions waiting to be processed by every partition.
+pub async fn run_with_capacity<R, F, P, W>(
+  transactions_reader: R,
+  partitions: usize,
+  capacity: usize,
+  create_engine: F,
+  accounts_report_writer: W,
+) -> Result<()>
+where
+  R: TransactionsReader,
+  F: Fn() -> P,
+  P: PaymentsEngine + Send + Sync + 'static,
+  W: AccountsReportWriter,
+{
+  run_with_batching(
+    transactions_reader,
+    partitions,
+    capacity,
+    AdaptiveBatching::default(),
+    create_engine,
+    accounts_report_writer,
+  )
+  .await
+}
+
+/// Same as [`run_with_capacity`] but with the bounds of the batches sent to every partition and their latency budget.
+pub async fn run_with_batching<R, F, P, W>(
+  transactions_reader: R,
+  partitions: usize,
+  capacity: usize,
+  batching: AdaptiveBatching,
+  create_engine: F,
+  accounts_report_writer: W,
+) -> Result<()>
+where
+  R: TransactionsReader,
+  F: Fn() -> P,
+  P: PaymentsEngine + Send + Sync + 'static,
+  W: AccountsReportWriter,
+{
+  process_partitions(
+    transactions_reader,
+    partitions,
+    capacity,
+    batching,
+    create_engine,
+    accounts_report_writer,
+    None,
+  )
+  .await
+}
+
+/// Same as [`run_with_capacity`] but writing every rejected record into the [`ErrorSink`], instead of skipping it silently.
+/// The rejections of the workers are written as they arrive, so they don't keep the order of the input,
+/// and the workers wait for them to be written when there are `capacity` of them waiting.
+pub async fn run_with_errors<R, F, P, W, E>(
+  transactions_reader: R,
+  partitions: usize,
+  capacity: usize,
+  create_engine: F,
+  accounts_report_writer: W,
+  mut error_sink: E,
+) -> Result<()>
+where
+  R: TransactionsReader,
+  F: Fn() -> P,
+  P: PaymentsEngine + Send + Sync + 'static,
+  W: AccountsReportWriter,
+  E: ErrorSink,
+{
+  process_partitions(
+    transactions_reader,
+    partitions,
+    capacity,
+    AdaptiveBatching::default(),
+    create_engine,
+    accounts_report_writer,
+    Some(&mut error_sink),
+  )
+  .await
+}
+
+/// The sending side of a worker, which keeps the batch being filled for it.
+struct Partition {
+  sender: mpsc::Sender<Vec<Item>>,
+  feedback: Arc<BatchFeedback>,
+  batching: AdaptiveBatching,
+  batch: Vec<Item>,
+}
+
+impl Partition {
+  async fn push(&mut self, item: Item) -> Result<()> {
+    self.batch.push(item);
+    if self.batch.len() >= self.batching.size() {
+      self.flush().await?;
+    }
+    Ok(())
+  }
+
+  async fn flush(&mut self) -> Result<()> {
+    if self.batch.is_empty() {
+      return Ok(());
+    }
+    let (latency, queued) = self.feedback.observed();
+    self.batching.observe(latency, queued);
+    let batch = std::mem::replace(&mut self.batch, Vec::with_capacity(self.batching.size()));
+    self.feedback.sent();
+    self.sender.send(batch).await?;
+    Ok(())
+  }
+}
+
+/// Process the transactions in the partitions, writing the rejected records into the sink, when given.
+async fn process_partitions<R, F, P, W>(
+  mut transactions_reader: R,
+  partitions: usize,
+  capacity: usize,
+  batching: AdaptiveBatching,
+  create_engine: F,
+  mut accounts_report_writer: W,
+  mut error_sink: Option<&mut dyn ErrorSink>,
+) -> Result<()>
+where
+  R: TransactionsReader,
+  F: Fn() -> P,
+  P: PaymentsEngine + Send + Sync + 'static,
+  W: AccountsReportWriter,
+{
+  let partitions = partitions.max(1);
+  let collects_rejections = error_sink.is_some();
+  let (rejections_sender, mut rejections) = mpsc::channel::<Rejection>(capacity.max(1));
+  // the capacity is in transactions, while the channels keep batches of up to the largest size
+  let capacity = (capacity / batching.max_size()).max(1);
+
+  let (mut senders, workers): (Vec<_>, Vec<_>) = (0..partitions)
+    .map(|_| {
+      let (sender, mut receiver) = mpsc::channel::<Vec<Item>>(capacity);
+      let feedback = Arc::new(BatchFeedback::default());
+      let worker_feedback = feedback.clone();
+      let rejections_sender = rejections_sender.clone();
+      let mut payments_engine = create_engine();
+      let worker = tokio::spawn(async move {
+        while let Some(batch) = receiver.recv().await {
+          worker_feedback.received();
+          let start = Instant::now();
+          for (line, raw, transaction) in batch {
+            if let Err(err) = payments_engine.process(transaction).await {
+              // the engines already log their rejections, so they are only collected for the sink
+              if collects_rejections {
+                rejections_sender
+                  .send(Rejection {
+                    line,
+                    record: raw,
+                    reason: RejectionReason::Engine(err),
+                  })
+                  .await
+                  .ok();
+              }
+            }
+          }
+          worker_feedback.processed(start.elapsed());
+        }
+        payments_engine
+      });
+      let partition = Partition {
+        sender,
+        feedback,
+        batching: batching.clone(),
+        batch: Vec::new(),
+      };
+      (partition, worker)
+    })
+    .unzip();
+  drop(rejections_sender);
+
+  let mut records = transactions_reader.read_records();
+  let mut flush_deadline = tokio::time::Instant::now() + batching.flush_interval();
+  loop {
+    // the partial batches are flushed when the input is slower than their flush interval
+    let record = tokio::select! {
+      record = records.next() => record,
+      _ = tokio::time::sleep_until(flush_deadline) => {
+        let mut flush_interval = batching.flush_interval();
+        for partition in senders.iter_mut() {
+          draining(partition.flush(), &mut rejections, &mut error_sink).await?;
+          flush_interval = flush_interval.min(partition.batching.flush_interval());
+        }
+        flush_deadline = tokio::time::Instant::now() + flush_interval;
+        continue;
+      }
+    };
+    let record = match record {
+      Some(record) => record,
+      None => break,
+    };
+    if let Some(sink) = error_sink.as_mut() {
+      while let Ok(rejection) = rejections.try_recv() {
+        sink.reject(rejection).await?;
+      }
+    }
+
+    let TransactionRecord {
+      line,
+      raw,
+      transaction,
+    } = record;
+    let reason = match transaction {
+      Ok(transaction) => {
+        // client IDs are usually sequential, so the modulo distributes them uniformly
+        let partition = transaction.client_id() as usize % partitions;
+        match transaction {
+          // transfers can only be applied atomically when both clients are owned by the same worker
+          Transaction::Transfer {
+            from_client,
+            to_client,
+            ..
+          } if to_client as usize % partitions != partition => {
+            let err = PaymentsEngineError::CrossShardTransfer(from_client, to_client);
+            tracing::warn!(line, error = %err, kind = err.kind(), "Transaction rejected");
+            RejectionReason::Engine(err)
+          }
+          transaction => {
+            let raw = raw.filter(|_| collects_rejections);
+            let push = senders[partition].push((line, raw, transaction));
+            draining(push, &mut rejections, &mut error_sink).await?;
+            continue;
+          }
+        }
+      }
+      Err(err) => RejectionReason::Read(err),
+    };
+
+    if let Some(sink) = error_sink.as_mut() {
+      sink
+        .reject(Rejection {
+          line,
+          record: raw,
+          reason,
+        })
+        .await?;
+    }
+  }
+  for partition in senders.iter_mut() {
+    draining(partition.flush(), &mut rejections, &mut error_sink).await?;
+  }
+  drop(senders);
+
+  // the workers only finish once their rejections are taken, so they are written before waiting for them
+  if let Some(sink) = error_sink.as_mut() {
+    while let Some(rejection) = rejections.recv().await {
+      sink.reject(rejection).await?;
+    }
+  }
+
+  let mut payments_engines = Vec::with_capacity(partitions);
+  for worker in workers {
+    payments_engines.push(worker.await?);
+  }
+
+  let report = payments_engines
+    .iter()
+    .flat_map(|payments_engine| payments_engine.accounts_report());
+
+  accounts_report_writer
+    .write_accounts_report(AccountsReportStream::iter(report))
+    .await
+}
+
+/// Wait for a batch to be sent to a worker while writing the rejections of the workers into the sink, if any,
+/// as a worker with all its rejections waiting to be written doesn't take more batches until they are.
+async fn draining<F>(
+  sending: F,
+  rejections: &mut mpsc::Receiver<Rejection>,
+  error_sink: &mut Option<&mut dyn ErrorSink>,
+) -> Result<()>
+where
+  F: Future<Output = Result<()>>,
+{
+  tokio::pin!(sending);
+  loop {
+    tokio::select! {
+      result = &mut sending => return result,
+      Some(rejection) = rejections.recv(), if error_sink.is_some() => {
+        if let Some(sink) = error_sink.as_mut() {
+          sink.reject(rejection).await?;
+        }
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod test {
+
+  use indoc::indoc;
+
+  use super::*;
+  use crate::io::{CsvAccountsReportWriter, CsvErrorSink, CsvTransactionsReader};
+  use crate::payments::InMemoryPaymentsEngine;
+
+  #[tokio::test(flavor = "multi_thread")]
+  async fn run_successfully() {
+    let transactions = indoc! { "
+      type,       client,   tx,  amount
+      deposit,         2,  201,      20
+      deposit,         1,  101,     100
+      deposit,         3,  301,      10
+      wrong
+      withdrawal,      1,  102,      30
+      withdrawal,      2,  202,      30
+      dispute,         3,  301,
+      deposit,         4,  401,       5
+      chargeback,      3,  301,
+    " }
+    .as_bytes();
+    let transactions_reader = CsvTransactionsReader::new(transactions);
+
+    let mut buffer = Vec::<u8>::with_capacity(1024);
+
+    let result = run(
+      transactions_reader,
+      3,
+      InMemoryPaymentsEngine::new,
+      CsvAccountsReportWriter::new(&mut buffer),
+    )
+    .await;
+
+    assert!(result.is_ok());
+    let report = String::from_utf8_lossy(buffer.as_slice()).to_string();
+    let mut lines: Vec<&str> = report.lines().collect();
+    lines.sort_unstable();
+    assert_eq!(
+      lines,
+      vec![
+        "1,70,0,70,false",
+        "2,20,0,20,false",
+        "3,0,0,0,true",
+        "4,5,0,5,false",
+        "client,available,held,total,locked",
+      ]
+    );
+  }
+
+  #[tokio::test(flavor = "multi_thread")]
+  async fn run_with_errors_writes_rejections() {
+    let transactions = indoc! { "
+      type,       client,   tx,  amount,  to
+      deposit,         1,  101,     100,
+      deposit,         2,  201,      20,
+      transfer,        1,  102,      10,   2
+      withdrawal,      2,  202,      50,
+      deposit,         1,  103,     abc,
+    " }
+    .as_bytes();
+
+    let mut report = Vec::<u8>::new();
+    let mut errors = Vec::<u8>::new();
+
+    let result = run_with_errors(
+      CsvTransactionsReader::new(transactions),
+      2,
+      CHANNEL_CAPACITY,
+      InMemoryPaymentsEngine::new,
+      CsvAccountsReportWriter::new(&mut report),
+      CsvErrorSink::new(&mut errors),
+    )
+    .await;
+
+    assert!(result.is_ok());
+    let report = String::from_utf8_lossy(&report).to_string();
+    let mut lines: Vec<&str> = report.lines().collect();
+    lines.sort_unstable();
+    assert_eq!(
+      lines,
+      vec![
+        "1,100,0,100,false",
+        "2,20,0,20,false",
+        "client,available,held,total,locked",
+      ]
+    );
+    // the rejections of the workers don't keep the order of the input
+    let errors = String::from_utf8_lossy(&errors).to_string();
+    let mut lines: Vec<&str> = errors.lines().collect();
+    lines.sort_unstable();
+    assert_eq!(lines.len(), 4);
+    assert_eq!(
+      lines[0],
+      "4,\"transfer,        1,  102,      10,   2\",engine,Transfer from client 1 to client 2 of a different shard"
+    );
+    assert_eq!(
+      lines[1],
+      "5,\"withdrawal,      2,  202,      50,\",engine,Not enough available funds"
+    );
+    assert!(lines[2].starts_with("6,\"deposit,         1,  103,     abc,\",read,"));
+    assert_eq!(lines[3], "line,record,stage,error");
+  }
+
+  #[tokio::test(flavor = "multi_thread")]
+  async fn run_with_errors_waits_for_the_rejections_to_be_written() {
+    let mut transactions = String::from("type,client,tx,amount\n");
+    for transaction_id in 1..=100 {
+      transactions.push_str(&format!(
+        "withdrawal,{},{},1\n",
+        transaction_id % 4,
+        transaction_id
+      ));
+    }
+
+    let mut report = Vec::<u8>::new();
+    let mut errors = Vec::<u8>::new();
+
+    // the workers are blocked with a single rejection waiting, until it is written
+    let result = run_with_errors(
+      CsvTransactionsReader::new(transactions.as_bytes()),
+      2,
+      1,
+      InMemoryPaymentsEngine::new,
+      CsvAccountsReportWriter::new(&mut report),
+      CsvErrorSink::new(&mut errors),
+    )
+    .await;
+
+    assert!(result.is_ok());
+    let errors = String::from_utf8_lossy(&errors).to_string();
+    assert_eq!(errors.lines().count(), 101);
+  }
+}
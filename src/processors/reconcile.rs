@@ -0,0 +1,122 @@
+use anyhow::Result;
+use rust_decimal::Decimal;
+use tokio_stream::StreamExt;
+
+use super::simple::process_transactions;
+use crate::io::{rounded_account_report, BalancesReader, BreaksReportWriter, TransactionsReader};
+use crate::payments::{reconcile, PaymentsEngine};
+
+/// This processor reconciles the accounts against the balances from an external source. It
+/// - reads and processes transactions the same way than the [`simple`](super::simple) processor
+/// - reads the external balances using a [`BalancesReader`]
+/// - matches the accounts report with the external balances per client, with their funds rounded as they are written out
+/// - writes the breaks found (missing clients and amount mismatches beyond the `tolerance`) using a [`BreaksReportWriter`]
+///
+/// Contrary to the transactions, a balance that can not be read makes the reconciliation fail,
+/// as skipping it would report that client as missing.
+///
+pub async fn run<R, P, B, W>(
+  mut transactions_reader: R,
+  mut payments_engine: P,
+  mut balances_reader: B,
+  mut breaks_report_writer: W,
+  tolerance: Decimal,
+) -> Result<()>
+where
+  R: TransactionsReader,
+  P: PaymentsEngine,
+  B: BalancesReader,
+  W: BreaksReportWriter,
+{
+  process_transactions(&mut transactions_reader, &mut payments_engine, None).await;
+
+  let mut balances = Vec::new();
+  let mut balances_stream = balances_reader.read_balances();
+  while let Some(balance) = balances_stream.next().await {
+    balances.push(balance?);
+  }
+
+  let breaks = reconcile(
+    payments_engine
+      .accounts_report()
+      .map(rounded_account_report),
+    balances,
+    tolerance,
+  );
+
+  breaks_report_writer
+    .write_breaks_report(breaks.into_iter())
+    .await
+}
+
+#[cfg(test)]
+mod test {
+
+  use indoc::indoc;
+  use rust_decimal_macros::dec;
+
+  use super::*;
+  use crate::io::{CsvBalancesReader, CsvBreaksReportWriter, CsvTransactionsReader};
+  use crate::payments::InMemoryPaymentsEngine;
+
+  #[tokio::test]
+  async fn run_successfully() {
+    let transactions = indoc! { "
+      type,       client,   tx,  amount
+      deposit,         1,  101, 100.00004
+      deposit,         2,  102,      20
+      withdrawal,      2,  103,       5
+      deposit,         3,  104,      30
+    " }
+    .as_bytes();
+
+    let balances = indoc! { "
+      client, total
+      1,      100
+      2,      15.5
+      4,      40
+    " }
+    .as_bytes();
+
+    let mut buffer = Vec::<u8>::with_capacity(1024);
+
+    let result = run(
+      CsvTransactionsReader::new(transactions),
+      InMemoryPaymentsEngine::new(),
+      CsvBalancesReader::new(balances),
+      CsvBreaksReportWriter::new(&mut buffer),
+      dec!(0.1),
+    )
+    .await;
+
+    assert!(result.is_ok());
+    assert_eq!(
+      String::from_utf8_lossy(buffer.as_slice()),
+      "client,kind,engine_total,external_total,difference\n\
+       2,amount_mismatch,15,15.5,-0.5\n\
+       3,missing_in_external,30,,\n\
+       4,missing_in_engine,,40,\n"
+        .to_string()
+    )
+  }
+
+  #[tokio::test]
+  async fn run_fails_with_wrong_balances() {
+    let transactions = "type,client,tx,amount\n".as_bytes();
+    let balances = "client,total\n1,wrong\n".as_bytes();
+
+    let mut buffer = Vec::<u8>::with_capacity(1024);
+
+    let result = run(
+      CsvTransactionsReader::new(transactions),
+      InMemoryPaymentsEngine::new(),
+      CsvBalancesReader::new(balances),
+      CsvBreaksReportWriter::new(&mut buffer),
+      Decimal::ZERO,
+    )
+    .await;
+
+    assert!(result.is_err());
+    assert!(buffer.is_empty());
+  }
+}
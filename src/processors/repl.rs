@@ -0,0 +1,156 @@
+use anyhow::Result;
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncWrite, AsyncWriteExt};
+use tokio_stream::StreamExt;
+
+use crate::io::{CsvStatementsWriter, StatementsWriter, TransactionsReader};
+use crate::payments::{ClientId, PaymentsEngine, Statements};
+
+/// A query of the session.
+#[derive(Debug, PartialEq)]
+enum Query {
+  /// `history client=<id>`: the timeline of the client, with every transaction applied to its account and the balances after it.
+  History { client_id: ClientId },
+}
+
+impl Query {
+  fn parse(line: &str) -> core::result::Result<Self, String> {
+    let mut words = line.split_whitespace();
+    match (words.next(), words.next(), words.next()) {
+      (Some("history"), Some(param), None) => param
+        .strip_prefix("client=")
+        .and_then(|client_id| client_id.parse().ok())
+        .map(|client_id| Query::History { client_id })
+        .ok_or_else(|| format!("Invalid client: {}", param)),
+      _ => Err(format!("Unknown query: {}", line)),
+    }
+  }
+}
+
+/// This processor answers the queries of an interactive session about the transactions it processed. It
+/// - reads and processes transactions the same way than the [`simple`](super::simple) processor
+/// - records every accepted transaction into the [`Statements`] of the clients involved
+/// - reads one query per line, answering every one into the output:
+///   - `history client=<id>` writes the timeline of the client as CSV, like the [`statements`](super::statements) processor
+///
+/// The queries that can't be parsed are answered with an error line, and the session ends with the queries.
+///
+pub async fn run<R, P, Q, W>(
+  mut transactions_reader: R,
+  mut payments_engine: P,
+  queries: Q,
+  mut output: W,
+) -> Result<()>
+where
+  R: TransactionsReader,
+  P: PaymentsEngine,
+  Q: AsyncBufRead + Unpin,
+  W: AsyncWrite + Unpin + Send + Sync,
+{
+  let mut statements = Statements::new();
+  let mut transactions = transactions_reader.read_transactions();
+
+  while let Some(maybe_transaction) = transactions.next().await {
+    if let Ok(transaction) = maybe_transaction {
+      if payments_engine.process(transaction.clone()).await.is_ok() {
+        statements.record(&payments_engine, &transaction)?;
+      }
+    }
+  }
+  drop(transactions);
+
+  let mut lines = queries.lines();
+  while let Some(line) = lines.next_line().await? {
+    if line.trim().is_empty() {
+      continue;
+    }
+    match Query::parse(&line) {
+      Ok(Query::History { client_id }) => {
+        let timeline = statements.timeline(client_id).to_vec();
+        if timeline.is_empty() {
+          answer(
+            &mut output,
+            &format!("No transactions for client {}", client_id),
+          )
+          .await?;
+        } else {
+          CsvStatementsWriter::new(&mut output)
+            .write_statements(timeline.into_iter())
+            .await?;
+        }
+      }
+      Err(err) => answer(&mut output, &err).await?,
+    }
+  }
+  Ok(())
+}
+
+async fn answer<W>(output: &mut W, line: &str) -> std::io::Result<()>
+where
+  W: AsyncWrite + Unpin,
+{
+  output.write_all(format!("{}\n", line).as_bytes()).await?;
+  output.flush().await
+}
+
+#[cfg(test)]
+mod test {
+
+  use indoc::indoc;
+
+  use super::*;
+  use crate::io::CsvTransactionsReader;
+  use crate::payments::InMemoryPaymentsEngine;
+
+  #[test]
+  fn parse_queries() {
+    assert_eq!(
+      Query::parse("history client=7"),
+      Ok(Query::History { client_id: 7 })
+    );
+    assert_eq!(
+      Query::parse("history client=seven"),
+      Err("Invalid client: client=seven".to_string())
+    );
+    assert_eq!(
+      Query::parse("balance client=7"),
+      Err("Unknown query: balance client=7".to_string())
+    );
+  }
+
+  #[tokio::test]
+  async fn run_successfully() {
+    let transactions = indoc! { "
+      type,       client,   tx,  amount
+      deposit,         1,  101,     100
+      deposit,         2,  201,      30
+      withdrawal,      1,  102,     500
+      dispute,         1,  101,
+      chargeback,      1,  101,
+    " }
+    .as_bytes();
+    let queries = "history client=1\n\nhistory client=3\nhistory\n".as_bytes();
+
+    let mut buffer = Vec::<u8>::with_capacity(1024);
+
+    let result = run(
+      CsvTransactionsReader::new(transactions),
+      InMemoryPaymentsEngine::new(),
+      queries,
+      &mut buffer,
+    )
+    .await;
+
+    assert!(result.is_ok());
+    assert_eq!(
+      String::from_utf8_lossy(buffer.as_slice()),
+      indoc! {"
+        client,tx,type,amount,available,held,total
+        1,101,deposit,100,100,0,100
+        1,101,dispute,100,0,100,100
+        1,101,chargeback,100,0,0,0
+        No transactions for client 3
+        Unknown query: history
+      "}
+    )
+  }
+}
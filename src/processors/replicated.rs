@@ -0,0 +1,134 @@
+use anyhow::Result;
+use tokio_stream::StreamExt;
+
+use crate::io::{AccountsReportWriter, DigestExchange, TransactionsReader};
+use crate::payments::{PaymentsEngine, StateDigest};
+
+/// This processor works the same way than the [`simple`](super::simple) one, but as one of the replicas
+/// that process the same transactions, like the instances consuming the same Kafka topic for high availability.
+///
+/// Every `interval` records read (and at the end of the input), it takes the [`StateDigest`] of the accounts,
+/// and exchanges it with the rest of the replicas through the [`DigestExchange`].
+/// The divergences from the rest of the replicas are logged as errors, to be alerted on,
+/// and reported by the exchange with the clients whose accounts differ.
+///
+/// The records are counted in the order they are read, so the digests are only comparable between replicas
+/// that read the records in the same order. Failing to exchange a digest doesn't stop the processing.
+///
+pub async fn run<R, P, W>(
+  mut transactions_reader: R,
+  mut payments_engine: P,
+  mut accounts_report_writer: W,
+  exchange: DigestExchange,
+  interval: u64,
+) -> Result<()>
+where
+  R: TransactionsReader,
+  P: PaymentsEngine,
+  W: AccountsReportWriter,
+{
+  let interval = interval.max(1);
+  let mut transactions = transactions_reader.read_transactions();
+  let mut position = 0u64;
+
+  while let Some(maybe_transaction) = transactions.next().await {
+    if let Ok(transaction) = maybe_transaction {
+      payments_engine.process(transaction).await.ok();
+    }
+    position += 1;
+    if position % interval == 0 {
+      exchange_digest(&payments_engine, &exchange, position).await;
+    }
+  }
+  drop(transactions);
+
+  if position % interval != 0 {
+    exchange_digest(&payments_engine, &exchange, position).await;
+  }
+
+  accounts_report_writer
+    .write_accounts_report(payments_engine.accounts_report_stream())
+    .await
+}
+
+async fn exchange_digest<P>(payments_engine: &P, exchange: &DigestExchange, position: u64)
+where
+  P: PaymentsEngine,
+{
+  let digest = StateDigest::of(position, payments_engine.accounts_report());
+  match exchange.exchange(&digest).await {
+    Ok(divergences) => {
+      for divergence in divergences {
+        let clients: Vec<String> = divergence
+          .clients
+          .iter()
+          .map(|range| format!("{}-{}", range.start(), range.end()))
+          .collect();
+        tracing::error!(
+          position,
+          peer = %divergence.peer,
+          clients = %clients.join(";"),
+          "Replicas diverged"
+        );
+      }
+    }
+    Err(err) => tracing::warn!(error = %err, position, "Failed to exchange the state digest"),
+  }
+}
+
+#[cfg(test)]
+mod test {
+
+  use indoc::indoc;
+
+  use super::*;
+  use crate::io::{CsvAccountsReportWriter, CsvTransactionsReader};
+  use crate::payments::InMemoryPaymentsEngine;
+
+  #[tokio::test]
+  async fn run_replicas() {
+    let dir = std::env::temp_dir().join(format!(
+      "toy-payments-engine-replicas-{}",
+      std::process::id()
+    ));
+    tokio::fs::remove_dir_all(&dir).await.ok();
+
+    let transactions = indoc! { "
+      type,       client,   tx,  amount
+      deposit,         1,  101,     100
+      deposit,       300,  301,      20
+      withdrawal,      1,  102,      30
+    " };
+    // the second replica missed the withdrawal
+    let missed = indoc! { "
+      type,       client,   tx,  amount
+      deposit,         1,  101,     100
+      deposit,       300,  301,      20
+      wrong
+    " };
+
+    for (replica, transactions) in [("first", transactions), ("second", missed)] {
+      let mut buffer = Vec::<u8>::new();
+      let result = run(
+        CsvTransactionsReader::new(transactions.as_bytes()),
+        InMemoryPaymentsEngine::new(),
+        CsvAccountsReportWriter::new(&mut buffer),
+        DigestExchange::new(&dir, replica.to_string()),
+        2,
+      )
+      .await;
+      assert!(result.is_ok());
+      assert!(!buffer.is_empty());
+    }
+
+    let report = tokio::fs::read_to_string(dir.join("divergences.csv"))
+      .await
+      .unwrap();
+    tokio::fs::remove_dir_all(&dir).await.unwrap();
+
+    assert_eq!(
+      report,
+      "position,replica,peer,clients\n3,second,first,0-255\n"
+    );
+  }
+}
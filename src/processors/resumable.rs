@@ -0,0 +1,118 @@
+use anyhow::Result;
+use tokio_stream::StreamExt;
+
+use crate::io::{
+  AccountsReportWriter, ErrorSink, ProgressFile, Rejection, RejectionReason, TransactionsReader,
+};
+use crate::payments::PaymentsEngine;
+
+/// A processor that can be resumed after a crash, without processing again the records that were already processed.
+///
+/// It works the same way than the [`simple`](super::simple) processor, but the number of records processed so far
+/// is recorded into a [`ProgressFile`] after every record, and the ones already recorded are skipped.
+/// The rejected records are written into the [`ErrorSink`], when given.
+///
+/// Skipping the records only makes sense when the payments engine recovers its state on its own
+/// (like the [`WalPaymentsEngine`](crate::payments::WalPaymentsEngine)), and the input is the same, or it only grew since the previous run.
+/// The record being processed when the crash happened can be processed again, which the engine rejects as a duplicate when it was accepted.
+pub async fn run<R, P, W>(
+  mut transactions_reader: R,
+  mut payments_engine: P,
+  mut accounts_report_writer: W,
+  mut progress: ProgressFile,
+  mut error_sink: Option<&mut dyn ErrorSink>,
+) -> Result<()>
+where
+  R: TransactionsReader,
+  P: PaymentsEngine,
+  W: AccountsReportWriter,
+{
+  let skipped = progress.processed();
+  if skipped > 0 {
+    tracing::info!(skipped, "Resuming the processing");
+  }
+
+  let mut records = transactions_reader.read_records();
+  let mut position = 0u64;
+  while let Some(record) = records.next().await {
+    position += 1;
+    if position <= skipped {
+      continue;
+    }
+
+    let reason = match record.transaction {
+      Ok(transaction) => payments_engine
+        .process(transaction)
+        .await
+        .err()
+        .map(RejectionReason::Engine),
+      Err(err) => Some(RejectionReason::Read(err)),
+    };
+    if let (Some(reason), Some(sink)) = (reason, error_sink.as_mut()) {
+      sink
+        .reject(Rejection {
+          line: record.line,
+          record: record.raw,
+          reason,
+        })
+        .await?;
+    }
+    progress.record(position).await?;
+  }
+  drop(records);
+
+  accounts_report_writer
+    .write_accounts_report(payments_engine.accounts_report_stream())
+    .await
+}
+
+#[cfg(test)]
+mod test {
+
+  use indoc::indoc;
+
+  use super::*;
+  use crate::io::{CsvAccountsReportWriter, CsvTransactionsReader};
+  use crate::payments::InMemoryPaymentsEngine;
+
+  #[tokio::test]
+  async fn run_skips_processed_records() {
+    let input = std::env::temp_dir().join(format!(
+      "toy-payments-engine-resumable-{}.csv",
+      std::process::id()
+    ));
+    tokio::fs::write(ProgressFile::path(&input), "2\n")
+      .await
+      .unwrap();
+    let transactions = indoc! { "
+      type,client,tx,amount
+      deposit,1,1,100
+      deposit,2,2,50
+      withdrawal,1,3,10
+      deposit,2,4,5
+    " };
+    let mut output = Vec::<u8>::new();
+
+    run(
+      CsvTransactionsReader::new(transactions.as_bytes()),
+      InMemoryPaymentsEngine::new(),
+      CsvAccountsReportWriter::new(&mut output),
+      ProgressFile::open(&input).await.unwrap(),
+      None,
+    )
+    .await
+    .unwrap();
+
+    // the first two records were processed by a previous run, whose state is not recovered here
+    assert_eq!(
+      String::from_utf8(output).unwrap(),
+      "client,available,held,total,locked\n2,5,0,5,false\n"
+    );
+    let progress = ProgressFile::open(&input).await.unwrap();
+    assert_eq!(progress.processed(), 4);
+    drop(progress);
+    tokio::fs::remove_file(ProgressFile::path(&input))
+      .await
+      .unwrap();
+  }
+}
@@ -1,8 +1,12 @@
 use anyhow::Result;
+use serde::Serialize;
 use tokio_stream::StreamExt;
 
-use crate::io::{AccountsReportWriter, TransactionsReader};
-use crate::payments::PaymentsEngine;
+use crate::io::{
+  AccountsReportWriter, DuplicateDetector, DuplicatesSink, ErrorSink, Rejection, RejectionReason,
+  TransactionsReader,
+};
+use crate::payments::{Metrics, PaymentsEngine};
 
 /// This is a simple processor of payments that
 /// - reads transactions from a [`TransactionsReader`]
@@ -15,16 +19,19 @@ use crate::payments::PaymentsEngine;
 /// - errors from the transactions reader will be skipped
 /// - errors from the payments engine will be skipped
 ///
+/// Those errors can be written into an [`ErrorSink`] instead with [`run_with_errors`],
+/// and the duplicated transactions into a [`DuplicatesSink`] with [`run_with_duplicates`].
+///
 /// In the reality, those errors should be instrumented as metrics and/or logs that can be tracked and alerted on,
-/// and the errors happening in the payments engine could be reported as events to a fraud detection system.
+/// and the errors happening in the payments engine can be reported as events to a fraud detection system
+/// by wrapping the engine into a [`ListeningPaymentsEngine`](crate::payments::ListeningPaymentsEngine).
 ///
 /// Following similar ideas, and thanks the way that the architecture have been designed,
 /// it shouldn't be too difficult to write other kind of processors like:
 /// - An HTTP streaming processor, where transactions are sent as a request and accounts reports returned as an stream
+///   (see `http`, available with the `http` feature).
 /// - A partitioned multi-threaded processor, where multiple threads, everyone with its own instance of a payments engine,
-///   process transactions in parallel. The key idea would be to partition the transactions by a uniform hash of the client_id,
-///   and then send them to the corresponding thread using a channel. The multi-threaded logic could be implemented using
-///   the [`PaymentsEngine`] trait so this simple processor could still be used.
+///   process transactions in parallel (see [`partitioned`](super::partitioned)).
 ///
 pub async fn run<R, P, W>(
   mut transactions_reader: R,
@@ -35,32 +42,174 @@ where
   R: TransactionsReader,
   P: PaymentsEngine,
   W: AccountsReportWriter,
+{
+  process_transactions(&mut transactions_reader, &mut payments_engine, None).await;
+
+  accounts_report_writer
+    .write_accounts_report(payments_engine.accounts_report_stream())
+    .await
+}
+
+/// Same as [`run`] but writing every rejected record into the [`ErrorSink`], instead of skipping it silently.
+/// The processing continues after a rejection, but failing to write it stops the processing.
+pub async fn run_with_errors<R, P, W, E>(
+  mut transactions_reader: R,
+  mut payments_engine: P,
+  mut accounts_report_writer: W,
+  mut error_sink: E,
+) -> Result<()>
+where
+  R: TransactionsReader,
+  P: PaymentsEngine,
+  W: AccountsReportWriter,
+  E: ErrorSink,
+{
+  process_records(
+    &mut transactions_reader,
+    &mut payments_engine,
+    Some(&mut error_sink),
+    None,
+  )
+  .await?;
+
+  accounts_report_writer
+    .write_accounts_report(payments_engine.accounts_report_stream())
+    .await
+}
+
+/// Same as [`run`] but writing every duplicated transaction into the [`DuplicatesSink`] as soon as it is detected,
+/// along with the line of the transaction accepted first. The rejected records are written into the [`ErrorSink`], when given.
+pub async fn run_with_duplicates<R, P, W, D>(
+  mut transactions_reader: R,
+  mut payments_engine: P,
+  mut accounts_report_writer: W,
+  mut duplicates_sink: D,
+  error_sink: Option<&mut dyn ErrorSink>,
+) -> Result<()>
+where
+  R: TransactionsReader,
+  P: PaymentsEngine,
+  W: AccountsReportWriter,
+  D: DuplicatesSink,
+{
+  process_records(
+    &mut transactions_reader,
+    &mut payments_engine,
+    error_sink,
+    Some(&mut duplicates_sink),
+  )
+  .await?;
+
+  accounts_report_writer
+    .write_accounts_report(payments_engine.accounts_report_stream())
+    .await
+}
+
+/// Read all the records and process their transactions, writing the rejections and duplicates into the sinks given.
+async fn process_records<R, P>(
+  transactions_reader: &mut R,
+  payments_engine: &mut P,
+  mut error_sink: Option<&mut dyn ErrorSink>,
+  mut duplicates_sink: Option<&mut dyn DuplicatesSink>,
+) -> Result<()>
+where
+  R: TransactionsReader,
+  P: PaymentsEngine,
+{
+  let mut duplicates = DuplicateDetector::new();
+  let mut records = transactions_reader.read_records();
+
+  while let Some(record) = records.next().await {
+    let reason = match record.transaction {
+      Ok(transaction) => {
+        let observed = duplicates_sink.as_ref().map(|_| transaction.clone());
+        let result = payments_engine.process(transaction).await;
+        if let (Some(sink), Some(transaction)) = (duplicates_sink.as_mut(), observed) {
+          if let Some(duplicate) = duplicates.observe(record.line, transaction, &result) {
+            sink.duplicate(duplicate).await?;
+          }
+        }
+        match result {
+          Ok(()) => continue,
+          Err(err) => RejectionReason::Engine(err),
+        }
+      }
+      Err(err) => RejectionReason::Read(err),
+    };
+
+    if let Some(sink) = error_sink.as_mut() {
+      sink
+        .reject(Rejection {
+          line: record.line,
+          record: record.raw,
+          reason,
+        })
+        .await?;
+    }
+  }
+
+  Ok(())
+}
+
+/// The number of records of an input by how they ended, like the summary returned by the [`http`](super::http) processor.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+pub struct ProcessingSummary {
+  /// The transactions accepted by the payments engine.
+  pub accepted: u64,
+  /// The transactions rejected by the payments engine.
+  pub rejected: u64,
+  /// The records that could not be read as transactions.
+  pub unreadable: u64,
+}
+
+/// Read all the transactions and process them, skipping any error from the reader or the payments engine.
+/// The records that can not be read are counted into the [`Metrics`], when given.
+pub(crate) async fn process_transactions<R, P>(
+  transactions_reader: &mut R,
+  payments_engine: &mut P,
+  metrics: Option<&dyn Metrics>,
+) -> ProcessingSummary
+where
+  R: TransactionsReader,
+  P: PaymentsEngine,
 {
   let mut transactions = transactions_reader.read_transactions();
+  let mut summary = ProcessingSummary::default();
 
   while let Some(maybe_transaction) = transactions.next().await {
-    if let Ok(transaction) = maybe_transaction {
-      payments_engine.process(transaction).await.ok();
+    match maybe_transaction {
+      Ok(transaction) => match payments_engine.process(transaction).await {
+        Ok(()) => summary.accepted += 1,
+        Err(_) => summary.rejected += 1,
+      },
+      Err(_) => {
+        summary.unreadable += 1;
+        if let Some(metrics) = metrics {
+          metrics.record_unreadable();
+        }
+      }
     }
   }
-
-  accounts_report_writer
-    .write_accounts_report(payments_engine.accounts_report())
-    .await
+  summary
 }
 
 #[cfg(test)]
 mod test {
 
   use async_trait::async_trait;
+  use indoc::indoc;
   use mock_it::Mock;
   use rust_decimal_macros::dec;
   use tokio_stream::Stream;
 
   use super::*;
+  use crate::io::{
+    CsvAccountsReportWriter, CsvDuplicatesSink, CsvErrorSink, CsvTransactionsReader,
+  };
   use crate::payments::{
-    AccountReport, AccountsReportIter, EngineResult, PaymentsEngine, PaymentsEngineError,
-    Transaction,
+    AccountFilter, AccountReport, AccountsReportIter, ClientId, EngineConfig, EngineResult,
+    InMemoryPaymentsEngine, PaymentsEngine, PaymentsEngineError, ShardedPaymentsEngine,
+    Transaction, TransactionId, TransactionInfo, TransactionsReportIter,
   };
 
   #[tokio::test]
@@ -69,12 +218,16 @@ mod test {
       client_id: 1,
       transaction_id: 102,
       amount: dec!(-10),
+      timestamp: None,
+      sub_account: 0,
     };
 
     let transaction2 = Transaction::Deposit {
       client_id: 1,
       transaction_id: 101,
       amount: dec!(10),
+      timestamp: None,
+      sub_account: 0,
     };
 
     let transactions_reader = create_transaction_reader_mock(vec![
@@ -100,6 +253,115 @@ mod test {
     assert!(result.is_ok())
   }
 
+  #[tokio::test]
+  async fn run_with_errors_writes_rejections() {
+    let input = indoc! { "
+      type,       client,   tx,  amount
+      deposit,         1,  101,     100
+      withdrawal,      1,  102,     200
+      deposit,         1,  103,     abc
+    " };
+
+    let mut report = Vec::<u8>::new();
+    let mut errors = Vec::<u8>::new();
+
+    let result = run_with_errors(
+      CsvTransactionsReader::new(input.as_bytes()),
+      InMemoryPaymentsEngine::new(),
+      CsvAccountsReportWriter::new(&mut report),
+      CsvErrorSink::new(&mut errors),
+    )
+    .await;
+
+    assert!(result.is_ok());
+    assert_eq!(
+      String::from_utf8_lossy(&report),
+      "client,available,held,total,locked\n1,100,0,100,false\n"
+    );
+    let errors = String::from_utf8_lossy(&errors);
+    let lines: Vec<&str> = errors.lines().collect();
+    assert_eq!(lines.len(), 3);
+    assert_eq!(lines[0], "line,record,stage,error");
+    assert_eq!(
+      lines[1],
+      "3,\"withdrawal,      1,  102,     200\",engine,Not enough available funds"
+    );
+    assert!(lines[2].starts_with("4,\"deposit,         1,  103,     abc\",read,"));
+  }
+
+  #[tokio::test]
+  async fn run_with_duplicates_writes_duplicates() {
+    let input = indoc! { "
+      type,       client,   tx,  amount
+      deposit,         1,  101,     100
+      deposit,         1,  101,     100
+      withdrawal,      1,  102,      10
+      deposit,         1,  102,      20
+    " };
+
+    let mut report = Vec::<u8>::new();
+    let mut duplicates = Vec::<u8>::new();
+
+    let result = run_with_duplicates(
+      CsvTransactionsReader::new(input.as_bytes()),
+      InMemoryPaymentsEngine::new(),
+      CsvAccountsReportWriter::new(&mut report),
+      CsvDuplicatesSink::new(&mut duplicates),
+      None,
+    )
+    .await;
+
+    assert!(result.is_ok());
+    assert_eq!(
+      String::from_utf8_lossy(&report),
+      "client,available,held,total,locked\n1,90,0,90,false\n"
+    );
+    assert_eq!(
+      String::from_utf8_lossy(&duplicates),
+      indoc! { "
+        client,tx,first_line,duplicate_line,matching
+        1,101,2,3,true
+        1,102,4,5,false
+      " }
+    );
+  }
+
+  #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+  async fn run_with_sharded_engine() {
+    let input = indoc! { "
+      type,       client,   tx,  amount
+      deposit,         1,  101,     100
+      deposit,         2,  102,      50
+      deposit,         3,  103,      20
+      withdrawal,      2,  104,      10
+      dispute,         3,  103,
+    " };
+
+    let config = EngineConfig {
+      deterministic: true,
+      ..EngineConfig::default()
+    };
+    let mut report = Vec::<u8>::new();
+
+    let result = run(
+      CsvTransactionsReader::new(input.as_bytes()),
+      ShardedPaymentsEngine::new(2, config),
+      CsvAccountsReportWriter::new(&mut report),
+    )
+    .await;
+
+    assert!(result.is_ok());
+    assert_eq!(
+      String::from_utf8_lossy(&report),
+      indoc! { "
+        client,available,held,total,locked
+        1,100,0,100,false
+        2,40,0,40,false
+        3,0,20,20,false
+      " }
+    );
+  }
+
   mockall::mock! {
     TestTransactionReader {}
     impl TransactionsReader for TestTransactionReader {
@@ -131,7 +393,12 @@ mod test {
     #[async_trait]
     impl PaymentsEngine for TestPaymentsEngine {
       async fn process(&mut self, transaction: Transaction) -> EngineResult<()>;
+      fn validate(&self, transaction: &Transaction) -> EngineResult<()>;
       fn accounts_report(&self) -> AccountsReportIter<'_>;
+      fn accounts_matching(&self, filter: AccountFilter) -> AccountsReportIter<'_>;
+      fn account(&self, client_id: ClientId) -> Option<AccountReport>;
+      fn transaction(&self, client_id: ClientId, transaction_id: TransactionId) -> EngineResult<Option<TransactionInfo>>;
+      fn transactions_report<'a>(&'a self) -> EngineResult<TransactionsReportIter<'a>>;
     }
   }
 
@@ -167,15 +434,16 @@ mod test {
     }
   }
 
-  #[async_trait(?Send)]
+  #[async_trait]
   impl AccountsReportWriter for MockTestAccountsReportWriter {
-    async fn write_accounts_report<'a, T>(&'a mut self, report: T) -> anyhow::Result<()>
+    async fn write_accounts_report<'a, S>(&'a mut self, report: S) -> anyhow::Result<()>
     where
-      T: Iterator<Item = AccountReport> + 'a,
+      S: Stream<Item = Result<AccountReport, PaymentsEngineError>> + Send + 'a,
     {
+      let report = report.collect::<Result<_, _>>().await?;
       self
         .write_accounts_report
-        .called(report.collect())
+        .called(report)
         .map_err(|err| anyhow::anyhow!(err))
     }
   }
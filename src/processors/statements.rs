@@ -0,0 +1,87 @@
+use anyhow::Result;
+use tokio_stream::StreamExt;
+
+use crate::io::{StatementsWriter, TransactionsReader};
+use crate::payments::{PaymentsEngine, Statements};
+
+/// This processor writes the statement of every client, with the balances of the account after each of its transactions. It
+/// - reads and processes transactions the same way than the [`simple`](super::simple) processor
+/// - records every accepted transaction into the [`Statements`] of the clients involved
+/// - writes the statements using a [`StatementsWriter`], client by client in ascending order of client ID
+///
+/// The rejected transactions don't change the balances, so they are not part of the statements.
+///
+pub async fn run<R, P, W>(
+  mut transactions_reader: R,
+  mut payments_engine: P,
+  mut statements_writer: W,
+) -> Result<()>
+where
+  R: TransactionsReader,
+  P: PaymentsEngine,
+  W: StatementsWriter,
+{
+  let mut statements = Statements::new();
+  let mut transactions = transactions_reader.read_transactions();
+
+  while let Some(maybe_transaction) = transactions.next().await {
+    if let Ok(transaction) = maybe_transaction {
+      if payments_engine.process(transaction.clone()).await.is_ok() {
+        statements.record(&payments_engine, &transaction)?;
+      }
+    }
+  }
+  drop(transactions);
+
+  statements_writer
+    .write_statements(statements.into_lines())
+    .await
+}
+
+#[cfg(test)]
+mod test {
+
+  use indoc::indoc;
+
+  use super::*;
+  use crate::io::{CsvStatementsWriter, CsvTransactionsReader};
+  use crate::payments::InMemoryPaymentsEngine;
+
+  #[tokio::test]
+  async fn run_successfully() {
+    let transactions = indoc! { "
+      type,       client,   tx,  amount
+      deposit,         2,  201,      30
+      deposit,         1,  101,     100
+      deposit,         1,  102,      20
+      withdrawal,      1,  103,     500
+      withdrawal,      1,  104,      40
+      dispute,         1,  102,
+      chargeback,      1,  102,
+    " }
+    .as_bytes();
+
+    let mut buffer = Vec::<u8>::with_capacity(1024);
+
+    let result = run(
+      CsvTransactionsReader::new(transactions),
+      InMemoryPaymentsEngine::new(),
+      CsvStatementsWriter::new(&mut buffer),
+    )
+    .await;
+
+    assert!(result.is_ok());
+    assert_eq!(
+      String::from_utf8_lossy(buffer.as_slice()),
+      indoc! {"
+        client,tx,type,amount,available,held,total
+        1,101,deposit,100,100,0,100
+        1,102,deposit,20,120,0,120
+        1,104,withdrawal,40,80,0,80
+        1,102,dispute,20,60,20,80
+        1,102,chargeback,20,60,0,60
+        2,201,deposit,30,30,0,30
+      "}
+    )
+  }
+}
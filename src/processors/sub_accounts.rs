@@ -0,0 +1,82 @@
+use anyhow::Result;
+
+use super::simple::process_transactions;
+use crate::io::{SubAccountsWriter, TransactionsReader};
+use crate::payments::{AccountFilter, PaymentsEngine};
+
+/// This processor reports the funds of the sub-accounts of the clients, like the wallets of a product. It
+/// - reads and processes transactions the same way than the [`simple`](super::simple) processor
+/// - writes every sub-account of the accounts selected by the `filter`, with their funds and the lock of the client,
+///   using a [`SubAccountsWriter`]
+///
+pub async fn run<R, P, W>(
+  mut transactions_reader: R,
+  mut payments_engine: P,
+  filter: AccountFilter,
+  mut sub_accounts_writer: W,
+) -> Result<()>
+where
+  R: TransactionsReader,
+  P: PaymentsEngine,
+  W: SubAccountsWriter,
+{
+  process_transactions(&mut transactions_reader, &mut payments_engine, None).await;
+
+  sub_accounts_writer
+    .write_sub_accounts(payments_engine.accounts_matching(filter))
+    .await
+}
+
+#[cfg(test)]
+mod test {
+
+  use indoc::indoc;
+
+  use super::*;
+  use crate::io::{CsvSubAccountsWriter, CsvTransactionsReader};
+  use crate::payments::{EngineConfig, InMemoryPaymentsEngine};
+
+  #[tokio::test]
+  async fn run_successfully() {
+    let transactions = indoc! { "
+      type,       client,   tx,  amount,  to,  timestamp,  sub
+      deposit,         1,  101,     100,    ,           ,
+      deposit,         1,  102,      20,    ,           ,    1
+      withdrawal,      1,  103,       5,    ,           ,    1
+      deposit,         1,  104,      30,    ,           ,    2
+      dispute,         1,  104,
+      deposit,         2,  201,      40
+      deposit,         3,  301,      50,    ,           ,    1
+      dispute,         3,  301,
+      chargeback,      3,  301,
+    " }
+    .as_bytes();
+
+    let mut buffer = Vec::<u8>::with_capacity(1024);
+
+    let result = run(
+      CsvTransactionsReader::new(transactions),
+      InMemoryPaymentsEngine::with_config(EngineConfig {
+        deterministic: true,
+        ..EngineConfig::default()
+      }),
+      AccountFilter::default(),
+      CsvSubAccountsWriter::new(&mut buffer),
+    )
+    .await;
+
+    assert!(result.is_ok());
+    assert_eq!(
+      String::from_utf8_lossy(buffer.as_slice()),
+      indoc! {"
+        account,available,held,total,locked
+        1:0,100,0,100,false
+        1:1,15,0,15,false
+        1:2,0,30,30,false
+        2:0,40,0,40,false
+        3:0,0,0,0,true
+        3:1,0,0,0,true
+      "}
+    )
+  }
+}
@@ -0,0 +1,235 @@
+//! Detection of the resources available to the process, taking into account the limits of the container.
+//!
+//! The number of CPUs of the host is not a good default when running in a container limited by cgroups,
+//! as the runtime would start more worker threads than the CPU quota allows, and they would be throttled.
+//! Both cgroups v2 and v1 are supported. Any limit that can't be read is considered unlimited.
+//! The memory used is read from the same place, to admit new work only while there is memory left for it.
+//! The sizes of what is kept in memory by default, like the channels between the tasks, are derived from the limits too.
+
+use std::convert::TryFrom;
+use std::fmt;
+
+const CGROUP_V2_CPU_MAX: &str = "/sys/fs/cgroup/cpu.max";
+const CGROUP_V2_MEMORY_MAX: &str = "/sys/fs/cgroup/memory.max";
+const CGROUP_V1_CPU_QUOTA: &str = "/sys/fs/cgroup/cpu/cpu.cfs_quota_us";
+const CGROUP_V1_CPU_PERIOD: &str = "/sys/fs/cgroup/cpu/cpu.cfs_period_us";
+const CGROUP_V1_MEMORY_LIMIT: &str = "/sys/fs/cgroup/memory/memory.limit_in_bytes";
+#[cfg(feature = "http")]
+const CGROUP_V2_MEMORY_CURRENT: &str = "/sys/fs/cgroup/memory.current";
+#[cfg(feature = "http")]
+const CGROUP_V1_MEMORY_USAGE: &str = "/sys/fs/cgroup/memory/memory.usage_in_bytes";
+#[cfg(feature = "http")]
+const PROC_STATUS: &str = "/proc/self/status";
+
+/// cgroups v1 reports an unlimited memory as a huge number close to the maximum of an `i64`.
+const CGROUP_V1_MEMORY_UNLIMITED: u64 = 1 << 60;
+
+/// Approximate size in bytes of a transaction kept in memory, either queued with its raw record or recorded by an account.
+const TRANSACTION_SIZE: u64 = 256;
+/// Maximum number of items waiting in every channel, which is also the one without a memory limit.
+const MAX_CHANNEL_CAPACITY: usize = 1024;
+/// Maximum number of settled transactions kept in memory by default, which is also the one without a memory limit.
+#[cfg(feature = "kv")]
+const MAX_TRANSACTIONS_MEMORY: usize = 1_000_000;
+
+/// The resources available to the process.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Resources {
+  /// Number of CPUs available, which is used as the number of worker threads of the runtime.
+  pub cpus: usize,
+  /// Limit of memory in bytes, or `None` when there is no limit.
+  pub memory: Option<u64>,
+}
+
+impl Resources {
+  pub fn detect() -> Self {
+    let host_cpus = num_cpus::get();
+    let cpu_limit = read_file(CGROUP_V2_CPU_MAX)
+      .and_then(|content| parse_cpu_max(&content))
+      .or_else(|| {
+        let quota = read_file(CGROUP_V1_CPU_QUOTA)?;
+        let period = read_file(CGROUP_V1_CPU_PERIOD)?;
+        parse_cfs_quota(&quota, &period)
+      });
+
+    let memory = read_file(CGROUP_V2_MEMORY_MAX)
+      .and_then(|content| parse_memory_max(&content))
+      .or_else(|| {
+        read_file(CGROUP_V1_MEMORY_LIMIT).and_then(|content| parse_memory_limit(&content))
+      });
+
+    Self {
+      cpus: limit_cpus(host_cpus, cpu_limit),
+      memory,
+    }
+  }
+
+  /// Number of partitions to process the transactions in parallel with `PARTITIONS=auto`, which is one per CPU.
+  pub fn partitions(&self) -> usize {
+    self.cpus
+  }
+
+  /// Maximum number of items waiting in every channel between the tasks, like the transactions queued for a partition
+  /// or the events waiting to be written, so the channels of all the CPUs take up to 1/64 of the memory limit.
+  pub fn channel_capacity(&self) -> usize {
+    self
+      .transactions_in(64 * self.cpus as u64)
+      .clamp(1, MAX_CHANNEL_CAPACITY)
+  }
+
+  /// Number of settled transactions kept in memory when they are spilled, unless `TRANSACTIONS_MEMORY` is given,
+  /// so they take up to a quarter of the memory limit.
+  #[cfg(feature = "kv")]
+  pub fn transactions_memory(&self) -> usize {
+    self.transactions_in(4).clamp(1, MAX_TRANSACTIONS_MEMORY)
+  }
+
+  /// Number of transactions that fit into the fraction of the memory limit, which is unlimited without a limit.
+  fn transactions_in(&self, fraction: u64) -> usize {
+    self.memory.map_or(usize::MAX, |memory| {
+      usize::try_from(memory / fraction / TRANSACTION_SIZE).unwrap_or(usize::MAX)
+    })
+  }
+}
+
+/// The memory used in bytes, by the container when there are cgroups, or by the process otherwise.
+/// It is `None` when it can't be read.
+#[cfg(feature = "http")]
+pub fn memory_usage() -> Option<u64> {
+  read_file(CGROUP_V2_MEMORY_CURRENT)
+    .or_else(|| read_file(CGROUP_V1_MEMORY_USAGE))
+    .and_then(|content| content.trim().parse::<u64>().ok())
+    .or_else(|| read_file(PROC_STATUS).and_then(|content| parse_vm_rss(&content)))
+}
+
+impl fmt::Display for Resources {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "Using {} worker threads", self.cpus)?;
+    match self.memory {
+      Some(memory) => write!(f, " with a memory limit of {} MiB", memory / (1024 * 1024)),
+      None => write!(f, " without a memory limit"),
+    }
+  }
+}
+
+fn read_file(path: &str) -> Option<String> {
+  std::fs::read_to_string(path).ok()
+}
+
+/// The number of CPUs is the CPU quota rounded up, but never more than the CPUs of the host nor less than one.
+fn limit_cpus(host_cpus: usize, cpu_limit: Option<f64>) -> usize {
+  let cpus = cpu_limit.map_or(host_cpus, |limit| (limit.ceil() as usize).min(host_cpus));
+  cpus.max(1)
+}
+
+/// Parse the `cpu.max` of cgroups v2, with the format `<quota> <period>`, where the quota can be `max`.
+fn parse_cpu_max(content: &str) -> Option<f64> {
+  let mut fields = content.split_whitespace();
+  let quota = fields.next()?.parse::<f64>().ok()?;
+  let period = fields.next()?.parse::<f64>().ok()?;
+  Some(quota / period).filter(|limit| *limit > 0.0)
+}
+
+/// Parse the CFS quota and period of cgroups v1, where a negative quota means no limit.
+fn parse_cfs_quota(quota: &str, period: &str) -> Option<f64> {
+  let quota = quota.trim().parse::<f64>().ok()?;
+  let period = period.trim().parse::<f64>().ok()?;
+  Some(quota / period).filter(|limit| *limit > 0.0)
+}
+
+/// Parse the `memory.max` of cgroups v2, which is either a number of bytes or `max`.
+fn parse_memory_max(content: &str) -> Option<u64> {
+  content.trim().parse::<u64>().ok()
+}
+
+/// Parse the `memory.limit_in_bytes` of cgroups v1.
+fn parse_memory_limit(content: &str) -> Option<u64> {
+  content
+    .trim()
+    .parse::<u64>()
+    .ok()
+    .filter(|limit| *limit < CGROUP_V1_MEMORY_UNLIMITED)
+}
+
+/// Parse the resident memory of the process from its `/proc/self/status`, where it is given in kB.
+#[cfg(feature = "http")]
+fn parse_vm_rss(content: &str) -> Option<u64> {
+  let line = content.lines().find(|line| line.starts_with("VmRSS:"))?;
+  let kilobytes = line.split_whitespace().nth(1)?.parse::<u64>().ok()?;
+  Some(kilobytes * 1024)
+}
+
+#[cfg(test)]
+mod tests {
+
+  use super::*;
+
+  #[test]
+  fn parse_cgroup_limits() {
+    assert_eq!(parse_cpu_max("150000 100000\n"), Some(1.5));
+    assert_eq!(parse_cpu_max("max 100000\n"), None);
+    assert_eq!(parse_cfs_quota("50000\n", "100000\n"), Some(0.5));
+    assert_eq!(parse_cfs_quota("-1\n", "100000\n"), None);
+
+    assert_eq!(parse_memory_max("536870912\n"), Some(536870912));
+    assert_eq!(parse_memory_max("max\n"), None);
+    assert_eq!(parse_memory_limit("536870912\n"), Some(536870912));
+    assert_eq!(parse_memory_limit("9223372036854771712\n"), None);
+  }
+
+  #[cfg(feature = "http")]
+  #[test]
+  fn parse_process_memory() {
+    assert_eq!(
+      parse_vm_rss("Name:\ttoy-payments-engine\nVmRSS:\t   2048 kB\nThreads:\t4\n"),
+      Some(2048 * 1024)
+    );
+    assert_eq!(parse_vm_rss("Name:\tkthreadd\n"), None);
+  }
+
+  #[test]
+  fn derive_the_sizes_from_the_limits() {
+    let unlimited = Resources {
+      cpus: 4,
+      memory: None,
+    };
+    assert_eq!(unlimited.partitions(), 4);
+    assert_eq!(unlimited.channel_capacity(), MAX_CHANNEL_CAPACITY);
+
+    let limited = Resources {
+      cpus: 4,
+      memory: Some(64 * 1024 * 1024),
+    };
+    assert_eq!(limited.channel_capacity(), 1024);
+
+    let tiny = Resources {
+      cpus: 4,
+      memory: Some(1024 * 1024),
+    };
+    assert_eq!(tiny.channel_capacity(), 16);
+  }
+
+  #[cfg(feature = "kv")]
+  #[test]
+  fn derive_the_transactions_memory_from_the_limits() {
+    let unlimited = Resources {
+      cpus: 4,
+      memory: None,
+    };
+    assert_eq!(unlimited.transactions_memory(), MAX_TRANSACTIONS_MEMORY);
+
+    let limited = Resources {
+      cpus: 4,
+      memory: Some(64 * 1024 * 1024),
+    };
+    assert_eq!(limited.transactions_memory(), 65536);
+  }
+
+  #[test]
+  fn limit_cpus_to_quota() {
+    assert_eq!(limit_cpus(8, None), 8);
+    assert_eq!(limit_cpus(8, Some(1.5)), 2);
+    assert_eq!(limit_cpus(8, Some(0.5)), 1);
+    assert_eq!(limit_cpus(2, Some(4.0)), 2);
+  }
+}
@@ -0,0 +1,212 @@
+//! Properties of the payments engine over random sequences of transactions.
+//!
+//! Every sequence is generated from a seed and processed by an [`InMemoryPaymentsEngine`] wrapped in a [`DebugPaymentsEngine`],
+//! which panics as soon as a transaction breaks any of the invariants of the engine (see [`InvariantCheckingEngine`]).
+//! The sequences are biased towards the transactions that refer to earlier ones (disputes, resolves, chargebacks, captures, ...),
+//! so most of them exercise the lifecycle of the transactions instead of being rejected.
+//! Some of them refer to the first chargeback fee of the client instead, which is only disputable with some policies.
+//!
+//! The same sequences are also processed with a transaction store, which must not change the outcome of any transaction.
+//!
+//! A failure can be reproduced from the seed and the configuration in its message.
+//!
+//! [`InvariantCheckingEngine`]: toy_payments_engine::payments::InvariantCheckingEngine
+
+use rust_decimal::Decimal;
+use toy_payments_engine::payments::{
+  ChargebackFee, ClientId, DebugPaymentsEngine, EngineConfig, InMemoryPaymentsEngine,
+  InMemoryTransactionStore, LockedAccountDisputePolicy, PaymentsEngine, SyntheticDisputePolicy,
+  Transaction, TransactionId, UnlockHeldFundsPolicy,
+};
+
+const SEQUENCES: u64 = 200;
+
+/// A small deterministic generator (splitmix64), so the sequences can be reproduced from their seed.
+struct Generator(u64);
+
+impl Generator {
+  fn next(&mut self) -> u64 {
+    self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    let mut z = self.0;
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+  }
+
+  /// A number in `[low, high]`.
+  fn between(&mut self, low: u64, high: u64) -> u64 {
+    low + self.next() % (high - low + 1)
+  }
+
+  fn amount(&mut self) -> Decimal {
+    Decimal::new(self.between(1, 1_000_000) as i64, 4)
+  }
+}
+
+/// A random sequence of transactions between a few clients.
+fn transactions(seed: u64) -> Vec<Transaction> {
+  let mut generator = Generator(seed);
+  let clients = generator.between(1, 5);
+  let length = generator.between(1, 200);
+
+  let mut recorded = Vec::<(ClientId, TransactionId)>::new();
+  let mut transactions = Vec::with_capacity(length as usize);
+  for transaction_id in 1..=length as TransactionId {
+    let client_id = generator.between(1, clients) as ClientId;
+    let transaction = match (generator.between(0, 11), recorded.is_empty()) {
+      (0..=2, _) | (_, true) => {
+        recorded.push((client_id, transaction_id));
+        Transaction::Deposit {
+          client_id,
+          transaction_id,
+          amount: generator.amount(),
+          timestamp: None,
+          sub_account: 0,
+        }
+      }
+      (3..=4, _) => {
+        recorded.push((client_id, transaction_id));
+        Transaction::Withdrawal {
+          client_id,
+          transaction_id,
+          amount: generator.amount(),
+          timestamp: None,
+          sub_account: 0,
+        }
+      }
+      (5, _) => Transaction::Transfer {
+        from_client: client_id,
+        to_client: generator.between(1, clients) as ClientId,
+        transaction_id,
+        amount: generator.amount(),
+        timestamp: None,
+      },
+      (6, _) => {
+        recorded.push((client_id, transaction_id));
+        Transaction::Authorize {
+          client_id,
+          transaction_id,
+          amount: generator.amount(),
+          timestamp: None,
+          sub_account: 0,
+        }
+      }
+      (7, _) => Transaction::Unlock { client_id },
+      (kind, _) => {
+        // the rest refer to a recorded transaction, which might not be of the right kind,
+        // or to the synthetic one of the first chargeback fee of the client, which might not exist
+        let (client_id, transaction_id) = match generator.between(0, 9) {
+          0 => (client_id, TransactionId::MAX),
+          _ => recorded[generator.next() as usize % recorded.len()],
+        };
+        match kind {
+          8 => Transaction::Dispute {
+            client_id,
+            transaction_id,
+            timestamp: None,
+          },
+          9 => Transaction::Resolve {
+            client_id,
+            transaction_id,
+            timestamp: None,
+          },
+          10 => Transaction::Chargeback {
+            client_id,
+            transaction_id,
+            timestamp: None,
+          },
+          _ if generator.between(0, 1) == 0 => Transaction::Capture {
+            client_id,
+            transaction_id,
+            timestamp: None,
+          },
+          _ => Transaction::Void {
+            client_id,
+            transaction_id,
+            timestamp: None,
+          },
+        }
+      }
+    };
+    transactions.push(transaction);
+  }
+  transactions
+}
+
+/// The configurations whose policies change the outcome of the transactions.
+fn configs() -> Vec<EngineConfig> {
+  vec![
+    EngineConfig::default(),
+    EngineConfig {
+      locked_account_dispute_policy: LockedAccountDisputePolicy::Allow,
+      unlock_held_funds_policy: UnlockHeldFundsPolicy::Release,
+      ..EngineConfig::default()
+    },
+    EngineConfig {
+      chargeback_fee: Some(ChargebackFee {
+        amount: Decimal::new(15, 0),
+        allow_negative_available: true,
+      }),
+      max_open_disputes: Some(2),
+      ..EngineConfig::default()
+    },
+    EngineConfig {
+      chargeback_fee: Some(ChargebackFee {
+        amount: Decimal::new(15, 0),
+        allow_negative_available: false,
+      }),
+      locked_account_dispute_policy: LockedAccountDisputePolicy::Allow,
+      synthetic_dispute_policy: SyntheticDisputePolicy::Allow,
+      ..EngineConfig::default()
+    },
+  ]
+}
+
+#[tokio::test]
+async fn random_sequences_keep_the_invariants() {
+  for config in configs() {
+    for seed in 0..SEQUENCES {
+      let sequence = transactions(seed);
+      let engine_config = config.clone();
+      // the engine panics on the first violated invariant, which is reported with the seed
+      let result = tokio::spawn(async move {
+        let mut engine = DebugPaymentsEngine::with_config(
+          InMemoryPaymentsEngine::with_config(engine_config.clone()),
+          &engine_config,
+        );
+        for transaction in sequence {
+          engine.process(transaction).await.ok();
+        }
+      })
+      .await;
+
+      assert!(
+        result.is_ok(),
+        "Sequence {} violated the invariants with {:?}",
+        seed,
+        config
+      );
+    }
+  }
+}
+
+#[tokio::test]
+async fn random_sequences_with_transaction_store() {
+  for config in configs() {
+    for seed in 0..SEQUENCES {
+      let mut engine = InMemoryPaymentsEngine::with_config(config.clone())
+        .with_transaction_store(Box::new(InMemoryTransactionStore::new()));
+      let mut reference = InMemoryPaymentsEngine::with_config(config.clone());
+      for transaction in transactions(seed) {
+        assert_eq!(
+          engine.process(transaction.clone()).await,
+          reference.process(transaction).await,
+          "Sequence {} differs with a transaction store with {:?}",
+          seed,
+          config
+        );
+      }
+      assert_eq!(engine.snapshot(), reference.snapshot());
+    }
+  }
+}
@@ -0,0 +1,238 @@
+//! Chaos scenarios for the partitioned processor.
+//!
+//! Every scenario generates a random input and runs it through the partitioned processor with a random number of partitions
+//! and channel capacity, while the engines of the workers panic at random points.
+//! A failed run must not write any report, and it is restarted from the same input until it succeeds.
+//! The final report must always match the one of a single [`InMemoryPaymentsEngine`] processing the same input.
+//!
+//! The scenarios are generated from a seed, so a failure can be reproduced from the seed in its message.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use rust_decimal::Decimal;
+use toy_payments_engine::io::{CsvAccountsReportWriter, CsvTransactionsReader};
+use toy_payments_engine::payments::{
+  AccountFilter, AccountReport, AccountsReportIter, ClientId, InMemoryPaymentsEngine,
+  PaymentsEngine, PaymentsEngineError, Transaction, TransactionId, TransactionInfo,
+  TransactionsReportIter,
+};
+use toy_payments_engine::processors::{partitioned, simple};
+
+const SCENARIOS: u64 = 40;
+const MAX_ATTEMPTS: usize = 10;
+
+/// A small deterministic generator (splitmix64), so the scenarios can be reproduced from their seed.
+struct Generator(u64);
+
+impl Generator {
+  fn next(&mut self) -> u64 {
+    self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    let mut z = self.0;
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+  }
+
+  /// A number in `[low, high]`.
+  fn between(&mut self, low: u64, high: u64) -> u64 {
+    low + self.next() % (high - low + 1)
+  }
+}
+
+/// A scenario with its input as CSV, and the same input without the transfers that the processor rejects.
+struct Scenario {
+  partitions: usize,
+  capacity: usize,
+  input: String,
+  reference_input: String,
+  /// How many transactions are processed before a worker panics in every attempt, until it succeeds.
+  panics: Vec<usize>,
+}
+
+impl Scenario {
+  fn generate(seed: u64) -> Self {
+    let mut generator = Generator(seed);
+    let partitions = generator.between(1, 8) as usize;
+    let capacity = generator.between(1, 64) as usize;
+    let clients = generator.between(1, 20);
+    let length = generator.between(1, 300);
+
+    let header = "type,client,tx,amount,to\n";
+    let mut input = header.to_string();
+    let mut reference_input = header.to_string();
+    let mut transactions = Vec::<(u64, u64)>::new();
+    for tx in 1..=length {
+      let client = generator.between(1, clients);
+      let amount = Decimal::new(generator.between(1, 1_000_000) as i64, 4);
+      let kind = generator.between(0, 9);
+      let record = match (kind, transactions.is_empty()) {
+        (0..=3, _) | (_, true) => {
+          transactions.push((client, tx));
+          format!("deposit,{},{},{},\n", client, tx, amount)
+        }
+        (4..=5, _) => {
+          transactions.push((client, tx));
+          format!("withdrawal,{},{},{},\n", client, tx, amount)
+        }
+        (6, _) => {
+          let to = generator.between(1, clients);
+          format!("transfer,{},{},{},{}\n", client, tx, amount, to)
+        }
+        (9, _) if generator.between(0, 3) == 0 => format!("unlock,{},{},,\n", client, tx),
+        _ => {
+          // most of the disputes, resolves and chargebacks refer to existing transactions
+          let (client, tx) = transactions[generator.next() as usize % transactions.len()];
+          let kind = ["dispute", "resolve", "chargeback"][generator.between(0, 2) as usize];
+          format!("{},{},{},,\n", kind, client, tx)
+        }
+      };
+
+      input.push_str(&record);
+      if !is_discarded_transfer(&record, partitions) {
+        reference_input.push_str(&record);
+      }
+    }
+
+    let attempts = generator.between(1, 4) as usize;
+    let panics = (1..attempts)
+      .map(|_| generator.between(0, length) as usize)
+      .collect();
+
+    Scenario {
+      partitions,
+      capacity,
+      input,
+      reference_input,
+      panics,
+    }
+  }
+}
+
+/// Whether the record is a transfer between clients of different partitions, which the processor rejects.
+fn is_discarded_transfer(record: &str, partitions: usize) -> bool {
+  let fields: Vec<&str> = record.trim_end().split(',').collect();
+  let partition = |field: &str| field.parse::<usize>().unwrap() % partitions;
+  fields[0] == "transfer" && partition(fields[1]) != partition(fields[4])
+}
+
+/// An engine that panics once the shared countdown of processed transactions reaches zero,
+/// to inject the failure of a worker at any point of the processing.
+struct ChaosEngine {
+  inner: InMemoryPaymentsEngine,
+  countdown: Arc<AtomicUsize>,
+}
+
+#[async_trait]
+impl PaymentsEngine for ChaosEngine {
+  async fn process(&mut self, transaction: Transaction) -> Result<(), PaymentsEngineError> {
+    let remaining = self
+      .countdown
+      .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |remaining| {
+        remaining.checked_sub(1)
+      });
+    if remaining == Ok(1) {
+      panic!("Injected panic of a worker");
+    }
+    self.inner.process(transaction).await
+  }
+
+  fn validate(&self, transaction: &Transaction) -> Result<(), PaymentsEngineError> {
+    self.inner.validate(transaction)
+  }
+
+  fn accounts_report(&self) -> AccountsReportIter {
+    self.inner.accounts_report()
+  }
+
+  fn accounts_matching(&self, filter: AccountFilter) -> AccountsReportIter {
+    self.inner.accounts_matching(filter)
+  }
+
+  fn account(&self, client_id: ClientId) -> Option<AccountReport> {
+    self.inner.account(client_id)
+  }
+
+  fn transaction(
+    &self,
+    client_id: ClientId,
+    transaction_id: TransactionId,
+  ) -> Result<Option<TransactionInfo>, PaymentsEngineError> {
+    self.inner.transaction(client_id, transaction_id)
+  }
+
+  fn transactions_report(&self) -> Result<TransactionsReportIter, PaymentsEngineError> {
+    self.inner.transactions_report()
+  }
+}
+
+async fn reference_report(input: &str) -> Vec<String> {
+  let mut buffer = Vec::<u8>::new();
+  simple::run(
+    CsvTransactionsReader::new(input.as_bytes()),
+    InMemoryPaymentsEngine::new(),
+    CsvAccountsReportWriter::new(&mut buffer),
+  )
+  .await
+  .unwrap();
+  sorted_lines(&buffer)
+}
+
+fn sorted_lines(buffer: &[u8]) -> Vec<String> {
+  let mut lines: Vec<String> = String::from_utf8_lossy(buffer)
+    .lines()
+    .map(str::to_string)
+    .collect();
+  lines.sort_unstable();
+  lines
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn partitioned_matches_the_reference_engine() {
+  for seed in 0..SCENARIOS {
+    let scenario = Scenario::generate(seed);
+    let expected = reference_report(&scenario.reference_input).await;
+
+    let mut panics = scenario.panics.iter();
+    let mut attempts = 0;
+    let report = loop {
+      attempts += 1;
+      assert!(
+        attempts <= MAX_ATTEMPTS,
+        "Scenario {} never succeeded",
+        seed
+      );
+
+      // a countdown of zero never panics, and it is used once all the injected panics have been tried
+      let countdown = Arc::new(AtomicUsize::new(panics.next().map_or(0, |after| after + 1)));
+      let mut buffer = Vec::<u8>::new();
+      let result = partitioned::run_with_capacity(
+        CsvTransactionsReader::new(scenario.input.as_bytes()),
+        scenario.partitions,
+        scenario.capacity,
+        || ChaosEngine {
+          inner: InMemoryPaymentsEngine::new(),
+          countdown: countdown.clone(),
+        },
+        CsvAccountsReportWriter::new(&mut buffer),
+      )
+      .await;
+
+      match result {
+        Ok(()) => break sorted_lines(&buffer),
+        Err(_) => assert!(
+          buffer.is_empty(),
+          "Scenario {} wrote a partial report from a failed run",
+          seed
+        ),
+      }
+    };
+
+    assert_eq!(
+      report, expected,
+      "Scenario {} with {} partitions and capacity {}",
+      seed, scenario.partitions, scenario.capacity
+    );
+  }
+}